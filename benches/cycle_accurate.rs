@@ -0,0 +1,51 @@
+//! Compares the speed of the default mode against cycle accurate mode, where memory accesses happen
+//! on their machine cycle. Run with `cargo bench`.
+
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use gbcemu::{cartridge::Cartridge, emulator::EmulatorBuilder, machine::Machine, options::Options};
+
+/// Number of frames to run in each mode, a minute of emulated time
+const NUM_FRAMES: usize = 3600;
+
+fn time_frames(cycle_accurate: bool) -> Duration {
+    let rom_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("fill_vram.gb");
+    let cartridge = Cartridge::new_from_rom_bytes(std::fs::read(rom_path).unwrap()).unwrap();
+
+    // The fixture ROM fills VRAM without waiting for the PPU
+    let options = Options {
+        cycle_accurate,
+        no_access_restrictions: true,
+        ..Options::default()
+    };
+    let mut emulator = EmulatorBuilder::new_cartridge(cartridge, Machine::Dmg)
+        .with_options(Arc::new(options))
+        .build()
+        .unwrap();
+
+    emulator.power_on();
+
+    let start = Instant::now();
+    emulator.run_frames(NUM_FRAMES);
+    start.elapsed()
+}
+
+fn main() {
+    for (name, cycle_accurate) in [("default", false), ("cycle accurate", true)] {
+        let elapsed = time_frames(cycle_accurate);
+        println!(
+            "{:<16} {} frames in {:.2?} ({:.0} fps)",
+            name,
+            NUM_FRAMES,
+            elapsed,
+            NUM_FRAMES as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
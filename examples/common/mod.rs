@@ -0,0 +1,65 @@
+//! A small ROM shared by the examples, so that they run without any external files.
+
+use gbcemu::{
+    cartridge::{Cartridge, NINTENDO_LOGO},
+    emulator::EmulatorBuilder,
+    machine::Machine,
+};
+
+/// Plays a square wave on channel 1, fills VRAM with a pattern while the LCD is off, then scrolls
+/// the background by one pixel every frame.
+#[rustfmt::skip]
+const PROGRAM: [u8; 70] = [
+    0xF0, 0x44,             // wait_lcd_off: ldh a, [LY]
+    0xFE, 0x90,             // cp 144
+    0x20, 0xFA,             // jr nz, wait_lcd_off
+    0xAF,                   // xor a
+    0xE0, 0x40,             // ldh [LCDC], a (turn off the LCD during VBlank)
+    0x3E, 0x80, 0xE0, 0x26, // ld a, 0x80; ldh [NR52], a (turn on the APU)
+    0x3E, 0x77, 0xE0, 0x24, // ld a, 0x77; ldh [NR50], a (full volume)
+    0x3E, 0xFF, 0xE0, 0x25, // ld a, 0xFF; ldh [NR51], a (all channels to both speakers)
+    0x3E, 0x80, 0xE0, 0x11, // ld a, 0x80; ldh [NR11], a (50% duty cycle)
+    0x3E, 0xF0, 0xE0, 0x12, // ld a, 0xF0; ldh [NR12], a (full channel volume)
+    0x3E, 0x83, 0xE0, 0x13, // ld a, 0x83; ldh [NR13], a
+    0x3E, 0x87, 0xE0, 0x14, // ld a, 0x87; ldh [NR14], a (trigger at about 1kHz)
+    0x21, 0x00, 0x80,       // ld hl, 0x8000
+    0x7D,                   // fill: ld a, l
+    0x22,                   // ld [hl+], a
+    0x7C,                   // ld a, h
+    0xFE, 0x9C,             // cp 0x9C
+    0x20, 0xF9,             // jr nz, fill
+    0x3E, 0x91,             // ld a, 0x91
+    0xE0, 0x40,             // ldh [LCDC], a (turn the LCD back on)
+    0xF0, 0x44,             // wait_vblank: ldh a, [LY]
+    0xFE, 0x90,             // cp 144
+    0x20, 0xFA,             // jr nz, wait_vblank
+    0xF0, 0x43,             // ldh a, [SCX]
+    0x3C,                   // inc a
+    0xE0, 0x43,             // ldh [SCX], a
+    0xF0, 0x44,             // wait_vblank_end: ldh a, [LY]
+    0xFE, 0x90,             // cp 144
+    0x28, 0xFA,             // jr z, wait_vblank_end
+    0x18, 0xED,             // jr wait_vblank
+];
+
+const PROGRAM_START: usize = 0x0150;
+
+fn build_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+
+    // Entry point: nop, jp 0x0150
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x0134..0x013B].copy_from_slice(b"EXAMPLE");
+    rom[0x014D] = Cartridge::compute_header_checksum(&rom);
+
+    rom[PROGRAM_START..PROGRAM_START + PROGRAM.len()].copy_from_slice(&PROGRAM);
+
+    rom
+}
+
+/// Builder for an emulator running the example ROM on a DMG.
+pub fn example_emulator_builder() -> EmulatorBuilder {
+    let cartridge = Cartridge::new_from_rom_bytes(build_rom()).expect("Invalid example ROM");
+    EmulatorBuilder::new_cartridge(cartridge, Machine::Dmg)
+}
@@ -0,0 +1,179 @@
+//! An `AudioOutput` that hands samples to an audio callback through a lock-free ring buffer.
+//!
+//! Audio devices usually pull samples from a callback on their own thread, while the emulator
+//! pushes a frame of samples at the end of every frame on the emulator thread. Here a thread that
+//! wakes up every few milliseconds stands in for the device callback.
+//!
+//! Run with `cargo run --example custom_audio`.
+
+mod common;
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use gbcemu::{
+    audio::{AudioFrame, AudioOutput, SAMPLE_RATE},
+    emulator::REFRESH_RATE,
+};
+
+/// About four frames of interleaved stereo samples.
+const RING_CAPACITY: usize = 4 * 2 * SAMPLE_RATE as usize / 60;
+
+const CALLBACK_PERIOD: Duration = Duration::from_millis(5);
+
+const NUM_FRAMES: usize = 120;
+
+/// Single producer, single consumer ring of samples stored as their bits.
+struct SampleRing {
+    slots: Box<[AtomicU32]>,
+    /// Total number of samples read, only written by the consumer
+    read_index: AtomicUsize,
+    /// Total number of samples written, only written by the producer
+    write_index: AtomicUsize,
+}
+
+impl SampleRing {
+    fn new(capacity: usize) -> Self {
+        SampleRing {
+            slots: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            read_index: AtomicUsize::new(0),
+            write_index: AtomicUsize::new(0),
+        }
+    }
+
+    /// Push a sample from the producer thread, returning false if the ring is full.
+    fn push(&self, sample: f32) -> bool {
+        let write_index = self.write_index.load(Ordering::Relaxed);
+        let read_index = self.read_index.load(Ordering::Acquire);
+        if write_index - read_index == self.slots.len() {
+            return false;
+        }
+
+        self.slots[write_index % self.slots.len()].store(sample.to_bits(), Ordering::Relaxed);
+        self.write_index.store(write_index + 1, Ordering::Release);
+
+        true
+    }
+
+    /// Pop a sample from the consumer thread, if one is available.
+    fn pop(&self) -> Option<f32> {
+        let read_index = self.read_index.load(Ordering::Relaxed);
+        let write_index = self.write_index.load(Ordering::Acquire);
+        if read_index == write_index {
+            return None;
+        }
+
+        let sample = self.slots[read_index % self.slots.len()].load(Ordering::Relaxed);
+        self.read_index.store(read_index + 1, Ordering::Release);
+
+        Some(f32::from_bits(sample))
+    }
+}
+
+/// State shared between the emulator thread and the callback thread.
+struct SharedState {
+    ring: SampleRing,
+    is_paused: AtomicBool,
+    is_finished: AtomicBool,
+    num_dropped_samples: AtomicUsize,
+}
+
+struct RingAudioOutput {
+    state: Arc<SharedState>,
+}
+
+impl AudioOutput for RingAudioOutput {
+    fn send_frame(&self, samples: AudioFrame) {
+        for sample in samples {
+            // Drop samples that do not fit instead of blocking the emulator thread
+            if !self.state.ring.push(sample.left) || !self.state.ring.push(sample.right) {
+                self.state
+                    .num_dropped_samples
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn set_paused_state(&self, is_paused: bool) {
+        // No frames arrive while paused. Play silence instead of draining the buffered samples, so
+        // that playback resumes where it left off.
+        self.state.is_paused.store(is_paused, Ordering::Relaxed);
+    }
+
+    fn set_speed(&self, _speed: f64) {
+        // Frames arrive faster than they are played above normal speed, so samples that do not fit
+        // in the ring are dropped. Below normal speed the ring runs dry between frames.
+    }
+}
+
+/// Stands in for an audio device callback, pulling a fixed number of samples at a fixed rate.
+fn run_callback_thread(state: Arc<SharedState>) -> (usize, usize) {
+    let samples_per_callback =
+        2 * SAMPLE_RATE as usize * CALLBACK_PERIOD.as_millis() as usize / 1000;
+
+    let mut num_played = 0;
+    let mut num_underruns = 0;
+
+    while !state.is_finished.load(Ordering::Relaxed) {
+        thread::sleep(CALLBACK_PERIOD);
+
+        if state.is_paused.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        for _ in 0..samples_per_callback {
+            match state.ring.pop() {
+                // A real callback would write the sample to the device buffer here
+                Some(_) => num_played += 1,
+                None => num_underruns += 1,
+            }
+        }
+    }
+
+    (num_played, num_underruns)
+}
+
+fn main() {
+    let state = Arc::new(SharedState {
+        ring: SampleRing::new(RING_CAPACITY),
+        is_paused: AtomicBool::new(false),
+        is_finished: AtomicBool::new(false),
+        num_dropped_samples: AtomicUsize::new(0),
+    });
+
+    let callback_state = state.clone();
+    let callback_thread = thread::spawn(move || run_callback_thread(callback_state));
+
+    let mut emulator = common::example_emulator_builder()
+        .with_audio_output(Box::new(RingAudioOutput {
+            state: state.clone(),
+        }))
+        .build()
+        .expect("Failed to build emulator");
+    emulator.power_on();
+
+    // Run at roughly real time so that the callback keeps up with the emulator
+    let frame_duration = Duration::from_secs_f64(1.0 / REFRESH_RATE);
+    let start = Instant::now();
+    for frame in 1..=NUM_FRAMES {
+        emulator.run_frame();
+
+        let next_frame_start = start + frame_duration * frame as u32;
+        thread::sleep(next_frame_start.saturating_duration_since(Instant::now()));
+    }
+
+    state.is_finished.store(true, Ordering::Relaxed);
+    let (num_played, num_underruns) = callback_thread.join().unwrap();
+
+    println!("Played {num_played} samples with {num_underruns} underruns");
+    println!(
+        "Dropped {} samples",
+        state.num_dropped_samples.load(Ordering::Relaxed)
+    );
+}
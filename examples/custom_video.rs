@@ -0,0 +1,74 @@
+//! A `VideoSink` that writes frames to disk as a sequence of PPM images.
+//!
+//! Run with `cargo run --example custom_video [OUTPUT_DIR]`. Frames are written to a directory in
+//! the system's temporary directory if no output directory is given.
+
+mod common;
+
+use std::{env, fs, io::Write, path::PathBuf};
+
+use eframe::egui::Color32;
+use gbcemu::{
+    emulator::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    ppu::VideoSink,
+};
+
+const NUM_FRAMES: usize = 60;
+
+/// Only every nth frame is written.
+const FRAME_INTERVAL: usize = 10;
+
+struct PpmSequenceSink {
+    output_dir: PathBuf,
+    frame_number: usize,
+}
+
+impl PpmSequenceSink {
+    fn write_frame(&self, pixels: &[Color32]) -> std::io::Result<()> {
+        let path = self
+            .output_dir
+            .join(format!("frame_{:04}.ppm", self.frame_number));
+
+        let mut contents = format!("P6\n{SCREEN_WIDTH} {SCREEN_HEIGHT}\n255\n").into_bytes();
+        for pixel in pixels {
+            contents.extend_from_slice(&[pixel.r(), pixel.g(), pixel.b()]);
+        }
+
+        fs::File::create(path)?.write_all(&contents)
+    }
+}
+
+impl VideoSink for PpmSequenceSink {
+    fn send_frame(&mut self, pixels: &[Color32]) {
+        // Writing to disk is slow, a real-time sink would hand the frame off to another thread
+        if self.frame_number.is_multiple_of(FRAME_INTERVAL) {
+            self.write_frame(pixels).expect("Failed to write frame");
+        }
+
+        self.frame_number += 1;
+    }
+}
+
+fn main() {
+    let output_dir = env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::temp_dir().join("gbcemu-frames"));
+    fs::create_dir_all(&output_dir).expect("Failed to create output directory");
+
+    let mut emulator = common::example_emulator_builder()
+        .with_video_sink(Box::new(PpmSequenceSink {
+            output_dir: output_dir.clone(),
+            frame_number: 0,
+        }))
+        .build()
+        .expect("Failed to build emulator");
+    emulator.power_on();
+    emulator.run_frames(NUM_FRAMES);
+
+    println!(
+        "Wrote {} frames to {}",
+        NUM_FRAMES / FRAME_INTERVAL,
+        output_dir.display()
+    );
+}
@@ -19,6 +19,9 @@ pub const EXTERNAL_RAM_START: Address = 0xA000;
 pub const EXTERNAL_RAM_END: Address = 0xC000;
 pub const SINGLE_EXTERNAL_RAM_BANK_SIZE: usize = (EXTERNAL_RAM_END - EXTERNAL_RAM_START) as usize;
 
+/// Size of the RAM built into an MBC2, in half-bytes
+pub const MBC2_RAM_SIZE: usize = 512;
+
 /// First work RAM bank 0xC000-0xD000
 pub const FIRST_WORK_RAM_BANK_START: Address = 0xC000;
 pub const FIRST_WORK_RAM_BANK_END: Address = 0xD000;
@@ -68,3 +71,7 @@ pub const DMG_BIOS_END: Address = 0x0100;
 
 /// End address for CGB BIOS when mapped over ROM
 pub const CGB_BIOS_END: Address = 0x0900;
+
+/// Range in the middle of the CGB BIOS where the cartridge header remains visible while booting
+pub const CGB_BIOS_HOLE_START: Address = 0x0100;
+pub const CGB_BIOS_HOLE_END: Address = 0x0200;
@@ -1,6 +1,11 @@
 use std::{
     collections::VecDeque,
-    sync::mpsc::{self, Receiver, Sender},
+    fmt,
+    str::FromStr,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
+    },
 };
 
 use rodio::{OutputStream, OutputStreamBuilder, Sink, Source};
@@ -8,50 +13,96 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     address_space::{WAVE_RAM_SIZE, WAVE_RAM_START},
-    emulator::{REFRESH_RATE, Register, TICKS_PER_FRAME},
+    emulator::{MAX_SPEED, NORMAL_SPEED, REFRESH_RATE, Register, TICKS_PER_FRAME},
+    shared_stats::SharedStats,
+    thread_priority,
 };
 
 /// Rate to sample audio during playback, in Hz
-const SAMPLE_RATE: u32 = 44100;
+pub const SAMPLE_RATE: u32 = 44100;
 
 pub const NUM_AUDIO_CHANNELS: u8 = 4;
 
 pub const TICKS_PER_SAMPLE: f64 = (TICKS_PER_FRAME as f64 * REFRESH_RATE) / (SAMPLE_RATE as f64);
 
+/// Most samples the emulator produces in a single frame, used to size audio frame buffers up front
+pub const MAX_SAMPLES_PER_AUDIO_FRAME: usize = TICKS_PER_FRAME.div_ceil(TICKS_PER_SAMPLE as usize);
+
 const SYSTEM_VOLUME_LEVELS: [f32; 8] = [0.0, 0.0625, 0.125, 0.25, 0.375, 0.5, 0.65, 1.0];
 const DEFAULT_SYSTEM_VOLUME_INDEX: usize = 5;
 
-/// Recharge rate for the high pass filter's capacitor when sampling at 44100 Hz
+/// Recharge rate for the high pass filter's capacitor when sampling at 44100 Hz. Samples are taken
+/// at a fixed number of ticks which does not change in double speed mode, so the rate does not
+/// depend on CPU speed.
 const HPF_RECHARGE_RATE: f32 = 0.996;
 
-/// A generic audio output device which can be attached to an emulator
+/// A generic audio output device which can be attached to an emulator.
+///
+/// All methods are called on the emulator thread and should return quickly, handing samples off
+/// to the audio device's own thread. See `examples/custom_audio.rs` for an implementation.
 pub trait AudioOutput {
+    /// Called at the end of every frame with the samples produced during that frame.
     fn send_frame(&self, samples: AudioFrame);
+    /// Called when the emulator is paused or resumed. No frames are sent while paused.
     fn set_paused_state(&self, is_paused: bool);
+    /// Called when the speed changes, as a multiple of normal speed. Frames are sent faster than
+    /// real time above normal speed, and slower below it.
+    fn set_speed(&self, speed: f64);
+    /// Called before each frame to get back an emptied buffer from an earlier frame, so that the
+    /// emulator can reuse it instead of allocating a new one.
+    fn take_empty_frame(&self) -> Option<AudioFrame> {
+        None
+    }
 }
 
 enum AudioMessage {
-    /// The next audio frame
-    FrameSamples(AudioFrame),
     /// Whether audio should be paused
     PausedState(bool),
+    /// Speed the emulator is running at
+    Speed(f64),
 }
 
 /// A collection of audio samples corresponding to a single (graphical) frame
 pub type AudioFrame = Vec<TimedSample>;
 
+/// Most frames that can wait in either direction between the emulator and audio threads. Frames
+/// are passed over bounded channels, which unlike unbounded channels do not allocate per message.
+const MAX_QUEUED_FRAMES: usize = 64;
+
 fn shared_audio_channel() -> (SharedAudioSender, SharedAudioReceiver) {
     let (send, recv) = mpsc::channel();
-    (SharedAudioSender { send }, SharedAudioReceiver { recv })
+    let (send_frame, recv_frame) = mpsc::sync_channel(MAX_QUEUED_FRAMES);
+    let (send_empty_frame, recv_empty_frame) = mpsc::sync_channel(MAX_QUEUED_FRAMES);
+
+    let sender = SharedAudioSender {
+        send,
+        send_frame,
+        recv_empty_frame,
+    };
+    let receiver = SharedAudioReceiver {
+        recv,
+        recv_frame,
+        send_empty_frame,
+    };
+
+    (sender, receiver)
 }
 
+/// Sends frames of samples to a `BufferedSource` on the audio thread, which sends each frame's
+/// buffer back once its samples are buffered so that the buffers are reused.
 pub struct SharedAudioSender {
     send: Sender<AudioMessage>,
+    send_frame: SyncSender<AudioFrame>,
+    recv_empty_frame: Receiver<AudioFrame>,
 }
 
-impl SharedAudioSender {
+impl AudioOutput for SharedAudioSender {
     fn send_frame(&self, samples: AudioFrame) {
-        self.send.send(AudioMessage::FrameSamples(samples)).unwrap();
+        // The frame is dropped if the audio thread has fallen too far behind to keep up
+        match self.send_frame.try_send(samples) {
+            Ok(()) | Err(TrySendError::Full(_)) => {}
+            Err(TrySendError::Disconnected(_)) => panic!("Audio thread has stopped"),
+        }
     }
 
     fn set_paused_state(&self, is_paused: bool) {
@@ -59,16 +110,47 @@ impl SharedAudioSender {
             .send(AudioMessage::PausedState(is_paused))
             .unwrap();
     }
+
+    fn set_speed(&self, speed: f64) {
+        self.send.send(AudioMessage::Speed(speed)).unwrap();
+    }
+
+    fn take_empty_frame(&self) -> Option<AudioFrame> {
+        self.recv_empty_frame.try_recv().ok()
+    }
 }
 
 pub struct SharedAudioReceiver {
     recv: Receiver<AudioMessage>,
+    recv_frame: Receiver<AudioFrame>,
+    send_empty_frame: SyncSender<AudioFrame>,
 }
 
 impl SharedAudioReceiver {
     fn try_next_message(&self) -> Option<AudioMessage> {
         self.recv.try_recv().ok()
     }
+
+    fn try_next_frame(&self) -> Option<AudioFrame> {
+        self.recv_frame.try_recv().ok()
+    }
+
+    /// Hand an emptied frame back to the emulator. The frame is dropped if the emulator already
+    /// has plenty of empty frames, or has stopped.
+    fn return_empty_frame(&self, frame: AudioFrame) {
+        let _ = self.send_empty_frame.try_send(frame);
+    }
+}
+
+/// Create an audio output along with the source that plays its samples, for playing audio through
+/// something other than the default system output.
+pub fn buffered_audio_channel(
+    latency_frames: u32,
+    turbo_audio: TurboAudio,
+) -> (SharedAudioSender, BufferedSource) {
+    let (sender, receiver) = shared_audio_channel();
+    let source = BufferedSource::new(receiver, latency_frames, turbo_audio);
+    (sender, source)
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -77,33 +159,195 @@ pub struct TimedSample {
     pub left: f32,
     /// Amplitude of the right channel
     pub right: f32,
-    /// Tick within the frame for this sample
+    /// Tick within the frame for this sample. Ticks are increasing within a frame and are always
+    /// less than `TICKS_PER_FRAME`, so frames played back to back form a continuous timeline.
     pub tick: u32,
 }
 
-/// We target keeping the buffer full with two frames of audio. This introduces a frame of audio
-/// latency but reduces the chance of not having audio ready when requested.
-const TARGET_BUFFERED_FRAMES: u64 = 2;
+/// Default number of frames of audio to buffer. More frames add latency but reduce the chance of
+/// running out of samples.
+pub const DEFAULT_AUDIO_LATENCY_FRAMES: u32 = 2;
+
+/// Number of samples played per frame at normal speed
+const SAMPLES_PER_FRAME: f64 = SAMPLE_RATE as f64 / REFRESH_RATE;
+
+/// Largest change to the playback rate made to keep the buffer at its target fill level
+const MAX_PLAYBACK_RATE_ADJUSTMENT: f64 = 0.005;
+
+/// Buffered samples past this multiple of the target fill level are dropped
+const MAX_FILL_MULTIPLE: usize = 4;
+
+/// How audio is played above normal speed, such as in turbo mode, when the emulator produces
+/// samples faster than they can be played at the normal rate. Below normal speed audio is always
+/// played slower, lowering the pitch, since there are not enough samples to play at the normal
+/// rate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TurboAudio {
+    /// Play all samples faster, raising the pitch
+    #[default]
+    Pitch,
+    /// Play samples at the normal rate, dropping the samples that do not fit in the buffer
+    Drop,
+}
+
+impl FromStr for TurboAudio {
+    type Err = String;
 
-struct BufferedSource {
-    /// Whether the next sample is for the left channel (true) or right channel (false)
-    is_next_sample_left: bool,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pitch" => Ok(TurboAudio::Pitch),
+            "drop" => Ok(TurboAudio::Drop),
+            _ => Err(format!("expected pitch or drop but found {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for TurboAudio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TurboAudio::Pitch => write!(f, "pitch"),
+            TurboAudio::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+/// Converts the stream of samples from the emulator to the playback sample rate.
+///
+/// The emulator's clock and the audio device's clock drift apart, so samples are played from a
+/// buffer whose fill level is kept near a target. The playback rate is raised or lowered by up to
+/// `MAX_PLAYBACK_RATE_ADJUSTMENT` depending on how far the buffer is from its target, which is too
+/// small a change in pitch to be heard. Played samples are linearly interpolated between the
+/// buffered samples.
+struct Resampler {
+    /// Samples waiting to be played, as (left, right) pairs
+    buffer: VecDeque<(f32, f32)>,
+
+    /// Position of the next played sample between the first two buffered samples, in [0, 1)
+    position: f64,
+
+    /// Number of buffered samples to aim for at normal speed
+    target_fill: usize,
+
+    /// Whether playback is waiting for the buffer to fill up to its target, either at startup or
+    /// after running out of samples
+    is_buffering: bool,
+
+    /// The last sample played, repeated while buffering
+    last_sample: (f32, f32),
+
+    /// Buffered samples consumed per played sample, before adjusting for the fill level
+    speed: f64,
 
-    /// The current tick in this frame. Fractional since sample rate does not align perfectly with
-    /// ticks.
-    current_tick: f64,
+    turbo_audio: TurboAudio,
+}
+
+impl Resampler {
+    fn new(latency_frames: u32, turbo_audio: TurboAudio) -> Self {
+        let target_fill = (latency_frames.max(1) as f64 * SAMPLES_PER_FRAME) as usize;
+
+        // Room for the most samples buffered at once, so that the buffer never grows while playing
+        let max_scaled_target_fill = (target_fill as f64 * MAX_SPEED) as usize;
+        let capacity = max_scaled_target_fill * MAX_FILL_MULTIPLE + MAX_SAMPLES_PER_AUDIO_FRAME;
+
+        Self {
+            buffer: VecDeque::with_capacity(capacity),
+            position: 0.0,
+            target_fill,
+            is_buffering: true,
+            last_sample: (0.0, 0.0),
+            speed: 1.0,
+            turbo_audio,
+        }
+    }
+
+    fn push_frame(&mut self, frame: &[TimedSample]) {
+        self.buffer
+            .extend(frame.iter().map(|sample| (sample.left, sample.right)));
+
+        // Skip ahead to the newest samples if the buffer has grown far past its target, e.g. in
+        // turbo mode when samples are dropped
+        let target_fill = self.scaled_target_fill();
+        if self.buffer.len() > target_fill * MAX_FILL_MULTIPLE {
+            let num_dropped = self.buffer.len() - target_fill;
+            self.buffer.drain(..num_dropped);
+        }
+    }
+
+    /// Target fill level at the current speed, so that the buffer holds the same length of time
+    /// when samples are played faster.
+    fn scaled_target_fill(&self) -> usize {
+        (self.target_fill as f64 * self.speed) as usize
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        self.speed = if speed > NORMAL_SPEED && self.turbo_audio == TurboAudio::Drop {
+            NORMAL_SPEED
+        } else {
+            speed
+        };
+    }
+
+    /// How full the buffer is compared to its target at the current speed, as a percentage.
+    fn fill_percent(&self) -> f32 {
+        self.buffer.len() as f32 / self.scaled_target_fill() as f32 * 100.0
+    }
+
+    /// Number of buffered samples to advance by for each played sample.
+    fn playback_rate(&self) -> f64 {
+        let target_fill = self.scaled_target_fill() as f64;
+        let fill_error = (self.buffer.len() as f64 - target_fill) / target_fill;
+
+        self.speed * (1.0 + MAX_PLAYBACK_RATE_ADJUSTMENT * fill_error.clamp(-1.0, 1.0))
+    }
+
+    fn next_sample(&mut self) -> (f32, f32) {
+        if self.is_buffering {
+            if self.buffer.len() < self.scaled_target_fill() {
+                return self.last_sample;
+            }
 
-    /// The current sample
-    current_sample: TimedSample,
+            self.is_buffering = false;
+        }
+
+        // Interpolating needs the buffered samples on both sides of the current position
+        if self.buffer.len() < 2 {
+            self.is_buffering = true;
+            return self.last_sample;
+        }
+
+        let (left_before, right_before) = self.buffer[0];
+        let (left_after, right_after) = self.buffer[1];
+        let t = self.position as f32;
 
-    /// Buffer of completed frames ready for playback
-    frame_buffer: VecDeque<AudioFrame>,
+        self.last_sample = (
+            left_before + (left_after - left_before) * t,
+            right_before + (right_after - right_before) * t,
+        );
 
-    /// Queue of pending frames that have been received but not yet processed
-    pending_frames: VecDeque<AudioFrame>,
+        self.position += self.playback_rate();
 
-    /// Index of the next sample to read
-    next_sample_index: usize,
+        let num_consumed = self.position as usize;
+        if num_consumed < self.buffer.len() {
+            self.buffer.drain(..num_consumed);
+            self.position -= num_consumed as f64;
+        } else {
+            self.buffer.clear();
+            self.position = 0.0;
+        }
+
+        self.last_sample
+    }
+}
+
+/// A source of interleaved stereo samples played from the frames sent by a `SharedAudioSender`.
+pub struct BufferedSource {
+    /// Whether the next sample is for the left channel (true) or right channel (false)
+    is_next_sample_left: bool,
+
+    /// The current sample, whose left and right channels are returned in turn
+    current_sample: (f32, f32),
+
+    resampler: Resampler,
 
     /// Receiver for batches of samples for each frame
     receiver: SharedAudioReceiver,
@@ -111,38 +355,46 @@ struct BufferedSource {
     /// Whether the audio stream is currently paused
     is_paused: bool,
 
-    /// The frame number for the frame currently being played
-    frame_number: u64,
+    /// Whether to raise the priority of the audio thread when the first sample is requested, since
+    /// that is the first time code runs on it
+    should_raise_thread_priority: bool,
+
+    /// Statistics to report the buffer fill level to, if any
+    shared_stats: Option<Arc<SharedStats>>,
 }
 
 impl BufferedSource {
-    fn new(receiver: SharedAudioReceiver) -> Self {
+    fn new(receiver: SharedAudioReceiver, latency_frames: u32, turbo_audio: TurboAudio) -> Self {
         Self {
             is_next_sample_left: true,
-            current_tick: 0.0,
-            current_sample: TimedSample {
-                left: 0.0,
-                right: 0.0,
-                tick: 0,
-            },
-            frame_buffer: VecDeque::new(),
-            pending_frames: VecDeque::new(),
-            next_sample_index: 0,
+            current_sample: (0.0, 0.0),
+            resampler: Resampler::new(latency_frames, turbo_audio),
             receiver,
             is_paused: false,
-            frame_number: 0,
+            should_raise_thread_priority: false,
+            shared_stats: None,
         }
     }
 
     fn handle_messages(&mut self) {
+        // Settings are applied before new frames, since they were sent before those frames
         while let Some(message) = self.receiver.try_next_message() {
             match message {
-                AudioMessage::FrameSamples(frame_samples) => {
-                    self.pending_frames.push_back(frame_samples)
-                }
                 AudioMessage::PausedState(is_paused) => self.is_paused = is_paused,
+                AudioMessage::Speed(speed) => self.resampler.set_speed(speed),
             }
         }
+
+        while let Some(mut frame) = self.receiver.try_next_frame() {
+            self.resampler.push_frame(&frame);
+
+            frame.clear();
+            self.receiver.return_empty_frame(frame);
+        }
+
+        if let Some(shared_stats) = &self.shared_stats {
+            shared_stats.set_audio_buffer_fill_percent(self.resampler.fill_percent());
+        }
     }
 }
 
@@ -168,89 +420,31 @@ impl Iterator for BufferedSource {
     type Item = f32;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.should_raise_thread_priority {
+            self.should_raise_thread_priority = false;
+            thread_priority::configure_current_thread("audio", true, None);
+        }
+
         self.handle_messages();
 
+        // Channels are interleaved, and keep alternating while paused
+        let is_left = self.is_next_sample_left;
+        self.is_next_sample_left = !is_left;
+
         // Silence when paused
         if self.is_paused {
             return Some(0.0);
         }
 
-        // Check if frame is complete. Make sure not to skip if between left and right samples.
-        if self.is_next_sample_left && self.current_tick >= (TICKS_PER_FRAME as f64 - 0.1) {
-            // Frame is done, start a new frame
-            self.frame_number += 1;
-            self.current_tick = 0.0;
-            self.next_sample_index = 0;
-
-            // Move on to the next frame of samples if one exists. Otherwise loop current frame.
-            if self.frame_buffer.len() > 1 || !self.pending_frames.is_empty() {
-                self.frame_buffer.pop_front();
-            }
-
-            if self.frame_number >= TARGET_BUFFERED_FRAMES {
-                // Fill buffer up to target number of frames
-                for _ in self.frame_buffer.len()..(TARGET_BUFFERED_FRAMES as usize) {
-                    if let Some(frame) = self.pending_frames.pop_front() {
-                        self.frame_buffer.push_back(frame);
-                    }
-                }
-
-                // Combine all pending frames into a single frame if there are multiple.
-                if self.pending_frames.len() > 1 {
-                    let merged_frame = merge_into_single_frame(&self.pending_frames);
-
-                    self.pending_frames.clear();
-                    self.pending_frames.push_back(merged_frame);
-                }
-            }
-        }
-
-        // Find the next sample for the current tick. Remain at the last sample if we reach the end
-        // end of the sample buffer.
-        while let Some(sample) = self
-            .frame_buffer
-            .front()
-            .and_then(|f| f.get(self.next_sample_index))
-            && (sample.tick as f64) <= self.current_tick
-        {
-            self.current_sample = *sample;
-            self.next_sample_index += 1;
-        }
-
-        // Only increment tick after both left and right samples have been read
-        if !self.is_next_sample_left {
-            self.current_tick += TICKS_PER_SAMPLE;
-        }
-
-        // Return the sample for the appropriate channel, interleaving channels
-        self.is_next_sample_left = !self.is_next_sample_left;
-
-        if self.is_next_sample_left {
-            Some(self.current_sample.left)
+        if is_left {
+            self.current_sample = self.resampler.next_sample();
+            Some(self.current_sample.0)
         } else {
-            Some(self.current_sample.right)
+            Some(self.current_sample.1)
         }
     }
 }
 
-fn merge_into_single_frame(frames: &VecDeque<AudioFrame>) -> AudioFrame {
-    // Round up integer division to ensure we fill the entire new frame
-    let frame_length = frames[0].len();
-    let samples_per_frame = frame_length.div_ceil(frames.len());
-
-    let mut new_frame = Vec::with_capacity(frame_length);
-
-    // Choose samples evenly from each frame to fill the new frame
-    for i in 0..frame_length {
-        let frame_index = i / samples_per_frame;
-        let frame = &frames[frame_index];
-        let sample_index = ((i % samples_per_frame) * frames.len()).min(frame.len() - 1);
-        new_frame.push(frame[sample_index]);
-    }
-
-    new_frame
-}
-
 pub struct DefaultSystemAudioOutput {
     _output_stream: OutputStream,
     _sink: Sink,
@@ -258,13 +452,24 @@ pub struct DefaultSystemAudioOutput {
 }
 
 impl DefaultSystemAudioOutput {
-    pub fn new() -> Self {
+    /// Open the default output device. If `high_priority` is set the priority of the thread that
+    /// plays audio is raised. The buffer fill level is reported to `shared_stats`.
+    pub fn new(
+        latency_frames: u32,
+        turbo_audio: TurboAudio,
+        high_priority: bool,
+        shared_stats: Arc<SharedStats>,
+    ) -> Self {
         let (sender, receiver) = shared_audio_channel();
 
         let output_stream = OutputStreamBuilder::open_default_stream().unwrap();
 
+        let mut source = BufferedSource::new(receiver, latency_frames, turbo_audio);
+        source.should_raise_thread_priority = high_priority;
+        source.shared_stats = Some(shared_stats);
+
         let sink = Sink::connect_new(output_stream.mixer());
-        sink.append(BufferedSource::new(receiver));
+        sink.append(source);
 
         Self {
             _output_stream: output_stream,
@@ -282,9 +487,34 @@ impl AudioOutput for DefaultSystemAudioOutput {
     fn set_paused_state(&self, is_paused: bool) {
         self.sender.set_paused_state(is_paused);
     }
+
+    fn set_speed(&self, speed: f64) {
+        self.sender.set_speed(speed);
+    }
+
+    fn take_empty_frame(&self) -> Option<AudioFrame> {
+        self.sender.take_empty_frame()
+    }
 }
 
 /// Map digital 0x0-0xF to analog 1.0 to -1.0
+/// Clock a timer that counts down to 0 then reloads from `period` many times at once, returning
+/// the number of times its period ended. Equivalent to clocking it once `num_clocks` times.
+fn clock_period_timer(timer: &mut u16, period: u16, num_clocks: u32) -> u32 {
+    let timer_value = *timer as u32;
+    if num_clocks <= timer_value {
+        *timer -= num_clocks as u16;
+        return 0;
+    }
+
+    // The period ends on the first clock after reaching 0, then on every `period` clocks after
+    let period = period as u32;
+    let clocks_after_zero = num_clocks - timer_value;
+    *timer = (period - 1 - (clocks_after_zero - 1) % period) as u16;
+
+    clocks_after_zero.div_ceil(period)
+}
+
 fn digital_to_analog(digit: u8) -> f32 {
     1.0 - ((digit as f32) / 7.5)
 }
@@ -394,6 +624,10 @@ impl Apu {
         &mut self.channel_2
     }
 
+    pub fn channel_3(&self) -> &WaveChannel {
+        &self.channel_3
+    }
+
     pub fn channel_3_mut(&mut self) -> &mut WaveChannel {
         &mut self.channel_3
     }
@@ -406,6 +640,29 @@ impl Apu {
         self.is_on
     }
 
+    pub fn system_volume_index(&self) -> usize {
+        self.system_volume_index
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.is_muted
+    }
+
+    /// Whether the given channel (1-4) has been turned off with `toggle_channel`.
+    pub fn is_channel_disabled(&self, channel: usize) -> bool {
+        match channel {
+            1 => self.debug_disable_channel_1,
+            2 => self.debug_disable_channel_2,
+            3 => self.debug_disable_channel_3,
+            4 => self.debug_disable_channel_4,
+            _ => false,
+        }
+    }
+
+    pub fn is_hpf_disabled(&self) -> bool {
+        self.debug_disable_hpf
+    }
+
     pub fn increase_system_volume(&mut self) {
         self.system_volume_index =
             (self.system_volume_index + 1).min(SYSTEM_VOLUME_LEVELS.len() - 1);
@@ -476,6 +733,19 @@ impl Apu {
         }
     }
 
+    /// Advance period timers over `num_ticks` ticks starting at `tick_number`, with the same effect
+    /// as calling `advance_period_timers` for each tick.
+    pub fn advance_period_timers_by(&mut self, tick_number: u32, num_ticks: u32) {
+        // Number of ticks in the range that are a multiple of the clock period
+        let end_tick = tick_number + num_ticks;
+        let num_clocks = |period: u32| end_tick.div_ceil(period) - tick_number.div_ceil(period);
+
+        self.channel_1.advance_period_timer_by(num_clocks(4));
+        self.channel_2.advance_period_timer_by(num_clocks(4));
+        self.channel_3.advance_period_timer_by(num_clocks(2));
+        self.channel_4.advance_period_timer_by(num_clocks(8));
+    }
+
     pub fn write_nr50(&mut self, value: Register) {
         self.left_volume = (value & 0x70) >> 4;
         self.right_volume = value & 0x7;
@@ -736,7 +1006,11 @@ impl PulseChannel {
         // Upper two bits of NRX1
         self.duty_cycle = (value & 0xC0) >> 6;
 
-        // Lower six bits of NRX1
+        self.write_length_timer(value);
+    }
+
+    /// Write only the length timer in the lower six bits of NRX1, leaving the duty cycle.
+    pub fn write_length_timer(&mut self, value: Register) {
         self.length_timer = Self::MAX_LENGTH_TIMER - (value & 0x3F);
     }
 
@@ -834,6 +1108,14 @@ impl PulseChannel {
         self.period_timer -= 1;
     }
 
+    fn advance_period_timer_by(&mut self, num_clocks: u32) {
+        let period = self.initial_period_timer();
+        let num_periods = clock_period_timer(&mut self.period_timer, period, num_clocks);
+
+        self.duty_sample_index =
+            ((self.duty_sample_index as u32 + num_periods) % DUTY_WAVEFORM_LENGTH as u32) as u8;
+    }
+
     fn advance_length_timer(&mut self) {
         if self.is_length_timer_enabled && self.length_timer > 0 {
             self.length_timer -= 1;
@@ -1023,6 +1305,10 @@ impl WaveChannel {
         }
     }
 
+    pub fn read_wave_ram(&self, address: u16) -> u8 {
+        self.wave_ram[(address - WAVE_RAM_START) as usize]
+    }
+
     pub fn write_wave_ram(&mut self, address: u16, value: u8) {
         self.wave_ram[(address - WAVE_RAM_START) as usize] = value;
     }
@@ -1088,6 +1374,14 @@ impl WaveChannel {
         self.period_timer -= 1;
     }
 
+    fn advance_period_timer_by(&mut self, num_clocks: u32) {
+        let period = self.initial_period_timer();
+        let num_periods = clock_period_timer(&mut self.period_timer, period, num_clocks);
+
+        self.wave_sample_index =
+            ((self.wave_sample_index as u32 + num_periods) % NUM_CUSTOM_WAVE_SAMPLES as u32) as u8;
+    }
+
     fn advance_length_timer(&mut self) {
         if self.is_length_timer_enabled && self.length_timer > 0 {
             self.length_timer -= 1;
@@ -1196,7 +1490,7 @@ impl NoiseChannel {
         // Clock divider is lower three bits
         self.clock_divider = value & 0x7;
 
-        // LFSR width is bit three
+        // LFSR width is bit three, which is set for the narrow 7 bit LFSR
         self.is_lfsr_wide = (value & 0x8) == 0;
 
         // Top four bits are the shift clock frequency
@@ -1271,20 +1565,7 @@ impl NoiseChannel {
 
         // Subtracting would overflow so period is over
         if self.clock_timer == 0 {
-            // Advance the LFSR. Last bit becomes the current sample bit.
-            let new_bit = !(self.lfsr ^ (self.lfsr >> 1)) & 0x1;
-
-            if self.is_lfsr_wide {
-                // Wide LFSR copies new bit to bit 15
-                self.lfsr = (self.lfsr & 0x7FFF) | ((new_bit << 15) & 0x8000);
-            } else {
-                // Narrow LFSR copies new bit to bit 7
-                self.lfsr = (self.lfsr & 0x7F) | ((new_bit << 7) & 0x80);
-            }
-
-            // LFSR is shifted right one bit
-            self.lfsr >>= 1;
-            self.current_sample_bit = self.lfsr & 0x1 != 0;
+            self.clock_lfsr();
 
             // Reload period timer
             self.clock_timer = self.initial_clock_timer();
@@ -1293,6 +1574,46 @@ impl NoiseChannel {
         self.clock_timer -= 1;
     }
 
+    fn advance_period_timer_by(&mut self, num_clocks: u32) {
+        if self.clock_shift >= 14 {
+            return;
+        }
+
+        // The clock timer wraps to a period of 0 for the largest divider and shift, which can only
+        // be clocked one step at a time
+        let period = self.initial_clock_timer();
+        if period == 0 {
+            for _ in 0..num_clocks {
+                self.advance_period_timer();
+            }
+            return;
+        }
+
+        let num_periods = clock_period_timer(&mut self.clock_timer, period, num_clocks);
+
+        // Each bit of the LFSR depends on the last, so it can only be clocked one step at a time
+        for _ in 0..num_periods {
+            self.clock_lfsr();
+        }
+    }
+
+    /// Advance the LFSR by one bit. The XNOR of bits 0 and 1 is written to bit 15, and also to bit
+    /// 7 for the narrow LFSR, then the LFSR is shifted right. Bit 0 becomes the current sample bit.
+    ///
+    /// The narrow LFSR still writes bit 15 so that the upper bits are intact if the width is
+    /// switched back to wide.
+    fn clock_lfsr(&mut self) {
+        let new_bit = !(self.lfsr ^ (self.lfsr >> 1)) & 0x1;
+
+        self.lfsr = (self.lfsr & 0x7FFF) | (new_bit << 15);
+        if !self.is_lfsr_wide {
+            self.lfsr = (self.lfsr & !0x80) | (new_bit << 7);
+        }
+
+        self.lfsr >>= 1;
+        self.current_sample_bit = self.lfsr & 0x1 != 0;
+    }
+
     fn advance_length_timer(&mut self) {
         if self.is_length_timer_enabled && self.length_timer > 0 {
             self.length_timer -= 1;
@@ -1325,3 +1646,350 @@ impl NoiseChannel {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        address_space::WAVE_RAM_START,
+        emulator::{DEFAULT_TURBO_SPEED, TICKS_PER_FRAME},
+        machine::Machine,
+        shared_stats::SharedStats,
+        test_utils::{new_test_emulator, with_large_stack},
+    };
+
+    use super::{
+        AudioFrame, AudioOutput, BufferedSource, DEFAULT_AUDIO_LATENCY_FRAMES, HighPassFilter,
+        MAX_FILL_MULTIPLE, MAX_PLAYBACK_RATE_ADJUSTMENT, NoiseChannel, Resampler, SAMPLE_RATE,
+        SAMPLES_PER_FRAME, TICKS_PER_SAMPLE, TimedSample, TurboAudio, clock_period_timer,
+        shared_audio_channel,
+    };
+
+    /// Frames sampled the same way as the emulator, where each sample's value is its index in the
+    /// overall sample stream.
+    struct RampGenerator {
+        next_value: f32,
+    }
+
+    impl RampGenerator {
+        fn new() -> Self {
+            Self { next_value: 0.0 }
+        }
+
+        fn next_frame(&mut self) -> AudioFrame {
+            let mut frame = Vec::new();
+            for tick in 0..TICKS_PER_FRAME as u32 {
+                if tick.is_multiple_of(TICKS_PER_SAMPLE as u32) {
+                    frame.push(TimedSample {
+                        left: self.next_value,
+                        right: self.next_value,
+                        tick,
+                    });
+                    self.next_value += 1.0;
+                }
+            }
+            frame
+        }
+    }
+
+    /// Play audio through a buffered source while sending `speed` frames per played frame, and
+    /// check that the played samples never jump backwards or skip ahead by more than the speed.
+    fn assert_playback_is_continuous(speed: f64) {
+        let (sender, receiver) = shared_audio_channel();
+        let mut source = BufferedSource::new(receiver, 2, TurboAudio::Pitch);
+        let mut generator = RampGenerator::new();
+
+        sender.set_speed(speed);
+        for _ in 0..(speed * 2.0).ceil() as usize {
+            sender.send_frame(generator.next_frame());
+        }
+
+        // Frames owed to the source at fractional speeds
+        let mut num_frames_owed = 0.0;
+
+        let mut last_value = None;
+
+        for played_frame_number in 0..30 {
+            for _ in 0..SAMPLES_PER_FRAME as usize {
+                let left = source.next().unwrap();
+                let right = source.next().unwrap();
+                assert_eq!(left, right);
+
+                // Skip samples played while the initial backlog of frames is drained
+                if played_frame_number > 5 {
+                    if let Some(last_value) = last_value {
+                        assert!(left >= last_value, "{} follows {}", left, last_value);
+                        assert!(left - last_value <= 1.01 * speed as f32);
+                        assert!(!source.resampler.is_buffering);
+                    }
+
+                    last_value = Some(left);
+                }
+            }
+
+            num_frames_owed += speed;
+            while num_frames_owed >= 1.0 {
+                sender.send_frame(generator.next_frame());
+                num_frames_owed -= 1.0;
+            }
+        }
+    }
+
+    #[test]
+    fn playback_is_continuous_at_normal_speed() {
+        assert_playback_is_continuous(1.0);
+    }
+
+    #[test]
+    fn playback_is_continuous_in_turbo_mode() {
+        assert_playback_is_continuous(DEFAULT_TURBO_SPEED);
+    }
+
+    #[test]
+    fn playback_is_continuous_at_fractional_speeds() {
+        for speed in [0.5, 0.75, 1.5, 2.0] {
+            assert_playback_is_continuous(speed);
+        }
+    }
+
+    #[test]
+    fn buffered_source_reports_fill_level() {
+        let (sender, receiver) = shared_audio_channel();
+        let mut source = BufferedSource::new(receiver, 2, TurboAudio::Pitch);
+        let shared_stats = Arc::new(SharedStats::new());
+        source.shared_stats = Some(shared_stats.clone());
+        let mut generator = RampGenerator::new();
+
+        assert_eq!(shared_stats.audio_buffer_fill_percent(), None);
+
+        // Each frame is half of the two frame target. Nothing is played while buffering.
+        for expected_fill_percent in [50.0, 100.0] {
+            sender.send_frame(generator.next_frame());
+            source.next();
+
+            let fill_percent = shared_stats.audio_buffer_fill_percent().unwrap();
+            assert!(
+                (fill_percent - expected_fill_percent).abs() < 1.0,
+                "{}% full, expected {}%",
+                fill_percent,
+                expected_fill_percent
+            );
+        }
+    }
+
+    /// A 440 Hz square wave at the playback sample rate.
+    fn square_wave_frame(start_index: usize, num_samples: usize) -> AudioFrame {
+        (start_index..start_index + num_samples)
+            .map(|index| {
+                let phase = (index as f64 * 440.0 / SAMPLE_RATE as f64).fract();
+                let value = if phase < 0.5 { 1.0 } else { -1.0 };
+                TimedSample {
+                    left: value,
+                    right: value,
+                    tick: 0,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resampler_fill_level_stays_bounded_with_drifting_input_rate() {
+        let mut resampler = Resampler::new(DEFAULT_AUDIO_LATENCY_FRAMES, TurboAudio::Pitch);
+        let target_fill = resampler.target_fill;
+
+        let mut num_input_samples = 0.0;
+        let mut num_pushed_samples = 0;
+
+        for frame_number in 0..3000 {
+            // The input rate drifts between 0.3% slower and 0.3% faster than the playback rate
+            let drift = 0.003 * (frame_number as f64 / 200.0).sin();
+            num_input_samples += SAMPLES_PER_FRAME * (1.0 + drift);
+
+            let num_samples = num_input_samples as usize - num_pushed_samples;
+            resampler.push_frame(&square_wave_frame(num_pushed_samples, num_samples));
+            num_pushed_samples += num_samples;
+
+            for _ in 0..SAMPLES_PER_FRAME as usize {
+                let (left, right) = resampler.next_sample();
+                assert!((-1.0..=1.0).contains(&left));
+                assert_eq!(left, right);
+            }
+
+            if frame_number > 10 {
+                let fill = resampler.buffer.len();
+                assert!(!resampler.is_buffering);
+                assert!(
+                    (target_fill / 4..=target_fill * 2).contains(&fill),
+                    "fill level {} for target {} at frame {}",
+                    fill,
+                    target_fill,
+                    frame_number
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn resampler_drops_samples_in_turbo_mode() {
+        let mut resampler = Resampler::new(DEFAULT_AUDIO_LATENCY_FRAMES, TurboAudio::Drop);
+        resampler.set_speed(DEFAULT_TURBO_SPEED);
+
+        let frame = square_wave_frame(0, SAMPLES_PER_FRAME as usize);
+        for _ in 0..100 {
+            for _ in 0..DEFAULT_TURBO_SPEED as usize {
+                resampler.push_frame(&frame);
+            }
+
+            for _ in 0..SAMPLES_PER_FRAME as usize {
+                resampler.next_sample();
+            }
+
+            assert!(resampler.buffer.len() <= resampler.target_fill * MAX_FILL_MULTIPLE);
+            assert!(resampler.playback_rate() <= 1.0 + MAX_PLAYBACK_RATE_ADJUSTMENT);
+        }
+    }
+
+    /// Reference model of the noise LFSR using the equivalent formulation with a 15 bit register
+    /// that is all ones at reset. The XOR of bits 0 and 1 is shifted in at bit 14, and also at bit 6
+    /// in narrow mode, and the output is the complement of bit 0.
+    struct ReferenceLfsr {
+        lfsr: u16,
+    }
+
+    impl ReferenceLfsr {
+        fn new() -> Self {
+            Self { lfsr: 0x7FFF }
+        }
+
+        fn clock(&mut self, is_narrow: bool) -> bool {
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 0x1;
+            let feedback_mask = if is_narrow { 0x4040 } else { 0x4000 };
+
+            self.lfsr >>= 1;
+            if feedback != 0 {
+                self.lfsr |= feedback_mask;
+            } else {
+                self.lfsr &= !feedback_mask;
+            }
+
+            self.lfsr & 0x1 == 0
+        }
+    }
+
+    #[test]
+    fn noise_lfsr_matches_reference() {
+        let mut channel = NoiseChannel::new();
+        channel.trigger();
+        let mut reference = ReferenceLfsr::new();
+
+        // Wide, then narrow, then back to wide to check that switching width keeps the upper bits
+        for (nr43, num_clocks) in [(0x00, 5000), (0x08, 3000), (0x00, 3000)] {
+            channel.write_nr43(nr43);
+            let is_narrow = nr43 & 0x08 != 0;
+
+            for i in 0..num_clocks {
+                channel.clock_lfsr();
+                let expected = reference.clock(is_narrow);
+                assert_eq!(
+                    channel.current_sample_bit, expected,
+                    "NR43={:02X} clock {}",
+                    nr43, i
+                );
+            }
+        }
+
+        // The narrow LFSR repeats every 127 bits and the wide LFSR every 32767 bits
+        let mut channel = NoiseChannel::new();
+        channel.write_nr43(0x08);
+        channel.trigger();
+        let bits: Vec<bool> = (0..254)
+            .map(|_| {
+                channel.clock_lfsr();
+                channel.current_sample_bit
+            })
+            .collect();
+        assert_eq!(bits[..127], bits[127..]);
+    }
+
+    #[test]
+    fn high_pass_filter_removes_dc_offset() {
+        let mut hpf = HighPassFilter::new();
+
+        // A constant input passes through at first, then decays towards zero within a tenth of a
+        // second
+        let mut last_output = hpf.apply(1.0);
+        assert_eq!(last_output, 1.0);
+
+        for _ in 0..SAMPLE_RATE / 10 {
+            let output = hpf.apply(1.0);
+            assert!(output <= last_output);
+            last_output = output;
+        }
+
+        assert!(last_output.abs() < 0.001);
+    }
+
+    #[test]
+    fn wave_channel_plays_wave_ram_written_through_bus() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &[]);
+
+            // Two ramps from 0 to 15
+            for i in 0..16 {
+                let first_sample = (i % 8) * 2;
+                emulator.write_address(
+                    WAVE_RAM_START + i,
+                    (first_sample << 4) as u8 | (first_sample + 1) as u8,
+                );
+            }
+
+            // Reads come from the wave channel
+            assert_eq!(emulator.read_address(WAVE_RAM_START + 1), 0x23);
+
+            emulator.write_address(0xFF1A, 0x80); // DAC on
+            emulator.write_address(0xFF1C, 0x20); // Full volume
+            emulator.write_address(0xFF1D, 0xF0); // Period of 16
+            emulator.write_address(0xFF1E, 0x87); // Trigger
+
+            let mut samples = vec![emulator.apu().channel_3().sample_digital()];
+            let mut tick = 0;
+            while samples.len() < 32 {
+                emulator.apu_mut().advance_period_timers(tick);
+                tick += 1;
+
+                let sample = emulator.apu().channel_3().sample_digital();
+                if sample != *samples.last().unwrap() {
+                    samples.push(sample);
+                }
+            }
+
+            let expected: Vec<u8> = (0..32).map(|i| i % 16).collect();
+            assert_eq!(samples, expected);
+        });
+    }
+
+    #[test]
+    fn clock_period_timer_in_bulk() {
+        for period in [1, 3, 8] {
+            for initial_timer in 0..10 {
+                for num_clocks in 0..30 {
+                    // Clock one at a time, the same way the channels do
+                    let mut expected_timer = initial_timer;
+                    let mut expected_periods = 0;
+                    for _ in 0..num_clocks {
+                        if expected_timer == 0 {
+                            expected_periods += 1;
+                            expected_timer = period;
+                        }
+                        expected_timer -= 1;
+                    }
+
+                    let mut timer = initial_timer;
+                    let num_periods = clock_period_timer(&mut timer, period, num_clocks);
+                    assert_eq!((timer, num_periods), (expected_timer, expected_periods));
+                }
+            }
+        }
+    }
+}
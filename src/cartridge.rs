@@ -1,10 +1,17 @@
-use std::fmt;
+use std::{fmt, ops::Range, sync::Arc};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    address_space::{ROM_BANK_SIZE, SINGLE_EXTERNAL_RAM_BANK_SIZE},
+    address_space::{
+        EXTERNAL_RAM_START, MBC2_RAM_SIZE, ROM_BANK_SIZE, SINGLE_EXTERNAL_RAM_BANK_SIZE,
+    },
+    error::Error,
+    licensee::{USE_NEW_LICENSEE_CODE, new_licensee_publisher, old_licensee_publisher},
     mbc::types::{Mbc, MbcKind, create_mbc},
+    save_compat,
+    save_file::SaveFileError,
+    symbols::BankedAddress,
 };
 
 struct Scanner<'a> {
@@ -39,18 +46,34 @@ impl<'a> Scanner<'a> {
     }
 }
 
+/// Bitmap that every ROM header must contain at 0x0104 to pass the boot ROM's logo check.
 #[rustfmt::skip]
-const NINTENDO_LOGO: [u8; 48] = [
+pub const NINTENDO_LOGO: [u8; 48] = [
     0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
     0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
     0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
 ];
 
+const NINTENDO_LOGO_START: usize = 0x0104;
+
+/// End of the cartridge header. Every ROM is at least this long.
+const HEADER_END: usize = 0x0150;
+
+const HEADER_CHECKSUM_ADDRESS: usize = 0x014D;
+
+/// Big endian sum of the rest of the ROM, which is not checked by hardware
+const GLOBAL_CHECKSUM_RANGE: Range<usize> = 0x014E..0x0150;
+
 #[derive(Serialize, Deserialize)]
 pub struct Cartridge {
-    /// Raw ROM data
-    #[serde(with = "serde_bytes")]
-    rom: Vec<u8>,
+    /// Raw ROM data. The ROM never changes so it is shared rather than copied, and is not
+    /// serialized with the rest of the cartridge state. It must be reattached with `attach_rom`
+    /// after deserializing.
+    #[serde(skip, default = "empty_rom")]
+    rom: Arc<[u8]>,
+
+    /// Checksum of the ROM, used to check that deserialized state is reattached to the same ROM
+    rom_checksum: u32,
 
     /// RAM for this cartridge
     #[serde(with = "serde_bytes")]
@@ -79,8 +102,25 @@ impl Cartridge {
         &self.rom
     }
 
-    pub fn rom_mut(&mut self) -> &mut [u8] {
-        &mut self.rom
+    /// Shared handle to the ROM, for storing the ROM alongside serialized cartridge state.
+    pub fn shared_rom(&self) -> Arc<[u8]> {
+        self.rom.clone()
+    }
+
+    /// Checksum of the ROM, identifying which ROM serialized state belongs to.
+    pub fn rom_checksum(&self) -> u32 {
+        self.rom_checksum
+    }
+
+    /// Attach the ROM to cartridge state that was deserialized without it. Fails if the ROM is not
+    /// the one the state was serialized with.
+    pub fn attach_rom(&mut self, rom: Arc<[u8]>) -> Result<(), SaveFileError> {
+        if save_compat::checksum(&rom) != self.rom_checksum {
+            return Err(SaveFileError::RomMismatch);
+        }
+
+        self.rom = rom;
+        Ok(())
     }
 
     pub fn ram(&self) -> &[u8] {
@@ -107,7 +147,46 @@ impl Cartridge {
         self.cgb_byte & 0x80 != 0
     }
 
-    pub fn new_from_rom_bytes(rom_bytes: Vec<u8>) -> Self {
+    /// Whether the cartridge has a battery, so that its RAM (and RTC) persist when powered off.
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type_byte,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+        )
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        matches!(self.cartridge_type_byte, 0x0F | 0x10)
+    }
+
+    /// Size of external RAM as declared in the header, or the RAM built into an MBC2. May be
+    /// smaller than the RAM that is actually allocated, since cartridges that declare no RAM are
+    /// still given a RAM bank.
+    pub fn header_ram_size(&self) -> usize {
+        if self.mbc.kind() == MbcKind::Mbc2 {
+            return MBC2_RAM_SIZE;
+        }
+
+        match self.rom[0x0149] {
+            0x02 => SINGLE_EXTERNAL_RAM_BANK_SIZE,
+            0x03 => 4 * SINGLE_EXTERNAL_RAM_BANK_SIZE,
+            0x04 => 16 * SINGLE_EXTERNAL_RAM_BANK_SIZE,
+            0x05 => 8 * SINGLE_EXTERNAL_RAM_BANK_SIZE,
+            _ => 0,
+        }
+    }
+
+    pub fn new_from_rom_bytes(rom_bytes: Vec<u8>) -> Result<Self, Error> {
+        if rom_bytes.len() < HEADER_END {
+            return Err(Error::RomTooSmall {
+                size: rom_bytes.len(),
+            });
+        }
+
+        // Check the header checksum first, so that files that are not ROMs at all are reported as
+        // such instead of by whichever header field happens to be invalid
+        Self::validate_header_checksum(&rom_bytes)?;
+
         let mut scanner = Scanner::new(&rom_bytes);
 
         // Header starts at 0x0100
@@ -116,9 +195,9 @@ impl Cartridge {
         // Entry point code (4 bytes)
         let entry_point_code = scanner.read_bytes(4).try_into().unwrap();
 
-        // Must be followed by a bitmap of the Nintendo logo (48 bytes)
-        let nintendo_logo = scanner.read_bytes(NINTENDO_LOGO.len());
-        assert_eq!(nintendo_logo, NINTENDO_LOGO);
+        // Followed by a bitmap of the Nintendo logo (48 bytes). Some homebrew and test ROMs have an
+        // invalid logo on purpose, which is allowed since only the boot ROM checks it.
+        scanner.skip(NINTENDO_LOGO.len());
 
         // Title is ended by a null byte (16 bytes long)
         let title_bytes = scanner.read_bytes(11);
@@ -145,13 +224,24 @@ impl Cartridge {
 
         // ROM size (1 byte)
         let rom_size_byte = scanner.read_u8();
-        assert!(rom_size_byte <= 0x08, "Unsupported ROM size");
+        if rom_size_byte > 0x08 {
+            return Err(Error::UnsupportedRomSize(rom_size_byte));
+        }
 
         let rom_size = (2 * ROM_BANK_SIZE) << rom_size_byte;
-        assert_eq!(rom_bytes.len(), rom_size, "ROM size mismatch");
+        if rom_bytes.len() != rom_size {
+            return Err(Error::RomSizeMismatch {
+                size: rom_bytes.len(),
+                expected: rom_size,
+            });
+        }
 
         // Create MBC for this cartridge type
-        let mbc_kind = Self::mbc_kind_for_cartridge_type(cartridge_type_byte);
+        let mut mbc_kind = Self::mbc_kind_for_cartridge_type(cartridge_type_byte)
+            .ok_or(Error::UnsupportedMbc(cartridge_type_byte))?;
+        if mbc_kind == MbcKind::Mbc1 && Self::is_mbc1_multicart(&rom_bytes) {
+            mbc_kind = MbcKind::Mbc1Multicart;
+        }
 
         // RAM size (1 byte)
         let ram_size_byte = scanner.read_u8();
@@ -163,7 +253,7 @@ impl Cartridge {
             0x03 => 4 * SINGLE_EXTERNAL_RAM_BANK_SIZE,
             0x04 => 16 * SINGLE_EXTERNAL_RAM_BANK_SIZE,
             0x05 => 8 * SINGLE_EXTERNAL_RAM_BANK_SIZE,
-            _ => panic!("Unsupported RAM size"),
+            _ => return Err(Error::UnsupportedRamSize(ram_size_byte)),
         };
 
         let mbc = create_mbc(mbc_kind, rom_size, ram_size);
@@ -174,6 +264,11 @@ impl Cartridge {
             ram_size = 8 * 1024;
         }
 
+        // MBC2 has RAM built in, and cartridges always declare no RAM in the header
+        if mbc_kind == MbcKind::Mbc2 {
+            ram_size = MBC2_RAM_SIZE;
+        }
+
         let ram = vec![0; ram_size];
 
         // Skip destination code (1 byte)
@@ -185,41 +280,90 @@ impl Cartridge {
         // Skip mask ROM version number (1 byte)
         scanner.skip(1);
 
-        // Header checksum (1 byte)
-        let header_checksum = scanner.read_u8();
-        Self::validate_header_checksum(&rom_bytes, header_checksum);
+        // Skip header checksum (1 byte), which was already validated
+        scanner.skip(1);
 
         // Skip global checksum (2 bytes)
         scanner.skip(2);
 
-        assert_eq!(scanner.pos, 0x0150, "Unexpected header size");
+        assert_eq!(scanner.pos, HEADER_END, "Unexpected header size");
 
-        Cartridge {
-            rom: rom_bytes,
+        Ok(Cartridge {
+            rom_checksum: save_compat::checksum(&rom_bytes),
+            rom: rom_bytes.into(),
             ram,
             mbc,
             entry_point_code,
             title,
             cartridge_type_byte,
             cgb_byte,
-        }
+        })
     }
 
-    fn validate_header_checksum(data: &[u8], checksum: u8) {
-        // Checksum over header bytes 0x0134..=0x014C
+    /// Checksum over header bytes 0x0134..=0x014C, which must be stored at 0x014D
+    pub fn compute_header_checksum(data: &[u8]) -> u8 {
         let mut sum: u8 = 0;
         for byte in data.iter().take(0x014C + 1).skip(0x0134) {
             sum = sum.wrapping_sub(*byte).wrapping_sub(1);
         }
-        assert_eq!(sum, checksum, "Header checksum mismatch");
+        sum
     }
 
-    fn mbc_kind_for_cartridge_type(cartridge_type: u8) -> MbcKind {
+    fn validate_header_checksum(data: &[u8]) -> Result<(), Error> {
+        let found = data[HEADER_CHECKSUM_ADDRESS];
+        let expected = Self::compute_header_checksum(data);
+        if found != expected {
+            return Err(Error::HeaderChecksumMismatch { found, expected });
+        }
+
+        Ok(())
+    }
+
+    /// The parsed header. Only valid once the ROM is attached.
+    pub fn header(&self) -> CartridgeHeader {
+        CartridgeHeader::parse(&self.rom).expect("loaded ROMs contain a full header")
+    }
+
+    /// Whether the header contains the Nintendo logo. The boot ROM locks up on ROMs without it, so
+    /// they would not boot on real hardware.
+    pub fn has_valid_logo(&self) -> bool {
+        Self::has_nintendo_logo(&self.rom)
+    }
+
+    fn has_nintendo_logo(bytes: &[u8]) -> bool {
+        bytes[NINTENDO_LOGO_START..NINTENDO_LOGO_START + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+    }
+
+    /// Whether a file's contents look like a ROM: a full header containing the Nintendo logo and a
+    /// matching header checksum. Used for files that do not have a ROM file extension.
+    pub fn looks_like_rom(bytes: &[u8]) -> bool {
+        if bytes.len() < HEADER_END {
+            return false;
+        }
+
+        Self::has_nintendo_logo(bytes)
+            && bytes[HEADER_CHECKSUM_ADDRESS] == Self::compute_header_checksum(bytes)
+    }
+
+    /// MBC1 multicarts are 1MB and have a copy of the header, including the Nintendo logo, at the
+    /// start of each 256KB game. The menu is the game in bank 0.
+    fn is_mbc1_multicart(rom_bytes: &[u8]) -> bool {
+        const MULTICART_SIZE: usize = 64 * ROM_BANK_SIZE;
+        const SECOND_GAME_LOGO_START: usize = 0x10 * ROM_BANK_SIZE + NINTENDO_LOGO_START;
+
+        rom_bytes.len() == MULTICART_SIZE
+            && rom_bytes[SECOND_GAME_LOGO_START..SECOND_GAME_LOGO_START + NINTENDO_LOGO.len()]
+                == NINTENDO_LOGO
+    }
+
+    fn mbc_kind_for_cartridge_type(cartridge_type: u8) -> Option<MbcKind> {
         match cartridge_type {
-            0x00 => MbcKind::None,
-            0x01..=0x03 => MbcKind::Mbc1,
-            0x0F..=0x13 => MbcKind::Mbc3,
-            _ => panic!("Unsupported cartridge type: 0x{:02X}", cartridge_type),
+            0x00 => Some(MbcKind::None),
+            0x01..=0x03 => Some(MbcKind::Mbc1),
+            0x05 | 0x06 => Some(MbcKind::Mbc2),
+            0x0F..=0x13 => Some(MbcKind::Mbc3),
+            0x19..=0x1E => Some(MbcKind::Mbc5),
+            _ => None,
         }
     }
 }
@@ -233,6 +377,7 @@ impl fmt::Debug for Cartridge {
   title: {},
   cartridge_type_byte: {:02X},
   is_cgb: {},
+  has_valid_logo: {},
   rom_size: {},
   ram_size: {},
 }}",
@@ -240,10 +385,724 @@ impl fmt::Debug for Cartridge {
             self.title,
             self.cartridge_type_byte,
             self.is_cgb(),
+            self.has_valid_logo(),
             self.rom.len(),
             self.ram.len()
         )
     }
 }
 
+/// The fields of the cartridge header at 0x0100-0x014F, parsed into types.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    /// Title with trailing padding removed. Not valid UTF-8 in general, so invalid bytes are
+    /// replaced.
+    pub title: String,
+    /// Four character code that newer CGB cartridges store in the last bytes of the title area
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: CgbFlag,
+    pub licensee: Licensee,
+    /// Whether the cartridge supports SGB functions
+    pub sgb_flag: bool,
+    pub cartridge_type: CartridgeType,
+    /// ROM size in bytes, or `None` if the ROM size byte is not a known size
+    pub rom_size: Option<usize>,
+    /// External RAM size in bytes, or `None` if the RAM size byte is not a known size
+    pub ram_size: Option<usize>,
+    pub destination: Destination,
+    /// Mask ROM version number
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+
+    /// Checksums computed from the ROM, to compare against the ones stored in the header
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgbFlag {
+    /// Runs in DMG mode on a CGB
+    DmgOnly,
+    /// Runs in CGB mode on a CGB and also runs on a DMG
+    CgbCompatible,
+    /// Only runs on a CGB
+    CgbOnly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LicenseeCode {
+    /// Single byte code at 0x014B
+    Old(u8),
+    /// Two character ASCII code at 0x0144-0x0145, used when the old code is 0x33
+    New([u8; 2]),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Licensee {
+    pub code: LicenseeCode,
+    /// Name of the publisher, if the code is a known one
+    pub publisher: Option<&'static str>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+    Unknown(u8),
+}
+
+impl CartridgeType {
+    pub fn from_byte(byte: u8) -> Self {
+        match byte {
+            0x00 => CartridgeType::RomOnly,
+            0x01 => CartridgeType::Mbc1,
+            0x02 => CartridgeType::Mbc1Ram,
+            0x03 => CartridgeType::Mbc1RamBattery,
+            0x05 => CartridgeType::Mbc2,
+            0x06 => CartridgeType::Mbc2Battery,
+            0x08 => CartridgeType::RomRam,
+            0x09 => CartridgeType::RomRamBattery,
+            0x0B => CartridgeType::Mmm01,
+            0x0C => CartridgeType::Mmm01Ram,
+            0x0D => CartridgeType::Mmm01RamBattery,
+            0x0F => CartridgeType::Mbc3TimerBattery,
+            0x10 => CartridgeType::Mbc3TimerRamBattery,
+            0x11 => CartridgeType::Mbc3,
+            0x12 => CartridgeType::Mbc3Ram,
+            0x13 => CartridgeType::Mbc3RamBattery,
+            0x19 => CartridgeType::Mbc5,
+            0x1A => CartridgeType::Mbc5Ram,
+            0x1B => CartridgeType::Mbc5RamBattery,
+            0x1C => CartridgeType::Mbc5Rumble,
+            0x1D => CartridgeType::Mbc5RumbleRam,
+            0x1E => CartridgeType::Mbc5RumbleRamBattery,
+            0x20 => CartridgeType::Mbc6,
+            0x22 => CartridgeType::Mbc7SensorRumbleRamBattery,
+            0xFC => CartridgeType::PocketCamera,
+            0xFD => CartridgeType::BandaiTama5,
+            0xFE => CartridgeType::HuC3,
+            0xFF => CartridgeType::HuC1RamBattery,
+            _ => CartridgeType::Unknown(byte),
+        }
+    }
+}
+
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CartridgeType::RomOnly => "ROM ONLY",
+            CartridgeType::Mbc1 => "MBC1",
+            CartridgeType::Mbc1Ram => "MBC1+RAM",
+            CartridgeType::Mbc1RamBattery => "MBC1+RAM+BATTERY",
+            CartridgeType::Mbc2 => "MBC2",
+            CartridgeType::Mbc2Battery => "MBC2+BATTERY",
+            CartridgeType::RomRam => "ROM+RAM",
+            CartridgeType::RomRamBattery => "ROM+RAM+BATTERY",
+            CartridgeType::Mmm01 => "MMM01",
+            CartridgeType::Mmm01Ram => "MMM01+RAM",
+            CartridgeType::Mmm01RamBattery => "MMM01+RAM+BATTERY",
+            CartridgeType::Mbc3TimerBattery => "MBC3+TIMER+BATTERY",
+            CartridgeType::Mbc3TimerRamBattery => "MBC3+TIMER+RAM+BATTERY",
+            CartridgeType::Mbc3 => "MBC3",
+            CartridgeType::Mbc3Ram => "MBC3+RAM",
+            CartridgeType::Mbc3RamBattery => "MBC3+RAM+BATTERY",
+            CartridgeType::Mbc5 => "MBC5",
+            CartridgeType::Mbc5Ram => "MBC5+RAM",
+            CartridgeType::Mbc5RamBattery => "MBC5+RAM+BATTERY",
+            CartridgeType::Mbc5Rumble => "MBC5+RUMBLE",
+            CartridgeType::Mbc5RumbleRam => "MBC5+RUMBLE+RAM",
+            CartridgeType::Mbc5RumbleRamBattery => "MBC5+RUMBLE+RAM+BATTERY",
+            CartridgeType::Mbc6 => "MBC6",
+            CartridgeType::Mbc7SensorRumbleRamBattery => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            CartridgeType::PocketCamera => "POCKET CAMERA",
+            CartridgeType::BandaiTama5 => "BANDAI TAMA5",
+            CartridgeType::HuC3 => "HuC3",
+            CartridgeType::HuC1RamBattery => "HuC1+RAM+BATTERY",
+            CartridgeType::Unknown(byte) => return write!(f, "Unknown (0x{:02X})", byte),
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl CartridgeHeader {
+    /// Parse the header of a ROM. Only fails if the ROM is too small to contain a header, since
+    /// unknown values are kept as-is instead of being rejected.
+    pub fn parse(rom: &[u8]) -> Result<Self, Error> {
+        if rom.len() < HEADER_END {
+            return Err(Error::RomTooSmall { size: rom.len() });
+        }
+
+        let cgb_byte = rom[0x0143];
+        let cgb_flag = if cgb_byte & 0x80 == 0 {
+            CgbFlag::DmgOnly
+        } else if cgb_byte & 0x40 == 0 {
+            CgbFlag::CgbCompatible
+        } else {
+            CgbFlag::CgbOnly
+        };
+
+        // The title is 16 bytes on DMG cartridges and 15 bytes on CGB cartridges, where the last
+        // byte is the CGB flag. Newer CGB cartridges end the title after 11 bytes and have a
+        // manufacturer code of 4 uppercase characters in the remaining bytes.
+        let manufacturer_code_bytes = &rom[0x013F..0x0143];
+        let has_manufacturer_code = cgb_flag != CgbFlag::DmgOnly
+            && manufacturer_code_bytes
+                .iter()
+                .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+
+        let title_end = match cgb_flag {
+            _ if has_manufacturer_code => 0x013F,
+            CgbFlag::DmgOnly => 0x0144,
+            CgbFlag::CgbCompatible | CgbFlag::CgbOnly => 0x0143,
+        };
+        let title_bytes = &rom[0x0134..title_end];
+        let title_len = title_bytes
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(title_bytes.len());
+        let title = String::from_utf8_lossy(&title_bytes[..title_len])
+            .trim()
+            .to_string();
+
+        let manufacturer_code = has_manufacturer_code
+            .then(|| String::from_utf8_lossy(manufacturer_code_bytes).into_owned());
+
+        let old_licensee_code = rom[0x014B];
+        let licensee = if old_licensee_code == USE_NEW_LICENSEE_CODE {
+            let code = [rom[0x0144], rom[0x0145]];
+            Licensee {
+                code: LicenseeCode::New(code),
+                publisher: new_licensee_publisher(code),
+            }
+        } else {
+            Licensee {
+                code: LicenseeCode::Old(old_licensee_code),
+                publisher: old_licensee_publisher(old_licensee_code),
+            }
+        };
+
+        let rom_size_byte = rom[0x0148];
+        let rom_size = (rom_size_byte <= 0x08).then(|| (2 * ROM_BANK_SIZE) << rom_size_byte);
+
+        let ram_size = match rom[0x0149] {
+            0x00 => Some(0),
+            0x01 => Some(2 * 1024),
+            0x02 => Some(SINGLE_EXTERNAL_RAM_BANK_SIZE),
+            0x03 => Some(4 * SINGLE_EXTERNAL_RAM_BANK_SIZE),
+            0x04 => Some(16 * SINGLE_EXTERNAL_RAM_BANK_SIZE),
+            0x05 => Some(8 * SINGLE_EXTERNAL_RAM_BANK_SIZE),
+            _ => None,
+        };
+
+        let destination = match rom[0x014A] {
+            0x00 => Destination::Japan,
+            0x01 => Destination::Overseas,
+            byte => Destination::Unknown(byte),
+        };
+
+        Ok(CartridgeHeader {
+            title,
+            manufacturer_code,
+            cgb_flag,
+            licensee,
+            sgb_flag: rom[0x0146] == 0x03,
+            cartridge_type: CartridgeType::from_byte(rom[0x0147]),
+            rom_size,
+            ram_size,
+            destination,
+            version: rom[0x014C],
+            header_checksum: rom[HEADER_CHECKSUM_ADDRESS],
+            global_checksum: u16::from_be_bytes([rom[0x014E], rom[0x014F]]),
+            computed_header_checksum: Cartridge::compute_header_checksum(rom),
+            computed_global_checksum: Self::compute_global_checksum(rom),
+        })
+    }
+
+    /// 16-bit sum of every byte in the ROM other than the global checksum itself
+    pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|(i, _)| !GLOBAL_CHECKSUM_RANGE.contains(i))
+            .fold(0u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16))
+    }
+
+    /// Whether the header checksum matches the header. The boot ROM refuses to run ROMs where it
+    /// does not.
+    pub fn verify_header_checksum(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    /// Whether the global checksum matches the ROM. Not checked by hardware, so some ROMs (homebrew
+    /// especially) have an incorrect global checksum.
+    pub fn verify_global_checksum(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    pub fn computed_header_checksum(&self) -> u8 {
+        self.computed_header_checksum
+    }
+
+    pub fn computed_global_checksum(&self) -> u16 {
+        self.computed_global_checksum
+    }
+
+    /// The header as a JSON object, for tools that read `--dump-rom-info-json`.
+    pub fn to_json(&self) -> String {
+        let (licensee_code_kind, licensee_code) = match self.licensee.code {
+            LicenseeCode::Old(code) => ("old", format!("{:02X}", code)),
+            LicenseeCode::New(code) => ("new", String::from_utf8_lossy(&code).into_owned()),
+        };
+
+        let header = HeaderInfo {
+            title: &self.title,
+            manufacturer_code: self.manufacturer_code.as_deref(),
+            cgb_flag: self.cgb_flag.name(),
+            licensee_code_kind,
+            licensee_code,
+            publisher: self.licensee.publisher,
+            sgb_flag: self.sgb_flag,
+            cartridge_type: self.cartridge_type.to_string(),
+            rom_size: self.rom_size,
+            ram_size: self.ram_size,
+            destination: self.destination.to_string(),
+            version: self.version,
+            header_checksum: self.header_checksum,
+            header_checksum_valid: self.verify_header_checksum(),
+            global_checksum: self.global_checksum,
+            global_checksum_valid: self.verify_global_checksum(),
+        };
+
+        let mut json = serde_json::to_string_pretty(&header).unwrap();
+        json.push('\n');
+        json
+    }
+}
+
+/// The fields of a header written by `CartridgeHeader::to_json`.
+#[derive(Serialize)]
+struct HeaderInfo<'a> {
+    title: &'a str,
+    manufacturer_code: Option<&'a str>,
+    cgb_flag: &'static str,
+    licensee_code_kind: &'static str,
+    licensee_code: String,
+    publisher: Option<&'static str>,
+    sgb_flag: bool,
+    cartridge_type: String,
+    rom_size: Option<usize>,
+    ram_size: Option<usize>,
+    destination: String,
+    version: u8,
+    header_checksum: u8,
+    header_checksum_valid: bool,
+    global_checksum: u16,
+    global_checksum_valid: bool,
+}
+
+impl CgbFlag {
+    pub fn name(self) -> &'static str {
+        match self {
+            CgbFlag::DmgOnly => "DMG only",
+            CgbFlag::CgbCompatible => "CGB compatible",
+            CgbFlag::CgbOnly => "CGB only",
+        }
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Destination::Japan => write!(f, "Japan"),
+            Destination::Overseas => write!(f, "Overseas"),
+            Destination::Unknown(byte) => write!(f, "Unknown (0x{:02X})", byte),
+        }
+    }
+}
+
+/// Formats the header as a table with one field per line.
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let licensee_code = match self.licensee.code {
+            LicenseeCode::Old(code) => format!("0x{:02X} (old)", code),
+            LicenseeCode::New(code) => format!("{} (new)", String::from_utf8_lossy(&code)),
+        };
+        let size = |size: Option<usize>| match size {
+            Some(size) if size >= 1024 => format!("{} KB", size / 1024),
+            Some(size) => format!("{} bytes", size),
+            None => "Unknown".to_string(),
+        };
+        let checksum_status = |is_valid: bool| if is_valid { "ok" } else { "MISMATCH" };
+
+        let rows = [
+            ("Title", self.title.clone()),
+            (
+                "Manufacturer code",
+                self.manufacturer_code.clone().unwrap_or("-".to_string()),
+            ),
+            ("CGB flag", self.cgb_flag.name().to_string()),
+            ("Licensee code", licensee_code),
+            (
+                "Publisher",
+                self.licensee.publisher.unwrap_or("Unknown").to_string(),
+            ),
+            (
+                "SGB support",
+                if self.sgb_flag { "yes" } else { "no" }.to_string(),
+            ),
+            ("Cartridge type", self.cartridge_type.to_string()),
+            ("ROM size", size(self.rom_size)),
+            ("RAM size", size(self.ram_size)),
+            ("Destination", self.destination.to_string()),
+            ("Version", self.version.to_string()),
+            (
+                "Header checksum",
+                format!(
+                    "0x{:02X} ({})",
+                    self.header_checksum,
+                    checksum_status(self.verify_header_checksum())
+                ),
+            ),
+            (
+                "Global checksum",
+                format!(
+                    "0x{:04X} ({})",
+                    self.global_checksum,
+                    checksum_status(self.verify_global_checksum())
+                ),
+            ),
+        ];
+
+        let name_width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, value) in rows {
+            writeln!(f, "{:<width$}  {}", name, value, width = name_width)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn empty_rom() -> Arc<[u8]> {
+    Arc::new([])
+}
+
 unsafe impl Send for Cartridge {}
+
+/// Offsets of each bank in external RAM of the given size, in bank order. RAM smaller than a bank,
+/// such as 2KB RAM or the RAM built into an MBC2, is a single partial bank.
+pub fn external_ram_banks(ram_size: usize) -> Vec<Range<usize>> {
+    (0..ram_size.div_ceil(SINGLE_EXTERNAL_RAM_BANK_SIZE))
+        .map(|bank| {
+            let start = bank * SINGLE_EXTERNAL_RAM_BANK_SIZE;
+            start..(start + SINGLE_EXTERNAL_RAM_BANK_SIZE).min(ram_size)
+        })
+        .collect()
+}
+
+/// Where an offset into external RAM appears to the CPU: its bank, and its address in
+/// 0xA000-0xBFFF while that bank is mapped.
+pub fn external_ram_location(offset: usize) -> BankedAddress {
+    let bank = offset / SINGLE_EXTERNAL_RAM_BANK_SIZE;
+    let offset_in_bank = offset % SINGLE_EXTERNAL_RAM_BANK_SIZE;
+
+    BankedAddress::new(bank as u16, EXTERNAL_RAM_START + offset_in_bank as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::Error,
+        machine::Machine,
+        save_file::SaveFileError,
+        symbols::BankedAddress,
+        test_utils::{
+            FILL_VRAM_PROGRAM, build_bad_logo_test_rom, build_test_rom, new_test_emulator_builder,
+            with_large_stack, write_header_checksum,
+        },
+    };
+
+    use super::{
+        Cartridge, CartridgeHeader, CartridgeType, CgbFlag, Destination, LicenseeCode,
+        external_ram_banks, external_ram_location,
+    };
+
+    /// Size of a quick save for a ROM, along with the size of the ROM itself.
+    fn snapshot_size(rom_size_byte: u8) -> (usize, usize) {
+        let rom = build_test_rom(0x01, rom_size_byte, 0x00, &FILL_VRAM_PROGRAM);
+        let rom_len = rom.len();
+
+        let emulator = new_test_emulator_builder(Machine::Cgb, rom)
+            .build()
+            .unwrap();
+
+        (rmp_serde::to_vec(&emulator).unwrap().len(), rom_len)
+    }
+
+    #[test]
+    fn snapshots_exclude_rom() {
+        with_large_stack(|| {
+            let (small_snapshot, _) = snapshot_size(0x00);
+            let (large_snapshot, large_rom) = snapshot_size(0x06);
+
+            // Snapshot size does not depend on the size of the ROM
+            assert_eq!(small_snapshot, large_snapshot);
+            assert!(large_snapshot < large_rom / 8);
+        });
+    }
+
+    #[test]
+    fn attach_rom_checks_checksum() {
+        let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+        let cartridge = Cartridge::new_from_rom_bytes(rom.clone()).unwrap();
+        let bytes = rmp_serde::to_vec(&cartridge).unwrap();
+
+        let mut restored: Cartridge = rmp_serde::from_slice(&bytes).unwrap();
+        assert!(restored.rom().is_empty());
+        assert!(restored.attach_rom(rom.clone().into()).is_ok());
+        assert_eq!(restored.rom(), rom.as_slice());
+
+        let mut other_rom = rom;
+        other_rom[0x0200] ^= 0xFF;
+        let mut restored: Cartridge = rmp_serde::from_slice(&bytes).unwrap();
+        assert!(matches!(
+            restored.attach_rom(other_rom.into()),
+            Err(SaveFileError::RomMismatch)
+        ));
+    }
+
+    #[test]
+    fn sniff_rom_contents() {
+        let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+        assert!(Cartridge::looks_like_rom(&rom));
+
+        // Only the header is needed
+        assert!(Cartridge::looks_like_rom(&rom[..0x0150]));
+
+        let mut corrupted_logo = rom.clone();
+        corrupted_logo[0x0110] ^= 0x01;
+        assert!(!Cartridge::looks_like_rom(&corrupted_logo));
+
+        let mut bad_checksum = rom.clone();
+        bad_checksum[0x0134] = b'X';
+        assert!(!Cartridge::looks_like_rom(&bad_checksum));
+
+        assert!(!Cartridge::looks_like_rom(&rom[..0x014F]));
+        assert!(!Cartridge::looks_like_rom(&[]));
+    }
+
+    #[test]
+    fn invalid_roms_are_errors() {
+        let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+        assert!(Cartridge::new_from_rom_bytes(rom.clone()).is_ok());
+
+        // Truncated ROMs
+        assert!(matches!(
+            Cartridge::new_from_rom_bytes(rom[..0x0100].to_vec()),
+            Err(Error::RomTooSmall { size: 0x0100 })
+        ));
+        assert!(matches!(
+            Cartridge::new_from_rom_bytes(rom[..0x4000].to_vec()),
+            Err(Error::RomSizeMismatch {
+                size: 0x4000,
+                expected: 0x8000
+            })
+        ));
+
+        let mut bad_checksum = rom.clone();
+        bad_checksum[0x0134] = b'X';
+        assert!(matches!(
+            Cartridge::new_from_rom_bytes(bad_checksum),
+            Err(Error::HeaderChecksumMismatch { .. })
+        ));
+
+        let with_header_byte = |address: usize, value: u8| {
+            let mut rom = rom.clone();
+            rom[address] = value;
+            rom[0x014D] = Cartridge::compute_header_checksum(&rom);
+            Cartridge::new_from_rom_bytes(rom)
+        };
+
+        assert!(matches!(
+            with_header_byte(0x0147, 0xFC),
+            Err(Error::UnsupportedMbc(0xFC))
+        ));
+        assert!(matches!(
+            with_header_byte(0x0148, 0x09),
+            Err(Error::UnsupportedRomSize(0x09))
+        ));
+        assert!(matches!(
+            with_header_byte(0x0149, 0x06),
+            Err(Error::UnsupportedRamSize(0x06))
+        ));
+    }
+
+    #[test]
+    fn bad_logo_roms_load() {
+        let cartridge =
+            Cartridge::new_from_rom_bytes(build_test_rom(0x00, 0x00, 0x00, &[])).unwrap();
+        assert!(cartridge.has_valid_logo());
+
+        let cartridge = Cartridge::new_from_rom_bytes(build_bad_logo_test_rom(&[])).unwrap();
+        assert!(!cartridge.has_valid_logo());
+        assert!(format!("{:?}", cartridge).contains("has_valid_logo: false"));
+    }
+
+    /// A ROM with the given title area, CGB flag, and licensee bytes, and both checksums fixed up.
+    fn rom_with_header(
+        title_area: &[u8],
+        cgb_flag: u8,
+        new_licensee: &[u8; 2],
+        old_licensee: u8,
+    ) -> Vec<u8> {
+        let mut rom = build_test_rom(0x1B, 0x01, 0x03, &[]);
+        rom[0x0134..0x0143].fill(0);
+        rom[0x0134..0x0134 + title_area.len()].copy_from_slice(title_area);
+        rom[0x0143] = cgb_flag;
+        rom[0x0144..0x0146].copy_from_slice(new_licensee);
+        rom[0x0146] = 0x03;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = old_licensee;
+        rom[0x014C] = 0x02;
+        write_header_checksum(&mut rom);
+
+        let global_checksum = CartridgeHeader::compute_global_checksum(&rom);
+        rom[0x014E..0x0150].copy_from_slice(&global_checksum.to_be_bytes());
+
+        rom
+    }
+
+    #[test]
+    fn header_with_old_licensee_code() {
+        let rom = rom_with_header(b"OLD GAME TITLE!", 0x00, b"\0\0", 0x08);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+
+        assert_eq!(header.title, "OLD GAME TITLE!");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_flag, CgbFlag::DmgOnly);
+        assert_eq!(header.licensee.code, LicenseeCode::Old(0x08));
+        assert_eq!(header.licensee.publisher, Some("Capcom"));
+        assert!(header.sgb_flag);
+        assert_eq!(header.cartridge_type, CartridgeType::Mbc5RamBattery);
+        assert_eq!(header.rom_size, Some(64 * 1024));
+        assert_eq!(header.ram_size, Some(32 * 1024));
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.version, 0x02);
+        assert!(header.verify_header_checksum());
+        assert!(header.verify_global_checksum());
+
+        // Unknown codes have no publisher
+        let rom = rom_with_header(b"GAME", 0x00, b"\0\0", 0x02);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.licensee.code, LicenseeCode::Old(0x02));
+        assert_eq!(header.licensee.publisher, None);
+    }
+
+    #[test]
+    fn header_with_new_licensee_code() {
+        // Title is ended early by a manufacturer code
+        let rom = rom_with_header(b"POKEMON_SLVAAXE", 0x80, b"01", 0x33);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb_flag, CgbFlag::CgbCompatible);
+        assert_eq!(header.licensee.code, LicenseeCode::New(*b"01"));
+        assert_eq!(
+            header.licensee.publisher,
+            Some("Nintendo Research & Development 1")
+        );
+
+        let rom = rom_with_header(b"ZELDA\0lower", 0xC0, b"A4", 0x33);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "ZELDA");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_flag, CgbFlag::CgbOnly);
+        assert_eq!(header.licensee.publisher, Some("Konami (Yu-Gi-Oh!)"));
+
+        let rom = rom_with_header(b"GAME", 0x80, b"ZZ", 0x33);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.licensee.code, LicenseeCode::New(*b"ZZ"));
+        assert_eq!(header.licensee.publisher, None);
+    }
+
+    #[test]
+    fn header_checksums_and_output() {
+        let mut rom = rom_with_header(b"TITLE \"1\"", 0x00, b"\0\0", 0x01);
+        rom[0x0200] ^= 0xFF;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.verify_header_checksum());
+        assert!(!header.verify_global_checksum());
+
+        rom[0x0134] = b'X';
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.verify_header_checksum());
+
+        assert!(header.to_string().contains("Global checksum    0x"));
+        assert!(header.to_string().contains("(MISMATCH)"));
+
+        let json = header.to_json();
+        assert!(json.contains("\"title\": \"XITLE \\\"1\\\"\",\n"));
+        assert!(json.contains("\"publisher\": \"Nintendo\",\n"));
+        assert!(json.ends_with("\"global_checksum_valid\": false\n}\n"));
+
+        assert!(matches!(
+            CartridgeHeader::parse(&rom[..0x014F]),
+            Err(Error::RomTooSmall { size: 0x014F })
+        ));
+    }
+
+    #[test]
+    fn external_ram_bank_boundaries() {
+        // 2KB RAM is a single partial bank
+        assert_eq!(external_ram_banks(0x800), vec![0..0x800]);
+        assert_eq!(external_ram_banks(0x2000), vec![0..0x2000]);
+        assert_eq!(
+            external_ram_banks(0x8000),
+            vec![0..0x2000, 0x2000..0x4000, 0x4000..0x6000, 0x6000..0x8000]
+        );
+        assert!(external_ram_banks(0).is_empty());
+    }
+
+    #[test]
+    fn external_ram_locations() {
+        assert_eq!(external_ram_location(0), BankedAddress::new(0, 0xA000));
+        assert_eq!(external_ram_location(0x7FF), BankedAddress::new(0, 0xA7FF));
+        assert_eq!(external_ram_location(0x1FFF), BankedAddress::new(0, 0xBFFF));
+        assert_eq!(external_ram_location(0x2000), BankedAddress::new(1, 0xA000));
+        assert_eq!(external_ram_location(0x7FFF), BankedAddress::new(3, 0xBFFF));
+    }
+}
@@ -0,0 +1,175 @@
+//! Colorization of DMG games running on a CGB.
+//!
+//! The CGB boot ROM picks palettes for DMG games from a table keyed by a checksum of the title in
+//! the cartridge header. Only games licensed by Nintendo are looked up, all other games get the
+//! default palettes. While in DMG compatibility mode the PPU uses BGP, OBP0, and OBP1 as indices
+//! into background palette 0 and object palettes 0 and 1.
+//!
+//! Only part of the boot ROM's table is included here. Other Nintendo titles get the default
+//! palettes.
+
+const TITLE_START: usize = 0x0134;
+const TITLE_END: usize = 0x0143;
+
+/// The title's fourth character, used to tell apart titles with the same checksum.
+const TITLE_FOURTH_CHARACTER: usize = TITLE_START + 3;
+
+const NEW_LICENSEE_CODE: usize = 0x0144;
+const OLD_LICENSEE_CODE: usize = 0x014B;
+
+/// A CGB palette of four 15-bit colors.
+pub type CompatPalette = [u16; 4];
+
+/// The palettes written to CGB palette memory for a DMG game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompatPalettes {
+    pub background: CompatPalette,
+    pub object0: CompatPalette,
+    pub object1: CompatPalette,
+}
+
+const WHITE_GREEN_BLUE: CompatPalette = [0x7FFF, 0x1BEF, 0x6180, 0x0000];
+const WHITE_PINK_RED: CompatPalette = [0x7FFF, 0x421F, 0x1CF2, 0x0000];
+const WHITE_GREEN: CompatPalette = [0x7FFF, 0x1BEF, 0x0200, 0x0000];
+const WHITE_BLUE: CompatPalette = [0x7FFF, 0x7E8C, 0x7C00, 0x0000];
+const YELLOW_RED: CompatPalette = [0x7FFF, 0x03FF, 0x001F, 0x0000];
+
+/// Palettes for games that are not in the table.
+pub const DEFAULT_COMPAT_PALETTES: CompatPalettes = CompatPalettes {
+    background: WHITE_GREEN_BLUE,
+    object0: WHITE_PINK_RED,
+    object1: WHITE_PINK_RED,
+};
+
+struct CompatPalettesEntry {
+    title_checksum: u8,
+    /// Only set for checksums shared by multiple titles
+    fourth_character: Option<u8>,
+    palettes: CompatPalettes,
+}
+
+const COMPAT_PALETTES_TABLE: [CompatPalettesEntry; 4] = [
+    // POKEMON RED
+    CompatPalettesEntry {
+        title_checksum: 0x14,
+        fourth_character: None,
+        palettes: CompatPalettes {
+            background: WHITE_PINK_RED,
+            object0: WHITE_GREEN,
+            object1: WHITE_BLUE,
+        },
+    },
+    // POKEMON GREEN
+    CompatPalettesEntry {
+        title_checksum: 0xAA,
+        fourth_character: None,
+        palettes: CompatPalettes {
+            background: WHITE_GREEN,
+            object0: WHITE_PINK_RED,
+            object1: WHITE_BLUE,
+        },
+    },
+    // POKEMON BLUE
+    CompatPalettesEntry {
+        title_checksum: 0x61,
+        fourth_character: Some(b'E'),
+        palettes: CompatPalettes {
+            background: WHITE_BLUE,
+            object0: WHITE_PINK_RED,
+            object1: WHITE_GREEN,
+        },
+    },
+    // TETRIS
+    CompatPalettesEntry {
+        title_checksum: 0xDB,
+        fourth_character: None,
+        palettes: CompatPalettes {
+            background: YELLOW_RED,
+            object0: YELLOW_RED,
+            object1: YELLOW_RED,
+        },
+    },
+];
+
+/// Sum of the bytes of the full 16 byte title area, which includes the CGB flag.
+fn title_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_START..=TITLE_END]
+        .iter()
+        .fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn is_licensed_by_nintendo(rom: &[u8]) -> bool {
+    match rom[OLD_LICENSEE_CODE] {
+        0x01 => true,
+        0x33 => &rom[NEW_LICENSEE_CODE..NEW_LICENSEE_CODE + 2] == b"01",
+        _ => false,
+    }
+}
+
+/// The palettes the CGB boot ROM selects for a DMG game.
+pub fn compat_palettes_for_rom(rom: &[u8]) -> CompatPalettes {
+    if !is_licensed_by_nintendo(rom) {
+        return DEFAULT_COMPAT_PALETTES;
+    }
+
+    let checksum = title_checksum(rom);
+    let fourth_character = rom[TITLE_FOURTH_CHARACTER];
+
+    COMPAT_PALETTES_TABLE
+        .iter()
+        .find(|entry| {
+            entry.title_checksum == checksum
+                && entry
+                    .fourth_character
+                    .is_none_or(|character| character == fourth_character)
+        })
+        .map_or(DEFAULT_COMPAT_PALETTES, |entry| entry.palettes)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_utils::build_test_rom;
+
+    use super::{
+        DEFAULT_COMPAT_PALETTES, WHITE_BLUE, WHITE_GREEN, WHITE_PINK_RED, compat_palettes_for_rom,
+    };
+
+    fn rom_with_title(title: &[u8], old_licensee_code: u8) -> Vec<u8> {
+        let mut rom = build_test_rom(0x00, 0x00, 0x00, &[]);
+        rom[0x0134..0x0144].fill(0);
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x014B] = old_licensee_code;
+        rom
+    }
+
+    #[test]
+    fn selects_palettes_by_title() {
+        let rom = rom_with_title(b"POKEMON RED", 0x01);
+        let palettes = compat_palettes_for_rom(&rom);
+        assert_eq!(palettes.background, WHITE_PINK_RED);
+        assert_eq!(palettes.object0, WHITE_GREEN);
+        assert_eq!(palettes.object1, WHITE_BLUE);
+
+        // Only the fourth character distinguishes titles with the same checksum
+        let rom = rom_with_title(b"POKEMON BLUE", 0x01);
+        assert_eq!(compat_palettes_for_rom(&rom).background, WHITE_BLUE);
+
+        let mut rom = rom_with_title(b"POKFMON BLUD", 0x01);
+        assert_eq!(compat_palettes_for_rom(&rom), DEFAULT_COMPAT_PALETTES);
+
+        // New licensee code is used when the old licensee code is 0x33
+        rom = rom_with_title(b"POKEMON RED", 0x33);
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        assert_eq!(compat_palettes_for_rom(&rom).background, WHITE_PINK_RED);
+    }
+
+    #[test]
+    fn other_licensees_get_default_palettes() {
+        let rom = rom_with_title(b"POKEMON RED", 0x00);
+        assert_eq!(compat_palettes_for_rom(&rom), DEFAULT_COMPAT_PALETTES);
+
+        let mut rom = rom_with_title(b"POKEMON RED", 0x33);
+        rom[0x0144..0x0146].copy_from_slice(b"08");
+        assert_eq!(compat_palettes_for_rom(&rom), DEFAULT_COMPAT_PALETTES);
+    }
+}
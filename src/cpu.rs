@@ -1,12 +1,41 @@
-use crate::emulator::{Emulator, Interrupt};
+use crate::{
+    address_space::Address,
+    disassembler::InstructionFormatter,
+    emulator::{Emulator, Interrupt, TestResult},
+};
 
 impl Emulator {
     /// Execute an instruction, returning the number of clock cycles taken by the instruction.
     pub fn execute_instruction(&mut self) {
+        if self.in_trace_mode() {
+            self.trace_instruction();
+        }
+
         let opcode = self.read_opcode();
         DISPATCH_TABLE[opcode as usize](self, opcode);
     }
 
+    /// Print the instruction at PC along with the current register state.
+    fn trace_instruction(&self) {
+        let state = self.cpu_state();
+        let (instruction, _) = self.disassemble_at(state.pc);
+
+        println!(
+            "[TRACE] {:04X}: {:<20} A={:02X} F={:02X} B={:02X} C={:02X} D={:02X} E={:02X} H={:02X} L={:02X} SP={:04X}",
+            state.pc,
+            instruction,
+            state.a,
+            state.f,
+            state.b,
+            state.c,
+            state.d,
+            state.e,
+            state.h,
+            state.l,
+            state.sp,
+        );
+    }
+
     fn execute_cb_instruction(&mut self) {
         let opcode = self.read_opcode();
         CB_DISPATCH_TABLE[opcode as usize](self, opcode);
@@ -18,28 +47,40 @@ impl Emulator {
         self.regs_mut().set_pc(interrupt.handler_address());
     }
 
+    /// Read a byte from memory on behalf of the current instruction.
+    fn read_bus(&mut self, addr: Address) -> u8 {
+        self.step_to_next_memory_access();
+        self.read_address(addr)
+    }
+
+    /// Write a byte to memory on behalf of the current instruction.
+    fn write_bus(&mut self, addr: Address, value: u8) {
+        self.step_to_next_memory_access();
+        self.write_address(addr, value);
+    }
+
     /// Read the opcode at PC and advance PC to the following byte.
     fn read_opcode(&mut self) -> Opcode {
         let pc = self.regs().pc();
-        let byte = self.read_address(pc);
-        self.regs_mut().set_pc(pc + 1);
+        let byte = self.read_bus(pc);
+        self.regs_mut().set_pc(pc.wrapping_add(1));
         byte
     }
 
     /// Read the 8-bit immediate value at PC and advance PC to the following byte.
     fn read_imm8_operand(&mut self) -> u8 {
         let pc = self.regs().pc();
-        let byte = self.read_address(pc);
-        self.regs_mut().set_pc(pc + 1);
+        let byte = self.read_bus(pc);
+        self.regs_mut().set_pc(pc.wrapping_add(1));
         byte
     }
 
     /// Read the 16-bit immediate value at PC and advance PC to the following byte.
     fn read_imm16_operand(&mut self) -> u16 {
         let pc = self.regs().pc();
-        let low = self.read_address(pc) as u16;
-        let high = self.read_address(pc + 1) as u16;
-        self.regs_mut().set_pc(pc + 2);
+        let low = self.read_bus(pc) as u16;
+        let high = self.read_bus(pc.wrapping_add(1)) as u16;
+        self.regs_mut().set_pc(pc.wrapping_add(2));
         (high << 8) | low
     }
 
@@ -56,7 +97,7 @@ impl Emulator {
     /// Get the value of the specified 8-bit register operand.
     ///
     /// `r8_operand` must be in the range 0-7.
-    fn read_r8_operand_value(&self, r8_operand: R8Operand) -> u8 {
+    fn read_r8_operand_value(&mut self, r8_operand: R8Operand) -> u8 {
         match r8_operand {
             0 => self.regs().b(),
             1 => self.regs().c(),
@@ -64,7 +105,7 @@ impl Emulator {
             3 => self.regs().e(),
             4 => self.regs().h(),
             5 => self.regs().l(),
-            R8_OPERAND_HL_MEM => self.read_address(self.regs().hl()),
+            R8_OPERAND_HL_MEM => self.read_bus(self.regs().hl()),
             R8_OPERAND_A => self.regs().a(),
             _ => unreachable!("Invalid r8 operand"),
         }
@@ -81,7 +122,7 @@ impl Emulator {
             3 => self.regs_mut().set_e(value),
             4 => self.regs_mut().set_h(value),
             5 => self.regs_mut().set_l(value),
-            R8_OPERAND_HL_MEM => self.write_address(self.regs().hl(), value),
+            R8_OPERAND_HL_MEM => self.write_bus(self.regs().hl(), value),
             R8_OPERAND_A => self.regs_mut().set_a(value),
             _ => unreachable!("Invalid r8 operand"),
         }
@@ -117,8 +158,8 @@ impl Emulator {
     fn pop_u16_from_stack(&mut self) -> u16 {
         let sp = self.regs().sp();
 
-        let low = self.read_address(sp) as u16;
-        let high = self.read_address(sp.wrapping_add(1)) as u16;
+        let low = self.read_bus(sp) as u16;
+        let high = self.read_bus(sp.wrapping_add(1)) as u16;
         let result = (high << 8) | low;
 
         let new_sp = sp.wrapping_add(2);
@@ -134,8 +175,11 @@ impl Emulator {
 
         let new_sp = sp.wrapping_sub(2);
 
-        self.write_address(new_sp.wrapping_add(1), high);
-        self.write_address(new_sp, low);
+        // Stack pointer is decremented on an internal machine cycle before the first write
+        self.step_internal_machine_cycle();
+
+        self.write_bus(new_sp.wrapping_add(1), high);
+        self.write_bus(new_sp, low);
 
         self.regs_mut().set_sp(new_sp);
     }
@@ -151,164 +195,19 @@ impl Emulator {
     }
 }
 
-struct InstructionFormatter {
-    builder: String,
-}
-
-impl InstructionFormatter {
-    fn new() -> Self {
-        Self {
-            builder: String::new(),
-        }
-    }
-
-    fn finish(self) -> String {
-        self.builder
-    }
-
-    #[allow(unused)]
-    fn format(instrs: &[u8]) -> String {
-        let format_handler = INSTRUCTION_FORMATTERS[instrs[0] as usize];
-        let mut formatter = InstructionFormatter::new();
-        format_handler(instrs, &mut formatter);
-
-        formatter.finish()
-    }
-
-    fn opcode(&mut self, opcode: &str) {
-        self.builder.push_str(opcode);
-        self.builder.push(' ');
-    }
-
-    fn simple_opcode(&mut self, opcode: &str) {
-        self.builder.push_str(opcode);
-    }
-
-    fn comma(&mut self) {
-        self.builder.push_str(", ");
-    }
-
-    fn plus(&mut self) {
-        self.builder.push_str(" + ");
-    }
-
-    fn r8_operand(&mut self, operand: R8Operand) {
-        let formatted = match operand {
-            0 => 'b',
-            1 => 'c',
-            2 => 'd',
-            3 => 'e',
-            4 => 'h',
-            5 => 'l',
-            R8_OPERAND_HL_MEM => {
-                self.builder.push_str("[hl]");
-                return;
-            }
-            R8_OPERAND_A => 'a',
-            _ => panic!("Invalid r8 operand"),
-        };
-
-        self.builder.push(formatted);
-    }
-
-    fn a_operand(&mut self) {
-        self.r8_operand(R8_OPERAND_A);
-    }
-
-    fn r16_operand(&mut self, operand: R16Operand) {
-        let formatted = match operand {
-            0 => "bc",
-            1 => "de",
-            R16_OPERAND_HL => "hl",
-            R16_OPERAND_SP => "sp",
-            _ => panic!("Invalid r16 operand"),
-        };
-
-        self.builder.push_str(formatted);
-    }
-
-    fn hl_operand(&mut self) {
-        self.r16_operand(R16_OPERAND_HL);
-    }
-
-    fn sp_operand(&mut self) {
-        self.r16_operand(R16_OPERAND_SP);
-    }
-
-    fn af_operand(&mut self) {
-        self.builder.push_str("af");
-    }
-
-    fn r16_mem_operand(&mut self, operand: R16Operand) {
-        let formatted = match operand {
-            0 => "[bc]",
-            1 => "[de]",
-            _ => panic!("Invalid r16 mem operand"),
-        };
-
-        self.builder.push_str(formatted);
-    }
-
-    fn cc_operand(&mut self, operand: CcOperand) {
-        let formatted = match operand {
-            0 => "nz",
-            1 => "z",
-            2 => "nc",
-            3 => "c",
-            _ => panic!("Invalid cc operand"),
-        };
-
-        self.builder.push_str(formatted);
-    }
-
-    fn imm8_operand(&mut self, operand: u8) {
-        self.builder.push_str(&format!("#{}", operand));
-    }
-
-    fn imm8_mem_operand(&mut self, operand: u8) {
-        self.builder.push_str(&format!("[#{}]", operand));
-    }
-
-    fn imm16_operand(&mut self, operand: u16) {
-        self.builder.push_str(&format!("#{}", operand));
-    }
-
-    fn imm16_mem_operand(&mut self, operand: u16) {
-        self.builder.push_str(&format!("[#{}]", operand));
-    }
-
-    fn imm8_signed_operand(&mut self, operand: u8) {
-        self.builder.push_str(&format!("#{}", operand as i8));
-    }
-
-    fn cmem_operand(&mut self) {
-        self.builder.push_str("[c]");
-    }
-
-    fn hli_operand(&mut self) {
-        self.builder.push_str("[hl+]");
-    }
-
-    fn hld_operand(&mut self) {
-        self.builder.push_str("[hl-]");
-    }
-
-    fn rst_target_operand(&mut self, operand: u8) {
-        self.builder.push_str(&format!("0x{:02X}", operand))
-    }
-
-    fn bit_index_operand(&mut self, operand: u8) {
-        self.builder.push_str(&format!("{}", operand));
-    }
-}
-
 type Opcode = u8;
-type R8Operand = u8;
-type R16Operand = u8;
+pub(crate) type R8Operand = u8;
+pub(crate) type R16Operand = u8;
 type CcOperand = u8;
 type InstructionHandler = fn(&mut Emulator, Opcode);
 type FormatterHandler = fn(&[u8], &mut InstructionFormatter);
 
+/// Format the instruction at the start of the slice using the same opcode tables as execution.
+pub(crate) fn format_instruction(instrs: &[u8], formatter: &mut InstructionFormatter) {
+    let format_handler = INSTRUCTION_FORMATTERS[instrs[0] as usize];
+    format_handler(instrs, formatter);
+}
+
 /// An r8 operand encoded in bits 0-2 of the opcode.
 fn low_r8_operand(opcode: Opcode) -> R8Operand {
     opcode & 0x07
@@ -334,19 +233,11 @@ fn bit_index_operand(opcode: Opcode) -> u8 {
     (opcode >> 3) & 0x07
 }
 
-/// Read an imm16 operand from the start of an instruction slice.
-fn imm16_operand_from_slice(instrs: &[u8]) -> u16 {
-    let low = instrs[0] as u16;
-    let high = instrs[1] as u16;
+pub(crate) const R8_OPERAND_HL_MEM: R8Operand = 6;
+pub(crate) const R8_OPERAND_A: R8Operand = 7;
 
-    (high << 8) | low
-}
-
-const R8_OPERAND_HL_MEM: R8Operand = 6;
-const R8_OPERAND_A: R8Operand = 7;
-
-const R16_OPERAND_HL: R16Operand = 2;
-const R16_OPERAND_SP: R16Operand = 3;
+pub(crate) const R16_OPERAND_HL: R16Operand = 2;
+pub(crate) const R16_OPERAND_SP: R16Operand = 3;
 
 fn single_r8_operand_cycles(r8_operand: R8Operand) -> usize {
     if r8_operand == R8_OPERAND_HL_MEM {
@@ -439,29 +330,11 @@ define_instruction!(
     },
 );
 
-fn has_tests_passed_registers(emulator: &Emulator) -> bool {
-    emulator.regs().b() == 3
-        && emulator.regs().c() == 5
-        && emulator.regs().d() == 8
-        && emulator.regs().e() == 13
-        && emulator.regs().h() == 21
-        && emulator.regs().l() == 34
-}
-
-fn has_tests_failed_registers(emulator: &Emulator) -> bool {
-    emulator.regs().b() == 0x42
-        && emulator.regs().c() == 0x42
-        && emulator.regs().d() == 0x42
-        && emulator.regs().e() == 0x42
-        && emulator.regs().h() == 0x42
-        && emulator.regs().l() == 0x42
-}
-
 fn check_test_results(emulator: &Emulator) {
-    if has_tests_passed_registers(emulator) {
-        println!("Test passed!");
-    } else if has_tests_failed_registers(emulator) {
-        println!("Test failed!",);
+    match emulator.test_result() {
+        Some(TestResult::Passed) => println!("Test passed!"),
+        Some(TestResult::Failed) => println!("Test failed!"),
+        None => {}
     }
 }
 
@@ -507,7 +380,7 @@ define_instruction!(
     },
     fn format(instrs, formatter) {
         let r8_operand = high_r8_operand(instrs[0]);
-        let imm8_operand = instrs[1];
+        let imm8_operand = formatter.read_imm8(instrs);
 
         formatter.opcode("ld");
         formatter.r8_operand(r8_operand);
@@ -528,7 +401,7 @@ define_instruction!(
     },
     fn format(instrs, formatter) {
         let r16_operand = r16_operand(instrs[0]);
-        let imm16_operand = imm16_operand_from_slice(&instrs[1..]);
+        let imm16_operand = formatter.read_imm16(instrs);
 
         formatter.opcode("ld");
         formatter.r16_operand(r16_operand);
@@ -544,7 +417,7 @@ define_instruction!(
         let r16_value = emulator.read_r16_operand_value(r16_operand);
 
         let accumulator = emulator.regs().a();
-        emulator.write_address(r16_value, accumulator);
+        emulator.write_bus(r16_value, accumulator);
 
         emulator.schedule_next_instruction(8);
     },
@@ -564,7 +437,7 @@ define_instruction!(
         let r16_operand = r16_operand(opcode);
         let r16_value = emulator.read_r16_operand_value(r16_operand);
 
-        let r16_mem = emulator.read_address(r16_value);
+        let r16_mem = emulator.read_bus(r16_value);
         emulator.regs_mut().set_a(r16_mem);
 
         emulator.schedule_next_instruction(8);
@@ -585,12 +458,12 @@ define_instruction!(
         let imm16 = emulator.read_imm16_operand();
         let accumulator = emulator.regs().a();
 
-        emulator.write_address(imm16, accumulator);
+        emulator.write_bus(imm16, accumulator);
 
         emulator.schedule_next_instruction(16);
     },
     fn format(instrs, formatter) {
-        let imm16_operand = imm16_operand_from_slice(&instrs[1..]);
+        let imm16_operand = formatter.read_imm16(instrs);
 
         formatter.opcode("ld");
         formatter.imm16_mem_operand(imm16_operand);
@@ -603,14 +476,14 @@ define_instruction!(
     ld_a_imm16mem,
     fn execute(emulator, _) {
         let imm16 = emulator.read_imm16_operand();
-        let imm16_mem = emulator.read_address(imm16);
+        let imm16_mem = emulator.read_bus(imm16);
 
         emulator.regs_mut().set_a(imm16_mem);
 
         emulator.schedule_next_instruction(16);
     },
     fn format(instrs, formatter) {
-        let imm16_operand = imm16_operand_from_slice(&instrs[1..]);
+        let imm16_operand = formatter.read_imm16(instrs);
 
         formatter.opcode("ld");
         formatter.a_operand();
@@ -625,13 +498,13 @@ define_instruction!(
         let imm16 = emulator.read_imm16_operand();
         let [low, high] = emulator.regs().sp().to_le_bytes();
 
-        emulator.write_address(imm16, low);
-        emulator.write_address(imm16 + 1, high);
+        emulator.write_bus(imm16, low);
+        emulator.write_bus(imm16.wrapping_add(1), high);
 
         emulator.schedule_next_instruction(20);
     },
     fn format(instrs, formatter) {
-        let imm16_operand = imm16_operand_from_slice(&instrs[1..]);
+        let imm16_operand = formatter.read_imm16(instrs);
 
         formatter.opcode("ld");
         formatter.imm16_mem_operand(imm16_operand);
@@ -646,7 +519,7 @@ define_instruction!(
         let accumulator = emulator.regs().a();
         let c = emulator.regs().c();
 
-        emulator.write_address(ldh_address(c), accumulator);
+        emulator.write_bus(ldh_address(c), accumulator);
 
         emulator.schedule_next_instruction(8);
     },
@@ -662,7 +535,7 @@ define_instruction!(
     ldh_a_cmem,
     fn execute(emulator, _) {
         let c = emulator.regs().c();
-        let c_mem = emulator.read_address(ldh_address(c));
+        let c_mem = emulator.read_bus(ldh_address(c));
 
         emulator.regs_mut().set_a(c_mem);
 
@@ -682,15 +555,15 @@ define_instruction!(
         let imm8 = emulator.read_imm8_operand();
         let accumulator = emulator.regs().a();
 
-        emulator.write_address(ldh_address(imm8), accumulator);
+        emulator.write_bus(ldh_address(imm8), accumulator);
 
         emulator.schedule_next_instruction(12);
     },
     fn format(instrs, formatter) {
-        let imm8_operand = instrs[1];
+        let imm8_operand = formatter.read_imm8(instrs);
 
         formatter.opcode("ldh");
-        formatter.imm8_mem_operand(imm8_operand);
+        formatter.imm16_mem_operand(ldh_address(imm8_operand));
         formatter.comma();
         formatter.a_operand();
     },
@@ -700,19 +573,19 @@ define_instruction!(
     ldh_a_imm8mem,
     fn execute(emulator, _) {
         let imm8 = emulator.read_imm8_operand();
-        let imm8_mem = emulator.read_address(ldh_address(imm8));
+        let imm8_mem = emulator.read_bus(ldh_address(imm8));
 
         emulator.regs_mut().set_a(imm8_mem);
 
         emulator.schedule_next_instruction(12);
     },
     fn format(instrs, formatter) {
-        let imm8_operand = instrs[1];
+        let imm8_operand = formatter.read_imm8(instrs);
 
         formatter.opcode("ldh");
         formatter.a_operand();
         formatter.comma();
-        formatter.imm8_mem_operand(imm8_operand);
+        formatter.imm16_mem_operand(ldh_address(imm8_operand));
     },
 );
 
@@ -733,7 +606,7 @@ define_instruction!(
         emulator.schedule_next_instruction(12);
     },
     fn format(instrs, formatter) {
-        let imm8_operand = instrs[1];
+        let imm8_operand = formatter.read_imm8(instrs);
 
         formatter.opcode("ld");
         formatter.hl_operand();
@@ -748,7 +621,7 @@ define_instruction!(
     ld_a_hli,
     fn execute (emulator, _) {
         let hl = emulator.regs().hl();
-        let hl_mem = emulator.read_address(hl);
+        let hl_mem = emulator.read_bus(hl);
 
         emulator.regs_mut().set_a(hl_mem);
         emulator.regs_mut().set_hl(hl.wrapping_add(1));
@@ -767,7 +640,7 @@ define_instruction!(
     ld_a_hld,
     fn execute (emulator, _) {
         let hl = emulator.regs().hl();
-        let hl_mem = emulator.read_address(hl);
+        let hl_mem = emulator.read_bus(hl);
 
         emulator.regs_mut().set_a(hl_mem);
         emulator.regs_mut().set_hl(hl.wrapping_sub(1));
@@ -788,7 +661,7 @@ define_instruction!(
         let hl = emulator.regs().hl();
         let accumulator = emulator.regs().a();
 
-        emulator.write_address(hl, accumulator);
+        emulator.write_bus(hl, accumulator);
         emulator.regs_mut().set_hl(hl.wrapping_add(1));
 
         emulator.schedule_next_instruction(8);
@@ -807,7 +680,7 @@ define_instruction!(
         let hl = emulator.regs().hl();
         let accumulator = emulator.regs().a();
 
-        emulator.write_address(hl, accumulator);
+        emulator.write_bus(hl, accumulator);
         emulator.regs_mut().set_hl(hl.wrapping_sub(1));
 
         emulator.schedule_next_instruction(8);
@@ -1128,7 +1001,7 @@ define_instruction!(
         let has_interrupts = emulator.interrupt_bits() != 0;
         let new_speed = !emulator.is_double_speed();
 
-        if emulator.in_cgb_mode() && !has_button_pressed && is_speed_switch_armed {
+        if emulator.compat_mode().has_cgb_registers() && !has_button_pressed && is_speed_switch_armed {
             if !has_interrupts {
                 // This STOP is a two-byte instruction where the second byte is ignored
                 let _ = emulator.read_imm8_operand();
@@ -1149,7 +1022,24 @@ define_instruction!(
             }
         }
 
-        panic!("STOP instruction that does not result in a speed switch")
+        if has_button_pressed {
+            // With a button already held STOP mode would be exited immediately, so the CPU enters
+            // HALT mode instead. STOP is a one-byte instruction if an interrupt is pending.
+            if !has_interrupts {
+                let _ = emulator.read_imm8_operand();
+                emulator.halt_cpu();
+            }
+        } else {
+            // Otherwise STOP mode is entered until a selected button is pressed. STOP is a one-byte
+            // instruction if an interrupt is pending.
+            if !has_interrupts {
+                let _ = emulator.read_imm8_operand();
+            }
+
+            emulator.stop_cpu();
+        }
+
+        emulator.schedule_next_instruction(4);
     },
     fn format(_, formatter) {
         formatter.simple_opcode("stop");
@@ -1371,7 +1261,7 @@ fn format_arithmetic_a_imm8_instruction(
     formatter: &mut InstructionFormatter,
     opcode: &str,
 ) {
-    let imm8_operand = instrs[1];
+    let imm8_operand = formatter.read_imm8(instrs);
 
     formatter.opcode(opcode);
     formatter.a_operand();
@@ -1535,10 +1425,10 @@ define_instruction!(
         emulator.schedule_next_instruction(12);
     },
     fn format(instrs, formatter) {
-        let imm8_operand = instrs[1];
+        let imm8_operand = formatter.read_imm8(instrs);
 
         formatter.opcode("jr");
-        formatter.imm8_signed_operand(imm8_operand);
+        formatter.relative_jump_target_operand(imm8_operand);
     },
 );
 
@@ -1559,12 +1449,12 @@ define_instruction!(
     },
     fn format(instrs, formatter) {
         let cc_operand = cc_operand(instrs[0]);
-        let imm8_operand = instrs[1];
+        let imm8_operand = formatter.read_imm8(instrs);
 
         formatter.opcode("jr");
         formatter.cc_operand(cc_operand);
         formatter.comma();
-        formatter.imm8_signed_operand(imm8_operand);
+        formatter.relative_jump_target_operand(imm8_operand);
     },
 );
 
@@ -1577,7 +1467,7 @@ define_instruction!(
         emulator.schedule_next_instruction(16);
     },
     fn format(instrs, formatter) {
-        let imm16_operand = imm16_operand_from_slice(&instrs[1..]);
+        let imm16_operand = formatter.read_imm16(instrs);
 
         formatter.opcode("jp");
         formatter.imm16_operand(imm16_operand);
@@ -1601,7 +1491,7 @@ define_instruction!(
     },
     fn format(instrs, formatter) {
         let cc_operand = cc_operand(instrs[0]);
-        let imm16_operand = imm16_operand_from_slice(&instrs[1..]);
+        let imm16_operand = formatter.read_imm16(instrs);
 
         formatter.opcode("jp");
         formatter.cc_operand(cc_operand);
@@ -1688,7 +1578,7 @@ define_instruction!(
         emulator.schedule_next_instruction(24);
     },
     fn format(instrs, formatter) {
-        let imm16_operand = imm16_operand_from_slice(&instrs[1..]);
+        let imm16_operand = formatter.read_imm16(instrs);
 
         formatter.opcode("call");
         formatter.imm16_operand(imm16_operand);
@@ -1714,7 +1604,7 @@ define_instruction!(
     },
     fn format(instrs, formatter) {
         let cc_operand = cc_operand(instrs[0]);
-        let imm16_operand = imm16_operand_from_slice(&instrs[1..]);
+        let imm16_operand = formatter.read_imm16(instrs);
 
         formatter.opcode("call");
         formatter.cc_operand(cc_operand);
@@ -1824,7 +1714,7 @@ define_instruction!(
         emulator.schedule_next_instruction(16);
     },
     fn format(instrs, formatter) {
-        let imm8_operand = instrs[1];
+        let imm8_operand = formatter.read_imm8(instrs);
 
         formatter.opcode("add");
         formatter.sp_operand();
@@ -1841,7 +1731,7 @@ define_instruction!(
         emulator.schedule_next_instruction(4);
     },
     fn format(_, formatter) {
-        formatter.opcode("di");
+        formatter.simple_opcode("di");
     },
 );
 
@@ -1854,7 +1744,7 @@ define_instruction!(
         emulator.schedule_next_instruction(4);
     },
     fn format(_, formatter) {
-        formatter.opcode("ei");
+        formatter.simple_opcode("ei");
     },
 );
 
@@ -1864,7 +1754,12 @@ define_instruction!(
         emulator.execute_cb_instruction();
     },
     fn format(instrs, formatter) {
-        let format_handler = CB_FORMATTER_TABLE[instrs[1] as usize];
+        let cb_opcode = formatter.read_imm8(instrs);
+        if formatter.is_truncated() {
+            return;
+        }
+
+        let format_handler = CB_FORMATTER_TABLE[cb_opcode as usize];
         format_handler(&instrs[1..], formatter);
     },
 );
@@ -2157,14 +2052,16 @@ define_instruction!(
     },
 );
 
-// An opcode that does not match any valid instruction.
+// An opcode that does not match any valid instruction. Locks up the CPU.
 define_instruction!(
     invalid,
-    fn execute (_, opcode) {
-        panic!("Invalid opcode {:02X}", opcode);
+    fn execute (emulator, opcode) {
+        emulator.lock_cpu(opcode);
     },
-    fn format(_, formatter) {
-       formatter.simple_opcode("<invalid>");
+    fn format(instrs, formatter) {
+        // Invalid opcodes are shown as data bytes
+        formatter.opcode("db");
+        formatter.imm8_operand(instrs[0]);
     },
 );
 
@@ -2240,7 +2137,7 @@ define_cb_instructions!(
 
 #[cfg(test)]
 mod test {
-    use super::InstructionFormatter;
+    use crate::disassembler::disassemble;
 
     #[test]
     pub fn format_all_opcodes() {
@@ -2260,8 +2157,8 @@ mod test {
         let mut formatted_cb_instrs = vec![];
 
         for i in 0..256 {
-            formatted_instrs.push(InstructionFormatter::format(&instrs[i..]));
-            formatted_cb_instrs.push(InstructionFormatter::format(&cb_instrs[(i * 2)..]));
+            formatted_instrs.push(disassemble(&instrs[i..], i as u16).0);
+            formatted_cb_instrs.push(disassemble(&cb_instrs[(i * 2)..], 0).0);
         }
 
         for i in 0..16 {
@@ -2276,10 +2173,10 @@ mod test {
     }
 
     const EXPECTED_INSTRS: [&'static str; 16] = [
-        "nop | ld bc, #770 | ld [bc], a | inc bc | inc b | dec b | ld b, #7 | rlca | ld [#2569], sp | add hl, bc | ld a, [bc] | dec bc | inc c | dec c | ld c, #15 | rrca",
-        "stop | ld de, #4882 | ld [de], a | inc de | inc d | dec d | ld d, #23 | rla | jr #25 | add hl, de | ld a, [de] | dec de | inc e | dec e | ld e, #31 | rra",
-        "jr nz, #33 | ld hl, #8994 | ld [hl+], a | inc hl | inc h | dec h | ld h, #39 | daa | jr z, #41 | add hl, hl | ld a, [hl+] | dec hl | inc l | dec l | ld l, #47 | cpl",
-        "jr nc, #49 | ld sp, #13106 | ld [hl-], a | inc sp | inc [hl] | dec [hl] | ld [hl], #55 | scf | jr c, #57 | add hl, sp | ld a, [hl-] | dec sp | inc a | dec a | ld a, #63 | ccf",
+        "nop | ld bc, $0302 | ld [bc], a | inc bc | inc b | dec b | ld b, $07 | rlca | ld [$0A09], sp | add hl, bc | ld a, [bc] | dec bc | inc c | dec c | ld c, $0F | rrca",
+        "stop | ld de, $1312 | ld [de], a | inc de | inc d | dec d | ld d, $17 | rla | jr $0033 | add hl, de | ld a, [de] | dec de | inc e | dec e | ld e, $1F | rra",
+        "jr nz, $0043 | ld hl, $2322 | ld [hl+], a | inc hl | inc h | dec h | ld h, $27 | daa | jr z, $0053 | add hl, hl | ld a, [hl+] | dec hl | inc l | dec l | ld l, $2F | cpl",
+        "jr nc, $0063 | ld sp, $3332 | ld [hl-], a | inc sp | inc [hl] | dec [hl] | ld [hl], $37 | scf | jr c, $0073 | add hl, sp | ld a, [hl-] | dec sp | inc a | dec a | ld a, $3F | ccf",
         "ld b, b | ld b, c | ld b, d | ld b, e | ld b, h | ld b, l | ld b, [hl] | ld b, a | ld c, b | ld c, c | ld c, d | ld c, e | ld c, h | ld c, l | ld c, [hl] | ld c, a",
         "ld d, b | ld d, c | ld d, d | ld d, e | ld d, h | ld d, l | ld d, [hl] | ld d, a | ld e, b | ld e, c | ld e, d | ld e, e | ld e, h | ld e, l | ld e, [hl] | ld e, a",
         "ld h, b | ld h, c | ld h, d | ld h, e | ld h, h | ld h, l | ld h, [hl] | ld h, a | ld l, b | ld l, c | ld l, d | ld l, e | ld l, h | ld l, l | ld l, [hl] | ld l, a",
@@ -2288,10 +2185,10 @@ mod test {
         "sub a, b | sub a, c | sub a, d | sub a, e | sub a, h | sub a, l | sub a, [hl] | sub a, a | sbc a, b | sbc a, c | sbc a, d | sbc a, e | sbc a, h | sbc a, l | sbc a, [hl] | sbc a, a",
         "and a, b | and a, c | and a, d | and a, e | and a, h | and a, l | and a, [hl] | and a, a | xor a, b | xor a, c | xor a, d | xor a, e | xor a, h | xor a, l | xor a, [hl] | xor a, a",
         "or a, b | or a, c | or a, d | or a, e | or a, h | or a, l | or a, [hl] | or a, a | cp a, b | cp a, c | cp a, d | cp a, e | cp a, h | cp a, l | cp a, [hl] | cp a, a",
-        "ret nz | pop bc | jp nz, #50371 | jp #50628 | call nz, #50885 | push bc | add a, #199 | rst 0x00 | ret z | ret | jp z, #52427 | set 1, h | call z, #52941 | call #53198 | adc a, #207 | rst 0x08",
-        "ret nc | pop de | jp nc, #54483 | <invalid> | call nc, #54997 | push de | sub a, #215 | rst 0x10 | ret c | reti | jp c, #56539 | <invalid> | call c, #57053 | <invalid> | sbc a, #223 | rst 0x18",
-        "ldh [#225], a | pop hl | ldh [c], a | <invalid> | <invalid> | push hl | and a, #231 | rst 0x20 | add sp, #-23 | jp hl | ld [#60651], a | <invalid> | <invalid> | <invalid> | xor a, #239 | rst 0x28",
-        "ldh a, [#241] | pop af | ldh a, [c] | di  | <invalid> | push af | or a, #247 | rst 0x30 | ld hl, sp + #-7 | ld sp, hl | ld a, [#64763] | ei  | <invalid> | <invalid> | cp a, #255 | rst 0x38",
+        "ret nz | pop bc | jp nz, $C4C3 | jp $C5C4 | call nz, $C6C5 | push bc | add a, $C7 | rst $00 | ret z | ret | jp z, $CCCB | set 1, h | call z, $CECD | call $CFCE | adc a, $CF | rst $08",
+        "ret nc | pop de | jp nc, $D4D3 | db $D3 | call nc, $D6D5 | push de | sub a, $D7 | rst $10 | ret c | reti | jp c, $DCDB | db $DB | call c, $DEDD | db $DD | sbc a, $DF | rst $18",
+        "ldh [$FFE1], a | pop hl | ldh [c], a | db $E3 | db $E4 | push hl | and a, $E7 | rst $20 | add sp, -23 | jp hl | ld [$ECEB], a | db $EB | db $EC | db $ED | xor a, $EF | rst $28",
+        "ldh a, [$FFF1] | pop af | ldh a, [c] | di | db $F4 | push af | or a, $F7 | rst $30 | ld hl, sp + -7 | ld sp, hl | ld a, [$FCFB] | ei | db $FC | db $FD | cp a, $FF | rst $38",
     ];
 
     const EXPECTED_CB_INSTRS: [&'static str; 16] = [
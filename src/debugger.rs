@@ -0,0 +1,340 @@
+//! Instruction-level debugging: breakpoints, memory watchpoints, and single stepping.
+//!
+//! The emulator consults its `Debugger` before and after executing each instruction. Stopping is
+//! done by pausing the emulator, which keeps handling commands so that the GUI stays responsive
+//! and can continue or step.
+
+use std::{cell::Cell, collections::HashSet};
+
+use crate::{address_space::Address, symbols::BankedAddress};
+
+/// Pauses when an instruction accesses an address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Watchpoint {
+    pub address: Address,
+    /// Whether writes trigger the watchpoint, otherwise reads do
+    pub on_write: bool,
+}
+
+/// A watchpoint that was triggered by an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchpointHit {
+    pub watchpoint: Watchpoint,
+    /// Address of the instruction that accessed the watched address
+    pub pc: Address,
+}
+
+pub struct Debugger {
+    /// Execution pauses before executing an instruction at any of these addresses, but only when
+    /// the given bank is mapped at that address
+    breakpoints: HashSet<BankedAddress>,
+
+    watchpoints: HashSet<Watchpoint>,
+
+    /// The last breakpoint that execution stopped at
+    last_breakpoint_hit: Option<BankedAddress>,
+
+    /// Pause after the next instruction is executed
+    is_step_requested: bool,
+
+    /// Address of the instruction currently being executed, if any. Only memory accesses made by
+    /// instructions trigger watchpoints, not accesses by DMA or by tooling inspecting memory.
+    executing_instruction_pc: Option<Address>,
+
+    /// Watchpoint triggered by the instruction being executed. Recorded from memory reads, which
+    /// cannot otherwise modify the emulator.
+    watchpoint_hit: Cell<Option<WatchpointHit>>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: HashSet::new(),
+            watchpoints: HashSet::new(),
+            last_breakpoint_hit: None,
+            is_step_requested: false,
+            executing_instruction_pc: None,
+            watchpoint_hit: Cell::new(None),
+        }
+    }
+
+    pub fn has_breakpoints(&self) -> bool {
+        !self.breakpoints.is_empty()
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: BankedAddress) {
+        self.breakpoints.insert(breakpoint);
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: BankedAddress) {
+        self.breakpoints.remove(&breakpoint);
+    }
+
+    pub fn is_breakpoint(&self, location: BankedAddress) -> bool {
+        self.breakpoints.contains(&location)
+    }
+
+    pub fn last_breakpoint_hit(&self) -> Option<BankedAddress> {
+        self.last_breakpoint_hit
+    }
+
+    pub fn record_breakpoint_hit(&mut self, breakpoint: BankedAddress) {
+        self.last_breakpoint_hit = Some(breakpoint);
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.insert(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.remove(&watchpoint);
+    }
+
+    pub fn is_watchpoint(&self, watchpoint: Watchpoint) -> bool {
+        self.watchpoints.contains(&watchpoint)
+    }
+
+    pub fn request_step(&mut self) {
+        self.is_step_requested = true;
+    }
+
+    pub fn cancel_step(&mut self) {
+        self.is_step_requested = false;
+    }
+
+    /// Whether a step was requested, clearing the request.
+    pub fn take_step_request(&mut self) -> bool {
+        std::mem::replace(&mut self.is_step_requested, false)
+    }
+
+    pub fn start_instruction(&mut self, pc: Address) {
+        self.executing_instruction_pc = Some(pc);
+    }
+
+    /// Finish executing an instruction, returning the watchpoint it triggered if any.
+    pub fn finish_instruction(&mut self) -> Option<WatchpointHit> {
+        self.executing_instruction_pc = None;
+        self.watchpoint_hit.take()
+    }
+
+    /// Record an access to an address if it triggers a watchpoint. Only the address is checked, so
+    /// watching an IO register never causes an extra read with side effects.
+    #[inline]
+    pub fn check_access(&self, address: Address, is_write: bool) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+
+        let Some(pc) = self.executing_instruction_pc else {
+            return;
+        };
+
+        let watchpoint = Watchpoint {
+            address,
+            on_write: is_write,
+        };
+
+        // Only the first access of an instruction is reported
+        if self.watchpoint_hit.get().is_none() && self.watchpoints.contains(&watchpoint) {
+            self.watchpoint_hit
+                .set(Some(WatchpointHit { watchpoint, pc }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::mpsc::{Receiver, Sender, channel},
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    use crate::{
+        emulator::{Command, EmulatorEvent, SharedInputAdapter},
+        machine::Machine,
+        state::CpuState,
+        symbols::BankedAddress,
+        test_utils::{PROGRAM_START, build_test_rom, new_test_emulator_builder},
+    };
+
+    use super::{Watchpoint, WatchpointHit};
+
+    const EVENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Run a ROM on its own thread for a few frames, controlled by the returned command channel.
+    /// Pausing blocks the emulator thread, so the test thread steps and continues it.
+    fn spawn_debugged_emulator(
+        rom: Vec<u8>,
+        setup_commands: Vec<Command>,
+    ) -> (
+        Sender<Command>,
+        Receiver<EmulatorEvent>,
+        JoinHandle<CpuState>,
+    ) {
+        let (commands_tx, commands_rx) = channel();
+        let (events_tx, events_rx) = channel();
+
+        // Commands sent before starting are handled on the first tick
+        for command in setup_commands {
+            commands_tx.send(command).unwrap();
+        }
+
+        let handle = thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(move || {
+                let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                    .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+                    .build()
+                    .unwrap();
+                emulator.emulate_boot_sequence();
+                emulator.run_frames(3);
+                emulator.cpu_state()
+            })
+            .unwrap();
+
+        (commands_tx, events_rx, handle)
+    }
+
+    fn next_event(events_rx: &Receiver<EmulatorEvent>) -> EmulatorEvent {
+        events_rx.recv_timeout(EVENT_TIMEOUT).unwrap()
+    }
+
+    fn breakpoint(address: u16) -> BankedAddress {
+        BankedAddress::new(0, address)
+    }
+
+    #[test]
+    fn step_from_breakpoint() {
+        #[rustfmt::skip]
+        let program = [
+            0x06, 0x00, // ld b, 0
+            0x04,       // inc b
+            0x04,       // inc b
+            0x04,       // inc b
+            0x18, 0xFE, // jr -2
+        ];
+        let rom = build_test_rom(0x00, 0x00, 0x00, &program);
+        let start = PROGRAM_START as u16;
+
+        let (commands_tx, events_rx, handle) =
+            spawn_debugged_emulator(rom, vec![Command::AddBreakpoint(breakpoint(start + 2))]);
+
+        assert_eq!(
+            next_event(&events_rx),
+            EmulatorEvent::BreakpointHit(breakpoint(start + 2))
+        );
+
+        for pc in [start + 3, start + 4, start + 5, start + 5] {
+            commands_tx.send(Command::StepInstruction).unwrap();
+            assert_eq!(next_event(&events_rx), EmulatorEvent::Stepped(pc));
+        }
+
+        commands_tx.send(Command::Continue).unwrap();
+        let cpu_state = handle.join().unwrap();
+        assert_eq!(cpu_state.b, 3);
+        assert!(events_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn breakpoint_in_interrupt_handler() {
+        #[rustfmt::skip]
+        let program = [
+            0x3E, 0x04, // ld a, 0x04
+            0xE0, 0xFF, // ldh [IE], a (enable timer interrupt)
+            0x3E, 0x05, // ld a, 0x05
+            0xE0, 0x07, // ldh [TAC], a (enable timer at its fastest rate)
+            0xFB,       // ei
+            0x18, 0xFE, // loop: jr -2
+        ];
+        let mut rom = build_test_rom(0x00, 0x00, 0x00, &program);
+        let loop_address = PROGRAM_START as u16 + 9;
+
+        // Timer interrupt handler
+        rom[0x0050..0x0052].copy_from_slice(&[
+            0x04, // inc b
+            0xD9, // reti
+        ]);
+
+        let (commands_tx, events_rx, handle) =
+            spawn_debugged_emulator(rom, vec![Command::AddBreakpoint(breakpoint(0x0050))]);
+
+        assert_eq!(
+            next_event(&events_rx),
+            EmulatorEvent::BreakpointHit(breakpoint(0x0050))
+        );
+
+        // Steps through the handler and returns to the interrupted loop
+        commands_tx.send(Command::StepInstruction).unwrap();
+        assert_eq!(next_event(&events_rx), EmulatorEvent::Stepped(0x0051));
+        commands_tx.send(Command::StepInstruction).unwrap();
+        assert_eq!(next_event(&events_rx), EmulatorEvent::Stepped(loop_address));
+
+        // The handler runs again without stopping once the breakpoint is removed
+        commands_tx
+            .send(Command::RemoveBreakpoint(breakpoint(0x0050)))
+            .unwrap();
+        commands_tx.send(Command::Continue).unwrap();
+
+        let cpu_state = handle.join().unwrap();
+        assert!(cpu_state.b > 1);
+    }
+
+    #[test]
+    fn watchpoints_stop_after_access() {
+        #[rustfmt::skip]
+        let program = [
+            0x3E, 0x42,       // ld a, 0x42
+            0xEA, 0x00, 0xC0, // ld [0xC000], a
+            0xF0, 0x00,       // ldh a, [JOYP]
+            0x18, 0xFE,       // jr -2
+        ];
+        let rom = build_test_rom(0x00, 0x00, 0x00, &program);
+        let start = PROGRAM_START as u16;
+
+        let write_watchpoint = Watchpoint {
+            address: 0xC000,
+            on_write: true,
+        };
+        let read_watchpoint = Watchpoint {
+            address: 0xFF00,
+            on_write: false,
+        };
+        let unused_watchpoint = Watchpoint {
+            address: 0xC000,
+            on_write: false,
+        };
+
+        let (commands_tx, events_rx, handle) = spawn_debugged_emulator(
+            rom,
+            vec![
+                Command::AddWatchpoint(write_watchpoint),
+                Command::AddWatchpoint(read_watchpoint),
+                Command::AddWatchpoint(unused_watchpoint),
+            ],
+        );
+
+        assert_eq!(
+            next_event(&events_rx),
+            EmulatorEvent::WatchpointHit(WatchpointHit {
+                watchpoint: write_watchpoint,
+                pc: start + 2,
+            })
+        );
+        commands_tx.send(Command::Continue).unwrap();
+
+        // Reading an IO register with a watchpoint on it reads the value normally
+        assert_eq!(
+            next_event(&events_rx),
+            EmulatorEvent::WatchpointHit(WatchpointHit {
+                watchpoint: read_watchpoint,
+                pc: start + 5,
+            })
+        );
+        commands_tx.send(Command::Continue).unwrap();
+
+        let cpu_state = handle.join().unwrap();
+        assert_eq!(cpu_state.a, 0xCF);
+        assert!(events_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,140 @@
+//! Sources of randomness and time for the emulator.
+//!
+//! Everything the emulator does that is not determined by the ROM and inputs draws from an
+//! `EmulatorRng` or an `EmulatorClock` owned by the emulator. Both are part of the emulator state,
+//! so a run can be reproduced from its seed and a quick save resumes the same sequence.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::{TICKS_PER_FRAME, TICKS_PER_SECOND};
+
+/// A seed taken from the current time, for runs that are not given a seed. This is the only source
+/// of entropy, so the seed must be recorded for the run to be reproduced.
+pub fn entropy_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    nanos as u64
+}
+
+/// SplitMix64, a small PRNG with a 64-bit seed. Not suitable for anything but reproducible noise.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct EmulatorRng {
+    seed: u64,
+    state: u64,
+}
+
+impl EmulatorRng {
+    pub fn new(seed: u64) -> Self {
+        EmulatorRng { seed, state: seed }
+    }
+
+    /// Seed the sequence started from, which reproduces the sequence when passed to `new`
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn fill_bytes(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            let random_bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&random_bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Wall-clock time as seen by the emulated hardware, e.g. by the real time clock in MBC3
+/// cartridges. Starts at a fixed time and advances with emulated time, not with the system clock.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct EmulatorClock {
+    /// Seconds since the UNIX epoch at power-on
+    start_secs: u64,
+    /// Ticks run in completed frames since power-on
+    elapsed_ticks: u64,
+}
+
+impl EmulatorClock {
+    pub fn new(start_secs: u64) -> Self {
+        EmulatorClock {
+            start_secs,
+            elapsed_ticks: 0,
+        }
+    }
+
+    /// A clock that starts at the current system time.
+    pub fn starting_now() -> Self {
+        Self::new(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        )
+    }
+
+    pub fn frame_complete(&mut self) {
+        self.elapsed_ticks += TICKS_PER_FRAME as u64;
+    }
+
+    /// Current time, given the number of ticks run so far in the current frame.
+    pub fn now(&self, tick_within_frame: u32) -> SystemTime {
+        let elapsed_ticks = self.elapsed_ticks + tick_within_frame as u64;
+        let elapsed = Duration::from_secs_f64(elapsed_ticks as f64 / TICKS_PER_SECOND);
+
+        UNIX_EPOCH + Duration::from_secs(self.start_secs) + elapsed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::emulator::TICKS_PER_FRAME;
+
+    use super::{EmulatorClock, EmulatorRng};
+
+    #[test]
+    fn rng_resumes_after_serialization() {
+        let mut rng = EmulatorRng::new(1234);
+        rng.next_u64();
+
+        let mut restored: EmulatorRng =
+            rmp_serde::from_slice(&rmp_serde::to_vec(&rng).unwrap()).unwrap();
+        assert_eq!(restored.seed(), 1234);
+        for _ in 0..4 {
+            assert_eq!(restored.next_u64(), rng.next_u64());
+        }
+
+        let mut other_seed = EmulatorRng::new(5678);
+        assert_ne!(other_seed.next_u64(), EmulatorRng::new(1234).next_u64());
+    }
+
+    #[test]
+    fn clock_advances_with_emulated_time() {
+        let mut clock = EmulatorClock::new(100);
+        assert_eq!(clock.now(0), UNIX_EPOCH + Duration::from_secs(100));
+
+        // Around 60 frames per second
+        for _ in 0..60 {
+            clock.frame_complete();
+        }
+        let elapsed = clock
+            .now(0)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64()
+            - 100.0;
+        assert!(elapsed > 1.0 && elapsed < 1.01);
+
+        assert!(clock.now(TICKS_PER_FRAME as u32 - 1) > clock.now(0));
+    }
+}
@@ -0,0 +1,29 @@
+//! Heuristics that notice when a game is running on hardware it does not support, so that the user
+//! can be told why the game looks broken.
+
+/// Writes to CGB-only registers on a DMG after which a game is assumed to require a CGB. DMG games
+/// have no reason to write to these registers, while CGB games write to them every frame.
+pub const CGB_ONLY_REGISTER_WRITE_THRESHOLD: u32 = 32;
+
+/// Counts writes to registers that only work on a CGB, such as selecting VRAM bank 1, writing CGB
+/// palette data, or starting a VRAM DMA transfer.
+#[derive(Default)]
+pub struct CgbOnlyDetector {
+    num_writes: u32,
+    has_reported: bool,
+}
+
+impl CgbOnlyDetector {
+    /// Count a write to a CGB-only register. Returns true only for the write that reaches the
+    /// threshold, so that the game is reported once.
+    pub fn register_written(&mut self) -> bool {
+        if self.has_reported {
+            return false;
+        }
+
+        self.num_writes += 1;
+        self.has_reported = self.num_writes >= CGB_ONLY_REGISTER_WRITE_THRESHOLD;
+
+        self.has_reported
+    }
+}
@@ -0,0 +1,334 @@
+//! Disassembly of SM83 instructions into human-readable mnemonics.
+//!
+//! Instructions are decoded with the same opcode tables used to execute them, so the disassembly
+//! always matches what the CPU will do.
+
+use crate::{
+    cpu::{
+        R8_OPERAND_A, R8_OPERAND_HL_MEM, R8Operand, R16_OPERAND_HL, R16_OPERAND_SP, R16Operand,
+        format_instruction,
+    },
+    emulator::Emulator,
+};
+
+/// Maximum length of any instruction in bytes
+pub const MAX_INSTRUCTION_LENGTH: usize = 3;
+
+/// Disassemble the instruction at the start of `bytes`, which is located at address `address`.
+///
+/// Returns the formatted instruction and its length in bytes. Invalid opcodes and instructions
+/// that are cut off by the end of `bytes` are shown as a single data byte (e.g. `db $D3`). Returns
+/// a length of 0 only if `bytes` is empty.
+pub fn disassemble(bytes: &[u8], address: u16) -> (String, usize) {
+    if bytes.is_empty() {
+        return (String::new(), 0);
+    }
+
+    let mut formatter = InstructionFormatter::new(address);
+    format_instruction(bytes, &mut formatter);
+
+    if formatter.is_truncated() {
+        let mut formatter = InstructionFormatter::new(address);
+        formatter.opcode("db");
+        formatter.imm8_operand(bytes[0]);
+        return (formatter.builder, 1);
+    }
+
+    let length = formatter.length;
+    (formatter.builder, length)
+}
+
+impl Emulator {
+    /// Disassemble the instruction at the given address as currently mapped into memory. Memory is
+    /// read without side effects in the same way as `read_memory_bulk`, so disassembling never
+    /// triggers a watchpoint.
+    pub fn disassemble_at(&self, address: u16) -> (String, usize) {
+        let bytes = self.read_memory_bulk(address, MAX_INSTRUCTION_LENGTH);
+        disassemble(&bytes, address)
+    }
+}
+
+pub(crate) struct InstructionFormatter {
+    builder: String,
+    /// Address of the instruction, used to resolve relative jump targets
+    address: u16,
+    /// Number of bytes read so far, including the opcode and any prefix
+    length: usize,
+    /// Whether the instruction extends past the end of the bytes being formatted
+    is_truncated: bool,
+}
+
+impl InstructionFormatter {
+    fn new(address: u16) -> Self {
+        Self {
+            builder: String::new(),
+            address,
+            length: 1,
+            is_truncated: false,
+        }
+    }
+
+    pub(crate) fn is_truncated(&self) -> bool {
+        self.is_truncated
+    }
+
+    fn read_operand_byte(&mut self, instrs: &[u8], index: usize) -> u8 {
+        self.length = self.length.max(index + 1);
+
+        match instrs.get(index) {
+            Some(byte) => *byte,
+            None => {
+                self.is_truncated = true;
+                0
+            }
+        }
+    }
+
+    /// Read the imm8 operand (or prefixed opcode) following the opcode at the start of `instrs`.
+    pub(crate) fn read_imm8(&mut self, instrs: &[u8]) -> u8 {
+        self.read_operand_byte(instrs, 1)
+    }
+
+    /// Read the imm16 operand following the opcode at the start of `instrs`.
+    pub(crate) fn read_imm16(&mut self, instrs: &[u8]) -> u16 {
+        let low = self.read_operand_byte(instrs, 1) as u16;
+        let high = self.read_operand_byte(instrs, 2) as u16;
+
+        (high << 8) | low
+    }
+
+    pub(crate) fn opcode(&mut self, opcode: &str) {
+        self.builder.push_str(opcode);
+        self.builder.push(' ');
+    }
+
+    pub(crate) fn simple_opcode(&mut self, opcode: &str) {
+        self.builder.push_str(opcode);
+    }
+
+    pub(crate) fn comma(&mut self) {
+        self.builder.push_str(", ");
+    }
+
+    pub(crate) fn plus(&mut self) {
+        self.builder.push_str(" + ");
+    }
+
+    pub(crate) fn r8_operand(&mut self, operand: R8Operand) {
+        let formatted = match operand {
+            0 => "b",
+            1 => "c",
+            2 => "d",
+            3 => "e",
+            4 => "h",
+            5 => "l",
+            R8_OPERAND_HL_MEM => "[hl]",
+            R8_OPERAND_A => "a",
+            _ => panic!("Invalid r8 operand"),
+        };
+
+        self.builder.push_str(formatted);
+    }
+
+    pub(crate) fn a_operand(&mut self) {
+        self.builder.push('a');
+    }
+
+    pub(crate) fn r16_operand(&mut self, operand: R16Operand) {
+        let formatted = match operand {
+            0 => "bc",
+            1 => "de",
+            R16_OPERAND_HL => "hl",
+            R16_OPERAND_SP => "sp",
+            _ => panic!("Invalid r16 operand"),
+        };
+
+        self.builder.push_str(formatted);
+    }
+
+    pub(crate) fn hl_operand(&mut self) {
+        self.builder.push_str("hl");
+    }
+
+    pub(crate) fn sp_operand(&mut self) {
+        self.builder.push_str("sp");
+    }
+
+    pub(crate) fn af_operand(&mut self) {
+        self.builder.push_str("af");
+    }
+
+    pub(crate) fn r16_mem_operand(&mut self, operand: u8) {
+        let formatted = match operand {
+            0 => "[bc]",
+            1 => "[de]",
+            _ => panic!("Invalid r16 mem operand"),
+        };
+
+        self.builder.push_str(formatted);
+    }
+
+    pub(crate) fn cc_operand(&mut self, operand: u8) {
+        let formatted = match operand {
+            0 => "nz",
+            1 => "z",
+            2 => "nc",
+            3 => "c",
+            _ => panic!("Invalid cc operand"),
+        };
+
+        self.builder.push_str(formatted);
+    }
+
+    pub(crate) fn imm8_operand(&mut self, operand: u8) {
+        self.builder.push_str(&format!("${:02X}", operand));
+    }
+
+    pub(crate) fn imm16_operand(&mut self, operand: u16) {
+        self.builder.push_str(&format!("${:04X}", operand));
+    }
+
+    pub(crate) fn imm16_mem_operand(&mut self, operand: u16) {
+        self.builder.push_str(&format!("[${:04X}]", operand));
+    }
+
+    pub(crate) fn imm8_signed_operand(&mut self, operand: u8) {
+        self.builder.push_str(&format!("{}", operand as i8));
+    }
+
+    /// Target of a relative jump, resolved to an absolute address. Offset is relative to the end
+    /// of the 2-byte jump instruction.
+    pub(crate) fn relative_jump_target_operand(&mut self, operand: u8) {
+        let target = self
+            .address
+            .wrapping_add(2)
+            .wrapping_add_signed(operand as i8 as i16);
+        self.imm16_operand(target);
+    }
+
+    pub(crate) fn cmem_operand(&mut self) {
+        self.builder.push_str("[c]");
+    }
+
+    pub(crate) fn hli_operand(&mut self) {
+        self.builder.push_str("[hl+]");
+    }
+
+    pub(crate) fn hld_operand(&mut self) {
+        self.builder.push_str("[hl-]");
+    }
+
+    pub(crate) fn rst_target_operand(&mut self, operand: u8) {
+        self.imm8_operand(operand);
+    }
+
+    pub(crate) fn bit_index_operand(&mut self, operand: u8) {
+        self.builder.push_str(&format!("{}", operand));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        machine::Machine,
+        state::CpuState,
+        test_utils::{
+            build_test_rom, new_test_emulator, new_test_emulator_builder, with_large_stack,
+        },
+    };
+
+    use super::disassemble;
+
+    const WORK_RAM_ADDRESS: u16 = 0xC000;
+
+    const CONTROL_FLOW_MNEMONICS: [&str; 9] = [
+        "jp", "jr", "call", "ret", "reti", "rst", "halt", "stop", "db",
+    ];
+
+    #[test]
+    fn disassemble_resolves_operands() {
+        assert_eq!(
+            disassemble(&[0xF0, 0x44], 0x0150),
+            ("ldh a, [$FF44]".to_string(), 2)
+        );
+        assert_eq!(
+            disassemble(&[0xFA, 0x44, 0xFF], 0x0150),
+            ("ld a, [$FF44]".to_string(), 3)
+        );
+        assert_eq!(
+            disassemble(&[0x20, 0xFE], 0x0150),
+            ("jr nz, $0150".to_string(), 2)
+        );
+        assert_eq!(
+            disassemble(&[0xCB, 0x7C, 0x00], 0x0150),
+            ("bit 7, h".to_string(), 2)
+        );
+        assert_eq!(disassemble(&[0x00, 0x01], 0x0150), ("nop".to_string(), 1));
+    }
+
+    #[test]
+    fn disassemble_invalid_and_truncated() {
+        assert_eq!(disassemble(&[0xD3], 0), ("db $D3".to_string(), 1));
+        assert_eq!(disassemble(&[0xFD, 0x00], 0), ("db $FD".to_string(), 1));
+
+        // CB prefix at the end of ROM
+        assert_eq!(disassemble(&[0xCB], 0x7FFF), ("db $CB".to_string(), 1));
+
+        // Missing imm16 bytes
+        assert_eq!(disassemble(&[0xC3, 0x50], 0), ("db $C3".to_string(), 1));
+        assert_eq!(disassemble(&[], 0), (String::new(), 0));
+    }
+
+    #[test]
+    fn disassemble_imm16_across_region_boundary() {
+        with_large_stack(|| {
+            // jp $1234 at the very end of ROM bank 0, with the imm16 in the switchable bank
+            let mut rom = build_test_rom(0x01, 0x01, 0x00, &[]);
+            rom[0x3FFF] = 0xC3;
+            rom[0x4000] = 0x34;
+            rom[0x4001] = 0x12;
+
+            let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                .build()
+                .unwrap();
+            emulator.emulate_boot_sequence();
+
+            assert_eq!(emulator.disassemble_at(0x3FFF), ("jp $1234".to_string(), 3));
+
+            // Wraps around the end of the address space instead of panicking
+            assert_eq!(emulator.disassemble_at(0xFFFF).1, 1);
+        });
+    }
+
+    #[test]
+    fn length_matches_execution() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &[]);
+
+            let initial_state = emulator.cpu_state();
+
+            for prefix in [None, Some(0xCB)] {
+                for opcode in 0..=255 {
+                    let bytes: Vec<u8> = prefix.into_iter().chain([opcode, 0x00, 0x00]).collect();
+                    let (formatted, length) = disassemble(&bytes, WORK_RAM_ADDRESS);
+
+                    // Skip instructions that change control flow or stop the CPU
+                    let mnemonic = formatted.split(' ').next().unwrap();
+                    if CONTROL_FLOW_MNEMONICS.contains(&mnemonic) {
+                        continue;
+                    }
+
+                    emulator.write_memory_bulk(WORK_RAM_ADDRESS, &bytes);
+                    emulator.set_cpu_state(CpuState {
+                        pc: WORK_RAM_ADDRESS,
+                        ..initial_state
+                    });
+                    emulator.execute_instruction();
+
+                    let executed_length = emulator.cpu_state().pc.wrapping_sub(WORK_RAM_ADDRESS);
+                    assert_eq!(executed_length as usize, length, "{}", formatted);
+                }
+            }
+        });
+    }
+}
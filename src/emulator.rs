@@ -1,32 +1,71 @@
 use std::{
-    fs, mem,
+    array,
+    collections::VecDeque,
+    fmt, fs,
+    io::{self, Write},
+    mem,
     ops::Deref,
-    sync::{Arc, mpsc::Receiver},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{Receiver, RecvError, RecvTimeoutError, Sender},
+    },
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
+use eframe::egui::Color32;
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 
 use crate::{
     address_space::{
-        Address, CGB_BIOS_END, DMG_BIOS_END, ECHO_RAM_END, EXTERNAL_RAM_END,
-        FIRST_WORK_RAM_BANK_END, FIRST_WORK_RAM_BANK_START, HRAM_END, HRAM_SIZE, HRAM_START,
-        IE_ADDRESS, IO_REGISTERS_END, OAM_END, OAM_SIZE, OAM_START, ROM_END,
-        SECOND_WORK_RAM_BANK_END, SECOND_WORK_RAM_BANK_START, SINGLE_VRAM_BANK_SIZE,
-        SINGLE_WORK_RAM_BANK_SIZE, UNUSABLE_SPACE_END, VRAM_END, VRAM_START,
+        Address, CGB_BIOS_END, CGB_BIOS_HOLE_END, CGB_BIOS_HOLE_START, DMG_BIOS_END, ECHO_RAM_END,
+        EXTERNAL_RAM_END, FIRST_WORK_RAM_BANK_END, FIRST_WORK_RAM_BANK_START, HRAM_END, HRAM_SIZE,
+        HRAM_START, IE_ADDRESS, IO_REGISTERS_END, IO_REGISTERS_SIZE, IO_REGISTERS_START, OAM_END,
+        OAM_SIZE, OAM_START, ROM_BANK_SIZE, ROM_END, ROM_START, SECOND_WORK_RAM_BANK_END,
+        SECOND_WORK_RAM_BANK_START, SINGLE_VRAM_BANK_SIZE, SINGLE_WORK_RAM_BANK_SIZE,
+        UNUSABLE_SPACE_END, VRAM_END, VRAM_START, WAVE_RAM_END, WAVE_RAM_START,
+    },
+    audio::{
+        Apu, AudioFrame, AudioOutput, MAX_SAMPLES_PER_AUDIO_FRAME, TICKS_PER_SAMPLE, TimedSample,
     },
-    audio::{Apu, AudioFrame, AudioOutput, TICKS_PER_SAMPLE, TimedSample},
-    cartridge::Cartridge,
+    cartridge::{Cartridge, CgbFlag},
+    compat_palettes::compat_palettes_for_rom,
+    debugger::{Debugger, Watchpoint, WatchpointHit},
+    determinism::{EmulatorClock, EmulatorRng, entropy_seed},
+    diagnostics::CgbOnlyDetector,
+    error::Error,
+    frame_pacer::{CatchUp, FramePacer},
+    frame_timing::{FrameTimings, TimingCategory, TimingScope},
     frame_tracker::FrameTracker,
+    gui::gamepad::GamepadMapping,
     io_registers::IoRegisters,
-    machine::Machine,
-    mbc::types::Location,
+    machine::{CompatMode, Machine},
+    mbc::types::{Location, MbcDebugInfo},
+    object_priority::SpritePriority,
     options::Options,
-    ppu::{Color, WindowLineCounter, draw_scanline},
+    ppu::{
+        CgbColor, Color, DrawTimingMetrics, MIN_DRAW_TICKS, PaletteCache, PaletteColor,
+        PixelProvenance, PixelProvenanceBuffer, ScanlineRenderer, VideoSink, WindowLineCounter,
+        cgb_color_offset, draw_scanline, skip_scanline,
+    },
+    ppu_dump,
+    ram_init::RamInit,
     registers::Registers,
-    save_file::{NUM_QUICK_SAVE_SLOTS, SAVE_FILE_AUTO_FLUSH_INTERVAL_SECS, SaveFile},
+    rewind::RewindBuffer,
+    save_compat::{self, BlobKind},
+    save_file::{
+        NUM_QUICK_SAVE_SLOTS, SaveFile, SaveFileError, SaveFileFlushState, StateFile,
+        load_raw_save, raw_ram_bytes, raw_save_bytes, write_file_atomically, write_with_fallback,
+    },
+    screen_palette::ScreenColorPalette,
+    shared_stats::SharedStats,
+    state::{CpuState, PpuState},
+    symbols::BankedAddress,
+    tile_map_export,
+    watchdog::PcHistory,
 };
 
 /// Width of the gameboy screen in pixels
@@ -50,23 +89,87 @@ pub enum Button {
 
 type ButtonSet = u8;
 
+/// Result reported by a test ROM through the registers, following the Mooneye convention.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestResult {
+    Passed,
+    Failed,
+}
+
+/// Where the CPU locked up after executing an invalid opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuLock {
+    /// Address of the invalid opcode
+    pub address: Address,
+    pub opcode: u8,
+}
+
 pub struct SharedInputAdapter {
     commands_rx: Receiver<Command>,
+    events_tx: Sender<EmulatorEvent>,
+    /// Commands received from the channel that have not been handled yet, with button updates
+    /// coalesced
+    pending_commands: VecDeque<Command>,
 }
 
+/// Version of the command protocol between the GUI and the emulator thread.
+///
+/// Version history:
+/// - 1: Commands that can fail carry a `CommandId` and are acknowledged with a
+///   `EmulatorEvent::CommandResult`.
+pub const COMMAND_PROTOCOL_VERSION: u32 = 1;
+
+/// Identifies a single command sent to the emulator so that its result can be matched to it.
+/// Chosen by the sender, the emulator only echoes it back.
+pub type CommandId = u64;
+
+/// Number of commands the commands channel holds before senders see it as full.
+///
+/// The emulator drains the channel at least once per millisecond, so it only fills when the
+/// emulator thread has stalled. Senders should not block on a full channel. Instead the GUI drops
+/// the command, retrying button and turbo updates once there is room so that the latest state is
+/// always delivered.
+pub const COMMANDS_CHANNEL_CAPACITY: usize = 1024;
+
+/// Commands sent from the GUI to the emulator thread over the commands channel.
+///
+/// Commands are handled in order, at least once per millisecond of emulated time (including while
+/// paused or stopped). Only the latest set of pressed buttons matters, so a button update that has
+/// not been handled yet is replaced by any later one, unless emulation may run between them.
+///
+/// Commands that cannot fail are fire-and-forget. Commands that can fail carry a `CommandId` and
+/// the emulator responds to each with exactly one `EmulatorEvent::CommandResult` on the events
+/// channel, sent after the command has been fully handled.
 pub enum Command {
     /// Send a new set of pressed buttons encoded as a byte
     UpdatePressedButtons(u8),
     /// Toggle paused state of the emulator
     TogglePause,
+    /// Run exactly one frame's worth of ticks then pause again. Only has an effect while paused.
+    /// Commands sent after this one are not handled until the frame has finished.
+    FrameAdvance,
     /// Save the entire emulator state to disk
-    Save,
+    Save(CommandId),
     /// Save emulator state into the given quick save slot
-    QuickSave(usize),
-    /// Load a quick save from the given slot
-    LoadQuickSave(usize),
-    /// Set whether the emulator is in turbo mode
+    QuickSave(usize, CommandId),
+    /// Load a quick save from the given slot. Results in `CommandError::NotFound` if the slot is
+    /// empty.
+    LoadQuickSave(usize, CommandId),
+    /// Write the entire emulator state to a standalone state file at the given path
+    ExportState(PathBuf, CommandId),
+    /// Load a state file written by `ExportState`. Results in `CommandError::InvalidData` and leaves
+    /// the emulator untouched if the state belongs to a different ROM.
+    ImportState(PathBuf, CommandId),
+    /// Set whether the emulator is in turbo mode, which runs at `Options::turbo_speed`. Leaving
+    /// turbo mode returns to normal speed.
     SetTurboMode(bool),
+    /// Run at the given multiple of the GameBoy's native speed, clamped between `MIN_SPEED` and
+    /// `MAX_SPEED`. Audio is played faster or slower to match.
+    SetSpeed(f64),
+    /// Set whether the emulator is rewinding. While rewinding the emulator steps back through the
+    /// snapshots taken every `Options::rewind_interval_frames` frames, one per frame, instead of
+    /// running. Audio is muted while rewinding.
+    SetRewinding(bool),
     /// Increase volume of the emulator
     VolumeUp,
     /// Decrease volume of the emulator
@@ -77,11 +180,152 @@ pub enum Command {
     ToggleAudioChannel(usize),
     /// Toggle the high pass filter on or off
     ToggleHpf,
+    /// Write a dump of all PPU state to a new directory at the given path
+    DumpPpuState(PathBuf, CommandId),
+    /// Export a tile map (0 or 1) as a Tiled JSON map at the given path, interpreting tile indices
+    /// with the given tile data addressing mode (0 or 1)
+    ExportTileMap(u8, u8, PathBuf, CommandId),
+    /// Pause before executing the instruction at an address when the given bank is mapped there
+    AddBreakpoint(BankedAddress),
+    /// Remove a breakpoint added with `AddBreakpoint`
+    RemoveBreakpoint(BankedAddress),
+    /// Pause after an instruction accesses the watched address
+    AddWatchpoint(Watchpoint),
+    /// Remove a watchpoint added with `AddWatchpoint`
+    RemoveWatchpoint(Watchpoint),
+    /// Execute a single instruction then pause. Waits for the next instruction if the CPU is
+    /// halted.
+    StepInstruction,
+    /// Resume execution if paused
+    Continue,
+    /// Change the colors that DMG shades are shown as, starting from the next frame
+    SetScreenPalette(ScreenColorPalette),
+    /// Set whether to record which layer, palette, and color index each drawn pixel came from
+    SetRecordPixelProvenance(bool),
+    /// Overwrite a color in CGB palette memory directly, for experimenting with palettes. The game
+    /// may overwrite the color again at any time.
+    WriteCgbPalette {
+        is_object: bool,
+        palette: u8,
+        index: u8,
+        color: u16,
+    },
+    /// Overwrite cartridge RAM starting at an offset into the RAM declared in the header, even if
+    /// the game has disabled RAM. Results in `CommandError::InvalidData` if the bytes do not fit.
+    PatchCartridgeRam(usize, Vec<u8>, CommandId),
+    /// Write the cartridge RAM to a file in the raw save file format
+    ExportCartridgeRam(PathBuf, CommandId),
+    /// Replace the cartridge RAM with a raw save file. Results in `CommandError::InvalidData` if
+    /// its size does not match the cartridge RAM.
+    ImportCartridgeRam(PathBuf, CommandId),
+    /// Write a byte to memory as seen by the CPU, in the same way as `write_memory_bulk`
+    WriteMemory(Address, u8),
+    /// Stop running, even if paused, and flush the save file. `run` returns once handled.
+    Shutdown,
+}
+
+/// Reasons a command can fail.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandError {
+    /// The requested item does not exist, e.g. an empty quick save slot
+    NotFound(String),
+    /// The command requires a save file but the emulator is running without one
+    NoSaveFile,
+    /// Stored data could not be decoded
+    InvalidData(String),
+    /// A file could not be read or written
+    Io(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotFound(item) => write!(f, "{} not found", item),
+            CommandError::NoSaveFile => write!(f, "no save file"),
+            CommandError::InvalidData(reason) => write!(f, "invalid data: {}", reason),
+            CommandError::Io(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Events sent from the emulator to the GUI.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmulatorEvent {
+    /// The save file or raw save file could not be written. Only sent for the first failure. If a
+    /// fallback location was written to instead it is included, otherwise saves are disabled.
+    SaveFileWriteFailed {
+        reason: String,
+        fallback_path: Option<String>,
+    },
+    /// Execution paused at a breakpoint. Send `Command::Continue` to continue.
+    BreakpointHit(BankedAddress),
+    /// Execution paused after an instruction accessed a watched address
+    WatchpointHit(WatchpointHit),
+    /// Execution paused after `Command::StepInstruction`, before the instruction at this address
+    Stepped(Address),
+    /// The game keeps writing to registers that only work on a CGB while running on a DMG, so it
+    /// likely requires a CGB and is not drawn correctly. Only sent once.
+    CgbRequired,
+    /// Acknowledgement of a command that can fail
+    CommandResult {
+        command_id: CommandId,
+        result: Result<(), CommandError>,
+    },
+}
+
+impl Command {
+    /// Whether emulation may run between this command and the next one handled.
+    fn may_run_emulation(&self) -> bool {
+        matches!(
+            self,
+            Command::TogglePause
+                | Command::FrameAdvance
+                | Command::StepInstruction
+                | Command::Continue
+        )
+    }
 }
 
 impl SharedInputAdapter {
-    pub fn new(commands_rx: Receiver<Command>) -> Self {
-        Self { commands_rx }
+    pub fn new(commands_rx: Receiver<Command>, events_tx: Sender<EmulatorEvent>) -> Self {
+        Self {
+            commands_rx,
+            events_tx,
+            pending_commands: VecDeque::new(),
+        }
+    }
+
+    /// Queue a command to be handled, replacing any earlier button update that has not been
+    /// handled yet if this is a button update.
+    fn push_pending_command(&mut self, command: Command) {
+        if matches!(command, Command::UpdatePressedButtons(_)) {
+            let earlier_index = self.pending_commands.iter().rposition(|pending| {
+                matches!(pending, Command::UpdatePressedButtons(_)) || pending.may_run_emulation()
+            });
+
+            if let Some(index) = earlier_index
+                && matches!(
+                    self.pending_commands[index],
+                    Command::UpdatePressedButtons(_)
+                )
+            {
+                self.pending_commands.remove(index);
+            }
+        }
+
+        self.pending_commands.push_back(command);
+    }
+
+    /// Move commands that have arrived into the pending queue without blocking, up to
+    /// `COMMANDS_CHANNEL_CAPACITY` at a time, then return the next one to handle.
+    fn next_command(&mut self) -> Option<Command> {
+        while self.pending_commands.len() < COMMANDS_CHANNEL_CAPACITY
+            && let Ok(command) = self.commands_rx.try_recv()
+        {
+            self.push_pending_command(command);
+        }
+
+        self.pending_commands.pop_front()
     }
 }
 
@@ -93,6 +337,9 @@ pub const REFRESH_RATE: f64 = 59.7;
 /// Total number of scanlines including VBlank period. Larger than the height of the screen.
 const NUM_VIRTUAL_SCANLINES: usize = 154;
 
+/// A tick is a single PPU dot. The PPU and APU advance once per tick in both speed modes, while the
+/// CPU, timer, serial port, and OAM DMA are clocked by the CPU and advance twice per tick in double
+/// speed mode. See `Emulator::cpu_ticks_per_tick`.
 pub const TICKS_PER_FRAME: usize = 70224;
 
 pub const TICKS_PER_SECOND: f64 = TICKS_PER_FRAME as f64 * REFRESH_RATE;
@@ -103,34 +350,52 @@ pub const TICKS_PER_MILLISECOND_U32: u32 = TICKS_PER_MILLISECOND as u32;
 
 const TICKS_PER_SCANLINE: usize = TICKS_PER_FRAME / NUM_VIRTUAL_SCANLINES;
 
+/// The last scanline, during which LY only reads 153 briefly before reading 0
+const LAST_SCANLINE: u8 = NUM_VIRTUAL_SCANLINES as u8 - 1;
+
+/// Number of ticks at the start of the last scanline before LY reads 0
+const LAST_SCANLINE_LY_RESET_TICKS: usize = 4;
+
 /// Number of ticks in OAM Scan mode at the beginning of each scanline
 const OAM_SCAN_TICKS: usize = 80;
 
-/// Number of ticks in Draw mode. In reality this is variable, but we choose the minimum time here.
-const DRAW_TICKS: usize = 172;
-
-/// Total number of ticks to complete an OAM DMA transfer
+/// Total number of CPU ticks to complete an OAM DMA transfer. Clocked by the CPU, so it takes half as
+/// many ticks in double speed mode.
 const OAM_DMA_TRANSFER_TICKS: usize = 640;
 
 /// Size of a single block transferred in a VRAM DMA transfer
 const VRAM_DMA_TRANSFER_BLOCK_SIZE: u16 = 16;
 
-/// Number of ticks to transfer a single 16-byte block in a VRAM DMA transfer
+/// Number of ticks to transfer a single 16-byte block in a VRAM DMA transfer. Takes the same time in
+/// both speed modes, which is twice as many CPU machine cycles in double speed mode.
 const VRAM_DMA_TRANSFER_TICKS_PER_BLOCK: usize = 32;
 
+/// Number of CPU ticks in a machine cycle
+const CPU_TICKS_PER_MACHINE_CYCLE: usize = 4;
+
 /// Number of ticks to halt after executing a speed switch
 const SPEED_SWITCH_TICKS: usize = 0x20000;
 
-/// How much faster the emulator tries to run in turbo mode
-const TURBO_MULTIPLIER: u64 = 10;
+/// Speed the emulator runs at normally, as a multiple of the GameBoy's native speed
+pub const NORMAL_SPEED: f64 = 1.0;
 
-/// Nanoseconds in real time per frame in regular mode
-const NS_PER_FRAME: f64 = 1_000_000_000.0f64 / REFRESH_RATE;
+/// Speed the emulator runs at in turbo mode, unless set in the options
+pub const DEFAULT_TURBO_SPEED: f64 = 10.0;
 
-/// Nanoseconds in real time per frame in turbo mode
-const NS_PER_MICROFRAME: f64 = NS_PER_FRAME / (TURBO_MULTIPLIER as f64);
+/// Slowest speed the emulator can run at
+pub const MIN_SPEED: f64 = 0.1;
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+/// Fastest speed the emulator can run at
+pub const MAX_SPEED: f64 = 16.0;
+
+/// Number of ticks between a button press waking the CPU from STOP mode and the next instruction
+/// executing. We resume after a single machine cycle.
+const STOP_WAKE_TICKS: usize = 4;
+
+/// Nanoseconds in real time per frame at normal speed
+const NS_PER_FRAME: f64 = 1_000_000_000.0f64 / REFRESH_RATE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Mode {
     /// Mode 0: Move to the next scanline
     HBlank,
@@ -212,12 +477,58 @@ enum PendingEnableInterrupt {
     },
 }
 
+/// Progress through the instruction being executed in cycle accurate mode. The rest of the system is
+/// advanced one machine cycle before each memory access after the opcode fetch, so that accesses
+/// happen on the machine cycle they would on hardware rather than all at the start of the
+/// instruction.
+#[derive(Default)]
+struct CpuStepState {
+    /// Number of memory accesses made so far by the current instruction
+    num_accesses: usize,
+    /// Number of CPU ticks that have already elapsed during the current instruction
+    elapsed_cpu_ticks: usize,
+    /// Number of ticks the rest of the system has been advanced during the current instruction
+    elapsed_ticks: usize,
+}
+
+/// Progress of TIMA being reloaded from TMA after it overflows. The reload happens one machine cycle
+/// after the overflow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum TimaOverflow {
+    #[default]
+    None,
+    /// TIMA overflowed and reads as 0 until it is reloaded. Writing TIMA cancels the reload and
+    /// the timer interrupt.
+    Pending { ticks_remaining: usize },
+    /// TIMA was reloaded from TMA during the current machine cycle. Writes to TIMA are ignored, and
+    /// writes to TMA are also written to TIMA.
+    Reloading { ticks_remaining: usize },
+}
+
 #[derive(Serialize, Deserialize)]
 struct OamDmaTransfer {
     /// The source address which data is copied from into OAM
     source_address: Address,
     /// The number of ticks until this transfer is complete
     ticks_remaining: usize,
+    /// The number of bytes that have been copied into OAM so far
+    #[serde(default)]
+    num_bytes_copied: usize,
+    /// The byte most recently copied, which the CPU sees when reading outside the high page
+    #[serde(default = "default_oam_dma_current_byte")]
+    current_byte: u8,
+}
+
+fn default_oam_dma_current_byte() -> u8 {
+    0xFF
+}
+
+fn default_speed() -> f64 {
+    NORMAL_SPEED
+}
+
+fn default_screen_colors() -> Box<[Color32]> {
+    vec![Color32::WHITE; SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice()
 }
 
 #[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -243,9 +554,23 @@ const TAC_MASK_64_TICKS: u16 = 0x0020;
 const TAC_MASK_256_TICKS: u16 = 0x0080;
 const TAC_MASK_1024_TICKS: u16 = 0x0200;
 
+/// Divider register bit whose falling edge shifts one bit of a serial transfer using the internal
+/// clock, which runs at 8192 Hz.
+const SERIAL_CLOCK_MASK: u16 = 0x0100;
+
+/// Divider register bit used instead when the CGB fast serial clock is selected, which runs at
+/// 262144 Hz.
+const SERIAL_FAST_CLOCK_MASK: u16 = 0x0008;
+
+/// Number of bits shifted in a single serial transfer
+const SERIAL_TRANSFER_BITS: u8 = 8;
+
 const IE_INIT: Register = 0x00;
 
-/// Value returned when reading from VRAM (or CGB palettes) during draw mode
+/// Echo RAM (0xE000-0xFE00) mirrors work RAM starting at 0xC000
+const ECHO_RAM_OFFSET: Address = 0x2000;
+
+/// Value returned when reading from VRAM, OAM, or CGB palettes while the PPU is using them
 pub const VRAM_READ_FAILED_VALUE: u8 = 0xFF;
 
 pub type CgbPaletteData = [u8; 64];
@@ -268,10 +593,27 @@ pub struct Emulator {
     #[serde(with = "serde_big_array::BigArray")]
     pixels: [serde_big_array::Array<Color, SCREEN_WIDTH>; SCREEN_HEIGHT],
 
+    /// Each pixel on the screen in row-major order as displayed, with the screen palette applied.
+    /// Rebuilt from `pixels` when the screen palette changes.
+    #[serde(skip, default = "default_screen_colors")]
+    screen_colors: Box<[Color32]>,
+
     /// Sender for audio samples, batched by frame
     #[serde(skip)]
     audio_output: Option<Box<dyn AudioOutput>>,
 
+    /// Receiver for each drawn frame, if any
+    #[serde(skip)]
+    video_sink: Option<Box<dyn VideoSink>>,
+
+    /// Performance statistics shared with the GUI, if any
+    #[serde(skip)]
+    shared_stats: Option<Arc<SharedStats>>,
+
+    /// Every byte sent over the serial port, if serial output is being captured
+    #[serde(skip)]
+    serial_output: Option<Vec<u8>>,
+
     /// Contents of the BIOS used during boot, if any
     bios: Option<Vec<u8>>,
 
@@ -283,16 +625,36 @@ pub struct Emulator {
     #[serde(skip)]
     save_file_path: Option<String>,
 
+    /// Failures writing the save file, used to back off retries
+    #[serde(skip)]
+    save_file_flush_state: SaveFileFlushState,
+
+    /// The path to also write battery-backed RAM to as a raw .sav file, if any
+    #[serde(skip)]
+    raw_save_file_path: Option<String>,
+
+    /// The path to write the state to on a clean shutdown and to resume from on launch, if resuming
+    /// on launch is enabled
+    #[serde(skip)]
+    auto_state_path: Option<String>,
+
+    /// Whether to resume from the auto-state once `run` has powered on the emulator
+    #[serde(skip)]
+    should_resume_from_auto_state: bool,
+
     /// The machine type being emulated (DMG or CGB)
     machine: Machine,
 
     /// Current tick (T-cycle) within a frame
     tick: u32,
 
-    /// Current turbo frame number. The microframe number divided by the turbo multiplier is the
-    /// frame number in regular mode.
+    /// Ticks the last call to `run_frame` ran past its frame in cycle accurate mode
+    #[serde(skip)]
+    frame_overshoot_ticks: usize,
+
+    /// Decides when each frame should start while running in real time
     #[serde(skip)]
-    microframe: u64,
+    frame_pacer: FramePacer,
 
     /// Current (virtual) scanline number
     scanline: u8,
@@ -300,7 +662,8 @@ pub struct Emulator {
     /// Current mode
     mode: Mode,
 
-    /// Whether the emulator is currently in CGB mode
+    /// Whether CGB features are enabled. False on a DMG and in DMG compatibility mode. Together with
+    /// `is_pgb_mode` this stores the mode selected by KEY0, see `compat_mode`.
     in_cgb_mode: bool,
 
     /// VRAM region, including all banks
@@ -337,9 +700,23 @@ pub struct Emulator {
     /// Object color palettes (CGB only)
     cgb_object_palettes: Box<StoredCgbPalette>,
 
-    /// Number of ticks remaining until the next instruction is executed
+    /// All palettes decoded into color tables, rebuilt from the palette registers when dirty
+    #[serde(skip, default = "PaletteCache::new")]
+    palette_cache: PaletteCache,
+
+    /// Progress drawing the current scanline during Draw mode. Not saved, so a quick save made
+    /// partway through Draw mode leaves the rest of that scanline undrawn when loaded.
+    #[serde(skip)]
+    scanline_renderer: Option<ScanlineRenderer>,
+
+    /// Number of CPU ticks remaining until the next instruction is executed
     ticks_to_next_instruction: usize,
 
+    /// Progress through the current instruction, only present while an instruction is executing in
+    /// cycle accurate mode
+    #[serde(skip)]
+    cpu_step_state: Option<CpuStepState>,
+
     /// State machine for `ei` instructions to enable interrupts after the next instruction
     pending_enable_interrupts: PendingEnableInterrupt,
 
@@ -352,6 +729,15 @@ pub struct Emulator {
     /// The number of ticks remaining in a general purpose VRAM DMA transfer, if one is in progress
     current_general_purpose_vram_dma_transfer: Option<usize>,
 
+    /// Whether a VRAM DMA transfer with a source in VRAM has been reported, so that games which
+    /// start one every frame only produce a single diagnostic
+    #[serde(skip)]
+    has_reported_vram_dma_source_in_vram: bool,
+
+    /// Writes to CGB-only registers while running on a DMG, for noticing games that require a CGB
+    #[serde(skip)]
+    cgb_only_detector: CgbOnlyDetector,
+
     /// The number of ticks remaining in the current CPU halt after a speed switch was executed
     current_speed_switch: Option<usize>,
 
@@ -377,9 +763,31 @@ pub struct Emulator {
     /// Whether the timer (TIMA) can be incremented
     is_timer_enabled: bool,
 
-    /// Whether the emulator is currently in turbo mode
+    /// Speed the emulator is running at, as a multiple of the GameBoy's native speed
+    #[serde(skip, default = "default_speed")]
+    speed: f64,
+
+    /// Number of frames started since speeding up past normal speed, used to pick which frames to
+    /// render
+    #[serde(skip)]
+    fast_frame_index: u64,
+
+    /// Recent snapshots of the emulator state to rewind to
+    #[serde(skip, default = "RewindBuffer::new")]
+    rewind_buffer: RewindBuffer,
+
+    /// Whether the emulator is currently rewinding instead of running
+    #[serde(skip)]
+    is_rewinding: bool,
+
+    /// Whether scanlines in the current frame are processed without being drawn, since the frame
+    /// would never be displayed
+    #[serde(skip)]
+    is_skipping_render: bool,
+
+    /// Total number of frames whose pixels were drawn
     #[serde(skip)]
-    in_turbo_mode: bool,
+    num_rendered_frames: u64,
 
     /// Whether the emulator is currently booting (running the boot ROM)
     is_booting: bool,
@@ -391,12 +799,116 @@ pub struct Emulator {
     #[serde(skip)]
     is_paused: bool,
 
+    /// Number of ticks left to run before pausing again, while advancing a single frame
+    #[serde(skip)]
+    frame_advance_ticks_remaining: Option<usize>,
+
+    /// Whether a shutdown was requested, after which the emulator stops running
+    #[serde(skip)]
+    is_shutting_down: bool,
+
     /// All audio samples in the current frame
     current_audio_frame: AudioFrame,
 
     /// Utility which tracks frame rate
     #[serde(skip)]
     frame_tracker: FrameTracker,
+
+    /// Draw mode timing metrics for the frame in progress
+    #[serde(skip, default = "DrawTimingMetrics::new")]
+    current_draw_timing_metrics: DrawTimingMetrics,
+
+    /// Draw mode timing metrics for the last completed frame
+    #[serde(skip, default = "DrawTimingMetrics::new")]
+    last_draw_timing_metrics: DrawTimingMetrics,
+
+    /// Time spent in each part of the frame in progress, if the `frame-timing` feature is enabled
+    #[serde(skip)]
+    current_frame_timings: FrameTimings,
+
+    /// Measures the frame in progress, started at its first tick
+    #[serde(skip, default = "TimingScope::start")]
+    frame_timing_scope: TimingScope,
+
+    /// Time spent in each part of the last completed frame
+    #[serde(skip)]
+    last_frame_timings: FrameTimings,
+
+    // Fields below were added after version 1 of the save format. New serialized fields must be
+    // added at the end with a default so that older quick saves can still be loaded.
+    /// Number of ticks that Draw mode lasts for on the current scanline
+    #[serde(default = "default_draw_ticks")]
+    draw_ticks: usize,
+
+    /// Whether the CPU is in STOP mode, waiting for a button press. The PPU, timers, and APU are all
+    /// frozen while stopped.
+    #[serde(default)]
+    is_cpu_stopped: bool,
+
+    /// Ticks spent in STOP mode since commands were last handled. The frame tick does not advance
+    /// while stopped, so commands are polled based on this counter instead.
+    #[serde(skip)]
+    ticks_since_stopped_commands: u32,
+
+    /// Incremented at the start of every frame, and while in STOP mode whenever commands are
+    /// handled. Read by the GUI's watchdog to detect when the emulator thread has stalled.
+    #[serde(skip)]
+    heartbeat: AtomicU64,
+
+    /// Addresses of the most recently executed instructions, for stall diagnostics
+    #[serde(skip, default = "PcHistory::new")]
+    pc_history: PcHistory,
+
+    /// Seed used to randomize RAM at power-on, recorded so that the run can be reproduced
+    #[serde(default)]
+    ram_init_seed: Option<u64>,
+
+    /// Breakpoints, watchpoints, and single stepping
+    #[serde(skip, default = "Debugger::new")]
+    debugger: Debugger,
+
+    /// Where each drawn pixel came from, only recorded while a debug view needs it
+    #[serde(skip)]
+    pixel_provenance: Option<Box<PixelProvenanceBuffer>>,
+
+    /// Number of bits remaining in the serial transfer in progress, or 0 if there is none
+    #[serde(default)]
+    serial_transfer_bits_remaining: u8,
+
+    /// Reload of TIMA from TMA in progress after TIMA overflowed
+    #[serde(default)]
+    tima_overflow: TimaOverflow,
+
+    /// Whether KEY0 selected PGB mode
+    #[serde(default)]
+    is_pgb_mode: bool,
+
+    /// Whether any of the enabled STAT interrupt conditions hold. These are combined into a single
+    /// line, and an LcdStat interrupt is only requested when the line rises.
+    #[serde(default)]
+    stat_interrupt_line: bool,
+
+    /// Source of all randomness, such as randomized RAM at power-on
+    #[serde(default)]
+    rng: EmulatorRng,
+
+    /// Time as seen by the emulated hardware, such as the real time clock in MBC3 cartridges
+    #[serde(default)]
+    clock: EmulatorClock,
+
+    /// Set once the CPU executes an invalid opcode, after which it never executes another
+    /// instruction
+    #[serde(default)]
+    cpu_lock: Option<CpuLock>,
+
+    /// Colors that DMG shades are shown as. Not part of the emulated state, set from the options.
+    #[serde(skip)]
+    screen_palette: ScreenColorPalette,
+
+    /// Screen palette to switch to at the start of the next frame, so that a frame is never shown
+    /// partly in one palette and partly in another
+    #[serde(skip)]
+    pending_screen_palette: Option<ScreenColorPalette>,
 }
 
 /// An immutable reference to an Emulator. Allows for sharing across threads where we are willing
@@ -418,11 +930,49 @@ unsafe impl Send for EmulatorRef {}
 
 pub struct EmulatorBuilder {
     emulator: Emulator,
+    /// BIOS file to read when the emulator is built, if any
+    bios_path: Option<String>,
+}
+
+/// Errors that can occur when loading a BIOS.
+#[derive(Debug)]
+pub enum BiosError {
+    Io(io::Error),
+    /// The BIOS is not the right size for the machine, e.g. a DMG BIOS used on a CGB
+    InvalidSize {
+        size: usize,
+        machine: Machine,
+    },
+}
+
+impl fmt::Display for BiosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BiosError::Io(error) => write!(f, "could not read BIOS: {}", error),
+            BiosError::InvalidSize { size, machine } => {
+                let expected_sizes = machine
+                    .bios_sizes()
+                    .iter()
+                    .map(|size| size.to_string())
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "BIOS is {} bytes but a {} BIOS must be {} bytes",
+                    size,
+                    machine.name(),
+                    expected_sizes.join(" or ")
+                )
+            }
+        }
+    }
 }
 
 impl EmulatorBuilder {
     fn new(emulator: Emulator) -> Self {
-        EmulatorBuilder { emulator }
+        EmulatorBuilder {
+            emulator,
+            bios_path: None,
+        }
     }
 
     pub fn new_cartridge(cartridge: Cartridge, machine: Machine) -> Self {
@@ -432,32 +982,89 @@ impl EmulatorBuilder {
         Self::new(emulator)
     }
 
-    pub fn from_saved_cartidge(save_file: Box<SaveFile>, machine: Machine) -> Self {
-        let cartridge = rmp_serde::from_slice(&save_file.cartridge).unwrap();
+    pub fn from_saved_cartidge(
+        save_file: Box<SaveFile>,
+        machine: Machine,
+    ) -> Result<Self, SaveFileError> {
+        let mut cartridge: Cartridge = rmp_serde::from_slice(&save_file.cartridge)?;
+        cartridge.attach_rom(save_file.rom.clone())?;
 
         let mut emulator = Emulator::initial_state(cartridge, machine);
         emulator.save_file = Some(save_file);
 
-        Self::new(emulator)
+        Ok(Self::new(emulator))
     }
 
-    pub fn from_quick_save_bytes(save_file: Box<SaveFile>, serialized_bytes: &[u8]) -> Self {
-        let mut emulator: Emulator = rmp_serde::from_slice(serialized_bytes).unwrap();
+    /// Restore an emulator from a quick save written by any supported version of the save format.
+    ///
+    /// The quick save is restored on the machine it was made on. If that is not the given machine a
+    /// warning is printed, since the emulator will switch machines.
+    pub fn from_quick_save_bytes(
+        save_file: Box<SaveFile>,
+        serialized_bytes: &[u8],
+        machine: Machine,
+    ) -> Result<Self, SaveFileError> {
+        let payload = save_compat::decode(BlobKind::QuickSave, serialized_bytes)?;
+
+        let mut emulator: Emulator = rmp_serde::from_slice(&payload)?;
+        emulator.check_memory_sizes()?;
+        emulator.cartridge.attach_rom(save_file.rom.clone())?;
+
+        if emulator.machine != machine {
+            eprintln!(
+                "Warning: quick save was made on a {} and will run as one instead of a {}",
+                emulator.machine.name(),
+                machine.name()
+            );
+        }
         emulator.save_file = Some(save_file);
 
-        Self::new(emulator)
+        Ok(Self::new(emulator))
     }
 
     pub fn with_options(mut self, options: Arc<Options>) -> Self {
+        self.emulator.screen_palette = options.screen_palette;
         self.emulator.options = options;
         self
     }
 
+    /// Set the contents of RAM at power-on. Only applies when starting fresh, not when restoring a
+    /// quick save.
+    pub fn with_ram_init(mut self, ram_init: RamInit) -> Self {
+        self.emulator.init_ram(ram_init);
+        self
+    }
+
+    /// Seed the emulator's RNG and start its clock at a fixed time, so that the run can be
+    /// reproduced. Without a seed the RNG is seeded from the current time and the clock starts at
+    /// the current time. Must be called before `with_ram_init`, which reseeds the RNG when RAM is
+    /// randomized.
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        let (seed, clock) = match seed {
+            Some(seed) => (seed, EmulatorClock::default()),
+            None => (entropy_seed(), EmulatorClock::starting_now()),
+        };
+
+        self.emulator.rng = EmulatorRng::new(seed);
+        self.emulator.clock = clock;
+        self
+    }
+
     pub fn with_save_file_path(mut self, save_file_path: String) -> Self {
         self.emulator.save_file_path = Some(save_file_path);
         self
     }
 
+    pub fn with_raw_save_file_path(mut self, raw_save_file_path: String) -> Self {
+        self.emulator.raw_save_file_path = Some(raw_save_file_path);
+        self
+    }
+
+    pub fn with_auto_state_path(mut self, auto_state_path: String) -> Self {
+        self.emulator.auto_state_path = Some(auto_state_path);
+        self
+    }
+
     pub fn with_input_adapter(mut self, input_adapter: SharedInputAdapter) -> Self {
         self.emulator.input_adapter = Some(input_adapter);
         self
@@ -468,35 +1075,114 @@ impl EmulatorBuilder {
         self
     }
 
-    pub fn with_bios_path(mut self, bios_path: String) -> Self {
-        let bios = fs::read(bios_path).expect("Failed to read BIOS");
+    pub fn with_video_sink(mut self, video_sink: Box<dyn VideoSink>) -> Self {
+        self.emulator.video_sink = Some(video_sink);
+        self
+    }
+
+    pub fn with_shared_stats(mut self, shared_stats: Arc<SharedStats>) -> Self {
+        self.emulator.shared_stats = Some(shared_stats);
+        self
+    }
+
+    /// Record every byte sent over the serial port, to be read with `Emulator::serial_output`.
+    pub fn with_serial_capture(mut self) -> Self {
+        self.emulator.serial_output = Some(vec![]);
+        self
+    }
+
+    /// Run the given BIOS at power-on instead of starting from the state after the BIOS completes.
+    /// The BIOS is checked when the emulator is built.
+    pub fn with_bios(mut self, bios: Vec<u8>) -> Self {
         self.emulator.bios = Some(bios);
         self
     }
 
-    pub fn build(self) -> Emulator {
-        self.emulator
+    /// Run the BIOS at the given path, which is read when the emulator is built.
+    pub fn with_bios_path(mut self, bios_path: String) -> Self {
+        self.bios_path = Some(bios_path);
+        self
+    }
+
+    pub fn build(mut self) -> Result<Emulator, Error> {
+        if let Some(bios_path) = self.bios_path {
+            let bios = fs::read(bios_path).map_err(BiosError::Io)?;
+            self.emulator.bios = Some(bios);
+        }
+
+        self.emulator.check_bios_size()?;
+        self.emulator.sync_cartridge_clock();
+        self.emulator.refresh_screen_colors();
+
+        Ok(self.emulator)
     }
 }
 
 impl Emulator {
+    /// Check that deserialized memory is the right size for the machine, so that a corrupt quick
+    /// save is rejected instead of indexing out of bounds later.
+    fn check_memory_sizes(&self) -> Result<(), SaveFileError> {
+        self.check_bios_size()
+            .map_err(|error| SaveFileError::Corrupt(error.to_string()))?;
+
+        let memories = [
+            ("VRAM", self.vram.len(), self.machine.vram_size()),
+            ("work RAM", self.work_ram.len(), self.machine.wram_size()),
+        ];
+
+        for (name, size, expected_size) in memories {
+            if size != expected_size {
+                return Err(SaveFileError::Corrupt(format!(
+                    "{} is {} bytes but a {} has {} bytes",
+                    name,
+                    size,
+                    self.machine.name(),
+                    expected_size
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_bios_size(&self) -> Result<(), BiosError> {
+        match &self.bios {
+            Some(bios) if !self.machine.bios_sizes().contains(&bios.len()) => {
+                Err(BiosError::InvalidSize {
+                    size: bios.len(),
+                    machine: self.machine,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// The initial state of the emulator for a given cartridge and machine type.
     ///
     /// Initialized to the standard state after the BIOS has run and the cartridge entry point code
     /// is ready to execute.
     fn initial_state(cartridge: Cartridge, machine: Machine) -> Self {
-        Emulator {
+        let mut emulator = Emulator {
             cartridge,
             options: Arc::new(Options::default()),
             input_adapter: None,
             pixels: [serde_big_array::Array([Color::Dmg(0); SCREEN_WIDTH]); SCREEN_HEIGHT],
+            screen_colors: default_screen_colors(),
             audio_output: None,
+            video_sink: None,
+            shared_stats: None,
+            serial_output: None,
             bios: None,
             save_file: None,
             save_file_path: None,
+            save_file_flush_state: SaveFileFlushState::default(),
+            raw_save_file_path: None,
+            auto_state_path: None,
+            should_resume_from_auto_state: false,
             machine,
             tick: 0,
-            microframe: 0,
+            frame_overshoot_ticks: 0,
+            frame_pacer: FramePacer::new(),
             scanline: 0,
             mode: Mode::OamScan,
             in_cgb_mode: false,
@@ -508,15 +1194,20 @@ impl Emulator {
             cgb_background_palettes: Box::new(serde_big_array::Array([0xFF; 64])),
             // CGB object palettes only requires that first byte is 0x00, rest are uninitialized
             cgb_object_palettes: Box::new(serde_big_array::Array([0x00; 64])),
+            palette_cache: PaletteCache::new(),
+            scanline_renderer: None,
             regs: Registers::init_for_machine(machine),
             io_regs: IoRegisters::init_for_machine(machine),
             apu: Apu::new(),
             ie: IE_INIT,
             ticks_to_next_instruction: 0,
+            cpu_step_state: None,
             pending_enable_interrupts: PendingEnableInterrupt::None,
             current_oam_dma_transfer: None,
             current_hblank_vram_dma_transfer: None,
             current_general_purpose_vram_dma_transfer: None,
+            has_reported_vram_dma_source_in_vram: false,
+            cgb_only_detector: CgbOnlyDetector::default(),
             current_speed_switch: None,
             is_cpu_halted: false,
             is_cpu_stopped_for_vram_dma: false,
@@ -525,13 +1216,46 @@ impl Emulator {
             full_divider_register: 0,
             tac_mask: TAC_MASK_1024_TICKS,
             is_timer_enabled: false,
-            in_turbo_mode: false,
+            speed: NORMAL_SPEED,
+            fast_frame_index: 0,
+            rewind_buffer: RewindBuffer::new(),
+            is_rewinding: false,
+            is_skipping_render: false,
+            num_rendered_frames: 0,
             is_booting: true,
             is_double_speed: false,
             is_paused: false,
-            current_audio_frame: Vec::new(),
+            frame_advance_ticks_remaining: None,
+            is_shutting_down: false,
+            current_audio_frame: AudioFrame::with_capacity(MAX_SAMPLES_PER_AUDIO_FRAME),
             frame_tracker: FrameTracker::new(),
-        }
+            current_draw_timing_metrics: DrawTimingMetrics::new(),
+            last_draw_timing_metrics: DrawTimingMetrics::new(),
+            current_frame_timings: FrameTimings::default(),
+            frame_timing_scope: TimingScope::start(),
+            last_frame_timings: FrameTimings::default(),
+            draw_ticks: MIN_DRAW_TICKS,
+            is_cpu_stopped: false,
+            ticks_since_stopped_commands: 0,
+            heartbeat: AtomicU64::new(0),
+            pc_history: PcHistory::new(),
+            ram_init_seed: None,
+            debugger: Debugger::new(),
+            pixel_provenance: None,
+            serial_transfer_bits_remaining: 0,
+            tima_overflow: TimaOverflow::None,
+            is_pgb_mode: false,
+            stat_interrupt_line: false,
+            rng: EmulatorRng::new(0),
+            clock: EmulatorClock::default(),
+            cpu_lock: None,
+            screen_palette: ScreenColorPalette::Grayscale,
+            pending_screen_palette: None,
+        };
+
+        emulator.init_wave_ram();
+
+        emulator
     }
 
     pub fn to_ref(&self) -> EmulatorRef {
@@ -548,6 +1272,17 @@ impl Emulator {
         self.scanline
     }
 
+    /// Value of the LY register, which is the current scanline except for most of the last
+    /// scanline where it already reads 0.
+    pub fn current_ly(&self) -> u8 {
+        let tick_within_scanline = self.tick as usize % TICKS_PER_SCANLINE;
+        if self.scanline == LAST_SCANLINE && tick_within_scanline >= LAST_SCANLINE_LY_RESET_TICKS {
+            0
+        } else {
+            self.scanline
+        }
+    }
+
     pub fn mode(&self) -> Mode {
         self.mode
     }
@@ -556,121 +1291,430 @@ impl Emulator {
         self.in_cgb_mode
     }
 
-    pub fn set_in_cgb_mode(&mut self, in_cgb_mode: bool) {
-        self.in_cgb_mode = in_cgb_mode;
+    /// The mode the machine is running games in. Hardware behavior that differs between CGB mode
+    /// and DMG compatibility mode should check this rather than the machine.
+    pub fn compat_mode(&self) -> CompatMode {
+        if !self.is_cgb_machine() {
+            CompatMode::Dmg
+        } else if !self.in_cgb_mode {
+            CompatMode::DmgCompat
+        } else if self.is_pgb_mode {
+            CompatMode::Pgb
+        } else {
+            CompatMode::Cgb
+        }
+    }
+
+    pub fn set_compat_mode(&mut self, compat_mode: CompatMode) {
+        self.in_cgb_mode = compat_mode.has_cgb_rendering();
+        self.is_pgb_mode = compat_mode == CompatMode::Pgb;
     }
 
     pub fn is_cgb_machine(&self) -> bool {
         matches!(self.machine, Machine::Cgb)
     }
 
+    /// Whether a CGB is running a DMG game.
+    pub fn in_dmg_compatibility_mode(&self) -> bool {
+        self.compat_mode() == CompatMode::DmgCompat
+    }
+
     pub fn in_test_mode(&self) -> bool {
         self.options.in_test_mode
     }
 
-    pub fn vram(&self) -> &[u8] {
-        &self.vram
+    /// Result of a test ROM if the registers currently hold the magic pass or fail values.
+    pub fn test_result(&self) -> Option<TestResult> {
+        let state = self.cpu_state();
+        match (state.b, state.c, state.d, state.e, state.h, state.l) {
+            (3, 5, 8, 13, 21, 34) => Some(TestResult::Passed),
+            (0x42, 0x42, 0x42, 0x42, 0x42, 0x42) => Some(TestResult::Failed),
+            _ => None,
+        }
     }
 
-    pub fn oam(&self) -> &[u8] {
-        &self.oam
+    pub fn in_trace_mode(&self) -> bool {
+        self.options.trace
     }
 
-    pub fn regs(&self) -> &Registers {
-        &self.regs
+    /// Object priority mode that rendering uses instead of the one selected by OPRI, if any.
+    pub fn forced_sprite_priority(&self) -> Option<SpritePriority> {
+        self.options.force_sprite_priority
     }
 
-    pub fn regs_mut(&mut self) -> &mut Registers {
-        &mut self.regs
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
     }
 
-    pub fn io_regs(&self) -> &IoRegisters {
-        &self.io_regs
+    pub fn speed(&self) -> f64 {
+        self.speed
     }
 
-    pub fn io_regs_mut(&mut self) -> &mut IoRegisters {
-        &mut self.io_regs
+    pub fn is_rewinding(&self) -> bool {
+        self.is_rewinding
     }
 
-    pub fn apu(&self) -> &Apu {
-        &self.apu
+    pub fn screen_palette(&self) -> ScreenColorPalette {
+        self.screen_palette
     }
 
-    pub fn apu_mut(&mut self) -> &mut Apu {
-        &mut self.apu
+    /// Switch screen palettes at the start of the next frame, or immediately while paused since the
+    /// paused frame would otherwise keep the old palette.
+    fn set_screen_palette(&mut self, screen_palette: ScreenColorPalette) {
+        if self.is_paused {
+            self.apply_screen_palette(screen_palette);
+            self.pending_screen_palette = None;
+        } else {
+            self.pending_screen_palette = Some(screen_palette);
+        }
     }
 
-    pub fn cgb_background_palettes(&self) -> &CgbPaletteData {
-        &self.cgb_background_palettes
+    /// Switch screen palettes now, recoloring the current frame.
+    fn apply_screen_palette(&mut self, screen_palette: ScreenColorPalette) {
+        self.screen_palette = screen_palette;
+        self.palette_cache.mark_dirty();
+        self.refresh_screen_colors();
     }
 
-    pub fn cgb_background_palettes_mut(&mut self) -> &mut CgbPaletteData {
-        &mut self.cgb_background_palettes
+    /// Convert every pixel on the screen with the current screen palette.
+    fn refresh_screen_colors(&mut self) {
+        let pixels = self.pixels.iter().flat_map(|row| row.iter());
+        for (color32, color) in self.screen_colors.iter_mut().zip(pixels) {
+            *color32 = self.screen_palette.color_to_color32(*color);
+        }
     }
 
-    pub fn cgb_object_palettes(&self) -> &CgbPaletteData {
-        &self.cgb_object_palettes
+    /// Start or stop recording where each drawn pixel came from. Nothing is recorded while disabled.
+    /// Pixels drawn before recording started have the default provenance.
+    pub fn set_record_pixel_provenance(&mut self, is_enabled: bool) {
+        if !is_enabled {
+            self.pixel_provenance = None;
+        } else if self.pixel_provenance.is_none() {
+            self.pixel_provenance = Some(Box::new(
+                [[PixelProvenance::default(); SCREEN_WIDTH]; SCREEN_HEIGHT],
+            ));
+        }
     }
 
-    pub fn cgb_object_palettes_mut(&mut self) -> &mut CgbPaletteData {
-        &mut self.cgb_object_palettes
+    /// Where the pixel at (x, y) on the screen came from, if recording is enabled.
+    pub fn pixel_provenance(&self, x: u8, y: u8) -> Option<PixelProvenance> {
+        let provenance = self.pixel_provenance.as_ref()?;
+        provenance
+            .get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .copied()
     }
 
-    pub fn halt_cpu(&mut self) {
-        self.is_cpu_halted = true;
+    pub fn pixel_provenance_mut(&mut self) -> Option<&mut PixelProvenanceBuffer> {
+        self.pixel_provenance.as_deref_mut()
     }
 
-    pub fn resume_halted_cpu(&mut self) {
-        self.is_cpu_halted = false;
+    pub fn heartbeat(&self) -> u64 {
+        self.heartbeat.load(Ordering::Relaxed)
+    }
 
-        // Reset the speed switch countdown until the current halt is cleared
-        self.current_speed_switch = None;
+    fn beat_heartbeat(&self) {
+        self.heartbeat.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn window_line_counter_mut(&mut self) -> &mut WindowLineCounter {
-        &mut self.window_line_counter
+    pub fn pc_history(&self) -> &PcHistory {
+        &self.pc_history
     }
 
-    pub fn pressed_buttons(&self) -> ButtonSet {
-        self.pressed_buttons
+    pub fn symbols_path(&self) -> Option<&Path> {
+        self.options.symbols_path.as_deref()
     }
 
-    pub fn full_divider_register(&self) -> u16 {
-        self.full_divider_register
+    pub fn gamepad_mapping(&self) -> GamepadMapping {
+        self.options.gamepad_mapping
     }
 
-    pub fn reset_divider_register(&mut self) {
-        self.full_divider_register = 0;
+    pub fn keymap_path(&self) -> Option<&Path> {
+        self.options.keymap_path.as_deref()
     }
 
-    pub fn is_timer_enabled(&self) -> bool {
-        self.is_timer_enabled
+    pub fn window_layout_path(&self) -> Option<&Path> {
+        self.options.window_layout_path.as_deref()
     }
 
-    pub fn set_timer_enabled(&mut self, enabled: bool) {
-        self.is_timer_enabled = enabled;
+    /// ROM or save file given on the command line
+    pub fn rom_or_save_path(&self) -> &str {
+        &self.options.rom_or_save_path
     }
 
-    pub fn is_booting(&self) -> bool {
-        self.is_booting
+    /// Contents of RAM at power-on
+    pub fn ram_init(&self) -> RamInit {
+        self.options.ram_init
     }
 
-    pub fn set_is_booting(&mut self, is_booting: bool) {
-        self.is_booting = is_booting;
+    /// Flags of the options that were ignored because the emulator started in safe mode
+    pub fn suppressed_options(&self) -> &[&'static str] {
+        &self.options.suppressed_options
     }
 
-    pub fn is_double_speed(&self) -> bool {
-        self.is_double_speed
+    fn init_ram(&mut self, ram_init: RamInit) {
+        if let RamInit::Random(seed) = ram_init {
+            self.rng = EmulatorRng::new(seed);
+        }
+
+        let mut filler = ram_init.filler(&mut self.rng);
+        filler.fill(&mut self.work_ram);
+        filler.fill(&mut self.vram);
+        filler.fill(&mut self.hram);
+        filler.fill(&mut self.oam);
+
+        // Uninitialized registers are only randomized along with RAM, otherwise they keep their
+        // typical values.
+        if let RamInit::Random(_) = ram_init {
+            self.io_regs
+                .randomize_uninitialized(self.machine, &mut filler);
+            self.init_wave_ram();
+            self.palette_cache_mut().mark_dirty();
+        }
+
+        self.ram_init_seed = ram_init.seed();
     }
 
-    pub fn set_is_double_speed(&mut self, is_double_speed: bool) {
-        self.is_double_speed = is_double_speed;
+    /// Seed used to randomize RAM at power-on, if RAM was randomized.
+    pub fn ram_init_seed(&self) -> Option<u64> {
+        self.ram_init_seed
     }
 
-    /// Map from the divider register mask to the corresponding bits of the TAC register
-    pub fn tac_bits(&self) -> u8 {
-        match self.tac_mask {
-            TAC_MASK_1024_TICKS => 0b00,
+    /// Seed of the emulator's RNG, which reproduces the run along with the same ROM and inputs
+    pub fn seed(&self) -> u64 {
+        self.rng.seed()
+    }
+
+    /// Current time as seen by the emulated hardware
+    pub fn clock_time(&self) -> SystemTime {
+        self.clock.now(self.tick)
+    }
+
+    /// Pass the emulator's clock to the cartridge, which never reads the system clock itself.
+    fn sync_cartridge_clock(&mut self) {
+        let now = self.clock_time();
+        self.cartridge.mbc_mut().set_current_time(now);
+    }
+
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub fn vram_mut(&mut self) -> &mut [u8] {
+        &mut self.vram
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    pub fn oam_mut(&mut self) -> &mut [u8] {
+        &mut self.oam
+    }
+
+    pub fn regs(&self) -> &Registers {
+        &self.regs
+    }
+
+    pub fn regs_mut(&mut self) -> &mut Registers {
+        &mut self.regs
+    }
+
+    pub fn io_regs(&self) -> &IoRegisters {
+        &self.io_regs
+    }
+
+    pub fn io_regs_mut(&mut self) -> &mut IoRegisters {
+        &mut self.io_regs
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn cgb_background_palettes(&self) -> &CgbPaletteData {
+        &self.cgb_background_palettes
+    }
+
+    pub fn cgb_background_palettes_mut(&mut self) -> &mut CgbPaletteData {
+        self.palette_cache.mark_dirty();
+        &mut self.cgb_background_palettes
+    }
+
+    pub fn cgb_object_palettes(&self) -> &CgbPaletteData {
+        &self.cgb_object_palettes
+    }
+
+    pub fn cgb_object_palettes_mut(&mut self) -> &mut CgbPaletteData {
+        self.palette_cache.mark_dirty();
+        &mut self.cgb_object_palettes
+    }
+
+    /// Overwrite a single color in CGB palette memory, bypassing BCPS/BCPD and OCPS/OCPD.
+    pub fn write_cgb_palette_color(
+        &mut self,
+        is_object: bool,
+        palette_number: usize,
+        color_index: usize,
+        color: CgbColor,
+    ) {
+        let offset = cgb_color_offset(palette_number, color_index);
+        let palettes = if is_object {
+            self.cgb_object_palettes_mut()
+        } else {
+            self.cgb_background_palettes_mut()
+        };
+
+        palettes[offset..offset + 2].copy_from_slice(&color.raw().to_le_bytes());
+    }
+
+    pub fn palette_cache(&self) -> &PaletteCache {
+        &self.palette_cache
+    }
+
+    pub fn palette_cache_mut(&mut self) -> &mut PaletteCache {
+        &mut self.palette_cache
+    }
+
+    pub fn halt_cpu(&mut self) {
+        self.is_cpu_halted = true;
+    }
+
+    pub fn resume_halted_cpu(&mut self) {
+        self.is_cpu_halted = false;
+
+        // Reset the speed switch countdown until the current halt is cleared
+        self.current_speed_switch = None;
+    }
+
+    /// Lock up the CPU after executing an invalid opcode, like real hardware. No more instructions
+    /// or interrupts are executed, but the rest of the system keeps running. Panics instead if the
+    /// options ask for it.
+    pub fn lock_cpu(&mut self, opcode: u8) {
+        let address = self.regs().pc().wrapping_sub(1);
+        if self.options.panic_on_invalid {
+            panic!("Invalid opcode {:02X} at {:04X}", opcode, address);
+        }
+
+        eprintln!(
+            "CPU locked up after executing invalid opcode ${:02X} at ${:04X}",
+            opcode, address
+        );
+        self.cpu_lock = Some(CpuLock { address, opcode });
+    }
+
+    /// Where the CPU locked up, if it executed an invalid opcode.
+    pub fn cpu_lock(&self) -> Option<CpuLock> {
+        self.cpu_lock
+    }
+
+    /// Whether the CPU can never execute another instruction, either because it locked up or
+    /// because it is halted with no interrupts enabled to wake it.
+    pub fn is_locked_up(&self) -> bool {
+        self.cpu_lock.is_some() || (self.is_cpu_halted && self.ie & 0x1F == 0)
+    }
+
+    pub fn is_cpu_stopped(&self) -> bool {
+        self.is_cpu_stopped
+    }
+
+    /// Enter STOP mode. The divider is reset on entry on both DMG and CGB, then stays frozen along
+    /// with the PPU and APU until a selected button is pressed.
+    pub fn stop_cpu(&mut self) {
+        self.is_cpu_stopped = true;
+        self.ticks_since_stopped_commands = 0;
+        self.reset_divider_register();
+    }
+
+    fn resume_stopped_cpu(&mut self) {
+        self.is_cpu_stopped = false;
+        self.schedule_next_instruction(STOP_WAKE_TICKS);
+    }
+
+    pub fn window_line_counter(&self) -> &WindowLineCounter {
+        &self.window_line_counter
+    }
+
+    pub fn window_line_counter_mut(&mut self) -> &mut WindowLineCounter {
+        &mut self.window_line_counter
+    }
+
+    pub fn pressed_buttons(&self) -> ButtonSet {
+        self.pressed_buttons
+    }
+
+    pub fn full_divider_register(&self) -> u16 {
+        self.full_divider_register
+    }
+
+    /// Resetting the divider can increment TIMA, see `update_timer_signal`.
+    pub fn reset_divider_register(&mut self) {
+        self.update_timer_signal(|emulator| emulator.full_divider_register = 0);
+    }
+
+    pub fn is_timer_enabled(&self) -> bool {
+        self.is_timer_enabled
+    }
+
+    /// Set the timer enable bit and clock select bits of TAC. Changing either can increment TIMA,
+    /// see `update_timer_signal`.
+    pub fn set_timer_control(&mut self, is_enabled: bool, tac_bits: u8) {
+        self.update_timer_signal(|emulator| {
+            emulator.is_timer_enabled = is_enabled;
+            emulator.set_tac_bits(tac_bits);
+        });
+    }
+
+    /// The signal that TIMA is clocked by: the divider bit selected by TAC, ANDed with the timer
+    /// enable bit.
+    fn timer_signal(&self) -> bool {
+        self.is_timer_enabled && (self.full_divider_register & self.tac_mask) != 0
+    }
+
+    /// TIMA is incremented on a falling edge of the timer signal rather than at a fixed interval,
+    /// so any change to the divider or TAC that drops the signal from 1 to 0 increments TIMA as
+    /// well. This happens when resetting the divider while the selected bit is set, disabling the
+    /// timer while the selected bit is set, or selecting a bit that is clear while the old one is
+    /// set.
+    fn update_timer_signal(&mut self, update: impl FnOnce(&mut Self)) {
+        let old_signal = self.timer_signal();
+        update(self);
+
+        if old_signal && !self.timer_signal() {
+            self.increment_tima();
+        }
+    }
+
+    pub fn is_booting(&self) -> bool {
+        self.is_booting
+    }
+
+    pub fn set_is_booting(&mut self, is_booting: bool) {
+        self.is_booting = is_booting;
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.is_double_speed
+    }
+
+    pub fn set_is_double_speed(&mut self, is_double_speed: bool) {
+        self.is_double_speed = is_double_speed;
+    }
+
+    /// Number of CPU ticks that elapse during each tick. The CPU clock is doubled in double speed
+    /// mode but the PPU dot clock is not, so everything clocked by the CPU advances twice per tick.
+    pub fn cpu_ticks_per_tick(&self) -> usize {
+        if self.is_double_speed { 2 } else { 1 }
+    }
+
+    /// Map from the divider register mask to the corresponding bits of the TAC register
+    pub fn tac_bits(&self) -> u8 {
+        match self.tac_mask {
+            TAC_MASK_1024_TICKS => 0b00,
             TAC_MASK_16_TICKS => 0b01,
             TAC_MASK_64_TICKS => 0b10,
             TAC_MASK_256_TICKS => 0b11,
@@ -679,7 +1723,7 @@ impl Emulator {
     }
 
     /// Map from bits of the TAC register to the corresponding divider register mask
-    pub fn set_tac_bits(&mut self, tac_bits: u8) {
+    fn set_tac_bits(&mut self, tac_bits: u8) {
         let tac_mask = match tac_bits & 0x3 {
             0b00 => TAC_MASK_1024_TICKS,
             0b01 => TAC_MASK_16_TICKS,
@@ -696,35 +1740,125 @@ impl Emulator {
         self.mode != Mode::Draw || !self.is_lcdc_lcd_enabled()
     }
 
-    fn ns_per_frame(&self) -> f64 {
-        if self.in_turbo_mode {
-            NS_PER_MICROFRAME
-        } else {
-            NS_PER_FRAME
+    /// Whether we can currently access OAM
+    pub fn can_access_oam(&self) -> bool {
+        !matches!(self.mode, Mode::OamScan | Mode::Draw) || !self.is_lcdc_lcd_enabled()
+    }
+
+    /// Snapshot of all CPU registers and interrupt state.
+    pub fn cpu_state(&self) -> CpuState {
+        let regs = self.regs();
+        let [a, f] = regs.af().to_be_bytes();
+
+        CpuState {
+            a,
+            f,
+            b: regs.b(),
+            c: regs.c(),
+            d: regs.d(),
+            e: regs.e(),
+            h: regs.h(),
+            l: regs.l(),
+            sp: regs.sp(),
+            pc: regs.pc(),
+            ime: regs.interrupts_enabled(),
+            is_halted: self.is_cpu_halted,
         }
     }
 
-    fn microframes_per_frame(&self) -> u64 {
-        if self.in_turbo_mode {
-            1
-        } else {
-            TURBO_MULTIPLIER
+    /// Overwrite all CPU registers and interrupt state.
+    pub fn set_cpu_state(&mut self, state: CpuState) {
+        let regs = self.regs_mut();
+        regs.set_af(state.af());
+        regs.set_bc(state.bc());
+        regs.set_de(state.de());
+        regs.set_hl(state.hl());
+        regs.set_sp(state.sp);
+        regs.set_pc(state.pc);
+        regs.set_interrupts_enabled(state.ime);
+
+        self.is_cpu_halted = state.is_halted;
+    }
+
+    /// Snapshot of the PPU's position within the frame.
+    pub fn ppu_state(&self) -> PpuState {
+        PpuState {
+            mode: self.mode,
+            scanline: self.scanline,
+            window_line_counter: self.window_line_counter.line(),
+            tick: self.tick,
+        }
+    }
+
+    /// Read a range of memory as seen by the CPU, wrapping around at the end of the address space.
+    ///
+    /// Unlike `read_address` this has no side effects and never fails: IO registers are read raw
+    /// without applying any special read behavior, and echo RAM mirrors work RAM.
+    pub fn read_memory_bulk(&self, addr: Address, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.peek_address(addr.wrapping_add(i as Address)))
+            .collect()
+    }
+
+    /// Snapshot of the full IO register file (0xFF00-0xFF80), read without side effects in the
+    /// same way as `read_memory_bulk`.
+    pub fn io_registers_snapshot(&self) -> [Register; IO_REGISTERS_SIZE] {
+        array::from_fn(|i| self.peek_address(IO_REGISTERS_START + i as Address))
+    }
+
+    /// Write a range of memory as seen by the CPU, wrapping around at the end of the address space.
+    ///
+    /// IO registers are written raw without applying any special write behavior, and echo RAM
+    /// mirrors work RAM. Writes to the ROM area still go to the MBC and may switch banks.
+    pub fn write_memory_bulk(&mut self, addr: Address, bytes: &[u8]) {
+        for (i, value) in bytes.iter().enumerate() {
+            self.poke_address(addr.wrapping_add(i as Address), *value);
         }
     }
 
-    /// The expected start time in nanoseconds for the current frame (or microframe)
-    fn expected_frame_start_nanos(&self) -> u64 {
-        ((self.microframe as f64) * NS_PER_MICROFRAME) as u64
+    /// Read a single byte in the same way as `read_memory_bulk`, without side effects. Watchpoints
+    /// are not triggered and PPU access restrictions are ignored.
+    pub fn peek_address(&self, addr: Address) -> u8 {
+        if (SECOND_WORK_RAM_BANK_END..ECHO_RAM_END).contains(&addr) {
+            self.read_address_unrestricted(addr - ECHO_RAM_OFFSET)
+        } else if (WAVE_RAM_START..WAVE_RAM_END).contains(&addr) {
+            self.read_io_register(addr)
+        } else if (IO_REGISTERS_START..IO_REGISTERS_END).contains(&addr) {
+            self.read_register_raw(addr)
+        } else {
+            self.read_address_unrestricted(addr)
+        }
     }
 
-    fn format_frame_number(&self, microframe: u64) -> String {
-        if self.in_turbo_mode {
-            format!("{:.1}", (microframe as f64) / (TURBO_MULTIPLIER as f64))
+    fn poke_address(&mut self, addr: Address, value: u8) {
+        if (SECOND_WORK_RAM_BANK_END..ECHO_RAM_END).contains(&addr) {
+            self.write_address_unrestricted(addr - ECHO_RAM_OFFSET, value);
+        } else if (WAVE_RAM_START..WAVE_RAM_END).contains(&addr) {
+            self.write_io_register(addr, value);
+        } else if (IO_REGISTERS_START..IO_REGISTERS_END).contains(&addr) {
+            // Raw writes bypass the palette register handlers
+            self.write_register_raw(addr, value);
+            self.palette_cache.mark_dirty();
         } else {
-            format!("{}", microframe / TURBO_MULTIPLIER)
+            self.write_address_unrestricted(addr, value);
         }
     }
 
+    fn ns_per_frame(&self) -> f64 {
+        NS_PER_FRAME / self.speed
+    }
+
+    /// Draw mode timing metrics for the last completed frame.
+    pub fn draw_timing_metrics(&self) -> &DrawTimingMetrics {
+        &self.last_draw_timing_metrics
+    }
+
+    /// Time spent in each part of the last completed frame. All zero unless the `frame-timing`
+    /// feature is enabled.
+    pub fn frame_timings(&self) -> &FrameTimings {
+        &self.last_frame_timings
+    }
+
     pub fn current_frame_rate(&self) -> u32 {
         self.frame_tracker.current_frame_rate()
     }
@@ -733,19 +1867,109 @@ impl Emulator {
         self.pixels[y][x]
     }
 
+    /// Copy the whole screen into `colors` in row-major order, converted with the current screen
+    /// palette.
+    pub fn copy_screen_colors(&self, colors: &mut [Color32]) {
+        let len = colors.len().min(self.screen_colors.len());
+        colors[..len].copy_from_slice(&self.screen_colors[..len]);
+    }
+
     pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
-        self.pixels[y][x] = color
+        self.pixels[y][x] = color;
+        self.screen_colors[y * SCREEN_WIDTH + x] = self.screen_palette.color_to_color32(color);
     }
 
-    /// Run the emulator at the GameBoy's native framerate
-    pub fn run(&mut self) {
+    /// Redraw every scanline of the screen from the current VRAM, OAM, and PPU registers without
+    /// advancing any other emulator state.
+    ///
+    /// Register writes made partway through the original frame (e.g. scroll effects) are not
+    /// replayed, so the result only matches the original frame for static scenes.
+    pub fn rerender_frame(&mut self) {
+        if !self.is_lcdc_lcd_enabled() {
+            return;
+        }
+
+        // Drawing advances the window line counter, which must be restored afterwards
+        let window_line_counter =
+            mem::replace(&mut self.window_line_counter, WindowLineCounter::new());
+
+        for scanline in 0..SCREEN_HEIGHT {
+            draw_scanline(self, scanline as u8);
+        }
+
+        self.window_line_counter = window_line_counter;
+    }
+
+    /// Draw the pixels of the current scanline that are due by the given tick within Draw mode.
+    fn advance_scanline_renderer(&mut self, draw_tick: usize) {
+        match &self.scanline_renderer {
+            Some(renderer) if renderer.next_group_tick() <= draw_tick => {}
+            _ => return,
+        }
+
+        let scope = TimingScope::start();
+        let mut renderer = self.scanline_renderer.take().unwrap();
+        renderer.draw_until(self, draw_tick);
+        scope.finish(&mut self.current_frame_timings, TimingCategory::Draw);
+
+        if !renderer.is_finished() {
+            self.scanline_renderer = Some(renderer);
+        }
+    }
+
+    /// Prepare to run from power-on. Must be called once before running any frames.
+    pub fn power_on(&mut self) {
+        if !self.is_cgb_machine() && self.cartridge.header().cgb_flag == CgbFlag::CgbOnly {
+            eprintln!(
+                "This ROM requires a Game Boy Color, so it will not run correctly without --cgb"
+            );
+        }
+
         // Execute the BIOS if one was provided, otherwise start directly at the cartridge entry
         // point from the standard initial state after the BIOS completes.
         self.set_is_booting(true);
 
-        if self.bios.is_none() {
+        // The BIOS locks up if the cartridge's logo is invalid, just like on real hardware
+        if self.bios.is_some()
+            && self.options.skip_boot_on_bad_logo
+            && !self.cartridge.has_valid_logo()
+        {
+            self.bios = None;
+        }
+
+        if self.bios.is_some() {
+            self.init_pre_boot_state();
+        } else {
             self.emulate_boot_sequence();
         }
+    }
+
+    /// Start from the state at power-on, before the BIOS runs. The BIOS initializes everything
+    /// else, including the state that is otherwise set up by `emulate_boot_sequence`.
+    fn init_pre_boot_state(&mut self) {
+        self.regs = Registers::init_pre_boot();
+
+        // The CGB boot ROM runs in CGB mode, and switches to DMG compatibility mode for DMG games
+        self.set_compat_mode(if self.is_cgb_machine() {
+            CompatMode::Cgb
+        } else {
+            CompatMode::Dmg
+        });
+
+        // The LCD and APU are off until the BIOS turns them on
+        self.write_lcdc_raw(0x00);
+        self.write_nr52(0x00);
+    }
+
+    /// Run the emulator at the GameBoy's native framerate
+    pub fn run(&mut self) {
+        self.power_on();
+
+        if mem::take(&mut self.should_resume_from_auto_state) {
+            self.resume_from_auto_state();
+        }
+
+        self.set_speed(self.options.speed.unwrap_or(NORMAL_SPEED));
 
         let start_time = Instant::now();
         let mut last_save_file_flush_time = start_time;
@@ -755,52 +1979,83 @@ impl Emulator {
         loop {
             let frame_start_nanos = duration_to_nanos(Instant::now().duration_since(start_time));
             if self.options.log_frames {
-                let expected_frame_start_nanos = self.expected_frame_start_nanos();
+                let expected_frame_start_nanos = self.frame_pacer.expected_frame_start_nanos();
                 let frame_start_diff_nanos =
                     frame_start_nanos as i64 - expected_frame_start_nanos as i64;
                 println!(
                     "[FRAME] Frame start at {}ns, frame {}, {:.2}% through frame ({:.2}% on time)",
                     frame_start_nanos,
-                    self.format_frame_number(self.microframe),
+                    self.frame_pacer.frame(),
                     frame_start_diff_nanos as f64 / self.ns_per_frame() * 100.0,
                     self.frame_tracker.total_on_time_percent()
                 );
             }
 
-            // Run a single frame
-            self.run_frame();
+            // Run a single frame, or step back to the previous snapshot while rewinding
+            if self.is_rewinding {
+                self.rewind_frame();
+            } else {
+                self.run_frame();
+            }
+
+            if self.is_shutting_down {
+                break;
+            }
 
             // Track frame completion in FPS counter
             self.frame_tracker.frame_complete();
 
-            // Increment frame number (backed by microframes)
-            self.microframe += self.microframes_per_frame();
+            self.frame_pacer.advance(1);
 
             // Target time (since start) to run the next frame
-            let mut next_frame_time_nanos = self.expected_frame_start_nanos();
+            let next_frame_time_nanos = self.frame_pacer.expected_frame_start_nanos();
 
             // Current time (since start)
             let current_time = Instant::now();
             let current_time_nanos = duration_to_nanos(current_time.duration_since(start_time));
 
-            // Flush the save file to disk at regular intervals
-            if current_time
-                .duration_since(last_save_file_flush_time)
-                .as_secs()
-                >= SAVE_FILE_AUTO_FLUSH_INTERVAL_SECS
+            // Flush the save file to disk at regular intervals. Not while rewinding, so that the
+            // battery-backed RAM of the states passed through is never written, only that of the
+            // state where rewinding stops.
+            if !self.is_rewinding
+                && current_time
+                    .duration_since(last_save_file_flush_time)
+                    .as_secs()
+                    >= self.save_file_flush_state.flush_interval_secs()
             {
                 last_save_file_flush_time = Instant::now();
-                self.save_cartridge_state_to_disk();
+
+                let scope = TimingScope::start();
+                let _ = self.save_cartridge_state_to_disk();
+                scope.finish(&mut self.last_frame_timings, TimingCategory::SaveFlush);
+            }
+
+            let frame_budget_used_percent =
+                ((current_time_nanos - frame_start_nanos) as f64 / self.ns_per_frame()) * 100.0;
+
+            if let Some(shared_stats) = &self.shared_stats {
+                shared_stats.frame_complete(frame_budget_used_percent as f32);
             }
 
             if self.options.log_frames {
                 println!(
                     "[FRAME] Frame end at {}ns, frame {}, {:.2}% of frame budget used, ({:.2}% on time)",
                     current_time_nanos,
-                    self.format_frame_number(self.microframe - self.microframes_per_frame()),
-                    ((current_time_nanos - frame_start_nanos) as f64 / self.ns_per_frame()) * 100.0,
+                    self.frame_pacer.frame() - 1,
+                    frame_budget_used_percent,
                     self.frame_tracker.total_on_time_percent()
                 );
+
+                if cfg!(feature = "frame-timing") {
+                    let timings = &self.last_frame_timings;
+                    println!(
+                        "[FRAME] Frame timings: {}ns other ticks, {}ns draw, {}ns audio, {}ns save flush",
+                        timings.other_tick_time().as_nanos(),
+                        timings.draw.as_nanos(),
+                        timings.audio.as_nanos(),
+                        timings.save_flush.as_nanos()
+                    );
+                }
             }
 
             // Schedule the next frame and sleep until then
@@ -810,79 +2065,245 @@ impl Emulator {
                 // Calculate how long to sleep until the next frame
                 let nanos_to_next_frame = next_frame_time_nanos - current_time_nanos;
 
-                thread::sleep(Duration::from_nanos(
+                self.sleep_until_next_frame(Duration::from_nanos(
                     nanos_to_next_frame.saturating_sub(self.ns_per_frame() as u64 / 20),
                 ));
 
+                if self.is_shutting_down {
+                    break;
+                }
+
                 continue;
             }
 
-            // Skip frames whose expected start time has already passed
-            while next_frame_time_nanos <= current_time_nanos {
-                self.frame_tracker.mark_frame_missed();
+            // Skip frames whose expected start time has already passed. If the emulator is far
+            // behind, e.g. after the system was suspended, pacing starts over from now instead.
+            let first_missed_frame = self.frame_pacer.frame();
+            match self.frame_pacer.catch_up(current_time_nanos) {
+                CatchUp::SkippedFrames(num_skipped) => {
+                    for _ in 0..num_skipped {
+                        self.frame_tracker.mark_frame_missed();
+                    }
 
-                if self.options.log_frames {
-                    println!(
-                        "[FRAME] Missed frame {} by {}ns",
-                        self.format_frame_number(self.microframe),
-                        current_time_nanos - next_frame_time_nanos
-                    );
+                    if self.options.log_frames {
+                        println!(
+                            "[FRAME] Missed {} frame(s) starting at frame {} by {}ns",
+                            num_skipped,
+                            first_missed_frame,
+                            current_time_nanos - next_frame_time_nanos
+                        );
+                    }
+                }
+                CatchUp::Reanchored { behind_nanos } => {
+                    self.frame_tracker.mark_frame_missed();
+
+                    if self.options.log_frames {
+                        println!(
+                            "[FRAME] Fell behind by {}ns at frame {}, restarting frame pacing",
+                            behind_nanos, first_missed_frame,
+                        );
+                    }
                 }
-
-                self.microframe += self.microframes_per_frame();
-                next_frame_time_nanos = self.expected_frame_start_nanos();
             }
 
             // Continue directly to the next frame, starting it early since a frame was skipped
         }
-    }
 
-    pub fn run_frame(&mut self) {
-        for _ in 0..TICKS_PER_FRAME {
-            self.run_tick();
-        }
+        self.flush_on_shutdown();
     }
 
-    fn enter_vblank(&mut self) {
-        self.set_mode(Mode::VBlank);
-        self.window_line_counter.reset();
+    /// Final flush so that no progress since the last automatic flush is lost. Frontends that run
+    /// frames themselves instead of calling `run` must call this once they stop.
+    pub fn flush_on_shutdown(&mut self) {
+        let _ = self.save_cartridge_state_to_disk();
+        let _ = self.write_auto_state();
     }
 
-    fn enter_hblank(&mut self) {
-        self.set_mode(Mode::HBlank);
-
-        if self.current_hblank_vram_dma_transfer.is_some() {
-            self.start_hblank_vram_dma_transfer_block();
+    /// Sleep until the next frame should start. Commands received in the meantime are handled
+    /// immediately, so that a shutdown does not wait for the rest of the sleep.
+    fn sleep_until_next_frame(&mut self, duration: Duration) {
+        if self.input_adapter.is_none() {
+            thread::sleep(duration);
+            return;
         }
-    }
 
-    fn set_mode(&mut self, mode: Mode) {
-        self.mode = mode;
-
-        match mode {
-            Mode::HBlank => {
-                if self.is_stat_hblank_interrupt_enabled() {
-                    self.request_interrupt(Interrupt::LcdStat);
-                }
-            }
-            Mode::VBlank => {
-                self.request_interrupt(Interrupt::VBlank);
+        let deadline = Instant::now() + duration;
 
-                if self.is_stat_vblank_interrupt_enabled() {
-                    self.request_interrupt(Interrupt::LcdStat);
+        while !self.is_shutting_down {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let input_adapter = self.input_adapter.as_mut().unwrap();
+            match input_adapter.commands_rx.recv_timeout(remaining) {
+                Ok(command) => {
+                    input_adapter.push_pending_command(command);
+                    self.handle_commands();
                 }
-            }
-            Mode::OamScan => {
-                if self.is_stat_oam_scan_interrupt_enabled() {
-                    self.request_interrupt(Interrupt::LcdStat);
+                Err(RecvTimeoutError::Timeout) => return,
+                // No more commands can arrive, so sleep out the rest of the frame
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    return;
                 }
             }
-            Mode::Draw => {}
         }
     }
 
-    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
-        let current_if = self.if_reg();
+    /// Run at least a full frame of ticks. A single step may run past the end of the frame in cycle
+    /// accurate mode, in which case the next frame is shortened by the overshoot so that frames do
+    /// not drift away from frame boundaries.
+    pub fn run_frame(&mut self) {
+        let frame_ticks = TICKS_PER_FRAME - self.frame_overshoot_ticks;
+
+        let mut num_ticks = 0;
+        while num_ticks < frame_ticks {
+            num_ticks += self.run_tick();
+        }
+
+        self.frame_overshoot_ticks = if self.options.cycle_accurate {
+            (num_ticks - frame_ticks).min(TICKS_PER_FRAME - 1)
+        } else {
+            0
+        };
+
+        self.record_rewind_snapshot();
+    }
+
+    /// Take a snapshot to rewind to if one is due after this frame. Snapshots are taken between
+    /// frames, where no instruction is partway through executing.
+    fn record_rewind_snapshot(&mut self) {
+        let interval_frames = self.options.rewind_interval_frames;
+        if !self.rewind_buffer.frame_complete(interval_frames) {
+            return;
+        }
+
+        let emulator_bytes = rmp_serde::to_vec(self).unwrap();
+        let snapshot = save_compat::encode(BlobKind::QuickSave, &emulator_bytes);
+        self.rewind_buffer.push(snapshot, interval_frames);
+    }
+
+    /// Restore the newest rewind snapshot in place of running a frame, and display it. Once there
+    /// are no snapshots left the emulator stays on the oldest state until rewinding stops.
+    fn rewind_frame(&mut self) {
+        self.beat_heartbeat();
+
+        // No ticks are run, so commands must be handled here to stay responsive
+        self.handle_commands();
+
+        let Some(snapshot) = self.rewind_buffer.pop() else {
+            return;
+        };
+
+        if let Err(error) = self.restore_state(&snapshot) {
+            eprintln!("Could not rewind: {}", error);
+            return;
+        }
+
+        self.flush_video_frame();
+    }
+
+    /// Run at least the given number of ticks as fast as possible, returning the number of ticks
+    /// that were run. Unlike `run_frame` no rewind snapshots are taken.
+    pub fn run_ticks(&mut self, num_ticks: usize) -> usize {
+        let mut num_ticks_run = 0;
+        while num_ticks_run < num_ticks {
+            num_ticks_run += self.run_tick();
+        }
+
+        num_ticks_run
+    }
+
+    /// Run until the CPU is ready to start its next instruction or interrupt handler, returning the
+    /// number of ticks that were run. Runs a single tick while the CPU is halted or stopped.
+    pub fn step_instruction(&mut self) -> usize {
+        let mut num_ticks_run = self.run_tick();
+        while self.ticks_to_next_instruction > 0 && !self.is_cpu_stopped {
+            num_ticks_run += self.run_tick();
+        }
+
+        num_ticks_run
+    }
+
+    /// Run a fixed number of frames as fast as possible, without pacing to wall-clock time or
+    /// flushing the save file. Used for headless runs such as automated screenshot tests.
+    pub fn run_frames(&mut self, num_frames: usize) {
+        for _ in 0..num_frames {
+            self.run_frame();
+        }
+    }
+
+    /// Above normal speed frames are produced faster than the GUI displays them, so only around one
+    /// frame per frame at normal speed is drawn. Skipped frames still have all other PPU side
+    /// effects.
+    fn start_frame(&mut self) {
+        self.beat_heartbeat();
+        self.sync_cartridge_clock();
+
+        if let Some(screen_palette) = self.pending_screen_palette.take() {
+            self.apply_screen_palette(screen_palette);
+        }
+
+        if self.speed > NORMAL_SPEED {
+            // Draw the frames where the number of frames that would have run at normal speed
+            // increases
+            let frame_index = self.fast_frame_index as f64;
+            self.is_skipping_render = self.fast_frame_index != 0
+                && (frame_index / self.speed).floor() == ((frame_index - 1.0) / self.speed).floor();
+            self.fast_frame_index += 1;
+        } else {
+            self.is_skipping_render = false;
+            self.fast_frame_index = 0;
+        }
+    }
+
+    /// Total number of frames that were drawn, which excludes frames skipped above normal speed.
+    pub fn num_rendered_frames(&self) -> u64 {
+        self.num_rendered_frames
+    }
+
+    fn enter_vblank(&mut self) {
+        self.set_mode(Mode::VBlank);
+        self.window_line_counter.reset();
+    }
+
+    fn enter_hblank(&mut self) {
+        self.set_mode(Mode::HBlank);
+
+        if self.current_hblank_vram_dma_transfer.is_some() {
+            self.start_hblank_vram_dma_transfer_block();
+        }
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+
+        if mode == Mode::VBlank {
+            self.request_interrupt(Interrupt::VBlank);
+        }
+
+        self.update_stat_interrupt_line();
+    }
+
+    /// Recompute the STAT interrupt line from the mode, LY, LYC, and the STAT interrupt enable bits,
+    /// requesting an LcdStat interrupt if it rises. A condition that starts while another enabled
+    /// condition still holds does not request a second interrupt.
+    pub fn update_stat_interrupt_line(&mut self) {
+        let is_mode_condition_met = match self.mode {
+            Mode::HBlank => self.is_stat_hblank_interrupt_enabled(),
+            Mode::VBlank => self.is_stat_vblank_interrupt_enabled(),
+            Mode::OamScan => self.is_stat_oam_scan_interrupt_enabled(),
+            Mode::Draw => false,
+        };
+        let is_lyc_condition_met = self.is_stat_lyc_interrupt_enabled() && self.ly() == self.lyc();
+
+        let stat_interrupt_line = is_mode_condition_met || is_lyc_condition_met;
+        if stat_interrupt_line && !self.stat_interrupt_line {
+            self.request_interrupt(Interrupt::LcdStat);
+        }
+
+        self.stat_interrupt_line = stat_interrupt_line;
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        let current_if = self.if_reg();
         self.write_if_reg(current_if | interrupt.flag_bit());
     }
 
@@ -890,41 +2311,306 @@ impl Emulator {
         self.ticks_to_next_instruction = ticks;
     }
 
-    fn run_tick(&mut self) {
-        // Check commands every millisecond to keep input responsive
-        if self.tick.is_multiple_of(TICKS_PER_MILLISECOND_U32) {
+    /// Run a single tick, returning the number of ticks that were run. This is more than one if an
+    /// instruction advanced the rest of the system in cycle accurate mode.
+    fn run_tick(&mut self) -> usize {
+        // Nothing advances in STOP mode. Only input is handled, since a button press is the only
+        // way to leave STOP mode.
+        if self.is_cpu_stopped {
+            self.run_stopped_tick();
+            self.count_frame_advance_ticks(1);
+            return 1;
+        }
+
+        // Check commands at the start of every scanline to keep input responsive. Scanline starts
+        // are never part of a batch of idle ticks.
+        if self.tick.is_multiple_of(TICKS_PER_SCANLINE as u32) {
             self.handle_commands();
         }
 
+        let num_idle_ticks = self.num_idle_ticks();
+        if num_idle_ticks > 0 {
+            self.run_idle_ticks(num_idle_ticks);
+            return num_idle_ticks;
+        }
+
+        self.start_tick();
+
+        let mut num_ticks = 1;
+
+        // Ready for next instruction. Either execute the next instruction or an interrupt handler.
+        'handled: {
+            if self.ticks_to_next_instruction == 0 && self.cpu_lock.is_none() {
+                let interrupt_bits = self.interrupt_bits();
+                if interrupt_bits != 0 {
+                    // A pending interrupts resumes a halted CPU, even if IME is disabled and
+                    // interrupt won't actually be handled.
+                    self.resume_halted_cpu();
+
+                    if self.regs().interrupts_enabled() {
+                        self.handle_interrupt(Interrupt::for_bits(interrupt_bits));
+                        break 'handled;
+                    }
+                }
+
+                if !self.is_cpu_halted && !self.is_cpu_stopped_for_vram_dma {
+                    if self.debugger.has_breakpoints() {
+                        self.check_breakpoints();
+                    }
+
+                    let pc = self.regs().pc();
+                    self.pc_history.record(pc);
+
+                    self.debugger.start_instruction(pc);
+                    num_ticks += self.execute_instruction_stepped();
+                    self.finish_debugged_instruction();
+
+                    break 'handled;
+                }
+            }
+        }
+
+        // CPU runs twice as fast in double speed mode
+        self.ticks_to_next_instruction = self
+            .ticks_to_next_instruction
+            .saturating_sub(self.cpu_ticks_per_tick());
+
+        self.finish_tick();
+        self.count_frame_advance_ticks(num_ticks);
+
+        num_ticks
+    }
+
+    /// Number of ticks starting at the current one in which only the divider and APU period timers
+    /// would change, so that they can be run as a single batch. The batch ends before the next tick
+    /// in which the CPU starts an instruction or interrupt, the PPU changes mode or draws, audio is
+    /// sampled, a timer edge falls, or the frame ends. Zero if the current tick must run on its own.
+    fn num_idle_ticks(&self) -> usize {
+        if self.options.per_tick_stepping
+            || self.tima_overflow != TimaOverflow::None
+            || self.serial_transfer_bits_remaining != 0
+            || !matches!(self.pending_enable_interrupts, PendingEnableInterrupt::None)
+            || self.current_oam_dma_transfer.is_some()
+            || self.current_general_purpose_vram_dma_transfer.is_some()
+            || self.current_hblank_vram_dma_transfer.is_some()
+            || self.current_speed_switch.is_some()
+        {
+            return 0;
+        }
+
+        let cpu_ticks_per_tick = self.cpu_ticks_per_tick();
+
+        // The CPU is either partway through an instruction or halted with no interrupt to wake it
+        let cpu_idle_ticks = if self.ticks_to_next_instruction > 0 {
+            self.ticks_to_next_instruction.div_ceil(cpu_ticks_per_tick)
+        } else if (self.is_cpu_halted && self.interrupt_bits() == 0) || self.cpu_lock.is_some() {
+            usize::MAX
+        } else {
+            return 0;
+        };
+
+        // The PPU only waits in OAM scan, HBlank, and VBlank. Draw mode advances every tick.
+        let tick_within_scanline = self.tick as usize % TICKS_PER_SCANLINE;
+        let ppu_idle_ticks = if tick_within_scanline == 0 {
+            0
+        } else if self.scanline == LAST_SCANLINE
+            && tick_within_scanline < LAST_SCANLINE_LY_RESET_TICKS
+        {
+            LAST_SCANLINE_LY_RESET_TICKS - tick_within_scanline
+        } else if self.scanline >= SCREEN_HEIGHT as u8 {
+            TICKS_PER_SCANLINE - tick_within_scanline
+        } else if tick_within_scanline < OAM_SCAN_TICKS {
+            OAM_SCAN_TICKS - tick_within_scanline
+        } else if self.mode == Mode::HBlank {
+            TICKS_PER_SCANLINE - tick_within_scanline
+        } else {
+            0
+        };
+
+        // The last tick of the frame flushes the frame
+        let frame_idle_ticks = TICKS_PER_FRAME - 1 - self.tick as usize;
+
+        let ticks_per_sample = TICKS_PER_SAMPLE as usize;
+        let tick_within_sample = self.tick as usize % ticks_per_sample;
+        let sample_idle_ticks = if tick_within_sample == 0 {
+            0
+        } else {
+            ticks_per_sample - tick_within_sample
+        };
+
+        // Number of ticks before the divider has a falling edge on a bit, which would clock
+        // whatever is driven by that bit
+        let ticks_to_falling_edge = |mask: u16| {
+            let edge_period = 2 * mask as usize;
+            let distance = edge_period - self.full_divider_register as usize % edge_period;
+            distance.div_ceil(cpu_ticks_per_tick) - 1
+        };
+
+        let div_apu_idle_ticks = ticks_to_falling_edge(0x1000 * cpu_ticks_per_tick as u16);
+        let timer_idle_ticks = if self.is_timer_enabled {
+            ticks_to_falling_edge(self.tac_mask)
+        } else {
+            usize::MAX
+        };
+
+        let frame_advance_idle_ticks = self.frame_advance_ticks_remaining.unwrap_or(usize::MAX);
+
+        [
+            cpu_idle_ticks,
+            ppu_idle_ticks,
+            frame_idle_ticks,
+            sample_idle_ticks,
+            div_apu_idle_ticks,
+            timer_idle_ticks,
+            frame_advance_idle_ticks,
+        ]
+        .into_iter()
+        .min()
+        .unwrap()
+    }
+
+    /// Run a batch of ticks found by `num_idle_ticks`, with the same effect as running each tick.
+    fn run_idle_ticks(&mut self, num_ticks: usize) {
+        let num_cpu_ticks = num_ticks * self.cpu_ticks_per_tick();
+        self.full_divider_register = self
+            .full_divider_register
+            .wrapping_add(num_cpu_ticks as u16);
+
+        let tick_number = self.tick;
+        self.apu_mut()
+            .advance_period_timers_by(tick_number, num_ticks as u32);
+
+        self.ticks_to_next_instruction =
+            self.ticks_to_next_instruction.saturating_sub(num_cpu_ticks);
+
+        self.tick += num_ticks as u32;
+        self.count_frame_advance_ticks(num_ticks);
+    }
+
+    /// Execute the next instruction, returning the number of extra ticks the rest of the system was
+    /// advanced while it executed. Always zero unless in cycle accurate mode.
+    fn execute_instruction_stepped(&mut self) -> usize {
+        if !self.options.cycle_accurate {
+            self.execute_instruction();
+            return 0;
+        }
+
+        self.cpu_step_state = Some(CpuStepState::default());
+        self.execute_instruction();
+        let step_state = self.cpu_step_state.take().unwrap();
+
+        // Only wait out the part of the instruction that has not already elapsed
+        self.ticks_to_next_instruction = self
+            .ticks_to_next_instruction
+            .saturating_sub(step_state.elapsed_cpu_ticks);
+
+        step_state.elapsed_ticks
+    }
+
+    /// Called by the CPU before each memory access. In cycle accurate mode every access after the
+    /// opcode fetch happens on the following machine cycle, so advance the rest of the system to it.
+    pub fn step_to_next_memory_access(&mut self) {
+        let Some(step_state) = &mut self.cpu_step_state else {
+            return;
+        };
+
+        step_state.num_accesses += 1;
+        if step_state.num_accesses > 1 {
+            self.step_machine_cycle();
+        }
+    }
+
+    /// Called by the CPU for a machine cycle of an instruction that does not access memory.
+    pub fn step_internal_machine_cycle(&mut self) {
+        if self.cpu_step_state.is_some() {
+            self.step_machine_cycle();
+        }
+    }
+
+    /// Number of ticks in a CPU machine cycle. The CPU runs twice as fast in double speed mode, so a
+    /// machine cycle spans fewer ticks.
+    fn ticks_per_machine_cycle(&self) -> usize {
+        CPU_TICKS_PER_MACHINE_CYCLE / self.cpu_ticks_per_tick()
+    }
+
+    /// Advance the rest of the system by one machine cycle of the current instruction.
+    fn step_machine_cycle(&mut self) {
+        let num_ticks = self.ticks_per_machine_cycle();
+        for _ in 0..num_ticks {
+            self.finish_tick();
+            self.start_tick();
+        }
+
+        let step_state = self.cpu_step_state.as_mut().unwrap();
+        step_state.elapsed_cpu_ticks += CPU_TICKS_PER_MACHINE_CYCLE;
+        step_state.elapsed_ticks += num_ticks;
+    }
+
+    /// Advance the PPU, timers, and APU at the start of a tick, before the CPU runs.
+    fn start_tick(&mut self) {
+        if self.tick == 0 {
+            self.frame_timing_scope = TimingScope::start();
+        }
+
         // Start a scanline and perform the necessary mdoe transitions
         let tick_within_scanline = self.tick % (TICKS_PER_SCANLINE as u32);
         if tick_within_scanline == 0 {
-            self.scanline = if self.tick == 0 { 0 } else { self.scanline + 1 };
-
-            // Request interrupt for LYC=LY if necessary
-            if self.is_stat_lyc_interrupt_enabled() && (self.scanline == self.lyc()) {
-                self.request_interrupt(Interrupt::LcdStat);
+            if self.tick == 0 {
+                self.start_frame();
             }
 
+            self.scanline = if self.tick == 0 { 0 } else { self.scanline + 1 };
+
             // Enter OAM scan at the start of each scanline on screen, otherwise enter VBlank at the
             // start of the first scanline after the screen.
             if self.scanline < SCREEN_HEIGHT as u8 {
                 self.set_mode(Mode::OamScan);
             } else if self.scanline == SCREEN_HEIGHT as u8 {
                 self.enter_vblank();
+            } else {
+                self.update_stat_interrupt_line();
             }
+        } else if self.scanline == LAST_SCANLINE
+            && tick_within_scanline == LAST_SCANLINE_LY_RESET_TICKS as u32
+        {
+            // LY changes to 0 partway through the last scanline
+            self.update_stat_interrupt_line();
         }
 
         // Transition to Draw and HBlank modes at the appropriate ticks within each screen scanline
         if self.scanline < SCREEN_HEIGHT as u8 {
             if tick_within_scanline == OAM_SCAN_TICKS as u32 {
-                // OAM scan is followed by a draw period. We simplify by making this a fixed length and
-                // drawing the entire scanline at once, at the start of the draw period.
+                // OAM scan is followed by a draw period, whose length is determined up front. The
+                // scanline is then drawn progressively over the course of the draw period.
                 self.set_mode(Mode::Draw);
-                draw_scanline(self, self.scanline);
-            } else if tick_within_scanline == (OAM_SCAN_TICKS + DRAW_TICKS) as u32 {
-                // Finally enter HBlank for the rest of the scanline
-                self.enter_hblank();
+                self.draw_ticks = if self.is_skipping_render {
+                    skip_scanline(self, self.scanline)
+                } else {
+                    if self.scanline == SCREEN_HEIGHT as u8 - 1 {
+                        self.num_rendered_frames += 1;
+                    }
+
+                    let scope = TimingScope::start();
+                    let renderer = ScanlineRenderer::start(self, self.scanline);
+                    let draw_ticks = renderer.draw_ticks();
+                    self.scanline_renderer = Some(renderer);
+                    scope.finish(&mut self.current_frame_timings, TimingCategory::Draw);
+
+                    draw_ticks
+                };
+                self.current_draw_timing_metrics
+                    .record_scanline(self.draw_ticks);
+            }
+
+            if self.mode == Mode::Draw {
+                let draw_tick = tick_within_scanline as usize - OAM_SCAN_TICKS;
+                if draw_tick >= self.draw_ticks {
+                    // Finally enter HBlank for the rest of the scanline
+                    self.advance_scanline_renderer(usize::MAX);
+                    self.enter_hblank();
+                } else {
+                    self.advance_scanline_renderer(draw_tick);
+                }
             }
         }
 
@@ -932,39 +2618,16 @@ impl Emulator {
 
         let tick_number = self.tick;
         self.apu_mut().advance_period_timers(tick_number);
+    }
 
-        // Ready for next instruction. Either execute the next instruction or an interrupt handler.
-        'handled: {
-            if self.ticks_to_next_instruction == 0 {
-                let interrupt_bits = self.interrupt_bits();
-                if interrupt_bits != 0 {
-                    // A pending interrupts resumes a halted CPU, even if IME is disabled and
-                    // interrupt won't actually be handled.
-                    self.resume_halted_cpu();
-
-                    if self.regs().interrupts_enabled() {
-                        self.handle_interrupt(Interrupt::for_bits(interrupt_bits));
-                        break 'handled;
-                    }
-                }
-
-                if !self.is_cpu_halted && !self.is_cpu_stopped_for_vram_dma {
-                    self.execute_instruction();
-                    break 'handled;
-                }
-            }
-        }
-
+    /// Sample audio and advance DMA and other pending state at the end of a tick, after the CPU
+    /// runs, then move on to the next tick.
+    fn finish_tick(&mut self) {
         // Sample audio if necessary
         if self.tick.is_multiple_of(TICKS_PER_SAMPLE as u32) {
+            let scope = TimingScope::start();
             self.push_next_sample();
-        }
-
-        // CPU runs twice as fast in double speed mode
-        if self.is_double_speed() {
-            self.ticks_to_next_instruction = self.ticks_to_next_instruction.saturating_sub(2);
-        } else {
-            self.ticks_to_next_instruction = self.ticks_to_next_instruction.saturating_sub(1);
+            scope.finish(&mut self.current_frame_timings, TimingCategory::Audio);
         }
 
         // Advance states at the end of the tick
@@ -977,104 +2640,615 @@ impl Emulator {
         // Increment tick counter, resetting to 0 at the end of the frame
         self.tick += 1;
 
-        if self.tick == TICKS_PER_FRAME as u32 {
+        let is_frame_end = self.tick == TICKS_PER_FRAME as u32;
+        if is_frame_end {
             self.tick = 0;
+            self.clock.frame_complete();
+
+            self.last_draw_timing_metrics = mem::replace(
+                &mut self.current_draw_timing_metrics,
+                DrawTimingMetrics::new(),
+            );
 
             // Push a single audio frame to the audio output, if any
+            let scope = TimingScope::start();
             self.flush_audio_frame();
-        }
-    }
+            scope.finish(&mut self.current_frame_timings, TimingCategory::Audio);
 
-    fn handle_commands(&mut self) {
-        if self.input_adapter.is_none() {
-            return;
+            self.flush_video_frame();
         }
 
-        while let Ok(command) = self.input_adapter.as_ref().unwrap().commands_rx.try_recv() {
-            match command {
-                Command::UpdatePressedButtons(new_pressed_buttons) => {
-                    self.handle_update_pressed_buttons(new_pressed_buttons)
-                }
-                Command::TogglePause => self.toggle_paused(),
-                Command::Save => self.save_cartridge_state_to_disk(),
-                Command::QuickSave(slot) => self.quick_save(slot),
-                Command::LoadQuickSave(slot) => self.load_quick_save(slot),
-                Command::SetTurboMode(in_turbo_mode) => self.in_turbo_mode = in_turbo_mode,
-                Command::VolumeUp => self.apu_mut().increase_system_volume(),
-                Command::VolumeDown => self.apu_mut().decrease_system_volume(),
-                Command::ToggleMute => self.apu_mut().toggle_muted(),
-                Command::ToggleAudioChannel(channel) => self.apu_mut().toggle_channel(channel),
-                Command::ToggleHpf => self.apu_mut().toggle_hpf(),
-            }
+        if is_frame_end {
+            let frame_timing_scope =
+                mem::replace(&mut self.frame_timing_scope, TimingScope::start());
+            frame_timing_scope.finish(&mut self.current_frame_timings, TimingCategory::Frame);
+            self.last_frame_timings = mem::take(&mut self.current_frame_timings);
         }
     }
 
-    fn toggle_paused(&mut self) {
-        self.is_paused = !self.is_paused;
-
-        // Notify audio output of paused state
-        if let Some(audio_output) = self.audio_output.as_ref() {
-            audio_output.set_paused_state(self.is_paused);
-        }
-
-        while self.is_paused {
+    fn run_stopped_tick(&mut self) {
+        self.ticks_since_stopped_commands += 1;
+        if self.ticks_since_stopped_commands >= TICKS_PER_MILLISECOND_U32 {
+            self.ticks_since_stopped_commands = 0;
+            self.beat_heartbeat();
             self.handle_commands();
         }
     }
 
-    fn quick_save(&mut self, slot: usize) {
-        if slot >= NUM_QUICK_SAVE_SLOTS || self.save_file.is_none() {
+    fn handle_commands(&mut self) {
+        if self.input_adapter.is_none() {
             return;
         }
 
-        let emulator_bytes = rmp_serde::to_vec(self).unwrap();
-
-        let save_file = self.save_file.as_mut().unwrap();
-        save_file.quick_saves[slot] = Some(ByteBuf::from(emulator_bytes));
+        // Later commands wait until an advanced frame has finished, so that e.g. releasing a button
+        // sent after a frame advance does not take effect before the advanced frame runs.
+        while !self.is_shutting_down
+            && self.frame_advance_ticks_remaining.is_none()
+            && let Some(command) = self.input_adapter.as_mut().unwrap().next_command()
+        {
+            self.handle_command(command);
+        }
+    }
 
-        if let Some(save_file_path) = &mut self.save_file_path {
-            save_file.flush_to_disk(save_file_path);
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::UpdatePressedButtons(new_pressed_buttons) => {
+                self.handle_update_pressed_buttons(new_pressed_buttons)
+            }
+            Command::TogglePause => self.toggle_paused(),
+            Command::FrameAdvance => self.start_frame_advance(),
+            Command::Save(command_id) => {
+                let result = self.save_cartridge_state_to_disk();
+                self.send_command_result(command_id, result);
+            }
+            Command::QuickSave(slot, command_id) => {
+                let result = self.quick_save(slot);
+                self.send_command_result(command_id, result);
+            }
+            Command::LoadQuickSave(slot, command_id) => {
+                let result = self.load_quick_save(slot);
+                self.send_command_result(command_id, result);
+            }
+            Command::ExportState(path, command_id) => {
+                let result = self.export_state(&path);
+                self.send_command_result(command_id, result);
+            }
+            Command::ImportState(path, command_id) => {
+                let result = self.import_state(&path);
+                self.send_command_result(command_id, result);
+            }
+            Command::SetTurboMode(in_turbo_mode) => self.set_turbo_mode(in_turbo_mode),
+            Command::SetSpeed(speed) => self.set_speed(speed),
+            Command::SetRewinding(is_rewinding) => self.set_rewinding(is_rewinding),
+            Command::VolumeUp => self.apu_mut().increase_system_volume(),
+            Command::VolumeDown => self.apu_mut().decrease_system_volume(),
+            Command::ToggleMute => self.apu_mut().toggle_muted(),
+            Command::ToggleAudioChannel(channel) => self.apu_mut().toggle_channel(channel),
+            Command::ToggleHpf => self.apu_mut().toggle_hpf(),
+            Command::DumpPpuState(path, command_id) => {
+                let result = self.dump_ppu_state(&path);
+                self.send_command_result(command_id, result);
+            }
+            Command::ExportTileMap(tile_map_number, addressing_mode, path, command_id) => {
+                let result = self.export_tile_map(tile_map_number, addressing_mode, &path);
+                self.send_command_result(command_id, result);
+            }
+            Command::AddBreakpoint(breakpoint) => self.debugger.add_breakpoint(breakpoint),
+            Command::RemoveBreakpoint(breakpoint) => self.debugger.remove_breakpoint(breakpoint),
+            Command::AddWatchpoint(watchpoint) => self.debugger.add_watchpoint(watchpoint),
+            Command::RemoveWatchpoint(watchpoint) => self.debugger.remove_watchpoint(watchpoint),
+            Command::StepInstruction => {
+                self.debugger.request_step();
+                self.set_paused(false);
+            }
+            Command::Continue => self.set_paused(false),
+            Command::SetScreenPalette(screen_palette) => self.set_screen_palette(screen_palette),
+            Command::SetRecordPixelProvenance(is_enabled) => {
+                self.set_record_pixel_provenance(is_enabled)
+            }
+            Command::WriteCgbPalette {
+                is_object,
+                palette,
+                index,
+                color,
+            } => self.write_cgb_palette_color(
+                is_object,
+                palette as usize,
+                index as usize,
+                CgbColor::new(color),
+            ),
+            Command::PatchCartridgeRam(offset, bytes, command_id) => {
+                let result = self.patch_cartridge_ram(offset, &bytes);
+                self.send_command_result(command_id, result);
+            }
+            Command::ExportCartridgeRam(path, command_id) => {
+                let result = self.export_cartridge_ram(&path);
+                self.send_command_result(command_id, result);
+            }
+            Command::ImportCartridgeRam(path, command_id) => {
+                let result = self.import_cartridge_ram(&path);
+                self.send_command_result(command_id, result);
+            }
+            Command::WriteMemory(addr, value) => self.write_memory_bulk(addr, &[value]),
+            Command::Shutdown => {
+                self.is_shutting_down = true;
+                self.is_paused = false;
+            }
         }
     }
 
-    fn load_quick_save(&mut self, slot: usize) {
-        if slot >= NUM_QUICK_SAVE_SLOTS
-            || self.save_file.is_none()
-            || self.save_file.as_ref().unwrap().quick_saves[slot].is_none()
-        {
+    /// Pause if there is a breakpoint at the instruction about to be executed.
+    fn check_breakpoints(&mut self) {
+        let pc = self.regs().pc();
+        let location = BankedAddress::new(self.current_bank_at(pc), pc);
+        if !self.debugger.is_breakpoint(location) {
             return;
         }
 
-        // Deserialize emulator state
-        let save_file = self.save_file.take().unwrap();
-        let serialized_bytes = save_file.quick_saves[slot].as_ref().unwrap().to_vec();
+        self.debugger.record_breakpoint_hit(location);
+        self.send_event(EmulatorEvent::BreakpointHit(location));
+        self.pause_for_debugger();
+    }
 
-        // Some state was not included in serialization and must be preserved
-        let microframe = self.microframe;
+    /// Pause if the instruction that just executed hit a watchpoint or finished a single step.
+    fn finish_debugged_instruction(&mut self) {
+        if let Some(watchpoint_hit) = self.debugger.finish_instruction() {
+            self.send_event(EmulatorEvent::WatchpointHit(watchpoint_hit));
+            self.pause_for_debugger();
+        } else if self.debugger.take_step_request() {
+            self.send_event(EmulatorEvent::Stepped(self.regs().pc()));
+            self.pause_for_debugger();
+        }
+    }
 
-        let mut emulator_builder =
-            EmulatorBuilder::from_quick_save_bytes(save_file, &serialized_bytes)
-                .with_options(self.options.clone());
+    /// Pause until the debugger continues or steps. Blocks only the emulator thread, which keeps
+    /// handling commands while paused.
+    fn pause_for_debugger(&mut self) {
+        self.debugger.cancel_step();
 
-        if let Some(save_file_path) = self.save_file_path.take() {
-            emulator_builder = emulator_builder.with_save_file_path(save_file_path);
+        // Without an input adapter nothing could ever resume execution
+        if self.input_adapter.is_some() {
+            self.set_paused(true);
         }
+    }
 
-        if let Some(input_adapter) = self.input_adapter.take() {
-            emulator_builder = emulator_builder.with_input_adapter(input_adapter);
-        }
+    pub fn add_breakpoint(&mut self, breakpoint: BankedAddress) {
+        self.debugger.add_breakpoint(breakpoint);
+    }
+
+    pub fn last_breakpoint_hit(&self) -> Option<BankedAddress> {
+        self.debugger.last_breakpoint_hit()
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    /// The bank currently mapped at an address, numbered as in RGBDS symbol files. ROM addresses
+    /// use the ROM bank, 0xD000-0xDFFF uses the work RAM bank, and VRAM uses the VRAM bank. All
+    /// other addresses are in bank 0.
+    pub fn current_bank_at(&self, addr: Address) -> u16 {
+        if addr < ROM_END {
+            let mapped_addr = self.cartridge.mbc().map_read_rom_address(addr);
+            (mapped_addr / ROM_BANK_SIZE) as u16
+        } else if addr < VRAM_END {
+            (self.physical_vram_bank_address(addr) / SINGLE_VRAM_BANK_SIZE) as u16
+        } else if (SECOND_WORK_RAM_BANK_START..SECOND_WORK_RAM_BANK_END).contains(&addr) {
+            self.second_wram_bank_num() as u16
+        } else {
+            0
+        }
+    }
+
+    /// The ROM bank currently mapped at 0x4000-0x7FFF.
+    pub fn current_rom_bank(&self) -> u16 {
+        self.current_bank_at(ROM_START)
+    }
+
+    /// Banking state of the cartridge's MBC, for debugging.
+    pub fn mbc_debug_state(&self) -> MbcDebugInfo {
+        self.cartridge.mbc().debug_state()
+    }
+
+    /// All banks of the cartridge RAM declared in the header.
+    pub fn cartridge_ram(&self) -> &[u8] {
+        &self.cartridge.ram()[..self.cartridge.header_ram_size()]
+    }
+
+    /// Writes directly to cartridge RAM instead of through the bus, so that the MBC's RAM enable
+    /// and bank selection are bypassed.
+    fn patch_cartridge_ram(&mut self, offset: usize, bytes: &[u8]) -> Result<(), CommandError> {
+        let ram_size = self.cartridge.header_ram_size();
+        let Some(end) = offset
+            .checked_add(bytes.len())
+            .filter(|end| *end <= ram_size)
+        else {
+            return Err(CommandError::InvalidData(format!(
+                "{} bytes at offset {:04X} do not fit in {} bytes of cartridge RAM",
+                bytes.len(),
+                offset,
+                ram_size
+            )));
+        };
+
+        self.cartridge.ram_mut()[offset..end].copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    fn export_cartridge_ram(&self, path: &Path) -> Result<(), CommandError> {
+        fs::write(path, raw_ram_bytes(&self.cartridge))
+            .map_err(|error| CommandError::Io(error.to_string()))
+    }
+
+    fn import_cartridge_ram(&mut self, path: &Path) -> Result<(), CommandError> {
+        let bytes = fs::read(path).map_err(|error| CommandError::Io(error.to_string()))?;
+        load_raw_save(&mut self.cartridge, &bytes)
+            .map_err(|error| CommandError::InvalidData(error.to_string()))
+    }
+
+    fn set_turbo_mode(&mut self, in_turbo_mode: bool) {
+        let speed = if in_turbo_mode {
+            self.options.turbo_speed.unwrap_or(DEFAULT_TURBO_SPEED)
+        } else {
+            NORMAL_SPEED
+        };
+
+        self.set_speed(speed);
+    }
+
+    /// Frames already scheduled keep their start times, and frames after them are run at the new
+    /// speed.
+    fn set_speed(&mut self, speed: f64) {
+        let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+
+        self.speed = speed;
+        self.frame_pacer.set_speed(speed);
+
+        if let Some(audio_output) = self.audio_output.as_ref() {
+            audio_output.set_speed(speed);
+        }
+    }
+
+    /// No audio is produced while rewinding, so the audio output is paused to avoid underruns.
+    fn set_rewinding(&mut self, is_rewinding: bool) {
+        self.is_rewinding = is_rewinding;
+
+        if let Some(audio_output) = self.audio_output.as_ref() {
+            audio_output.set_paused_state(is_rewinding || self.is_paused);
+        }
+    }
+
+    fn toggle_paused(&mut self) {
+        self.set_paused(!self.is_paused);
+    }
+
+    /// Pausing blocks until the emulator is resumed, handling commands in the meantime. The thread
+    /// sleeps while waiting for each command.
+    ///
+    /// The save file is flushed when pausing, since the emulator may stay paused indefinitely.
+    fn set_paused(&mut self, is_paused: bool) {
+        // Nothing can resume the emulator once it is shutting down
+        if self.is_paused == is_paused || self.is_shutting_down {
+            return;
+        }
+
+        self.is_paused = is_paused;
+
+        if is_paused && !self.is_rewinding {
+            let _ = self.save_cartridge_state_to_disk();
+        }
+
+        // Notify audio output of paused state
+        if let Some(audio_output) = self.audio_output.as_ref() {
+            audio_output.set_paused_state(self.is_paused);
+        }
+
+        while self.is_paused {
+            self.wait_for_command();
+        }
+    }
+
+    /// Handle the next command, blocking until one arrives if none are pending. Nothing could
+    /// resume the emulator once no more commands can arrive, so it is resumed instead.
+    fn wait_for_command(&mut self) {
+        let Some(input_adapter) = self.input_adapter.as_mut() else {
+            self.is_paused = false;
+            return;
+        };
+
+        if input_adapter.pending_commands.is_empty() {
+            match input_adapter.commands_rx.recv() {
+                Ok(command) => input_adapter.push_pending_command(command),
+                Err(RecvError) => {
+                    self.is_paused = false;
+                    return;
+                }
+            }
+        }
+
+        if let Some(command) = input_adapter.next_command() {
+            self.handle_command(command);
+        }
+    }
+
+    /// Leave the pause loop to run a single frame. The audio output is left paused, so the frame's
+    /// audio is not heard.
+    fn start_frame_advance(&mut self) {
+        if !self.is_paused {
+            return;
+        }
+
+        self.frame_advance_ticks_remaining = Some(TICKS_PER_FRAME);
+        self.is_paused = false;
+    }
+
+    /// Pause again once a frame's worth of ticks have run after a frame advance.
+    fn count_frame_advance_ticks(&mut self, num_ticks: usize) {
+        let Some(ticks_remaining) = self.frame_advance_ticks_remaining else {
+            return;
+        };
+
+        let ticks_remaining = ticks_remaining.saturating_sub(num_ticks);
+        if ticks_remaining > 0 {
+            self.frame_advance_ticks_remaining = Some(ticks_remaining);
+            return;
+        }
+
+        self.frame_advance_ticks_remaining = None;
+        self.set_paused(true);
+    }
+
+    fn quick_save(&mut self, slot: usize) -> Result<(), CommandError> {
+        if slot >= NUM_QUICK_SAVE_SLOTS {
+            return Err(CommandError::NotFound(format!("Quick save slot {}", slot)));
+        }
+
+        if self.save_file.is_none() {
+            return Err(CommandError::NoSaveFile);
+        }
+
+        let emulator_bytes = rmp_serde::to_vec(self).unwrap();
+        let emulator_bytes = save_compat::encode(BlobKind::QuickSave, &emulator_bytes);
+
+        let save_file = self.save_file.as_mut().unwrap();
+        save_file.quick_saves[slot] = Some(ByteBuf::from(emulator_bytes));
+
+        self.flush_save_file(None)
+    }
+
+    fn load_quick_save(&mut self, slot: usize) -> Result<(), CommandError> {
+        let save_file = self.save_file.as_ref().ok_or(CommandError::NoSaveFile)?;
+
+        let serialized_bytes = match save_file.quick_saves.get(slot) {
+            Some(Some(quick_save)) => quick_save.to_vec(),
+            _ => {
+                return Err(CommandError::NotFound(format!(
+                    "Quick save in slot {}",
+                    slot
+                )));
+            }
+        };
+
+        if let Err(error) = self.restore_quick_save(save_file.clone(), &serialized_bytes) {
+            eprintln!("Could not load quick save from slot {}: {}", slot, error);
+            return Err(CommandError::InvalidData(error.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn export_state(&self, path: &Path) -> Result<(), CommandError> {
+        let emulator_bytes = rmp_serde::to_vec(self).unwrap();
+        let quick_save = save_compat::encode(BlobKind::QuickSave, &emulator_bytes);
+        let state_file = StateFile::new(&self.cartridge, quick_save);
+
+        match fs::write(path, state_file.to_bytes()) {
+            Ok(()) => {
+                println!("Exported state to {}", path.display());
+                Ok(())
+            }
+            Err(error) => {
+                eprintln!("Failed to export state to {}: {}", path.display(), error);
+                Err(CommandError::Io(error.to_string()))
+            }
+        }
+    }
+
+    fn import_state(&mut self, path: &Path) -> Result<(), CommandError> {
+        let bytes = fs::read(path).map_err(|error| CommandError::Io(error.to_string()))?;
+
+        // The ROM is checked before deserializing so that a state for another ROM is rejected
+        // without touching the running emulator
+        let state_file = StateFile::from_bytes(&bytes).and_then(|state_file| {
+            state_file.check_rom(&self.cartridge)?;
+            Ok(state_file)
+        });
+
+        if let Err(error) =
+            state_file.and_then(|state_file| self.restore_state(&state_file.quick_save))
+        {
+            eprintln!("Could not import state from {}: {}", path.display(), error);
+            return Err(CommandError::InvalidData(error.to_string()));
+        }
+
+        println!("Imported state from {}", path.display());
+
+        Ok(())
+    }
+
+    /// Write the state to resume from on the next launch, if resuming on launch is enabled. Only
+    /// written on a clean shutdown, so the auto-state is never from a session that crashed.
+    fn write_auto_state(&self) -> Result<(), CommandError> {
+        let Some(auto_state_path) = &self.auto_state_path else {
+            return Ok(());
+        };
+
+        let emulator_bytes = rmp_serde::to_vec(self).unwrap();
+        let quick_save = save_compat::encode(BlobKind::QuickSave, &emulator_bytes);
+        let state_file = StateFile::new(&self.cartridge, quick_save);
+
+        fs::write(auto_state_path, state_file.to_bytes()).map_err(|error| {
+            eprintln!("Unable to write auto-state {}: {}", auto_state_path, error);
+            CommandError::Io(error.to_string())
+        })
+    }
+
+    /// Read the auto-state, if there is one for the current ROM. An auto-state that cannot be read,
+    /// or that belongs to a different ROM because the ROM file changed, is deleted so that it is not
+    /// offered again.
+    fn read_auto_state(&self) -> Option<StateFile> {
+        let auto_state_path = self.auto_state_path.as_ref()?;
+        let bytes = fs::read(auto_state_path).ok()?;
+
+        let state_file = StateFile::from_bytes(&bytes).and_then(|state_file| {
+            state_file.check_rom(&self.cartridge)?;
+            Ok(state_file)
+        });
+
+        match state_file {
+            Ok(state_file) => Some(state_file),
+            Err(error) => {
+                eprintln!("Discarding auto-state {}: {}", auto_state_path, error);
+                let _ = fs::remove_file(auto_state_path);
+                None
+            }
+        }
+    }
+
+    /// Whether there is an auto-state for the current ROM to resume from.
+    pub fn has_auto_state(&self) -> bool {
+        self.read_auto_state().is_some()
+    }
+
+    /// Resume from the auto-state once `run` has powered on the emulator.
+    pub fn resume_from_auto_state_on_power_on(&mut self) {
+        self.should_resume_from_auto_state = true;
+    }
+
+    /// Replace the emulator with the auto-state, returning whether there was one to resume from.
+    /// The auto-state is deleted once loaded, so that if the resumed session crashes it is not
+    /// offered again.
+    pub fn resume_from_auto_state(&mut self) -> bool {
+        let Some(state_file) = self.read_auto_state() else {
+            return false;
+        };
+
+        let auto_state_path = self.auto_state_path.clone().unwrap();
+        let _ = fs::remove_file(&auto_state_path);
+
+        if let Err(error) = self.restore_state(&state_file.quick_save) {
+            eprintln!(
+                "Could not resume from auto-state {}: {}",
+                auto_state_path, error
+            );
+            return false;
+        }
+
+        println!("Resumed from {}", auto_state_path);
+
+        true
+    }
+
+    /// Replace the emulator with the state from a serialized quick save, keeping the current save
+    /// file. Quick saves are restored through a save file holding the ROM, so without a save file a
+    /// temporary one is used and the emulator is left without a save file afterwards.
+    fn restore_state(&mut self, serialized_bytes: &[u8]) -> Result<(), SaveFileError> {
+        let has_save_file = self.save_file.is_some();
+        let save_file = match &self.save_file {
+            Some(save_file) => save_file.clone(),
+            None => Box::new(SaveFile::new(&self.cartridge)),
+        };
+
+        self.restore_quick_save(save_file, serialized_bytes)?;
+
+        if !has_save_file {
+            self.save_file = None;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the emulator with the state from a serialized quick save. State that is not included
+    /// in quick saves, such as breakpoints and where to save, is kept. On failure the emulator is
+    /// left unchanged.
+    fn restore_quick_save(
+        &mut self,
+        save_file: Box<SaveFile>,
+        serialized_bytes: &[u8],
+    ) -> Result<(), SaveFileError> {
+        let mut emulator_builder =
+            EmulatorBuilder::from_quick_save_bytes(save_file, serialized_bytes, self.machine)?
+                .with_options(self.options.clone());
+
+        // Some state was not included in serialization and must be preserved
+        let frame_pacer = mem::take(&mut self.frame_pacer);
+        let save_file_flush_state = mem::take(&mut self.save_file_flush_state);
+        let debugger = mem::replace(&mut self.debugger, Debugger::new());
+        let pixel_provenance = self.pixel_provenance.take();
+        let cgb_only_detector = mem::take(&mut self.cgb_only_detector);
+        let screen_palette = self.screen_palette;
+        let pending_screen_palette = self.pending_screen_palette;
+        let rewind_buffer = mem::replace(&mut self.rewind_buffer, RewindBuffer::new());
+        let is_rewinding = self.is_rewinding;
+        let speed = self.speed;
+        let serial_output = self.serial_output.take();
+
+        if let Some(save_file_path) = self.save_file_path.take() {
+            emulator_builder = emulator_builder.with_save_file_path(save_file_path);
+        }
+
+        if let Some(raw_save_file_path) = self.raw_save_file_path.take() {
+            emulator_builder = emulator_builder.with_raw_save_file_path(raw_save_file_path);
+        }
+
+        if let Some(auto_state_path) = self.auto_state_path.take() {
+            emulator_builder = emulator_builder.with_auto_state_path(auto_state_path);
+        }
+
+        if let Some(input_adapter) = self.input_adapter.take() {
+            emulator_builder = emulator_builder.with_input_adapter(input_adapter);
+        }
 
         if let Some(audio_output) = self.audio_output.take() {
             emulator_builder = emulator_builder.with_audio_output(audio_output);
         }
 
-        *self = emulator_builder.build();
+        if let Some(video_sink) = self.video_sink.take() {
+            emulator_builder = emulator_builder.with_video_sink(video_sink);
+        }
+
+        if let Some(shared_stats) = self.shared_stats.take() {
+            emulator_builder = emulator_builder.with_shared_stats(shared_stats);
+        }
+
+        *self = emulator_builder
+            .build()
+            .expect("BIOS size is checked when the quick save is decoded");
 
         // Restore state excluded from quick save
-        self.microframe = microframe;
+        self.frame_pacer = frame_pacer;
+        self.save_file_flush_state = save_file_flush_state;
+        self.debugger = debugger;
+        self.pixel_provenance = pixel_provenance;
+        self.cgb_only_detector = cgb_only_detector;
+        self.apply_screen_palette(screen_palette);
+        self.pending_screen_palette = pending_screen_palette;
+        self.rewind_buffer = rewind_buffer;
+        self.is_rewinding = is_rewinding;
+        self.speed = speed;
+        self.serial_output = serial_output;
+
+        // A quick save made partway through a frame contains a screen that is part old frame and
+        // part new frame. Redraw it so the loaded state is displayed consistently, even if paused.
+        if self.mode() != Mode::VBlank {
+            self.rerender_frame();
+        }
+
+        Ok(())
     }
 
-    fn handle_update_pressed_buttons(&mut self, new_pressed_buttons: u8) {
+    pub fn handle_update_pressed_buttons(&mut self, new_pressed_buttons: u8) {
         if self.pressed_buttons == new_pressed_buttons {
             return;
         }
@@ -1100,6 +3274,11 @@ impl Emulator {
         // Update state to new pressed buttons
         self.pressed_buttons = new_pressed_buttons;
         self.write_joypad_reg(new_joypad_reg);
+
+        // Any pressed button in a selected group wakes the CPU from STOP mode
+        if self.is_cpu_stopped && (new_joypad_reg & 0x0F) != 0x0F {
+            self.resume_stopped_cpu();
+        }
     }
 
     pub fn buttons_to_joypad_reg(
@@ -1145,27 +3324,145 @@ impl Emulator {
         result
     }
 
-    fn save_cartridge_state_to_disk(&mut self) {
-        if let (Some(save_file), Some(save_file_path)) = (&mut self.save_file, &self.save_file_path)
+    fn dump_ppu_state(&self, path: &Path) -> Result<(), CommandError> {
+        match ppu_dump::dump_ppu_state(self, path) {
+            Ok(()) => {
+                println!("Dumped PPU state to {}", path.display());
+                Ok(())
+            }
+            Err(error) => {
+                eprintln!("Failed to dump PPU state to {}: {}", path.display(), error);
+                Err(CommandError::Io(error.to_string()))
+            }
+        }
+    }
+
+    fn export_tile_map(
+        &self,
+        tile_map_number: u8,
+        addressing_mode: u8,
+        path: &Path,
+    ) -> Result<(), CommandError> {
+        match tile_map_export::export_tile_map(self, tile_map_number, addressing_mode, path) {
+            Ok(()) => {
+                println!("Exported tile map to {}", path.display());
+                Ok(())
+            }
+            Err(error) => {
+                eprintln!("Failed to export tile map to {}: {}", path.display(), error);
+                Err(CommandError::Io(error.to_string()))
+            }
+        }
+    }
+
+    fn save_cartridge_state_to_disk(&mut self) -> Result<(), CommandError> {
+        let save_file = self.save_file.as_mut().ok_or(CommandError::NoSaveFile)?;
+        save_file.update_cartridge_state(&self.cartridge);
+
+        let raw_save = self
+            .raw_save_file_path
+            .as_ref()
+            .and_then(|_| raw_save_bytes(&self.cartridge));
+        self.flush_save_file(raw_save.as_deref())
+    }
+
+    /// Write the save file to disk, along with battery-backed RAM to the raw save file if given.
+    /// Failures are not fatal, the user is notified of the first failure and later flushes are
+    /// retried less frequently. If a fallback directory is set the files are moved there instead.
+    ///
+    /// Only fails if a file could not be written to any location.
+    fn flush_save_file(&mut self, raw_save: Option<&[u8]>) -> Result<(), CommandError> {
+        let fallback_dir = self.options.save_fallback_dir.as_deref();
+        let mut failures = vec![];
+
+        if let (Some(save_file), Some(save_file_path)) = (&self.save_file, &mut self.save_file_path)
+            && let Err(failure) = write_with_fallback(save_file_path, fallback_dir, |path| {
+                save_file.flush_to_disk(path)
+            })
+        {
+            failures.push(failure);
+        }
+
+        if let (Some(bytes), Some(raw_save_file_path)) = (raw_save, &mut self.raw_save_file_path)
+            && let Err(failure) = write_with_fallback(raw_save_file_path, fallback_dir, |path| {
+                write_file_atomically(Path::new(path), bytes)
+            })
         {
-            save_file.update_cartridge_state(&self.cartridge);
-            save_file.flush_to_disk(save_file_path);
+            failures.push(failure);
+        }
+
+        let Some((first_error, first_fallback_path)) = failures.first() else {
+            self.save_file_flush_state.record_success();
+            return Ok(());
+        };
+
+        let reason = first_error.kind().to_string();
+        let (result, fallback_path) = match failures.iter().find(|(_, path)| path.is_none()) {
+            Some((error, _)) => {
+                self.save_file_flush_state.record_failure();
+                (Err(CommandError::Io(error.to_string())), None)
+            }
+            None => {
+                self.save_file_flush_state.record_success();
+                (Ok(()), first_fallback_path.clone())
+            }
+        };
+
+        if self.save_file_flush_state.should_report_failure() {
+            self.send_event(EmulatorEvent::SaveFileWriteFailed {
+                reason,
+                fallback_path,
+            });
+        }
+
+        result
+    }
+
+    fn send_event(&self, event: EmulatorEvent) {
+        if let Some(input_adapter) = &self.input_adapter {
+            // The GUI may have already shut down, in which case there is no one to notify
+            let _ = input_adapter.events_tx.send(event);
         }
     }
 
-    pub fn write_color(&mut self, x: u8, y: u8, color: Color) {
-        self.write_pixel(x as usize, y as usize, color);
+    fn send_command_result(&self, command_id: CommandId, result: Result<(), CommandError>) {
+        self.send_event(EmulatorEvent::CommandResult { command_id, result });
     }
 
-    /// Read a byte from the given virtual address.
+    /// Write a pixel drawn by the PPU, whose displayed color has already been looked up.
+    pub fn write_color(&mut self, x: u8, y: u8, color: PaletteColor) {
+        let (x, y) = (x as usize, y as usize);
+        self.pixels[y][x] = color.color;
+        self.screen_colors[y * SCREEN_WIDTH + x] = color.color32;
+    }
+
+    /// Read a byte from the given virtual address as the CPU.
     ///
-    /// May be mapped to a register or may be mapped to cartridge memory via the MBC.
+    /// May be mapped to a register or may be mapped to cartridge memory via the MBC. Reads from VRAM
+    /// and OAM fail while the PPU is using them.
     pub fn read_address(&self, addr: Address) -> u8 {
+        self.debugger.check_access(addr, false);
+
+        if let Some(transfer) = &self.current_oam_dma_transfer
+            && self.is_blocked_by_oam_dma(addr)
+        {
+            return transfer.current_byte;
+        }
+
+        if self.is_blocked_by_ppu(addr) {
+            return VRAM_READ_FAILED_VALUE;
+        }
+
+        self.read_address_unrestricted(addr)
+    }
+
+    /// Read a byte from the given virtual address, ignoring PPU access restrictions. Used by DMA
+    /// and for inspecting memory.
+    fn read_address_unrestricted(&self, addr: Address) -> u8 {
         if addr < ROM_END {
             // While booting this may be mapped to the BIOS instead
-            if self.is_booting() && self.is_bios_addr(addr) {
-                let physical_addr = self.physical_bios_address(addr);
-                return self.bios.as_ref().unwrap()[physical_addr];
+            if let Some(bios_byte) = self.read_bios_overlay(addr) {
+                return bios_byte;
             }
 
             // No support needed yet for reading registers from RAM area
@@ -1176,7 +3473,9 @@ impl Emulator {
             self.vram[physical_addr]
         } else if addr < EXTERNAL_RAM_END {
             match self.cartridge.mbc().map_read_ram_address(addr) {
-                Location::Address(mapped_addr) => self.cartridge.ram()[mapped_addr],
+                Location::Address(mapped_addr) => {
+                    self.cartridge.ram()[mapped_addr] | !self.cartridge.mbc().ram_value_mask()
+                }
                 Location::Register(reg) => self.cartridge.mbc().read_register(reg),
             }
         } else if addr < FIRST_WORK_RAM_BANK_END {
@@ -1186,7 +3485,12 @@ impl Emulator {
             let physical_addr = self.physical_second_work_ram_bank_address(addr);
             self.work_ram[physical_addr]
         } else if addr < ECHO_RAM_END {
-            panic!("Attempted to read from Echo RAM at address {:04X}", addr);
+            if self.options.strict_memory {
+                panic!("Attempted to read from Echo RAM at address {:04X}", addr);
+            }
+
+            let physical_addr = self.physical_echo_ram_address(addr);
+            self.work_ram[physical_addr]
         } else if addr < OAM_END {
             let physical_addr = self.physical_oam_address(addr);
             self.oam[physical_addr]
@@ -1205,10 +3509,23 @@ impl Emulator {
         }
     }
 
-    /// Write a byte to the given virtual address.
+    /// Write a byte to the given virtual address as the CPU.
     ///
-    /// May be mapped to a register or may be mapped to cartridge memory via the MBC.
+    /// May be mapped to a register or may be mapped to cartridge memory via the MBC. Writes to VRAM
+    /// and OAM are ignored while the PPU is using them.
     pub fn write_address(&mut self, addr: Address, value: u8) {
+        self.debugger.check_access(addr, true);
+
+        if self.is_blocked_by_oam_dma(addr) || self.is_blocked_by_ppu(addr) {
+            return;
+        }
+
+        self.write_address_unrestricted(addr, value);
+    }
+
+    /// Write a byte to the given virtual address, ignoring PPU access restrictions. Used by DMA
+    /// and for editing memory.
+    fn write_address_unrestricted(&mut self, addr: Address, value: u8) {
         if addr < ROM_END {
             match self.cartridge.mbc().map_write_rom_address(addr) {
                 // Writes to physical ROM memory are ignored
@@ -1220,7 +3537,10 @@ impl Emulator {
             self.vram[physical_addr] = value;
         } else if addr < EXTERNAL_RAM_END {
             match self.cartridge.mbc().map_write_ram_address(addr) {
-                Location::Address(mapped_addr) => self.cartridge.ram_mut()[mapped_addr] = value,
+                Location::Address(mapped_addr) => {
+                    let mask = self.cartridge.mbc().ram_value_mask();
+                    self.cartridge.ram_mut()[mapped_addr] = value & mask;
+                }
                 Location::Register(reg) => self.cartridge.mbc_mut().write_register(reg, value),
             }
         } else if addr < FIRST_WORK_RAM_BANK_END {
@@ -1230,7 +3550,12 @@ impl Emulator {
             let physical_addr = self.physical_second_work_ram_bank_address(addr);
             self.work_ram[physical_addr] = value;
         } else if addr < ECHO_RAM_END {
-            panic!("Attempted to write to Echo RAM at address {:04X}", addr);
+            if self.options.strict_memory {
+                panic!("Attempted to write to Echo RAM at address {:04X}", addr);
+            }
+
+            let physical_addr = self.physical_echo_ram_address(addr);
+            self.work_ram[physical_addr] = value;
         } else if addr < OAM_END {
             let physical_addr = self.physical_oam_address(addr);
             self.oam[physical_addr] = value;
@@ -1248,22 +3573,63 @@ impl Emulator {
         }
     }
 
-    fn is_bios_addr(&self, addr: Address) -> bool {
-        if self.is_cgb_machine() {
-            // The range from 0x100-0x200 is mapped to cartridge memory, not BIOS
-            addr < CGB_BIOS_END && !(0x100..0x200).contains(&addr)
+    /// Whether the CPU cannot access an address because an OAM DMA transfer is using the bus. Only
+    /// the high page, which holds the IO registers and HRAM, remains accessible during a transfer.
+    fn is_blocked_by_oam_dma(&self, addr: Address) -> bool {
+        self.current_oam_dma_transfer.is_some()
+            && !self.options.no_access_restrictions
+            && addr < IO_REGISTERS_START
+    }
+
+    /// Whether the CPU cannot access an address because the PPU is using it. VRAM is in use while
+    /// drawing, and OAM is in use while scanning OAM and drawing.
+    fn is_blocked_by_ppu(&self, addr: Address) -> bool {
+        if self.options.no_access_restrictions {
+            return false;
+        }
+
+        if (VRAM_START..VRAM_END).contains(&addr) {
+            !self.can_access_vram()
+        } else if (OAM_START..OAM_END).contains(&addr) {
+            !self.can_access_oam()
         } else {
-            addr < DMG_BIOS_END
+            false
         }
     }
 
-    fn physical_bios_address(&self, addr: Address) -> usize {
-        if addr < DMG_BIOS_END {
-            // First 0x100 bytes
-            addr as usize
+    /// Read the byte of the BIOS that is overlaid on cartridge ROM at an address while booting, if
+    /// any. Instruction fetches, interrupt handlers, and RST targets all read through here.
+    ///
+    /// The DMG BIOS is mapped at 0000-00FF. The CGB BIOS is mapped at 0000-00FF and 0200-08FF,
+    /// leaving the cartridge header at 0100-01FF visible.
+    fn read_bios_overlay(&self, addr: Address) -> Option<u8> {
+        if !self.is_booting() {
+            return None;
+        }
+
+        let bios = self.bios.as_ref()?;
+
+        let is_bios_addr = if self.is_cgb_machine() {
+            addr < CGB_BIOS_END && !(CGB_BIOS_HOLE_START..CGB_BIOS_HOLE_END).contains(&addr)
         } else {
-            (addr - 0x100) as usize
+            addr < DMG_BIOS_END
+        };
+
+        if !is_bios_addr {
+            return None;
         }
+
+        // CGB BIOS dumps usually include the unused hole, but some omit it
+        let includes_hole = bios.len() >= CGB_BIOS_END as usize;
+        let physical_addr = if addr < CGB_BIOS_HOLE_START || includes_hole {
+            addr as usize
+        } else {
+            (addr - (CGB_BIOS_HOLE_END - CGB_BIOS_HOLE_START)) as usize
+        };
+
+        // A BIOS that is too small for the machine (e.g. a DMG BIOS on a CGB) falls through to
+        // the cartridge
+        bios.get(physical_addr).copied()
     }
 
     /// Map from a virtual address in VRAM to a physical address in the VRAM array for the given
@@ -1275,7 +3641,7 @@ impl Emulator {
     /// Map from a virtual address in VRAM to a physical address in the VRAM array for the currently
     /// selected bank in VBK.
     fn physical_vram_bank_address(&self, addr: Address) -> usize {
-        let bank_num = if self.in_cgb_mode() {
+        let bank_num = if self.compat_mode().has_cgb_registers() {
             (self.vbk() & 0x01) as usize
         } else {
             0
@@ -1289,7 +3655,7 @@ impl Emulator {
     }
 
     fn second_wram_bank_num(&self) -> usize {
-        if self.in_cgb_mode() {
+        if self.compat_mode().has_cgb_registers() {
             // Cannot access bank 0, instead return bank 1
             (self.wbk() & 0x7).max(1) as usize
         } else {
@@ -1302,6 +3668,16 @@ impl Emulator {
             + SINGLE_WORK_RAM_BANK_SIZE * self.second_wram_bank_num()
     }
 
+    /// Echo RAM mirrors both work RAM banks, including the bank selected by WBK.
+    fn physical_echo_ram_address(&self, addr: Address) -> usize {
+        let work_ram_addr = addr - ECHO_RAM_OFFSET;
+        if work_ram_addr < FIRST_WORK_RAM_BANK_END {
+            self.physical_first_work_ram_bank_address(work_ram_addr)
+        } else {
+            self.physical_second_work_ram_bank_address(work_ram_addr)
+        }
+    }
+
     fn physical_oam_address(&self, addr: Address) -> usize {
         (addr - OAM_START) as usize
     }
@@ -1329,7 +3705,7 @@ impl Emulator {
 
     fn advance_pending_enable_interrupts_state(&mut self) {
         // Pending interrupts state only advances when an instruction finishes
-        if self.ticks_to_next_instruction != 0 {
+        if self.ticks_to_next_instruction != 0 || self.cpu_step_state.is_some() {
             return;
         }
 
@@ -1383,38 +3759,89 @@ impl Emulator {
         self.current_oam_dma_transfer = Some(OamDmaTransfer {
             source_address,
             ticks_remaining: OAM_DMA_TRANSFER_TICKS,
+            num_bytes_copied: 0,
+            current_byte: 0xFF,
         });
     }
 
-    /// Complete an OAM DMA transfer, actually writing all data to OAM.
-    fn complete_oam_dma_transfer(&mut self) {
-        let transfer = self.current_oam_dma_transfer.take().unwrap();
+    /// Copy the bytes of the current OAM DMA transfer into OAM up to (but not including) the given
+    /// index.
+    fn copy_oam_dma_bytes(&mut self, end_index: usize) {
+        let transfer = self.current_oam_dma_transfer.as_ref().unwrap();
         let source_address = transfer.source_address;
-        debug_assert!(transfer.ticks_remaining == 0);
+        let start_index = transfer.num_bytes_copied;
 
-        for i in 0..OAM_SIZE {
-            let byte = self.read_address(source_address.wrapping_add(i as u16));
+        for i in start_index..end_index {
+            let byte = self.read_address_unrestricted(source_address.wrapping_add(i as u16));
             self.oam[i] = byte;
-        }
+
+            let transfer = self.current_oam_dma_transfer.as_mut().unwrap();
+            transfer.num_bytes_copied = i + 1;
+            transfer.current_byte = byte;
+        }
     }
 
-    /// Advance the state of the current OAM DMA transfer each tick, if one is in progress.
+    /// Advance the state of the current OAM DMA transfer each tick, if one is in progress. A single
+    /// byte is copied every machine cycle, so the partially copied OAM is visible mid-transfer.
     fn advance_oam_dma_transfer_state(&mut self) {
+        let cpu_ticks_per_tick = self.cpu_ticks_per_tick();
         if let Some(transfer) = &mut self.current_oam_dma_transfer {
             if transfer.ticks_remaining == 0 {
-                self.complete_oam_dma_transfer();
+                self.copy_oam_dma_bytes(OAM_SIZE);
+                self.current_oam_dma_transfer = None;
                 return;
             }
 
-            let is_double_speed = self.is_double_speed();
-            let transfer = self.current_oam_dma_transfer.as_mut().unwrap();
+            // OAM DMA transfers are clocked by the CPU, so run twice as fast in double speed mode
+            transfer.ticks_remaining = transfer.ticks_remaining.saturating_sub(cpu_ticks_per_tick);
 
-            // OAM DMA transfers run twice as fast in double speed mode
-            if is_double_speed {
-                transfer.ticks_remaining = transfer.ticks_remaining.saturating_sub(2);
-            } else {
-                transfer.ticks_remaining -= 1;
-            }
+            let elapsed_ticks = OAM_DMA_TRANSFER_TICKS - transfer.ticks_remaining;
+            self.copy_oam_dma_bytes(elapsed_ticks / CPU_TICKS_PER_MACHINE_CYCLE);
+        }
+    }
+
+    /// The source address of a VRAM DMA transfer from the values of HDMA1 and HDMA2. The low four
+    /// bits are ignored, and sources in E000-FFFF alias work RAM at C000-DFFF.
+    pub fn vram_dma_source_address(hdma1: u8, hdma2: u8) -> Address {
+        let source = (((hdma1 as u16) << 8) | hdma2 as u16) & 0xFFF0;
+        if source >= SECOND_WORK_RAM_BANK_END {
+            source - (SECOND_WORK_RAM_BANK_END - FIRST_WORK_RAM_BANK_START)
+        } else {
+            source
+        }
+    }
+
+    /// Read a byte from the source of a VRAM DMA transfer. VRAM cannot be a source since it is
+    /// the destination of the transfer, and these reads return unreliable data on hardware.
+    fn read_vram_dma_source(&self, address: Address) -> u8 {
+        if (VRAM_START..VRAM_END).contains(&address) {
+            return 0xFF;
+        }
+
+        self.read_address_unrestricted(address)
+    }
+
+    /// Count a write to a register that only works on a CGB. Games that keep writing to them on a
+    /// DMG most likely require a CGB, which is reported once.
+    pub fn report_cgb_only_register_write(&mut self) {
+        if self.is_cgb_machine() || !self.cgb_only_detector.register_written() {
+            return;
+        }
+
+        eprintln!(
+            "This game appears to require Game Boy Color \u{2014} rendering will be incorrect"
+        );
+        self.send_event(EmulatorEvent::CgbRequired);
+    }
+
+    /// Log a diagnostic the first time a VRAM DMA transfer is started with a source in VRAM. This
+    /// is usually a bug in the game.
+    pub fn report_vram_dma_source_in_vram(&mut self, source: Address) {
+        if !mem::replace(&mut self.has_reported_vram_dma_source_in_vram, true) {
+            eprintln!(
+                "VRAM DMA source {:04X} is in VRAM, which transfers unreliable data (0xFF)",
+                source
+            );
         }
     }
 
@@ -1431,8 +3858,8 @@ impl Emulator {
 
         // This means it is not observable so we can perform the entire transfer at once.
         for i in 0..((num_blocks as u16) * VRAM_DMA_TRANSFER_BLOCK_SIZE) {
-            let byte = self.read_address(source_address.wrapping_add(i));
-            self.write_address(dest_address.wrapping_add(i), byte);
+            let byte = self.read_vram_dma_source(source_address.wrapping_add(i));
+            self.write_address_unrestricted(dest_address.wrapping_add(i), byte);
         }
     }
 
@@ -1494,8 +3921,8 @@ impl Emulator {
 
         // Perform a single block transfer
         for i in 0..VRAM_DMA_TRANSFER_BLOCK_SIZE {
-            let byte = self.read_address(source_block_start + i);
-            self.write_address(dest_block_start + i, byte);
+            let byte = self.read_vram_dma_source(source_block_start + i);
+            self.write_address_unrestricted(dest_block_start + i, byte);
         }
 
         // Update state to reflect completed block
@@ -1556,33 +3983,26 @@ impl Emulator {
     }
 
     fn increment_timers(&mut self) {
-        // Divider register is incremented every tick but only top byte is exposed via DIV register
+        self.advance_tima_overflow();
+
+        // Divider register is incremented every CPU tick but only top byte is exposed via DIV
+        // register. It is clocked by the CPU, so increments twice as fast in double speed mode.
         let old_divider = self.full_divider_register;
-        let div_apu_falling_edge_mask;
+        let cpu_ticks_per_tick = self.cpu_ticks_per_tick();
+        self.full_divider_register = self
+            .full_divider_register
+            .wrapping_add(cpu_ticks_per_tick as u16);
 
-        // Divider register increments twice as fast in double speed mode
-        if self.is_double_speed() {
-            self.full_divider_register = self.full_divider_register.wrapping_add(2);
-            div_apu_falling_edge_mask = 0x2000;
-        } else {
-            self.full_divider_register = self.full_divider_register.wrapping_add(1);
-            div_apu_falling_edge_mask = 0x1000;
-        }
+        // DIV-APU is clocked by a higher bit in double speed mode, so that the APU still advances at
+        // the same rate
+        let div_apu_falling_edge_mask = 0x1000 * cpu_ticks_per_tick as u16;
 
         let falling_edges = old_divider & !self.full_divider_register;
 
         // Increment timer if there was falling edge on the TAC-selected bit of the divider register
         let has_tac_falling_edge = (falling_edges & self.tac_mask) != 0;
         if has_tac_falling_edge && self.is_timer_enabled {
-            let (new_tima, overflowed) = self.tima().overflowing_add(1);
-
-            // Reset to TMA and generate an interrupt when timer overflows
-            if overflowed {
-                self.write_tima(self.tma());
-                self.request_interrupt(Interrupt::Timer);
-            } else {
-                self.write_tima(new_tima);
-            }
+            self.increment_tima();
         }
 
         // Increment APU divider if there was a falling edge on the appropriate bit
@@ -1590,6 +4010,118 @@ impl Emulator {
         if has_div_apu_falling_edge {
             self.apu_mut().advance_div_apu();
         }
+
+        if self.serial_transfer_bits_remaining != 0 {
+            self.advance_serial_transfer(falling_edges);
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let (new_tima, overflowed) = self.tima().overflowing_add(1);
+        self.write_tima_raw(new_tima);
+
+        // TIMA is reloaded from TMA and an interrupt is requested a machine cycle later
+        if overflowed {
+            self.tima_overflow = TimaOverflow::Pending {
+                ticks_remaining: self.ticks_per_machine_cycle(),
+            };
+        }
+    }
+
+    fn advance_tima_overflow(&mut self) {
+        self.tima_overflow = match self.tima_overflow {
+            TimaOverflow::None => TimaOverflow::None,
+            TimaOverflow::Pending { ticks_remaining: 1 } => {
+                // Reset to TMA and generate an interrupt
+                self.write_tima_raw(self.tma());
+                self.request_interrupt(Interrupt::Timer);
+
+                TimaOverflow::Reloading {
+                    ticks_remaining: self.ticks_per_machine_cycle(),
+                }
+            }
+            TimaOverflow::Pending { ticks_remaining } => TimaOverflow::Pending {
+                ticks_remaining: ticks_remaining - 1,
+            },
+            TimaOverflow::Reloading { ticks_remaining: 1 } => TimaOverflow::None,
+            TimaOverflow::Reloading { ticks_remaining } => TimaOverflow::Reloading {
+                ticks_remaining: ticks_remaining - 1,
+            },
+        };
+    }
+
+    /// Write TIMA, which cancels a pending reload from TMA but is ignored while reloading.
+    pub(crate) fn write_tima_during_overflow(&mut self, value: Register) {
+        match self.tima_overflow {
+            TimaOverflow::None => self.write_tima_raw(value),
+            TimaOverflow::Pending { .. } => {
+                self.write_tima_raw(value);
+                self.tima_overflow = TimaOverflow::None;
+            }
+            TimaOverflow::Reloading { .. } => {}
+        }
+    }
+
+    /// Write TMA, which is also written to TIMA if TIMA is being reloaded from it.
+    pub(crate) fn write_tma_during_overflow(&mut self, value: Register) {
+        self.write_tma_raw(value);
+
+        if let TimaOverflow::Reloading { .. } = self.tima_overflow {
+            self.write_tima_raw(value);
+        }
+    }
+
+    /// Start a serial transfer of the byte in SB using the internal clock.
+    pub fn start_serial_transfer(&mut self) {
+        self.serial_transfer_bits_remaining = SERIAL_TRANSFER_BITS;
+
+        let sb = self.sb();
+        if let Some(serial_output) = &mut self.serial_output {
+            serial_output.push(sb);
+        }
+
+        if self.options.serial_log {
+            print!("{}", self.sb() as char);
+            let _ = io::stdout().flush();
+        }
+    }
+
+    pub fn cancel_serial_transfer(&mut self) {
+        self.serial_transfer_bits_remaining = 0;
+    }
+
+    /// Bytes sent over the serial port so far, or an empty slice if serial output is not being
+    /// captured.
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial_output.as_deref().unwrap_or_default()
+    }
+
+    pub fn is_serial_transfer_in_progress(&self) -> bool {
+        self.serial_transfer_bits_remaining != 0
+    }
+
+    /// Shift one bit of the serial transfer on each falling edge of the serial clock. There is
+    /// never a link partner, so 1s are shifted in.
+    fn advance_serial_transfer(&mut self, falling_edges: u16) {
+        let uses_fast_clock = self.compat_mode().has_cgb_registers() && (self.sc() & 0x02) != 0;
+        let clock_mask = if uses_fast_clock {
+            SERIAL_FAST_CLOCK_MASK
+        } else {
+            SERIAL_CLOCK_MASK
+        };
+
+        if (falling_edges & clock_mask) == 0 {
+            return;
+        }
+
+        self.write_sb((self.sb() << 1) | 0x01);
+        self.serial_transfer_bits_remaining -= 1;
+
+        // Transfer is complete, so clear the transfer enable bit
+        if self.serial_transfer_bits_remaining == 0 {
+            self.write_sc_raw(self.sc() & 0x7F);
+            self.request_interrupt(Interrupt::Serial);
+        }
     }
 
     /// Most initialization is emulated statically by setting the initial state. Perform any dynamic
@@ -1601,8 +4133,7 @@ impl Emulator {
             } else {
                 self.write_key0(0x04);
                 self.write_opri(0x01);
-
-                // TODO: Write compatibility palette
+                self.write_compat_palettes();
             }
         }
 
@@ -1613,6 +4144,28 @@ impl Emulator {
         self.regs.set_pc(0x0100);
     }
 
+    /// Write the palettes that the CGB boot ROM picks for a DMG game.
+    fn write_compat_palettes(&mut self) {
+        let palettes = compat_palettes_for_rom(self.cartridge().rom());
+
+        let palette_writes = [
+            (false, 0, palettes.background),
+            (true, 0, palettes.object0),
+            (true, 1, palettes.object1),
+        ];
+
+        for (is_object, palette_number, palette) in palette_writes {
+            for (color_index, color) in palette.into_iter().enumerate() {
+                self.write_cgb_palette_color(
+                    is_object,
+                    palette_number,
+                    color_index,
+                    CgbColor::new(color),
+                );
+            }
+        }
+    }
+
     /// Sample the current audio channels and push to the current frame builder
     fn push_next_sample(&mut self) {
         let (left, right) = self.apu().sample_audio();
@@ -1625,11 +4178,24 @@ impl Emulator {
         });
     }
 
+    /// Send the drawn frame to the video sink, if any
+    fn flush_video_frame(&mut self) {
+        if self.is_skipping_render {
+            return;
+        }
+
+        if let Some(video_sink) = &mut self.video_sink {
+            video_sink.send_frame(&self.screen_colors);
+        }
+    }
+
     /// Flush the current audio frame to the audio output, if any
     fn flush_audio_frame(&mut self) {
         if let Some(audio_output) = &mut self.audio_output {
-            let mut audio_frame = Vec::new();
-            mem::swap(&mut audio_frame, &mut self.current_audio_frame);
+            let empty_frame = audio_output
+                .take_empty_frame()
+                .unwrap_or_else(|| AudioFrame::with_capacity(MAX_SAMPLES_PER_AUDIO_FRAME));
+            let audio_frame = mem::replace(&mut self.current_audio_frame, empty_frame);
             audio_output.send_frame(audio_frame);
         } else {
             self.current_audio_frame.clear();
@@ -1637,9 +4203,2808 @@ impl Emulator {
     }
 }
 
+fn default_draw_ticks() -> usize {
+    MIN_DRAW_TICKS
+}
+
 /// Convert a duration to nanoseconds, assuming it fits in u64.
 pub fn duration_to_nanos(duration: Duration) -> u64 {
     let seconds = duration.as_secs();
     let subsec_nanos = duration.subsec_nanos() as u64;
     seconds * 1_000_000_000 + subsec_nanos
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs, mem,
+        path::{Path, PathBuf},
+        process,
+        sync::{
+            Arc,
+            mpsc::{Receiver, Sender, channel},
+        },
+        time::UNIX_EPOCH,
+    };
+
+    use eframe::egui::Color32;
+
+    use crate::{
+        address_space::{OAM_SIZE, WAVE_RAM_END, WAVE_RAM_START},
+        cartridge::Cartridge,
+        determinism::EmulatorRng,
+        diagnostics::CGB_ONLY_REGISTER_WRITE_THRESHOLD,
+        machine::Machine,
+        options::Options,
+        ppu::Color,
+        save_file::{SAVE_FILE_AUTO_FLUSH_INTERVAL_SECS, StateFile},
+        screen_palette::ScreenColorPalette,
+        state::CpuState,
+        symbols::BankedAddress,
+        test_utils::{
+            FILL_VRAM_PROGRAM, PROGRAM_START, build_bad_logo_test_rom, build_cgb_test_rom,
+            build_test_rom, new_test_emulator, new_test_emulator_builder, with_large_stack,
+        },
+    };
+
+    use super::{
+        Button, COMMANDS_CHANNEL_CAPACITY, Command, CommandError, CpuLock, DEFAULT_TURBO_SPEED,
+        Emulator, EmulatorBuilder, EmulatorEvent, Interrupt, Mode, NORMAL_SPEED, SCREEN_HEIGHT,
+        SCREEN_WIDTH, STOP_WAKE_TICKS, SharedInputAdapter, TAC_MASK_16_TICKS, TAC_MASK_64_TICKS,
+        TAC_MASK_256_TICKS, TAC_MASK_1024_TICKS, TICKS_PER_FRAME, TICKS_PER_SCANLINE,
+    };
+
+    #[rustfmt::skip]
+    const STOP_PROGRAM: [u8; 9] = [
+        0x3E, 0x20, // ld a, 0x20 (select the d-pad group)
+        0xE0, 0x00, // ldh [0xFF00], a
+        0x10, 0x00, // stop
+        0x04,       // inc b
+        0x18, 0xFE, // jr -2
+    ];
+
+    /// Address of the instruction following STOP in the test program
+    const AFTER_STOP_ADDRESS: u16 = 0x0156;
+
+    #[test]
+    fn joypad_wakes_from_stop() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &STOP_PROGRAM);
+
+            while !emulator.is_cpu_stopped() {
+                emulator.run_tick();
+            }
+
+            let b = emulator.cpu_state().b;
+            let ppu_state = emulator.ppu_state();
+            let divider = emulator.full_divider_register();
+
+            // PPU and timers are frozen while stopped
+            for _ in 0..10000 {
+                emulator.run_tick();
+            }
+
+            assert!(emulator.is_cpu_stopped());
+            assert_eq!(emulator.ppu_state(), ppu_state);
+            assert_eq!(emulator.full_divider_register(), divider);
+            assert_eq!(divider, 0);
+            assert_eq!(emulator.cpu_state().pc, AFTER_STOP_ADDRESS);
+
+            // Pressing a button in the selected group resumes execution after the wake delay
+            emulator.handle_update_pressed_buttons(Button::Down as u8);
+            assert!(!emulator.is_cpu_stopped());
+
+            let mut num_ticks = 0;
+            while num_ticks < STOP_WAKE_TICKS {
+                num_ticks += emulator.run_tick();
+                assert_eq!(emulator.cpu_state().b, b);
+            }
+            assert_eq!(num_ticks, STOP_WAKE_TICKS);
+
+            emulator.run_tick();
+            assert_eq!(emulator.cpu_state().b, b.wrapping_add(1));
+            assert_ne!(emulator.ppu_state(), ppu_state);
+        });
+    }
+
+    #[test]
+    fn unselected_button_does_not_wake_from_stop() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &STOP_PROGRAM);
+
+            while !emulator.is_cpu_stopped() {
+                emulator.run_tick();
+            }
+
+            emulator.handle_update_pressed_buttons(Button::A as u8);
+            assert!(emulator.is_cpu_stopped());
+        });
+    }
+
+    /// Fresh directory for a test. Contains a regular file named `not_a_directory`, so any path
+    /// inside it can never be written.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("gbcemu-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("not_a_directory"), []).unwrap();
+        dir
+    }
+
+    fn unwritable_save_file_path(dir: &Path) -> String {
+        let path = dir.join("not_a_directory").join("game.svgb");
+        path.to_str().unwrap().to_string()
+    }
+
+    fn new_saving_emulator(
+        save_file_path: String,
+        options: Options,
+    ) -> (Emulator, Receiver<EmulatorEvent>) {
+        let (_, commands_rx) = channel();
+        let (events_tx, events_rx) = channel();
+
+        let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+        let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+            .with_options(Arc::new(options))
+            .with_save_file_path(save_file_path)
+            .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+
+        (emulator, events_rx)
+    }
+
+    #[test]
+    fn save_file_write_failure_is_not_fatal() {
+        with_large_stack(|| {
+            let dir = test_dir("save-failure");
+            let (mut emulator, events_rx) =
+                new_saving_emulator(unwritable_save_file_path(&dir), Options::default());
+
+            assert!(matches!(
+                emulator.save_cartridge_state_to_disk(),
+                Err(CommandError::Io(_))
+            ));
+            emulator.run_frame();
+            assert!(emulator.quick_save(0).is_err());
+            assert!(emulator.save_cartridge_state_to_disk().is_err());
+            emulator.run_frame();
+
+            // Only the first failure is reported
+            let events: Vec<_> = events_rx.try_iter().collect();
+            assert_eq!(events.len(), 1);
+            assert!(matches!(
+                &events[0],
+                EmulatorEvent::SaveFileWriteFailed {
+                    fallback_path: None,
+                    ..
+                }
+            ));
+
+            // Retries back off
+            assert!(
+                emulator.save_file_flush_state.flush_interval_secs()
+                    > SAVE_FILE_AUTO_FLUSH_INTERVAL_SECS
+            );
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn save_file_written_to_fallback_dir() {
+        with_large_stack(|| {
+            let dir = test_dir("save-fallback");
+            let fallback_dir = dir.join("fallback");
+            let options = Options {
+                save_fallback_dir: Some(fallback_dir.clone()),
+                ..Options::default()
+            };
+            let (mut emulator, events_rx) =
+                new_saving_emulator(unwritable_save_file_path(&dir), options);
+
+            assert_eq!(emulator.save_cartridge_state_to_disk(), Ok(()));
+            assert_eq!(emulator.quick_save(0), Ok(()));
+
+            let fallback_path = fallback_dir.join("game.svgb");
+            let events: Vec<_> = events_rx.try_iter().collect();
+            assert_eq!(events.len(), 1);
+            assert!(matches!(
+                &events[0],
+                EmulatorEvent::SaveFileWriteFailed {
+                    fallback_path: Some(path),
+                    ..
+                } if Path::new(path) == fallback_path
+            ));
+
+            // Later saves go directly to the fallback location
+            assert!(fallback_path.exists());
+            assert_eq!(
+                emulator.save_file_flush_state.flush_interval_secs(),
+                SAVE_FILE_AUTO_FLUSH_INTERVAL_SECS
+            );
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn raw_save_file_written_to_fallback_dir() {
+        with_large_stack(|| {
+            let dir = test_dir("raw-save-fallback");
+            let fallback_dir = dir.join("fallback");
+            let options = Options {
+                save_fallback_dir: Some(fallback_dir.clone()),
+                ..Options::default()
+            };
+            let raw_save_file_path = dir.join("not_a_directory").join("game.sav");
+
+            // MBC1+RAM+BATTERY
+            let rom = build_test_rom(0x03, 0x01, 0x02, &FILL_VRAM_PROGRAM);
+            let (_, commands_rx) = channel();
+            let (events_tx, events_rx) = channel();
+            let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                .with_options(Arc::new(options))
+                .with_save_file_path(unwritable_save_file_path(&dir))
+                .with_raw_save_file_path(raw_save_file_path.to_str().unwrap().to_string())
+                .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+                .build()
+                .unwrap();
+            emulator.emulate_boot_sequence();
+
+            assert_eq!(emulator.save_cartridge_state_to_disk(), Ok(()));
+            assert_eq!(emulator.save_cartridge_state_to_disk(), Ok(()));
+
+            // Both files moved, with a single notification
+            let events: Vec<_> = events_rx.try_iter().collect();
+            assert_eq!(events.len(), 1);
+            assert!(matches!(
+                &events[0],
+                EmulatorEvent::SaveFileWriteFailed {
+                    fallback_path: Some(_),
+                    ..
+                }
+            ));
+
+            let fallback_raw_save_file_path = fallback_dir.join("game.sav");
+            assert!(fallback_dir.join("game.svgb").exists());
+            assert!(fallback_raw_save_file_path.exists());
+            assert_eq!(
+                emulator.raw_save_file_path.as_deref().map(Path::new),
+                Some(fallback_raw_save_file_path.as_path())
+            );
+            assert_eq!(
+                emulator.save_file_flush_state.flush_interval_secs(),
+                SAVE_FILE_AUTO_FLUSH_INTERVAL_SECS
+            );
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    /// Emulator with a save file and a commands channel. The save file has no path so it is never
+    /// written to disk.
+    fn new_commanded_emulator() -> (Emulator, Sender<Command>, Receiver<EmulatorEvent>) {
+        let (commands_tx, commands_rx) = channel();
+        let (events_tx, events_rx) = channel();
+
+        let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+        let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+            .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+
+        (emulator, commands_tx, events_rx)
+    }
+
+    /// Writes to VBK selecting bank 1, BCPD, and HDMA5 in turn.
+    fn write_cgb_only_registers(emulator: &mut Emulator, num_writes: u32) {
+        for i in 0..num_writes {
+            match i % 3 {
+                0 => emulator.write_address(0xFF4F, 0x01),
+                1 => emulator.write_address(0xFF69, 0x1F),
+                _ => emulator.write_address(0xFF55, 0x00),
+            }
+        }
+    }
+
+    #[test]
+    fn cgb_only_register_writes_on_dmg_are_reported_once() {
+        with_large_stack(|| {
+            let (mut emulator, _commands_tx, events_rx) = new_commanded_emulator();
+
+            // Selecting VRAM bank 0 does not count
+            for _ in 0..CGB_ONLY_REGISTER_WRITE_THRESHOLD {
+                emulator.write_address(0xFF4F, 0x00);
+            }
+
+            write_cgb_only_registers(&mut emulator, CGB_ONLY_REGISTER_WRITE_THRESHOLD - 1);
+            assert_eq!(events_rx.try_iter().count(), 0);
+
+            write_cgb_only_registers(&mut emulator, CGB_ONLY_REGISTER_WRITE_THRESHOLD * 2);
+            assert_eq!(
+                events_rx.try_iter().collect::<Vec<_>>(),
+                vec![EmulatorEvent::CgbRequired]
+            );
+
+            // Never reported on a CGB, even in DMG compatibility mode
+            let (_commands_tx, commands_rx) = channel();
+            let (events_tx, events_rx) = channel();
+            let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+            let mut emulator = new_test_emulator_builder(Machine::Cgb, rom)
+                .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+                .build()
+                .unwrap();
+            emulator.emulate_boot_sequence();
+
+            write_cgb_only_registers(&mut emulator, CGB_ONLY_REGISTER_WRITE_THRESHOLD * 2);
+            assert_eq!(events_rx.try_iter().count(), 0);
+        });
+    }
+
+    #[test]
+    fn step_instruction_runs_one_instruction() {
+        with_large_stack(|| {
+            // ld a, 1 (8 ticks), then nop (4 ticks) forever
+            let mut emulator = new_test_emulator(Machine::Dmg, &[0x3E, 0x01, 0x00, 0x18, 0xFD]);
+
+            // nop, then jp 0x0150 at the entry point
+            assert_eq!(emulator.regs().pc(), 0x0100);
+            assert_eq!(emulator.step_instruction(), 4);
+            assert_eq!(emulator.regs().pc(), 0x0101);
+            assert_eq!(emulator.step_instruction(), 16);
+            assert_eq!(emulator.regs().pc(), 0x0150);
+
+            assert_eq!(emulator.step_instruction(), 8);
+            assert_eq!(emulator.regs().pc(), 0x0152);
+
+            // Runs at least the given number of ticks
+            let num_ticks = emulator.run_ticks(10);
+            assert!(num_ticks >= 10);
+        });
+    }
+
+    #[test]
+    fn backed_up_commands_are_coalesced() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, _events_rx) = new_commanded_emulator();
+
+            // Commands pile up while the emulator is stalled
+            let mut seed: u32 = 1;
+            let mut last_buttons = 0;
+            let mut last_speed = NORMAL_SPEED;
+            for _ in 0..10_000 {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                let command = if seed & 0x8000_0000 == 0 {
+                    last_buttons = (seed >> 16) as u8;
+                    Command::UpdatePressedButtons(last_buttons)
+                } else {
+                    let in_turbo_mode = seed & 0x0100_0000 != 0;
+                    last_speed = if in_turbo_mode {
+                        DEFAULT_TURBO_SPEED
+                    } else {
+                        NORMAL_SPEED
+                    };
+                    Command::SetTurboMode(in_turbo_mode)
+                };
+                commands_tx.send(command).unwrap();
+            }
+
+            // Each batch received holds at most one button update
+            let input_adapter = emulator.input_adapter.as_mut().unwrap();
+            let first_command = input_adapter.next_command().unwrap();
+            let num_button_updates = input_adapter
+                .pending_commands
+                .iter()
+                .chain([&first_command])
+                .filter(|command| matches!(command, Command::UpdatePressedButtons(_)))
+                .count();
+            assert_eq!(num_button_updates, 1);
+            assert!(input_adapter.pending_commands.len() < COMMANDS_CHANNEL_CAPACITY);
+
+            // Ends up in the same state as applying every command in order
+            emulator.handle_command(first_command);
+            emulator.handle_commands();
+            assert_eq!(emulator.pressed_buttons(), last_buttons);
+            assert_eq!(emulator.speed, last_speed);
+            assert!(
+                emulator
+                    .input_adapter
+                    .as_ref()
+                    .unwrap()
+                    .pending_commands
+                    .is_empty()
+            );
+        });
+    }
+
+    #[test]
+    fn button_updates_are_not_coalesced_across_frame_advance() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, _events_rx) = new_commanded_emulator();
+            emulator.is_paused = true;
+
+            // The button is held for the advanced frame, then released
+            commands_tx
+                .send(Command::UpdatePressedButtons(0x01))
+                .unwrap();
+            commands_tx.send(Command::FrameAdvance).unwrap();
+            commands_tx
+                .send(Command::UpdatePressedButtons(0x00))
+                .unwrap();
+
+            emulator.handle_commands();
+            assert_eq!(emulator.pressed_buttons(), 0x01);
+            assert!(emulator.frame_advance_ticks_remaining.is_some());
+
+            let pending_commands = &emulator.input_adapter.as_ref().unwrap().pending_commands;
+            assert!(matches!(
+                pending_commands.iter().collect::<Vec<_>>().as_slice(),
+                [Command::UpdatePressedButtons(0x00)]
+            ));
+        });
+    }
+
+    #[test]
+    fn screen_palette_switches_at_frame_boundary() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, _events_rx) = new_commanded_emulator();
+            for _ in 0..TICKS_PER_FRAME / 2 {
+                emulator.run_tick();
+            }
+
+            commands_tx
+                .send(Command::SetScreenPalette(ScreenColorPalette::Green))
+                .unwrap();
+            emulator.handle_commands();
+            assert_eq!(emulator.screen_palette(), ScreenColorPalette::Grayscale);
+
+            emulator.run_frame();
+            assert_eq!(emulator.screen_palette(), ScreenColorPalette::Green);
+
+            // Applied immediately while paused
+            emulator.is_paused = true;
+            commands_tx
+                .send(Command::SetScreenPalette(ScreenColorPalette::Grayscale))
+                .unwrap();
+            emulator.handle_commands();
+            assert_eq!(emulator.screen_palette(), ScreenColorPalette::Grayscale);
+        });
+    }
+
+    #[test]
+    fn copy_screen_colors_is_row_major() {
+        with_large_stack(|| {
+            let (mut emulator, _commands_tx, _events_rx) = new_commanded_emulator();
+            emulator.write_pixel(1, 0, Color::Dmg(3));
+            emulator.write_pixel(0, 1, Color::Dmg(1));
+
+            let mut colors = vec![Color32::TRANSPARENT; SCREEN_WIDTH * SCREEN_HEIGHT];
+            emulator.copy_screen_colors(&mut colors);
+
+            let palette = emulator.screen_palette();
+            assert_eq!(colors[0], palette.color_to_color32(Color::Dmg(0)));
+            assert_eq!(colors[1], palette.color_to_color32(Color::Dmg(3)));
+            assert_eq!(
+                colors[SCREEN_WIDTH],
+                palette.color_to_color32(Color::Dmg(1))
+            );
+        });
+    }
+
+    #[test]
+    fn screen_colors_follow_screen_palette() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, _events_rx) = new_commanded_emulator();
+            emulator.run_frames(5);
+
+            let expected_colors = |emulator: &Emulator, palette: ScreenColorPalette| {
+                screen_pixels(emulator)
+                    .into_iter()
+                    .map(|color| palette.color_to_color32(color))
+                    .collect::<Vec<_>>()
+            };
+            let mut colors = vec![Color32::TRANSPARENT; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+            emulator.copy_screen_colors(&mut colors);
+            assert_eq!(
+                colors,
+                expected_colors(&emulator, ScreenColorPalette::Grayscale)
+            );
+
+            // The paused frame is recolored immediately
+            emulator.is_paused = true;
+            commands_tx
+                .send(Command::SetScreenPalette(ScreenColorPalette::Green))
+                .unwrap();
+            emulator.handle_commands();
+            emulator.copy_screen_colors(&mut colors);
+            assert_eq!(
+                colors,
+                expected_colors(&emulator, ScreenColorPalette::Green)
+            );
+
+            // Later frames are drawn with the new palette
+            emulator.is_paused = false;
+            emulator.run_frame();
+            emulator.copy_screen_colors(&mut colors);
+            assert_eq!(
+                colors,
+                expected_colors(&emulator, ScreenColorPalette::Green)
+            );
+        });
+    }
+
+    #[test]
+    fn shutdown_while_paused_stops_run() {
+        with_large_stack(|| {
+            let dir = test_dir("shutdown");
+            let save_file_path = dir.join("game.svgb");
+
+            let (commands_tx, commands_rx) = channel();
+            let (events_tx, _events_rx) = channel();
+
+            let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+            let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                .with_save_file_path(save_file_path.to_str().unwrap().to_string())
+                .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+                .build()
+                .unwrap();
+
+            // Shutting down while paused both resumes and stops the emulator
+            commands_tx.send(Command::TogglePause).unwrap();
+            commands_tx.send(Command::Shutdown).unwrap();
+            commands_tx.send(Command::TogglePause).unwrap();
+            emulator.run();
+
+            assert!(!emulator.is_paused());
+            assert!(save_file_path.exists());
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn load_empty_quick_save_slot_is_not_found() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, events_rx) = new_commanded_emulator();
+
+            commands_tx.send(Command::LoadQuickSave(1, 7)).unwrap();
+            emulator.handle_commands();
+
+            let events: Vec<_> = events_rx.try_iter().collect();
+            assert!(matches!(
+                events.as_slice(),
+                [EmulatorEvent::CommandResult {
+                    command_id: 7,
+                    result: Err(CommandError::NotFound(_)),
+                }]
+            ));
+        });
+    }
+
+    #[test]
+    fn quick_save_then_load_is_acknowledged() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, events_rx) = new_commanded_emulator();
+
+            commands_tx.send(Command::QuickSave(0, 1)).unwrap();
+            commands_tx.send(Command::LoadQuickSave(0, 2)).unwrap();
+            emulator.handle_commands();
+
+            let events: Vec<_> = events_rx.try_iter().collect();
+            assert_eq!(
+                events,
+                vec![
+                    EmulatorEvent::CommandResult {
+                        command_id: 1,
+                        result: Ok(()),
+                    },
+                    EmulatorEvent::CommandResult {
+                        command_id: 2,
+                        result: Ok(()),
+                    },
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn toggle_hpf_changes_emitted_samples() {
+        with_large_stack(|| {
+            let (mut filtered, _commands_tx, _events_rx) = new_commanded_emulator();
+            let (mut unfiltered, commands_tx, _events_rx) = new_commanded_emulator();
+
+            commands_tx.send(Command::ToggleHpf).unwrap();
+            unfiltered.handle_commands();
+
+            // Turning on channel 1's DAC without triggering the channel outputs a DC offset, which
+            // the filter removes
+            for emulator in [&mut filtered, &mut unfiltered] {
+                emulator.write_address(0xFF26, 0x80);
+                emulator.write_address(0xFF24, 0x77);
+                emulator.write_address(0xFF25, 0x11);
+                emulator.write_address(0xFF12, 0xF0);
+            }
+
+            for _ in 0..TICKS_PER_FRAME / 2 {
+                filtered.run_tick();
+                unfiltered.run_tick();
+            }
+
+            let filtered_sample = filtered.current_audio_frame.last().unwrap().left;
+            let unfiltered_sample = unfiltered.current_audio_frame.last().unwrap().left;
+            assert!(unfiltered_sample > 0.0);
+            assert!(filtered_sample.abs() < unfiltered_sample / 2.0);
+        });
+    }
+
+    #[test]
+    fn quick_save_preserves_wave_ram() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, _events_rx) = new_commanded_emulator();
+            for (i, address) in (WAVE_RAM_START..WAVE_RAM_END).enumerate() {
+                emulator.write_address(address, i as u8 * 0x11);
+            }
+
+            commands_tx.send(Command::QuickSave(0, 1)).unwrap();
+            emulator.handle_commands();
+
+            emulator.write_address(WAVE_RAM_START, 0xAB);
+
+            commands_tx.send(Command::LoadQuickSave(0, 2)).unwrap();
+            emulator.handle_commands();
+
+            for (i, address) in (WAVE_RAM_START..WAVE_RAM_END).enumerate() {
+                assert_eq!(emulator.read_address(address), i as u8 * 0x11);
+            }
+        });
+    }
+
+    #[test]
+    fn quick_save_without_save_file_fails() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, events_rx) = new_commanded_emulator();
+            emulator.save_file = None;
+
+            commands_tx.send(Command::QuickSave(0, 3)).unwrap();
+            emulator.handle_commands();
+
+            assert_eq!(
+                events_rx.try_recv(),
+                Ok(EmulatorEvent::CommandResult {
+                    command_id: 3,
+                    result: Err(CommandError::NoSaveFile),
+                })
+            );
+        });
+    }
+
+    #[test]
+    fn export_then_import_state_restores_emulator() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, events_rx) = new_commanded_emulator();
+            let path = test_dir("export-state").join("game.state");
+
+            for _ in 0..10 {
+                emulator.run_frame();
+            }
+
+            commands_tx
+                .send(Command::ExportState(path.clone(), 1))
+                .unwrap();
+            emulator.handle_commands();
+
+            let cpu_state = emulator.cpu_state();
+            let ppu_state = emulator.ppu_state();
+            let frame = screen_pixels(&emulator);
+
+            for _ in 0..(TICKS_PER_FRAME / 3) {
+                emulator.run_tick();
+            }
+            assert_ne!(emulator.ppu_state(), ppu_state);
+
+            // Importing does not require a save file, and does not create one
+            emulator.save_file = None;
+
+            commands_tx.send(Command::ImportState(path, 2)).unwrap();
+            emulator.handle_commands();
+
+            let events: Vec<_> = events_rx.try_iter().collect();
+            assert_eq!(
+                events,
+                vec![
+                    EmulatorEvent::CommandResult {
+                        command_id: 1,
+                        result: Ok(()),
+                    },
+                    EmulatorEvent::CommandResult {
+                        command_id: 2,
+                        result: Ok(()),
+                    },
+                ]
+            );
+
+            assert_eq!(emulator.cpu_state(), cpu_state);
+            assert_eq!(emulator.ppu_state(), ppu_state);
+            assert!(screen_pixels(&emulator) == frame);
+            assert!(emulator.save_file.is_none());
+        });
+    }
+
+    #[test]
+    fn import_state_for_different_rom_is_rejected() {
+        with_large_stack(|| {
+            let dir = test_dir("import-state");
+            let path = dir.join("other.state");
+
+            let rom = build_test_rom(0x00, 0x00, 0x00, &[0x18, 0xFE]);
+            let mut other_emulator = EmulatorBuilder::new_cartridge(
+                Cartridge::new_from_rom_bytes(rom).unwrap(),
+                Machine::Dmg,
+            )
+            .build()
+            .unwrap();
+            other_emulator.emulate_boot_sequence();
+            other_emulator.export_state(&path).unwrap();
+
+            let (mut emulator, commands_tx, events_rx) = new_commanded_emulator();
+            emulator.run_frame();
+            let cpu_state = emulator.cpu_state();
+
+            commands_tx.send(Command::ImportState(path, 1)).unwrap();
+            emulator.handle_commands();
+
+            assert!(matches!(
+                events_rx.try_recv(),
+                Ok(EmulatorEvent::CommandResult {
+                    command_id: 1,
+                    result: Err(CommandError::InvalidData(_)),
+                })
+            ));
+
+            // Files that are not state files are rejected as well
+            let path = dir.join("not_a_state.state");
+            fs::write(&path, b"not a state file").unwrap();
+            commands_tx.send(Command::ImportState(path, 2)).unwrap();
+            emulator.handle_commands();
+
+            assert!(matches!(
+                events_rx.try_recv(),
+                Ok(EmulatorEvent::CommandResult {
+                    command_id: 2,
+                    result: Err(CommandError::InvalidData(_)),
+                })
+            ));
+
+            // The emulator keeps running with its own state
+            assert_eq!(emulator.cpu_state(), cpu_state);
+            assert!(emulator.save_file.is_some());
+            emulator.run_frame();
+        });
+    }
+
+    #[test]
+    fn import_state_from_other_machine_switches_machine() {
+        with_large_stack(|| {
+            let dir = test_dir("import-state-machine");
+            let path = dir.join("cgb.state");
+
+            let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+            let mut cgb_emulator = EmulatorBuilder::new_cartridge(
+                Cartridge::new_from_rom_bytes(rom).unwrap(),
+                Machine::Cgb,
+            )
+            .build()
+            .unwrap();
+            cgb_emulator.emulate_boot_sequence();
+            cgb_emulator.run_frame();
+            cgb_emulator.export_state(&path).unwrap();
+
+            let (mut emulator, commands_tx, events_rx) = new_commanded_emulator();
+            commands_tx.send(Command::ImportState(path, 1)).unwrap();
+            emulator.handle_commands();
+
+            assert!(matches!(
+                events_rx.try_recv(),
+                Ok(EmulatorEvent::CommandResult {
+                    command_id: 1,
+                    result: Ok(()),
+                })
+            ));
+
+            // The state runs on the machine it was saved on, with all of its memory
+            assert!(emulator.is_cgb_machine());
+            assert_eq!(emulator.vram.len(), Machine::Cgb.vram_size());
+            assert_eq!(emulator.work_ram.len(), Machine::Cgb.wram_size());
+            emulator.run_frame();
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn import_state_with_wrong_memory_size_is_rejected() {
+        with_large_stack(|| {
+            let dir = test_dir("import-state-memory-size");
+            let path = dir.join("bad.state");
+
+            // A DMG state with VRAM sized for a CGB
+            let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+            let mut bad_emulator = EmulatorBuilder::new_cartridge(
+                Cartridge::new_from_rom_bytes(rom).unwrap(),
+                Machine::Dmg,
+            )
+            .build()
+            .unwrap();
+            bad_emulator.vram = vec![0; Machine::Cgb.vram_size()];
+            bad_emulator.export_state(&path).unwrap();
+
+            let (mut emulator, commands_tx, events_rx) = new_commanded_emulator();
+            commands_tx.send(Command::ImportState(path, 1)).unwrap();
+            emulator.handle_commands();
+
+            match events_rx.try_recv() {
+                Ok(EmulatorEvent::CommandResult {
+                    command_id: 1,
+                    result: Err(CommandError::InvalidData(message)),
+                }) => assert_eq!(
+                    message,
+                    "save data is corrupt: VRAM is 16384 bytes but a DMG has 8192 bytes"
+                ),
+                _ => panic!("Expected the state to be rejected"),
+            }
+
+            // The emulator keeps its own memory
+            assert_eq!(emulator.vram.len(), Machine::Dmg.vram_size());
+            emulator.run_frame();
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    fn new_auto_state_emulator(
+        program: &[u8],
+        auto_state_path: &Path,
+    ) -> (Emulator, Sender<Command>) {
+        let (commands_tx, commands_rx) = channel();
+        let (events_tx, _) = channel();
+
+        let rom = build_test_rom(0x00, 0x00, 0x00, program);
+        let emulator = EmulatorBuilder::new_cartridge(
+            Cartridge::new_from_rom_bytes(rom).unwrap(),
+            Machine::Dmg,
+        )
+        .with_auto_state_path(auto_state_path.to_str().unwrap().to_string())
+        .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+        .build()
+        .unwrap();
+
+        (emulator, commands_tx)
+    }
+
+    #[test]
+    fn auto_state_written_on_shutdown() {
+        with_large_stack(|| {
+            let dir = test_dir("auto-state-shutdown");
+            let auto_state_path = dir.join("game.autostate");
+
+            let (mut emulator, commands_tx) =
+                new_auto_state_emulator(&FILL_VRAM_PROGRAM, &auto_state_path);
+            commands_tx.send(Command::Shutdown).unwrap();
+            emulator.run();
+
+            let state_file = StateFile::from_bytes(&fs::read(&auto_state_path).unwrap()).unwrap();
+            assert!(state_file.check_rom(&emulator.cartridge).is_ok());
+            assert!(emulator.has_auto_state());
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn auto_state_for_changed_rom_is_discarded() {
+        with_large_stack(|| {
+            let dir = test_dir("auto-state-changed-rom");
+            let auto_state_path = dir.join("game.autostate");
+
+            let (mut other_emulator, _) = new_auto_state_emulator(&[0x18, 0xFE], &auto_state_path);
+            other_emulator.emulate_boot_sequence();
+            other_emulator.write_auto_state().unwrap();
+
+            // The ROM at the same path has changed since the auto-state was written
+            let (mut emulator, _) = new_auto_state_emulator(&FILL_VRAM_PROGRAM, &auto_state_path);
+            emulator.emulate_boot_sequence();
+            let cpu_state = emulator.cpu_state();
+
+            assert!(!emulator.has_auto_state());
+            assert!(!auto_state_path.exists());
+            assert!(!emulator.resume_from_auto_state());
+            assert_eq!(emulator.cpu_state(), cpu_state);
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn resume_from_auto_state_on_launch() {
+        with_large_stack(|| {
+            let dir = test_dir("auto-state-resume");
+            let auto_state_path = dir.join("game.autostate");
+
+            // jr -2
+            let program = [0x18, 0xFE];
+
+            let (mut emulator, _) = new_auto_state_emulator(&program, &auto_state_path);
+            emulator.emulate_boot_sequence();
+            emulator.write_address(0xC000, 0x42);
+            emulator.write_auto_state().unwrap();
+
+            // Resumes once powered on, then writes a new auto-state on shutdown
+            let (mut emulator, commands_tx) = new_auto_state_emulator(&program, &auto_state_path);
+            emulator.resume_from_auto_state_on_power_on();
+            commands_tx.send(Command::Shutdown).unwrap();
+            emulator.run();
+
+            assert_eq!(emulator.read_address(0xC000), 0x42);
+            assert!(auto_state_path.exists());
+
+            // The auto-state is consumed by resuming, so it is not offered again
+            let (mut emulator, _) = new_auto_state_emulator(&program, &auto_state_path);
+            emulator.emulate_boot_sequence();
+            assert!(emulator.resume_from_auto_state());
+            assert_eq!(emulator.read_address(0xC000), 0x42);
+            assert!(!auto_state_path.exists());
+            assert!(!emulator.resume_from_auto_state());
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn rerender_frame_does_not_change_state() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Cgb, &FILL_VRAM_PROGRAM);
+
+            for _ in 0..60 {
+                emulator.run_frame();
+            }
+
+            // Stop partway through a frame
+            for _ in 0..(TICKS_PER_FRAME / 3) {
+                emulator.run_tick();
+            }
+
+            let frame = screen_pixels(&emulator);
+            let cpu_state = emulator.cpu_state();
+            let ppu_state = emulator.ppu_state();
+            let divider = emulator.full_divider_register();
+            let io_registers = emulator.read_memory_bulk(0xFF00, 0x80);
+
+            // Clobber the screen, which should be fully restored by re-rendering a static scene
+            for y in 0..SCREEN_HEIGHT {
+                for x in 0..SCREEN_WIDTH {
+                    emulator.write_pixel(x, y, Color::Dmg(3));
+                }
+            }
+
+            emulator.rerender_frame();
+
+            assert_eq!(screen_pixels(&emulator), frame);
+            assert_eq!(emulator.cpu_state(), cpu_state);
+            assert_eq!(emulator.ppu_state(), ppu_state);
+            assert_eq!(emulator.full_divider_register(), divider);
+            assert_eq!(emulator.read_memory_bulk(0xFF00, 0x80), io_registers);
+        });
+    }
+
+    #[rustfmt::skip]
+    const BANKED_CALL_PROGRAM: [u8; 18] = [
+        0x3E, 0x01,       // ld a, 1
+        0xEA, 0x00, 0x20, // ld [0x2000], a (select ROM bank 1)
+        0xCD, 0x00, 0x40, // call 0x4000
+        0x3E, 0x02,       // ld a, 2
+        0xEA, 0x00, 0x20, // ld [0x2000], a (select ROM bank 2)
+        0xCD, 0x00, 0x40, // call 0x4000
+        0x18, 0xFE,       // jr -2
+    ];
+
+    /// Address of the final loop in the banked call program
+    const BANKED_CALL_LOOP_ADDRESS: u16 = 0x0160;
+
+    /// An MBC1 emulator running `BANKED_CALL_PROGRAM`. Each ROM bank has a routine at 0x4000 that
+    /// loads its bank number times 0x11 into C.
+    #[rustfmt::skip]
+    const RTC_AND_JOYPAD_PROGRAM: [u8; 29] = [
+        0x3E, 0x0A,       // ld a, 0x0A (enable RAM and RTC)
+        0xEA, 0x00, 0x00, // ld [0x0000], a
+        0x3E, 0x08,       // ld a, 0x08 (select the RTC seconds register)
+        0xEA, 0x00, 0x40, // ld [0x4000], a
+        0xAF,             // xor a (latch the clock)
+        0xEA, 0x00, 0x60, // ld [0x6000], a
+        0x3C,             // inc a
+        0xEA, 0x00, 0x60, // ld [0x6000], a
+        0xFA, 0x00, 0xA0, // ld a, [0xA000]
+        0x47,             // ld b, a
+        0xF0, 0x00,       // ldh a, [0xFF00]
+        0xEA, 0x00, 0xC0, // ld [0xC000], a
+        0x18, 0xED,       // jr -19
+    ];
+
+    /// An MBC3 emulator with an RTC whose RNG and clock come from the seed.
+    fn new_seeded_emulator(seed: u64) -> (Emulator, Sender<Command>, Receiver<EmulatorEvent>) {
+        let (commands_tx, commands_rx) = channel();
+        let (events_tx, events_rx) = channel();
+
+        let rom = build_test_rom(0x10, 0x01, 0x03, &RTC_AND_JOYPAD_PROGRAM);
+        let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+            .with_seed(Some(seed))
+            .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+
+        (emulator, commands_tx, events_rx)
+    }
+
+    /// Serialized state of the emulator after each checkpoint, pressing different buttons before
+    /// each one.
+    fn seeded_run_checkpoints(seed: u64) -> Vec<Vec<u8>> {
+        let (mut emulator, commands_tx, _events_rx) = new_seeded_emulator(seed);
+
+        let mut checkpoints = vec![];
+        for buttons in [
+            0,
+            Button::A as u8,
+            Button::Start as u8 | Button::Down as u8,
+            0,
+        ] {
+            commands_tx
+                .send(Command::UpdatePressedButtons(buttons))
+                .unwrap();
+            emulator.run_frames(40);
+
+            // Differences in the RNG are expected between seeds, so it is checked separately
+            let rng = mem::replace(&mut emulator.rng, EmulatorRng::new(0));
+            checkpoints.push(rmp_serde::to_vec(&emulator).unwrap());
+            emulator.rng = rng;
+        }
+
+        checkpoints
+    }
+
+    #[test]
+    fn seeded_runs_are_deterministic() {
+        with_large_stack(|| {
+            let first = seeded_run_checkpoints(1234);
+            let second = seeded_run_checkpoints(1234);
+            assert!(first == second);
+
+            // Only the RNG differs between seeds, since nothing else is random
+            let other_seed = seeded_run_checkpoints(5678);
+            assert!(first == other_seed);
+
+            let (emulator, _commands_tx, _events_rx) = new_seeded_emulator(1234);
+            let (other_emulator, _commands_tx, _events_rx) = new_seeded_emulator(5678);
+            assert_eq!(emulator.seed(), 1234);
+            assert!(
+                rmp_serde::to_vec(&emulator).unwrap()
+                    != rmp_serde::to_vec(&other_emulator).unwrap()
+            );
+
+            // A seeded clock starts at a fixed time
+            assert_eq!(emulator.clock_time(), UNIX_EPOCH);
+        });
+    }
+
+    #[test]
+    fn serialized_state_resumes_rng_and_clock() {
+        with_large_stack(|| {
+            let (mut emulator, _commands_tx, _events_rx) = new_seeded_emulator(1234);
+            emulator.run_frames(130);
+            emulator.rng.next_u64();
+            let clock_time = emulator.clock_time();
+
+            // The game latched the RTC seconds from the emulator's clock, which is past 2 seconds
+            assert_eq!(emulator.cpu_state().b, 2);
+
+            let bytes = rmp_serde::to_vec(&emulator).unwrap();
+            let mut expected_rng = emulator.rng.clone();
+
+            let restored: Emulator = rmp_serde::from_slice(&bytes).unwrap();
+            let mut restored_rng = restored.rng.clone();
+            assert_eq!(restored.seed(), 1234);
+            assert_eq!(restored_rng.next_u64(), expected_rng.next_u64());
+            assert_eq!(restored.clock_time(), clock_time);
+        });
+    }
+
+    fn new_banked_call_emulator() -> Emulator {
+        let mut rom = build_test_rom(0x01, 0x01, 0x00, &BANKED_CALL_PROGRAM);
+        for bank in 1..4 {
+            let routine_start = bank * 0x4000;
+            rom[routine_start..routine_start + 3].copy_from_slice(&[0x0E, bank as u8 * 0x11, 0xC9]);
+        }
+
+        let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+
+        emulator
+    }
+
+    #[test]
+    fn breakpoint_hit_flushes_save_file() {
+        with_large_stack(|| {
+            let dir = test_dir("breakpoint_flush");
+            let save_file_path = dir.join("game.svgb");
+
+            let (commands_tx, commands_rx) = channel();
+            let (events_tx, _events_rx) = channel();
+
+            let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+            let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                .with_save_file_path(save_file_path.to_str().unwrap().to_string())
+                .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+                .build()
+                .unwrap();
+            emulator.emulate_boot_sequence();
+
+            let breakpoint = BankedAddress::new(0, PROGRAM_START as u16);
+            emulator.add_breakpoint(breakpoint);
+
+            // Nothing can resume the emulator once the commands channel is closed, so the
+            // breakpoint pauses and immediately resumes
+            drop(commands_tx);
+            while emulator.last_breakpoint_hit().is_none() {
+                assert!(!save_file_path.exists());
+                emulator.run_tick();
+            }
+
+            assert!(save_file_path.exists());
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn breakpoints_match_bank() {
+        with_large_stack(|| {
+            // Bank 3 is never mapped so its breakpoint is never hit
+            let mut emulator = new_banked_call_emulator();
+            emulator.add_breakpoint(BankedAddress::new(3, 0x4000));
+
+            while emulator.cpu_state().pc != BANKED_CALL_LOOP_ADDRESS {
+                emulator.run_tick();
+            }
+
+            assert_eq!(emulator.cpu_state().c, 0x22);
+            assert_eq!(emulator.current_rom_bank(), 2);
+            assert_eq!(emulator.last_breakpoint_hit(), None);
+
+            // Code in both bank 1 and bank 2 is executed at 0x4000, but only bank 2 matches
+            let mut emulator = new_banked_call_emulator();
+            let breakpoint = BankedAddress::new(2, 0x4000);
+            emulator.add_breakpoint(breakpoint);
+
+            while emulator.last_breakpoint_hit().is_none() {
+                assert_ne!(emulator.cpu_state().pc, BANKED_CALL_LOOP_ADDRESS);
+                emulator.run_tick();
+            }
+
+            // Stopped before executing bank 2's routine, after bank 1's routine has run
+            assert_eq!(emulator.last_breakpoint_hit(), Some(breakpoint));
+            assert_eq!(emulator.cpu_state().c, 0x22);
+            assert_eq!(emulator.cpu_state().pc, 0x4002);
+        });
+    }
+
+    /// CGB emulator with the LCD off so that VRAM can be freely accessed.
+    fn new_vram_dma_emulator() -> Emulator {
+        let rom = build_cgb_test_rom(0x00, 0x00, 0x00, &[]);
+        let mut emulator = new_test_emulator_builder(Machine::Cgb, rom)
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+        emulator.write_address(0xFF40, 0x00);
+        emulator
+    }
+
+    /// Start a general purpose VRAM DMA transfer of a single block, which completes immediately.
+    fn start_single_block_vram_dma(emulator: &mut Emulator, source: u16, dest: u16) {
+        emulator.write_address(0xFF51, (source >> 8) as u8);
+        emulator.write_address(0xFF52, source as u8);
+        emulator.write_address(0xFF53, (dest >> 8) as u8);
+        emulator.write_address(0xFF54, dest as u8);
+        emulator.write_address(0xFF55, 0x00);
+    }
+
+    #[test]
+    fn vram_dma_from_vram_transfers_ff() {
+        with_large_stack(|| {
+            let mut emulator = new_vram_dma_emulator();
+
+            for i in 0..0x10 {
+                emulator.write_address(0x8000 + i, 0x12);
+            }
+
+            start_single_block_vram_dma(&mut emulator, 0x8000, 0x8100);
+            assert!(emulator.vram[0x100..0x110].iter().all(|byte| *byte == 0xFF));
+
+            // The source is unchanged
+            assert!(emulator.vram[0x000..0x010].iter().all(|byte| *byte == 0x12));
+        });
+    }
+
+    #[test]
+    fn vram_dma_from_echo_range_aliases_work_ram() {
+        with_large_stack(|| {
+            let mut emulator = new_vram_dma_emulator();
+
+            assert_eq!(Emulator::vram_dma_source_address(0xF0, 0x2F), 0xD020);
+            assert_eq!(Emulator::vram_dma_source_address(0xE0, 0x00), 0xC000);
+            assert_eq!(Emulator::vram_dma_source_address(0xFF, 0xFF), 0xDFF0);
+            assert_eq!(Emulator::vram_dma_source_address(0xDF, 0xF5), 0xDFF0);
+
+            for i in 0..0x10 {
+                emulator.write_address(0xD000 + i, i as u8 + 1);
+                emulator.write_address(0xDE00 + i, i as u8 + 0x81);
+            }
+
+            start_single_block_vram_dma(&mut emulator, 0xF000, 0x8200);
+            let expected: Vec<u8> = (1..=0x10).collect();
+            assert_eq!(&emulator.vram[0x200..0x210], expected.as_slice());
+
+            // Reads work RAM rather than OAM
+            start_single_block_vram_dma(&mut emulator, 0xFE00, 0x8300);
+            let expected: Vec<u8> = (0x81..=0x90).collect();
+            assert_eq!(&emulator.vram[0x300..0x310], expected.as_slice());
+        });
+    }
+
+    /// Start a serial transfer with the divider reset and return the number of ticks until the
+    /// transfer completes.
+    fn serial_transfer_ticks(machine: Machine, is_double_speed: bool, sc: u8) -> usize {
+        let rom = build_cgb_test_rom(0x00, 0x00, 0x00, &[]);
+        let mut emulator = new_test_emulator_builder(machine, rom).build().unwrap();
+        emulator.emulate_boot_sequence();
+        emulator.set_is_double_speed(is_double_speed);
+
+        emulator.write_sb(0x41);
+        emulator.write_div(0);
+        emulator.write_sc(sc);
+
+        let mut ticks = 0;
+        while (emulator.sc() & 0x80) != 0 {
+            assert_eq!(emulator.if_reg() & 0x08, 0);
+            emulator.run_tick();
+            ticks += 1;
+        }
+
+        // No link partner, so all 1s are shifted in
+        assert_eq!(emulator.sb(), 0xFF);
+        assert_ne!(emulator.if_reg() & 0x08, 0);
+
+        ticks
+    }
+
+    #[test]
+    fn serial_transfer_timing() {
+        with_large_stack(|| {
+            assert_eq!(serial_transfer_ticks(Machine::Dmg, false, 0x81), 4096);
+            assert_eq!(serial_transfer_ticks(Machine::Cgb, false, 0x81), 4096);
+            assert_eq!(serial_transfer_ticks(Machine::Cgb, true, 0x81), 2048);
+
+            // CGB fast clock
+            assert_eq!(serial_transfer_ticks(Machine::Cgb, false, 0x83), 128);
+            assert_eq!(serial_transfer_ticks(Machine::Cgb, true, 0x83), 64);
+
+            // Fast clock bit is ignored on DMG
+            assert_eq!(serial_transfer_ticks(Machine::Dmg, false, 0x83), 4096);
+        });
+    }
+
+    /// Number of NOPs executed during a single scanline, starting at the beginning of the scanline.
+    fn instructions_per_scanline(is_double_speed: bool, cycle_accurate: bool) -> usize {
+        // The ROM is all NOPs after the header
+        let rom = build_cgb_test_rom(0x00, 0x00, 0x00, &[]);
+        let mut emulator = new_test_emulator_builder(Machine::Cgb, rom)
+            .with_options(Arc::new(Options {
+                cycle_accurate,
+                ..Options::default()
+            }))
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+        emulator.set_is_double_speed(is_double_speed);
+
+        // Run past the jump from the entry point, then to the start of a scanline
+        while (emulator.regs().pc() as usize) < PROGRAM_START {
+            emulator.step_instruction();
+        }
+        while !emulator.tick.is_multiple_of(TICKS_PER_SCANLINE as u32) {
+            emulator.run_tick();
+        }
+
+        let start_pc = emulator.regs().pc();
+        assert_eq!(emulator.run_ticks(TICKS_PER_SCANLINE), TICKS_PER_SCANLINE);
+        assert!(emulator.tick.is_multiple_of(TICKS_PER_SCANLINE as u32));
+
+        (emulator.regs().pc() - start_pc) as usize
+    }
+
+    #[test]
+    fn instructions_per_scanline_in_both_speeds() {
+        with_large_stack(|| {
+            // A scanline is 456 dots in both speeds, which is 114 machine cycles at single speed and
+            // 228 at double speed
+            for cycle_accurate in [false, true] {
+                assert_eq!(instructions_per_scanline(false, cycle_accurate), 114);
+                assert_eq!(instructions_per_scanline(true, cycle_accurate), 228);
+            }
+        });
+    }
+
+    /// Number of ticks for an OAM DMA transfer and a single block general purpose VRAM DMA transfer
+    /// to complete.
+    fn dma_transfer_ticks(is_double_speed: bool) -> (usize, usize) {
+        let mut emulator = new_vram_dma_emulator();
+        emulator.set_is_double_speed(is_double_speed);
+
+        emulator.start_oam_dma_transfer(0xC000);
+        let mut oam_dma_ticks = 0;
+        while emulator.current_oam_dma_transfer.is_some() {
+            oam_dma_ticks += emulator.run_tick();
+        }
+
+        start_single_block_vram_dma(&mut emulator, 0xC000, 0x8000);
+        let mut vram_dma_ticks = 0;
+        while emulator.is_cpu_stopped_for_vram_dma {
+            vram_dma_ticks += emulator.run_tick();
+        }
+
+        (oam_dma_ticks, vram_dma_ticks)
+    }
+
+    #[test]
+    fn dma_timing_in_both_speeds() {
+        with_large_stack(|| {
+            // OAM DMA copies a byte every machine cycle so is twice as fast in double speed mode,
+            // while VRAM DMA takes the same time in both speeds
+            assert_eq!(dma_transfer_ticks(false), (641, 33));
+            assert_eq!(dma_transfer_ticks(true), (321, 33));
+        });
+    }
+
+    #[test]
+    fn serial_transfer_with_external_clock_never_completes() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &[]);
+
+            emulator.write_sb(0x41);
+            emulator.write_sc(0x80);
+            assert_eq!(emulator.sc(), 0xFE);
+
+            emulator.run_frame();
+
+            assert_eq!(emulator.sc(), 0xFE);
+            assert_eq!(emulator.sb(), 0x41);
+            assert_eq!(emulator.if_reg() & 0x08, 0);
+        });
+    }
+
+    fn screen_pixels(emulator: &Emulator) -> Vec<Color> {
+        (0..SCREEN_HEIGHT)
+            .flat_map(|y| (0..SCREEN_WIDTH).map(move |x| emulator.read_pixel(x, y)))
+            .collect()
+    }
+
+    /// An emulator that loops forever with the window and 10 objects on screen.
+    fn new_window_and_objects_emulator() -> Emulator {
+        let mut emulator = new_test_emulator(Machine::Dmg, &[0x18, 0xFE]);
+
+        // Enable the window and objects
+        let lcdc = emulator.lcdc();
+        emulator.write_lcdc(lcdc | 0x22);
+        emulator.write_address(0xFF4A, 40);
+        emulator.write_address(0xFF4B, 60);
+
+        for i in 0..10 {
+            let address = 0xFE00 + i * 4;
+            emulator.write_address(address, 16 + (i as u8) * 12);
+            emulator.write_address(address + 1, 8 + (i as u8) * 16);
+        }
+
+        emulator
+    }
+
+    #[test]
+    fn turbo_mode_skips_rendering() {
+        with_large_stack(|| {
+            let mut emulator = new_window_and_objects_emulator();
+            let mut reference_emulator = new_window_and_objects_emulator();
+
+            emulator.speed = DEFAULT_TURBO_SPEED;
+            for _ in 0..20 {
+                emulator.run_frame();
+                reference_emulator.run_frame();
+            }
+
+            assert_eq!(emulator.num_rendered_frames(), 2);
+            assert_eq!(reference_emulator.num_rendered_frames(), 20);
+
+            // Skipped frames have the same timing and side effects as rendered frames
+            assert_eq!(emulator.cpu_state(), reference_emulator.cpu_state());
+            assert_eq!(emulator.if_reg(), reference_emulator.if_reg());
+            assert_eq!(
+                emulator.draw_timing_metrics().histogram,
+                reference_emulator.draw_timing_metrics().histogram
+            );
+
+            // Every frame is rendered again as soon as turbo mode is disabled
+            emulator.speed = NORMAL_SPEED;
+            for _ in 0..5 {
+                emulator.run_frame();
+            }
+
+            assert_eq!(emulator.num_rendered_frames(), 7);
+
+            // At fractional speeds around one frame per frame at normal speed is rendered
+            emulator.speed = 2.5;
+            for _ in 0..20 {
+                emulator.run_frame();
+            }
+
+            assert_eq!(emulator.num_rendered_frames(), 15);
+        });
+    }
+
+    #[test]
+    fn progressive_drawing_matches_drawing_whole_scanlines() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &FILL_VRAM_PROGRAM);
+
+            // Fine scroll, the window, and objects all change the length of Draw mode
+            let lcdc = emulator.lcdc();
+            emulator.write_lcdc(lcdc | 0x22);
+            emulator.write_address(0xFF42, 5);
+            emulator.write_address(0xFF43, 3);
+            emulator.write_address(0xFF4A, 40);
+            emulator.write_address(0xFF4B, 60);
+
+            for _ in 0..10 {
+                emulator.run_frame();
+            }
+
+            let frame = screen_pixels(&emulator);
+            assert!(frame.iter().any(|color| *color != frame[0]));
+
+            // A static scene is identical when every scanline is drawn at once
+            emulator.rerender_frame();
+            assert_eq!(screen_pixels(&emulator), frame);
+        });
+    }
+
+    #[test]
+    fn scx_write_during_draw_splits_scanline() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &[0x18, 0xFE]);
+
+            // Tile 1 is solid color 3. The right half of the background tile map uses tile 1.
+            for i in 0..16 {
+                emulator.write_address(0x8010 + i, 0xFF);
+            }
+            for row in 0..32 {
+                for column in 16..32 {
+                    emulator.write_address(0x9800 + row * 32 + column, 0x01);
+                }
+            }
+            emulator.write_bgp(0b11_10_01_00);
+            emulator.write_address(0xFF43, 0);
+
+            // Run until Draw mode starts on scanline 20
+            while emulator.ly() != 20 || emulator.mode() != Mode::Draw {
+                emulator.run_tick();
+            }
+
+            // Draw mode lasts 172 ticks, so each group of 8 pixels is drawn 8.6 ticks after the
+            // last. Write SCX after the first 10 groups have been drawn.
+            for _ in 0..80 {
+                emulator.run_tick();
+            }
+            emulator.write_address(0xFF43, 64);
+
+            while emulator.mode() == Mode::Draw {
+                emulator.run_tick();
+            }
+
+            // The left half was drawn with SCX=0 and the right half with SCX=64
+            let scanline: Vec<u8> = (0..SCREEN_WIDTH)
+                .map(|x| emulator.read_pixel(x, 20).unwrap_dmg())
+                .collect();
+            assert!(scanline[..80].iter().all(|color| *color == 0));
+            assert!(scanline[80..].iter().all(|color| *color == 3));
+        });
+    }
+
+    fn new_stat_interrupt_emulator() -> Emulator {
+        let rom = build_test_rom(0x00, 0x00, 0x00, &[0x18, 0xFE]);
+        let mut emulator = new_stepping_emulator(rom, Machine::Dmg, true);
+
+        // Start at the beginning of a frame
+        emulator.run_frame();
+        emulator
+    }
+
+    fn is_stat_interrupt_requested(emulator: &Emulator) -> bool {
+        emulator.if_reg() & Interrupt::LcdStat.flag_bit() != 0
+    }
+
+    fn clear_interrupt_flags(emulator: &mut Emulator) {
+        emulator.write_if_reg(0);
+    }
+
+    #[test]
+    fn stat_interrupt_line_blocks_overlapping_conditions() {
+        with_large_stack(|| {
+            let mut emulator = new_stat_interrupt_emulator();
+
+            // HBlank and LY=LYC interrupts enabled, with LYC matching the line after an HBlank
+            emulator.write_address(0xFF45, 5);
+            emulator.write_address(0xFF41, 0x48);
+            clear_interrupt_flags(&mut emulator);
+
+            while emulator.ly() != 4 || emulator.mode() != Mode::HBlank {
+                emulator.run_tick();
+            }
+            assert!(is_stat_interrupt_requested(&emulator));
+            clear_interrupt_flags(&mut emulator);
+
+            // LY=LYC starts while HBlank still holds the line high, and HBlank on the same line
+            // starts while LY=LYC holds it high, so neither requests an interrupt
+            while emulator.ly() != 6 {
+                emulator.run_tick();
+                assert!(!is_stat_interrupt_requested(&emulator));
+            }
+
+            // The line fell at the start of line 6, so the next HBlank requests an interrupt
+            while emulator.mode() != Mode::HBlank {
+                emulator.run_tick();
+            }
+            assert!(is_stat_interrupt_requested(&emulator));
+            clear_interrupt_flags(&mut emulator);
+
+            // Enabling a condition that already holds while the line is low raises it
+            emulator.write_address(0xFF41, 0x00);
+            emulator.write_address(0xFF45, 6);
+            emulator.write_address(0xFF41, 0x40);
+            assert!(is_stat_interrupt_requested(&emulator));
+        });
+    }
+
+    #[test]
+    fn ly_reads_zero_early_in_last_scanline() {
+        with_large_stack(|| {
+            let mut emulator = new_stat_interrupt_emulator();
+
+            // LY=LYC interrupt enabled for line 0
+            emulator.write_address(0xFF45, 0);
+            emulator.write_address(0xFF41, 0x40);
+
+            while emulator.ly() != 152 {
+                emulator.run_tick();
+            }
+            while emulator.ly() == 152 {
+                emulator.run_tick();
+            }
+            clear_interrupt_flags(&mut emulator);
+
+            // Poll LY through the rest of the frame. LY reads 153 for only the first 4 ticks of the
+            // last scanline, one of which has already run, and then reads 0 until the frame ends.
+            let mut ly_reads = vec![];
+            while emulator.tick != 0 {
+                ly_reads.push(emulator.read_address(0xFF44));
+                emulator.run_tick();
+            }
+
+            let num_153_reads = ly_reads.iter().take_while(|ly| **ly == 153).count();
+            assert_eq!(num_153_reads, 3);
+            assert!(ly_reads[num_153_reads..].iter().all(|ly| *ly == 0));
+
+            // LY=LYC for line 0 was first seen during line 153, and the line stays high into line 0
+            assert!(is_stat_interrupt_requested(&emulator));
+            clear_interrupt_flags(&mut emulator);
+
+            emulator.run_tick();
+            assert_eq!(emulator.ly(), 0);
+            assert!(!is_stat_interrupt_requested(&emulator));
+        });
+    }
+
+    /// Address of the shared interrupt handler routine in the interrupt test ROM
+    const SHARED_INTERRUPT_HANDLER: usize = 0x0200;
+
+    /// An emulator that enables all interrupts, writes `initial_if` to IF, and then loops
+    /// incrementing B with interrupts enabled. Each interrupt handler appends the interrupt's flag
+    /// bit, the value of IF during the handler, and B to a log at C000.
+    fn new_interrupt_log_emulator(initial_if: u8) -> Emulator {
+        #[rustfmt::skip]
+        let program = [
+            0x21, 0x00, 0xC0,   // ld hl, 0xC000
+            0x3E, 0x1F,         // ld a, 0x1F
+            0xE0, 0xFF,         // ldh [IE], a
+            0x3E, initial_if,   // ld a, initial_if
+            0xE0, 0x0F,         // ldh [IF], a
+            0x06, 0x00,         // ld b, 0
+            0xFB,               // ei
+            0x04,               // loop: inc b
+            0x18, 0xFD,         // jr loop
+        ];
+
+        let mut rom = build_test_rom(0x00, 0x00, 0x00, &program);
+
+        // Each vector loads its flag bit then jumps to the shared handler
+        for (i, vector) in (0x40..=0x60).step_by(8).enumerate() {
+            let [handler_low, handler_high] = (SHARED_INTERRUPT_HANDLER as u16).to_le_bytes();
+            rom[vector..vector + 5].copy_from_slice(&[
+                0x3E,
+                1 << i,
+                0xC3,
+                handler_low,
+                handler_high,
+            ]);
+        }
+
+        #[rustfmt::skip]
+        rom[SHARED_INTERRUPT_HANDLER..SHARED_INTERRUPT_HANDLER + 7].copy_from_slice(&[
+            0x22,       // ld [hl+], a
+            0xF0, 0x0F, // ldh a, [IF]
+            0x22,       // ld [hl+], a
+            0x78,       // ld a, b
+            0x22,       // ld [hl+], a
+            0xD9,       // reti
+        ]);
+
+        let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+
+        emulator
+    }
+
+    /// Read the (flag bit, IF, B) entries logged by the interrupt handlers so far.
+    fn read_interrupt_log(emulator: &Emulator) -> Vec<(u8, u8, u8)> {
+        let log_end = emulator.cpu_state().hl();
+        let log = emulator.read_memory_bulk(0xC000, (log_end - 0xC000) as usize);
+
+        log.chunks(3)
+            .map(|entry| (entry[0], entry[1] & 0x1F, entry[2]))
+            .collect()
+    }
+
+    #[test]
+    fn pending_interrupts_serviced_in_priority_order() {
+        with_large_stack(|| {
+            let mut emulator = new_interrupt_log_emulator(0x1F);
+            for _ in 0..2000 {
+                emulator.run_tick();
+            }
+
+            // Each handler runs in priority order with its own flag cleared. The next handler runs
+            // directly after RETI with no instructions of the interrupted program in between.
+            assert_eq!(
+                read_interrupt_log(&emulator),
+                vec![
+                    (0x01, 0x1E, 1),
+                    (0x02, 0x1C, 1),
+                    (0x04, 0x18, 1),
+                    (0x08, 0x10, 1),
+                    (0x10, 0x00, 1),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn interrupt_requested_during_dispatch_is_not_lost() {
+        with_large_stack(|| {
+            let mut emulator = new_interrupt_log_emulator(0x00);
+            for _ in 0..200 {
+                emulator.run_tick();
+            }
+
+            // Request the timer interrupt and run until it is dispatched
+            emulator.request_interrupt(Interrupt::Timer);
+            while emulator.cpu_state().pc != 0x0050 {
+                emulator.run_tick();
+            }
+
+            // A higher priority interrupt arrives while the timer interrupt is being dispatched
+            emulator.request_interrupt(Interrupt::VBlank);
+            for _ in 0..2000 {
+                emulator.run_tick();
+            }
+
+            let log = read_interrupt_log(&emulator);
+            assert_eq!(log.len(), 2);
+            assert_eq!((log[0].0, log[0].1), (0x04, 0x01));
+            assert_eq!((log[1].0, log[1].1), (0x01, 0x00));
+
+            // Both were serviced with no instructions of the interrupted program in between
+            assert_eq!(log[0].2, log[1].2);
+        });
+    }
+
+    /// A booting emulator with the given BIOS overlaid on cartridge ROM. The cartridge has
+    /// `ld a, 0x99` at the RST 0x38 vector and `ld a, 0x98` at the VBlank interrupt vector.
+    fn new_booting_emulator(machine: Machine, bios: Vec<u8>) -> Emulator {
+        let mut rom = build_test_rom(0x00, 0x00, 0x00, &[]);
+        rom[0x0038..0x003A].copy_from_slice(&[0x3E, 0x99]);
+        rom[0x0040..0x0042].copy_from_slice(&[0x3E, 0x98]);
+
+        let mut emulator = new_test_emulator_builder(machine, rom).build().unwrap();
+        emulator.bios = Some(bios);
+        emulator.set_is_booting(true);
+
+        emulator
+    }
+
+    #[test]
+    fn bios_overlay_ranges() {
+        with_large_stack(|| {
+            // DMG BIOS only covers 0000-00FF, the entry point at 0100 is in the cartridge
+            let emulator = new_booting_emulator(Machine::Dmg, vec![0xB1; 0x100]);
+            assert_eq!(
+                emulator.read_memory_bulk(0x00FE, 4),
+                vec![0xB1, 0xB1, 0x00, 0xC3]
+            );
+
+            // CGB BIOS leaves a hole for the cartridge header at 0100-01FF
+            let mut bios = vec![0xB1; 0x900];
+            bios[0x0200] = 0xB2;
+            let emulator = new_booting_emulator(Machine::Cgb, bios);
+            assert_eq!(emulator.read_memory_bulk(0x00FF, 3), vec![0xB1, 0x00, 0xC3]);
+            assert_eq!(emulator.read_memory_bulk(0x01FF, 2), vec![0x00, 0xB2]);
+            assert_eq!(emulator.read_memory_bulk(0x08FF, 2), vec![0xB1, 0x00]);
+
+            // Dumps that omit the hole are shifted down
+            let mut bios = vec![0xB1; 0x800];
+            bios[0x0100] = 0xB2;
+            let emulator = new_booting_emulator(Machine::Cgb, bios);
+            assert_eq!(emulator.read_memory_bulk(0x01FF, 2), vec![0x00, 0xB2]);
+
+            // A DMG BIOS on a CGB only overlays the range it covers
+            let emulator = new_booting_emulator(Machine::Cgb, vec![0xB1; 0x100]);
+            assert_eq!(emulator.read_memory_bulk(0x00FF, 1), vec![0xB1]);
+            assert_eq!(emulator.read_memory_bulk(0x0200, 1), vec![0x00]);
+        });
+    }
+
+    #[test]
+    fn boot_rom_runs_then_jumps_to_cartridge() {
+        with_large_stack(|| {
+            #[rustfmt::skip]
+            let boot_program = [
+                0x21, 0x00, 0x80, // ld hl, 0x8000
+                0x3E, 0x5A,       // ld a, 0x5A
+                0x22,             // ld [hl+], a
+                0x22,             // ld [hl+], a
+                0x3E, 0x01,       // ld a, 1
+                0xC3, 0xFC, 0x00, // jp 0x00FC
+            ];
+            // Unmap the boot ROM as its last instruction, like the real boot ROMs
+            let unmap_program = [
+                0x3E, 0x01, // ld a, 1
+                0xE0, 0x50, // ldh [BANK], a
+            ];
+
+            for machine in [Machine::Dmg, Machine::Cgb] {
+                let mut bios = vec![0x00; machine.bios_sizes()[0]];
+                bios[..boot_program.len()].copy_from_slice(&boot_program);
+                bios[0x00FC..0x0100].copy_from_slice(&unmap_program);
+
+                let rom = build_test_rom(0x00, 0x00, 0x00, &[0x18, 0xFE]); // jr -2
+                let mut emulator = new_test_emulator_builder(machine, rom)
+                    .with_bios(bios)
+                    .build()
+                    .unwrap();
+                emulator.power_on();
+
+                // Starts from the state before the boot ROM runs
+                assert_eq!(emulator.cpu_state().pc, 0x0000);
+                assert_eq!(emulator.cpu_state().a, 0x00);
+                assert!(!emulator.is_lcdc_lcd_enabled());
+
+                emulator.run_frames(1);
+
+                assert!(!emulator.is_booting());
+                assert_eq!(emulator.read_memory_bulk(0x8000, 3), vec![0x5A, 0x5A, 0x00]);
+                assert_eq!(emulator.cpu_state().pc, PROGRAM_START as u16);
+            }
+        });
+    }
+
+    #[test]
+    fn ppu_blocks_vram_and_oam_access() {
+        with_large_stack(|| {
+            let new_emulator = |options: Options| {
+                let rom = build_test_rom(0x00, 0x00, 0x00, &[]);
+                let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                    .with_options(Arc::new(options))
+                    .build()
+                    .unwrap();
+                emulator.emulate_boot_sequence();
+                emulator
+            };
+
+            let mut emulator = new_emulator(Options::default());
+
+            emulator.mode = Mode::HBlank;
+            emulator.write_address(0x8000, 0x11);
+            emulator.write_address(0xFE00, 0x22);
+            assert_eq!(emulator.read_address(0x8000), 0x11);
+            assert_eq!(emulator.read_address(0xFE00), 0x22);
+
+            // OAM is blocked while scanning OAM
+            emulator.mode = Mode::OamScan;
+            emulator.write_address(0x8000, 0x33);
+            emulator.write_address(0xFE00, 0x44);
+            assert_eq!(emulator.read_address(0x8000), 0x33);
+            assert_eq!(emulator.read_address(0xFE00), 0xFF);
+
+            // VRAM and OAM are both blocked while drawing
+            emulator.mode = Mode::Draw;
+            emulator.write_address(0x8000, 0x55);
+            emulator.write_address(0xFE00, 0x66);
+            assert_eq!(emulator.read_address(0x8000), 0xFF);
+            assert_eq!(emulator.read_address(0xFE00), 0xFF);
+
+            // Blocked writes were dropped, and tools can still inspect memory
+            assert_eq!(emulator.read_memory_bulk(0x8000, 1), vec![0x33]);
+            assert_eq!(emulator.read_memory_bulk(0xFE00, 1), vec![0x22]);
+
+            // Nothing is blocked while the LCD is off
+            emulator.write_lcdc(0x00);
+            emulator.write_address(0x8000, 0x77);
+            assert_eq!(emulator.read_address(0x8000), 0x77);
+
+            // Or when access restrictions are disabled
+            let mut emulator = new_emulator(Options {
+                no_access_restrictions: true,
+                ..Options::default()
+            });
+            emulator.mode = Mode::Draw;
+            emulator.write_address(0x8000, 0x88);
+            emulator.write_address(0xFE00, 0x99);
+            assert_eq!(emulator.read_address(0x8000), 0x88);
+            assert_eq!(emulator.read_address(0xFE00), 0x99);
+        });
+    }
+
+    #[test]
+    fn echo_ram_mirrors_work_ram() {
+        with_large_stack(|| {
+            let rom = build_cgb_test_rom(0x00, 0x00, 0x00, &[]);
+            let mut emulator = new_test_emulator_builder(Machine::Cgb, rom)
+                .build()
+                .unwrap();
+            emulator.power_on();
+
+            emulator.write_address(0xC123, 0x42);
+            assert_eq!(emulator.read_address(0xE123), 0x42);
+            emulator.write_address(0xE124, 0x43);
+            assert_eq!(emulator.read_address(0xC124), 0x43);
+
+            // The upper half mirrors the work RAM bank selected by WBK
+            emulator.write_wbk(0x03);
+            emulator.write_address(0xD123, 0x44);
+            assert_eq!(emulator.read_address(0xF123), 0x44);
+            emulator.write_address(0xF124, 0x45);
+            assert_eq!(emulator.read_address(0xD124), 0x45);
+
+            emulator.write_wbk(0x01);
+            assert_ne!(emulator.read_address(0xF123), 0x44);
+            assert_ne!(emulator.read_address(0xD124), 0x45);
+        });
+    }
+
+    fn new_invalid_opcode_emulator(panic_on_invalid: bool) -> Emulator {
+        // nop, then the invalid opcode 0xD3
+        let rom = build_test_rom(0x00, 0x00, 0x00, &[0x00, 0xD3]);
+        let options = Options {
+            panic_on_invalid,
+            ..Options::default()
+        };
+        let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+            .with_options(Arc::new(options))
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+        emulator
+    }
+
+    #[test]
+    fn invalid_opcode_locks_cpu() {
+        with_large_stack(|| {
+            let mut emulator = new_invalid_opcode_emulator(false);
+            emulator.run_frame();
+
+            let invalid_opcode_address = PROGRAM_START as u16 + 1;
+            assert_eq!(
+                emulator.cpu_lock(),
+                Some(CpuLock {
+                    address: invalid_opcode_address,
+                    opcode: 0xD3,
+                })
+            );
+
+            // Interrupts are not handled either
+            emulator.regs_mut().set_interrupts_enabled(true);
+            emulator.write_address(0xFFFF, 0x01);
+            emulator.request_interrupt(Interrupt::VBlank);
+
+            // Frames are still produced, but the CPU never executes another instruction
+            let pc = emulator.regs().pc();
+            let num_rendered_frames = emulator.num_rendered_frames();
+            emulator.run_frames(3);
+
+            assert_eq!(emulator.regs().pc(), pc);
+            assert_eq!(pc, invalid_opcode_address + 1);
+            assert_eq!(emulator.num_rendered_frames(), num_rendered_frames + 3);
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_opcode_panics_when_requested() {
+        with_large_stack(|| {
+            let mut emulator = new_invalid_opcode_emulator(true);
+            emulator.run_frame();
+        });
+    }
+
+    #[test]
+    fn pc_wraps_around_end_of_memory() {
+        with_large_stack(|| {
+            // ld sp, $ABCD, then ld [$FFFF], sp
+            let mut emulator =
+                new_test_emulator(Machine::Dmg, &[0x31, 0xCD, 0xAB, 0x08, 0xFF, 0xFF]);
+
+            // The high byte of SP is written to 0x0000 after wrapping, which is ignored by the ROM
+            emulator.regs_mut().set_pc(PROGRAM_START as u16);
+            emulator.step_instruction();
+            emulator.step_instruction();
+            assert_eq!(emulator.read_address(0xFFFF), 0xCD);
+
+            // nop in IE
+            emulator.write_address(0xFFFF, 0x00);
+            emulator.regs_mut().set_pc(0xFFFF);
+            emulator.step_instruction();
+            assert_eq!(emulator.regs().pc(), 0x0000);
+
+            // ld a, $05 with the operand in IE
+            emulator.write_address(0xFFFE, 0x3E);
+            emulator.write_address(0xFFFF, 0x05);
+            emulator.regs_mut().set_pc(0xFFFE);
+            emulator.step_instruction();
+            assert_eq!(emulator.regs().a(), 0x05);
+            assert_eq!(emulator.regs().pc(), 0x0000);
+
+            // ld bc, $1234 with the operand in the last byte of HRAM and IE
+            emulator.write_address(0xFFFD, 0x01);
+            emulator.write_address(0xFFFE, 0x34);
+            emulator.write_address(0xFFFF, 0x12);
+            emulator.regs_mut().set_pc(0xFFFD);
+            emulator.step_instruction();
+            assert_eq!(emulator.regs().bc(), 0x1234);
+            assert_eq!(emulator.regs().pc(), 0x0000);
+
+            // ld bc, imm16 with the high byte of the operand read from ROM after wrapping
+            let rom_byte = emulator.read_address(0x0000) as u16;
+            emulator.write_address(0xFFFE, 0x01);
+            emulator.write_address(0xFFFF, 0x78);
+            emulator.regs_mut().set_pc(0xFFFE);
+            emulator.step_instruction();
+            assert_eq!(emulator.regs().bc(), (rom_byte << 8) | 0x78);
+            assert_eq!(emulator.regs().pc(), 0x0001);
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn echo_ram_panics_in_strict_memory_mode() {
+        with_large_stack(|| {
+            let rom = build_test_rom(0x00, 0x00, 0x00, &[]);
+            let options = Options {
+                strict_memory: true,
+                ..Options::default()
+            };
+            let emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                .with_options(Arc::new(options))
+                .build()
+                .unwrap();
+
+            emulator.read_address(0xE000);
+        });
+    }
+
+    #[test]
+    fn bios_size_must_match_machine() {
+        with_large_stack(|| {
+            let new_builder = |machine| {
+                let rom = build_test_rom(0x00, 0x00, 0x00, &[]);
+                EmulatorBuilder::new_cartridge(Cartridge::new_from_rom_bytes(rom).unwrap(), machine)
+            };
+
+            assert!(
+                new_builder(Machine::Dmg)
+                    .with_bios(vec![0; 0x100])
+                    .build()
+                    .is_ok()
+            );
+            assert!(
+                new_builder(Machine::Cgb)
+                    .with_bios(vec![0; 0x900])
+                    .build()
+                    .is_ok()
+            );
+            assert!(
+                new_builder(Machine::Cgb)
+                    .with_bios(vec![0; 0x800])
+                    .build()
+                    .is_ok()
+            );
+
+            let error = new_builder(Machine::Cgb)
+                .with_bios(vec![0; 0x100])
+                .build()
+                .err()
+                .unwrap();
+            assert_eq!(
+                error.to_string(),
+                "BIOS is 256 bytes but a CGB BIOS must be 2304 or 2048 bytes"
+            );
+
+            let error = new_builder(Machine::Dmg)
+                .with_bios(vec![0; 0x900])
+                .build()
+                .err()
+                .unwrap();
+            assert_eq!(
+                error.to_string(),
+                "BIOS is 2304 bytes but a DMG BIOS must be 256 bytes"
+            );
+        });
+    }
+
+    #[test]
+    fn rst_and_interrupts_read_through_bios_overlay() {
+        with_large_stack(|| {
+            for (machine, bios_size) in [(Machine::Dmg, 0x100), (Machine::Cgb, 0x900)] {
+                let mut bios = vec![0x00; bios_size];
+                bios[0x0000] = 0xFF; // rst 0x38
+                bios[0x0038..0x003A].copy_from_slice(&[0x3E, 0x42]); // ld a, 0x42
+                bios[0x0040..0x0042].copy_from_slice(&[0x3E, 0x43]); // ld a, 0x43
+
+                let mut emulator = new_booting_emulator(machine, bios);
+                emulator.set_cpu_state(CpuState {
+                    pc: 0x0000,
+                    sp: 0xFFFE,
+                    ..emulator.cpu_state()
+                });
+
+                emulator.execute_instruction();
+                assert_eq!(emulator.cpu_state().pc, 0x0038);
+                emulator.execute_instruction();
+                assert_eq!(emulator.cpu_state().a, 0x42);
+
+                emulator.call_interrupt_handler(Interrupt::VBlank);
+                emulator.execute_instruction();
+                assert_eq!(emulator.cpu_state().a, 0x43);
+
+                // Once the BIOS is unmapped the cartridge's handlers are used
+                emulator.write_address(0xFF50, 0x01);
+                emulator.call_interrupt_handler(Interrupt::VBlank);
+                emulator.execute_instruction();
+                assert_eq!(emulator.cpu_state().a, 0x98);
+            }
+        });
+    }
+
+    #[test]
+    fn cycle_accurate_frames_stay_on_frame_boundaries() {
+        with_large_stack(|| {
+            let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+            let options = Options {
+                cycle_accurate: true,
+                ..Options::default()
+            };
+            let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                .with_options(Arc::new(options))
+                .build()
+                .unwrap();
+            emulator.emulate_boot_sequence();
+
+            // Each frame ends no further past the frame boundary than a single instruction, rather
+            // than drifting further with every frame
+            for _ in 0..100 {
+                emulator.run_frame();
+                assert!(emulator.tick < 24);
+            }
+        });
+    }
+
+    #[test]
+    fn cycle_accurate_memory_accesses_happen_on_their_machine_cycle() {
+        #[rustfmt::skip]
+        const PROGRAM: [u8; 4] = [
+            0xF0, 0x04, // ldh a, [0xFF04] (read DIV)
+            0x18, 0xFC, // jr -4
+        ];
+
+        with_large_stack(|| {
+            for cycle_accurate in [false, true] {
+                let rom = build_test_rom(0x00, 0x00, 0x00, &PROGRAM);
+                let options = Options {
+                    cycle_accurate,
+                    ..Options::default()
+                };
+                let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                    .with_options(Arc::new(options))
+                    .build()
+                    .unwrap();
+                emulator.emulate_boot_sequence();
+                emulator.regs_mut().set_pc(PROGRAM_START as u16);
+
+                // DIV is incremented 8 ticks into the instruction, right as its read happens in
+                // the third machine cycle
+                emulator.full_divider_register = 0x00F7;
+                let num_ticks = emulator.run_tick();
+
+                let expected_div = if cycle_accurate { 0x01 } else { 0x00 };
+                assert_eq!(emulator.cpu_state().a, expected_div);
+                assert_eq!(num_ticks, if cycle_accurate { 9 } else { 1 });
+
+                // The instruction takes the same total time in both modes
+                assert_eq!(emulator.ticks_to_next_instruction + num_ticks, 12);
+                assert_eq!(emulator.full_divider_register, 0x00F7 + num_ticks as u16);
+            }
+        });
+    }
+
+    #[test]
+    fn oam_dma_only_allows_high_page_access() {
+        #[rustfmt::skip]
+        const PROGRAM: [u8; 12] = [
+            0x3E, 0xC0,       // ld a, 0xC0
+            0xCD, 0x80, 0xFF, // call 0xFF80 (start OAM DMA from HRAM)
+            0xFA, 0x00, 0xC0, // ld a, [0xC000]
+            0x57,             // ld d, a
+            0x18, 0xFE,       // jr -2
+            0x00,
+        ];
+
+        #[rustfmt::skip]
+        const HRAM_ROUTINE: [u8; 12] = [
+            0xE0, 0x46,       // ldh [0xFF46], a
+            0xFA, 0x00, 0xC0, // ld a, [0xC000] (mid-transfer)
+            0x5F,             // ld e, a
+            0x06, 0x28,       // ld b, 40
+            0x05,             // dec b
+            0x20, 0xFD,       // jr nz, -3
+            0xC9,             // ret
+        ];
+
+        const WAIT_LOOP_ADDRESS: u16 = 0xFF88;
+        const END_ADDRESS: u16 = PROGRAM_START as u16 + 9;
+
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &PROGRAM);
+            emulator.regs_mut().set_pc(PROGRAM_START as u16);
+
+            let source = (0..OAM_SIZE as u8).map(|i| 0x10 + i).collect::<Vec<_>>();
+            emulator.write_memory_bulk(0xC000, &source);
+            emulator.write_memory_bulk(0xFE00, &[0; OAM_SIZE]);
+            emulator.write_memory_bulk(0xFF80, &HRAM_ROUTINE);
+
+            while emulator.regs().pc() != WAIT_LOOP_ADDRESS {
+                emulator.run_tick();
+            }
+
+            // Reading work RAM mid-transfer sees the byte being copied instead
+            assert_eq!(emulator.cpu_state().e, 0x12);
+
+            // Bytes are copied one at a time, so OAM is only partially written
+            assert_eq!(emulator.oam[0], 0x10);
+            assert_eq!(emulator.oam[OAM_SIZE - 1], 0x00);
+
+            // Code in HRAM keeps running until the transfer completes and it returns
+            while emulator.regs().pc() != END_ADDRESS {
+                emulator.run_tick();
+            }
+
+            assert_eq!(emulator.oam[..], source[..]);
+            assert_eq!(emulator.cpu_state().d, 0x10);
+        });
+    }
+
+    #[test]
+    fn bad_logo_can_skip_boot_rom() {
+        with_large_stack(|| {
+            for skip_boot_on_bad_logo in [false, true] {
+                // Boot ROM that locks up, like the real one does when the logo check fails
+                let mut bios = vec![0x00; 0x100];
+                bios[..2].copy_from_slice(&[0x18, 0xFE]); // jr -2
+
+                let rom = build_bad_logo_test_rom(&[0x18, 0xFE]); // jr -2
+                let options = Options {
+                    skip_boot_on_bad_logo,
+                    ..Options::default()
+                };
+                let mut emulator = EmulatorBuilder::new_cartridge(
+                    Cartridge::new_from_rom_bytes(rom).unwrap(),
+                    Machine::Dmg,
+                )
+                .with_bios(bios)
+                .with_options(Arc::new(options))
+                .build()
+                .unwrap();
+                emulator.power_on();
+                emulator.run_frames(1);
+
+                if skip_boot_on_bad_logo {
+                    assert!(!emulator.is_booting());
+                    assert_eq!(emulator.cpu_state().pc, PROGRAM_START as u16);
+                } else {
+                    assert!(emulator.is_booting());
+                    assert_eq!(emulator.cpu_state().pc, 0x0000);
+                }
+            }
+        });
+    }
+
+    const TIMA_ADDRESS: u16 = 0xFF05;
+    const TMA_ADDRESS: u16 = 0xFF06;
+
+    /// Number of ticks after the timer starts until TIMA overflows, at 16 ticks per increment
+    const TICKS_TO_TIMA_OVERFLOW: usize = 16;
+
+    /// Emulator whose TIMA overflows and is reloaded with 0x42 after `TICKS_TO_TIMA_OVERFLOW`
+    /// calls to `increment_timers`.
+    fn new_tima_overflow_emulator() -> Emulator {
+        let rom = build_test_rom(0x00, 0x00, 0x00, &[0x18, 0xFE]);
+        let mut emulator = EmulatorBuilder::new_cartridge(
+            Cartridge::new_from_rom_bytes(rom).unwrap(),
+            Machine::Dmg,
+        )
+        .build()
+        .unwrap();
+        emulator.emulate_boot_sequence();
+
+        emulator.reset_divider_register();
+        emulator.write_tac(0x05);
+        emulator.write_tima(0xFF);
+        emulator.write_tma(0x42);
+        emulator.write_if_reg(0x00);
+
+        emulator
+    }
+
+    fn is_timer_interrupt_requested(emulator: &Emulator) -> bool {
+        emulator.if_reg() & Interrupt::Timer.flag_bit() != 0
+    }
+
+    #[test]
+    fn timer_reads_during_tima_overflow() {
+        with_large_stack(|| {
+            // (TIMA, whether the timer interrupt is requested) after each tick starting with the
+            // tick that TIMA overflows on
+            const EXPECTED: [(u8, bool); 10] = [
+                (0x00, false),
+                (0x00, false),
+                (0x00, false),
+                (0x00, false),
+                (0x42, true),
+                (0x42, true),
+                (0x42, true),
+                (0x42, true),
+                (0x42, true),
+                (0x42, true),
+            ];
+
+            let mut emulator = new_tima_overflow_emulator();
+            for _ in 0..TICKS_TO_TIMA_OVERFLOW - 1 {
+                emulator.increment_timers();
+            }
+            assert_eq!(emulator.read_io_register(TIMA_ADDRESS), 0xFF);
+
+            for (tick, (tima, is_interrupt_requested)) in EXPECTED.into_iter().enumerate() {
+                emulator.increment_timers();
+
+                assert_eq!(emulator.read_io_register(TIMA_ADDRESS), tima, "tick {tick}");
+                assert_eq!(
+                    is_timer_interrupt_requested(&emulator),
+                    is_interrupt_requested,
+                    "tick {tick}"
+                );
+
+                // DIV, TMA, and TAC are not affected by the overflow
+                assert_eq!(emulator.div(), 0x00);
+                assert_eq!(emulator.read_io_register(TMA_ADDRESS), 0x42);
+                assert_eq!(emulator.tac(), 0x05);
+            }
+        });
+    }
+
+    #[test]
+    fn timer_writes_during_tima_overflow() {
+        with_large_stack(|| {
+            // (TIMA, whether the timer interrupt is requested) once the overflow has finished, for
+            // a write to TIMA of 0x99 after each tick starting with the tick that TIMA overflows on
+            const EXPECTED_AFTER_TIMA_WRITE: [(u8, bool); 10] = [
+                // Cancels the reload and interrupt
+                (0x99, false),
+                (0x99, false),
+                (0x99, false),
+                (0x99, false),
+                // Ignored while TIMA is being reloaded
+                (0x42, true),
+                (0x42, true),
+                (0x42, true),
+                (0x42, true),
+                (0x99, true),
+                (0x99, true),
+            ];
+
+            // TIMA once the overflow has finished, for a write to TMA of 0x77 after each tick
+            // starting with the tick that TIMA overflows on
+            #[rustfmt::skip]
+            const EXPECTED_AFTER_TMA_WRITE: [u8; 10] = [
+                // Written before the reload, so TIMA is reloaded with the new value
+                0x77, 0x77, 0x77, 0x77,
+                // Written during the reload, so also written to TIMA
+                0x77, 0x77, 0x77, 0x77,
+                // Written after the reload
+                0x42, 0x42,
+            ];
+
+            let run_overflow = |address: u16, value: u8, write_tick: usize| {
+                let mut emulator = new_tima_overflow_emulator();
+                for tick in 0..TICKS_TO_TIMA_OVERFLOW + EXPECTED_AFTER_TMA_WRITE.len() {
+                    emulator.increment_timers();
+
+                    if tick + 1 == TICKS_TO_TIMA_OVERFLOW + write_tick {
+                        emulator.write_io_register(address, value);
+                    }
+                }
+
+                emulator
+            };
+
+            for (write_tick, (tima, is_interrupt_requested)) in
+                EXPECTED_AFTER_TIMA_WRITE.into_iter().enumerate()
+            {
+                let emulator = run_overflow(TIMA_ADDRESS, 0x99, write_tick);
+                assert_eq!(emulator.tima(), tima, "write on tick {write_tick}");
+                assert_eq!(
+                    is_timer_interrupt_requested(&emulator),
+                    is_interrupt_requested,
+                    "write on tick {write_tick}"
+                );
+            }
+
+            for (write_tick, tima) in EXPECTED_AFTER_TMA_WRITE.into_iter().enumerate() {
+                let emulator = run_overflow(TMA_ADDRESS, 0x77, write_tick);
+                assert_eq!(emulator.tima(), tima, "write on tick {write_tick}");
+                assert!(is_timer_interrupt_requested(&emulator));
+            }
+        });
+    }
+
+    #[test]
+    fn divider_and_tac_writes_increment_tima_on_falling_edge() {
+        with_large_stack(|| {
+            const DIV_ADDRESS: u16 = 0xFF04;
+            const TAC_ADDRESS: u16 = 0xFF07;
+
+            let frequencies = [
+                (0b00, TAC_MASK_1024_TICKS),
+                (0b01, TAC_MASK_16_TICKS),
+                (0b10, TAC_MASK_64_TICKS),
+                (0b11, TAC_MASK_256_TICKS),
+            ];
+
+            for (tac_bits, tac_mask) in frequencies {
+                // Emulator with the timer enabled at this frequency, run until the divider is
+                // `divider`. TIMA is 0.
+                let new_timer_emulator = |divider: u16| {
+                    let mut emulator = new_tima_overflow_emulator();
+                    emulator.write_io_register(TAC_ADDRESS, 0x04 | tac_bits);
+                    for _ in 0..divider {
+                        emulator.increment_timers();
+                    }
+                    emulator.write_io_register(TIMA_ADDRESS, 0x00);
+                    emulator
+                };
+
+                // (divider, TAC written or None to write DIV, TIMA after the write)
+                let other_tac_bits = (tac_bits + 1) & 0x03;
+                let cases = [
+                    // Resetting the divider while the selected bit is set
+                    (tac_mask, None, 1),
+                    (tac_mask - 1, None, 0),
+                    // Disabling the timer while the selected bit is set
+                    (tac_mask, Some(tac_bits), 1),
+                    (tac_mask - 1, Some(tac_bits), 0),
+                    // Selecting a bit that is clear while the old one is set
+                    (tac_mask, Some(0x04 | other_tac_bits), 1),
+                    (tac_mask, Some(0x04 | tac_bits), 0),
+                ];
+
+                for (divider, tac, expected_tima) in cases {
+                    let mut emulator = new_timer_emulator(divider);
+                    match tac {
+                        Some(tac) => emulator.write_io_register(TAC_ADDRESS, tac),
+                        None => emulator.write_io_register(DIV_ADDRESS, 0x00),
+                    }
+
+                    assert_eq!(
+                        emulator.read_io_register(TIMA_ADDRESS),
+                        expected_tima,
+                        "TAC bits {tac_bits:02b}, divider {divider:04X}, TAC write {tac:?}"
+                    );
+                }
+            }
+        });
+    }
+
+    #[rustfmt::skip]
+    const COUNTER_PROGRAM: [u8; 6] = [
+        0x21, 0x00, 0xC0, // ld hl, 0xC000
+        0x34,             // inc [hl]
+        0x18, 0xFD,       // jr -3
+    ];
+
+    /// Serialized registers and work RAM, which change every frame while running `COUNTER_PROGRAM`.
+    fn counter_state(emulator: &Emulator) -> (Vec<u8>, Vec<u8>) {
+        (
+            rmp_serde::to_vec(&emulator.regs).unwrap(),
+            emulator.work_ram.clone(),
+        )
+    }
+
+    #[test]
+    fn rewind_restores_earlier_snapshots() {
+        with_large_stack(|| {
+            let (commands_tx, commands_rx) = channel();
+            let (events_tx, _events_rx) = channel();
+
+            let rom = build_test_rom(0x00, 0x00, 0x00, &COUNTER_PROGRAM);
+            let mut emulator = EmulatorBuilder::new_cartridge(
+                Cartridge::new_from_rom_bytes(rom).unwrap(),
+                Machine::Dmg,
+            )
+            .with_options(Arc::new(Options {
+                rewind_interval_frames: 2,
+                ..Options::default()
+            }))
+            .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+            .build()
+            .unwrap();
+            emulator.emulate_boot_sequence();
+
+            // Snapshots are taken after every second frame
+            let mut snapshot_states = vec![];
+            for frame in 1..=6 {
+                emulator.run_frame();
+                if frame % 2 == 0 {
+                    snapshot_states.push(counter_state(&emulator));
+                }
+            }
+            assert_eq!(emulator.rewind_buffer.len(), 3);
+
+            emulator.run_frame();
+            let latest_state = counter_state(&emulator);
+            assert_ne!(&latest_state, snapshot_states.last().unwrap());
+
+            commands_tx.send(Command::SetRewinding(true)).unwrap();
+            emulator.handle_commands();
+            assert!(emulator.is_rewinding);
+
+            // Each rewound frame steps back to the previous snapshot, keeping the rest of the
+            // snapshots and the rewinding state
+            for expected_state in snapshot_states.iter().rev() {
+                emulator.rewind_frame();
+                assert_eq!(&counter_state(&emulator), expected_state);
+                assert!(emulator.is_rewinding);
+            }
+            assert!(emulator.rewind_buffer.is_empty());
+
+            // Stays on the oldest snapshot once there are none left
+            emulator.rewind_frame();
+            assert_eq!(counter_state(&emulator), snapshot_states[0]);
+
+            // Commands are still received after restoring snapshots, and running resumes from the
+            // rewound state
+            commands_tx.send(Command::SetRewinding(false)).unwrap();
+            emulator.rewind_frame();
+            assert!(!emulator.is_rewinding);
+
+            emulator.run_frame();
+            emulator.run_frame();
+            assert_eq!(counter_state(&emulator), snapshot_states[1]);
+            assert_eq!(emulator.rewind_buffer.len(), 1);
+        });
+    }
+
+    #[rustfmt::skip]
+    const JOYPAD_POLL_PROGRAM: [u8; 11] = [
+        0x3E, 0x10,       // ld a, 0x10 (select action buttons)
+        0xE0, 0x00,       // ldh [0x00], a
+        0xF0, 0x00,       // ldh a, [0x00]
+        0xEA, 0x00, 0xC0, // ld [0xC000], a
+        0x18, 0xF9,       // jr -7
+    ];
+
+    #[test]
+    fn frame_advance_runs_one_frame() {
+        with_large_stack(|| {
+            let (commands_tx, commands_rx) = channel();
+            let (events_tx, _events_rx) = channel();
+
+            // Ticks are stepped one at a time to check exactly when the frame advance ends
+            let rom = build_test_rom(0x00, 0x00, 0x00, &JOYPAD_POLL_PROGRAM);
+            let mut emulator = EmulatorBuilder::new_cartridge(
+                Cartridge::new_from_rom_bytes(rom).unwrap(),
+                Machine::Dmg,
+            )
+            .with_options(Arc::new(Options {
+                per_tick_stepping: true,
+                ..Options::default()
+            }))
+            .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+            .build()
+            .unwrap();
+            emulator.emulate_boot_sequence();
+            emulator.run_frame();
+
+            // A is not pressed
+            assert_eq!(emulator.work_ram[0] & 0x01, 0x01);
+
+            for _ in 0..TICKS_PER_FRAME / 3 {
+                emulator.run_tick();
+            }
+            let start_tick = emulator.tick;
+            let num_rendered_frames = emulator.num_rendered_frames();
+
+            // Buttons pressed while paused are applied before the advanced frame, while commands
+            // after the frame advance wait until the frame has finished.
+            commands_tx.send(Command::TogglePause).unwrap();
+            commands_tx
+                .send(Command::UpdatePressedButtons(Button::A as u8))
+                .unwrap();
+            commands_tx.send(Command::FrameAdvance).unwrap();
+            commands_tx.send(Command::TogglePause).unwrap();
+            emulator.handle_commands();
+
+            assert!(!emulator.is_paused());
+            assert_eq!(emulator.pressed_buttons, Button::A as u8);
+
+            for _ in 0..TICKS_PER_FRAME - 1 {
+                assert_eq!(emulator.run_tick(), 1);
+                assert!(emulator.frame_advance_ticks_remaining.is_some());
+            }
+
+            // The game saw A pressed during the advanced frame
+            assert_eq!(emulator.work_ram[0] & 0x01, 0x00);
+
+            // The last tick of the frame pauses again, and the queued command resumes
+            emulator.run_tick();
+            assert!(emulator.frame_advance_ticks_remaining.is_none());
+            assert!(!emulator.is_paused());
+
+            assert_eq!(emulator.tick, start_tick);
+            assert_eq!(emulator.num_rendered_frames(), num_rendered_frames + 1);
+        });
+    }
+
+    #[test]
+    fn frame_advance_is_ignored_while_running() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, _events_rx) = new_commanded_emulator();
+
+            commands_tx.send(Command::FrameAdvance).unwrap();
+            emulator.handle_commands();
+
+            assert!(emulator.frame_advance_ticks_remaining.is_none());
+            assert!(!emulator.is_paused());
+        });
+    }
+
+    #[test]
+    fn write_memory_command_has_no_side_effects() {
+        with_large_stack(|| {
+            let (mut emulator, commands_tx, _events_rx) = new_commanded_emulator();
+
+            commands_tx
+                .send(Command::WriteMemory(0xC123, 0x42))
+                .unwrap();
+            // Written raw, so no OAM DMA transfer is started
+            commands_tx
+                .send(Command::WriteMemory(0xFF46, 0xC0))
+                .unwrap();
+            emulator.handle_commands();
+
+            assert_eq!(emulator.peek_address(0xC123), 0x42);
+            assert_eq!(emulator.peek_address(0xE123), 0x42);
+            assert_eq!(emulator.peek_address(0xFF46), 0xC0);
+            assert!(emulator.current_oam_dma_transfer.is_none());
+        });
+    }
+
+    #[test]
+    fn patch_cartridge_ram_bypasses_ram_enable() {
+        with_large_stack(|| {
+            let (commands_tx, commands_rx) = channel();
+            let (events_tx, events_rx) = channel();
+
+            // MBC1 with battery and 32KB of RAM, which the game never enables
+            let rom = build_test_rom(0x03, 0x00, 0x03, &FILL_VRAM_PROGRAM);
+            let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+                .build()
+                .unwrap();
+            emulator.emulate_boot_sequence();
+            assert_eq!(emulator.cartridge_ram().len(), 0x8000);
+            assert_eq!(emulator.mbc_debug_state().is_ram_enabled, Some(false));
+
+            commands_tx
+                .send(Command::PatchCartridgeRam(0x2005, vec![1, 2, 3], 1))
+                .unwrap();
+            emulator.handle_commands();
+
+            assert_eq!(
+                events_rx.try_recv(),
+                Ok(EmulatorEvent::CommandResult {
+                    command_id: 1,
+                    result: Ok(()),
+                })
+            );
+            assert_eq!(&emulator.cartridge_ram()[0x2004..0x2009], &[0, 1, 2, 3, 0]);
+
+            // Patches that run past the end of RAM are rejected without writing anything
+            commands_tx
+                .send(Command::PatchCartridgeRam(0x7FFE, vec![4, 5, 6], 2))
+                .unwrap();
+            emulator.handle_commands();
+
+            assert!(matches!(
+                events_rx.try_recv(),
+                Ok(EmulatorEvent::CommandResult {
+                    command_id: 2,
+                    result: Err(CommandError::InvalidData(_)),
+                })
+            ));
+            assert_eq!(&emulator.cartridge_ram()[0x7FFE..], &[0, 0]);
+
+            // Exported RAM can be imported again after further patches
+            let path = test_dir("cartridge-ram").join("ram.sav");
+            commands_tx
+                .send(Command::ExportCartridgeRam(path.clone(), 3))
+                .unwrap();
+            commands_tx
+                .send(Command::PatchCartridgeRam(0x2005, vec![0xFF], 4))
+                .unwrap();
+            commands_tx
+                .send(Command::ImportCartridgeRam(path, 5))
+                .unwrap();
+            emulator.handle_commands();
+
+            for command_id in 3..=5 {
+                assert_eq!(
+                    events_rx.try_recv(),
+                    Ok(EmulatorEvent::CommandResult {
+                        command_id,
+                        result: Ok(()),
+                    })
+                );
+            }
+            assert_eq!(&emulator.cartridge_ram()[0x2005..0x2008], &[1, 2, 3]);
+        });
+    }
+
+    /// Plays square wave and noise, runs the timer, and halts between VBlank
+    /// and timer interrupts, counting them in D and E.
+    #[rustfmt::skip]
+    const TIMER_AND_AUDIO_PROGRAM: [u8; 34] = [
+        0x3E, 0x80, // ld a, 0x80
+        0xE0, 0x26, // ldh [NR52], a (enable APU)
+        0x3E, 0xF0, // ld a, 0xF0
+        0xE0, 0x12, // ldh [NR12], a
+        0xE0, 0x21, // ldh [NR42], a
+        0x3E, 0x87, // ld a, 0x87
+        0xE0, 0x14, // ldh [NR14], a (trigger channel 1)
+        0xE0, 0x23, // ldh [NR44], a (trigger channel 4)
+        0x3E, 0x07, // ld a, 0x07
+        0xE0, 0x07, // ldh [TAC], a (enable timer every 256 ticks)
+        0x3E, 0x05, // ld a, 0x05
+        0xE0, 0xFF, // ldh [IE], a (enable VBlank and timer interrupts)
+        0xFB,       // ei
+        0x76,       // loop: halt
+        0x04,       // inc b
+        0x7E,       // ld a, [hl]
+        0x23,       // inc hl
+        0x18, 0xFA, // jr loop
+        0x00, 0x00, 0x00,
+    ];
+
+    /// Switches to double speed mode, then runs `TIMER_AND_AUDIO_PROGRAM`.
+    #[rustfmt::skip]
+    const DOUBLE_SPEED_PREFIX: [u8; 5] = [
+        0x3E, 0x01, // ld a, 0x01
+        0xE0, 0x4D, // ldh [KEY1], a
+        0x10,       // stop
+    ];
+
+    fn new_stepping_emulator(rom: Vec<u8>, machine: Machine, per_tick_stepping: bool) -> Emulator {
+        let mut rom = rom;
+        rom[0x40..0x42].copy_from_slice(&[0x14, 0xD9]); // VBlank: inc d, reti
+        rom[0x50..0x52].copy_from_slice(&[0x1C, 0xD9]); // Timer: inc e, reti
+
+        let mut emulator =
+            EmulatorBuilder::new_cartridge(Cartridge::new_from_rom_bytes(rom).unwrap(), machine)
+                .with_options(Arc::new(Options {
+                    per_tick_stepping,
+                    ..Options::default()
+                }))
+                .build()
+                .unwrap();
+        emulator.emulate_boot_sequence();
+        emulator
+    }
+
+    /// Run the ROM with every tick stepped on its own and with idle ticks batched, and check that
+    /// the emulator state is identical after every frame.
+    fn assert_batched_ticks_match_per_tick_stepping(rom: Vec<u8>, machine: Machine) {
+        let mut per_tick = new_stepping_emulator(rom.clone(), machine, true);
+        let mut batched = new_stepping_emulator(rom, machine, false);
+
+        let mut num_batched_calls = 0;
+        for frame in 0..10 {
+            per_tick.run_frame();
+
+            let mut num_ticks = 0;
+            while num_ticks < TICKS_PER_FRAME {
+                num_ticks += batched.run_tick();
+                num_batched_calls += 1;
+            }
+
+            assert!(
+                rmp_serde::to_vec(&per_tick).unwrap() == rmp_serde::to_vec(&batched).unwrap(),
+                "state differs after frame {}",
+                frame
+            );
+        }
+
+        // The interrupts fired and many ticks were batched, even though drawing is never batched
+        let cpu_state = batched.cpu_state();
+        assert!(cpu_state.d > 5 && cpu_state.e > 5);
+        assert!(num_batched_calls < 10 * TICKS_PER_FRAME * 3 / 4);
+    }
+
+    #[test]
+    fn batched_ticks_match_per_tick_stepping() {
+        with_large_stack(|| {
+            let rom = build_test_rom(0x00, 0x00, 0x00, &TIMER_AND_AUDIO_PROGRAM);
+            assert_batched_ticks_match_per_tick_stepping(rom, Machine::Dmg);
+
+            let program = [&DOUBLE_SPEED_PREFIX[..], &TIMER_AND_AUDIO_PROGRAM].concat();
+            let rom = build_cgb_test_rom(0x00, 0x00, 0x00, &program);
+            assert_batched_ticks_match_per_tick_stepping(rom, Machine::Cgb);
+        });
+    }
+}
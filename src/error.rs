@@ -0,0 +1,166 @@
+//! Errors from loading a ROM, save file, or BIOS, which are reported to the user instead of
+//! crashing the emulator.
+
+use std::{fmt, io};
+
+use zip::result::ZipError;
+
+use crate::{emulator::BiosError, save_file::SaveFileError, save_paths::SavePathsError};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The ROM is too small to contain a cartridge header
+    RomTooSmall {
+        size: usize,
+    },
+    /// The ROM's size does not match the ROM size declared in its header
+    RomSizeMismatch {
+        size: usize,
+        expected: usize,
+    },
+    UnsupportedRomSize(u8),
+    UnsupportedRamSize(u8),
+    /// The header's cartridge type is not one of the supported MBCs
+    UnsupportedMbc(u8),
+    /// The header checksum does not match the header, which the boot ROM refuses to run
+    HeaderChecksumMismatch {
+        found: u8,
+        expected: u8,
+    },
+    /// A file without a ROM extension does not have a valid ROM header
+    NotARom,
+    /// A zip archive could not be read
+    InvalidArchive(String),
+    /// A zip archive does not contain a .gb or .gbc file
+    NoRomInArchive,
+    /// A ROM in a zip archive is larger than any supported ROM
+    RomTooLarge {
+        max_size: usize,
+    },
+    /// The save data was written with a newer format version than this build supports. Includes
+    /// the version of the emulator that wrote it, if known.
+    SaveVersionMismatch {
+        found: u16,
+        supported: u16,
+        written_by: Option<String>,
+    },
+    /// The save data belongs to a different ROM than the one it is being loaded with
+    SaveRomMismatch,
+    /// Save data is truncated or malformed
+    Decode(String),
+    Bios(BiosError),
+    SavePaths(SavePathsError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::RomTooSmall { size } => write!(
+                f,
+                "ROM is {} bytes, which is too small to contain a cartridge header",
+                size
+            ),
+            Error::RomSizeMismatch { size, expected } => write!(
+                f,
+                "ROM is {} bytes but its header declares {} bytes",
+                size, expected
+            ),
+            Error::UnsupportedRomSize(rom_size_byte) => {
+                write!(f, "unsupported ROM size 0x{:02X}", rom_size_byte)
+            }
+            Error::UnsupportedRamSize(ram_size_byte) => {
+                write!(f, "unsupported RAM size 0x{:02X}", ram_size_byte)
+            }
+            Error::UnsupportedMbc(cartridge_type) => {
+                write!(f, "unsupported cartridge type 0x{:02X}", cartridge_type)
+            }
+            Error::HeaderChecksumMismatch { found, expected } => write!(
+                f,
+                "header checksum is 0x{:02X} but the header sums to 0x{:02X}",
+                found, expected
+            ),
+            Error::NotARom => write!(
+                f,
+                "not a GameBoy ROM or save file, since it does not have a valid ROM header"
+            ),
+            Error::InvalidArchive(reason) => write!(f, "could not read zip archive: {}", reason),
+            Error::NoRomInArchive => {
+                write!(f, "zip archive does not contain a .gb or .gbc file")
+            }
+            Error::RomTooLarge { max_size } => write!(
+                f,
+                "ROM is larger than the largest supported ROM size of {} bytes",
+                max_size
+            ),
+            Error::SaveVersionMismatch {
+                found,
+                supported,
+                written_by,
+            } => {
+                write!(f, "save format version {}", found)?;
+                if let Some(written_by) = written_by {
+                    write!(f, " written by version {}", written_by)?;
+                }
+                write!(
+                    f,
+                    " is not supported (newest supported version is {})",
+                    supported
+                )
+            }
+            Error::SaveRomMismatch => write!(f, "save data belongs to a different ROM"),
+            Error::Decode(reason) => write!(f, "save data is corrupt: {}", reason),
+            Error::Bios(error) => write!(f, "{}", error),
+            Error::SavePaths(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<ZipError> for Error {
+    fn from(error: ZipError) -> Self {
+        match error {
+            ZipError::Io(error) => Error::Io(error),
+            error => Error::InvalidArchive(error.to_string()),
+        }
+    }
+}
+
+impl From<SaveFileError> for Error {
+    fn from(error: SaveFileError) -> Self {
+        match error {
+            SaveFileError::SaveFormatMismatch {
+                found,
+                supported,
+                written_by,
+            } => Error::SaveVersionMismatch {
+                found,
+                supported,
+                written_by,
+            },
+            SaveFileError::Corrupt(reason) => Error::Decode(reason),
+            SaveFileError::RomMismatch => Error::SaveRomMismatch,
+            error @ SaveFileError::RawSizeMismatch { .. } => Error::Decode(error.to_string()),
+        }
+    }
+}
+
+impl From<BiosError> for Error {
+    fn from(error: BiosError) -> Self {
+        Error::Bios(error)
+    }
+}
+
+impl From<SavePathsError> for Error {
+    fn from(error: SavePathsError) -> Self {
+        Error::SavePaths(error)
+    }
+}
@@ -0,0 +1,298 @@
+use crate::emulator::NORMAL_SPEED;
+
+/// `REFRESH_RATE` as a fraction, so that frame start times can be computed exactly with integers
+/// no matter how long the emulator has been running.
+const REFRESH_RATE_NUMERATOR: u128 = 597;
+const REFRESH_RATE_DENOMINATOR: u128 = 10;
+
+/// Speeds are stored in thousandths, so that frame start times at fractional speeds are also
+/// computed exactly with integers.
+const SPEED_DENOMINATOR: u128 = 1000;
+
+const NS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Furthest behind schedule the emulator catches up from by skipping frames. Past this, e.g. after
+/// the system was suspended, pacing restarts from the current time instead.
+const MAX_CATCH_UP_NANOS: u64 = 1_000_000_000;
+
+/// What was done to get back on schedule after a frame finished late.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CatchUp {
+    /// Skipped this many frames whose start time had passed
+    SkippedFrames(u64),
+    /// Too far behind to catch up, so pacing was restarted from the current time
+    Reanchored { behind_nanos: u64 },
+}
+
+/// Decides when each frame should start. Times are nanoseconds since the emulator started running,
+/// so that the pacer can be driven by any clock.
+///
+/// Start times are measured from an anchor, which moves when the speed changes so that frames at
+/// the new speed are spaced from the current frame, and when the emulator falls too far behind to
+/// catch up.
+pub struct FramePacer {
+    /// Current frame number
+    frame: u64,
+
+    /// Frame that started at `anchor_nanos`
+    anchor_frame: u64,
+
+    /// Time at which `anchor_frame` started
+    anchor_nanos: u64,
+
+    /// Speed as a multiple of normal speed, in units of `1 / SPEED_DENOMINATOR`
+    speed: u128,
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        Self {
+            frame: 0,
+            anchor_frame: 0,
+            anchor_nanos: 0,
+            speed: speed_to_fraction(NORMAL_SPEED),
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Move on to the frame the given number of frames after the current one.
+    pub fn advance(&mut self, num_frames: u64) {
+        self.frame += num_frames;
+    }
+
+    /// Change the speed at which frames are run, starting from the current frame.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.anchor_nanos = self.expected_frame_start_nanos();
+        self.anchor_frame = self.frame;
+        self.speed = speed_to_fraction(speed);
+    }
+
+    /// The expected start time of the current frame.
+    pub fn expected_frame_start_nanos(&self) -> u64 {
+        let num_frames = (self.frame - self.anchor_frame) as u128;
+        let nanos_since_anchor =
+            num_frames * NS_PER_SECOND * REFRESH_RATE_DENOMINATOR * SPEED_DENOMINATOR
+                / (REFRESH_RATE_NUMERATOR * self.speed);
+
+        self.anchor_nanos + nanos_since_anchor as u64
+    }
+
+    /// Skip frames whose expected start time has already passed, so that the next frame starts no
+    /// later than `now_nanos`.
+    pub fn catch_up(&mut self, now_nanos: u64) -> CatchUp {
+        let behind_nanos = now_nanos.saturating_sub(self.expected_frame_start_nanos());
+        if behind_nanos > MAX_CATCH_UP_NANOS {
+            self.anchor_frame = self.frame;
+            self.anchor_nanos = now_nanos;
+            return CatchUp::Reanchored { behind_nanos };
+        }
+
+        let mut num_skipped = 0;
+        while self.expected_frame_start_nanos() <= now_nanos {
+            self.advance(1);
+            num_skipped += 1;
+        }
+
+        CatchUp::SkippedFrames(num_skipped)
+    }
+}
+
+/// Speed in units of `1 / SPEED_DENOMINATOR`, never zero.
+fn speed_to_fraction(speed: f64) -> u128 {
+    ((speed * SPEED_DENOMINATOR as f64).round() as u128).max(1)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::{MAX_SPEED, REFRESH_RATE};
+
+    use super::{
+        CatchUp, FramePacer, MAX_CATCH_UP_NANOS, REFRESH_RATE_DENOMINATOR, REFRESH_RATE_NUMERATOR,
+    };
+
+    const NS_PER_FRAME: u64 = (1_000_000_000.0 / REFRESH_RATE) as u64;
+
+    /// A clock that only moves when told to.
+    struct MockClock {
+        now_nanos: u64,
+    }
+
+    impl MockClock {
+        fn advance(&mut self, nanos: u64) {
+            self.now_nanos += nanos;
+        }
+    }
+
+    /// Run a frame that takes `frame_nanos`, returning how the pacer caught up if it finished late.
+    fn run_frame(pacer: &mut FramePacer, clock: &mut MockClock, frame_nanos: u64) -> CatchUp {
+        clock.advance(frame_nanos);
+        pacer.advance(1);
+
+        // Sleep until the next frame if it is not due yet
+        let next_frame_nanos = pacer.expected_frame_start_nanos();
+        if next_frame_nanos > clock.now_nanos {
+            clock.now_nanos = next_frame_nanos;
+            return CatchUp::SkippedFrames(0);
+        }
+
+        pacer.catch_up(clock.now_nanos)
+    }
+
+    #[test]
+    fn refresh_rate_fraction_matches() {
+        assert_eq!(
+            REFRESH_RATE_NUMERATOR as f64 / REFRESH_RATE_DENOMINATOR as f64,
+            REFRESH_RATE
+        );
+    }
+
+    #[test]
+    fn frame_start_times_do_not_drift() {
+        // Ten hours of frames start within a nanosecond of the exact time
+        let mut pacer = FramePacer::new();
+        let num_frames = 10 * 60 * 60 * 597 / 10;
+        pacer.advance(num_frames);
+
+        assert_eq!(
+            pacer.expected_frame_start_nanos(),
+            10 * 60 * 60 * 1_000_000_000
+        );
+    }
+
+    #[test]
+    fn skips_frames_when_slightly_behind() {
+        let mut pacer = FramePacer::new();
+        let mut clock = MockClock { now_nanos: 0 };
+
+        assert_eq!(
+            run_frame(&mut pacer, &mut clock, NS_PER_FRAME / 2),
+            CatchUp::SkippedFrames(0)
+        );
+
+        // The second frame takes two and a half frames, so it finishes partway through the fourth
+        // frame's slot. The third and fourth frames are skipped.
+        assert_eq!(
+            run_frame(&mut pacer, &mut clock, NS_PER_FRAME * 5 / 2),
+            CatchUp::SkippedFrames(2)
+        );
+        assert_eq!(pacer.frame(), 4);
+        assert!(pacer.expected_frame_start_nanos() > clock.now_nanos);
+    }
+
+    #[test]
+    fn reanchors_after_clock_jump() {
+        let mut pacer = FramePacer::new();
+        let mut clock = MockClock { now_nanos: 0 };
+
+        for _ in 0..100 {
+            run_frame(&mut pacer, &mut clock, NS_PER_FRAME / 2);
+        }
+
+        // Two hours pass during a single frame, such as when the system is suspended
+        let jump_nanos = 2 * 60 * 60 * 1_000_000_000;
+        let frame_before_jump = pacer.frame();
+        let catch_up = run_frame(&mut pacer, &mut clock, jump_nanos);
+        assert!(
+            matches!(catch_up, CatchUp::Reanchored { behind_nanos } if behind_nanos > jump_nanos / 2)
+        );
+
+        // No frames were skipped, and the next frame starts right away
+        assert_eq!(pacer.frame(), frame_before_jump + 1);
+        assert_eq!(pacer.expected_frame_start_nanos(), clock.now_nanos);
+
+        // Pacing continues as normal from the new anchor
+        for _ in 0..100 {
+            assert_eq!(
+                run_frame(&mut pacer, &mut clock, NS_PER_FRAME / 2),
+                CatchUp::SkippedFrames(0)
+            );
+        }
+    }
+
+    #[test]
+    fn catch_up_work_is_bounded() {
+        // Falling behind by just under the limit skips at most a second of frames
+        let mut pacer = FramePacer::new();
+        match pacer.catch_up(MAX_CATCH_UP_NANOS) {
+            CatchUp::SkippedFrames(num_skipped) => assert!(num_skipped <= 60),
+            catch_up => panic!("unexpected {:?}", catch_up),
+        }
+
+        // Even at the fastest speed
+        let mut pacer = FramePacer::new();
+        pacer.set_speed(MAX_SPEED);
+        match pacer.catch_up(MAX_CATCH_UP_NANOS) {
+            CatchUp::SkippedFrames(num_skipped) => {
+                assert!(num_skipped <= 60 * MAX_SPEED as u64)
+            }
+            catch_up => panic!("unexpected {:?}", catch_up),
+        }
+
+        // Anything further behind does no skipping at all
+        let mut pacer = FramePacer::new();
+        assert!(matches!(
+            pacer.catch_up(MAX_CATCH_UP_NANOS + 1),
+            CatchUp::Reanchored { .. }
+        ));
+        assert_eq!(pacer.frame(), 0);
+    }
+
+    /// Run frames that each take a tenth of a frame at normal speed for `seconds`, returning the
+    /// number of frames run.
+    fn count_frames_run(pacer: &mut FramePacer, clock: &mut MockClock, seconds: u64) -> u64 {
+        let end_nanos = clock.now_nanos + seconds * 1_000_000_000;
+        let mut num_frames = 0;
+        while clock.now_nanos < end_nanos {
+            run_frame(pacer, clock, NS_PER_FRAME / 10);
+            num_frames += 1;
+        }
+
+        num_frames
+    }
+
+    #[test]
+    fn frames_run_at_fractional_speeds() {
+        for speed in [0.5, 0.75, 1.0, 1.5, 2.5] {
+            let mut pacer = FramePacer::new();
+            let mut clock = MockClock { now_nanos: 0 };
+            pacer.set_speed(speed);
+
+            // An hour at the given speed runs the expected number of frames, with no drift
+            let num_frames = count_frames_run(&mut pacer, &mut clock, 60 * 60);
+            let expected_num_frames = (60.0 * 60.0 * REFRESH_RATE * speed) as u64;
+            assert!(
+                num_frames.abs_diff(expected_num_frames) <= 1,
+                "{} frames at speed {}, expected {}",
+                num_frames,
+                speed,
+                expected_num_frames
+            );
+        }
+    }
+
+    #[test]
+    fn speed_changes_apply_from_current_frame() {
+        let mut pacer = FramePacer::new();
+        let mut clock = MockClock { now_nanos: 0 };
+
+        assert_eq!(count_frames_run(&mut pacer, &mut clock, 10), 597);
+
+        // The next frame keeps its start time, and only later frames are spaced at the new speed
+        let next_frame_nanos = pacer.expected_frame_start_nanos();
+        pacer.set_speed(0.5);
+        assert_eq!(pacer.expected_frame_start_nanos(), next_frame_nanos);
+        assert_eq!(count_frames_run(&mut pacer, &mut clock, 10), 299);
+
+        pacer.set_speed(1.5);
+        assert_eq!(count_frames_run(&mut pacer, &mut clock, 10), 896);
+    }
+}
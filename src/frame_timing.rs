@@ -0,0 +1,131 @@
+//! Accounting of where emulation time goes within each frame, for optimizing the core without a
+//! full profiler run.
+//!
+//! Timing is only measured when the `frame-timing` feature is enabled. Otherwise `TimingScope` is
+//! zero-sized and starting or finishing a scope compiles to nothing, so the scopes can be left in
+//! the hottest paths of the emulator.
+
+use std::time::Duration;
+
+#[cfg(feature = "frame-timing")]
+use std::time::Instant;
+
+/// The parts of a frame that time is attributed to.
+#[derive(Clone, Copy)]
+pub enum TimingCategory {
+    /// All ticks of the frame, including the draw and audio time within them
+    Frame,
+    /// Drawing scanlines
+    Draw,
+    /// Generating audio samples and sending them to the audio output
+    Audio,
+    /// Flushing the save file to disk after the frame
+    SaveFlush,
+}
+
+/// Time spent in each part of a single frame. All zero unless the `frame-timing` feature is
+/// enabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTimings {
+    /// Total time spent running ticks, which includes `draw` and `audio`
+    pub ticks: Duration,
+    pub draw: Duration,
+    pub audio: Duration,
+    /// Time spent flushing the save file. Not part of running ticks, since flushes happen between
+    /// frames.
+    pub save_flush: Duration,
+}
+
+impl FrameTimings {
+    pub fn add(&mut self, category: TimingCategory, duration: Duration) {
+        match category {
+            TimingCategory::Frame => self.ticks += duration,
+            TimingCategory::Draw => self.draw += duration,
+            TimingCategory::Audio => self.audio += duration,
+            TimingCategory::SaveFlush => self.save_flush += duration,
+        }
+    }
+
+    /// Time spent running ticks outside of drawing and audio, mostly CPU dispatch, timers, and DMA
+    /// bookkeeping.
+    pub fn other_tick_time(&self) -> Duration {
+        self.ticks.saturating_sub(self.draw + self.audio)
+    }
+
+    /// Total time accounted for in the frame.
+    pub fn total(&self) -> Duration {
+        self.ticks + self.save_flush
+    }
+}
+
+/// Measures the time from `start` until `finish`.
+pub struct TimingScope {
+    #[cfg(feature = "frame-timing")]
+    start: Instant,
+}
+
+impl TimingScope {
+    #[inline(always)]
+    pub fn start() -> Self {
+        TimingScope {
+            #[cfg(feature = "frame-timing")]
+            start: Instant::now(),
+        }
+    }
+
+    /// Add the time since the scope was started to a category of the given timings.
+    #[inline(always)]
+    pub fn finish(self, timings: &mut FrameTimings, category: TimingCategory) {
+        #[cfg(feature = "frame-timing")]
+        timings.add(category, self.start.elapsed());
+
+        #[cfg(not(feature = "frame-timing"))]
+        let _ = (timings, category);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "frame-timing")]
+    use std::time::Instant;
+
+    #[cfg(feature = "frame-timing")]
+    use crate::{
+        machine::Machine,
+        test_utils::{FILL_VRAM_PROGRAM, new_test_emulator, with_large_stack},
+    };
+
+    #[cfg(not(feature = "frame-timing"))]
+    use super::TimingScope;
+
+    #[cfg(not(feature = "frame-timing"))]
+    #[test]
+    fn timing_scope_is_free_when_disabled() {
+        assert_eq!(size_of::<TimingScope>(), 0);
+    }
+
+    #[cfg(feature = "frame-timing")]
+    #[test]
+    fn frame_timings_account_for_frame_time() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &FILL_VRAM_PROGRAM);
+            emulator.run_frame();
+
+            for _ in 0..5 {
+                let start = Instant::now();
+                emulator.run_frame();
+                let frame_time = start.elapsed();
+
+                let timings = emulator.frame_timings();
+                assert!(!timings.draw.is_zero());
+                assert!(timings.draw + timings.audio <= timings.ticks);
+
+                // Everything but the loop over ticks in `run_frame` is accounted for
+                let accounted = timings.total().as_secs_f64();
+                let measured = frame_time.as_secs_f64();
+                assert!(accounted <= measured);
+                assert!(accounted >= measured * 0.9, "{accounted} of {measured}");
+            }
+        });
+    }
+}
@@ -0,0 +1,271 @@
+use eframe::egui::{
+    self, Color32, Label, Pos2, RichText, ScrollArea, Sense, TextEdit, Vec2, ViewportId,
+};
+
+use crate::{
+    cartridge::{external_ram_banks, external_ram_location},
+    emulator::Command,
+    gui::{
+        shell::EmulatorShellApp,
+        window_layout::{DOCK_BUTTON_BAR_HEIGHT, DebugView},
+    },
+    mbc::types::RamSelection,
+    save_file::RAW_SAVE_FILE_EXTENSION,
+};
+
+pub const WINDOW_INNER_SIZE: Vec2 = Vec2::new(560.0, 600.0);
+
+/// Number of bytes shown on each row of the hex view
+const BYTES_PER_ROW: usize = 16;
+
+/// Background of the bytes in the bank the CPU currently sees at 0xA000-0xBFFF
+const MAPPED_BANK_COLOR: Color32 = Color32::from_rgb(0x20, 0x40, 0x60);
+
+const SELECTED_BYTE_COLOR: Color32 = Color32::from_rgb(0xC0, 0x90, 0x00);
+
+const BANK_START_COLOR: Color32 = Color32::from_rgb(0x60, 0xC0, 0x60);
+
+fn raw_save_file_dialog() -> rfd::FileDialog {
+    let extension = RAW_SAVE_FILE_EXTENSION.trim_start_matches('.');
+    rfd::FileDialog::new().add_filter("Raw Save File", &[extension])
+}
+
+pub struct CartridgeRamViewport {
+    /// Whether the viewport is currently shown
+    is_shown: bool,
+    /// Initial position of the viewport
+    initial_position: Pos2,
+    /// Offset of the byte selected for editing, if any
+    selected_offset: Option<usize>,
+    /// Text of the new value for the selected byte
+    edit_text: String,
+    /// Edit waiting for the user to confirm it, as an offset and the new value
+    pending_edit: Option<(usize, u8)>,
+}
+
+impl CartridgeRamViewport {
+    pub fn new() -> Self {
+        CartridgeRamViewport {
+            is_shown: false,
+            initial_position: Pos2::ZERO,
+            selected_offset: None,
+            edit_text: String::new(),
+            pending_edit: None,
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.is_shown
+    }
+
+    pub fn open(&mut self, initial_position: Pos2) {
+        self.is_shown = true;
+        self.initial_position = initial_position;
+    }
+
+    pub fn close(&mut self) {
+        self.is_shown = false;
+        self.selected_offset = None;
+        self.pending_edit = None;
+    }
+}
+
+impl EmulatorShellApp {
+    pub fn cartridge_ram_viewport_id(&self) -> ViewportId {
+        ViewportId::from_hash_of("cartridge_ram_viewport_id")
+    }
+
+    pub(super) fn draw_cartridge_ram_viewport(&mut self, ui: &mut egui::Ui) {
+        ui.ctx().show_viewport_immediate(
+            self.cartridge_ram_viewport_id(),
+            egui::ViewportBuilder::default()
+                .with_inner_size(WINDOW_INNER_SIZE + Vec2::new(0.0, DOCK_BUTTON_BAR_HEIGHT))
+                .with_position(self.cartridge_ram_view().initial_position)
+                .with_resizable(true)
+                .with_active(true)
+                .with_title(DebugView::CartridgeRam.title()),
+            |ctx, _| {
+                self.draw_dock_button_bar(DebugView::CartridgeRam, ctx);
+                egui::CentralPanel::default().show(ctx, |ui| self.draw_cartridge_ram_view(ui));
+            },
+        );
+    }
+
+    pub(super) fn draw_cartridge_ram_view(&mut self, ui: &mut egui::Ui) {
+        let ram_size = self.emulator().cartridge_ram().len();
+        if ram_size == 0 {
+            ui.label("This cartridge has no RAM.");
+            return;
+        }
+
+        let banks = external_ram_banks(ram_size);
+        let mbc_state = self.emulator().mbc_debug_state();
+        let mapped_bank = match mbc_state.ram_selection {
+            Some(RamSelection::Bank(bank)) => Some(bank),
+            _ => None,
+        };
+
+        let mapped_text = match mapped_bank {
+            Some(bank) if mbc_state.is_ram_enabled != Some(false) => {
+                format!("bank {bank} is mapped")
+            }
+            Some(bank) => format!("bank {bank} is mapped but RAM is disabled"),
+            None => "no bank is mapped".to_string(),
+        };
+        ui.label(format!(
+            "{} bytes in {} banks, {}.",
+            ram_size,
+            banks.len(),
+            mapped_text
+        ));
+
+        ui.horizontal(|ui| {
+            if ui.button("Export...").clicked() {
+                self.export_cartridge_ram();
+            }
+
+            if ui.button("Import...").clicked() {
+                self.import_cartridge_ram();
+            }
+        });
+        ui.separator();
+
+        self.draw_cartridge_ram_editor(ui);
+        ui.separator();
+
+        let mapped_range = mapped_bank.and_then(|bank| banks.get(bank).cloned());
+        let mut clicked_offset = None;
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let num_rows = ram_size.div_ceil(BYTES_PER_ROW);
+
+        ScrollArea::vertical().auto_shrink(false).show_rows(
+            ui,
+            row_height,
+            num_rows,
+            |ui, row_range| {
+                ui.spacing_mut().item_spacing = Vec2::ZERO;
+
+                let ram = self.emulator().cartridge_ram();
+                let selected_offset = self.cartridge_ram_view().selected_offset;
+
+                for row in row_range {
+                    let row_start = row * BYTES_PER_ROW;
+                    let row_end = (row_start + BYTES_PER_ROW).min(ram_size);
+
+                    ui.horizontal(|ui| {
+                        // Annotate the first row of each bank so that boundaries stand out
+                        let location = external_ram_location(row_start);
+                        let mut address = RichText::new(format!("{}  ", location)).monospace();
+                        if banks.iter().any(|bank| bank.start == row_start) {
+                            address = address.color(BANK_START_COLOR).strong();
+                        }
+                        ui.label(address);
+
+                        for (offset, value) in ram[row_start..row_end].iter().enumerate() {
+                            let offset = row_start + offset;
+
+                            let background_color = if selected_offset == Some(offset) {
+                                SELECTED_BYTE_COLOR
+                            } else if mapped_range
+                                .as_ref()
+                                .is_some_and(|range| range.contains(&offset))
+                            {
+                                MAPPED_BANK_COLOR
+                            } else {
+                                Color32::TRANSPARENT
+                            };
+
+                            let text = RichText::new(format!("{:02X} ", value))
+                                .monospace()
+                                .background_color(background_color);
+                            if ui.add(Label::new(text).sense(Sense::click())).clicked() {
+                                clicked_offset = Some(offset);
+                            }
+                        }
+                    });
+                }
+            },
+        );
+
+        if let Some(offset) = clicked_offset {
+            let value = self.emulator().cartridge_ram()[offset];
+            let view = self.cartridge_ram_view_mut();
+            view.selected_offset = Some(offset);
+            view.edit_text = format!("{:02X}", value);
+            view.pending_edit = None;
+        }
+    }
+
+    /// Draw the editor for the selected byte. Edits must be confirmed since they bypass the game
+    /// and can easily corrupt a save.
+    fn draw_cartridge_ram_editor(&mut self, ui: &mut egui::Ui) {
+        let Some(offset) = self.cartridge_ram_view().selected_offset else {
+            ui.label("Click a byte to edit it.");
+            return;
+        };
+
+        if let Some((offset, value)) = self.cartridge_ram_view().pending_edit {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "Write {:02X} to {}? This may corrupt the save.",
+                    value,
+                    external_ram_location(offset)
+                ));
+
+                if ui.button("Write").clicked() {
+                    self.send_fallible_command("Edit cartridge RAM", |id| {
+                        Command::PatchCartridgeRam(offset, vec![value], id)
+                    });
+                    self.cartridge_ram_view_mut().pending_edit = None;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    self.cartridge_ram_view_mut().pending_edit = None;
+                }
+            });
+            return;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label(format!("{}:", external_ram_location(offset)));
+
+            let view = self.cartridge_ram_view_mut();
+            ui.add(
+                TextEdit::singleline(&mut view.edit_text)
+                    .char_limit(2)
+                    .desired_width(24.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+
+            let value = u8::from_str_radix(view.edit_text.trim(), 16).ok();
+            if ui
+                .add_enabled(value.is_some(), egui::Button::new("Edit..."))
+                .clicked()
+            {
+                view.pending_edit = value.map(|value| (offset, value));
+            }
+        });
+    }
+
+    /// Ask for a file, then write the cartridge RAM to it in the same format as raw save files.
+    fn export_cartridge_ram(&mut self) {
+        let Some(path) = raw_save_file_dialog().save_file() else {
+            return;
+        };
+
+        self.send_fallible_command("Export cartridge RAM", |id| {
+            Command::ExportCartridgeRam(path, id)
+        });
+    }
+
+    fn import_cartridge_ram(&mut self) {
+        let Some(path) = raw_save_file_dialog().pick_file() else {
+            return;
+        };
+
+        self.send_fallible_command("Import cartridge RAM", |id| {
+            Command::ImportCartridgeRam(path, id)
+        });
+    }
+}
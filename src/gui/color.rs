@@ -0,0 +1,81 @@
+//! Encoding of output colors and blending between frames.
+//!
+//! Frames shown by the GUI are stored as packed straight-alpha (non-premultiplied) RGBA, one `u32`
+//! per pixel with red in the lowest byte and alpha in the highest byte. Emulated pixels are always
+//! opaque, but overlays and blended frames may write any alpha. Consumers of packed frames must
+//! treat the color channels as unmultiplied sRGB.
+
+use eframe::egui::{
+    Color32,
+    ecolor::{gamma_u8_from_linear_f32, linear_f32_from_gamma_u8},
+};
+
+/// A straight-alpha RGBA color packed into a single `u32`.
+pub type PackedColor = u32;
+
+pub fn pack_color(color: Color32) -> PackedColor {
+    u32::from_le_bytes(color.to_srgba_unmultiplied())
+}
+
+pub fn unpack_color(packed: PackedColor) -> Color32 {
+    let [r, g, b, a] = packed.to_le_bytes();
+    Color32::from_rgba_unmultiplied(r, g, b, a)
+}
+
+/// Blend from color `a` to color `b` by factor `t`, where 0.0 is all `a` and 1.0 is all `b`.
+///
+/// Color channels are blended in linear space so that blending does not darken midtones, while
+/// alpha is blended directly.
+pub fn blend_linear(a: Color32, b: Color32, t: f32) -> Color32 {
+    let [ar, ag, ab, aa] = a.to_srgba_unmultiplied();
+    let [br, bg, bb, ba] = b.to_srgba_unmultiplied();
+
+    let blend_channel = |x: u8, y: u8| {
+        let x = linear_f32_from_gamma_u8(x);
+        let y = linear_f32_from_gamma_u8(y);
+        gamma_u8_from_linear_f32(x + (y - x) * t)
+    };
+
+    let alpha = (aa as f32 + (ba as f32 - aa as f32) * t).round() as u8;
+
+    Color32::from_rgba_unmultiplied(
+        blend_channel(ar, br),
+        blend_channel(ag, bg),
+        blend_channel(ab, bb),
+        alpha,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use eframe::egui::Color32;
+
+    use super::{blend_linear, pack_color, unpack_color};
+
+    #[test]
+    fn packed_colors_round_trip() {
+        for bits in 0..16 {
+            let channel = |bit: u8| if (bits >> bit) & 1 == 1 { 0xFF } else { 0x00 };
+            let color =
+                Color32::from_rgba_unmultiplied(channel(0), channel(1), channel(2), channel(3));
+
+            assert_eq!(unpack_color(pack_color(color)), color);
+        }
+
+        let opaque = Color32::from_rgb(0x12, 0x34, 0x56);
+        assert_eq!(pack_color(opaque), 0xFF563412);
+        assert_eq!(unpack_color(pack_color(opaque)), opaque);
+    }
+
+    #[test]
+    fn blending_is_linear() {
+        let black = Color32::BLACK;
+        let white = Color32::WHITE;
+
+        assert_eq!(blend_linear(black, white, 0.0), black);
+        assert_eq!(blend_linear(black, white, 1.0), white);
+
+        // Half of full intensity in linear space is 0xBC in sRGB, not 0x80
+        assert_eq!(blend_linear(black, white, 0.5), Color32::from_gray(0xBC));
+    }
+}
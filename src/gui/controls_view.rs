@@ -0,0 +1,159 @@
+use eframe::egui::{self, Color32, Event, Grid, Key, Pos2, Vec2, ViewportId};
+
+use crate::gui::{key_bindings::Action, shell::EmulatorShellApp};
+
+pub const WINDOW_INNER_SIZE: Vec2 = Vec2::new(320.0, 560.0);
+
+pub struct ControlsViewport {
+    /// Whether the viewport is currently shown
+    is_shown: bool,
+    /// Initial position of the viewport
+    initial_position: Pos2,
+    /// The action waiting for a key to be pressed, if any
+    rebinding_action: Option<Action>,
+    /// Message from the last rebinding, such as a conflict between bindings
+    message: Option<String>,
+}
+
+impl ControlsViewport {
+    pub fn new() -> Self {
+        ControlsViewport {
+            is_shown: false,
+            initial_position: Pos2::ZERO,
+            rebinding_action: None,
+            message: None,
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.is_shown
+    }
+
+    pub fn open(&mut self, initial_position: Pos2) {
+        self.is_shown = true;
+        self.initial_position = initial_position;
+    }
+
+    pub fn close(&mut self) {
+        self.is_shown = false;
+        self.rebinding_action = None;
+        self.message = None;
+    }
+}
+
+impl EmulatorShellApp {
+    pub fn controls_viewport_id(&self) -> ViewportId {
+        ViewportId::from_hash_of("controls_viewport_id")
+    }
+
+    pub(super) fn draw_controls_viewport(&mut self, ui: &mut egui::Ui) {
+        ui.ctx().show_viewport_immediate(
+            self.controls_viewport_id(),
+            egui::ViewportBuilder::default()
+                .with_inner_size(WINDOW_INNER_SIZE)
+                .with_position(self.controls_view().initial_position)
+                .with_resizable(false)
+                .with_active(true)
+                .with_title("Controls"),
+            |ctx, _| {
+                self.handle_rebinding_key_press(ctx);
+                egui::CentralPanel::default().show(ctx, |ui| self.draw_controls_view(ui));
+            },
+        );
+    }
+
+    fn draw_controls_view(&mut self, ui: &mut egui::Ui) {
+        ui.label("Click a binding then press a key to rebind it. Press Escape to cancel.");
+        ui.add_space(10.0);
+
+        let mut clicked_action = None;
+        let mut unbound_action = None;
+
+        Grid::new("controls_grid")
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                for action in Action::all() {
+                    ui.label(action.to_string());
+
+                    let key_text = if self.controls_view().rebinding_action == Some(action) {
+                        "Press a key...".to_string()
+                    } else {
+                        match self.key_bindings().key(action) {
+                            Some(key) => key.name().to_string(),
+                            None => "None".to_string(),
+                        }
+                    };
+
+                    if ui.button(key_text).clicked() {
+                        clicked_action = Some(action);
+                    }
+
+                    if self.key_bindings().key(action).is_some()
+                        && ui.small_button("Clear").clicked()
+                    {
+                        unbound_action = Some(action);
+                    }
+
+                    ui.end_row();
+                }
+            });
+
+        if let Some(message) = &self.controls_view().message {
+            ui.add_space(10.0);
+            ui.colored_label(Color32::RED, message);
+        }
+
+        if let Some(action) = clicked_action {
+            let controls_view = self.controls_view_mut();
+            controls_view.rebinding_action = Some(action);
+            controls_view.message = None;
+        }
+
+        if let Some(action) = unbound_action {
+            self.key_bindings_mut().unbind(action);
+            self.save_key_bindings();
+        }
+    }
+
+    /// Bind the first key pressed while waiting to rebind an action.
+    fn handle_rebinding_key_press(&mut self, ctx: &egui::Context) {
+        let Some(action) = self.controls_view().rebinding_action else {
+            return;
+        };
+
+        let pressed_key = ctx.input(|input| {
+            input.events.iter().find_map(|event| match event {
+                Event::Key {
+                    key, pressed: true, ..
+                } => Some(*key),
+                _ => None,
+            })
+        });
+
+        let Some(key) = pressed_key else {
+            return;
+        };
+
+        self.controls_view_mut().rebinding_action = None;
+        if key == Key::Escape {
+            return;
+        }
+
+        match self.key_bindings_mut().bind(action, key) {
+            Ok(()) => self.save_key_bindings(),
+            Err(error) => self.controls_view_mut().message = Some(error.to_string()),
+        }
+    }
+
+    fn save_key_bindings(&mut self) {
+        let result = match self.emulator().keymap_path() {
+            Some(path) => self.key_bindings().save(path),
+            None => Ok(()),
+        };
+
+        self.controls_view_mut().message = result
+            .err()
+            .map(|error| format!("Unable to save key bindings: {}", error));
+    }
+}
@@ -6,12 +6,21 @@ use eframe::egui::{
     text::{CCursor, CCursorRange, LayoutJob},
 };
 
-use crate::gui::shell::EmulatorShellApp;
+use crate::{
+    debugger::{Watchpoint, WatchpointHit},
+    emulator::Command,
+    gui::shell::EmulatorShellApp,
+    state::CpuState,
+    symbols::BankedAddress,
+};
 
 pub const WINDOW_INNER_SIZE: Vec2 = Vec2::new(400.0, 800.0);
 const WINDOW_PADDING: f32 = 4.0;
 
-const SCROLL_AREA_HEIGHT: f32 = 700.0;
+const SCROLL_AREA_HEIGHT: f32 = 520.0;
+
+/// Number of instructions disassembled starting at PC
+const NUM_DISASSEMBLY_LINES: usize = 8;
 
 const MAX_OUTPUT_LINES: usize = 10000;
 const MAX_INPUT_HISTORY_ENTRIES: usize = 10000;
@@ -175,6 +184,10 @@ impl EmulatorShellApp {
 
     fn draw_debugger_view(&mut self, ui: &mut egui::Ui) {
         ui.vertical(|ui| {
+            self.draw_cpu_panel(ui);
+
+            ui.separator();
+
             self.draw_scrollable_output_area(ui);
 
             ui.separator();
@@ -183,6 +196,89 @@ impl EmulatorShellApp {
         });
     }
 
+    /// Registers, flags, and the instructions starting at PC, along with buttons to step and
+    /// continue. Shows the state as of the last GUI update, which is exact while paused.
+    ///
+    /// Instructions are read with `disassemble_at`, which never goes through `read_address`, since
+    /// the debugger's watchpoint state must only be touched by the emulator thread.
+    fn draw_cpu_panel(&mut self, ui: &mut egui::Ui) {
+        let emulator = self.emulator();
+        let cpu_state = emulator.cpu_state();
+        let is_paused = emulator.is_paused();
+
+        let mut lines = Self::format_registers(&cpu_state);
+        lines.push(String::new());
+
+        let mut address = cpu_state.pc;
+        for i in 0..NUM_DISASSEMBLY_LINES {
+            let (instruction, length) = emulator.disassemble_at(address);
+            let marker = if i == 0 { '>' } else { ' ' };
+            lines.push(format!("{} {:04X}  {}", marker, address, instruction));
+            address = address.wrapping_add(length as u16);
+        }
+
+        ui.spacing_mut().item_spacing = Vec2::ZERO;
+        for line in lines {
+            ui.add(Label::new(LayoutJob::simple_format(
+                line,
+                Self::text_format(ui),
+            )));
+        }
+
+        ui.add_space(WINDOW_PADDING);
+
+        let mut should_step = false;
+        let mut should_continue = false;
+        ui.horizontal(|ui| {
+            ui.spacing_mut().item_spacing = Vec2::new(WINDOW_PADDING, 0.0);
+
+            // Stepping while running pauses after the next instruction
+            should_step = ui.button("Step").clicked();
+            should_continue = ui
+                .add_enabled(is_paused, egui::Button::new("Continue"))
+                .clicked();
+        });
+
+        if should_step {
+            self.send_command(Command::StepInstruction);
+        }
+
+        if should_continue {
+            self.send_command(Command::Continue);
+        }
+    }
+
+    fn format_registers(cpu_state: &CpuState) -> Vec<String> {
+        let flag = |bit: u8, name: char| {
+            if cpu_state.f & (1 << bit) != 0 {
+                name
+            } else {
+                '-'
+            }
+        };
+
+        vec![
+            format!(
+                "AF {:04X}  BC {:04X}  DE {:04X}  HL {:04X}",
+                cpu_state.af(),
+                cpu_state.bc(),
+                cpu_state.de(),
+                cpu_state.hl()
+            ),
+            format!(
+                "SP {:04X}  PC {:04X}  Flags {}{}{}{}  IME {}{}",
+                cpu_state.sp,
+                cpu_state.pc,
+                flag(7, 'Z'),
+                flag(6, 'N'),
+                flag(5, 'H'),
+                flag(4, 'C'),
+                cpu_state.ime as u8,
+                if cpu_state.is_halted { "  HALTED" } else { "" }
+            ),
+        ]
+    }
+
     fn draw_scrollable_output_area(&mut self, ui: &mut egui::Ui) {
         let scroll_area_size = Vec2::new(ui.available_width(), SCROLL_AREA_HEIGHT);
         let scroll_area_layout = Layout::top_down(Align::LEFT).with_cross_justify(true);
@@ -392,6 +488,134 @@ impl EmulatorShellApp {
         self.debugger_view_mut().input_history_cursor = None;
 
         self.push_output_line(format!("{} {}", GBDB_PREFIX, raw_input_line));
+        self.run_debugger_command(&raw_input_line);
+    }
+
+    fn run_debugger_command(&mut self, line: &str) {
+        let mut parts = line.split_whitespace();
+        let Some(command) = parts.next() else {
+            return;
+        };
+        let argument = parts.next();
+
+        match (command, argument, parts.next()) {
+            ("break" | "b", Some(location), None) => match self.resolve_location(location) {
+                Some(breakpoint) => {
+                    self.send_command(Command::AddBreakpoint(breakpoint));
+                    let formatted = self.symbols().format(breakpoint);
+                    self.push_output_line(format!("Breakpoint set at {}", formatted));
+                }
+                None => self.push_output_line(format!("Unknown location: {}", location)),
+            },
+            ("delete" | "d", Some(location), None) => match self.resolve_location(location) {
+                Some(breakpoint) => {
+                    self.send_command(Command::RemoveBreakpoint(breakpoint));
+                    let formatted = self.symbols().format(breakpoint);
+                    self.push_output_line(format!("Breakpoint deleted at {}", formatted));
+                }
+                None => self.push_output_line(format!("Unknown location: {}", location)),
+            },
+            ("mbc", None, None) => {
+                let mbc_debug_state = self.emulator().mbc_debug_state();
+                self.push_output_line(mbc_debug_state.to_string());
+            }
+            ("continue" | "c", None, None) => {
+                if self.emulator().is_paused() {
+                    self.send_command(Command::Continue);
+                } else {
+                    self.push_output_line("Not stopped".to_string());
+                }
+            }
+            ("step" | "s", None, None) => self.send_command(Command::StepInstruction),
+            ("watch" | "w", Some(address), access) => {
+                match Self::parse_watchpoint(address, access) {
+                    Some(watchpoint) => self.add_watchpoint(watchpoint),
+                    None => self.push_output_line(format!("Invalid watchpoint: {}", line)),
+                }
+            }
+            ("unwatch", Some(address), access) => match Self::parse_watchpoint(address, access) {
+                Some(watchpoint) => {
+                    self.send_command(Command::RemoveWatchpoint(watchpoint));
+                    self.push_output_line(format!(
+                        "Watchpoint deleted on {}",
+                        Self::format_watchpoint(watchpoint)
+                    ));
+                }
+                None => self.push_output_line(format!("Invalid watchpoint: {}", line)),
+            },
+            _ => self.push_output_line(format!("Unknown command: {}", line)),
+        }
+    }
+
+    /// Resolve a location given as a label, as `<bank>:<address>`, or as an address in whichever
+    /// bank is currently mapped there.
+    fn resolve_location(&self, location: &str) -> Option<BankedAddress> {
+        if let Some(banked_address) = self.symbols().lookup(location) {
+            return Some(banked_address);
+        }
+
+        if let Some(banked_address) = BankedAddress::parse(location) {
+            return Some(banked_address);
+        }
+
+        let address = u16::from_str_radix(location.trim_start_matches('$'), 16).ok()?;
+        let bank = self.emulator().current_bank_at(address);
+
+        Some(BankedAddress::new(bank, address))
+    }
+
+    /// Parse a watchpoint given as an address followed by `r` or `w` for reads or writes. Watches
+    /// writes if the access is omitted.
+    fn parse_watchpoint(address: &str, access: Option<&str>) -> Option<Watchpoint> {
+        let address = u16::from_str_radix(address.trim_start_matches('$'), 16).ok()?;
+        let on_write = match access {
+            None | Some("w") => true,
+            Some("r") => false,
+            Some(_) => return None,
+        };
+
+        Some(Watchpoint { address, on_write })
+    }
+
+    pub(super) fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.send_command(Command::AddWatchpoint(watchpoint));
+        self.push_output_line(format!(
+            "Watchpoint set on {}",
+            Self::format_watchpoint(watchpoint)
+        ));
+    }
+
+    fn format_watchpoint(watchpoint: Watchpoint) -> String {
+        let access = if watchpoint.on_write {
+            "writes to"
+        } else {
+            "reads from"
+        };
+
+        format!("{} {:04X}", access, watchpoint.address)
+    }
+
+    pub(super) fn handle_watchpoint_hit(&mut self, watchpoint_hit: WatchpointHit) {
+        let (instruction, _) = self.emulator().disassemble_at(watchpoint_hit.pc);
+
+        self.push_output_line(format!(
+            "Watchpoint hit on {} at {:04X}: {}",
+            Self::format_watchpoint(watchpoint_hit.watchpoint),
+            watchpoint_hit.pc,
+            instruction
+        ));
+    }
+
+    pub(super) fn handle_stepped(&mut self, pc: u16) {
+        let (instruction, _) = self.emulator().disassemble_at(pc);
+        self.push_output_line(format!("Stepped to {:04X}: {}", pc, instruction));
+    }
+
+    pub(super) fn handle_breakpoint_hit(&mut self, breakpoint: BankedAddress) {
+        let (instruction, _) = self.emulator().disassemble_at(breakpoint.address);
+        let formatted = self.symbols().format(breakpoint);
+
+        self.push_output_line(format!("Breakpoint hit at {}: {}", formatted, instruction));
     }
 
     fn push_output_line(&mut self, line: String) {
@@ -0,0 +1,304 @@
+//! Game controller input.
+//!
+//! Connected gamepads are polled every GUI frame. The D-pad and left stick control the direction
+//! buttons, while the face buttons, Start, Select, and turbo are mapped by a `GamepadMapping`.
+
+use std::{fmt, str::FromStr};
+
+use gilrs::{Axis, Gilrs};
+
+use crate::emulator::Button;
+
+/// How far the left stick must be pushed in a direction for it to count as pressed
+const STICK_THRESHOLD: f32 = 0.5;
+
+/// Names of the gamepad buttons that can be mapped, using the position of the face buttons
+const GAMEPAD_BUTTON_NAMES: [(&str, gilrs::Button); 12] = [
+    ("south", gilrs::Button::South),
+    ("east", gilrs::Button::East),
+    ("north", gilrs::Button::North),
+    ("west", gilrs::Button::West),
+    ("start", gilrs::Button::Start),
+    ("select", gilrs::Button::Select),
+    ("left-shoulder", gilrs::Button::LeftTrigger),
+    ("right-shoulder", gilrs::Button::RightTrigger),
+    ("left-trigger", gilrs::Button::LeftTrigger2),
+    ("right-trigger", gilrs::Button::RightTrigger2),
+    ("left-stick", gilrs::Button::LeftThumb),
+    ("right-stick", gilrs::Button::RightThumb),
+];
+
+fn gamepad_button_name(button: gilrs::Button) -> &'static str {
+    GAMEPAD_BUTTON_NAMES
+        .iter()
+        .find(|(_, named_button)| *named_button == button)
+        .map_or("unknown", |(name, _)| name)
+}
+
+/// The gamepad buttons that the GameBoy's buttons and turbo mode are mapped to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GamepadMapping {
+    pub a: gilrs::Button,
+    pub b: gilrs::Button,
+    pub start: gilrs::Button,
+    pub select: gilrs::Button,
+    pub turbo: gilrs::Button,
+}
+
+impl Default for GamepadMapping {
+    fn default() -> Self {
+        GamepadMapping {
+            a: gilrs::Button::East,
+            b: gilrs::Button::South,
+            start: gilrs::Button::Start,
+            select: gilrs::Button::Select,
+            turbo: gilrs::Button::RightTrigger,
+        }
+    }
+}
+
+impl GamepadMapping {
+    /// The state of a single gamepad, given which of its buttons are pressed and the position of
+    /// its left stick.
+    pub fn read_state(
+        &self,
+        is_pressed: impl Fn(gilrs::Button) -> bool,
+        left_stick: (f32, f32),
+    ) -> InputState {
+        let mut buttons = 0;
+
+        let mapped_buttons = [
+            (self.a, Button::A),
+            (self.b, Button::B),
+            (self.start, Button::Start),
+            (self.select, Button::Select),
+            (gilrs::Button::DPadUp, Button::Up),
+            (gilrs::Button::DPadDown, Button::Down),
+            (gilrs::Button::DPadLeft, Button::Left),
+            (gilrs::Button::DPadRight, Button::Right),
+        ];
+        for (gamepad_button, button) in mapped_buttons {
+            if is_pressed(gamepad_button) {
+                buttons |= button as u8;
+            }
+        }
+
+        // Stick y axis points up
+        let (stick_x, stick_y) = left_stick;
+        if stick_x <= -STICK_THRESHOLD {
+            buttons |= Button::Left as u8;
+        } else if stick_x >= STICK_THRESHOLD {
+            buttons |= Button::Right as u8;
+        }
+        if stick_y >= STICK_THRESHOLD {
+            buttons |= Button::Up as u8;
+        } else if stick_y <= -STICK_THRESHOLD {
+            buttons |= Button::Down as u8;
+        }
+
+        InputState {
+            buttons,
+            is_turbo_pressed: is_pressed(self.turbo),
+        }
+    }
+}
+
+impl FromStr for GamepadMapping {
+    type Err = String;
+
+    /// Parse a comma separated list of `button=gamepad_button` pairs, e.g. `a=east,b=south`.
+    /// Buttons that are not listed keep their default mapping.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mapping = GamepadMapping::default();
+
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let Some((button, gamepad_button)) = pair.split_once('=') else {
+                return Err(format!("expected button=gamepad_button but found {}", pair));
+            };
+
+            let gamepad_button = GAMEPAD_BUTTON_NAMES
+                .iter()
+                .find(|(name, _)| *name == gamepad_button)
+                .map(|(_, gamepad_button)| *gamepad_button)
+                .ok_or_else(|| {
+                    let names = GAMEPAD_BUTTON_NAMES.map(|(name, _)| name);
+                    format!(
+                        "unknown gamepad button {}, expected one of {}",
+                        gamepad_button,
+                        names.join(", ")
+                    )
+                })?;
+
+            match button {
+                "a" => mapping.a = gamepad_button,
+                "b" => mapping.b = gamepad_button,
+                "start" => mapping.start = gamepad_button,
+                "select" => mapping.select = gamepad_button,
+                "turbo" => mapping.turbo = gamepad_button,
+                _ => {
+                    return Err(format!(
+                        "unknown button {}, expected a, b, start, select, or turbo",
+                        button
+                    ));
+                }
+            }
+        }
+
+        Ok(mapping)
+    }
+}
+
+impl fmt::Display for GamepadMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a={},b={},start={},select={},turbo={}",
+            gamepad_button_name(self.a),
+            gamepad_button_name(self.b),
+            gamepad_button_name(self.start),
+            gamepad_button_name(self.select),
+            gamepad_button_name(self.turbo)
+        )
+    }
+}
+
+/// Buttons held on an input device, such as a gamepad or the keyboard.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputState {
+    pub buttons: u8,
+    pub is_turbo_pressed: bool,
+}
+
+impl InputState {
+    /// Combine with the buttons held on another input, so that a button is held if it is held on
+    /// either.
+    pub fn merge(self, other: InputState) -> InputState {
+        InputState {
+            buttons: self.buttons | other.buttons,
+            is_turbo_pressed: self.is_turbo_pressed || other.is_turbo_pressed,
+        }
+    }
+}
+
+pub struct Gamepads {
+    /// Not present if gamepads are not supported on this platform
+    gilrs: Option<Gilrs>,
+    mapping: GamepadMapping,
+}
+
+impl Gamepads {
+    pub fn new(mapping: GamepadMapping) -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(error) => {
+                eprintln!("Gamepads are not available: {}", error);
+                None
+            }
+        };
+
+        Gamepads { gilrs, mapping }
+    }
+
+    /// The combined state of all connected gamepads. Pending events are drained first, which picks
+    /// up gamepads that were connected or disconnected since the last poll.
+    pub fn poll(&mut self) -> InputState {
+        let Some(gilrs) = &mut self.gilrs else {
+            return InputState::default();
+        };
+
+        while gilrs.next_event().is_some() {}
+
+        gilrs
+            .gamepads()
+            .map(|(_, gamepad)| {
+                self.mapping.read_state(
+                    |button| gamepad.is_pressed(button),
+                    (
+                        gamepad.value(Axis::LeftStickX),
+                        gamepad.value(Axis::LeftStickY),
+                    ),
+                )
+            })
+            .fold(InputState::default(), InputState::merge)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use crate::emulator::Button;
+
+    use super::{GamepadMapping, InputState};
+
+    #[test]
+    fn parse_mapping() {
+        let default = GamepadMapping::default();
+        assert_eq!(GamepadMapping::from_str(""), Ok(default));
+        assert_eq!(GamepadMapping::from_str(&default.to_string()), Ok(default));
+
+        let mapping = GamepadMapping::from_str("a=south,b=west,turbo=left-trigger").unwrap();
+        assert_eq!(mapping.a, gilrs::Button::South);
+        assert_eq!(mapping.b, gilrs::Button::West);
+        assert_eq!(mapping.start, default.start);
+        assert_eq!(mapping.turbo, gilrs::Button::LeftTrigger2);
+        assert_eq!(
+            mapping.to_string(),
+            "a=south,b=west,start=start,select=select,turbo=left-trigger"
+        );
+
+        assert_eq!(
+            GamepadMapping::from_str("a"),
+            Err("expected button=gamepad_button but found a".to_string())
+        );
+        assert_eq!(
+            GamepadMapping::from_str("up=north"),
+            Err("unknown button up, expected a, b, start, select, or turbo".to_string())
+        );
+        assert!(GamepadMapping::from_str("a=triangle").is_err());
+    }
+
+    #[test]
+    fn read_mapped_buttons_and_stick() {
+        let mapping = GamepadMapping::default();
+
+        let state = mapping.read_state(
+            |button| matches!(button, gilrs::Button::East | gilrs::Button::DPadUp),
+            (0.0, 0.0),
+        );
+        assert_eq!(state.buttons, Button::A as u8 | Button::Up as u8);
+        assert!(!state.is_turbo_pressed);
+
+        let state =
+            mapping.read_state(|button| button == gilrs::Button::RightTrigger, (-0.9, -0.6));
+        assert_eq!(state.buttons, Button::Left as u8 | Button::Down as u8);
+        assert!(state.is_turbo_pressed);
+
+        // Small stick movements are ignored
+        let state = mapping.read_state(|_| false, (0.3, -0.2));
+        assert_eq!(state, InputState::default());
+    }
+
+    #[test]
+    fn merge_keyboard_and_gamepad() {
+        let keyboard = InputState {
+            buttons: Button::A as u8 | Button::Left as u8,
+            is_turbo_pressed: false,
+        };
+        let gamepad = InputState {
+            buttons: Button::A as u8 | Button::Start as u8,
+            is_turbo_pressed: true,
+        };
+
+        // Buttons held on either input are held, and releasing one input does not release a
+        // button still held on the other.
+        assert_eq!(
+            keyboard.merge(gamepad),
+            InputState {
+                buttons: Button::A as u8 | Button::Left as u8 | Button::Start as u8,
+                is_turbo_pressed: true,
+            }
+        );
+        assert_eq!(keyboard.merge(InputState::default()), keyboard);
+    }
+}
@@ -0,0 +1,335 @@
+use eframe::egui::{self, Color32, Label, Pos2, RichText, ScrollArea, Sense, Vec2, ViewportId};
+
+use crate::{
+    address_space::{Address, IO_REGISTERS_SIZE, IO_REGISTERS_START},
+    debugger::Watchpoint,
+    emulator::Register,
+    gui::{
+        shell::EmulatorShellApp,
+        window_layout::{DOCK_BUTTON_BAR_HEIGHT, DebugView},
+    },
+};
+
+pub const WINDOW_INNER_SIZE: Vec2 = Vec2::new(300.0, 600.0);
+
+/// Number of GUI frames over which the highlight on a changed register fades out
+const CHANGE_FADE_FRAMES: u32 = 30;
+
+const CHANGE_HIGHLIGHT_COLOR: Color32 = Color32::from_rgb(0xC0, 0x90, 0x00);
+
+/// Names of the IO registers shown in the viewport, in address order.
+const IO_REGISTER_NAMES: [(Address, &str); 72] = [
+    (0xFF00, "P1"),
+    (0xFF01, "SB"),
+    (0xFF02, "SC"),
+    (0xFF04, "DIV"),
+    (0xFF05, "TIMA"),
+    (0xFF06, "TMA"),
+    (0xFF07, "TAC"),
+    (0xFF0F, "IF"),
+    (0xFF10, "NR10"),
+    (0xFF11, "NR11"),
+    (0xFF12, "NR12"),
+    (0xFF13, "NR13"),
+    (0xFF14, "NR14"),
+    (0xFF16, "NR21"),
+    (0xFF17, "NR22"),
+    (0xFF18, "NR23"),
+    (0xFF19, "NR24"),
+    (0xFF1A, "NR30"),
+    (0xFF1B, "NR31"),
+    (0xFF1C, "NR32"),
+    (0xFF1D, "NR33"),
+    (0xFF1E, "NR34"),
+    (0xFF20, "NR41"),
+    (0xFF21, "NR42"),
+    (0xFF22, "NR43"),
+    (0xFF23, "NR44"),
+    (0xFF24, "NR50"),
+    (0xFF25, "NR51"),
+    (0xFF26, "NR52"),
+    (0xFF30, "WAVE0"),
+    (0xFF31, "WAVE1"),
+    (0xFF32, "WAVE2"),
+    (0xFF33, "WAVE3"),
+    (0xFF34, "WAVE4"),
+    (0xFF35, "WAVE5"),
+    (0xFF36, "WAVE6"),
+    (0xFF37, "WAVE7"),
+    (0xFF38, "WAVE8"),
+    (0xFF39, "WAVE9"),
+    (0xFF3A, "WAVEA"),
+    (0xFF3B, "WAVEB"),
+    (0xFF3C, "WAVEC"),
+    (0xFF3D, "WAVED"),
+    (0xFF3E, "WAVEE"),
+    (0xFF3F, "WAVEF"),
+    (0xFF40, "LCDC"),
+    (0xFF41, "STAT"),
+    (0xFF42, "SCY"),
+    (0xFF43, "SCX"),
+    (0xFF44, "LY"),
+    (0xFF45, "LYC"),
+    (0xFF46, "DMA"),
+    (0xFF47, "BGP"),
+    (0xFF48, "OBP0"),
+    (0xFF49, "OBP1"),
+    (0xFF4A, "WY"),
+    (0xFF4B, "WX"),
+    (0xFF4C, "KEY0"),
+    (0xFF4D, "KEY1"),
+    (0xFF4F, "VBK"),
+    (0xFF50, "BANK"),
+    (0xFF51, "HDMA1"),
+    (0xFF52, "HDMA2"),
+    (0xFF53, "HDMA3"),
+    (0xFF54, "HDMA4"),
+    (0xFF55, "HDMA5"),
+    (0xFF56, "RP"),
+    (0xFF68, "BCPS"),
+    (0xFF69, "BCPD"),
+    (0xFF6A, "OCPS"),
+    (0xFF6B, "OCPD"),
+    (0xFF70, "SVBK"),
+];
+
+/// Tracks which IO registers changed between successive snapshots of the IO register file.
+pub struct IoRegisterChanges {
+    /// The last snapshot, if any have been taken
+    previous_snapshot: Option<[Register; IO_REGISTERS_SIZE]>,
+    /// Number of snapshots since each register last changed, if it has changed
+    frames_since_change: [Option<u32>; IO_REGISTERS_SIZE],
+    /// Number of times each register has changed since the counts were last reset
+    change_counts: [u32; IO_REGISTERS_SIZE],
+}
+
+impl IoRegisterChanges {
+    pub fn new() -> Self {
+        IoRegisterChanges {
+            previous_snapshot: None,
+            frames_since_change: [None; IO_REGISTERS_SIZE],
+            change_counts: [0; IO_REGISTERS_SIZE],
+        }
+    }
+
+    /// Diff a new snapshot against the previous one. Expected to be called once per frame.
+    pub fn update(&mut self, snapshot: [Register; IO_REGISTERS_SIZE]) {
+        for offset in 0..IO_REGISTERS_SIZE {
+            let is_changed = self
+                .previous_snapshot
+                .is_some_and(|previous| previous[offset] != snapshot[offset]);
+
+            if is_changed {
+                self.frames_since_change[offset] = Some(0);
+                self.change_counts[offset] += 1;
+            } else if let Some(frames) = &mut self.frames_since_change[offset] {
+                *frames = frames.saturating_add(1);
+            }
+        }
+
+        self.previous_snapshot = Some(snapshot);
+    }
+
+    /// Strength of the highlight for a register, from 1.0 on the frame it changed fading linearly
+    /// to 0.0 after `CHANGE_FADE_FRAMES` frames.
+    pub fn highlight(&self, offset: usize) -> f32 {
+        match self.frames_since_change[offset] {
+            Some(frames) if frames < CHANGE_FADE_FRAMES => {
+                1.0 - (frames as f32 / CHANGE_FADE_FRAMES as f32)
+            }
+            _ => 0.0,
+        }
+    }
+
+    pub fn change_count(&self, offset: usize) -> u32 {
+        self.change_counts[offset]
+    }
+
+    pub fn reset_change_counts(&mut self) {
+        self.change_counts = [0; IO_REGISTERS_SIZE];
+    }
+}
+
+pub struct IoRegistersViewport {
+    /// Whether the viewport is currently shown
+    is_shown: bool,
+    /// Initial position of the viewport
+    initial_position: Pos2,
+    /// Recent changes to the IO registers
+    changes: IoRegisterChanges,
+    /// Whether the number of times each register changed is shown
+    show_change_counts: bool,
+}
+
+impl IoRegistersViewport {
+    pub fn new() -> Self {
+        IoRegistersViewport {
+            is_shown: false,
+            initial_position: Pos2::ZERO,
+            changes: IoRegisterChanges::new(),
+            show_change_counts: false,
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.is_shown
+    }
+
+    pub fn open(&mut self, initial_position: Pos2) {
+        self.is_shown = true;
+        self.initial_position = initial_position;
+    }
+
+    /// Changes are only tracked while the viewport is shown, so start fresh when it is reopened.
+    pub fn close(&mut self) {
+        self.is_shown = false;
+        self.changes = IoRegisterChanges::new();
+    }
+}
+
+impl EmulatorShellApp {
+    pub fn io_registers_viewport_id(&self) -> ViewportId {
+        ViewportId::from_hash_of("io_registers_viewport_id")
+    }
+
+    pub(super) fn draw_io_registers_viewport(&mut self, ui: &mut egui::Ui) {
+        ui.ctx().show_viewport_immediate(
+            self.io_registers_viewport_id(),
+            egui::ViewportBuilder::default()
+                .with_inner_size(WINDOW_INNER_SIZE + Vec2::new(0.0, DOCK_BUTTON_BAR_HEIGHT))
+                .with_position(self.io_registers_view().initial_position)
+                .with_resizable(true)
+                .with_active(true)
+                .with_title(DebugView::IoRegisters.title()),
+            |ctx, _| {
+                self.draw_dock_button_bar(DebugView::IoRegisters, ctx);
+                egui::CentralPanel::default().show(ctx, |ui| self.draw_io_registers_view(ui));
+            },
+        );
+    }
+
+    pub(super) fn draw_io_registers_view(&mut self, ui: &mut egui::Ui) {
+        let snapshot = self.emulator().io_registers_snapshot();
+        self.io_registers_view_mut().changes.update(snapshot);
+
+        ui.horizontal(|ui| {
+            let view = self.io_registers_view_mut();
+            ui.checkbox(&mut view.show_change_counts, "Show change counts");
+            if ui.button("Reset").clicked() {
+                view.changes.reset_change_counts();
+            }
+        });
+        ui.label("Right click a register to break on writes to it.");
+        ui.separator();
+
+        let mut watched_address = None;
+
+        ScrollArea::vertical().auto_shrink(false).show(ui, |ui| {
+            ui.spacing_mut().item_spacing = Vec2::ZERO;
+
+            let view = self.io_registers_view();
+            for (address, name) in IO_REGISTER_NAMES {
+                let offset = (address - IO_REGISTERS_START) as usize;
+                let value = snapshot[offset];
+
+                let mut line =
+                    format!("{:04X}  {:<5}  {:02X}  {:08b}", address, name, value, value);
+                if view.show_change_counts {
+                    line.push_str(&format!("  {:>6}", view.changes.change_count(offset)));
+                }
+
+                let background_color =
+                    CHANGE_HIGHLIGHT_COLOR.gamma_multiply(view.changes.highlight(offset));
+                let text = RichText::new(line)
+                    .monospace()
+                    .background_color(background_color);
+
+                ui.add(Label::new(text).sense(Sense::click()))
+                    .context_menu(|ui| {
+                        if ui.button("Break on Write").clicked() {
+                            watched_address = Some(address);
+                            ui.close();
+                        }
+                    });
+            }
+        });
+
+        if let Some(address) = watched_address {
+            self.add_watchpoint(Watchpoint {
+                address,
+                on_write: true,
+            });
+            self.show_toast(format!("Breaking on writes to {:04X}", address));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::address_space::IO_REGISTERS_SIZE;
+
+    use super::{CHANGE_FADE_FRAMES, IoRegisterChanges};
+
+    #[test]
+    fn first_snapshot_has_no_changes() {
+        let mut changes = IoRegisterChanges::new();
+        changes.update([0xFF; IO_REGISTERS_SIZE]);
+
+        for offset in 0..IO_REGISTERS_SIZE {
+            assert_eq!(changes.highlight(offset), 0.0);
+            assert_eq!(changes.change_count(offset), 0);
+        }
+    }
+
+    #[test]
+    fn changed_register_highlight_fades() {
+        let mut snapshot = [0; IO_REGISTERS_SIZE];
+        let mut changes = IoRegisterChanges::new();
+        changes.update(snapshot);
+
+        snapshot[0x40] = 0x91;
+        changes.update(snapshot);
+        assert_eq!(changes.highlight(0x40), 1.0);
+        assert_eq!(changes.highlight(0x41), 0.0);
+
+        // Highlight fades while the register is unchanged
+        let mut last_highlight = 1.0;
+        for _ in 1..CHANGE_FADE_FRAMES {
+            changes.update(snapshot);
+            let highlight = changes.highlight(0x40);
+            assert!(highlight > 0.0 && highlight < last_highlight);
+            last_highlight = highlight;
+        }
+
+        changes.update(snapshot);
+        assert_eq!(changes.highlight(0x40), 0.0);
+
+        // Changing again restarts the fade
+        snapshot[0x40] = 0x11;
+        changes.update(snapshot);
+        assert_eq!(changes.highlight(0x40), 1.0);
+    }
+
+    #[test]
+    fn change_counts() {
+        let mut snapshot = [0; IO_REGISTERS_SIZE];
+        let mut changes = IoRegisterChanges::new();
+        changes.update(snapshot);
+
+        for value in 1..=3 {
+            snapshot[0x04] = value;
+            changes.update(snapshot);
+        }
+
+        // Unchanged snapshots are not counted
+        changes.update(snapshot);
+        assert_eq!(changes.change_count(0x04), 3);
+        assert_eq!(changes.change_count(0x05), 0);
+
+        changes.reset_change_counts();
+        assert_eq!(changes.change_count(0x04), 0);
+
+        // Highlights are not affected by resetting counts
+        assert!(changes.highlight(0x04) > 0.0);
+    }
+}
@@ -0,0 +1,401 @@
+//! Keyboard controls.
+//!
+//! Each GameBoy button and meta action (turbo, rewind, pause, frame advance, and quick saving to a
+//! slot) can be bound to a key. Bindings are read from a TOML file mapping action names to key names, e.g.
+//! `a = "X"`, where an empty key name leaves the action unbound. Actions that are not listed in the
+//! file keep their default binding.
+
+use std::{collections::BTreeMap, fmt, fs, io, path::Path, str::FromStr};
+
+use eframe::egui::Key;
+
+use crate::{emulator::Button, gui::gamepad::InputState, save_file::NUM_QUICK_SAVE_SLOTS};
+
+pub const KEY_BINDINGS_FILE_NAME: &str = "keymap.toml";
+
+const QUICK_SAVE_ACTION_PREFIX: &str = "quick-save-";
+
+/// Something that can be triggered by a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    A,
+    B,
+    Start,
+    Select,
+    Up,
+    Down,
+    Left,
+    Right,
+    Turbo,
+    /// Rewind while held
+    Rewind,
+    Pause,
+    /// Run a single frame while paused
+    FrameAdvance,
+    /// Quick save to the slot with this number
+    QuickSave(usize),
+}
+
+impl Action {
+    const BUTTONS: [(Action, Button); 8] = [
+        (Action::A, Button::A),
+        (Action::B, Button::B),
+        (Action::Start, Button::Start),
+        (Action::Select, Button::Select),
+        (Action::Up, Button::Up),
+        (Action::Down, Button::Down),
+        (Action::Left, Button::Left),
+        (Action::Right, Button::Right),
+    ];
+
+    /// All actions that can be bound, in the order they are listed in the controls window.
+    pub fn all() -> impl Iterator<Item = Action> {
+        Self::BUTTONS
+            .into_iter()
+            .map(|(action, _)| action)
+            .chain([
+                Action::Turbo,
+                Action::Rewind,
+                Action::Pause,
+                Action::FrameAdvance,
+            ])
+            .chain((0..NUM_QUICK_SAVE_SLOTS).map(Action::QuickSave))
+    }
+
+    /// The GameBoy button held while this action's key is held, if any.
+    pub fn button(self) -> Option<Button> {
+        Self::BUTTONS
+            .into_iter()
+            .find(|(action, _)| *action == self)
+            .map(|(_, button)| button)
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::A => write!(f, "a"),
+            Action::B => write!(f, "b"),
+            Action::Start => write!(f, "start"),
+            Action::Select => write!(f, "select"),
+            Action::Up => write!(f, "up"),
+            Action::Down => write!(f, "down"),
+            Action::Left => write!(f, "left"),
+            Action::Right => write!(f, "right"),
+            Action::Turbo => write!(f, "turbo"),
+            Action::Rewind => write!(f, "rewind"),
+            Action::Pause => write!(f, "pause"),
+            Action::FrameAdvance => write!(f, "frame-advance"),
+            Action::QuickSave(slot) => write!(f, "{}{}", QUICK_SAVE_ACTION_PREFIX, slot),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::all()
+            .find(|action| action.to_string() == s)
+            .ok_or_else(|| format!("unknown action {}", s))
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyBindingsError {
+    Io(io::Error),
+    Parse(String),
+    /// The same key is bound to two different actions
+    Conflict {
+        key: Key,
+        first: Action,
+        second: Action,
+    },
+}
+
+impl fmt::Display for KeyBindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyBindingsError::Io(error) => write!(f, "{}", error),
+            KeyBindingsError::Parse(message) => write!(f, "{}", message),
+            KeyBindingsError::Conflict { key, first, second } => write!(
+                f,
+                "{} is already bound to {}, cannot also bind it to {}",
+                key.name(),
+                first,
+                second
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for KeyBindingsError {
+    fn from(error: io::Error) -> Self {
+        KeyBindingsError::Io(error)
+    }
+}
+
+/// The key bound to each action. No two actions share a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyBindings {
+    bindings: BTreeMap<Action, Key>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            bindings: BTreeMap::from([
+                (Action::A, Key::X),
+                (Action::B, Key::Z),
+                (Action::Start, Key::S),
+                (Action::Select, Key::A),
+                (Action::Up, Key::ArrowUp),
+                (Action::Down, Key::ArrowDown),
+                (Action::Left, Key::ArrowLeft),
+                (Action::Right, Key::ArrowRight),
+                (Action::Turbo, Key::Space),
+                (Action::Rewind, Key::Backspace),
+                (Action::FrameAdvance, Key::Period),
+            ]),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Read bindings from a file, or the defaults if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self, KeyBindingsError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::from_toml(&contents),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), KeyBindingsError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, self.to_toml())?;
+
+        Ok(())
+    }
+
+    /// Parse bindings from TOML, keeping the default binding for actions that are not listed.
+    pub fn from_toml(contents: &str) -> Result<Self, KeyBindingsError> {
+        let table: BTreeMap<String, String> =
+            toml::from_str(contents).map_err(|error| KeyBindingsError::Parse(error.to_string()))?;
+
+        let mut bindings = Self::default().bindings;
+        for (action, key) in table {
+            let action = Action::from_str(&action).map_err(KeyBindingsError::Parse)?;
+            if key.is_empty() {
+                bindings.remove(&action);
+                continue;
+            }
+
+            let key = Key::from_name(&key)
+                .ok_or_else(|| KeyBindingsError::Parse(format!("unknown key {}", key)))?;
+
+            bindings.insert(action, key);
+        }
+
+        let key_bindings = KeyBindings { bindings };
+        key_bindings.check_conflicts()?;
+
+        Ok(key_bindings)
+    }
+
+    /// Write all actions, with unbound actions given an empty key name.
+    pub fn to_toml(&self) -> String {
+        let table = Action::all()
+            .map(|action| {
+                let key_name = self.key(action).map_or("", |key| key.name());
+                (action.to_string(), key_name.to_string())
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        toml::to_string(&table).unwrap()
+    }
+
+    fn check_conflicts(&self) -> Result<(), KeyBindingsError> {
+        let mut actions_by_key: BTreeMap<&str, Action> = BTreeMap::new();
+
+        for (action, key) in &self.bindings {
+            if let Some(first) = actions_by_key.insert(key.name(), *action) {
+                return Err(KeyBindingsError::Conflict {
+                    key: *key,
+                    first,
+                    second: *action,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn key(&self, action: Action) -> Option<Key> {
+        self.bindings.get(&action).copied()
+    }
+
+    /// The action bound to a key, if any.
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, bound_key)| **bound_key == key)
+            .map(|(action, _)| *action)
+    }
+
+    /// Bind a key to an action, replacing the action's previous key. Fails if the key is already
+    /// bound to a different action.
+    pub fn bind(&mut self, action: Action, key: Key) -> Result<(), KeyBindingsError> {
+        match self.action(key) {
+            Some(first) if first != action => Err(KeyBindingsError::Conflict {
+                key,
+                first,
+                second: action,
+            }),
+            _ => {
+                self.bindings.insert(action, key);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn unbind(&mut self, action: Action) {
+        self.bindings.remove(&action);
+    }
+
+    /// Whether an action's key is held, given which keys are held.
+    pub fn is_action_down(&self, action: Action, is_down: impl Fn(Key) -> bool) -> bool {
+        self.key(action).is_some_and(is_down)
+    }
+
+    /// Buttons and turbo mode held on the keyboard, given which keys are held.
+    pub fn read_state(&self, is_down: impl Fn(Key) -> bool) -> InputState {
+        let is_action_down = |action| self.is_action_down(action, &is_down);
+
+        let buttons = Action::BUTTONS
+            .into_iter()
+            .filter(|(action, _)| is_action_down(*action))
+            .fold(0, |buttons, (_, button)| buttons | button as u8);
+
+        InputState {
+            buttons,
+            is_turbo_pressed: is_action_down(Action::Turbo),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use eframe::egui::Key;
+
+    use crate::{emulator::Button, gui::gamepad::InputState};
+
+    use super::{Action, KeyBindings, KeyBindingsError};
+
+    #[test]
+    fn parse_key_bindings() {
+        let bindings = KeyBindings::from_toml(
+            r#"
+            a = "K"
+            b = "J"
+            pause = "P"
+            quick-save-1 = "F1"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(bindings.key(Action::A), Some(Key::K));
+        assert_eq!(bindings.key(Action::B), Some(Key::J));
+        assert_eq!(bindings.key(Action::Pause), Some(Key::P));
+        assert_eq!(bindings.key(Action::QuickSave(1)), Some(Key::F1));
+        assert_eq!(bindings.key(Action::QuickSave(2)), None);
+
+        // Unlisted actions keep their defaults
+        assert_eq!(bindings.key(Action::Start), Some(Key::S));
+        assert_eq!(bindings.key(Action::Up), Some(Key::ArrowUp));
+
+        // Empty file is the defaults
+        assert_eq!(KeyBindings::from_toml("").unwrap(), KeyBindings::default());
+
+        // Empty key names unbind the action
+        let bindings = KeyBindings::from_toml("turbo = \"\"").unwrap();
+        assert_eq!(bindings.key(Action::Turbo), None);
+
+        assert!(matches!(
+            KeyBindings::from_toml("jump = \"K\""),
+            Err(KeyBindingsError::Parse(message)) if message == "unknown action jump"
+        ));
+        assert!(matches!(
+            KeyBindings::from_toml("a = \"NotAKey\""),
+            Err(KeyBindingsError::Parse(message)) if message == "unknown key NotAKey"
+        ));
+        assert!(matches!(
+            KeyBindings::from_toml("a = "),
+            Err(KeyBindingsError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn round_trip_key_bindings() {
+        let default = KeyBindings::default();
+        assert_eq!(KeyBindings::from_toml(&default.to_toml()).unwrap(), default);
+
+        let mut bindings = KeyBindings::default();
+        bindings.bind(Action::Pause, Key::Escape).unwrap();
+        bindings.bind(Action::QuickSave(9), Key::F9).unwrap();
+        bindings.unbind(Action::Turbo);
+        bindings.bind(Action::A, Key::Space).unwrap();
+
+        assert_eq!(
+            KeyBindings::from_toml(&bindings.to_toml()).unwrap(),
+            bindings
+        );
+    }
+
+    #[test]
+    fn conflicting_key_bindings_are_rejected() {
+        // Z is bound to B by default
+        assert!(matches!(
+            KeyBindings::from_toml("a = \"Z\""),
+            Err(KeyBindingsError::Conflict {
+                key: Key::Z,
+                first: Action::A,
+                second: Action::B,
+            })
+        ));
+
+        // Swapping keys is allowed when both are listed
+        let bindings = KeyBindings::from_toml("a = \"Z\"\nb = \"X\"").unwrap();
+        assert_eq!(bindings.key(Action::A), Some(Key::Z));
+
+        let mut bindings = KeyBindings::default();
+        let error = bindings.bind(Action::Start, Key::X).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "X is already bound to a, cannot also bind it to start"
+        );
+        assert_eq!(bindings, KeyBindings::default());
+
+        // Rebinding an action to its own key is not a conflict
+        bindings.bind(Action::A, Key::X).unwrap();
+    }
+
+    #[test]
+    fn read_keyboard_state() {
+        let bindings = KeyBindings::default();
+
+        let state = bindings.read_state(|key| matches!(key, Key::X | Key::ArrowLeft | Key::Space));
+        assert_eq!(
+            state,
+            InputState {
+                buttons: Button::A as u8 | Button::Left as u8,
+                is_turbo_pressed: true,
+            }
+        );
+
+        assert_eq!(bindings.read_state(|_| false), InputState::default());
+    }
+}
@@ -0,0 +1,331 @@
+use eframe::egui::{
+    self, Color32, Label, Pos2, RichText, ScrollArea, Sense, TextEdit, Vec2, ViewportId,
+};
+
+use crate::{
+    address_space::{
+        Address, ECHO_RAM_END, EXTERNAL_RAM_END, FIRST_WORK_RAM_BANK_END, HRAM_END,
+        IO_REGISTERS_END, OAM_END, ROM_END, SECOND_WORK_RAM_BANK_END, UNUSABLE_SPACE_END, VRAM_END,
+    },
+    emulator::{Command, Emulator},
+    gui::{
+        shell::EmulatorShellApp,
+        window_layout::{DOCK_BUTTON_BAR_HEIGHT, DebugView},
+    },
+    mbc::types::RamSelection,
+};
+
+pub const WINDOW_INNER_SIZE: Vec2 = Vec2::new(640.0, 600.0);
+
+/// Number of bytes shown on each row of the hex view
+const BYTES_PER_ROW: usize = 16;
+
+/// Number of rows needed to show the full address space
+const NUM_ROWS: usize = 0x10000 / BYTES_PER_ROW;
+
+const SELECTED_BYTE_COLOR: Color32 = Color32::from_rgb(0xC0, 0x90, 0x00);
+
+const REGION_LABEL_COLOR: Color32 = Color32::from_rgb(0x60, 0xC0, 0x60);
+
+/// Name of the memory region containing an address, including the bank currently mapped there.
+pub fn memory_region_label(emulator: &Emulator, addr: Address) -> String {
+    if addr < ROM_END {
+        format!("ROM bank {}", emulator.current_bank_at(addr))
+    } else if addr < VRAM_END {
+        format!("VRAM bank {}", emulator.current_bank_at(addr))
+    } else if addr < EXTERNAL_RAM_END {
+        match emulator.mbc_debug_state().ram_selection {
+            Some(RamSelection::Bank(bank)) => format!("SRAM bank {}", bank),
+            Some(RamSelection::RtcRegister(register)) => format!("RTC {}", register),
+            None => "SRAM".to_string(),
+        }
+    } else if addr < FIRST_WORK_RAM_BANK_END {
+        "WRAM bank 0".to_string()
+    } else if addr < SECOND_WORK_RAM_BANK_END {
+        format!("WRAM bank {}", emulator.current_bank_at(addr))
+    } else if addr < ECHO_RAM_END {
+        "Echo RAM".to_string()
+    } else if addr < OAM_END {
+        "OAM".to_string()
+    } else if addr < UNUSABLE_SPACE_END {
+        "Unusable".to_string()
+    } else if addr < IO_REGISTERS_END {
+        "IO".to_string()
+    } else if addr < HRAM_END {
+        "HRAM".to_string()
+    } else {
+        "IE".to_string()
+    }
+}
+
+/// Character shown for a byte in the ASCII column. Bytes outside printable ASCII are shown as dots.
+fn ascii_char(value: u8) -> char {
+    if value.is_ascii_graphic() || value == b' ' {
+        value as char
+    } else {
+        '.'
+    }
+}
+
+pub struct MemoryViewport {
+    /// Whether the viewport is currently shown
+    is_shown: bool,
+    /// Initial position of the viewport
+    initial_position: Pos2,
+    /// Text of the address or label to go to
+    go_to_text: String,
+    /// Row to scroll to on the next frame, after going to an address
+    scroll_to_row: Option<usize>,
+    /// Address of the byte selected for editing, if any
+    selected_address: Option<Address>,
+    /// Text of the new value for the selected byte
+    edit_text: String,
+}
+
+impl MemoryViewport {
+    pub fn new() -> Self {
+        MemoryViewport {
+            is_shown: false,
+            initial_position: Pos2::ZERO,
+            go_to_text: String::new(),
+            scroll_to_row: None,
+            selected_address: None,
+            edit_text: String::new(),
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.is_shown
+    }
+
+    pub fn open(&mut self, initial_position: Pos2) {
+        self.is_shown = true;
+        self.initial_position = initial_position;
+    }
+
+    pub fn close(&mut self) {
+        self.is_shown = false;
+        self.selected_address = None;
+    }
+
+    fn select(&mut self, addr: Address, value: u8) {
+        self.selected_address = Some(addr);
+        self.edit_text = format!("{:02X}", value);
+    }
+}
+
+impl EmulatorShellApp {
+    pub fn memory_viewport_id(&self) -> ViewportId {
+        ViewportId::from_hash_of("memory_viewport_id")
+    }
+
+    pub(super) fn draw_memory_viewport(&mut self, ui: &mut egui::Ui) {
+        ui.ctx().show_viewport_immediate(
+            self.memory_viewport_id(),
+            egui::ViewportBuilder::default()
+                .with_inner_size(WINDOW_INNER_SIZE + Vec2::new(0.0, DOCK_BUTTON_BAR_HEIGHT))
+                .with_position(self.memory_view().initial_position)
+                .with_resizable(true)
+                .with_active(true)
+                .with_title(DebugView::Memory.title()),
+            |ctx, _| {
+                self.draw_dock_button_bar(DebugView::Memory, ctx);
+                egui::CentralPanel::default().show(ctx, |ui| self.draw_memory_view(ui));
+            },
+        );
+    }
+
+    pub(super) fn draw_memory_view(&mut self, ui: &mut egui::Ui) {
+        self.draw_memory_go_to(ui);
+        self.draw_memory_editor(ui);
+        ui.separator();
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let mut scroll_area = ScrollArea::vertical().auto_shrink(false);
+        if let Some(row) = self.memory_view_mut().scroll_to_row.take() {
+            scroll_area = scroll_area.vertical_scroll_offset(row as f32 * row_height);
+        }
+
+        let mut clicked_address = None;
+
+        // Only the visible rows are read from the emulator each frame
+        scroll_area.show_rows(ui, row_height, NUM_ROWS, |ui, row_range| {
+            ui.spacing_mut().item_spacing = Vec2::ZERO;
+
+            let emulator = self.emulator();
+            let selected_address = self.memory_view().selected_address;
+
+            for row in row_range {
+                let row_start = (row * BYTES_PER_ROW) as Address;
+                let bytes = emulator.read_memory_bulk(row_start, BYTES_PER_ROW);
+
+                ui.horizontal(|ui| {
+                    ui.label(
+                        RichText::new(format!("{:<14}", memory_region_label(emulator, row_start)))
+                            .monospace()
+                            .color(REGION_LABEL_COLOR),
+                    );
+                    ui.label(RichText::new(format!("{:04X}  ", row_start)).monospace());
+
+                    for (i, value) in bytes.iter().enumerate() {
+                        let addr = row_start + i as Address;
+
+                        let mut text = RichText::new(format!("{:02X} ", value)).monospace();
+                        if selected_address == Some(addr) {
+                            text = text.background_color(SELECTED_BYTE_COLOR);
+                        }
+
+                        if ui.add(Label::new(text).sense(Sense::click())).clicked() {
+                            clicked_address = Some((addr, *value));
+                        }
+                    }
+
+                    let ascii = bytes
+                        .iter()
+                        .map(|value| ascii_char(*value))
+                        .collect::<String>();
+                    ui.label(RichText::new(format!(" {}", ascii)).monospace());
+                });
+            }
+        });
+
+        if let Some((addr, value)) = clicked_address {
+            self.memory_view_mut().select(addr, value);
+        }
+    }
+
+    /// Draw the box for going to an address, given in hex or as a label from the symbol file.
+    fn draw_memory_go_to(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Go to:");
+
+            let response = ui.add(
+                TextEdit::singleline(&mut self.memory_view_mut().go_to_text)
+                    .hint_text("address or label")
+                    .desired_width(160.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+            let is_submitted =
+                response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+
+            let go_to_text = self.memory_view().go_to_text.trim().to_string();
+            let addr = match self.symbols().lookup(&go_to_text) {
+                Some(banked_address) => Some(banked_address.address),
+                None => u16::from_str_radix(go_to_text.trim_start_matches('$'), 16).ok(),
+            };
+
+            let is_go_clicked = ui
+                .add_enabled(addr.is_some(), egui::Button::new("Go"))
+                .clicked();
+
+            if let Some(addr) = addr
+                && (is_submitted || is_go_clicked)
+            {
+                let value = self.emulator().peek_address(addr);
+                let view = self.memory_view_mut();
+                view.scroll_to_row = Some(addr as usize / BYTES_PER_ROW);
+                view.select(addr, value);
+            }
+        });
+    }
+
+    /// Draw the editor for the selected byte. The write is sent to the emulator thread, which
+    /// writes it without any of the side effects of a write by the CPU.
+    fn draw_memory_editor(&mut self, ui: &mut egui::Ui) {
+        let Some(addr) = self.memory_view().selected_address else {
+            ui.label("Click a byte to edit it.");
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            let region = memory_region_label(self.emulator(), addr);
+            ui.label(format!("{:04X} ({}):", addr, region));
+
+            let view = self.memory_view_mut();
+            ui.add(
+                TextEdit::singleline(&mut view.edit_text)
+                    .char_limit(2)
+                    .desired_width(24.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+
+            let value = u8::from_str_radix(view.edit_text.trim(), 16).ok();
+            if ui
+                .add_enabled(value.is_some(), egui::Button::new("Write"))
+                .clicked()
+                && let Some(value) = value
+            {
+                self.send_command(Command::WriteMemory(addr, value));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        emulator::Emulator,
+        machine::Machine,
+        test_utils::{build_cgb_test_rom, new_test_emulator_builder, with_large_stack},
+    };
+
+    use super::{ascii_char, memory_region_label};
+
+    fn new_idle_emulator() -> Emulator {
+        // MBC1 with 4 ROM banks and 32KB of RAM
+        let rom = build_cgb_test_rom(0x03, 0x01, 0x03, &[0x18, 0xFE]);
+        let mut emulator = new_test_emulator_builder(Machine::Cgb, rom)
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+        emulator
+    }
+
+    #[test]
+    fn region_labels_follow_banking() {
+        with_large_stack(|| {
+            let mut emulator = new_idle_emulator();
+
+            let labels = [
+                0x0000, 0x4000, 0x8000, 0xA000, 0xC000, 0xD000, 0xE000, 0xFE00, 0xFEA0, 0xFF00,
+                0xFF80, 0xFFFF,
+            ]
+            .map(|addr| memory_region_label(&emulator, addr));
+            assert_eq!(
+                labels,
+                [
+                    "ROM bank 0",
+                    "ROM bank 1",
+                    "VRAM bank 0",
+                    "SRAM bank 0",
+                    "WRAM bank 0",
+                    "WRAM bank 1",
+                    "Echo RAM",
+                    "OAM",
+                    "Unusable",
+                    "IO",
+                    "HRAM",
+                    "IE",
+                ]
+            );
+
+            // Switch ROM, SRAM, VRAM, and WRAM banks
+            emulator.write_address(0x2000, 0x03);
+            emulator.write_address(0x6000, 0x01);
+            emulator.write_address(0x4000, 0x02);
+            emulator.write_address(0xFF4F, 0x01);
+            emulator.write_address(0xFF70, 0x05);
+
+            assert_eq!(memory_region_label(&emulator, 0x7FFF), "ROM bank 3");
+            assert_eq!(memory_region_label(&emulator, 0x9FFF), "VRAM bank 1");
+            assert_eq!(memory_region_label(&emulator, 0xBFFF), "SRAM bank 2");
+            assert_eq!(memory_region_label(&emulator, 0xDFFF), "WRAM bank 5");
+        });
+    }
+
+    #[test]
+    fn ascii_column_hides_unprintable_bytes() {
+        let ascii = b"Hi! \x00\x7F\xFF~".map(ascii_char);
+        assert_eq!(ascii.iter().collect::<String>(), "Hi! ...~");
+    }
+}
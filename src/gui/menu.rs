@@ -2,15 +2,19 @@ use std::str::FromStr;
 
 use eframe::egui;
 use muda::{
-    CheckMenuItem, Menu, MenuEvent, MenuItem, MenuItemKind, PredefinedMenuItem, Submenu,
+    AboutMetadataBuilder, CheckMenuItem, Menu, MenuEvent, MenuItem, MenuItemKind,
+    PredefinedMenuItem, Submenu,
     accelerator::{Accelerator, Code, Modifiers},
 };
 
 use crate::{
     audio::NUM_AUDIO_CHANNELS,
     emulator::Command,
-    gui::shell::{EmulatorShellApp, ScreenColorPalette},
+    gui::{shell::EmulatorShellApp, window_layout::DebugView},
+    ram_init::RamInit,
     save_file::NUM_QUICK_SAVE_SLOTS,
+    screen_palette::ScreenColorPalette,
+    version::{self, BUILD_DATE, GIT_COMMIT_HASH, VERSION},
 };
 
 // Submenu IDs
@@ -18,6 +22,9 @@ const APP_NAME_SUBMENU_ID: &str = "app_name";
 const EMULATOR_SUBMENU_ID: &str = "emulator";
 const QUICK_SAVE_SUBMENU_ID: &str = "quick_save";
 const LOAD_QUICK_SAVE_SUBMENU_ID: &str = "load_quick_save";
+const SPEED_SUBMENU_ID: &str = "speed";
+const RAM_INIT_SUBMENU_ID: &str = "ram_init";
+const VIDEO_SUBMENU_ID: &str = "video";
 const COLOR_PALETTE_SUBMENU_ID: &str = "color_palette";
 const AUDIO_SUBMENU_ID: &str = "audio";
 const AUDIO_DEBUG_SUBMENU_ID: &str = "audio_debug";
@@ -27,9 +34,16 @@ const WINDOW_SUBMENU_ID: &str = "window";
 // Menu item IDs
 const QUIT_ITEM_ID: &str = "quit";
 const PAUSE_ITEM_ID: &str = "pause";
+const FRAME_ADVANCE_ITEM_ID: &str = "frame_advance";
 const SAVE_ITEM_ID: &str = "save";
 const QUICK_SAVE_ITEM_ID_PREFIX: &str = "quick_save_";
 const LOAD_QUICK_SAVE_ITEM_ID_PREFIX: &str = "load_quick_save_";
+const EXPORT_STATE_ITEM_ID: &str = "export_state";
+const IMPORT_STATE_ITEM_ID: &str = "import_state";
+const SPEED_ITEM_ID_PREFIX: &str = "speed_";
+const RAM_INIT_ITEM_ID_PREFIX: &str = "ram_init_";
+const OPEN_CONTROLS_VIEW_ITEM_ID: &str = "open_controls_view";
+const RESTART_AS_CGB_ITEM_ID: &str = "restart_as_cgb";
 const MUTE_ITEM_ID: &str = "mute";
 const VOLUME_UP_ITEM_ID: &str = "volume_up";
 const VOLUME_DOWN_ITEM_ID: &str = "volume_down";
@@ -37,10 +51,31 @@ const TOGGLE_HPF_ITEM_ID: &str = "toggle_hpf";
 const TOGGLE_AUDIO_CHANNEL_ITEM_ID_PREFIX: &str = "toggle_audio_channel_";
 const START_DEBUGGING_ITEM_ID: &str = "start_debugging";
 const OPEN_VRAM_VIEW_ITEM_ID: &str = "open_vram_view";
+const OPEN_OAM_VIEW_ITEM_ID: &str = "open_oam_view";
+const OPEN_PALETTE_VIEW_ITEM_ID: &str = "open_palette_view";
+const OPEN_IO_REGISTERS_VIEW_ITEM_ID: &str = "open_io_registers_view";
+const OPEN_CARTRIDGE_RAM_VIEW_ITEM_ID: &str = "open_cartridge_ram_view";
+const OPEN_MEMORY_VIEW_ITEM_ID: &str = "open_memory_view";
+const DUMP_PPU_STATE_ITEM_ID: &str = "dump_ppu_state";
+const RELOAD_SYMBOLS_ITEM_ID: &str = "reload_symbols";
 const SHOW_FPS_ITEM_ID: &str = "show_fps";
+const SHOW_PIXEL_INFO_ITEM_ID: &str = "show_pixel_info";
 const RESIZE_TO_FIT_ITEM_ID: &str = "resize_to_fit";
 const COLOR_PALETTE_GRAYSCALE_ITEM_ID: &str = "color_palette_grayscale";
 const COLOR_PALETTE_GREEN_ITEM_ID: &str = "color_palette_green";
+const FRAME_BLENDING_ITEM_ID: &str = "frame_blending";
+const INTEGER_SCALING_ITEM_ID: &str = "integer_scaling";
+
+/// Speeds that can be chosen from the Speed menu, as multiples of normal speed
+const MENU_SPEEDS: [f64; 4] = [0.5, 1.0, 2.0, 4.0];
+
+/// RAM contents that can be chosen from the Power-On RAM menu, by their `--ram-init` value
+const MENU_RAM_INITS: [(&str, &str); 4] = [
+    ("zero", "Zeros"),
+    ("ones", "Ones"),
+    ("pattern", "Pattern"),
+    ("random", "Random"),
+];
 
 impl EmulatorShellApp {
     pub(super) fn handle_menu_events(&mut self, ctx: &egui::Context) {
@@ -51,31 +86,63 @@ impl EmulatorShellApp {
                     ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                 }
                 PAUSE_ITEM_ID => self.send_command(Command::TogglePause),
-                SAVE_ITEM_ID => self.send_command(Command::Save),
+                FRAME_ADVANCE_ITEM_ID => self.send_command(Command::FrameAdvance),
+                SAVE_ITEM_ID => self.send_fallible_command("Save", Command::Save),
+                EXPORT_STATE_ITEM_ID => self.export_state(),
+                IMPORT_STATE_ITEM_ID => self.import_state(),
+                OPEN_CONTROLS_VIEW_ITEM_ID => self.show_controls_view(ctx),
+                RESTART_AS_CGB_ITEM_ID => self.restart_emulating_cgb(ctx),
                 MUTE_ITEM_ID => self.send_command(Command::ToggleMute),
                 VOLUME_UP_ITEM_ID => self.send_command(Command::VolumeUp),
                 VOLUME_DOWN_ITEM_ID => self.send_command(Command::VolumeDown),
                 TOGGLE_HPF_ITEM_ID => self.send_command(Command::ToggleHpf),
                 RESIZE_TO_FIT_ITEM_ID => self.resize_to_fit(ctx),
                 START_DEBUGGING_ITEM_ID => self.show_debugger_view(ctx),
-                OPEN_VRAM_VIEW_ITEM_ID => self.show_vram_view(ctx),
+                OPEN_VRAM_VIEW_ITEM_ID => self.show_debug_view(DebugView::Vram, ctx),
+                OPEN_PALETTE_VIEW_ITEM_ID => self.show_debug_view(DebugView::Palettes, ctx),
+                OPEN_OAM_VIEW_ITEM_ID => self.show_debug_view(DebugView::Oam, ctx),
+                OPEN_IO_REGISTERS_VIEW_ITEM_ID => {
+                    self.show_debug_view(DebugView::IoRegisters, ctx);
+                }
+                OPEN_CARTRIDGE_RAM_VIEW_ITEM_ID => {
+                    self.show_debug_view(DebugView::CartridgeRam, ctx);
+                }
+                OPEN_MEMORY_VIEW_ITEM_ID => self.show_debug_view(DebugView::Memory, ctx),
+                DUMP_PPU_STATE_ITEM_ID => self.dump_ppu_state(),
+                RELOAD_SYMBOLS_ITEM_ID => self.load_symbols(true),
                 SHOW_FPS_ITEM_ID => self.toggle_show_fps(),
+                SHOW_PIXEL_INFO_ITEM_ID => self.toggle_show_pixel_info(),
                 COLOR_PALETTE_GRAYSCALE_ITEM_ID => {
                     self.set_color_palette(ScreenColorPalette::Grayscale);
                 }
                 COLOR_PALETTE_GREEN_ITEM_ID => {
                     self.set_color_palette(ScreenColorPalette::Green);
                 }
+                FRAME_BLENDING_ITEM_ID => self.toggle_frame_blending(),
+                INTEGER_SCALING_ITEM_ID => self.toggle_integer_scaling(),
                 _ => {
                     if let Some(slot_number) = item_id.strip_prefix(QUICK_SAVE_ITEM_ID_PREFIX) {
                         let slot = usize::from_str(slot_number).unwrap();
-                        self.send_command(Command::QuickSave(slot));
+                        self.send_fallible_command("Quick save", |id| Command::QuickSave(slot, id));
                     }
 
                     if let Some(slot_number) = item_id.strip_prefix(LOAD_QUICK_SAVE_ITEM_ID_PREFIX)
                     {
                         let slot = usize::from_str(slot_number).unwrap();
-                        self.send_command(Command::LoadQuickSave(slot));
+                        self.send_fallible_command("Load quick save", |id| {
+                            Command::LoadQuickSave(slot, id)
+                        });
+                    }
+
+                    if let Some(speed_index) = item_id.strip_prefix(SPEED_ITEM_ID_PREFIX) {
+                        let speed = MENU_SPEEDS[usize::from_str(speed_index).unwrap()];
+                        self.send_command(Command::SetSpeed(speed));
+                    }
+
+                    // Random RAM is given a new seed on every restart
+                    if let Some(ram_init) = item_id.strip_prefix(RAM_INIT_ITEM_ID_PREFIX) {
+                        let ram_init = RamInit::from_str(ram_init).unwrap();
+                        self.restart_with_ram_init(ram_init, ctx);
                     }
 
                     if let Some(channel_number) =
@@ -97,6 +164,23 @@ impl EmulatorShellApp {
         grayscale_menu_item.set_checked(matches!(scren_palette, ScreenColorPalette::Grayscale));
         green_menu_item.set_checked(matches!(scren_palette, ScreenColorPalette::Green));
     }
+
+    /// Check the item for the given speed, if there is one. No item is checked in turbo mode.
+    pub(super) fn update_speed_menu(&self, speed: f64) {
+        for (i, menu_speed) in MENU_SPEEDS.iter().enumerate() {
+            let menu_item =
+                find_check_menu_item(self.menu(), &format!("{SPEED_ITEM_ID_PREFIX}{i}"));
+            menu_item.set_checked(*menu_speed == speed);
+        }
+    }
+
+    pub(super) fn enable_restart_as_cgb_menu_item(&self) {
+        if let Some(MenuItemKind::MenuItem(menu_item)) =
+            find_menu_item(self.menu(), RESTART_AS_CGB_ITEM_ID)
+        {
+            menu_item.set_enabled(true);
+        }
+    }
 }
 
 fn app_name_menu() -> Submenu {
@@ -104,17 +188,43 @@ fn app_name_menu() -> Submenu {
         APP_NAME_SUBMENU_ID,
         "GBC Emulator",
         true,
-        &[&MenuItem::with_id(
-            QUIT_ITEM_ID,
-            "Quit GBC Emulator",
-            true,
-            Some(Accelerator::new(Some(Modifiers::META), Code::KeyQ)),
-        )],
+        &[
+            &about_menu_item(),
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(
+                QUIT_ITEM_ID,
+                "Quit GBC Emulator",
+                true,
+                Some(Accelerator::new(Some(Modifiers::META), Code::KeyQ)),
+            ),
+        ],
     )
     .unwrap()
 }
 
-fn emulator_menu() -> Submenu {
+/// Native About dialog showing the version and build information.
+fn about_menu_item() -> PredefinedMenuItem {
+    let features = version::enabled_features();
+    let features = if features.is_empty() {
+        "none".to_string()
+    } else {
+        features.join(", ")
+    };
+
+    let metadata = AboutMetadataBuilder::new()
+        .name(Some("GBC Emulator"))
+        .version(Some(VERSION))
+        .short_version(Some(GIT_COMMIT_HASH))
+        .comments(Some(format!(
+            "Commit {}\nBuilt {}\nFeatures: {}",
+            GIT_COMMIT_HASH, BUILD_DATE, features
+        )))
+        .build();
+
+    PredefinedMenuItem::about(Some("About GBC Emulator"), Some(metadata))
+}
+
+fn emulator_menu(ram_init: RamInit) -> Submenu {
     let quick_save_submenu = Submenu::with_id(QUICK_SAVE_SUBMENU_ID, "Quick Save", true);
     let load_quick_save_submenu =
         Submenu::with_id(LOAD_QUICK_SAVE_SUBMENU_ID, "Load Quick Save", true);
@@ -145,22 +255,38 @@ fn emulator_menu() -> Submenu {
             .unwrap();
     }
 
-    let color_palette_submenu = Submenu::with_id_and_items(
-        COLOR_PALETTE_SUBMENU_ID,
-        "Color Palette",
-        true,
-        &[
-            &CheckMenuItem::with_id(
-                COLOR_PALETTE_GRAYSCALE_ITEM_ID,
-                "Grayscale",
+    // Checked once the GUI sees the emulator's speed, since the initial speed comes from the options
+    let speed_submenu = Submenu::with_id(SPEED_SUBMENU_ID, "Speed", true);
+    for (i, speed) in MENU_SPEEDS.iter().enumerate() {
+        speed_submenu
+            .append(&CheckMenuItem::with_id(
+                format!("{SPEED_ITEM_ID_PREFIX}{i}"),
+                format!("{speed}x"),
                 true,
+                false,
+                None,
+            ))
+            .unwrap();
+    }
+
+    // RAM is only initialized at power-on, so choosing an item restarts the emulator
+    let ram_init_submenu = Submenu::with_id(RAM_INIT_SUBMENU_ID, "Power-On RAM", true);
+    for (value, label) in MENU_RAM_INITS {
+        let is_checked = match ram_init {
+            RamInit::Random(_) => value == "random",
+            ram_init => ram_init.to_string() == value,
+        };
+
+        ram_init_submenu
+            .append(&CheckMenuItem::with_id(
+                format!("{RAM_INIT_ITEM_ID_PREFIX}{value}"),
+                label,
                 true,
+                is_checked,
                 None,
-            ),
-            &CheckMenuItem::with_id(COLOR_PALETTE_GREEN_ITEM_ID, "Green", true, false, None),
-        ],
-    )
-    .unwrap();
+            ))
+            .unwrap();
+    }
 
     Submenu::with_id_and_items(
         EMULATOR_SUBMENU_ID,
@@ -174,6 +300,8 @@ fn emulator_menu() -> Submenu {
                 false,
                 Some(Accelerator::new(Some(Modifiers::META), Code::KeyP)),
             ),
+            &MenuItem::with_id(FRAME_ADVANCE_ITEM_ID, "Advance Frame", true, None),
+            &speed_submenu,
             &PredefinedMenuItem::separator(),
             &MenuItem::with_id(
                 SAVE_ITEM_ID,
@@ -183,8 +311,57 @@ fn emulator_menu() -> Submenu {
             ),
             &quick_save_submenu,
             &load_quick_save_submenu,
+            &MenuItem::with_id(EXPORT_STATE_ITEM_ID, "Export State...", true, None),
+            &MenuItem::with_id(IMPORT_STATE_ITEM_ID, "Import State...", true, None),
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(OPEN_CONTROLS_VIEW_ITEM_ID, "Controls...", true, None),
             &PredefinedMenuItem::separator(),
+            &ram_init_submenu,
+            // Enabled once the game appears to require a CGB
+            &MenuItem::with_id(
+                RESTART_AS_CGB_ITEM_ID,
+                "Restart as Game Boy Color",
+                false,
+                None,
+            ),
+        ],
+    )
+    .unwrap()
+}
+
+fn video_menu() -> Submenu {
+    // Checked once the first frame is drawn, since the initial palette comes from the options
+    let color_palette_submenu = Submenu::with_id_and_items(
+        COLOR_PALETTE_SUBMENU_ID,
+        "Palette",
+        true,
+        &[
+            &CheckMenuItem::with_id(
+                COLOR_PALETTE_GRAYSCALE_ITEM_ID,
+                "Grayscale",
+                true,
+                false,
+                None,
+            ),
+            &CheckMenuItem::with_id(COLOR_PALETTE_GREEN_ITEM_ID, "Green", true, false, None),
+        ],
+    )
+    .unwrap();
+
+    Submenu::with_id_and_items(
+        VIDEO_SUBMENU_ID,
+        "Video",
+        true,
+        &[
             &color_palette_submenu,
+            &CheckMenuItem::with_id(FRAME_BLENDING_ITEM_ID, "Frame Blending", true, false, None),
+            &CheckMenuItem::with_id(
+                INTEGER_SCALING_ITEM_ID,
+                "Integer Scaling",
+                true,
+                false,
+                None,
+            ),
         ],
     )
     .unwrap()
@@ -266,9 +443,42 @@ fn debug_menu() -> Submenu {
                 true,
                 Some(Accelerator::new(Some(Modifiers::META), Code::KeyD)),
             ),
+            &MenuItem::with_id(RELOAD_SYMBOLS_ITEM_ID, "Reload Symbols", true, None),
             &PredefinedMenuItem::separator(),
             &MenuItem::with_id(OPEN_VRAM_VIEW_ITEM_ID, "Open VRAM View", true, None),
-            &CheckMenuItem::with_id(SHOW_FPS_ITEM_ID, "Show FPS", true, false, None),
+            &MenuItem::with_id(OPEN_OAM_VIEW_ITEM_ID, "Open OAM View", true, None),
+            &MenuItem::with_id(OPEN_PALETTE_VIEW_ITEM_ID, "Open Palette View", true, None),
+            &MenuItem::with_id(
+                OPEN_IO_REGISTERS_VIEW_ITEM_ID,
+                "Open IO Registers View",
+                true,
+                None,
+            ),
+            &MenuItem::with_id(
+                OPEN_CARTRIDGE_RAM_VIEW_ITEM_ID,
+                "Open Cartridge RAM View",
+                true,
+                None,
+            ),
+            &MenuItem::with_id(OPEN_MEMORY_VIEW_ITEM_ID, "Open Memory View", true, None),
+            &MenuItem::with_id(DUMP_PPU_STATE_ITEM_ID, "Dump PPU State", true, None),
+            &CheckMenuItem::with_id(
+                SHOW_FPS_ITEM_ID,
+                "Show Performance Overlay",
+                true,
+                false,
+                Some(Accelerator::new(
+                    Some(Modifiers::META | Modifiers::SHIFT),
+                    Code::KeyF,
+                )),
+            ),
+            &CheckMenuItem::with_id(
+                SHOW_PIXEL_INFO_ITEM_ID,
+                "Show Pixel Info",
+                true,
+                false,
+                None,
+            ),
         ],
     )
     .unwrap()
@@ -316,10 +526,11 @@ fn find_in_items(items: Vec<MenuItemKind>, id: &str) -> Option<MenuItemKind> {
     None
 }
 
-pub fn create_app_menu() -> Menu {
+pub fn create_app_menu(ram_init: RamInit) -> Menu {
     let menu = Menu::new();
     menu.append(&app_name_menu()).unwrap();
-    menu.append(&emulator_menu()).unwrap();
+    menu.append(&emulator_menu(ram_init)).unwrap();
+    menu.append(&video_menu()).unwrap();
     menu.append(&audio_menu()).unwrap();
     menu.append(&debug_menu()).unwrap();
     menu.append(&window_menu()).unwrap();
@@ -1,5 +1,15 @@
+mod cartridge_ram_view;
+mod color;
+mod controls_view;
 mod debugger_view;
+pub mod gamepad;
+mod io_registers_view;
+pub mod key_bindings;
+mod memory_view;
 mod menu;
+mod oam_view;
+mod palette_view;
+mod perf_overlay;
 pub mod shell;
-mod utils;
 mod vram_view;
+pub mod window_layout;
@@ -0,0 +1,305 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui::{
+    self, Color32, CornerRadius, Pos2, Rect, ScrollArea, Sense, Stroke, StrokeKind, Vec2,
+    ViewportId,
+};
+
+use crate::{
+    emulator::{Emulator, SCREEN_HEIGHT, SCREEN_WIDTH},
+    gui::{
+        shell::EmulatorShellApp,
+        window_layout::{DOCK_BUTTON_BAR_HEIGHT, DebugView},
+    },
+    ppu::{
+        NUM_OBJECTS, Object, TILE_SIZE, lookup_all_pixels_in_object, lookup_color_in_palette,
+        object_color_palette, object_height,
+    },
+};
+
+pub const WINDOW_INNER_SIZE: Vec2 = Vec2::new(680.0, 360.0);
+
+/// Number of screen pixels per object pixel in the thumbnails
+const THUMBNAIL_SCALE: f32 = 3.0;
+const THUMBNAIL_SPACING: f32 = 6.0;
+const THUMBNAILS_PER_ROW: usize = 8;
+
+/// Drawn behind transparent object pixels
+const TRANSPARENT_COLOR: Color32 = Color32::from_rgb(0x40, 0x40, 0x40);
+
+const SELECTED_OBJECT_COLOR: Color32 = Color32::RED;
+
+/// How long the selected object stays outlined on the main screen after it is clicked
+const HIGHLIGHT_DURATION: Duration = Duration::from_millis(500);
+
+pub struct OamViewport {
+    /// Whether the viewport is currently shown
+    is_shown: bool,
+    /// Initial position of the viewport
+    initial_position: Pos2,
+    /// Index of the object selected in the view, if any
+    selected_object: Option<usize>,
+    /// When the selected object was clicked, used to outline it on the main screen for a moment
+    selected_at: Option<Instant>,
+}
+
+impl OamViewport {
+    pub fn new() -> Self {
+        OamViewport {
+            is_shown: false,
+            initial_position: Pos2::ZERO,
+            selected_object: None,
+            selected_at: None,
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.is_shown
+    }
+
+    pub fn open(&mut self, initial_position: Pos2) {
+        self.is_shown = true;
+        self.initial_position = initial_position;
+    }
+
+    pub fn close(&mut self) {
+        self.is_shown = false;
+        self.selected_object = None;
+        self.selected_at = None;
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected_object = Some(index);
+        self.selected_at = Some(Instant::now());
+    }
+}
+
+/// Area an object covers on screen. Objects are placed at an offset of (8, 16) so that they can
+/// scroll in from the top left, meaning part or all of the area may be off-screen.
+fn object_screen_area(emulator: &Emulator, object: &Object) -> (i16, i16, i16) {
+    let x = object.x as i16 - 8;
+    let y = object.y as i16 - 16;
+    let height = object_height(emulator.is_lcdc_obj_double_size()) as i16;
+
+    (x, y, height)
+}
+
+fn is_object_on_screen(emulator: &Emulator, object: &Object) -> bool {
+    let (x, y, height) = object_screen_area(emulator, object);
+    x < SCREEN_WIDTH as i16
+        && x + TILE_SIZE as i16 > 0
+        && y < SCREEN_HEIGHT as i16
+        && y + height > 0
+}
+
+/// The attribute flags that apply in the current mode, as a short description.
+fn describe_attributes(emulator: &Emulator, object: &Object) -> String {
+    let mut flags = vec![];
+
+    if emulator.in_cgb_mode() {
+        flags.push(format!("Pal {}", object.cgb_pallette_number()));
+        flags.push(format!("Bank {}", object.vram_bank_number()));
+    } else {
+        flags.push(format!("OBP{}", object.dmg_palette_number()));
+    }
+
+    if object.is_horizontally_flipped() {
+        flags.push("X flip".to_string());
+    }
+
+    if object.is_vertically_flipped() {
+        flags.push("Y flip".to_string());
+    }
+
+    if object.in_background() {
+        flags.push("Behind BG".to_string());
+    }
+
+    flags.join(", ")
+}
+
+impl EmulatorShellApp {
+    pub fn oam_viewport_id(&self) -> ViewportId {
+        ViewportId::from_hash_of("oam_viewport_id")
+    }
+
+    pub(super) fn draw_oam_viewport(&mut self, ui: &mut egui::Ui) {
+        ui.ctx().show_viewport_immediate(
+            self.oam_viewport_id(),
+            egui::ViewportBuilder::default()
+                .with_inner_size(WINDOW_INNER_SIZE + Vec2::new(0.0, DOCK_BUTTON_BAR_HEIGHT))
+                .with_position(self.oam_view().initial_position)
+                .with_resizable(true)
+                .with_active(true)
+                .with_title(DebugView::Oam.title()),
+            |ctx, _| {
+                self.draw_dock_button_bar(DebugView::Oam, ctx);
+                egui::CentralPanel::default().show(ctx, |ui| self.draw_oam_view(ui));
+            },
+        );
+    }
+
+    pub(super) fn draw_oam_view(&mut self, ui: &mut egui::Ui) {
+        ui.label("Click an object to outline it on the screen.");
+        ui.add_space(10.0);
+
+        ui.horizontal_top(|ui| {
+            self.draw_object_thumbnails(ui);
+            ui.add_space(20.0);
+            self.draw_object_table(ui);
+        });
+    }
+
+    fn draw_object_thumbnails(&mut self, ui: &mut egui::Ui) {
+        let object_height = object_height(self.emulator().is_lcdc_obj_double_size()) as usize;
+        let thumbnail_size = Vec2::new(
+            TILE_SIZE as f32 * THUMBNAIL_SCALE,
+            object_height as f32 * THUMBNAIL_SCALE,
+        );
+
+        let mut clicked_object = None;
+
+        egui::Grid::new("oam_thumbnails")
+            .spacing(Vec2::splat(THUMBNAIL_SPACING))
+            .show(ui, |ui| {
+                for index in 0..NUM_OBJECTS {
+                    let (rect, response) = ui.allocate_exact_size(thumbnail_size, Sense::click());
+                    self.draw_object_thumbnail(ui.painter(), rect, index);
+
+                    let object = Object::from_oam(self.emulator().oam(), index);
+                    let response = response.on_hover_text(format!(
+                        "Object {}: tile 0x{:02X}, {}",
+                        index,
+                        object.tile_index,
+                        describe_attributes(self.emulator(), &object)
+                    ));
+                    if response.clicked() {
+                        clicked_object = Some(index);
+                    }
+
+                    if (index + 1) % THUMBNAILS_PER_ROW == 0 {
+                        ui.end_row();
+                    }
+                }
+            });
+
+        if let Some(index) = clicked_object {
+            self.oam_view_mut().select(index);
+        }
+    }
+
+    fn draw_object_thumbnail(&self, painter: &egui::Painter, rect: Rect, index: usize) {
+        let emulator = self.emulator();
+        let object = Object::from_oam(emulator.oam(), index);
+        let palette = object_color_palette(emulator, &object);
+
+        painter.rect_filled(rect, CornerRadius::ZERO, TRANSPARENT_COLOR);
+
+        for (y, row) in lookup_all_pixels_in_object(emulator, &object)
+            .iter()
+            .enumerate()
+        {
+            for (x, color_index) in row.iter().enumerate() {
+                // Color index 0 is transparent for objects
+                if *color_index == 0 {
+                    continue;
+                }
+
+                let color = lookup_color_in_palette(&palette, *color_index);
+                let pixel_min = rect.min + Vec2::new(x as f32, y as f32) * THUMBNAIL_SCALE;
+                let pixel_rect = Rect::from_min_size(pixel_min, Vec2::splat(THUMBNAIL_SCALE));
+                painter.rect_filled(pixel_rect, CornerRadius::ZERO, self.color_to_color32(color));
+            }
+        }
+
+        let stroke = if self.oam_view().selected_object == Some(index) {
+            Stroke::new(2.0, SELECTED_OBJECT_COLOR)
+        } else {
+            Stroke::new(1.0, Color32::BLACK)
+        };
+        painter.rect_stroke(rect, CornerRadius::ZERO, stroke, StrokeKind::Outside);
+    }
+
+    fn draw_object_table(&mut self, ui: &mut egui::Ui) {
+        let mut clicked_object = None;
+
+        ScrollArea::vertical().auto_shrink(false).show(ui, |ui| {
+            egui::Grid::new("oam_table")
+                .striped(true)
+                .num_columns(5)
+                .show(ui, |ui| {
+                    ui.strong("#");
+                    ui.strong("X");
+                    ui.strong("Y");
+                    ui.strong("Tile");
+                    ui.strong("Attributes");
+                    ui.end_row();
+
+                    for index in 0..NUM_OBJECTS {
+                        let emulator = self.emulator();
+                        let object = Object::from_oam(emulator.oam(), index);
+
+                        let is_selected = self.oam_view().selected_object == Some(index);
+                        if ui
+                            .selectable_label(is_selected, format!("{index}"))
+                            .clicked()
+                        {
+                            clicked_object = Some(index);
+                        }
+
+                        ui.monospace(format!("{}", object.x));
+                        ui.monospace(format!("{}", object.y));
+                        ui.monospace(format!("0x{:02X}", object.tile_index));
+
+                        let mut attributes = describe_attributes(emulator, &object);
+                        if !is_object_on_screen(emulator, &object) {
+                            attributes.push_str(" (off-screen)");
+                        }
+                        ui.label(attributes);
+                        ui.end_row();
+                    }
+                });
+        });
+
+        if let Some(index) = clicked_object {
+            self.oam_view_mut().select(index);
+        }
+    }
+
+    /// Outline the selected object on the main screen for a moment after it is clicked. Objects
+    /// that are partly off-screen are clipped to the screen.
+    pub(super) fn draw_selected_object_highlight(&mut self, ui: &mut egui::Ui) {
+        let (Some(index), Some(selected_at)) =
+            (self.oam_view().selected_object, self.oam_view().selected_at)
+        else {
+            return;
+        };
+
+        if !self.oam_view().is_shown() || selected_at.elapsed() > HIGHLIGHT_DURATION {
+            self.oam_view_mut().selected_at = None;
+            return;
+        }
+
+        let emulator = self.emulator();
+        let object = Object::from_oam(emulator.oam(), index);
+        if !is_object_on_screen(emulator, &object) {
+            return;
+        }
+
+        let screen_rect = self.screen_rect(ui.ctx());
+        let scale_factor = screen_rect.width() / SCREEN_WIDTH as f32;
+
+        let (x, y, height) = object_screen_area(emulator, &object);
+        let object_rect = Rect::from_min_size(
+            screen_rect.min + Vec2::new(x as f32, y as f32) * scale_factor,
+            Vec2::new(TILE_SIZE as f32, height as f32) * scale_factor,
+        );
+
+        ui.painter().with_clip_rect(screen_rect).rect_stroke(
+            object_rect,
+            CornerRadius::ZERO,
+            Stroke::new(2.0, SELECTED_OBJECT_COLOR),
+            StrokeKind::Inside,
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use eframe::egui::{self, Color32, Pos2, Sense, Stroke, StrokeKind, Vec2, ViewportId};
+
+use crate::{
+    emulator::Command,
+    gui::{
+        shell::EmulatorShellApp,
+        window_layout::{DOCK_BUTTON_BAR_HEIGHT, DebugView},
+    },
+    ppu::{CgbColor, NUM_CGB_PALETTES, PALETTE_SIZE, lookup_cgb_color},
+};
+
+const SWATCH_SIZE: f32 = 24.0;
+const SWATCH_SPACING: f32 = 4.0;
+
+pub const WINDOW_INNER_SIZE: Vec2 = Vec2::new(520.0, 520.0);
+
+/// A single color in CGB palette memory.
+#[derive(Clone, Copy, PartialEq)]
+struct Swatch {
+    is_object: bool,
+    palette: usize,
+    index: usize,
+}
+
+pub struct PaletteViewport {
+    /// Whether the viewport is currently shown
+    is_shown: bool,
+    /// Initial position of the viewport
+    initial_position: Pos2,
+    /// The swatch being edited in the color picker, if any
+    selected_swatch: Option<Swatch>,
+    /// Color shown in the color picker. Kept separately from the emulator's palette so that
+    /// picking does not snap to the nearest CGB color while dragging.
+    picked_color: Color32,
+}
+
+impl PaletteViewport {
+    pub fn new() -> Self {
+        PaletteViewport {
+            is_shown: false,
+            initial_position: Pos2::ZERO,
+            selected_swatch: None,
+            picked_color: Color32::BLACK,
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.is_shown
+    }
+
+    pub fn open(&mut self, initial_position: Pos2) {
+        self.is_shown = true;
+        self.initial_position = initial_position;
+    }
+
+    pub fn close(&mut self) {
+        self.is_shown = false;
+        self.selected_swatch = None;
+    }
+}
+
+impl EmulatorShellApp {
+    pub fn palette_viewport_id(&self) -> ViewportId {
+        ViewportId::from_hash_of("palette_viewport_id")
+    }
+
+    pub(super) fn draw_palette_viewport(&mut self, ui: &mut egui::Ui) {
+        ui.ctx().show_viewport_immediate(
+            self.palette_viewport_id(),
+            egui::ViewportBuilder::default()
+                .with_inner_size(WINDOW_INNER_SIZE + Vec2::new(0.0, DOCK_BUTTON_BAR_HEIGHT))
+                .with_position(self.palette_view().initial_position)
+                .with_resizable(false)
+                .with_active(true)
+                .with_title(DebugView::Palettes.title()),
+            |ctx, _| {
+                self.draw_dock_button_bar(DebugView::Palettes, ctx);
+                egui::CentralPanel::default().show(ctx, |ui| self.draw_palette_view(ui));
+            },
+        );
+    }
+
+    pub(super) fn draw_palette_view(&mut self, ui: &mut egui::Ui) {
+        if !self.emulator().in_cgb_mode() {
+            ui.label("CGB palettes are only used by games running in CGB mode.");
+            return;
+        }
+
+        ui.label("Click a color to edit it. Edits take effect on the next frame, and are lost if the game rewrites the palette.");
+        ui.add_space(10.0);
+
+        ui.horizontal_top(|ui| {
+            ui.vertical(|ui| {
+                ui.label("Background:");
+                self.draw_palette_swatches(ui, false);
+
+                ui.add_space(10.0);
+                ui.label("Object:");
+                self.draw_palette_swatches(ui, true);
+            });
+
+            ui.add_space(20.0);
+            self.draw_palette_color_picker(ui);
+        });
+    }
+
+    fn cgb_palette_color(&self, swatch: Swatch) -> CgbColor {
+        let palettes = if swatch.is_object {
+            self.emulator().cgb_object_palettes()
+        } else {
+            self.emulator().cgb_background_palettes()
+        };
+
+        lookup_cgb_color(palettes, swatch.palette, swatch.index)
+    }
+
+    fn draw_palette_swatches(&mut self, ui: &mut egui::Ui, is_object: bool) {
+        for palette in 0..NUM_CGB_PALETTES {
+            ui.horizontal(|ui| {
+                ui.spacing_mut().item_spacing.x = SWATCH_SPACING;
+                ui.monospace(format!("{palette}"));
+
+                for index in 0..PALETTE_SIZE {
+                    let swatch = Swatch {
+                        is_object,
+                        palette,
+                        index,
+                    };
+                    let color = self.cgb_palette_color(swatch);
+
+                    let (rect, response) =
+                        ui.allocate_exact_size(Vec2::splat(SWATCH_SIZE), Sense::click());
+                    ui.painter().rect_filled(rect, 0.0, color.to_color32());
+
+                    let is_selected = self.palette_view().selected_swatch == Some(swatch);
+                    let stroke = if is_selected {
+                        Stroke::new(2.0, Color32::RED)
+                    } else {
+                        Stroke::new(1.0, Color32::BLACK)
+                    };
+                    ui.painter()
+                        .rect_stroke(rect, 0.0, stroke, StrokeKind::Outside);
+
+                    let response = response.on_hover_text(format!("0x{:04X}", color.raw()));
+                    if response.clicked() {
+                        let palette_view = self.palette_view_mut();
+                        palette_view.selected_swatch = Some(swatch);
+                        palette_view.picked_color = color.to_color32();
+                    }
+                }
+            });
+        }
+    }
+
+    fn draw_palette_color_picker(&mut self, ui: &mut egui::Ui) {
+        let Some(swatch) = self.palette_view().selected_swatch else {
+            return;
+        };
+
+        ui.vertical(|ui| {
+            let kind = if swatch.is_object {
+                "Object"
+            } else {
+                "Background"
+            };
+            ui.label(format!(
+                "{} palette {}, color {}",
+                kind, swatch.palette, swatch.index
+            ));
+
+            let mut picked_color = self.palette_view().picked_color;
+            let is_changed = egui::color_picker::color_picker_color32(
+                ui,
+                &mut picked_color,
+                egui::color_picker::Alpha::Opaque,
+            );
+
+            let color = CgbColor::from_color32(picked_color);
+            ui.monospace(format!("0x{:04X}", color.raw()));
+
+            if is_changed {
+                self.palette_view_mut().picked_color = picked_color;
+
+                if color != self.cgb_palette_color(swatch) {
+                    self.send_command(Command::WriteCgbPalette {
+                        is_object: swatch.is_object,
+                        palette: swatch.palette as u8,
+                        index: swatch.index as u8,
+                        color: color.raw(),
+                    });
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use eframe::{
+    egui::{self, Align2, Color32, Vec2},
+    epaint::CornerRadius,
+};
+
+use crate::{
+    gui::shell::{EmulatorShellApp, TOAST_BACKGROUND_COLOR},
+    shared_stats::SharedStats,
+};
+
+/// How often the overlay's numbers change, so that they are slow enough to read
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Statistics shown in the performance overlay, averaged over the last update interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PerfReadout {
+    pub gui_fps: f32,
+    pub emulator_fps: f32,
+    pub frame_budget_used_percent: f32,
+    pub audio_buffer_fill_percent: Option<f32>,
+}
+
+/// Samples the shared stats at a fixed interval and turns them into a readout.
+pub struct PerfOverlay {
+    shared_stats: Arc<SharedStats>,
+
+    /// When the current interval started, or None if sampling has not started
+    interval_start: Option<Instant>,
+
+    /// Emulator frames run when the current interval started
+    interval_start_frames_run: u64,
+
+    /// GUI frames drawn so far in the current interval
+    interval_gui_frames: u32,
+
+    /// Readout from the last completed interval
+    readout: Option<PerfReadout>,
+}
+
+impl PerfOverlay {
+    pub fn new(shared_stats: Arc<SharedStats>) -> Self {
+        Self {
+            shared_stats,
+            interval_start: None,
+            interval_start_frames_run: 0,
+            interval_gui_frames: 0,
+            readout: None,
+        }
+    }
+
+    /// Start sampling over, so that time while the overlay was hidden is not counted.
+    pub fn reset(&mut self) {
+        self.interval_start = None;
+        self.readout = None;
+    }
+
+    /// Count a GUI frame drawn at `now`, updating the readout if the interval is over.
+    pub fn gui_frame(&mut self, now: Instant) {
+        let frames_run = self.shared_stats.frames_run();
+
+        let Some(interval_start) = self.interval_start else {
+            self.start_interval(now, frames_run);
+            return;
+        };
+
+        self.interval_gui_frames += 1;
+
+        let elapsed = now.duration_since(interval_start);
+        if elapsed < UPDATE_INTERVAL {
+            return;
+        }
+
+        let elapsed_secs = elapsed.as_secs_f32();
+        self.readout = Some(PerfReadout {
+            gui_fps: self.interval_gui_frames as f32 / elapsed_secs,
+            emulator_fps: (frames_run - self.interval_start_frames_run) as f32 / elapsed_secs,
+            frame_budget_used_percent: self.shared_stats.frame_budget_used_percent(),
+            audio_buffer_fill_percent: self.shared_stats.audio_buffer_fill_percent(),
+        });
+
+        self.start_interval(now, frames_run);
+    }
+
+    fn start_interval(&mut self, now: Instant, frames_run: u64) {
+        self.interval_start = Some(now);
+        self.interval_start_frames_run = frames_run;
+        self.interval_gui_frames = 0;
+    }
+
+    pub fn readout(&self) -> Option<PerfReadout> {
+        self.readout
+    }
+}
+
+impl EmulatorShellApp {
+    /// Draw the performance overlay in the top left corner, returning where its bottom edge is.
+    pub(super) fn draw_perf_overlay(&self, ui: &mut egui::Ui) -> f32 {
+        let text = match self.perf_overlay().readout() {
+            Some(readout) => {
+                let audio_buffer_fill = match readout.audio_buffer_fill_percent {
+                    Some(fill_percent) => format!("{:.0}%", fill_percent),
+                    None => "-".to_string(),
+                };
+
+                format!(
+                    "GUI FPS    {:.1}\nEmu FPS    {:.1}\nFrame time {:.0}%\nAudio buf  {}",
+                    readout.gui_fps,
+                    readout.emulator_fps,
+                    readout.frame_budget_used_percent,
+                    audio_buffer_fill
+                )
+            }
+            None => "Measuring...".to_string(),
+        };
+
+        let response = egui::Area::new(egui::Id::new("perf_overlay"))
+            .anchor(Align2::LEFT_TOP, Vec2::new(4.0, 4.0))
+            .interactable(false)
+            .show(ui.ctx(), |ui| {
+                egui::Frame::new()
+                    .fill(TOAST_BACKGROUND_COLOR)
+                    .corner_radius(CornerRadius::same(4))
+                    .inner_margin(6.0)
+                    .show(ui, |ui| {
+                        ui.label(egui::RichText::new(text).monospace().color(Color32::WHITE));
+                    });
+            })
+            .response;
+
+        response.rect.bottom()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::shared_stats::SharedStats;
+
+    use super::{PerfOverlay, PerfReadout, UPDATE_INTERVAL};
+
+    #[test]
+    fn readout_updates_once_per_interval() {
+        let shared_stats = Arc::new(SharedStats::new());
+        let mut overlay = PerfOverlay::new(shared_stats.clone());
+        let start = Instant::now();
+        let frame_duration = UPDATE_INTERVAL / 10;
+
+        // The first frame only starts the interval
+        overlay.gui_frame(start);
+        assert_eq!(overlay.readout(), None);
+
+        // 10 GUI frames and 15 emulator frames in one interval
+        for i in 1..=10 {
+            for _ in 0..(1 + i % 2) {
+                shared_stats.frame_complete(50.0);
+            }
+
+            overlay.gui_frame(start + frame_duration * i);
+
+            if i < 10 {
+                assert_eq!(overlay.readout(), None);
+            }
+        }
+
+        let intervals_per_sec = 1.0 / UPDATE_INTERVAL.as_secs_f32();
+        assert_eq!(
+            overlay.readout(),
+            Some(PerfReadout {
+                gui_fps: 10.0 * intervals_per_sec,
+                emulator_fps: 15.0 * intervals_per_sec,
+                frame_budget_used_percent: 50.0,
+                audio_buffer_fill_percent: None,
+            })
+        );
+
+        // The readout is kept until the next interval completes
+        shared_stats.set_audio_buffer_fill_percent(75.0);
+        overlay.gui_frame(start + UPDATE_INTERVAL + Duration::from_millis(1));
+        assert_eq!(overlay.readout().unwrap().audio_buffer_fill_percent, None);
+
+        overlay.reset();
+        assert_eq!(overlay.readout(), None);
+    }
+}
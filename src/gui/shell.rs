@@ -1,49 +1,76 @@
-use std::{sync::mpsc::Sender, time::Duration};
+use std::{
+    collections::HashMap,
+    fs, io, mem,
+    path::PathBuf,
+    process,
+    sync::{
+        Arc,
+        mpsc::{Receiver, SyncSender, TrySendError},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use eframe::{
-    egui::{self, Align2, Color32, FontId, Key, Pos2, Vec2, ViewportCommand, style::ScrollStyle},
+    egui::{
+        self, Align2, Color32, ColorImage, FontId, Pos2, Rect, TextureHandle, TextureOptions, Vec2,
+        ViewportCommand, style::ScrollStyle,
+    },
     epaint::CornerRadius,
 };
 use muda::Menu;
 
 use crate::{
-    emulator::{Button, Command, Emulator, EmulatorRef, SCREEN_HEIGHT, SCREEN_WIDTH},
+    cartridge::Cartridge,
+    emulator::{
+        Command, CommandId, Emulator, EmulatorEvent, EmulatorRef, SCREEN_HEIGHT, SCREEN_WIDTH,
+    },
+    error::Error,
     gui::{
+        cartridge_ram_view::CartridgeRamViewport,
+        color::{PackedColor, blend_linear, pack_color, unpack_color},
+        controls_view::{ControlsViewport, WINDOW_INNER_SIZE as CONTROLS_WINDOW_INNER_SIZE},
         debugger_view::{DebuggerViewport, WINDOW_INNER_SIZE as DEBUGGER_WINDOW_INNER_SIZE},
+        gamepad::Gamepads,
+        io_registers_view::IoRegistersViewport,
+        key_bindings::{Action, KeyBindings},
+        memory_view::MemoryViewport,
         menu::create_app_menu,
-        utils::rect_for_coordinate,
+        oam_view::OamViewport,
+        palette_view::PaletteViewport,
+        perf_overlay::PerfOverlay,
         vram_view::VramViewport,
+        window_layout::WindowLayout,
     },
-    ppu::Color,
+    ppu::{Color, PixelLayer, PixelProvenance},
+    ram_init::RamInit,
+    rom_file::read_rom_file,
+    safe_mode::PendingRestart,
+    save_file::{SAVE_FILE_EXTENSION, STATE_FILE_EXTENSION, SaveFile},
+    save_paths::{has_rom_extension, has_zip_extension},
+    screen_palette::ScreenColorPalette,
+    shared_stats::SharedStats,
+    symbols::SymbolTable,
+    watchdog::{StallWatchdog, stall_diagnostics},
 };
 
-/// The color palettes available for DMG (non-CGB) games.
-#[derive(Clone, Copy, PartialEq)]
-pub enum ScreenColorPalette {
-    Grayscale,
-    Green,
+fn state_file_dialog() -> rfd::FileDialog {
+    let extension = STATE_FILE_EXTENSION.trim_start_matches('.');
+    rfd::FileDialog::new().add_filter("State File", &[extension])
 }
 
-/// The default grayscale color palette.
-pub const SCREEN_COLOR_PALETTE_GRAYSCALE: [Color32; 4] = [
-    Color32::from_rgb(0xFF, 0xFF, 0xFF),
-    Color32::from_rgb(0xAA, 0xAA, 0xAA),
-    Color32::from_rgb(0x55, 0x55, 0x55),
-    Color32::from_rgb(0x00, 0x00, 0x00),
-];
-
-/// A green color palette for the original GameBoy screen.
-pub const SCREEN_COLOR_PALETTE_GREEN: [Color32; 4] = [
-    Color32::from_rgb(0x9B, 0xBC, 0x0F),
-    Color32::from_rgb(0x8B, 0xAC, 0x0F),
-    Color32::from_rgb(0x30, 0x62, 0x30),
-    Color32::from_rgb(0x0F, 0x38, 0x0F),
-];
-
 /// Number of screen pixels per emulated pixel by default
 const DEFAULT_SCALE_FACTOR: f32 = 4.0;
 
-pub fn start_emulator_shell_app(emulator: EmulatorRef, commands_tx: Sender<Command>) {
+/// How long a toast notification stays onscreen
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+
+pub fn start_emulator_shell_app(
+    emulator: EmulatorRef,
+    commands_tx: SyncSender<Command>,
+    events_rx: Receiver<EmulatorEvent>,
+    shared_stats: Arc<SharedStats>,
+    pending_restart: PendingRestart,
+) {
     eframe::run_native(
         "GBC Emulator",
         eframe::NativeOptions {
@@ -58,11 +85,65 @@ pub fn start_emulator_shell_app(emulator: EmulatorRef, commands_tx: Sender<Comma
                 .with_title_shown(true),
             ..Default::default()
         },
-        Box::new(|_| Ok(Box::new(EmulatorShellApp::new(emulator, commands_tx)))),
+        Box::new(|_| {
+            Ok(Box::new(EmulatorShellApp::new(
+                emulator,
+                commands_tx,
+                events_rx,
+                shared_stats,
+                pending_restart,
+            )))
+        }),
     )
     .unwrap()
 }
 
+/// Check that a ROM or save file can be loaded, without loading it into an emulator.
+fn check_rom_or_save_file(rom_or_save_path: &str) -> Result<(), Error> {
+    if rom_or_save_path.ends_with(SAVE_FILE_EXTENSION) {
+        SaveFile::from_bytes(&fs::read(rom_or_save_path)?)?;
+        return Ok(());
+    }
+
+    let rom_bytes = read_rom_file(rom_or_save_path)?;
+    if !has_rom_extension(rom_or_save_path)
+        && !has_zip_extension(rom_or_save_path)
+        && !Cartridge::looks_like_rom(&rom_bytes)
+    {
+        return Err(Error::NotARom);
+    }
+
+    Cartridge::new_from_rom_bytes(rom_bytes)?;
+    Ok(())
+}
+
+/// Show a modal dialog explaining why the ROM or save file could not be loaded, for when the
+/// emulator fails to start before there is a window to show a toast in.
+pub fn show_load_error_dialog(rom_or_save_path: &str, error: &Error) {
+    rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Error)
+        .set_title("Could not load ROM")
+        .set_description(format!("Could not load {}: {}", rom_or_save_path, error))
+        .set_buttons(rfd::MessageButtons::Ok)
+        .show();
+}
+
+/// Ask whether to resume from the state saved when the emulator last exited. Returns whether to
+/// resume.
+pub fn ask_to_resume_dialog(rom_or_save_path: &str) -> bool {
+    let result = rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Info)
+        .set_title("Resume where you left off?")
+        .set_description(format!(
+            "Resume {} from where it was when the emulator last exited?",
+            rom_or_save_path
+        ))
+        .set_buttons(rfd::MessageButtons::YesNo)
+        .show();
+
+    result == rfd::MessageDialogResult::Yes
+}
+
 /// Target frames per second for the GUI to refresh
 const GUI_FPS: f64 = 60.0;
 
@@ -71,7 +152,25 @@ pub struct EmulatorShellApp {
     emulator: EmulatorRef,
 
     /// Channel to send commands to the emulator
-    commands_tx: Sender<Command>,
+    commands_tx: SyncSender<Command>,
+
+    /// Channel to receive events from the emulator
+    events_rx: Receiver<EmulatorEvent>,
+
+    /// ID to use for the next command that is acknowledged by the emulator
+    next_command_id: CommandId,
+
+    /// Descriptions of commands that have been sent but not yet acknowledged, by command ID
+    pending_commands: HashMap<CommandId, &'static str>,
+
+    /// The toast notification currently onscreen, along with when it was first shown
+    toast: Option<(String, Instant)>,
+
+    /// Watches the emulator's heartbeat to detect when the emulator thread stalls
+    watchdog: StallWatchdog,
+
+    /// How long the emulator thread has been stalled, if it currently appears stalled
+    stalled_for: Option<Duration>,
 
     /// Set of buttons that were pressed last frame
     pressed_buttons: u8,
@@ -79,11 +178,47 @@ pub struct EmulatorShellApp {
     /// Whether we are currently in turbo mode, speeding up the emulation
     in_turbo_mode: bool,
 
-    /// Whether the FPS counter should be shown onscreen
+    /// Whether the rewind key is held, stepping the emulation back in time
+    is_rewinding: bool,
+
+    /// The emulator speed last shown in the Speed menu
+    displayed_speed: f64,
+
+    /// Whether the performance overlay should be shown onscreen
     show_fps: bool,
 
-    /// The active color palette for DMG games, translating from 2-bit color indices to RGB values.
-    screen_palette: ScreenColorPalette,
+    perf_overlay: PerfOverlay,
+
+    /// Whether to show where the screen pixel under the cursor came from
+    show_pixel_info: bool,
+
+    /// The color palette that the displayed frame was drawn with. The emulator switches palettes at
+    /// frame boundaries, so this lags behind the palette last chosen in the menu.
+    displayed_screen_palette: ScreenColorPalette,
+
+    /// Whether each displayed frame is blended with the previous one, approximating the slow
+    /// response of the original LCD. Games that flicker sprites on alternate frames rely on this.
+    frame_blending: bool,
+
+    /// The latest frame produced by the emulator, before any blending
+    current_frame: Vec<PackedColor>,
+
+    /// The emulator frame before `current_frame`, before any blending. Blending always combines
+    /// these two frames, so the result does not depend on how often the GUI repaints.
+    previous_frame: Vec<PackedColor>,
+
+    /// The emulator's rendered frame count when `current_frame` was produced
+    current_frame_number: u64,
+
+    /// Whether the previous frame used a different color palette, in which case it must not be
+    /// blended into the current frame.
+    is_displayed_frame_stale: bool,
+
+    /// Texture the screen is uploaded to every frame, created when the screen is first drawn
+    screen_texture: Option<TextureHandle>,
+
+    /// Whether the screen is only scaled by whole multiples, leaving a border around it
+    integer_scaling: bool,
 
     /// The VRAM viewport state
     vram_view: VramViewport,
@@ -91,27 +226,100 @@ pub struct EmulatorShellApp {
     /// The debugger viewport state
     debugger_view: DebuggerViewport,
 
+    /// The OAM viewport state
+    oam_view: OamViewport,
+
+    /// The palette viewport state
+    palette_view: PaletteViewport,
+
+    /// The IO registers viewport state
+    io_registers_view: IoRegistersViewport,
+
+    /// The cartridge RAM viewport state
+    cartridge_ram_view: CartridgeRamViewport,
+
+    /// The memory viewport state
+    memory_view: MemoryViewport,
+
+    /// The controls viewport state
+    controls_view: ControlsViewport,
+
+    /// Keys bound to each button and meta action
+    key_bindings: KeyBindings,
+
+    /// Which debug views are docked in the main window rather than popped out
+    window_layout: WindowLayout,
+
+    /// Labels loaded from the ROM's symbol file, used by the debugger
+    symbols: SymbolTable,
+
+    /// Connected game controllers
+    gamepads: Gamepads,
+
     /// The app menu. Must be kept alive for the menu to function.
     menu: Menu,
 
+    /// New instance to start once this instance has shut down
+    pending_restart: PendingRestart,
+
+    /// Whether the safe mode banner has been dismissed
+    is_safe_mode_banner_dismissed: bool,
+
     /// Whether the app has been initialized
     is_initialized: bool,
 }
 
 impl EmulatorShellApp {
-    fn new(emulator: EmulatorRef, commands_tx: Sender<Command>) -> Self {
-        let menu = create_app_menu();
+    fn new(
+        emulator: EmulatorRef,
+        commands_tx: SyncSender<Command>,
+        events_rx: Receiver<EmulatorEvent>,
+        shared_stats: Arc<SharedStats>,
+        pending_restart: PendingRestart,
+    ) -> Self {
+        let menu = create_app_menu(emulator.ram_init());
+        let watchdog = StallWatchdog::new(emulator.heartbeat(), Instant::now());
+        let gamepads = Gamepads::new(emulator.gamepad_mapping());
 
         Self {
             emulator,
             commands_tx,
+            events_rx,
+            next_command_id: 0,
+            pending_commands: HashMap::new(),
+            toast: None,
+            watchdog,
+            stalled_for: None,
             pressed_buttons: 0,
             in_turbo_mode: false,
+            is_rewinding: false,
             show_fps: false,
-            screen_palette: ScreenColorPalette::Grayscale,
+            perf_overlay: PerfOverlay::new(shared_stats),
+            show_pixel_info: false,
+            displayed_screen_palette: ScreenColorPalette::default(),
+            displayed_speed: 0.0,
+            frame_blending: false,
+            current_frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            previous_frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            current_frame_number: 0,
+            is_displayed_frame_stale: false,
+            screen_texture: None,
+            integer_scaling: false,
             vram_view: VramViewport::new(),
             debugger_view: DebuggerViewport::new(),
+            oam_view: OamViewport::new(),
+            palette_view: PaletteViewport::new(),
+            io_registers_view: IoRegistersViewport::new(),
+            cartridge_ram_view: CartridgeRamViewport::new(),
+            memory_view: MemoryViewport::new(),
+            controls_view: ControlsViewport::new(),
+            key_bindings: KeyBindings::default(),
+            window_layout: WindowLayout::default(),
+            symbols: SymbolTable::new(),
+            gamepads,
             menu,
+            pending_restart,
+            is_safe_mode_banner_dismissed: false,
             is_initialized: false,
         }
     }
@@ -120,23 +328,80 @@ impl EmulatorShellApp {
         &self.emulator
     }
 
+    /// The emulator switches to the new palette at the start of its next frame, or immediately if
+    /// paused.
     pub fn set_color_palette(&mut self, screen_palette: ScreenColorPalette) {
-        self.screen_palette = screen_palette;
-        self.update_color_palette_menu(screen_palette);
+        self.send_command(Command::SetScreenPalette(screen_palette));
+    }
+
+    /// Keep the Speed menu in sync with the emulator, whose speed also changes in turbo mode.
+    fn handle_speed_change(&mut self) {
+        let speed = self.emulator.speed();
+        if speed != self.displayed_speed {
+            self.displayed_speed = speed;
+            self.update_speed_menu(speed);
+        }
+    }
+
+    pub fn toggle_frame_blending(&mut self) {
+        self.frame_blending = !self.frame_blending;
+    }
+
+    pub fn toggle_integer_scaling(&mut self) {
+        self.integer_scaling = !self.integer_scaling;
     }
 
     pub fn menu(&self) -> &Menu {
         &self.menu
     }
 
+    /// Send a command to the emulator. The command is dropped instead of blocking the GUI if the
+    /// commands channel is full, which only happens when the emulator thread has stalled.
     pub fn send_command(&self, command: Command) {
-        self.commands_tx.send(command).unwrap();
+        self.try_send_command(command);
+    }
+
+    /// Send a command to the emulator, returning whether it was sent or dropped because the
+    /// commands channel is full.
+    pub fn try_send_command(&self, command: Command) -> bool {
+        match self.commands_tx.try_send(command) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => false,
+            Err(TrySendError::Disconnected(_)) => panic!("Emulator thread is no longer running"),
+        }
+    }
+
+    /// Send a command that can fail. If the command fails the user is shown a toast containing
+    /// the description and the reason for the failure.
+    pub fn send_fallible_command(
+        &mut self,
+        description: &'static str,
+        command: impl FnOnce(CommandId) -> Command,
+    ) {
+        let command_id = self.next_command_id;
+        self.next_command_id += 1;
+
+        if self.try_send_command(command(command_id)) {
+            self.pending_commands.insert(command_id, description);
+        } else {
+            self.show_toast(format!(
+                "{} failed: the emulator is not responding",
+                description
+            ));
+        }
     }
 
     fn init(&mut self, ctx: &egui::Context) {
         self.is_initialized = true;
 
         self.init_styles(ctx);
+        self.load_symbols(false);
+        self.load_key_bindings();
+        self.load_window_layout();
+
+        if !self.emulator().cartridge().has_valid_logo() {
+            self.show_toast("This ROM would not boot on real hardware, since its header does not contain the Nintendo logo".to_string());
+        }
     }
 
     fn init_styles(&self, ctx: &egui::Context) {
@@ -144,122 +409,583 @@ impl EmulatorShellApp {
         ctx.style_mut(|s| s.spacing.scroll = ScrollStyle::floating());
     }
 
-    fn handle_pressed_buttons(&mut self, ctx: &egui::Context) {
-        let mut buttons = 0;
-        if ctx.input(|i| i.key_down(Key::A)) {
-            buttons |= Button::Select as u8;
-        }
-        if ctx.input(|i| i.key_down(Key::S)) {
-            buttons |= Button::Start as u8;
-        }
-        if ctx.input(|i| i.key_down(Key::Z)) {
-            buttons |= Button::B as u8;
-        }
-        if ctx.input(|i| i.key_down(Key::X)) {
-            buttons |= Button::A as u8;
+    /// Load key bindings from the keymap file, falling back to the defaults if it cannot be read.
+    fn load_key_bindings(&mut self) {
+        let Some(path) = self.emulator.keymap_path() else {
+            return;
+        };
+
+        match KeyBindings::load(path) {
+            Ok(key_bindings) => self.key_bindings = key_bindings,
+            Err(error) => {
+                let message = format!("Unable to load {}: {}", path.display(), error);
+                self.show_toast(message);
+            }
         }
-        if ctx.input(|i| i.key_down(Key::ArrowUp)) {
-            buttons |= Button::Up as u8;
+    }
+
+    /// Trigger the pause, frame advance, and quick save actions whose keys were pressed this frame.
+    fn handle_meta_action_keys(&mut self, ctx: &egui::Context) {
+        let pressed_actions = Action::all()
+            .filter(|action| {
+                action.button().is_none() && !matches!(action, Action::Turbo | Action::Rewind)
+            })
+            .filter(|action| {
+                self.key_bindings
+                    .key(*action)
+                    .is_some_and(|key| ctx.input(|i| i.key_pressed(key)))
+            })
+            .collect::<Vec<_>>();
+
+        for action in pressed_actions {
+            match action {
+                Action::Pause => self.send_command(Command::TogglePause),
+                Action::FrameAdvance => self.send_command(Command::FrameAdvance),
+                Action::QuickSave(slot) => {
+                    self.send_fallible_command("Quick save", |id| Command::QuickSave(slot, id));
+                }
+                _ => {}
+            }
         }
-        if ctx.input(|i| i.key_down(Key::ArrowDown)) {
-            buttons |= Button::Down as u8;
+    }
+
+    /// Send the buttons and turbo mode held on either the keyboard or any gamepad to the emulator,
+    /// along with whether the rewind key is held, if they have changed. Updates dropped because the
+    /// commands channel is full are sent again on the next frame.
+    fn handle_input(&mut self, ctx: &egui::Context) {
+        self.handle_meta_action_keys(ctx);
+
+        let keyboard_input_state = self
+            .key_bindings
+            .read_state(|key| ctx.input(|i| i.key_down(key)));
+        let input_state = keyboard_input_state.merge(self.gamepads.poll());
+
+        if input_state.buttons != self.pressed_buttons
+            && self.try_send_command(Command::UpdatePressedButtons(input_state.buttons))
+        {
+            self.pressed_buttons = input_state.buttons;
         }
-        if ctx.input(|i| i.key_down(Key::ArrowLeft)) {
-            buttons |= Button::Left as u8;
+
+        if input_state.is_turbo_pressed != self.in_turbo_mode
+            && self.try_send_command(Command::SetTurboMode(input_state.is_turbo_pressed))
+        {
+            self.in_turbo_mode = input_state.is_turbo_pressed;
         }
-        if ctx.input(|i| i.key_down(Key::ArrowRight)) {
-            buttons |= Button::Right as u8;
+
+        let is_rewind_pressed = self
+            .key_bindings
+            .is_action_down(Action::Rewind, |key| ctx.input(|i| i.key_down(key)));
+        if is_rewind_pressed != self.is_rewinding
+            && self.try_send_command(Command::SetRewinding(is_rewind_pressed))
+        {
+            self.is_rewinding = is_rewind_pressed;
         }
+    }
 
-        if buttons != self.pressed_buttons {
-            self.pressed_buttons = buttons;
-            self.send_command(Command::UpdatePressedButtons(buttons));
+    fn handle_emulator_events(&mut self) {
+        while let Ok(event) = self.events_rx.try_recv() {
+            match event {
+                EmulatorEvent::SaveFileWriteFailed {
+                    reason,
+                    fallback_path,
+                } => {
+                    let message = match fallback_path {
+                        Some(fallback_path) => format!(
+                            "Unable to write save file: {} \u{2014} saving to {} instead",
+                            reason, fallback_path
+                        ),
+                        None => format!(
+                            "Unable to write save file: {} \u{2014} saves are disabled",
+                            reason
+                        ),
+                    };
+                    self.show_toast(message);
+                }
+                EmulatorEvent::BreakpointHit(breakpoint) => {
+                    self.handle_breakpoint_hit(breakpoint);
+                }
+                EmulatorEvent::WatchpointHit(watchpoint_hit) => {
+                    self.handle_watchpoint_hit(watchpoint_hit);
+                }
+                EmulatorEvent::Stepped(pc) => self.handle_stepped(pc),
+                EmulatorEvent::CgbRequired => {
+                    self.enable_restart_as_cgb_menu_item();
+                    self.show_toast(
+                        "This game appears to require Game Boy Color \u{2014} rendering will be \
+                         incorrect. Choose Emulator > Restart as Game Boy Color to fix it."
+                            .to_string(),
+                    );
+                }
+                EmulatorEvent::CommandResult { command_id, result } => {
+                    let description = self.pending_commands.remove(&command_id);
+                    if let (Some(description), Err(error)) = (description, result) {
+                        self.show_toast(format!("{} failed: {}", description, error));
+                    }
+                }
+            }
         }
     }
 
-    fn handle_turbo_mode(&mut self, ctx: &egui::Context) {
-        let in_turbo_mode = ctx.input(|i| i.key_down(Key::Space));
-        if in_turbo_mode != self.in_turbo_mode {
-            self.in_turbo_mode = in_turbo_mode;
-            self.send_command(Command::SetTurboMode(in_turbo_mode));
+    pub(super) fn show_toast(&mut self, message: String) {
+        self.toast = Some((message, Instant::now()));
+    }
+
+    fn check_for_stall(&mut self) {
+        let heartbeat = self.emulator.heartbeat();
+        let is_paused = self.emulator.is_paused();
+        self.stalled_for = self.watchdog.update(heartbeat, is_paused, Instant::now());
+    }
+
+    /// Write a report of the emulator's state to a new file in the working directory.
+    fn dump_stall_diagnostics(&mut self, stalled_for: Duration) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = PathBuf::from(format!("stall_diagnostics_{}.txt", timestamp));
+
+        let report = stall_diagnostics(&self.emulator, stalled_for);
+        match fs::write(&path, report) {
+            Ok(()) => self.show_toast(format!("Wrote diagnostics to {}", path.display())),
+            Err(error) => self.show_toast(format!("Unable to write diagnostics: {}", error)),
         }
     }
 
     fn draw(&mut self, ctx: &egui::Context) {
+        self.draw_dock(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
             self.draw_emulator_viewport(ui);
-
-            if self.vram_view().is_shown() {
-                self.draw_vram_viewport(ui);
-            }
+            self.draw_popped_out_debug_views(ui);
 
             if self.debugger_view().is_shown() {
                 self.draw_debugger_viewport(ui);
             }
+
+            if self.controls_view().is_shown() {
+                self.draw_controls_viewport(ui);
+            }
         });
     }
 
-    fn draw_emulator_viewport(&self, ui: &mut egui::Ui) {
+    fn draw_emulator_viewport(&mut self, ui: &mut egui::Ui) {
         self.draw_screen(ui);
+        self.draw_selected_object_highlight(ui);
 
         if self.show_fps {
             self.draw_frame_rate_counter(ui);
         }
+
+        if self.show_pixel_info {
+            self.draw_pixel_info(ui);
+        }
+
+        if self.emulator.is_paused() {
+            self.draw_paused_overlay(ui);
+        }
+
+        self.draw_stall_banner(ui);
+        self.draw_cpu_lock_banner(ui);
+        self.draw_safe_mode_banner(ui);
+        self.draw_toast(ui);
     }
 
-    pub fn color_to_color32(&self, color: Color) -> Color32 {
-        let palette = match self.screen_palette {
-            ScreenColorPalette::Grayscale => SCREEN_COLOR_PALETTE_GRAYSCALE,
-            ScreenColorPalette::Green => SCREEN_COLOR_PALETTE_GREEN,
+    /// Non-blocking banner shown while the emulator thread appears stalled.
+    fn draw_stall_banner(&mut self, ui: &mut egui::Ui) {
+        let Some(stalled_for) = self.stalled_for else {
+            return;
+        };
+
+        let mut should_dump_diagnostics = false;
+
+        egui::Area::new(egui::Id::new("stall_banner"))
+            .anchor(Align2::CENTER_TOP, Vec2::new(0.0, 8.0))
+            .show(ui.ctx(), |ui| {
+                egui::Frame::new()
+                    .fill(TOAST_BACKGROUND_COLOR)
+                    .corner_radius(CornerRadius::same(4))
+                    .inner_margin(8.0)
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.colored_label(
+                                Color32::WHITE,
+                                format!("Emulator appears stalled ({}s)", stalled_for.as_secs()),
+                            );
+
+                            if ui.button("Dump diagnostics").clicked() {
+                                should_dump_diagnostics = true;
+                            }
+
+                            if ui.button("Force quit").clicked() {
+                                process::exit(1);
+                            }
+                        });
+                    });
+            });
+
+        if should_dump_diagnostics {
+            self.dump_stall_diagnostics(stalled_for);
+        }
+    }
+
+    /// Banner explaining that the game executed an invalid opcode, which locks up the CPU. The screen
+    /// keeps showing whatever the PPU draws, so without it the game would appear frozen.
+    fn draw_cpu_lock_banner(&self, ui: &mut egui::Ui) {
+        let Some(cpu_lock) = self.emulator().cpu_lock() else {
+            return;
+        };
+
+        egui::Area::new(egui::Id::new("cpu_lock_banner"))
+            .anchor(Align2::CENTER_TOP, Vec2::new(0.0, 8.0))
+            .interactable(false)
+            .show(ui.ctx(), |ui| {
+                egui::Frame::new()
+                    .fill(TOAST_BACKGROUND_COLOR)
+                    .corner_radius(CornerRadius::same(4))
+                    .inner_margin(8.0)
+                    .show(ui, |ui| {
+                        ui.colored_label(
+                            Color32::WHITE,
+                            format!(
+                                "CPU locked at ${:04X} (opcode ${:02X})",
+                                cpu_lock.address, cpu_lock.opcode
+                            ),
+                        );
+                    });
+            });
+    }
+
+    /// Banner explaining which options were ignored because the emulator started in safe mode.
+    fn draw_safe_mode_banner(&mut self, ui: &mut egui::Ui) {
+        if self.is_safe_mode_banner_dismissed || self.emulator().suppressed_options().is_empty() {
+            return;
+        }
+
+        let mut should_restart = false;
+
+        egui::Area::new(egui::Id::new("safe_mode_banner"))
+            .anchor(Align2::CENTER_BOTTOM, Vec2::new(0.0, -8.0))
+            .show(ui.ctx(), |ui| {
+                egui::Frame::new()
+                    .fill(TOAST_BACKGROUND_COLOR)
+                    .corner_radius(CornerRadius::same(4))
+                    .inner_margin(8.0)
+                    .show(ui, |ui| {
+                        ui.colored_label(
+                            Color32::WHITE,
+                            format!(
+                                "Safe mode: the emulator did not shut down cleanly last time, so {} {} ignored",
+                                self.emulator().suppressed_options().join(", "),
+                                if self.emulator().suppressed_options().len() == 1 {
+                                    "was"
+                                } else {
+                                    "were"
+                                }
+                            ),
+                        );
+
+                        ui.horizontal(|ui| {
+                            if ui.button("Restart with all options").clicked() {
+                                should_restart = true;
+                            }
+
+                            if ui.button("Dismiss").clicked() {
+                                self.is_safe_mode_banner_dismissed = true;
+                            }
+                        });
+                    });
+            });
+
+        if should_restart {
+            self.pending_restart.request_with_all_options();
+            ui.ctx().send_viewport_cmd(ViewportCommand::Close);
+        }
+    }
+
+    /// Restart the emulator running a ROM, zipped ROM, or save file dropped onto the window. The file
+    /// is checked first so that a bad drop leaves the current game running.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let Some(path) = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .first()
+                .and_then(|file| file.path.clone())
+        }) else {
+            return;
         };
-        match color {
-            Color::Dmg(idx) => palette[idx as usize],
-            Color::Cgb(cgb) => cgb.to_color32(),
+
+        let Some(path_str) = path.to_str() else {
+            self.show_toast(format!(
+                "Unable to load {}: path is not valid UTF-8",
+                path.display()
+            ));
+            return;
+        };
+
+        if let Err(error) = check_rom_or_save_file(path_str) {
+            self.show_toast(format!("Unable to load {}: {}", path.display(), error));
+            return;
         }
+
+        self.pending_restart
+            .request_with_rom(self.emulator().rom_or_save_path(), &path);
+        ctx.send_viewport_cmd(ViewportCommand::Close);
     }
 
-    fn draw_screen(&self, ui: &mut egui::Ui) {
-        let scale_factor = self.calculate_scale_factor(ui.ctx());
+    /// Restart the emulator with the same game, emulating a GameBoy Color.
+    pub fn restart_emulating_cgb(&mut self, ctx: &egui::Context) {
+        self.pending_restart.request_as_cgb();
+        ctx.send_viewport_cmd(ViewportCommand::Close);
+    }
+
+    /// Restart the emulator with the same game, with different RAM contents at power-on.
+    pub fn restart_with_ram_init(&mut self, ram_init: RamInit, ctx: &egui::Context) {
+        self.pending_restart.request_with_ram_init(ram_init);
+        ctx.send_viewport_cmd(ViewportCommand::Close);
+    }
+
+    fn draw_toast(&mut self, ui: &mut egui::Ui) {
+        const MARGIN: f32 = 8.0;
+
+        let Some((message, shown_at)) = &self.toast else {
+            return;
+        };
+
+        if shown_at.elapsed() > TOAST_DURATION {
+            self.toast = None;
+            return;
+        }
+
         let painter = ui.painter();
+        let screen_rect = ui.ctx().viewport_rect();
+        let galley = painter.layout(
+            message.clone(),
+            FontId::proportional(16.0),
+            Color32::WHITE,
+            screen_rect.width() - 4.0 * MARGIN,
+        );
+
+        let text_pos = Pos2::new(
+            screen_rect.center().x - galley.size().x / 2.0,
+            screen_rect.bottom() - galley.size().y - 2.0 * MARGIN,
+        );
+        let background_rect = egui::Rect::from_min_size(text_pos, galley.size()).expand(MARGIN);
+
+        painter.rect_filled(
+            background_rect,
+            CornerRadius::same(4),
+            TOAST_BACKGROUND_COLOR,
+        );
+        painter.galley(text_pos, galley, Color32::WHITE);
+    }
+
+    pub fn color_to_color32(&self, color: Color) -> Color32 {
+        self.emulator.screen_palette().color_to_color32(color)
+    }
+
+    fn draw_screen(&mut self, ui: &mut egui::Ui) {
+        // A frame drawn in a different palette must not be blended into the next frame
+        let screen_palette = self.emulator.screen_palette();
+        if screen_palette != self.displayed_screen_palette {
+            self.displayed_screen_palette = screen_palette;
+            self.is_displayed_frame_stale = true;
+            self.update_color_palette_menu(screen_palette);
+        }
+
+        let mut pixels = vec![Color32::BLACK; SCREEN_WIDTH * SCREEN_HEIGHT];
+        self.emulator.copy_screen_colors(&mut pixels);
+
+        // Only a new emulator frame replaces the previous frame. Repaints of the same frame refresh
+        // it in place, e.g. when the palette is switched while paused.
+        let frame_number = self.emulator.num_rendered_frames();
+        if frame_number != self.current_frame_number {
+            self.current_frame_number = frame_number;
+            mem::swap(&mut self.previous_frame, &mut self.current_frame);
+        }
+
+        for (packed_pixel, color32) in self.current_frame.iter_mut().zip(&pixels) {
+            *packed_pixel = pack_color(*color32);
+        }
+
+        if self.is_displayed_frame_stale {
+            self.previous_frame.copy_from_slice(&self.current_frame);
+            self.is_displayed_frame_stale = false;
+        }
 
-        for y in 0..SCREEN_HEIGHT {
-            for x in 0..SCREEN_WIDTH {
-                let color32 = self.color_to_color32(self.emulator.read_pixel(x, y));
-                painter.rect_filled(
-                    rect_for_coordinate(x, y, scale_factor),
-                    CornerRadius::ZERO,
-                    color32,
-                );
+        if self.frame_blending {
+            for (color32, previous_pixel) in pixels.iter_mut().zip(&self.previous_frame) {
+                *color32 = blend_linear(unpack_color(*previous_pixel), *color32, 0.5);
             }
         }
+
+        // Upload the whole screen as a single texture, scaled up without smoothing
+        let image = ColorImage::new([SCREEN_WIDTH, SCREEN_HEIGHT], pixels);
+        match &mut self.screen_texture {
+            Some(texture) => texture.set(image, TextureOptions::NEAREST),
+            None => {
+                let texture = ui
+                    .ctx()
+                    .load_texture("screen", image, TextureOptions::NEAREST);
+                self.screen_texture = Some(texture);
+            }
+        }
+
+        let texture_id = self.screen_texture.as_ref().unwrap().id();
+        let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+        ui.painter()
+            .image(texture_id, self.screen_rect(ui.ctx()), uv, Color32::WHITE);
     }
 
     fn draw_frame_rate_counter(&self, ui: &mut egui::Ui) {
-        let fps = self.emulator.current_frame_rate();
+        let overlay_bottom = self.draw_perf_overlay(ui);
+        self.draw_draw_timing_graph(ui, overlay_bottom + 4.0);
+    }
 
-        ui.painter().text(
-            Pos2::new(4.0, 4.0),
-            Align2::LEFT_TOP,
-            fps.to_string(),
+    /// Readout next to the cursor of the screen pixel under it, and which layer, palette, and color
+    /// index it was drawn from.
+    fn draw_pixel_info(&self, ui: &mut egui::Ui) {
+        let Some(pointer_pos) = ui.ctx().pointer_hover_pos() else {
+            return;
+        };
+
+        let screen_rect = self.screen_rect(ui.ctx());
+        let Some((x, y)) = screen_pixel_at(screen_rect, pointer_pos) else {
+            return;
+        };
+
+        // Recording may not have started yet
+        let Some(provenance) = self.emulator.pixel_provenance(x, y) else {
+            return;
+        };
+
+        let in_cgb_mode = self.emulator.compat_mode().has_cgb_rendering();
+        let text = format!(
+            "({}, {})\n{}",
+            x,
+            y,
+            describe_pixel_provenance(provenance, in_cgb_mode)
+        );
+
+        egui::Area::new(egui::Id::new("pixel_info"))
+            .order(egui::Order::Tooltip)
+            .interactable(false)
+            .fixed_pos(pointer_pos + Vec2::new(16.0, 16.0))
+            .show(ui.ctx(), |ui| {
+                egui::Frame::new()
+                    .fill(TOAST_BACKGROUND_COLOR)
+                    .corner_radius(CornerRadius::same(4))
+                    .inner_margin(6.0)
+                    .show(ui, |ui| {
+                        ui.label(egui::RichText::new(text).monospace().color(Color32::WHITE));
+                    });
+            });
+    }
+
+    fn draw_paused_overlay(&self, ui: &mut egui::Ui) {
+        const MARGIN: f32 = 8.0;
+
+        let painter = ui.painter();
+        // Kept clear of the dock
+        let screen_rect = ui.ctx().available_rect();
+        let galley = painter.layout_no_wrap(
+            "PAUSED".to_string(),
             FontId::monospace(24.0),
-            FPS_COUNTER_COLOR,
+            Color32::WHITE,
+        );
+
+        let text_pos = Pos2::new(
+            screen_rect.right() - galley.size().x - 2.0 * MARGIN,
+            screen_rect.top() + 2.0 * MARGIN,
         );
+        let background_rect = egui::Rect::from_min_size(text_pos, galley.size()).expand(MARGIN);
+
+        painter.rect_filled(
+            background_rect,
+            CornerRadius::same(4),
+            TOAST_BACKGROUND_COLOR,
+        );
+        painter.galley(text_pos, galley, Color32::WHITE);
+    }
+
+    /// Draw a small bar graph of the distribution of Draw mode lengths in the last frame, from
+    /// shortest on the left to longest on the right. Bars for the longest Draw modes are highlighted
+    /// when any scanline hits the maximum length.
+    fn draw_draw_timing_graph(&self, ui: &mut egui::Ui, graph_top: f32) {
+        const BAR_WIDTH: f32 = 4.0;
+        const MAX_BAR_HEIGHT: f32 = 24.0;
+
+        let metrics = self.emulator.draw_timing_metrics();
+        let painter = ui.painter();
+
+        for (i, num_scanlines) in metrics.histogram.iter().enumerate() {
+            let height = (*num_scanlines as f32 / SCREEN_HEIGHT as f32) * MAX_BAR_HEIGHT;
+            let left = 4.0 + (i as f32) * (BAR_WIDTH + 1.0);
+            let bottom = graph_top + MAX_BAR_HEIGHT;
+
+            let is_last_bucket = i == metrics.histogram.len() - 1;
+            let color = if is_last_bucket && metrics.num_scanlines_at_max > 0 {
+                DRAW_TIMING_MAX_COLOR
+            } else {
+                FPS_COUNTER_COLOR
+            };
+
+            painter.rect_filled(
+                egui::Rect::from_min_max(
+                    Pos2::new(left, bottom - height),
+                    Pos2::new(left + BAR_WIDTH, bottom),
+                ),
+                CornerRadius::ZERO,
+                color,
+            );
+        }
     }
 
+    /// The screen fills the space not taken up by the dock. With integer scaling each screen pixel
+    /// covers a whole number of physical pixels, unless the window is too small for even one.
     fn calculate_scale_factor(&self, ctx: &egui::Context) -> f32 {
-        let viewport_rect = ctx.viewport_rect();
+        let screen_rect = ctx.available_rect();
+
+        let width_scale = screen_rect.width() / (SCREEN_WIDTH as f32);
+        let height_scale = screen_rect.height() / (SCREEN_HEIGHT as f32);
+        let scale_factor = width_scale.min(height_scale);
+
+        let pixels_per_point = ctx.pixels_per_point();
+        let physical_scale_factor = scale_factor * pixels_per_point;
+        if self.integer_scaling && physical_scale_factor >= 1.0 {
+            physical_scale_factor.floor() / pixels_per_point
+        } else {
+            scale_factor
+        }
+    }
 
-        let width_scale = viewport_rect.width() / (SCREEN_WIDTH as f32);
-        let height_scale = viewport_rect.height() / (SCREEN_HEIGHT as f32);
+    /// Area the screen is drawn in, centered in the space not taken up by the dock so that any
+    /// leftover space forms a border around it.
+    pub(super) fn screen_rect(&self, ctx: &egui::Context) -> Rect {
+        let scale_factor = self.calculate_scale_factor(ctx);
+        let size = Vec2::new(
+            scale_factor * (SCREEN_WIDTH as f32),
+            scale_factor * (SCREEN_HEIGHT as f32),
+        );
+
+        let available_rect = ctx.available_rect();
+        let min = available_rect.center() - size / 2.0;
+        let pixels_per_point = ctx.pixels_per_point();
+
+        // Start on a physical pixel so that screen pixels are not split unevenly
+        let min = Pos2::new(
+            (min.x * pixels_per_point).round() / pixels_per_point,
+            (min.y * pixels_per_point).round() / pixels_per_point,
+        );
 
-        width_scale.min(height_scale)
+        Rect::from_min_size(min, size)
     }
 
     pub fn resize_to_fit(&self, ctx: &egui::Context) {
         let scale_factor = self.calculate_scale_factor(ctx);
+        let dock_width = ctx.viewport_rect().width() - ctx.available_rect().width();
 
         let new_size = Vec2::new(
-            scale_factor * (SCREEN_WIDTH as f32),
+            scale_factor * (SCREEN_WIDTH as f32) + dock_width,
             scale_factor * (SCREEN_HEIGHT as f32),
         );
 
@@ -268,6 +994,77 @@ impl EmulatorShellApp {
 
     pub fn toggle_show_fps(&mut self) {
         self.show_fps = !self.show_fps;
+        self.perf_overlay.reset();
+    }
+
+    /// Pixel provenance is only recorded while the readout is shown, since it slows down drawing.
+    pub fn toggle_show_pixel_info(&mut self) {
+        self.show_pixel_info = !self.show_pixel_info;
+        self.send_command(Command::SetRecordPixelProvenance(self.show_pixel_info));
+    }
+
+    /// Dump the PPU state to a new timestamped directory in the current directory.
+    pub fn dump_ppu_state(&mut self) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = PathBuf::from(format!("ppu_dump_{}", timestamp));
+
+        self.send_fallible_command("Dump PPU state", |id| Command::DumpPpuState(path, id));
+    }
+
+    /// Ask where to write a state file, then export the emulator state to it.
+    pub fn export_state(&mut self) {
+        let Some(path) = state_file_dialog().save_file() else {
+            return;
+        };
+
+        self.send_fallible_command("Export state", |id| Command::ExportState(path, id));
+    }
+
+    /// Ask for a state file, then import it. The emulator keeps running with its current state if
+    /// the state file cannot be imported.
+    pub fn import_state(&mut self) {
+        let Some(path) = state_file_dialog().pick_file() else {
+            return;
+        };
+
+        self.send_fallible_command("Import state", |id| Command::ImportState(path, id));
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Load the symbol file for the ROM, replacing any symbols that were already loaded. A missing
+    /// symbol file is only reported if `report_missing` is set, since most ROMs do not have one.
+    pub fn load_symbols(&mut self, report_missing: bool) {
+        let Some(path) = self.emulator().symbols_path().map(|path| path.to_owned()) else {
+            return;
+        };
+
+        match SymbolTable::load(&path) {
+            Ok((symbols, errors)) => {
+                let mut message =
+                    format!("Loaded {} symbols from {}", symbols.len(), path.display());
+                if let Some(first_error) = errors.first() {
+                    message.push_str(&format!(
+                        " \u{2014} skipped {} malformed lines ({})",
+                        errors.len(),
+                        first_error
+                    ));
+                }
+
+                self.symbols = symbols;
+                self.show_toast(message);
+            }
+            Err(error) => {
+                if report_missing || error.kind() != io::ErrorKind::NotFound {
+                    self.show_toast(format!("Unable to load {}: {}", path.display(), error));
+                }
+            }
+        }
     }
 
     pub fn show_debugger_view(&mut self, ctx: &egui::Context) {
@@ -280,14 +1077,14 @@ impl EmulatorShellApp {
         self.debugger_view_mut().open(initial_position);
     }
 
-    pub fn show_vram_view(&mut self, ctx: &egui::Context) {
-        if self.vram_view().is_shown() {
+    pub fn show_controls_view(&mut self, ctx: &egui::Context) {
+        if self.controls_view().is_shown() {
             return;
         }
 
         let initial_position =
-            self.additional_viewport_initial_position(ctx, self.vram_viewport_size());
-        self.vram_view_mut().open(initial_position);
+            self.additional_viewport_initial_position(ctx, CONTROLS_WINDOW_INNER_SIZE);
+        self.controls_view_mut().open(initial_position);
     }
 
     pub fn vram_view(&self) -> &VramViewport {
@@ -306,6 +1103,74 @@ impl EmulatorShellApp {
         &mut self.debugger_view
     }
 
+    pub fn palette_view(&self) -> &PaletteViewport {
+        &self.palette_view
+    }
+
+    pub fn palette_view_mut(&mut self) -> &mut PaletteViewport {
+        &mut self.palette_view
+    }
+
+    pub fn perf_overlay(&self) -> &PerfOverlay {
+        &self.perf_overlay
+    }
+
+    pub fn oam_view(&self) -> &OamViewport {
+        &self.oam_view
+    }
+
+    pub fn oam_view_mut(&mut self) -> &mut OamViewport {
+        &mut self.oam_view
+    }
+
+    pub fn io_registers_view(&self) -> &IoRegistersViewport {
+        &self.io_registers_view
+    }
+
+    pub fn io_registers_view_mut(&mut self) -> &mut IoRegistersViewport {
+        &mut self.io_registers_view
+    }
+
+    pub fn cartridge_ram_view(&self) -> &CartridgeRamViewport {
+        &self.cartridge_ram_view
+    }
+
+    pub fn cartridge_ram_view_mut(&mut self) -> &mut CartridgeRamViewport {
+        &mut self.cartridge_ram_view
+    }
+
+    pub fn memory_view(&self) -> &MemoryViewport {
+        &self.memory_view
+    }
+
+    pub fn memory_view_mut(&mut self) -> &mut MemoryViewport {
+        &mut self.memory_view
+    }
+
+    pub fn controls_view(&self) -> &ControlsViewport {
+        &self.controls_view
+    }
+
+    pub fn controls_view_mut(&mut self) -> &mut ControlsViewport {
+        &mut self.controls_view
+    }
+
+    pub fn key_bindings(&self) -> &KeyBindings {
+        &self.key_bindings
+    }
+
+    pub fn key_bindings_mut(&mut self) -> &mut KeyBindings {
+        &mut self.key_bindings
+    }
+
+    pub fn window_layout(&self) -> &WindowLayout {
+        &self.window_layout
+    }
+
+    pub fn window_layout_mut(&mut self) -> &mut WindowLayout {
+        &mut self.window_layout
+    }
+
     /// Outer bounds of the root emulator viewport
     fn emulator_viewport_outer_rect(&self, ctx: &egui::Context) -> egui::Rect {
         ctx.viewport_for(egui::ViewportId::ROOT, |viewport| {
@@ -343,6 +1208,42 @@ impl EmulatorShellApp {
                 self.debugger_view.close();
             }
         });
+
+        ctx.viewport_for(self.oam_viewport_id(), |viewport| {
+            if viewport.input.viewport().close_requested() {
+                self.oam_view.close();
+            }
+        });
+
+        ctx.viewport_for(self.palette_viewport_id(), |viewport| {
+            if viewport.input.viewport().close_requested() {
+                self.palette_view.close();
+            }
+        });
+
+        ctx.viewport_for(self.io_registers_viewport_id(), |viewport| {
+            if viewport.input.viewport().close_requested() {
+                self.io_registers_view.close();
+            }
+        });
+
+        ctx.viewport_for(self.cartridge_ram_viewport_id(), |viewport| {
+            if viewport.input.viewport().close_requested() {
+                self.cartridge_ram_view.close();
+            }
+        });
+
+        ctx.viewport_for(self.memory_viewport_id(), |viewport| {
+            if viewport.input.viewport().close_requested() {
+                self.memory_view.close();
+            }
+        });
+
+        ctx.viewport_for(self.controls_viewport_id(), |viewport| {
+            if viewport.input.viewport().close_requested() {
+                self.controls_view.close();
+            }
+        });
     }
 }
 
@@ -359,12 +1260,63 @@ impl eframe::App for EmulatorShellApp {
         ctx.request_repaint_after(Duration::from_secs_f64(1.0 / GUI_FPS));
 
         self.handle_menu_events(ctx);
-        self.handle_pressed_buttons(ctx);
-        self.handle_turbo_mode(ctx);
+        self.handle_input(ctx);
+        self.handle_dropped_files(ctx);
+        self.handle_emulator_events();
+        self.handle_speed_change();
         self.handle_window_close_events(ctx);
+        self.check_for_stall();
+
+        if self.show_fps {
+            self.perf_overlay.gui_frame(Instant::now());
+        }
 
         self.draw(ctx);
     }
 }
 
 const FPS_COUNTER_COLOR: Color32 = Color32::from_rgba_unmultiplied_const(0, 0, 255, 128);
+
+const DRAW_TIMING_MAX_COLOR: Color32 = Color32::from_rgba_unmultiplied_const(255, 0, 0, 128);
+
+pub(super) const TOAST_BACKGROUND_COLOR: Color32 =
+    Color32::from_rgba_unmultiplied_const(0, 0, 0, 192);
+
+/// The screen pixel at a position in the window, given the rect the screen is drawn in. Returns
+/// None for positions in the border around the screen.
+fn screen_pixel_at(screen_rect: Rect, pos: Pos2) -> Option<(u8, u8)> {
+    if !screen_rect.contains(pos) {
+        return None;
+    }
+
+    let scale_factor = screen_rect.width() / SCREEN_WIDTH as f32;
+    let x = ((pos.x - screen_rect.left()) / scale_factor) as usize;
+    let y = ((pos.y - screen_rect.top()) / scale_factor) as usize;
+
+    // The right and bottom edges are inside the rect but past the last pixel
+    (x < SCREEN_WIDTH && y < SCREEN_HEIGHT).then_some((x as u8, y as u8))
+}
+
+fn describe_pixel_provenance(provenance: PixelProvenance, in_cgb_mode: bool) -> String {
+    let (layer, palette) = match provenance.layer {
+        PixelLayer::None => return "Background and window disabled".to_string(),
+        PixelLayer::Background => ("Background".to_string(), "BGP"),
+        PixelLayer::Window => ("Window".to_string(), "BGP"),
+        PixelLayer::Object(index) => (
+            format!("Object {}", index),
+            if provenance.palette == 0 {
+                "OBP0"
+            } else {
+                "OBP1"
+            },
+        ),
+    };
+
+    let palette = if in_cgb_mode {
+        format!("Palette {}", provenance.palette)
+    } else {
+        palette.to_string()
+    };
+
+    format!("{}\n{} color {}", layer, palette, provenance.color_index)
+}
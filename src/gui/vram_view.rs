@@ -1,11 +1,16 @@
-use eframe::egui::{self, CornerRadius, Pos2, Rect, Vec2, ViewportId};
+use eframe::egui::{self, CornerRadius, Pos2, Rect, Sense, Vec2, ViewportId};
 
 use crate::{
-    emulator::{SCREEN_HEIGHT, SCREEN_WIDTH},
-    gui::shell::EmulatorShellApp,
+    emulator::{Command, SCREEN_HEIGHT, SCREEN_WIDTH},
+    gui::{
+        shell::EmulatorShellApp,
+        window_layout::{DOCK_BUTTON_BAR_HEIGHT, DebugView},
+    },
     ppu::{
-        TILE_MAP_SIZE, TILE_MAP_TOTAL_TILES, TILE_SIZE, background_color_palette,
-        lookup_all_pixels_in_tile, lookup_byte_in_tile_map, lookup_color_in_palette,
+        BackgroundTileAttributes, ColorPalette, TILE_DATA_SIZE, TILE_MAP_SIZE,
+        TILE_MAP_TOTAL_TILES, TILE_SIZE, background_color_palette, decode_tile_pixels,
+        lookup_byte_in_tile_map, lookup_color_in_palette, lookup_tile_attributes_in_tile_map,
+        lookup_tile_data, tile_data_address,
     },
 };
 
@@ -14,16 +19,74 @@ const SCALE_FACTOR: f32 = 2.0;
 const WINDOW_PADDING: f32 = 10.0;
 const OPTIONS_WIDTH: f32 = 300.0;
 
-/// Position of the top-left corner of the VRAM view in the window
-const PIXELS_AREA_TOP_LEFT: Vec2 = Vec2::new(OPTIONS_WIDTH + WINDOW_PADDING, WINDOW_PADDING);
+/// Number of screen pixels per emulated pixel in the pinned tile's detail pane
+const TILE_DETAIL_SCALE_FACTOR: f32 = 8.0;
 
-const TOP_LEFT_PIXEL_PAINTER_COORDS: Pos2 = EmulatorShellApp::pixel_to_painter_coords(0, 0);
-const BOTTOM_RIGHT_PIXEL_PAINTER_COORDS: Pos2 = EmulatorShellApp::pixel_to_painter_coords(256, 256);
+/// Size of the area that the tile map is drawn in
+const PIXELS_AREA_SIZE: f32 = 256.0 * SCALE_FACTOR;
 
-const WINDOW_HEIGHT: f32 = (256.0 * SCALE_FACTOR) + (2.0 * WINDOW_PADDING);
+const WINDOW_HEIGHT: f32 = PIXELS_AREA_SIZE + (2.0 * WINDOW_PADDING);
 const WINDOW_WIDTH: f32 = OPTIONS_WIDTH + WINDOW_HEIGHT;
 const WINDOW_SIZE: Vec2 = Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT);
 
+/// Converts between pixels in the tile map and painter coordinates, relative to the top-left corner
+/// of the area the tile map is drawn in. The view can be drawn anywhere, such as in its own window
+/// or docked in the main window.
+#[derive(Clone, Copy)]
+struct PixelsArea {
+    top_left: Pos2,
+}
+
+impl PixelsArea {
+    fn pixel(self, x: usize, y: usize) -> Pos2 {
+        Pos2::new(self.x(x), self.y(y))
+    }
+
+    fn x(self, x: usize) -> f32 {
+        (x as f32) * SCALE_FACTOR + self.top_left.x
+    }
+
+    fn y(self, y: usize) -> f32 {
+        (y as f32) * SCALE_FACTOR + self.top_left.y
+    }
+
+    fn bottom_right(self) -> Pos2 {
+        self.pixel(256, 256)
+    }
+
+    /// The pixel in the tile map at the given painter position, if any. Inverse of `pixel`.
+    fn pixel_at(self, pos: Pos2) -> Option<(usize, usize)> {
+        let x = ((pos.x - self.top_left.x) / SCALE_FACTOR).floor();
+        let y = ((pos.y - self.top_left.y) / SCALE_FACTOR).floor();
+
+        if (0.0..256.0).contains(&x) && (0.0..256.0).contains(&y) {
+            Some((x as usize, y as usize))
+        } else {
+            None
+        }
+    }
+
+    fn right_border_x(self, x_start: u8) -> (f32, bool) {
+        let mut x_end = (x_start as usize) + SCREEN_WIDTH;
+        let x_overflowed = x_end > 256;
+        if x_overflowed {
+            x_end -= 256;
+        }
+
+        (self.x(x_end) + 1.0, x_overflowed)
+    }
+
+    fn bottom_border_y(self, y_start: u8) -> (f32, bool) {
+        let mut y_end = (y_start as usize) + SCREEN_HEIGHT;
+        let y_overflowed = y_end > 256;
+        if y_overflowed {
+            y_end -= 256;
+        }
+
+        (self.y(y_end) + 1.0, y_overflowed)
+    }
+}
+
 #[derive(PartialEq)]
 enum Layer {
     Background,
@@ -46,6 +109,77 @@ enum TileDataAddressingMode {
     Unsigned,
 }
 
+/// Everything shown about a single tile in the tile map. The emulator may change VRAM at any point
+/// in a GUI update, so every tile is read once at the start of the update and the tile map,
+/// tooltip, and detail pane are all drawn from the same snapshot.
+struct TileSnapshot {
+    /// Index of the tile within the tile map
+    tile_map_index: usize,
+    /// Address of the tile map entry
+    tile_map_address: usize,
+    /// Index of the tile in the tile data area
+    tile_index: u8,
+    /// Address of the tile's data
+    tile_data_address: usize,
+    /// Only present in CGB mode
+    attributes: Option<BackgroundTileAttributes>,
+    tile_data: [u8; TILE_DATA_SIZE],
+    palette: ColorPalette,
+}
+
+impl TileSnapshot {
+    fn vram_bank_number(&self) -> usize {
+        self.attributes
+            .as_ref()
+            .map_or(0, |attributes| attributes.vram_bank_number())
+    }
+
+    fn describe(&self) -> Vec<String> {
+        let tile_x = self.tile_map_index % TILE_MAP_SIZE;
+        let tile_y = self.tile_map_index / TILE_MAP_SIZE;
+
+        let mut lines = vec![
+            format!(
+                "Tile map entry: 0x{:04X} ({}, {})",
+                self.tile_map_address, tile_x, tile_y
+            ),
+            format!("Tile index: 0x{:02X}", self.tile_index),
+            format!(
+                "Tile data: {}:0x{:04X}",
+                self.vram_bank_number(),
+                self.tile_data_address
+            ),
+        ];
+
+        if let Some(attributes) = &self.attributes {
+            let mut flags = vec![
+                format!("Palette {}", attributes.color_palette()),
+                format!("Bank {}", attributes.vram_bank_number()),
+            ];
+
+            if attributes.is_horizontally_flipped() {
+                flags.push("X flip".to_string());
+            }
+
+            if attributes.is_vertically_flipped() {
+                flags.push("Y flip".to_string());
+            }
+
+            if attributes.in_foreground() {
+                flags.push("Priority".to_string());
+            }
+
+            lines.push(format!(
+                "Attributes: 0x{:02X} ({})",
+                attributes.raw(),
+                flags.join(", ")
+            ));
+        }
+
+        lines
+    }
+}
+
 pub struct VramViewport {
     /// Whether the viewport is currently shown
     is_shown: bool,
@@ -54,6 +188,8 @@ pub struct VramViewport {
     layer: Layer,
     tile_map: Option<TileMap>,
     tile_data_addressing_mode: Option<TileDataAddressingMode>,
+    /// Index in the tile map of the tile shown in the detail pane, if any
+    pinned_tile: Option<usize>,
 }
 
 impl VramViewport {
@@ -64,6 +200,7 @@ impl VramViewport {
             layer: Layer::Background,
             tile_map: None,
             tile_data_addressing_mode: None,
+            pinned_tile: None,
         }
     }
 
@@ -78,6 +215,7 @@ impl VramViewport {
 
     pub fn close(&mut self) {
         self.is_shown = false;
+        self.pinned_tile = None;
     }
 }
 
@@ -94,78 +232,179 @@ impl EmulatorShellApp {
         ui.ctx().show_viewport_immediate(
             self.vram_viewport_id(),
             egui::ViewportBuilder::default()
-                .with_inner_size(self.vram_viewport_size())
+                .with_inner_size(self.vram_viewport_size() + Vec2::new(0.0, DOCK_BUTTON_BAR_HEIGHT))
                 .with_position(self.vram_view().initial_position)
                 .with_resizable(false)
                 .with_active(true)
-                .with_title("VRAM View"),
-            |ctx, _| egui::CentralPanel::default().show(ctx, |ui| self.draw_vram_view(ui)),
+                .with_title(DebugView::Vram.title()),
+            |ctx, _| {
+                self.draw_dock_button_bar(DebugView::Vram, ctx);
+                egui::CentralPanel::default().show(ctx, |ui| self.draw_vram_view(ui));
+            },
         );
     }
 
     pub fn draw_vram_view(&mut self, ui: &mut egui::Ui) {
-        egui::Frame::NONE.inner_margin(10.0).show(ui, |ui| {
-            ui.horizontal(|ui| {
-                self.draw_vram_options(ui);
-                self.draw_vram_pixels_area(ui);
-            })
-        });
+        let tiles: Vec<TileSnapshot> = (0..TILE_MAP_TOTAL_TILES)
+            .map(|i| self.snapshot_tile(i))
+            .collect();
+
+        egui::Frame::NONE
+            .inner_margin(WINDOW_PADDING)
+            .show(ui, |ui| {
+                ui.horizontal_top(|ui| {
+                    self.draw_vram_options(ui, &tiles);
+                    self.draw_vram_pixels_area(ui, &tiles);
+                })
+            });
     }
 
-    fn draw_vram_pixels_area(&self, ui: &mut egui::Ui) {
-        const VRAM_BANK: usize = 0;
+    /// Read everything shown about a tile in the tile map selected by the options.
+    fn snapshot_tile(&self, tile_map_index: usize) -> TileSnapshot {
+        let emulator = self.emulator();
+        let tile_map_number = self.tile_map_number_from_option();
+        let addressing_mode = self.tile_data_addressing_mode_from_option();
+
+        let tile_index = lookup_byte_in_tile_map(emulator, 0, tile_map_number, tile_map_index);
+        let attributes = if emulator.in_cgb_mode() {
+            Some(lookup_tile_attributes_in_tile_map(
+                emulator,
+                tile_map_number,
+                tile_map_index,
+            ))
+        } else {
+            None
+        };
 
-        let painter = ui.painter();
+        let vram_bank_num = attributes
+            .as_ref()
+            .map_or(0, |attributes| attributes.vram_bank_number());
+        let tile_map_start = if tile_map_number == 0 { 0x9800 } else { 0x9C00 };
+
+        TileSnapshot {
+            tile_map_index,
+            tile_map_address: tile_map_start + tile_map_index,
+            tile_index,
+            tile_data_address: tile_data_address(addressing_mode, tile_index),
+            tile_data: lookup_tile_data(emulator, vram_bank_num, addressing_mode, tile_index),
+            palette: background_color_palette(emulator, attributes.as_ref()),
+            attributes,
+        }
+    }
 
-        for i in 0..TILE_MAP_TOTAL_TILES {
-            let tile_index = lookup_byte_in_tile_map(
-                self.emulator(),
-                VRAM_BANK,
-                self.tile_map_number_from_option(),
-                i,
-            );
+    /// Draw a tile's pixels with their top left corner at the given position, applying any flips
+    /// from the tile's attributes.
+    fn draw_tile(&self, painter: &egui::Painter, tile: &TileSnapshot, top_left: Pos2, scale: f32) {
+        let tile_pixels = decode_tile_pixels(&tile.tile_data);
+        let (is_x_flipped, is_y_flipped) = tile.attributes.as_ref().map_or((false, false), |a| {
+            (a.is_horizontally_flipped(), a.is_vertically_flipped())
+        });
 
-            let tile_pixels = lookup_all_pixels_in_tile(
-                self.emulator(),
-                VRAM_BANK,
-                self.tile_data_addressing_mode_from_option(),
-                tile_index,
-            );
+        for (y, row) in tile_pixels.iter().enumerate() {
+            for (x, color_index) in row.iter().enumerate() {
+                let drawn_x = if is_x_flipped { TILE_SIZE - 1 - x } else { x };
+                let drawn_y = if is_y_flipped { TILE_SIZE - 1 - y } else { y };
 
+                let color = lookup_color_in_palette(&tile.palette, *color_index);
+                let pixel_min = top_left + Vec2::new(drawn_x as f32, drawn_y as f32) * scale;
+                let pixel_rect = Rect::from_min_size(pixel_min, Vec2::splat(scale));
+
+                let color32 = self.color_to_color32(color);
+                painter.rect_filled(pixel_rect, CornerRadius::ZERO, color32);
+            }
+        }
+    }
+
+    fn draw_vram_pixels_area(&mut self, ui: &mut egui::Ui, tiles: &[TileSnapshot]) {
+        let (rect, response) =
+            ui.allocate_exact_size(Vec2::splat(PIXELS_AREA_SIZE), Sense::click());
+        let area = PixelsArea { top_left: rect.min };
+        let painter = ui.painter();
+
+        for (i, tile) in tiles.iter().enumerate() {
             // Top left corner of the tile
             let tile_start_x = (i % TILE_MAP_SIZE) * TILE_SIZE;
             let tile_start_y = (i / TILE_MAP_SIZE) * TILE_SIZE;
 
-            #[allow(clippy::needless_range_loop)]
-            for x in 0..TILE_SIZE {
-                for y in 0..TILE_SIZE {
-                    let color_index = tile_pixels[y][x];
-
-                    let color = lookup_color_in_palette(
-                        &background_color_palette(self.emulator(), None),
-                        color_index,
-                    );
-
-                    let pixel_x = tile_start_x + x;
-                    let pixel_y = tile_start_y + y;
-                    let pixel_rect = Rect::from_two_pos(
-                        Self::pixel_to_painter_coords(pixel_x, pixel_y),
-                        Self::pixel_to_painter_coords(pixel_x + 1, pixel_y + 1),
-                    );
-
-                    let color32 = self.color_to_color32(color);
-                    painter.rect_filled(pixel_rect, CornerRadius::ZERO, color32);
-                }
-            }
+            self.draw_tile(
+                painter,
+                tile,
+                area.pixel(tile_start_x, tile_start_y),
+                SCALE_FACTOR,
+            );
         }
 
         // Draw border around the entire VRAM view
-        self.draw_debugger_vram_border(painter);
+        Self::draw_debugger_vram_border(painter, area);
 
         // Draw border around the currently selected layer
         match self.vram_view().layer {
-            Layer::Background => self.draw_background_border(painter),
-            Layer::Window => self.draw_window_border(painter),
+            Layer::Background => self.draw_background_border(painter, area),
+            Layer::Window => self.draw_window_border(painter, area),
+        }
+
+        if let Some(tile_map_index) = self.vram_view().pinned_tile {
+            let tile_start_x = (tile_map_index % TILE_MAP_SIZE) * TILE_SIZE;
+            let tile_start_y = (tile_map_index / TILE_MAP_SIZE) * TILE_SIZE;
+            painter.rect_stroke(
+                Rect::from_two_pos(
+                    area.pixel(tile_start_x, tile_start_y),
+                    area.pixel(tile_start_x + TILE_SIZE, tile_start_y + TILE_SIZE),
+                ),
+                CornerRadius::ZERO,
+                egui::Stroke::new(2.0, egui::Color32::YELLOW),
+                egui::StrokeKind::Outside,
+            );
+        }
+
+        let hovered_tile = response
+            .hover_pos()
+            .and_then(|pos| area.pixel_at(pos))
+            .map(|(x, y)| (y / TILE_SIZE) * TILE_MAP_SIZE + (x / TILE_SIZE));
+
+        if let Some(tile_map_index) = hovered_tile {
+            if response.clicked() {
+                self.vram_view_mut().pinned_tile = Some(tile_map_index);
+            }
+
+            response.on_hover_ui_at_pointer(|ui| {
+                for line in tiles[tile_map_index].describe() {
+                    ui.label(line);
+                }
+            });
+        }
+    }
+
+    /// Draw the pinned tile zoomed in, along with its raw data.
+    fn draw_pinned_tile(&mut self, ui: &mut egui::Ui, tiles: &[TileSnapshot]) {
+        let Some(tile_map_index) = self.vram_view().pinned_tile else {
+            ui.label("Click a tile to inspect it.");
+            return;
+        };
+
+        let tile = &tiles[tile_map_index];
+
+        ui.horizontal(|ui| {
+            ui.label("Tile:");
+            if ui.small_button("Unpin").clicked() {
+                self.vram_view_mut().pinned_tile = None;
+            }
+        });
+
+        let (rect, _) = ui.allocate_exact_size(
+            Vec2::splat(TILE_SIZE as f32 * TILE_DETAIL_SCALE_FACTOR),
+            Sense::hover(),
+        );
+        self.draw_tile(ui.painter(), tile, rect.min, TILE_DETAIL_SCALE_FACTOR);
+
+        for line in tile.describe() {
+            ui.label(line);
+        }
+
+        // Two bytes per row of pixels, low bits first
+        for half in tile.tile_data.chunks(TILE_DATA_SIZE / 2) {
+            let bytes: Vec<String> = half.iter().map(|byte| format!("{:02X}", byte)).collect();
+            ui.monospace(bytes.join(" "));
         }
     }
 
@@ -174,15 +413,15 @@ impl EmulatorShellApp {
     }
 
     /// Draw a border around the currently visible window area in the VRAM view.
-    fn draw_window_border(&self, painter: &egui::Painter) {
+    fn draw_window_border(&self, painter: &egui::Painter, area: PixelsArea) {
         let wx = self.emulator().wx().saturating_sub(7);
         let wy = self.emulator().wy();
 
-        let start_x = Self::x_pixel_to_painter_coords(wx);
-        let start_y = Self::y_pixel_to_painter_coords(wy);
+        let start_x = area.x(wx as usize);
+        let start_y = area.y(wy as usize);
 
-        let end_x = Self::x_pixel_to_painter_coords_usize(SCREEN_WIDTH);
-        let end_y = Self::y_pixel_to_painter_coords_usize(SCREEN_HEIGHT);
+        let end_x = area.x(SCREEN_WIDTH);
+        let end_y = area.y(SCREEN_HEIGHT);
 
         painter.rect_stroke(
             Rect::from_x_y_ranges(start_x..=end_x, start_y..=end_y),
@@ -195,21 +434,21 @@ impl EmulatorShellApp {
     /// Draw a border around the currently visible background area in the VRAM view.
     ///
     /// The border is drawn in red and wraps around to the other side of the screen.
-    fn draw_background_border(&self, painter: &egui::Painter) {
+    fn draw_background_border(&self, painter: &egui::Painter, area: PixelsArea) {
         let stroke = Self::bg_window_border_stroke();
 
         let x_start = self.emulator().scx();
         let y_start = self.emulator().scy();
 
-        let left_border_painter_x = Self::x_pixel_to_painter_coords(x_start) - 1.0;
-        let top_border_painter_y = Self::y_pixel_to_painter_coords(y_start) - 1.0;
-        let (right_border_painter_x, x_overflowed) = Self::right_border_painter_x(x_start);
-        let (bottom_border_painter_y, y_overflowed) = Self::bottom_border_painter_y(y_start);
+        let left_border_painter_x = area.x(x_start as usize) - 1.0;
+        let top_border_painter_y = area.y(y_start as usize) - 1.0;
+        let (right_border_painter_x, x_overflowed) = area.right_border_x(x_start);
+        let (bottom_border_painter_y, y_overflowed) = area.bottom_border_y(y_start);
 
-        let left_edge_painter_x = TOP_LEFT_PIXEL_PAINTER_COORDS.x;
-        let top_edge_painter_y = TOP_LEFT_PIXEL_PAINTER_COORDS.y;
-        let right_edge_painter_x = BOTTOM_RIGHT_PIXEL_PAINTER_COORDS.x;
-        let bottom_edge_painter_y = BOTTOM_RIGHT_PIXEL_PAINTER_COORDS.y;
+        let left_edge_painter_x = area.top_left.x;
+        let top_edge_painter_y = area.top_left.y;
+        let right_edge_painter_x = area.bottom_right().x;
+        let bottom_edge_painter_y = area.bottom_right().y;
 
         // 1px longer on both sides to fill in corners.
         if x_overflowed {
@@ -245,68 +484,16 @@ impl EmulatorShellApp {
         }
     }
 
-    fn draw_debugger_vram_border(&self, painter: &egui::Painter) {
+    fn draw_debugger_vram_border(painter: &egui::Painter, area: PixelsArea) {
         painter.rect_stroke(
-            Rect::from_two_pos(
-                TOP_LEFT_PIXEL_PAINTER_COORDS,
-                BOTTOM_RIGHT_PIXEL_PAINTER_COORDS,
-            ),
+            Rect::from_two_pos(area.top_left, area.bottom_right()),
             CornerRadius::ZERO,
             egui::Stroke::new(2.0, egui::Color32::BLACK),
             egui::StrokeKind::Outside,
         );
     }
 
-    const fn pixel_to_painter_coords(x: usize, y: usize) -> Pos2 {
-        Pos2::new(
-            Self::x_pixel_to_painter_coords_usize(x),
-            Self::y_pixel_to_painter_coords_usize(y),
-        )
-    }
-
-    const fn x_pixel_to_painter_coords_usize(x: usize) -> f32 {
-        (x as f32) * SCALE_FACTOR + PIXELS_AREA_TOP_LEFT.x
-    }
-
-    const fn y_pixel_to_painter_coords_usize(y: usize) -> f32 {
-        (y as f32) * SCALE_FACTOR + PIXELS_AREA_TOP_LEFT.y
-    }
-
-    const fn x_pixel_to_painter_coords(x: u8) -> f32 {
-        (x as f32) * SCALE_FACTOR + PIXELS_AREA_TOP_LEFT.x
-    }
-
-    const fn y_pixel_to_painter_coords(y: u8) -> f32 {
-        (y as f32) * SCALE_FACTOR + PIXELS_AREA_TOP_LEFT.y
-    }
-
-    fn right_border_painter_x(x_start: u8) -> (f32, bool) {
-        let mut x_end = (x_start as usize) + SCREEN_WIDTH;
-        let x_overflowed = x_end > 256;
-        if x_overflowed {
-            x_end -= 256;
-        }
-
-        (
-            Self::x_pixel_to_painter_coords_usize(x_end) + 1.0,
-            x_overflowed,
-        )
-    }
-
-    fn bottom_border_painter_y(y_start: u8) -> (f32, bool) {
-        let mut y_end = (y_start as usize) + SCREEN_HEIGHT;
-        let y_overflowed = y_end > 256;
-        if y_overflowed {
-            y_end -= 256;
-        }
-
-        (
-            Self::y_pixel_to_painter_coords_usize(y_end) + 1.0,
-            y_overflowed,
-        )
-    }
-
-    fn draw_vram_options(&mut self, ui: &mut egui::Ui) {
+    fn draw_vram_options(&mut self, ui: &mut egui::Ui, tiles: &[TileSnapshot]) {
         const VERTICAL_GAP: f32 = 20.0;
 
         ui.vertical(|ui| {
@@ -317,6 +504,35 @@ impl EmulatorShellApp {
 
             ui.add_space(VERTICAL_GAP);
             self.draw_tile_data_addressing_mode_option(ui);
+
+            ui.add_space(VERTICAL_GAP);
+            if ui
+                .button("Export Map...")
+                .on_hover_text("Save the tile map as a Tiled JSON map with a PNG tile sheet")
+                .clicked()
+            {
+                self.export_tile_map();
+            }
+
+            ui.add_space(VERTICAL_GAP);
+            self.draw_pinned_tile(ui, tiles);
+        });
+    }
+
+    /// Ask for a file, then export the tile map selected by the options to it.
+    fn export_tile_map(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Tiled JSON Map", &["json"])
+            .set_file_name("tile_map.json")
+            .save_file()
+        else {
+            return;
+        };
+
+        let tile_map_number = self.tile_map_number_from_option();
+        let addressing_mode = self.tile_data_addressing_mode_from_option();
+        self.send_fallible_command("Export tile map", |id| {
+            Command::ExportTileMap(tile_map_number, addressing_mode, path, id)
         });
     }
 
@@ -403,3 +619,26 @@ impl EmulatorShellApp {
         );
     }
 }
+
+#[cfg(test)]
+mod test {
+    use eframe::egui::{Pos2, Vec2};
+
+    use super::{PixelsArea, SCALE_FACTOR};
+
+    #[test]
+    fn pixel_at_is_inverse_of_pixel() {
+        let area = PixelsArea {
+            top_left: Pos2::new(30.0, 40.0),
+        };
+
+        for (x, y) in [(0, 0), (17, 200), (255, 255)] {
+            let center = area.pixel(x, y) + Vec2::splat(SCALE_FACTOR / 2.0);
+            assert_eq!(area.pixel_at(area.pixel(x, y)), Some((x, y)));
+            assert_eq!(area.pixel_at(center), Some((x, y)));
+        }
+
+        assert_eq!(area.pixel_at(Pos2::new(29.0, 40.0)), None);
+        assert_eq!(area.pixel_at(area.bottom_right()), None);
+    }
+}
@@ -0,0 +1,457 @@
+//! Placement of the debug views.
+//!
+//! Each debug view is either docked as a tab in a panel on the side of the main window, or popped
+//! out into its own window. The layout is saved to a TOML file whenever it changes, and restored
+//! the next time the emulator starts.
+
+use std::{fs, io, path::Path};
+
+use eframe::egui::{self, Layout, ScrollArea, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::gui::{
+    cartridge_ram_view::WINDOW_INNER_SIZE as CARTRIDGE_RAM_WINDOW_INNER_SIZE,
+    io_registers_view::WINDOW_INNER_SIZE as IO_REGISTERS_WINDOW_INNER_SIZE,
+    memory_view::WINDOW_INNER_SIZE as MEMORY_WINDOW_INNER_SIZE,
+    oam_view::WINDOW_INNER_SIZE as OAM_WINDOW_INNER_SIZE,
+    palette_view::WINDOW_INNER_SIZE as PALETTE_WINDOW_INNER_SIZE, shell::EmulatorShellApp,
+};
+
+pub const WINDOW_LAYOUT_FILE_NAME: &str = "layout.toml";
+
+/// Height of the bar holding the dock button at the top of a popped out view's window
+pub const DOCK_BUTTON_BAR_HEIGHT: f32 = 32.0;
+
+const DEFAULT_DOCK_WIDTH: f32 = 400.0;
+
+/// A debug view that can be docked or popped out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DebugView {
+    Vram,
+    Oam,
+    Palettes,
+    IoRegisters,
+    CartridgeRam,
+    Memory,
+}
+
+impl DebugView {
+    pub fn title(self) -> &'static str {
+        match self {
+            DebugView::Vram => "VRAM View",
+            DebugView::Oam => "OAM View",
+            DebugView::Palettes => "Palette View",
+            DebugView::IoRegisters => "IO Registers",
+            DebugView::CartridgeRam => "Cartridge RAM",
+            DebugView::Memory => "Memory View",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowLayout {
+    /// Views shown as tabs in the dock, in tab order. All other views are popped out.
+    docked: Vec<DebugView>,
+    /// The docked view whose tab is selected, if any
+    selected_tab: Option<DebugView>,
+    /// Width of the dock in points
+    dock_width: f32,
+}
+
+impl Default for WindowLayout {
+    fn default() -> Self {
+        WindowLayout {
+            docked: vec![],
+            selected_tab: None,
+            dock_width: DEFAULT_DOCK_WIDTH,
+        }
+    }
+}
+
+impl WindowLayout {
+    /// Read the layout from a file, or the default layout if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::from_toml(&contents),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, self.to_toml())
+    }
+
+    /// Parse a layout from TOML. Settings that are not listed keep their default.
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        let mut layout: WindowLayout =
+            toml::from_str(contents).map_err(|error| error.to_string())?;
+
+        // A view may only have a single tab, and only a docked view can be selected
+        let mut docked = vec![];
+        for view in layout.docked {
+            if !docked.contains(&view) {
+                docked.push(view);
+            }
+        }
+        layout.docked = docked;
+
+        if layout
+            .selected_tab
+            .is_some_and(|view| !layout.is_docked(view))
+        {
+            layout.selected_tab = None;
+        }
+
+        Ok(layout)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+
+    pub fn is_docked(&self, view: DebugView) -> bool {
+        self.docked.contains(&view)
+    }
+
+    /// Docked views in tab order.
+    pub fn docked_views(&self) -> impl Iterator<Item = DebugView> + '_ {
+        self.docked.iter().copied()
+    }
+
+    /// Add a view to the end of the dock and select its tab.
+    pub fn dock(&mut self, view: DebugView) {
+        if !self.is_docked(view) {
+            self.docked.push(view);
+        }
+
+        self.selected_tab = Some(view);
+    }
+
+    /// Remove a view from the dock so that it is shown in its own window. If its tab was selected
+    /// the first remaining tab is selected instead.
+    pub fn pop_out(&mut self, view: DebugView) {
+        self.docked.retain(|docked_view| *docked_view != view);
+
+        if self.selected_tab == Some(view) {
+            self.selected_tab = self.docked.first().copied();
+        }
+    }
+
+    pub fn selected_tab(&self) -> Option<DebugView> {
+        self.selected_tab
+    }
+
+    /// Select the tab of a docked view. Has no effect if the view is not docked.
+    pub fn select_tab(&mut self, view: DebugView) {
+        if self.is_docked(view) {
+            self.selected_tab = Some(view);
+        }
+    }
+
+    pub fn dock_width(&self) -> f32 {
+        self.dock_width
+    }
+
+    pub fn set_dock_width(&mut self, dock_width: f32) {
+        self.dock_width = dock_width;
+    }
+}
+
+impl EmulatorShellApp {
+    /// Show a debug view where it was last placed. If it is docked its tab is selected.
+    pub fn show_debug_view(&mut self, view: DebugView, ctx: &egui::Context) {
+        self.window_layout_mut().select_tab(view);
+
+        if self.is_debug_view_shown(view) {
+            return;
+        }
+
+        let initial_position =
+            self.additional_viewport_initial_position(ctx, self.debug_view_window_size(view));
+        match view {
+            DebugView::Vram => self.vram_view_mut().open(initial_position),
+            DebugView::Oam => self.oam_view_mut().open(initial_position),
+            DebugView::Palettes => self.palette_view_mut().open(initial_position),
+            DebugView::IoRegisters => self.io_registers_view_mut().open(initial_position),
+            DebugView::CartridgeRam => self.cartridge_ram_view_mut().open(initial_position),
+            DebugView::Memory => self.memory_view_mut().open(initial_position),
+        }
+    }
+
+    fn is_debug_view_shown(&self, view: DebugView) -> bool {
+        match view {
+            DebugView::Vram => self.vram_view().is_shown(),
+            DebugView::Oam => self.oam_view().is_shown(),
+            DebugView::Palettes => self.palette_view().is_shown(),
+            DebugView::IoRegisters => self.io_registers_view().is_shown(),
+            DebugView::CartridgeRam => self.cartridge_ram_view().is_shown(),
+            DebugView::Memory => self.memory_view().is_shown(),
+        }
+    }
+
+    fn close_debug_view(&mut self, view: DebugView) {
+        match view {
+            DebugView::Vram => self.vram_view_mut().close(),
+            DebugView::Oam => self.oam_view_mut().close(),
+            DebugView::Palettes => self.palette_view_mut().close(),
+            DebugView::IoRegisters => self.io_registers_view_mut().close(),
+            DebugView::CartridgeRam => self.cartridge_ram_view_mut().close(),
+            DebugView::Memory => self.memory_view_mut().close(),
+        }
+    }
+
+    fn debug_view_window_size(&self, view: DebugView) -> Vec2 {
+        let content_size = match view {
+            DebugView::Vram => self.vram_viewport_size(),
+            DebugView::Oam => OAM_WINDOW_INNER_SIZE,
+            DebugView::Palettes => PALETTE_WINDOW_INNER_SIZE,
+            DebugView::IoRegisters => IO_REGISTERS_WINDOW_INNER_SIZE,
+            DebugView::CartridgeRam => CARTRIDGE_RAM_WINDOW_INNER_SIZE,
+            DebugView::Memory => MEMORY_WINDOW_INNER_SIZE,
+        };
+
+        content_size + Vec2::new(0.0, DOCK_BUTTON_BAR_HEIGHT)
+    }
+
+    /// Draw the contents of a debug view, whether docked or popped out.
+    fn draw_debug_view(&mut self, view: DebugView, ui: &mut egui::Ui) {
+        match view {
+            DebugView::Vram => self.draw_vram_view(ui),
+            DebugView::Oam => self.draw_oam_view(ui),
+            DebugView::Palettes => self.draw_palette_view(ui),
+            DebugView::IoRegisters => self.draw_io_registers_view(ui),
+            DebugView::CartridgeRam => self.draw_cartridge_ram_view(ui),
+            DebugView::Memory => self.draw_memory_view(ui),
+        }
+    }
+
+    /// Draw each shown debug view that is popped out in its own window.
+    pub(super) fn draw_popped_out_debug_views(&mut self, ui: &mut egui::Ui) {
+        if self.vram_view().is_shown() && !self.window_layout().is_docked(DebugView::Vram) {
+            self.draw_vram_viewport(ui);
+        }
+
+        if self.oam_view().is_shown() && !self.window_layout().is_docked(DebugView::Oam) {
+            self.draw_oam_viewport(ui);
+        }
+
+        if self.palette_view().is_shown() && !self.window_layout().is_docked(DebugView::Palettes) {
+            self.draw_palette_viewport(ui);
+        }
+
+        if self.io_registers_view().is_shown()
+            && !self.window_layout().is_docked(DebugView::IoRegisters)
+        {
+            self.draw_io_registers_viewport(ui);
+        }
+
+        if self.cartridge_ram_view().is_shown()
+            && !self.window_layout().is_docked(DebugView::CartridgeRam)
+        {
+            self.draw_cartridge_ram_viewport(ui);
+        }
+
+        if self.memory_view().is_shown() && !self.window_layout().is_docked(DebugView::Memory) {
+            self.draw_memory_viewport(ui);
+        }
+    }
+
+    /// Draw the bar at the top of a popped out view's window, with a button to dock it.
+    pub(super) fn draw_dock_button_bar(&mut self, view: DebugView, ctx: &egui::Context) {
+        egui::TopBottomPanel::top("dock_button_bar")
+            .exact_height(DOCK_BUTTON_BAR_HEIGHT)
+            .show(ctx, |ui| {
+                ui.with_layout(Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.button("Dock").clicked() {
+                        self.window_layout_mut().dock(view);
+                        self.save_window_layout();
+                    }
+                });
+            });
+    }
+
+    /// Draw the shown docked views as tabs in a panel on the right side of the main window. Must be
+    /// drawn before the central panel containing the screen.
+    pub(super) fn draw_dock(&mut self, ctx: &egui::Context) {
+        let docked_views = self
+            .window_layout()
+            .docked_views()
+            .filter(|view| self.is_debug_view_shown(*view))
+            .collect::<Vec<_>>();
+        let Some(first_view) = docked_views.first().copied() else {
+            return;
+        };
+
+        let selected_view = self
+            .window_layout()
+            .selected_tab()
+            .filter(|view| docked_views.contains(view))
+            .unwrap_or(first_view);
+
+        let mut clicked_tab = None;
+        let mut is_pop_out_clicked = false;
+        let mut is_close_clicked = false;
+
+        let response = egui::SidePanel::right("debug_dock")
+            .resizable(true)
+            .default_width(self.window_layout().dock_width())
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    for view in &docked_views {
+                        if ui
+                            .selectable_label(*view == selected_view, view.title())
+                            .clicked()
+                        {
+                            clicked_tab = Some(*view);
+                        }
+                    }
+
+                    ui.with_layout(Layout::right_to_left(egui::Align::Center), |ui| {
+                        is_close_clicked = ui.button("Close").clicked();
+                        is_pop_out_clicked = ui.button("Pop Out").clicked();
+                    });
+                });
+                ui.separator();
+
+                ScrollArea::both()
+                    .auto_shrink(false)
+                    .show(ui, |ui| self.draw_debug_view(selected_view, ui));
+            });
+
+        // Only recorded once resizing finishes, rather than on every frame of the drag
+        let dock_width = response.response.rect.width();
+        let is_dragging = ctx.input(|input| input.pointer.any_down());
+        let is_resized =
+            !is_dragging && (dock_width - self.window_layout().dock_width()).abs() >= 1.0;
+        if is_resized {
+            self.window_layout_mut().set_dock_width(dock_width);
+        }
+
+        if let Some(view) = clicked_tab {
+            self.window_layout_mut().select_tab(view);
+        }
+
+        if is_pop_out_clicked {
+            self.window_layout_mut().pop_out(selected_view);
+
+            // Open the window next to the main window, rather than where it was last popped out
+            self.close_debug_view(selected_view);
+            self.show_debug_view(selected_view, ctx);
+        }
+
+        if is_close_clicked {
+            self.close_debug_view(selected_view);
+        }
+
+        if clicked_tab.is_some() || is_pop_out_clicked || is_resized {
+            self.save_window_layout();
+        }
+    }
+
+    /// Load the layout of the debug views, falling back to the default if it cannot be read.
+    pub(super) fn load_window_layout(&mut self) {
+        let Some(path) = self.emulator().window_layout_path() else {
+            return;
+        };
+
+        match WindowLayout::load(path) {
+            Ok(window_layout) => *self.window_layout_mut() = window_layout,
+            Err(error) => {
+                let message = format!("Unable to load {}: {}", path.display(), error);
+                self.show_toast(message);
+            }
+        }
+    }
+
+    fn save_window_layout(&mut self) {
+        let Some(path) = self.emulator().window_layout_path() else {
+            return;
+        };
+
+        if let Err(error) = self.window_layout().save(path) {
+            let message = format!("Unable to save {}: {}", path.display(), error);
+            self.show_toast(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DebugView, WindowLayout};
+
+    #[test]
+    fn round_trip_window_layout() {
+        let default = WindowLayout::default();
+        assert_eq!(WindowLayout::from_toml(&default.to_toml()), Ok(default));
+
+        let mut layout = WindowLayout::default();
+        layout.dock(DebugView::IoRegisters);
+        layout.dock(DebugView::Vram);
+        layout.select_tab(DebugView::IoRegisters);
+        layout.set_dock_width(512.0);
+
+        let restored = WindowLayout::from_toml(&layout.to_toml()).unwrap();
+        assert_eq!(restored, layout);
+        assert_eq!(
+            restored.docked_views().collect::<Vec<_>>(),
+            vec![DebugView::IoRegisters, DebugView::Vram]
+        );
+        assert_eq!(restored.selected_tab(), Some(DebugView::IoRegisters));
+        assert_eq!(restored.dock_width(), 512.0);
+    }
+
+    #[test]
+    fn parse_window_layout() {
+        // Empty file is the default layout
+        assert_eq!(WindowLayout::from_toml(""), Ok(WindowLayout::default()));
+
+        let layout = WindowLayout::from_toml(
+            r#"
+            docked = ["palettes", "vram", "palettes"]
+            selected_tab = "io-registers"
+            "#,
+        )
+        .unwrap();
+
+        // Duplicate tabs are removed, and a view that is not docked cannot be selected
+        assert_eq!(
+            layout.docked_views().collect::<Vec<_>>(),
+            vec![DebugView::Palettes, DebugView::Vram]
+        );
+        assert_eq!(layout.selected_tab(), None);
+        assert_eq!(layout.dock_width(), WindowLayout::default().dock_width());
+
+        assert!(WindowLayout::from_toml("docked = [\"console\"]").is_err());
+    }
+
+    #[test]
+    fn dock_and_pop_out() {
+        let mut layout = WindowLayout::default();
+        assert!(!layout.is_docked(DebugView::Vram));
+
+        layout.dock(DebugView::Vram);
+        layout.dock(DebugView::Palettes);
+        assert!(layout.is_docked(DebugView::Vram));
+        assert_eq!(layout.selected_tab(), Some(DebugView::Palettes));
+
+        // Selecting a view that is not docked does nothing
+        layout.select_tab(DebugView::IoRegisters);
+        assert_eq!(layout.selected_tab(), Some(DebugView::Palettes));
+
+        // Popping out the selected tab selects the first remaining tab
+        layout.pop_out(DebugView::Palettes);
+        assert!(!layout.is_docked(DebugView::Palettes));
+        assert_eq!(layout.selected_tab(), Some(DebugView::Vram));
+
+        layout.pop_out(DebugView::Vram);
+        assert_eq!(layout.selected_tab(), None);
+        assert_eq!(layout.docked_views().count(), 0);
+    }
+}
@@ -2,9 +2,13 @@ use concat_idents::concat_idents;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    address_space::{Address, IO_REGISTERS_SIZE, NR10, NR52},
+    address_space::{
+        Address, IO_REGISTERS_SIZE, NR10, NR52, VRAM_END, VRAM_START, WAVE_RAM_END, WAVE_RAM_START,
+    },
+    audio::Apu,
     emulator::{Emulator, Register, VRAM_READ_FAILED_VALUE},
-    machine::Machine,
+    machine::{CompatMode, Machine},
+    ram_init::RamFiller,
 };
 
 /// File containing all IO registers.
@@ -26,6 +30,21 @@ impl IoRegisters {
         Self { registers }
     }
 
+    /// Give registers that are uninitialized at power-on values from the filler, instead of their
+    /// typical values.
+    pub fn randomize_uninitialized(&mut self, machine: Machine, filler: &mut RamFiller) {
+        let policies = match machine {
+            Machine::Dmg => &DMG_INIT_POLICIES,
+            Machine::Cgb => &CGB_INIT_POLICIES,
+        };
+
+        for (register, policy) in self.registers.iter_mut().zip(policies) {
+            if let InitPolicy::Uninitialized(_) = policy {
+                filler.fill(std::slice::from_mut(register));
+            }
+        }
+    }
+
     fn as_slice(&self) -> &IoRegisterFile {
         &self.registers
     }
@@ -57,7 +76,7 @@ impl Emulator {
     }
 
     /// Read a full byte without modification.
-    fn read_register_raw(&self, address: Address) -> Register {
+    pub(crate) fn read_register_raw(&self, address: Address) -> Register {
         self.io_regs().as_slice()[offset(address)]
     }
 
@@ -72,7 +91,7 @@ impl Emulator {
     }
 
     /// Write a full byte without modification.
-    fn write_register_raw(&mut self, address: Address, value: Register) {
+    pub(crate) fn write_register_raw(&mut self, address: Address, value: Register) {
         self.io_regs_mut().as_slice_mut()[offset(address)] = value;
     }
 
@@ -172,13 +191,41 @@ impl Emulator {
         self.reset_divider_register();
     }
 
+    fn write_tima_impl(&mut self, _: Address, value: Register) {
+        self.write_tima_during_overflow(value);
+    }
+
+    fn write_tma_impl(&mut self, _: Address, value: Register) {
+        self.write_tma_during_overflow(value);
+    }
+
     fn read_tac_impl(&self, _: Address) -> Register {
         self.tac_bits() | ((self.is_timer_enabled() as u8) << 2)
     }
 
     fn write_tac_impl(&mut self, _: Address, value: Register) {
-        self.set_timer_enabled(is_bit_set(value, 2));
-        self.set_tac_bits(value & 0x03);
+        self.set_timer_control(is_bit_set(value, 2), value & 0x03);
+    }
+
+    fn read_sc_impl(&self, _: Address) -> Register {
+        // Unused bits are always 1. Bit 1 selects the fast clock and only exists in CGB mode.
+        if self.compat_mode().has_cgb_registers() {
+            self.sc_raw() | 0x7C
+        } else {
+            self.sc_raw() | 0x7E
+        }
+    }
+
+    fn write_sc_impl(&mut self, _: Address, value: Register) {
+        self.write_sc_raw(value);
+
+        // Transfers with an external clock never complete since there is no link partner
+        let is_transfer_requested = is_bit_set(value, 7) && is_bit_set(value, 0);
+        if is_transfer_requested {
+            self.start_serial_transfer();
+        } else if !is_bit_set(value, 7) {
+            self.cancel_serial_transfer();
+        }
     }
 
     fn write_if_impl(&mut self, _: Address, value: Register) {
@@ -207,6 +254,7 @@ impl Emulator {
 
     fn write_nr11_impl(&mut self, _: Address, value: Register) {
         if !self.apu().is_on() {
+            self.write_length_timer_while_off(|apu| apu.channel_1_mut().write_length_timer(value));
             return;
         }
 
@@ -263,6 +311,7 @@ impl Emulator {
 
     fn write_nr21_impl(&mut self, _: Address, value: Register) {
         if !self.apu().is_on() {
+            self.write_length_timer_while_off(|apu| apu.channel_2_mut().write_length_timer(value));
             return;
         }
 
@@ -333,6 +382,7 @@ impl Emulator {
 
     fn write_nr31_impl(&mut self, _: Address, value: Register) {
         if !self.apu().is_on() {
+            self.write_length_timer_while_off(|apu| apu.channel_3_mut().write_nr31(value));
             return;
         }
 
@@ -389,6 +439,7 @@ impl Emulator {
 
     fn write_nr41_impl(&mut self, _: Address, value: Register) {
         if !self.apu().is_on() {
+            self.write_length_timer_while_off(|apu| apu.channel_4_mut().write_nr41(value));
             return;
         }
 
@@ -471,24 +522,46 @@ impl Emulator {
     }
 
     fn write_nr52_impl(&mut self, _: Address, value: Register) {
-        // Check if APU is being turned off. If so clear all audo channels before turning off APU.
+        // Check if APU is being turned off. If so clear all audio registers before turning off APU.
+        // Wave RAM is left untouched.
         let is_apu_turning_off = self.apu().is_on() && !is_bit_set(value, 7);
         if is_apu_turning_off {
             for audio_reg_addr in NR10..NR52 {
-                self.write_io_register(audio_reg_addr, value);
+                self.write_io_register(audio_reg_addr, 0);
             }
         }
 
         self.apu_mut().write_nr52(value);
     }
 
+    /// On DMG the length timers can still be written while the APU is off, but all other bits of
+    /// the register are ignored. On CGB writes are ignored entirely.
+    fn write_length_timer_while_off(&mut self, write_length_timer: impl FnOnce(&mut Apu)) {
+        if !self.is_cgb_machine() {
+            write_length_timer(self.apu_mut());
+        }
+    }
+
+    /// Wave RAM is stored only in the wave channel. The bytes for wave RAM in the register file
+    /// hold its initial values, which are moved to the wave channel by `init_wave_ram`.
+    fn read_wave_ram(&self, address: Address) -> Register {
+        self.apu().channel_3().read_wave_ram(address)
+    }
+
     fn write_wave_ram(&mut self, address: Address, value: Register) {
-        self.write_register_raw(address, value);
         self.apu_mut()
             .channel_3_mut()
             .write_wave_ram(address, value);
     }
 
+    /// Copy the initial values of wave RAM from the register file to the wave channel.
+    pub(crate) fn init_wave_ram(&mut self) {
+        for address in WAVE_RAM_START..WAVE_RAM_END {
+            let value = self.read_register_raw(address);
+            self.write_wave_ram(address, value);
+        }
+    }
+
     fn read_lcd_stat_impl(&self, _: Address) -> Register {
         // Construct the STAT register value on reads, allowing for raw writes.
         let raw = self.stat_raw();
@@ -496,24 +569,25 @@ impl Emulator {
         let unused_bits = 0x80;
         let interrupt_bits = raw | 0x78;
 
-        let lyc_bit = if self.scanline() == self.lyc() {
-            0x04
-        } else {
-            0x00
-        };
+        let lyc_bit = if self.ly() == self.lyc() { 0x04 } else { 0x00 };
 
         let mode_bits = self.mode().byte_value();
 
         unused_bits | interrupt_bits | lyc_bit | mode_bits
     }
 
+    fn write_lcd_stat_impl(&mut self, _: Address, value: Register) {
+        self.write_stat_raw(value);
+
+        // Enabling a condition that already holds raises the STAT interrupt line
+        self.update_stat_interrupt_line();
+    }
+
     fn write_lyc_impl(&mut self, _: Address, value: Register) {
         self.write_lyc_raw(value);
 
         // Request interrupt for LYC=LY if needed
-        if value == self.scanline() && self.is_stat_lyc_interrupt_enabled() {
-            self.request_interrupt(crate::emulator::Interrupt::LcdStat);
-        }
+        self.update_stat_interrupt_line();
     }
 
     fn write_dma_impl(&mut self, _: Address, value: Register) {
@@ -525,16 +599,16 @@ impl Emulator {
     }
 
     fn read_ly_impl(&self, _: Address) -> Register {
-        self.scanline()
+        self.current_ly()
     }
 
     fn write_key0_impl(&mut self, _: Address, value: Register) {
         // Writes are only allowed while booting
         if self.is_booting() && self.is_cgb_machine() {
-            self.set_in_cgb_mode(!is_bit_set(value, 2));
+            self.set_compat_mode(CompatMode::from_key0(value));
 
-            // Only write bit 2, leaving other bits set. This allows raw reads.
-            self.write_key0_raw(0xFB | (value & 0x04));
+            // Only write bits 2 and 3, leaving other bits set. This allows raw reads.
+            self.write_key0_raw(0xF3 | (value & 0x0C));
         }
     }
 
@@ -553,9 +627,24 @@ impl Emulator {
         self.write_key1_raw(value & 0x01);
     }
 
+    /// VRAM and WRAM banking only exist in CGB mode. On a DMG and in DMG compatibility mode the
+    /// bank registers read as 0xFF and writes are ignored, so the first VRAM bank and WRAM bank 1
+    /// stay mapped.
+    fn read_cgb_bank_register(&self, address: Address) -> Register {
+        if self.compat_mode().has_cgb_registers() {
+            self.read_register_raw(address)
+        } else {
+            0xFF
+        }
+    }
+
     fn write_vbk_impl(&mut self, _: Address, value: Register) {
         // Only write bottom bit, leaving top 7 bits set. This allows raw reads.
-        self.write_vbk_raw(0xFE | (0x01 & value));
+        if self.compat_mode().has_cgb_registers() {
+            self.write_vbk_raw(0xFE | (0x01 & value));
+        } else if value & 0x01 != 0 {
+            self.report_cgb_only_register_write();
+        }
     }
 
     fn write_bank_impl(&mut self, _: Address, _: Register) {
@@ -563,6 +652,12 @@ impl Emulator {
     }
 
     fn write_hdma5_impl(&mut self, _: Address, value: Register) {
+        // VRAM DMA only exists in CGB mode
+        if !self.compat_mode().has_cgb_registers() {
+            self.report_cgb_only_register_write();
+            return;
+        }
+
         self.write_hdma5_raw(value);
 
         let is_high_bit_set = is_bit_set(value, 7);
@@ -576,9 +671,13 @@ impl Emulator {
             return;
         }
 
-        let source = (((self.hdma1_raw() as u16) << 8) | self.hdma2_raw() as u16) & 0xFFF0;
+        let source = Self::vram_dma_source_address(self.hdma1_raw(), self.hdma2_raw());
         let dest = ((((self.hdma3_raw() as u16) << 8) | self.hdma4_raw() as u16) & 0x1FF0) | 0x8000;
 
+        if (VRAM_START..VRAM_END).contains(&source) {
+            self.report_vram_dma_source_in_vram(source);
+        }
+
         if is_high_bit_set {
             self.start_hblank_vram_dma_transfer(source, dest, num_blocks);
         } else {
@@ -596,17 +695,25 @@ impl Emulator {
         is_bit_set(reg, 7)
     }
 
+    fn write_dmg_palette_impl(&mut self, address: Address, value: Register) {
+        self.write_register_raw(address, value);
+        self.palette_cache_mut().mark_dirty();
+    }
+
     fn read_bcpd_impl(&self, _: Address) -> Register {
         // Reads fail when VRAM cannot be accessed, returning undefined data (usually 0xFF)
-        if self.can_access_vram() {
+        if !self.can_access_vram() {
             return VRAM_READ_FAILED_VALUE;
         }
 
-        // Reads fail during draw mode returning undefined data, usually 0xFF
         self.cgb_background_palettes()[Self::cgb_pallette_address(self.bcps_raw())]
     }
 
     fn write_bcpd_impl(&mut self, _: Address, value: Register) {
+        if !self.compat_mode().has_cgb_rendering() {
+            self.report_cgb_only_register_write();
+        }
+
         let bcps = self.bcps_raw();
         let address = Self::cgb_pallette_address(bcps);
 
@@ -625,7 +732,7 @@ impl Emulator {
 
     fn read_ocpd_impl(&self, _: Address) -> Register {
         // Reads fail when VRAM cannot be accessed, returning undefined data (usually 0xFF)
-        if self.can_access_vram() {
+        if !self.can_access_vram() {
             return VRAM_READ_FAILED_VALUE;
         }
 
@@ -648,9 +755,18 @@ impl Emulator {
         }
     }
 
+    /// OPRI only exists on CGB, where only bit 0 is used and the other bits read as 1.
+    fn read_opri_impl(&self, _: Address) -> Register {
+        if self.is_cgb_machine() {
+            0xFE | (self.opri_raw() & 0x01)
+        } else {
+            0xFF
+        }
+    }
+
     fn write_opri_impl(&mut self, _: Address, value: Register) {
         // Writes are ignored after booting
-        if self.is_booting() {
+        if self.is_booting() && self.is_cgb_machine() {
             // Only write bottom bit, leaving top 7 bits unset. This allows raw reads.
             self.write_opri_raw(0x01 & value);
         }
@@ -659,7 +775,9 @@ impl Emulator {
     fn write_wbk_impl(&mut self, _: Address, value: Register) {
         // Only write bottom 3 bits, leaving top 5 bits set. This allows raw reads.
         // Value 0 is treated as 1.
-        self.write_wbk_raw(0xF8 | ((0x07 & value).max(1)));
+        if self.compat_mode().has_cgb_registers() {
+            self.write_wbk_raw(0xF8 | ((0x07 & value).max(1)));
+        }
     }
 }
 
@@ -668,8 +786,51 @@ const fn offset(address: Address) -> usize {
     (address & 0xFF) as usize
 }
 
+/// How a register is initialized at power-on, after the boot ROM completes.
+#[derive(Clone, Copy)]
+enum InitPolicy {
+    /// Always has this value
+    Fixed(Register),
+    /// Set by the boot ROM to a value that depends on its duration or the header contents. The
+    /// typical value is used.
+    Variable(Register),
+    /// Never written by the boot ROM, so holds whatever value it powered on with. The typical value
+    /// is used unless RAM is randomized at power-on.
+    Uninitialized(Register),
+}
+
+impl InitPolicy {
+    const fn typical_value(self) -> Register {
+        match self {
+            InitPolicy::Fixed(value)
+            | InitPolicy::Variable(value)
+            | InitPolicy::Uninitialized(value) => value,
+        }
+    }
+}
+
+/// Initial values in the register table are either a literal value or a named policy.
+macro_rules! init_policy {
+    ($value:literal) => {
+        InitPolicy::Fixed($value)
+    };
+    ($policy:ident) => {
+        $policy
+    };
+}
+
+const fn init_io_registers(policies: &[InitPolicy; IO_REGISTERS_SIZE]) -> IoRegisterFile {
+    let mut registers = [0xFF; IO_REGISTERS_SIZE];
+    let mut i = 0;
+    while i < IO_REGISTERS_SIZE {
+        registers[i] = policies[i].typical_value();
+        i += 1;
+    }
+    registers
+}
+
 macro_rules! define_registers {
-    ($(($name:ident, $addr:expr, $dmg_init:expr, $cgb_init:expr, $read_fn:ident, $write_fn:ident)),*,) => {
+    ($(($name:ident, $addr:expr, $dmg_init:tt, $cgb_init:tt, $read_fn:ident, $write_fn:ident)),*,) => {
         impl Emulator {
             $(
                 /// Read the a value from the $name, applying any special behavior.
@@ -703,22 +864,26 @@ macro_rules! define_registers {
             )*
         }
 
-        const DMG_INIT_IO_REGISTERS: IoRegisterFile = const {
-            let mut registers = [0xFF; IO_REGISTERS_SIZE];
+        const DMG_INIT_POLICIES: [InitPolicy; IO_REGISTERS_SIZE] = const {
+            let mut policies = [NONE; IO_REGISTERS_SIZE];
             $(
-                registers[offset($addr)] = $dmg_init;
+                policies[offset($addr)] = init_policy!($dmg_init);
             )*
-            registers
+            policies
         };
 
-        const CGB_INIT_IO_REGISTERS: IoRegisterFile = const {
-            let mut registers = [0xFF; IO_REGISTERS_SIZE];
+        const CGB_INIT_POLICIES: [InitPolicy; IO_REGISTERS_SIZE] = const {
+            let mut policies = [NONE; IO_REGISTERS_SIZE];
             $(
-                registers[offset($addr)] = $cgb_init;
+                policies[offset($addr)] = init_policy!($cgb_init);
             )*
-            registers
+            policies
         };
 
+        const DMG_INIT_IO_REGISTERS: IoRegisterFile = init_io_registers(&DMG_INIT_POLICIES);
+
+        const CGB_INIT_IO_REGISTERS: IoRegisterFile = init_io_registers(&CGB_INIT_POLICIES);
+
         const READ_HANDLERS: [fn(&Emulator, Address) -> Register; IO_REGISTERS_SIZE] = const {
             let mut handlers: [fn(&Emulator, Address) -> Register; IO_REGISTERS_SIZE] =
                 [Emulator::read_non_register; IO_REGISTERS_SIZE];
@@ -744,13 +909,13 @@ macro_rules! define_registers {
 }
 
 /// Register is not present on this system
-const NONE: u8 = 0xFF;
+const NONE: InitPolicy = InitPolicy::Fixed(0xFF);
 
 /// Register has an arbitrary initial value (e.g. depends on boot ROM's duration or header contents)
-const VARIABLE: u8 = 0xFF;
+const VARIABLE: InitPolicy = InitPolicy::Variable(0xFF);
 
 /// Register is unitialized
-const UNITIALIZED: u8 = 0xFF;
+const UNITIALIZED: InitPolicy = InitPolicy::Uninitialized(0xFF);
 
 // (register name, address, DMG initial value, CGB initial value, read handler, write handler)
 define_registers!(
@@ -762,23 +927,18 @@ define_registers!(
         read_joypad_impl,
         write_register_raw
     ),
-    (div, 0xFF04, 0xAB, VARIABLE, read_div_impl, write_div_impl),
-    (
-        tima,
-        0xFF05,
-        0x00,
-        0x00,
-        read_register_raw,
-        write_register_raw
-    ),
     (
-        tma,
-        0xFF06,
+        sb,
+        0xFF01,
         0x00,
         0x00,
         read_register_raw,
         write_register_raw
     ),
+    (sc, 0xFF02, 0x7E, 0x7F, read_sc_impl, write_sc_impl),
+    (div, 0xFF04, 0xAB, VARIABLE, read_div_impl, write_div_impl),
+    (tima, 0xFF05, 0x00, 0x00, read_register_raw, write_tima_impl),
+    (tma, 0xFF06, 0x00, 0x00, read_register_raw, write_tma_impl),
     (tac, 0xFF07, 0xF8, 0xF8, read_tac_impl, write_tac_impl),
     (if_reg, 0xFF0F, 0xE1, 0xE1, read_register_raw, write_if_impl),
     (nr10, NR10, 0x80, 0x80, read_nr10_impl, write_nr10_impl),
@@ -802,64 +962,22 @@ define_registers!(
     (nr50, 0xFF24, 0x77, 0x77, read_nr50_impl, write_nr50_impl),
     (nr51, 0xFF25, 0xF3, 0xF3, read_nr51_impl, write_nr51_impl),
     (nr52, NR52, 0xF1, 0xF1, read_nr52_impl, write_nr52_impl),
-    (wave0, 0xFF30, 0x00, 0x00, read_register_raw, write_wave_ram),
-    (wave1, 0xFF31, 0xFF, 0xFF, read_register_raw, write_wave_ram),
-    (wave2, 0xFF32, 0x00, 0x00, read_register_raw, write_wave_ram),
-    (wave3, 0xFF33, 0xFF, 0xFF, read_register_raw, write_wave_ram),
-    (wave4, 0xFF34, 0x00, 0x00, read_register_raw, write_wave_ram),
-    (wave5, 0xFF35, 0xFF, 0xFF, read_register_raw, write_wave_ram),
-    (wave6, 0xFF36, 0x00, 0x00, read_register_raw, write_wave_ram),
-    (wave7, 0xFF37, 0xFF, 0xFF, read_register_raw, write_wave_ram),
-    (wave8, 0xFF38, 0x00, 0x00, read_register_raw, write_wave_ram),
-    (wave9, 0xFF39, 0xFF, 0xFF, read_register_raw, write_wave_ram),
-    (
-        wave10,
-        0xFF3A,
-        0x00,
-        0x00,
-        read_register_raw,
-        write_wave_ram
-    ),
-    (
-        wave11,
-        0xFF3B,
-        0xFF,
-        0xFF,
-        read_register_raw,
-        write_wave_ram
-    ),
-    (
-        wave12,
-        0xFF3C,
-        0x00,
-        0x00,
-        read_register_raw,
-        write_wave_ram
-    ),
-    (
-        wave13,
-        0xFF3D,
-        0xFF,
-        0xFF,
-        read_register_raw,
-        write_wave_ram
-    ),
-    (
-        wave14,
-        0xFF3E,
-        0x00,
-        0x00,
-        read_register_raw,
-        write_wave_ram
-    ),
-    (
-        wave15,
-        0xFF3F,
-        0xFF,
-        0xFF,
-        read_register_raw,
-        write_wave_ram
-    ),
+    (wave0, 0xFF30, 0x00, 0x00, read_wave_ram, write_wave_ram),
+    (wave1, 0xFF31, 0xFF, 0xFF, read_wave_ram, write_wave_ram),
+    (wave2, 0xFF32, 0x00, 0x00, read_wave_ram, write_wave_ram),
+    (wave3, 0xFF33, 0xFF, 0xFF, read_wave_ram, write_wave_ram),
+    (wave4, 0xFF34, 0x00, 0x00, read_wave_ram, write_wave_ram),
+    (wave5, 0xFF35, 0xFF, 0xFF, read_wave_ram, write_wave_ram),
+    (wave6, 0xFF36, 0x00, 0x00, read_wave_ram, write_wave_ram),
+    (wave7, 0xFF37, 0xFF, 0xFF, read_wave_ram, write_wave_ram),
+    (wave8, 0xFF38, 0x00, 0x00, read_wave_ram, write_wave_ram),
+    (wave9, 0xFF39, 0xFF, 0xFF, read_wave_ram, write_wave_ram),
+    (wave10, 0xFF3A, 0x00, 0x00, read_wave_ram, write_wave_ram),
+    (wave11, 0xFF3B, 0xFF, 0xFF, read_wave_ram, write_wave_ram),
+    (wave12, 0xFF3C, 0x00, 0x00, read_wave_ram, write_wave_ram),
+    (wave13, 0xFF3D, 0xFF, 0xFF, read_wave_ram, write_wave_ram),
+    (wave14, 0xFF3E, 0x00, 0x00, read_wave_ram, write_wave_ram),
+    (wave15, 0xFF3F, 0xFF, 0xFF, read_wave_ram, write_wave_ram),
     (
         lcdc,
         0xFF40,
@@ -874,7 +992,7 @@ define_registers!(
         0x85,
         VARIABLE,
         read_lcd_stat_impl,
-        write_register_raw
+        write_lcd_stat_impl
     ),
     (
         scy,
@@ -924,7 +1042,7 @@ define_registers!(
         0xFC,
         0xFC,
         read_register_raw,
-        write_register_raw
+        write_dmg_palette_impl
     ),
     (
         obp0,
@@ -932,7 +1050,7 @@ define_registers!(
         UNITIALIZED,
         UNITIALIZED,
         read_register_raw,
-        write_register_raw
+        write_dmg_palette_impl
     ),
     (
         obp1,
@@ -940,7 +1058,7 @@ define_registers!(
         UNITIALIZED,
         UNITIALIZED,
         read_register_raw,
-        write_register_raw
+        write_dmg_palette_impl
     ),
     (
         key0,
@@ -958,7 +1076,14 @@ define_registers!(
         read_key1_impl,
         write_key1_impl
     ),
-    (vbk, 0xFF4F, NONE, 0xFE, read_register_raw, write_vbk_impl),
+    (
+        vbk,
+        0xFF4F,
+        NONE,
+        0xFE,
+        read_cgb_bank_register,
+        write_vbk_impl
+    ),
     (bank, 0xFF50, NONE, NONE, read_register_raw, write_bank_impl),
     (
         hdma1,
@@ -1032,6 +1157,268 @@ define_registers!(
         read_ocpd_impl,
         write_ocpd_impl
     ),
-    (opri, 0xFF6C, NONE, 0x00, read_register_raw, write_opri_impl),
-    (wbk, 0xFF70, NONE, 0xF8, read_register_raw, write_wbk_impl),
+    (opri, 0xFF6C, NONE, 0x00, read_opri_impl, write_opri_impl),
+    (
+        wbk,
+        0xFF70,
+        NONE,
+        0xF8,
+        read_cgb_bank_register,
+        write_wbk_impl
+    ),
 );
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        address_space::{IO_REGISTERS_END, IO_REGISTERS_START},
+        machine::{CompatMode, Machine},
+        test_utils::{
+            build_cgb_test_rom, build_test_rom, new_test_emulator, new_test_emulator_builder,
+            with_large_stack,
+        },
+    };
+
+    /// DMG register values after the boot ROM completes, from Pan Docs. All other registers in the
+    /// IO range do not exist on DMG and read as 0xFF.
+    #[rustfmt::skip]
+    const DMG_POST_BOOT_VALUES: [(u16, u8); 42] = [
+        (0xFF00, 0xCF), // P1
+        (0xFF01, 0x00), // SB
+        (0xFF02, 0x7E), // SC
+        (0xFF04, 0xAB), // DIV
+        (0xFF05, 0x00), // TIMA
+        (0xFF06, 0x00), // TMA
+        (0xFF07, 0xF8), // TAC
+        (0xFF0F, 0xE1), // IF
+        (0xFF10, 0x80), // NR10
+        (0xFF11, 0xBF), // NR11
+        (0xFF12, 0xF3), // NR12
+        (0xFF13, 0xFF), // NR13
+        (0xFF14, 0xBF), // NR14
+        (0xFF16, 0x3F), // NR21
+        (0xFF17, 0x00), // NR22
+        (0xFF18, 0xFF), // NR23
+        (0xFF19, 0xBF), // NR24
+        (0xFF1A, 0x7F), // NR30
+        (0xFF1B, 0xFF), // NR31
+        (0xFF1C, 0x9F), // NR32
+        (0xFF1D, 0xFF), // NR33
+        (0xFF1E, 0xBF), // NR34
+        (0xFF20, 0xFF), // NR41
+        (0xFF21, 0x00), // NR42
+        (0xFF22, 0x00), // NR43
+        (0xFF23, 0xBF), // NR44
+        (0xFF24, 0x77), // NR50
+        (0xFF25, 0xF3), // NR51
+        (0xFF26, 0xF1), // NR52
+        (0xFF40, 0x91), // LCDC
+        (0xFF41, 0x85), // STAT
+        (0xFF42, 0x00), // SCY
+        (0xFF43, 0x00), // SCX
+        (0xFF44, 0x00), // LY
+        (0xFF45, 0x00), // LYC
+        (0xFF46, 0xFF), // DMA
+        (0xFF47, 0xFC), // BGP
+        (0xFF4A, 0x00), // WY
+        (0xFF4B, 0x00), // WX
+        (0xFF4D, 0xFF), // KEY1
+        (0xFF4F, 0xFF), // VBK
+        (0xFF70, 0xFF), // WBK
+    ];
+
+    /// Registers whose post-boot values are not documented: the object palettes are left
+    /// uninitialized and wave RAM contents are random on DMG.
+    fn is_undocumented_on_dmg(address: u16) -> bool {
+        matches!(address, 0xFF48 | 0xFF49 | 0xFF30..=0xFF3F)
+    }
+
+    #[test]
+    fn dmg_post_boot_register_values() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &[]);
+
+            for address in IO_REGISTERS_START..IO_REGISTERS_END {
+                if is_undocumented_on_dmg(address) {
+                    continue;
+                }
+
+                let expected = DMG_POST_BOOT_VALUES
+                    .iter()
+                    .find(|(register, _)| *register == address)
+                    .map_or(0xFF, |(_, value)| *value);
+
+                assert_eq!(
+                    emulator.read_register_raw(address),
+                    expected,
+                    "register {address:04X}"
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn apu_power_off_clears_registers() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &[]);
+
+            // Unreadable bits are set on reads
+            emulator.write_address(0xFF11, 0x5A);
+            emulator.write_address(0xFF1A, 0x80);
+            emulator.write_address(0xFF30, 0x12);
+            assert_eq!(emulator.read_address(0xFF11), 0x7F);
+            assert_eq!(emulator.read_address(0xFF13), 0xFF);
+            assert_eq!(emulator.read_address(0xFF1A), 0xFF);
+
+            // Turning the APU off clears all registers except wave RAM
+            emulator.write_address(0xFF26, 0x00);
+            assert_eq!(emulator.read_address(0xFF11), 0x3F);
+            assert_eq!(emulator.read_address(0xFF1A), 0x7F);
+            assert_eq!(emulator.read_address(0xFF26), 0x70);
+            assert_eq!(emulator.read_address(0xFF30), 0x12);
+
+            // Writes are ignored while the APU is off, including the duty bits of NR11
+            emulator.write_address(0xFF11, 0xC0);
+            emulator.write_address(0xFF12, 0xF3);
+            emulator.write_address(0xFF24, 0x77);
+            assert_eq!(emulator.read_address(0xFF11), 0x3F);
+            assert_eq!(emulator.read_address(0xFF12), 0x00);
+            assert_eq!(emulator.read_address(0xFF24), 0x00);
+
+            // Turning the APU back on allows writes again
+            emulator.write_address(0xFF26, 0x80);
+            emulator.write_address(0xFF12, 0xF3);
+            assert_eq!(emulator.read_address(0xFF12), 0xF3);
+            assert_eq!(emulator.read_address(0xFF26), 0xF0);
+        });
+    }
+
+    #[test]
+    fn bank_registers_only_switch_banks_in_cgb_mode() {
+        with_large_stack(|| {
+            let cases = [
+                (Machine::Dmg, build_test_rom(0x00, 0x00, 0x00, &[]), false),
+                (Machine::Cgb, build_test_rom(0x00, 0x00, 0x00, &[]), false),
+                (
+                    Machine::Cgb,
+                    build_cgb_test_rom(0x00, 0x00, 0x00, &[]),
+                    true,
+                ),
+            ];
+
+            for (machine, rom, in_cgb_mode) in cases {
+                let mut emulator = new_test_emulator_builder(machine, rom).build().unwrap();
+                emulator.emulate_boot_sequence();
+                assert_eq!(emulator.in_cgb_mode(), in_cgb_mode);
+
+                emulator.write_address(0xD000, 0x11);
+                emulator.write_address(0x8000, 0x22);
+
+                emulator.write_address(0xFF70, 0x03);
+                emulator.write_address(0xD000, 0x33);
+                emulator.write_address(0xFF4F, 0x01);
+                emulator.write_address(0x8000, 0x44);
+
+                if in_cgb_mode {
+                    assert_eq!(emulator.read_address(0xFF70), 0xFB);
+                    assert_eq!(emulator.read_address(0xFF4F), 0xFF);
+
+                    // Bank 0 selects bank 1
+                    emulator.write_address(0xFF70, 0x00);
+                    emulator.write_address(0xFF4F, 0x00);
+                    assert_eq!(emulator.read_address(0xFF70), 0xF9);
+                    assert_eq!(emulator.read_address(0xFF4F), 0xFE);
+                    assert_eq!(emulator.read_address(0xD000), 0x11);
+                    assert_eq!(emulator.read_address(0x8000), 0x22);
+
+                    emulator.write_address(0xFF70, 0x0B);
+                    emulator.write_address(0xFF4F, 0xFF);
+                    assert_eq!(emulator.read_address(0xFF70), 0xFB);
+                    assert_eq!(emulator.read_address(0xD000), 0x33);
+                    assert_eq!(emulator.read_address(0x8000), 0x44);
+                } else {
+                    // Registers do not exist and banks never switch
+                    assert_eq!(emulator.read_address(0xFF70), 0xFF);
+                    assert_eq!(emulator.read_address(0xFF4F), 0xFF);
+                    assert_eq!(emulator.read_address(0xD000), 0x33);
+                    assert_eq!(emulator.read_address(0x8000), 0x44);
+
+                    emulator.write_address(0xFF70, 0x00);
+                    emulator.write_address(0xFF4F, 0x00);
+                    assert_eq!(emulator.read_address(0xD000), 0x33);
+                    assert_eq!(emulator.read_address(0x8000), 0x44);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn vram_dma_only_runs_in_cgb_mode() {
+        with_large_stack(|| {
+            let cases = [
+                (build_test_rom(0x00, 0x00, 0x00, &[]), CompatMode::DmgCompat),
+                (build_cgb_test_rom(0x00, 0x00, 0x00, &[]), CompatMode::Cgb),
+            ];
+
+            for (rom, compat_mode) in cases {
+                let mut emulator = new_test_emulator_builder(Machine::Cgb, rom)
+                    .build()
+                    .unwrap();
+                emulator.emulate_boot_sequence();
+                assert_eq!(emulator.compat_mode(), compat_mode);
+
+                // General purpose transfer of one block from C000 to 8000
+                emulator.write_address(0xC000, 0xAA);
+                emulator.write_address(0xFF51, 0xC0);
+                emulator.write_address(0xFF52, 0x00);
+                emulator.write_address(0xFF53, 0x00);
+                emulator.write_address(0xFF54, 0x00);
+                emulator.write_address(0xFF55, 0x00);
+
+                let expected = if compat_mode.has_cgb_registers() {
+                    0xAA
+                } else {
+                    0x00
+                };
+                assert_eq!(emulator.read_address(0x8000), expected);
+            }
+        });
+    }
+
+    #[test]
+    fn key0_selects_compat_mode() {
+        with_large_stack(|| {
+            let cases = [
+                (0x80, CompatMode::Cgb),
+                (0x04, CompatMode::DmgCompat),
+                (0x08, CompatMode::Pgb),
+                (0x0C, CompatMode::Pgb),
+            ];
+
+            for (key0, compat_mode) in cases {
+                let rom = build_test_rom(0x00, 0x00, 0x00, &[]);
+                let mut emulator = new_test_emulator_builder(Machine::Cgb, rom)
+                    .build()
+                    .unwrap();
+                emulator.set_is_booting(true);
+                emulator.write_address(0xFF4C, key0);
+                assert_eq!(emulator.compat_mode(), compat_mode);
+                assert_eq!(emulator.read_address(0xFF4C), 0xF3 | (key0 & 0x0C));
+
+                // KEY0 is locked after booting
+                emulator.set_is_booting(false);
+                emulator.write_address(0xFF4C, 0x00);
+                assert_eq!(emulator.compat_mode(), compat_mode);
+            }
+
+            // A DMG has no KEY0
+            let rom = build_cgb_test_rom(0x00, 0x00, 0x00, &[]);
+            let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                .build()
+                .unwrap();
+            emulator.set_is_booting(true);
+            emulator.write_address(0xFF4C, 0x80);
+            assert_eq!(emulator.compat_mode(), CompatMode::Dmg);
+        });
+    }
+}
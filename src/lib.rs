@@ -1,14 +1,46 @@
 mod address_space;
 pub mod audio;
 pub mod cartridge;
+pub mod compat_palettes;
 mod cpu;
+pub mod debugger;
+pub mod determinism;
+mod diagnostics;
+pub mod disassembler;
 pub mod emulator;
+pub mod error;
+mod frame_pacer;
+pub mod frame_timing;
 mod frame_tracker;
 pub mod gui;
 mod io_registers;
+mod licensee;
 pub mod machine;
 mod mbc;
+pub mod object_priority;
 pub mod options;
 pub mod ppu;
+pub mod ppu_dump;
+pub mod ram_init;
 mod registers;
+pub mod rewind;
+pub mod rom_file;
+pub mod rom_library;
+pub mod safe_mode;
+pub mod save_compat;
 pub mod save_file;
+pub mod save_paths;
+pub mod screen_palette;
+pub mod shared_stats;
+pub mod smoke_test;
+pub mod state;
+pub mod symbols;
+pub mod test_runner;
+#[cfg(test)]
+mod test_utils;
+pub mod thread_priority;
+pub mod tile_map_export;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod version;
+pub mod watchdog;
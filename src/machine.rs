@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::address_space::{SINGLE_VRAM_BANK_SIZE, SINGLE_WORK_RAM_BANK_SIZE};
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Machine {
     /// The original GameBoy
     Dmg,
@@ -10,6 +10,50 @@ pub enum Machine {
     Cgb,
 }
 
+/// The mode a machine runs games in. A CGB picks its mode while booting by writing bits 2 and 3 of
+/// KEY0, which cannot be changed afterwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompatMode {
+    /// A DMG, which only has the one mode
+    Dmg,
+    /// A CGB running a CGB game with all CGB features available
+    Cgb,
+    /// A CGB running a DMG game. The CGB registers are inert, tiles have no attributes, and the DMG
+    /// palette registers pick colors from the palettes that the boot ROM set up.
+    DmgCompat,
+    /// A CGB whose LCD is driven externally. The external LCD is not emulated, so this otherwise
+    /// behaves like CGB mode.
+    Pgb,
+}
+
+impl CompatMode {
+    /// The mode selected by a value written to KEY0 on a CGB.
+    pub fn from_key0(value: u8) -> Self {
+        match (value >> 2) & 0x03 {
+            0 => CompatMode::Cgb,
+            1 => CompatMode::DmgCompat,
+            _ => CompatMode::Pgb,
+        }
+    }
+
+    /// Whether the CGB-only registers work: VRAM and WRAM banking, VRAM DMA, the speed switch, and
+    /// the fast serial clock.
+    pub fn has_cgb_registers(&self) -> bool {
+        matches!(self, CompatMode::Cgb | CompatMode::Pgb)
+    }
+
+    /// Whether tiles are drawn with their attributes and colored by the CGB palettes selected in
+    /// the attributes.
+    pub fn has_cgb_rendering(&self) -> bool {
+        matches!(self, CompatMode::Cgb | CompatMode::Pgb)
+    }
+
+    /// Whether the DMG palette registers index into CGB palettes instead of DMG shades.
+    pub fn uses_compat_palettes(&self) -> bool {
+        matches!(self, CompatMode::DmgCompat)
+    }
+}
+
 impl Machine {
     pub const fn vram_size(&self) -> usize {
         match self {
@@ -18,6 +62,22 @@ impl Machine {
         }
     }
 
+    /// Sizes of the BIOS dumps that can be used with this machine. CGB dumps may omit the unused
+    /// range at 0100-01FF where the cartridge header is visible while booting.
+    pub const fn bios_sizes(&self) -> &'static [usize] {
+        match self {
+            Machine::Dmg => &[0x100],
+            Machine::Cgb => &[0x900, 0x800],
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Machine::Dmg => "DMG",
+            Machine::Cgb => "CGB",
+        }
+    }
+
     pub const fn wram_size(&self) -> usize {
         match self {
             Machine::Dmg => 2 * SINGLE_WORK_RAM_BANK_SIZE,
@@ -2,107 +2,507 @@ use clap::Parser;
 use gbcemu::{
     audio::DefaultSystemAudioOutput,
     cartridge::Cartridge,
-    emulator::{EmulatorBuilder, EmulatorRef, SharedInputAdapter},
-    gui::shell::start_emulator_shell_app,
+    emulator::{
+        COMMANDS_CHANNEL_CAPACITY, Command, EmulatorBuilder, EmulatorRef, SharedInputAdapter,
+    },
+    error::Error,
+    gui::shell::{ask_to_resume_dialog, show_load_error_dialog, start_emulator_shell_app},
     machine::Machine,
     options::{Args, Options},
-    save_file::SAVE_FILE_EXTENSION,
+    ppu_dump,
+    rom_file::read_rom_file,
+    safe_mode::{CrashMarker, PendingRestart},
+    save_file::{SAVE_FILE_EXTENSION, SaveFile, SaveFormat, load_raw_save},
+    save_paths::{
+        auto_state_path_for_save_file, has_rom_extension, has_zip_extension, save_paths_for_rom,
+    },
+    shared_stats::SharedStats,
+    smoke_test::{self, SmokeTestOutcome},
+    test_runner::find_test_roms,
+    thread_priority::configure_current_thread,
 };
 
 use std::{
-    fs,
+    fs, io,
+    path::Path,
+    process,
     sync::{
         Arc,
-        mpsc::{self, channel},
+        mpsc::{self, Sender, SyncSender, TrySendError, channel, sync_channel},
     },
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-// The only supported ROM file extensions
-const GB_FILE_EXTENSION: &str = ".gb";
-const GBC_FILE_EXTENSION: &str = ".gbc";
+/// How long to wait for the emulator thread to finish after the GUI closes.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 fn main() {
-    let args = Args::parse();
-    let options = Arc::new(Options::from_args(&args));
+    let mut args = Args::parse();
 
-    let (commands_tx, commands_rx) = channel();
+    // A marker left behind by the last run means that it crashed, possibly because of one of its
+    // options, so start in safe mode with risky options ignored
+    let crash_marker = CrashMarker::in_platform_data_dir(&args.rom_or_save);
+    let in_safe_mode = crash_marker.as_ref().is_some_and(|marker| marker.exists());
+    let suppressed_options = if in_safe_mode {
+        let suppressed_options = args.suppress_risky_options();
+        println!("Emulator did not shut down cleanly last time, starting in safe mode");
+        if !suppressed_options.is_empty() {
+            println!("Ignoring options: {}", suppressed_options.join(" "));
+        }
+        suppressed_options
+    } else {
+        vec![]
+    };
 
-    let input_adapter = SharedInputAdapter::new(commands_rx);
+    let options = Arc::new(Options {
+        suppressed_options,
+        ..Options::from_args(&args)
+    });
 
-    let (emulator_thread, emulator) = start_emulator_thread(&args, options.clone(), input_adapter);
+    // Print the seed so that runs with randomized RAM can be reproduced
+    if let Some(seed) = options.ram_init.seed() {
+        println!("RAM initialized with random seed {}", seed);
+    }
 
-    if !args.headless && !args.dump_rom_info {
-        start_emulator_shell_app(emulator, commands_tx);
-    } else {
+    if let Some(rom_dir) = &args.smoke_test_dir {
+        run_smoke_tests(rom_dir, args.seconds, &args.smoke_test_out);
+        return;
+    }
+
+    if let Some(screenshot_args) = &args.screenshot_after {
+        start_screenshot_thread(&args, options, screenshot_args)
+            .join()
+            .unwrap();
+        return;
+    }
+
+    if args.dump_rom_info || args.dump_rom_info_json {
+        start_dump_rom_info_thread(&args, options).join().unwrap();
+        return;
+    }
+
+    #[cfg(feature = "tui")]
+    if args.tui {
+        start_tui_thread(&args, options).join().unwrap();
+        return;
+    }
+
+    let (commands_tx, commands_rx) = sync_channel(COMMANDS_CHANNEL_CAPACITY);
+    let (events_tx, events_rx) = channel();
+
+    let input_adapter = SharedInputAdapter::new(commands_rx, events_tx);
+
+    // Headless runs are usually stopped by killing the process, so only GUI runs are marked
+    let is_gui = !args.headless;
+    let crash_marker = crash_marker.filter(|_| is_gui);
+
+    if let Some(crash_marker) = &crash_marker
+        && let Err(error) = crash_marker.write()
+    {
+        eprintln!(
+            "Could not write crash marker {}: {}",
+            crash_marker.path().display(),
+            error
+        );
+    }
+
+    // The auto-state may be from the session that crashed, so never offer it in safe mode
+    let offer_resume = options.resume_on_launch && !in_safe_mode;
+    let shared_stats = Arc::new(SharedStats::new());
+    let (emulator_thread, emulator, resume_tx) = start_emulator_thread(
+        &args,
+        options.clone(),
+        input_adapter,
+        shared_stats.clone(),
+        offer_resume,
+    );
+
+    let (emulator, has_auto_state) = match emulator {
+        Ok(emulator) => emulator,
+        Err(error) => {
+            // Failing to load is not a crash, so the next run should not start in safe mode
+            if let Some(crash_marker) = &crash_marker {
+                let _ = crash_marker.remove();
+            }
+
+            if is_gui {
+                show_load_error_dialog(&args.rom_or_save, &error);
+            }
+
+            exit_with_load_error(&args.rom_or_save, &error);
+        }
+    };
+
+    // The emulator thread waits for an answer before running whenever there is an auto-state
+    if has_auto_state {
+        let resume = args.resume || (is_gui && ask_to_resume_dialog(&args.rom_or_save));
+        let _ = resume_tx.send(resume);
+    }
+
+    if !is_gui {
         emulator_thread.join().unwrap();
+        return;
+    }
+
+    let pending_restart = PendingRestart::new();
+    start_emulator_shell_app(
+        emulator,
+        commands_tx.clone(),
+        events_rx,
+        shared_stats,
+        pending_restart.clone(),
+    );
+
+    if !shut_down_emulator_thread(emulator_thread, &commands_tx) {
+        return;
+    }
+
+    if let Some(crash_marker) = &crash_marker
+        && let Err(error) = crash_marker.remove()
+    {
+        eprintln!(
+            "Could not remove crash marker {}: {}",
+            crash_marker.path().display(),
+            error
+        );
     }
+
+    // Only start the new instance once the save file has been flushed and the crash marker removed
+    if let Err(error) = pending_restart.spawn_if_requested() {
+        eprintln!("Unable to restart: {}", error);
+    }
+}
+
+/// Stop the emulator thread once the GUI has closed, waiting for it to flush the save file and
+/// close the audio output before the process exits. Returns whether the emulator thread shut down
+/// cleanly.
+fn shut_down_emulator_thread(
+    emulator_thread: JoinHandle<()>,
+    commands_tx: &SyncSender<Command>,
+) -> bool {
+    println!("Shutting down emulator");
+
+    // The emulator thread may have already exited, e.g. if it panicked. If the commands channel is
+    // full, keep trying to send the shutdown until the emulator drains it or the timeout passes.
+    let mut shutdown_command = Some(Command::Shutdown);
+
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while !emulator_thread.is_finished() {
+        if let Some(command) = shutdown_command.take()
+            && let Err(TrySendError::Full(command)) = commands_tx.try_send(command)
+        {
+            shutdown_command = Some(command);
+        }
+
+        if Instant::now() >= deadline {
+            eprintln!(
+                "Emulator did not shut down within {} seconds, exiting anyway",
+                SHUTDOWN_TIMEOUT.as_secs()
+            );
+            return false;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    if emulator_thread.join().is_err() {
+        eprintln!("Emulator thread panicked");
+        return false;
+    }
+
+    println!("Emulator shut down");
+    true
 }
 
+/// A loaded emulator, and whether it has an auto-state that can be resumed from.
+type LoadedEmulator = (EmulatorRef, bool);
+
+/// Start the emulator thread. If resuming is offered and there is an auto-state for the ROM, the
+/// thread waits to be sent whether to resume from it before it starts running.
 fn start_emulator_thread(
     args: &Args,
     options: Arc<Options>,
     input_adapter: SharedInputAdapter,
-) -> (JoinHandle<()>, EmulatorRef) {
+    shared_stats: Arc<SharedStats>,
+    offer_resume: bool,
+) -> (JoinHandle<()>, Result<LoadedEmulator, Error>, Sender<bool>) {
     let machine = if args.cgb { Machine::Cgb } else { Machine::Dmg };
     let rom_or_save_path = args.rom_or_save.clone();
-    let dump_rom_info = args.dump_rom_info;
     let bios_path = args.bios.clone();
 
     let (emulator_send, emulator_recv) = mpsc::channel();
+    let (resume_tx, resume_rx) = mpsc::channel();
 
     let join_handle = spawn_emulator_thread(move || {
-        let mut emulator_builder = if rom_or_save_path.ends_with(SAVE_FILE_EXTENSION) {
-            let save_file_bytes = fs::read(&rom_or_save_path).expect("Failed to read save file");
-            let save_file = rmp_serde::from_slice(&save_file_bytes)
-                .expect("Could not read save file, save file format may have changed");
-
-            EmulatorBuilder::from_saved_cartidge(save_file, machine)
-                .with_save_file_path(rom_or_save_path)
-        } else if rom_or_save_path.ends_with(GB_FILE_EXTENSION)
-            || rom_or_save_path.ends_with(GBC_FILE_EXTENSION)
-        {
-            let rom_bytes = fs::read(&rom_or_save_path).expect("Failed to read ROM");
-            let cartridge = Cartridge::new_from_rom_bytes(rom_bytes);
+        configure_current_thread("emulator", options.high_priority, options.pin_core);
+
+        let audio_output = DefaultSystemAudioOutput::new(
+            options.audio_latency_frames,
+            options.turbo_audio,
+            options.high_priority,
+            shared_stats.clone(),
+        );
+
+        let emulator = new_emulator_builder(&rom_or_save_path, machine, bios_path, options)
+            .and_then(|emulator_builder| {
+                emulator_builder
+                    .with_input_adapter(input_adapter)
+                    .with_audio_output(Box::new(audio_output))
+                    .with_shared_stats(shared_stats)
+                    .build()
+            });
+
+        let mut emulator = match emulator {
+            Ok(emulator) => emulator,
+            Err(error) => {
+                emulator_send.send(Err(error)).unwrap();
+                return;
+            }
+        };
+
+        let has_auto_state = offer_resume && emulator.has_auto_state();
+        emulator_send
+            .send(Ok((emulator.to_ref(), has_auto_state)))
+            .unwrap();
+
+        if has_auto_state && resume_rx.recv().unwrap_or(false) {
+            emulator.resume_from_auto_state_on_power_on();
+        }
+
+        // Only returns after a shutdown, once the save file has been flushed
+        emulator.run();
+        println!("Emulator stopped");
+
+        // Close the audio output before the thread exits, so that the process does not exit while
+        // the audio output is still playing
+        drop(emulator);
+        println!("Audio output closed");
+    });
+
+    let emulator_ref = emulator_recv.recv().unwrap();
 
-            let save_file_path = rom_or_save_path
-                .trim_end_matches(GB_FILE_EXTENSION)
-                .trim_end_matches(GBC_FILE_EXTENSION)
-                .to_string()
-                + SAVE_FILE_EXTENSION;
+    (join_handle, emulator_ref, resume_tx)
+}
 
-            EmulatorBuilder::new_cartridge(cartridge, machine).with_save_file_path(save_file_path)
+/// Load a raw .sav file into the cartridge if it exists. Returns whether the raw save file can be
+/// written back, which is not the case if it exists but could not be loaded, so that it is never
+/// overwritten.
+fn load_raw_save_file(cartridge: &mut Cartridge, raw_save_file_path: &str) -> bool {
+    let bytes = match fs::read(raw_save_file_path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return true,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", raw_save_file_path, error);
+            return false;
+        }
+    };
+
+    match load_raw_save(cartridge, &bytes) {
+        Ok(()) => true,
+        Err(error) => {
+            eprintln!("Could not load {}: {}", raw_save_file_path, error);
+            false
+        }
+    }
+}
+
+/// Create a builder for the ROM or save file at the given path, configured by the options.
+fn new_emulator_builder(
+    rom_or_save_path: &str,
+    machine: Machine,
+    bios_path: Option<String>,
+    options: Arc<Options>,
+) -> Result<EmulatorBuilder, Error> {
+    let mut emulator_builder = if rom_or_save_path.ends_with(SAVE_FILE_EXTENSION) {
+        let save_file = SaveFile::from_bytes(&fs::read(rom_or_save_path)?)?;
+
+        let emulator_builder = EmulatorBuilder::from_saved_cartidge(save_file, machine)?
+            .with_save_file_path(rom_or_save_path.to_string());
+
+        if options.resume_on_launch {
+            emulator_builder.with_auto_state_path(auto_state_path_for_save_file(rom_or_save_path))
         } else {
-            panic!(
-                "Unsupported file type, file must have {}, {}, or {} extension",
-                GB_FILE_EXTENSION, GBC_FILE_EXTENSION, SAVE_FILE_EXTENSION
+            emulator_builder
+        }
+    } else {
+        let rom_bytes = read_rom_file(rom_or_save_path)?;
+
+        // Files without a ROM or zip extension are only loaded if their contents look like a ROM
+        if !has_rom_extension(rom_or_save_path)
+            && !has_zip_extension(rom_or_save_path)
+            && !Cartridge::looks_like_rom(&rom_bytes)
+        {
+            return Err(Error::NotARom);
+        }
+
+        let mut cartridge = Cartridge::new_from_rom_bytes(rom_bytes)?;
+
+        // Hardware never checks the global checksum, so a mismatch is only worth a warning
+        let header = cartridge.header();
+        if !header.verify_global_checksum() {
+            eprintln!(
+                "Warning: global checksum is 0x{:04X} but the ROM sums to 0x{:04X}",
+                header.global_checksum,
+                header.computed_global_checksum()
             );
+        }
+
+        let save_paths = save_paths_for_rom(rom_or_save_path, options.save_dir.as_deref())?;
+
+        let raw_save_file_path = match options.save_format {
+            SaveFormat::Native => None,
+            SaveFormat::Raw => load_raw_save_file(&mut cartridge, &save_paths.raw_save_file_path)
+                .then_some(save_paths.raw_save_file_path),
         };
 
-        emulator_builder = emulator_builder
-            .with_options(options)
-            .with_input_adapter(input_adapter)
-            .with_audio_output(Box::new(DefaultSystemAudioOutput::new()));
+        let mut emulator_builder = EmulatorBuilder::new_cartridge(cartridge, machine)
+            .with_save_file_path(save_paths.save_file_path);
+
+        if options.resume_on_launch {
+            emulator_builder = emulator_builder.with_auto_state_path(save_paths.auto_state_path);
+        }
 
-        if let Some(bios_path) = bios_path {
-            emulator_builder = emulator_builder.with_bios_path(bios_path);
+        match raw_save_file_path {
+            Some(raw_save_file_path) => {
+                emulator_builder.with_raw_save_file_path(raw_save_file_path)
+            }
+            None => emulator_builder,
         }
+    };
+
+    emulator_builder = emulator_builder
+        .with_seed(options.seed)
+        .with_ram_init(options.ram_init)
+        .with_options(options);
+
+    if let Some(bios_path) = bios_path {
+        emulator_builder = emulator_builder.with_bios_path(bios_path);
+    }
 
-        let mut emulator = emulator_builder.build();
+    Ok(emulator_builder)
+}
+
+/// Print the header of the ROM, or of the ROM in a save file, as a table or as JSON.
+fn start_dump_rom_info_thread(args: &Args, options: Arc<Options>) -> JoinHandle<()> {
+    let machine = if args.cgb { Machine::Cgb } else { Machine::Dmg };
+    let rom_or_save_path = args.rom_or_save.clone();
+    let bios_path = args.bios.clone();
+    let as_json = args.dump_rom_info_json;
+
+    spawn_emulator_thread(move || {
+        let emulator = new_emulator_builder(&rom_or_save_path, machine, bios_path, options)
+            .and_then(|emulator_builder| emulator_builder.build())
+            .unwrap_or_else(|error| exit_with_load_error(&rom_or_save_path, &error));
 
-        if dump_rom_info {
-            println!("{:?}", emulator.cartridge());
-            return;
+        let header = emulator.cartridge().header();
+        if as_json {
+            print!("{}", header.to_json());
+        } else {
+            print!("{}", header);
         }
+    })
+}
 
-        emulator_send.send(emulator.to_ref()).unwrap();
+fn exit_with_load_error(rom_or_save_path: &str, error: &Error) -> ! {
+    eprintln!("Could not load {}: {}", rom_or_save_path, error);
+    process::exit(1);
+}
 
-        emulator.run();
+/// Run a fixed number of frames on the emulator thread with no audio output or input adapter, then
+/// write a screenshot and exit.
+fn start_screenshot_thread(
+    args: &Args,
+    options: Arc<Options>,
+    screenshot_args: &[String],
+) -> JoinHandle<()> {
+    let machine = if args.cgb { Machine::Cgb } else { Machine::Dmg };
+    let rom_or_save_path = args.rom_or_save.clone();
+    let bios_path = args.bios.clone();
+
+    let num_frames = screenshot_args[0].parse::<usize>().unwrap_or_else(|_| {
+        eprintln!("Invalid number of frames: {}", screenshot_args[0]);
+        process::exit(1);
     });
+    let screenshot_path = screenshot_args[1].clone();
 
-    let emulator_ref = emulator_recv.recv().unwrap();
+    spawn_emulator_thread(move || {
+        let mut emulator = new_emulator_builder(&rom_or_save_path, machine, bios_path, options)
+            .and_then(EmulatorBuilder::build)
+            .unwrap_or_else(|error| exit_with_load_error(&rom_or_save_path, &error));
+
+        emulator.power_on();
+        emulator.run_frames(num_frames);
+
+        if let Err(error) = ppu_dump::save_screenshot(&emulator, Path::new(&screenshot_path)) {
+            eprintln!("Could not write {}: {}", screenshot_path, error);
+            process::exit(1);
+        }
+    })
+}
+
+/// Run every ROM in a directory headless for the given number of emulated seconds, then write a
+/// report. Exits with an error if any ROM panicked or locked up.
+fn run_smoke_tests(rom_dir: &Path, seconds: f64, out_dir: &Path) {
+    let rom_paths = find_test_roms(rom_dir);
+    if rom_paths.is_empty() {
+        eprintln!("No ROMs found in {}", rom_dir.display());
+        process::exit(1);
+    }
+
+    let num_frames = smoke_test::frames_for_seconds(seconds);
+    let num_workers = smoke_test::default_num_workers();
+    println!(
+        "Running {} ROMs for {} frames each on {} threads",
+        rom_paths.len(),
+        num_frames,
+        num_workers
+    );
 
-    (join_handle, emulator_ref)
+    let results = smoke_test::run_smoke_tests(&rom_paths, num_frames, num_workers, |result| {
+        println!("{}: {}", result.rom_path.display(), result.outcome)
+    });
+
+    match smoke_test::write_report(&results, num_frames, rom_dir, out_dir) {
+        Ok(report_path) => println!("Wrote report to {}", report_path.display()),
+        Err(error) => {
+            eprintln!("Could not write report to {}: {}", out_dir.display(), error);
+            process::exit(1);
+        }
+    }
+
+    let num_failed = results
+        .iter()
+        .filter(|result| result.outcome != SmokeTestOutcome::Clean)
+        .count();
+    if num_failed > 0 {
+        eprintln!(
+            "{} of {} ROMs did not run cleanly",
+            num_failed,
+            results.len()
+        );
+        process::exit(1);
+    }
+}
+
+/// Run the terminal frontend on the emulator thread, with no audio output or input adapter.
+#[cfg(feature = "tui")]
+fn start_tui_thread(args: &Args, options: Arc<Options>) -> JoinHandle<()> {
+    let machine = if args.cgb { Machine::Cgb } else { Machine::Dmg };
+    let rom_or_save_path = args.rom_or_save.clone();
+    let bios_path = args.bios.clone();
+
+    spawn_emulator_thread(move || {
+        configure_current_thread("emulator", options.high_priority, options.pin_core);
+
+        let mut emulator = new_emulator_builder(&rom_or_save_path, machine, bios_path, options)
+            .and_then(EmulatorBuilder::build)
+            .unwrap_or_else(|error| exit_with_load_error(&rom_or_save_path, &error));
+
+        if let Err(error) = gbcemu::tui::run_tui(&mut emulator) {
+            eprintln!("Terminal error: {}", error);
+        }
+    })
 }
 
 fn spawn_emulator_thread(f: impl FnOnce() + Send + 'static) -> thread::JoinHandle<()> {
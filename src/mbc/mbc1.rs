@@ -5,7 +5,7 @@ use crate::{
         Address, EXTERNAL_RAM_START, FIRST_ROM_BANK_END, ROM_BANK_SIZE,
         SINGLE_EXTERNAL_RAM_BANK_SIZE,
     },
-    mbc::types::{Location, Mbc, MbcKind, RegisterHandle},
+    mbc::types::{Location, Mbc, MbcDebugInfo, MbcKind, RamSelection, RegisterHandle},
 };
 
 #[derive(Serialize, Deserialize)]
@@ -24,16 +24,37 @@ pub struct Mbc1 {
     is_advanced_banking_mode: bool,
     /// Mask to apply to full ROM bank number to ensure it doesn't exceed available banks
     rom_size_mask: usize,
+    /// Mask to apply to RAM bank number to ensure it doesn't exceed available banks
+    #[serde(default = "default_ram_size_mask")]
+    ram_size_mask: usize,
+    /// Multicart (MBC1M) wiring, where only the low 4 bits of the ROM bank number register are
+    /// connected so that the upper bits select one of four 256KB games.
+    #[serde(default)]
+    is_multicart: bool,
+}
+
+/// Saves from before the RAM size mask was tracked allow all four RAM banks
+fn default_ram_size_mask() -> usize {
+    0x3
 }
 
 impl Mbc1 {
-    pub fn new(rom_size: usize) -> Self {
+    pub fn new(rom_size: usize, ram_size: usize) -> Self {
         Mbc1 {
             is_ram_enabled: false,
             rom_bank_num: 1,
             ram_bank_num_or_upper_bits: 0,
             is_advanced_banking_mode: false,
             rom_size_mask: (rom_size / ROM_BANK_SIZE) - 1,
+            ram_size_mask: (ram_size / SINGLE_EXTERNAL_RAM_BANK_SIZE) - 1,
+            is_multicart: false,
+        }
+    }
+
+    pub fn new_multicart(rom_size: usize, ram_size: usize) -> Self {
+        Mbc1 {
+            is_multicart: true,
+            ..Self::new(rom_size, ram_size)
         }
     }
 }
@@ -48,21 +69,33 @@ const BANKING_MODE_SELECT_REGISTER: RegisterHandle = 3;
 const UNITIALIZED_RAM_VALUE_REGISTER: RegisterHandle = 4;
 
 impl Mbc1 {
+    /// Number of bits of the ROM bank number register that are connected, which is also the shift
+    /// for the upper bits.
+    fn rom_bank_num_bits(&self) -> usize {
+        if self.is_multicart { 4 } else { 5 }
+    }
+
+    /// In advanced banking mode the upper bits also bank the 0000-3FFF region on large ROMs.
     fn first_rom_bank_number(&self) -> usize {
         if self.is_advanced_banking_mode {
-            (self.ram_bank_num_or_upper_bits << 5) & self.rom_size_mask
+            (self.ram_bank_num_or_upper_bits << self.rom_bank_num_bits()) & self.rom_size_mask
         } else {
             0
         }
     }
 
+    /// Banks 0x20, 0x40, and 0x60 cannot be mapped at 4000-7FFF since the 0 to 1 remapping only
+    /// looks at the lower 5 bits, so they alias to 0x21, 0x41, and 0x61 instead.
     fn second_rom_bank_number(&self) -> usize {
-        (self.rom_bank_num + (self.ram_bank_num_or_upper_bits << 5)) & self.rom_size_mask
+        let num_bits = self.rom_bank_num_bits();
+        let lower_bits = self.rom_bank_num & ((1 << num_bits) - 1);
+
+        (lower_bits | (self.ram_bank_num_or_upper_bits << num_bits)) & self.rom_size_mask
     }
 
     fn ram_bank_number(&self) -> usize {
         if self.is_advanced_banking_mode {
-            self.ram_bank_num_or_upper_bits
+            self.ram_bank_num_or_upper_bits & self.ram_size_mask
         } else {
             0
         }
@@ -104,7 +137,11 @@ impl Mbc1 {
 #[typetag::serde]
 impl Mbc for Mbc1 {
     fn kind(&self) -> MbcKind {
-        MbcKind::Mbc1
+        if self.is_multicart {
+            MbcKind::Mbc1Multicart
+        } else {
+            MbcKind::Mbc1
+        }
     }
 
     fn map_read_rom_address(&self, addr: Address) -> usize {
@@ -170,4 +207,150 @@ impl Mbc for Mbc1 {
             _ => unreachable!(),
         }
     }
+
+    fn debug_state(&self) -> MbcDebugInfo {
+        let mode = if self.is_advanced_banking_mode {
+            "advanced"
+        } else {
+            "simple"
+        };
+
+        MbcDebugInfo {
+            rom_bank: Some(self.second_rom_bank_number()),
+            ram_selection: Some(RamSelection::Bank(self.ram_bank_number())),
+            is_ram_enabled: Some(self.is_ram_enabled),
+            extras: vec![
+                ("mode", mode.to_string()),
+                (
+                    "bank at 0000",
+                    format!("{:02X}", self.first_rom_bank_number()),
+                ),
+            ],
+            ..MbcDebugInfo::new(self.kind())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        cartridge::Cartridge,
+        mbc::types::{Location, Mbc, MbcKind, RamSelection},
+        test_utils::{build_test_rom, write_mbc_register},
+    };
+
+    use super::Mbc1;
+
+    #[test]
+    fn debug_state_tracks_bank_writes() {
+        // 1MB ROM, which uses the upper bits register for ROM banks 0x20 and above
+        let mut mbc = Mbc1::new(0x100000, 0x8000);
+
+        let state = mbc.debug_state();
+        assert_eq!(state.rom_bank, Some(1));
+        assert_eq!(state.ram_selection, Some(RamSelection::Bank(0)));
+        assert_eq!(state.is_ram_enabled, Some(false));
+
+        write_mbc_register(&mut mbc, 0x0000, 0x0A);
+        write_mbc_register(&mut mbc, 0x2000, 0x05);
+        assert_eq!(mbc.debug_state().rom_bank, Some(0x05));
+        assert_eq!(mbc.debug_state().is_ram_enabled, Some(true));
+
+        // Bank 0 is remapped to bank 1
+        write_mbc_register(&mut mbc, 0x2000, 0x00);
+        assert_eq!(mbc.debug_state().rom_bank, Some(0x01));
+
+        // Upper bits only select the RAM bank in advanced banking mode
+        write_mbc_register(&mut mbc, 0x4000, 0x01);
+        let state = mbc.debug_state();
+        assert_eq!(state.rom_bank, Some(0x21));
+        assert_eq!(state.ram_selection, Some(RamSelection::Bank(0)));
+
+        write_mbc_register(&mut mbc, 0x6000, 0x01);
+        assert_eq!(
+            mbc.debug_state().to_string(),
+            "MBC1, ROM bank 21, RAM bank 01 (enabled), mode: advanced, bank at 0000: 20"
+        );
+    }
+
+    #[test]
+    fn bank_0x20_0x40_0x60_aliasing() {
+        // 2MB ROM with 128 banks
+        let mut mbc = Mbc1::new(0x200000, 0x2000);
+
+        for upper_bits in 1..4 {
+            let bank = upper_bits * 0x20;
+            write_mbc_register(&mut mbc, 0x2000, 0x00);
+            write_mbc_register(&mut mbc, 0x4000, upper_bits as u8);
+
+            // Selecting bank 0x20, 0x40, or 0x60 maps the following bank at 4000-7FFF
+            write_mbc_register(&mut mbc, 0x6000, 0x00);
+            assert_eq!(mbc.map_read_rom_address(0x4000), (bank + 1) * 0x4000);
+            assert_eq!(mbc.map_read_rom_address(0x0000), 0);
+
+            // The bank itself is mapped at 0000-3FFF in advanced banking mode
+            write_mbc_register(&mut mbc, 0x6000, 0x01);
+            assert_eq!(mbc.map_read_rom_address(0x0000), bank * 0x4000);
+            assert_eq!(mbc.map_read_rom_address(0x3FFF), bank * 0x4000 + 0x3FFF);
+            assert_eq!(mbc.map_read_rom_address(0x4000), (bank + 1) * 0x4000);
+        }
+
+        // Only RAM bank 0 exists with 8KB of RAM, even though the upper bits are set
+        write_mbc_register(&mut mbc, 0x0000, 0x0A);
+        assert!(matches!(
+            mbc.map_read_ram_address(0xA000),
+            Location::Address(0)
+        ));
+
+        // The upper bits are ignored on ROMs smaller than 1MB
+        let mut mbc = Mbc1::new(0x80000, 0x8000);
+        write_mbc_register(&mut mbc, 0x4000, 0x02);
+        write_mbc_register(&mut mbc, 0x6000, 0x01);
+        write_mbc_register(&mut mbc, 0x2000, 0x05);
+        assert_eq!(mbc.map_read_rom_address(0x0000), 0);
+        assert_eq!(mbc.map_read_rom_address(0x4000), 0x05 * 0x4000);
+        assert_eq!(mbc.debug_state().ram_selection, Some(RamSelection::Bank(2)));
+    }
+
+    #[test]
+    fn multicart_banking() {
+        let mut mbc = Mbc1::new_multicart(0x100000, 0x2000);
+
+        // Upper bits select the 256KB game and the bank register only has 4 bits
+        write_mbc_register(&mut mbc, 0x4000, 0x01);
+        write_mbc_register(&mut mbc, 0x2000, 0x00);
+        assert_eq!(mbc.debug_state().rom_bank, Some(0x11));
+
+        write_mbc_register(&mut mbc, 0x2000, 0x12);
+        assert_eq!(mbc.debug_state().rom_bank, Some(0x12));
+
+        // Bank 0x10 is not remapped since the remapping checks all 5 bits
+        write_mbc_register(&mut mbc, 0x2000, 0x10);
+        assert_eq!(mbc.debug_state().rom_bank, Some(0x10));
+
+        write_mbc_register(&mut mbc, 0x6000, 0x01);
+        write_mbc_register(&mut mbc, 0x4000, 0x03);
+        assert_eq!(mbc.map_read_rom_address(0x0000), 0x30 * 0x4000);
+    }
+
+    #[test]
+    fn detect_multicart() {
+        // 1MB MBC1 ROM
+        let mut rom = build_test_rom(0x01, 0x05, 0x00, &[]);
+        assert_eq!(
+            Cartridge::new_from_rom_bytes(rom.clone())
+                .unwrap()
+                .mbc()
+                .kind(),
+            MbcKind::Mbc1
+        );
+
+        // Copy the header to the start of the second game
+        let header = rom[0x0100..0x0150].to_vec();
+        rom[0x40100..0x40150].copy_from_slice(&header);
+        assert_eq!(
+            Cartridge::new_from_rom_bytes(rom).unwrap().mbc().kind(),
+            MbcKind::Mbc1Multicart
+        );
+    }
 }
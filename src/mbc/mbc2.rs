@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    address_space::{
+        Address, EXTERNAL_RAM_START, FIRST_ROM_BANK_END, MBC2_RAM_SIZE, ROM_BANK_SIZE,
+    },
+    mbc::types::{Location, Mbc, MbcDebugInfo, MbcKind, RamSelection, RegisterHandle},
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct Mbc2 {
+    /// RAM Enable Register (0000–3FFF with address bit 8 clear)
+    is_ram_enabled: bool,
+    /// ROM Bank Number, 4 bits (0000–3FFF with address bit 8 set)
+    rom_bank_num: usize,
+    /// Mask to apply to full ROM bank number to ensure it doesn't exceed available banks
+    rom_size_mask: usize,
+}
+
+impl Mbc2 {
+    pub fn new(rom_size: usize) -> Self {
+        Mbc2 {
+            is_ram_enabled: false,
+            rom_bank_num: 1,
+            rom_size_mask: (rom_size / ROM_BANK_SIZE) - 1,
+        }
+    }
+}
+
+const RAM_ENABLE_REGISTER: RegisterHandle = 0;
+const ROM_BANK_NUMBER_REGISTER: RegisterHandle = 1;
+
+/// Writes to 4000-7FFF have no effect
+const UNUSED_REGISTER: RegisterHandle = 2;
+
+/// Treat reads or writes to uninitialized RAM value register as reading/writing from a register
+/// that always returns 0xFF/is ignored respectively.
+const UNITIALIZED_RAM_VALUE_REGISTER: RegisterHandle = 3;
+
+impl Mbc2 {
+    fn rom_bank_number(&self) -> usize {
+        self.rom_bank_num & self.rom_size_mask
+    }
+
+    fn map_ram_address(&self, addr: Address) -> Location {
+        if !self.is_ram_enabled {
+            return Location::Register(UNITIALIZED_RAM_VALUE_REGISTER);
+        }
+
+        // Only the low 9 bits of the address are used, so RAM is echoed throughout A000-BFFF
+        Location::Address((addr - EXTERNAL_RAM_START) as usize % MBC2_RAM_SIZE)
+    }
+}
+
+#[typetag::serde]
+impl Mbc for Mbc2 {
+    fn kind(&self) -> MbcKind {
+        MbcKind::Mbc2
+    }
+
+    fn map_read_rom_address(&self, addr: Address) -> usize {
+        if addr < FIRST_ROM_BANK_END {
+            addr as usize
+        } else {
+            let offset_in_bank = (addr - FIRST_ROM_BANK_END) as usize;
+            self.rom_bank_number() * ROM_BANK_SIZE + offset_in_bank
+        }
+    }
+
+    fn map_write_rom_address(&self, addr: Address) -> Location {
+        match addr {
+            // Bit 8 of the address selects the register
+            0..0x4000 if addr & 0x0100 == 0 => Location::Register(RAM_ENABLE_REGISTER),
+            0..0x4000 => Location::Register(ROM_BANK_NUMBER_REGISTER),
+            0x4000..0x8000 => Location::Register(UNUSED_REGISTER),
+            _ => unreachable!(),
+        }
+    }
+
+    fn map_read_ram_address(&self, addr: Address) -> Location {
+        self.map_ram_address(addr)
+    }
+
+    fn map_write_ram_address(&self, addr: Address) -> Location {
+        self.map_ram_address(addr)
+    }
+
+    fn ram_value_mask(&self) -> u8 {
+        0x0F
+    }
+
+    fn read_register(&self, reg: RegisterHandle) -> u8 {
+        match reg {
+            // The only readable register we need to implement is the unitialized RAM value
+            // register, which always returns 0xFF
+            UNITIALIZED_RAM_VALUE_REGISTER => 0xFF,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_register(&mut self, register: RegisterHandle, value: u8) {
+        match register {
+            // RAM is enabled by setting the lower nibble to 0xA, otherwise is disabled
+            RAM_ENABLE_REGISTER => {
+                self.is_ram_enabled = (value & 0xF) == 0xA;
+            }
+            // Only lower 4 bits of the value are used. Enforce that bank number 0 is remapped to 1
+            // when written.
+            ROM_BANK_NUMBER_REGISTER => {
+                let mut bank_num = (value & 0xF) as usize;
+                if bank_num == 0 {
+                    bank_num = 1;
+                }
+                self.rom_bank_num = bank_num;
+            }
+            UNUSED_REGISTER => {}
+            // Writes to unitialized RAM are modeled as a write to a register that is ignored
+            UNITIALIZED_RAM_VALUE_REGISTER => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn debug_state(&self) -> MbcDebugInfo {
+        MbcDebugInfo {
+            rom_bank: Some(self.rom_bank_number()),
+            ram_selection: Some(RamSelection::Bank(0)),
+            is_ram_enabled: Some(self.is_ram_enabled),
+            ..MbcDebugInfo::new(self.kind())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        address_space::MBC2_RAM_SIZE,
+        cartridge::Cartridge,
+        emulator::EmulatorBuilder,
+        machine::Machine,
+        mbc::types::{Mbc, MbcKind},
+        test_utils::{build_test_rom, with_large_stack, write_mbc_register},
+    };
+
+    use super::Mbc2;
+
+    #[test]
+    fn register_selected_by_address_bit_8() {
+        // 256KB ROM, the largest MBC2 supports
+        let mut mbc = Mbc2::new(0x40000);
+
+        // Bit 8 clear writes RAM enable, even when the value looks like a bank number
+        write_mbc_register(&mut mbc, 0x0000, 0x0A);
+        write_mbc_register(&mut mbc, 0x20FF, 0x05);
+        assert_eq!(mbc.debug_state().is_ram_enabled, Some(false));
+        assert_eq!(mbc.debug_state().rom_bank, Some(1));
+
+        // Bit 8 set writes the ROM bank number, including in 0000-1FFF
+        write_mbc_register(&mut mbc, 0x0100, 0x05);
+        assert_eq!(mbc.map_read_rom_address(0x4000), 0x05 * 0x4000);
+        write_mbc_register(&mut mbc, 0x3F00, 0xF0);
+        assert_eq!(mbc.debug_state().rom_bank, Some(1));
+        write_mbc_register(&mut mbc, 0x3FFF, 0x1F);
+        assert_eq!(mbc.debug_state().rom_bank, Some(0x0F));
+
+        // Bank numbers wrap around the size of smaller ROMs, including to bank 0
+        let mut mbc = Mbc2::new(0x8000);
+        write_mbc_register(&mut mbc, 0x0100, 0x02);
+        assert_eq!(mbc.map_read_rom_address(0x4000), 0x0000);
+    }
+
+    #[test]
+    fn ram_stores_low_nibble_and_mirrors() {
+        with_large_stack(|| {
+            // MBC2+BATTERY, which declares no RAM in the header
+            let cartridge =
+                Cartridge::new_from_rom_bytes(build_test_rom(0x06, 0x03, 0x00, &[])).unwrap();
+            assert_eq!(cartridge.mbc().kind(), MbcKind::Mbc2);
+            assert_eq!(cartridge.ram().len(), MBC2_RAM_SIZE);
+
+            let mut emulator = EmulatorBuilder::new_cartridge(cartridge, Machine::Dmg)
+                .build()
+                .unwrap();
+            emulator.emulate_boot_sequence();
+
+            // RAM reads as 0xFF until enabled
+            assert_eq!(emulator.read_address(0xA000), 0xFF);
+            emulator.write_address(0x0000, 0x0A);
+
+            // Only the low nibble is stored and the upper nibble reads as 1s
+            emulator.write_address(0xA000, 0x5C);
+            assert_eq!(emulator.cartridge().ram()[0], 0x0C);
+            assert_eq!(emulator.read_address(0xA000), 0xFC);
+
+            // The 512 half-bytes are echoed throughout A000-BFFF
+            for mirror in [0xA200, 0xA400, 0xB000, 0xBE00] {
+                assert_eq!(emulator.read_address(mirror), 0xFC);
+            }
+
+            emulator.write_address(0xBFFF, 0x03);
+            assert_eq!(emulator.read_address(0xA1FF), 0xF3);
+        });
+    }
+}
@@ -7,7 +7,8 @@ use crate::{
         Address, EXTERNAL_RAM_START, FIRST_ROM_BANK_END, ROM_BANK_SIZE,
         SINGLE_EXTERNAL_RAM_BANK_SIZE,
     },
-    mbc::types::{Location, Mbc, MbcKind, RegisterHandle},
+    mbc::types::{Location, Mbc, MbcDebugInfo, MbcKind, RamSelection, RegisterHandle},
+    save_file::RTC_FOOTER_SIZE,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -20,6 +21,9 @@ pub struct Mbc3 {
     ram_rtc_mapping: RamRtcMapping,
     /// Saved time value
     latched_clock_time: Option<SystemTime>,
+    /// Current time of the emulator's clock, updated by the emulator every frame
+    #[serde(skip, default = "unix_epoch")]
+    current_time: SystemTime,
     /// The last value written to the latch clock data register.
     /// Used to detect rising edge from 0x00 to 0x01.
     last_latched_write: Option<u8>,
@@ -29,7 +33,7 @@ pub struct Mbc3 {
     ram_size_mask: u8,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 enum RtcRegister {
     Seconds,
     Minutes,
@@ -38,6 +42,18 @@ enum RtcRegister {
     DayHigh,
 }
 
+impl RtcRegister {
+    fn name(&self) -> &'static str {
+        match self {
+            RtcRegister::Seconds => "seconds",
+            RtcRegister::Minutes => "minutes",
+            RtcRegister::Hours => "hours",
+            RtcRegister::DayLow => "day low",
+            RtcRegister::DayHigh => "day high",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 enum RamRtcMapping {
     RamBank(u8),
@@ -51,6 +67,7 @@ impl Mbc3 {
             rom_bank_num: 1,
             ram_rtc_mapping: RamRtcMapping::RamBank(0),
             latched_clock_time: None,
+            current_time: UNIX_EPOCH,
             last_latched_write: None,
             rom_size_mask: ((rom_size / ROM_BANK_SIZE) - 1) as u8,
             ram_size_mask: ((ram_size / SINGLE_EXTERNAL_RAM_BANK_SIZE) - 1) as u8,
@@ -72,6 +89,37 @@ const RTC_REGISTER_DAY_HIGH: RegisterHandle = 8;
 /// that always returns 0xFF/is ignored respectively.
 const UNITIALIZED_RAM_VALUE_REGISTER: RegisterHandle = 9;
 
+const ALL_RTC_REGISTERS: [RtcRegister; 5] = [
+    RtcRegister::Seconds,
+    RtcRegister::Minutes,
+    RtcRegister::Hours,
+    RtcRegister::DayLow,
+    RtcRegister::DayHigh,
+];
+
+fn unix_epoch() -> SystemTime {
+    UNIX_EPOCH
+}
+
+/// Value of an RTC register at a time, or 0 if the clock has never been latched.
+fn rtc_register_value(time: Option<SystemTime>, register: RtcRegister) -> u8 {
+    let Some(time) = time else {
+        return 0;
+    };
+
+    let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    match register {
+        RtcRegister::Seconds => (secs % 60) as u8,
+        RtcRegister::Minutes => ((secs / 60) % 60) as u8,
+        RtcRegister::Hours => ((secs / 3600) % 24) as u8,
+        // Low 8 bits of the (9 bit) day counter
+        RtcRegister::DayLow => ((secs / 86400) & 0xFF) as u8,
+        // High bit of the day counter
+        // TODO: Implement halt and carry bits
+        RtcRegister::DayHigh => (((secs / 86400) >> 8) & 0x1) as u8,
+    }
+}
+
 impl Mbc3 {
     /// Address expected to be in the range 0xA000-0xC000
     fn physical_ram_bank_address(bank_num: usize, addr: Address) -> usize {
@@ -145,47 +193,18 @@ impl Mbc for Mbc3 {
         match reg {
             // RAM always returns 0xFF until initialized
             UNITIALIZED_RAM_VALUE_REGISTER => 0xFF,
-            // Calculate current number of seconds in the minute from RTC
             RTC_REGISTER_SECONDS => {
-                if let Some(time) = &self.latched_clock_time {
-                    (time.duration_since(UNIX_EPOCH).unwrap().as_secs() % 60) as u8
-                } else {
-                    0
-                }
+                rtc_register_value(self.latched_clock_time, RtcRegister::Seconds)
             }
-            // Calculate current number of minutes in the hour from RTC
             RTC_REGISTER_MINUTES => {
-                if let Some(time) = &self.latched_clock_time {
-                    ((time.duration_since(UNIX_EPOCH).unwrap().as_secs() / 60) % 60) as u8
-                } else {
-                    0
-                }
+                rtc_register_value(self.latched_clock_time, RtcRegister::Minutes)
             }
-            // Calculate current number of hours in the day from RTC
-            RTC_REGISTER_HOURS => {
-                if let Some(time) = &self.latched_clock_time {
-                    ((time.duration_since(UNIX_EPOCH).unwrap().as_secs() / 3600) % 24) as u8
-                } else {
-                    0
-                }
-            }
-            // Low 8 bits of the (9 bit) day counter from RTC
+            RTC_REGISTER_HOURS => rtc_register_value(self.latched_clock_time, RtcRegister::Hours),
             RTC_REGISTER_DAY_LOW => {
-                if let Some(time) = &self.latched_clock_time {
-                    ((time.duration_since(UNIX_EPOCH).unwrap().as_secs() / 86400) & 0xFF) as u8
-                } else {
-                    0
-                }
+                rtc_register_value(self.latched_clock_time, RtcRegister::DayLow)
             }
-            // High bit of the day counter from RTC
-            // TODO: Implement halt and carry bits
             RTC_REGISTER_DAY_HIGH => {
-                if let Some(time) = &self.latched_clock_time {
-                    let days = (time.duration_since(UNIX_EPOCH).unwrap().as_secs() / 86400) as u16;
-                    ((days >> 8) & 0x1) as u8
-                } else {
-                    0
-                }
+                rtc_register_value(self.latched_clock_time, RtcRegister::DayHigh)
             }
             _ => unreachable!(),
         }
@@ -224,7 +243,7 @@ impl Mbc for Mbc3 {
                 }
 
                 if self.last_latched_write == Some(0) && value == 1 {
-                    self.latched_clock_time = Some(SystemTime::now());
+                    self.latched_clock_time = Some(self.current_time);
                     self.last_latched_write = None;
                     return;
                 }
@@ -243,4 +262,99 @@ impl Mbc for Mbc3 {
             _ => unreachable!(),
         }
     }
+
+    fn set_current_time(&mut self, time: SystemTime) {
+        self.current_time = time;
+    }
+
+    /// The RTC follows the emulator's clock, so the current registers are always the current time.
+    fn rtc_footer(&self) -> Option<Vec<u8>> {
+        let now = self.current_time;
+        let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+
+        for time in [Some(now), self.latched_clock_time] {
+            for register in ALL_RTC_REGISTERS {
+                let value = rtc_register_value(time, register) as u32;
+                footer.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        let timestamp = now.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        footer.extend_from_slice(&timestamp.to_le_bytes());
+
+        Some(footer)
+    }
+
+    fn debug_state(&self) -> MbcDebugInfo {
+        let ram_selection = match &self.ram_rtc_mapping {
+            RamRtcMapping::RamBank(bank_num) => RamSelection::Bank(*bank_num as usize),
+            RamRtcMapping::RtcRegister(register) => RamSelection::RtcRegister(register.name()),
+        };
+
+        // Latched time is shown as days and time of day, as read from the RTC registers
+        let latched_time = match &self.latched_clock_time {
+            Some(time) => {
+                let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+                format!(
+                    "day {} {:02}:{:02}:{:02}",
+                    (secs / 86400) & 0x1FF,
+                    (secs / 3600) % 24,
+                    (secs / 60) % 60,
+                    secs % 60
+                )
+            }
+            None => "none".to_string(),
+        };
+
+        MbcDebugInfo {
+            rom_bank: Some(self.rom_bank_num as usize),
+            ram_selection: Some(ram_selection),
+            is_ram_enabled: Some(self.is_ram_rtc_enabled),
+            extras: vec![("latched time", latched_time)],
+            ..MbcDebugInfo::new(self.kind())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        mbc::types::{Mbc, RamSelection},
+        test_utils::write_mbc_register,
+    };
+
+    use super::Mbc3;
+
+    #[test]
+    fn debug_state_tracks_bank_writes() {
+        // 2MB ROM and 32KB RAM
+        let mut mbc = Mbc3::new(0x200000, 0x8000);
+
+        let state = mbc.debug_state();
+        assert_eq!(state.rom_bank, Some(1));
+        assert_eq!(state.ram_selection, Some(RamSelection::Bank(0)));
+        assert_eq!(state.is_ram_enabled, Some(false));
+
+        write_mbc_register(&mut mbc, 0x0000, 0x0A);
+        write_mbc_register(&mut mbc, 0x2000, 0x45);
+        write_mbc_register(&mut mbc, 0x4000, 0x02);
+
+        let state = mbc.debug_state();
+        assert_eq!(state.rom_bank, Some(0x45));
+        assert_eq!(state.ram_selection, Some(RamSelection::Bank(2)));
+        assert_eq!(state.is_ram_enabled, Some(true));
+        assert_eq!(state.extras, vec![("latched time", "none".to_string())]);
+
+        // Select an RTC register and latch the clock
+        write_mbc_register(&mut mbc, 0x4000, 0x08);
+        write_mbc_register(&mut mbc, 0x6000, 0x00);
+        write_mbc_register(&mut mbc, 0x6000, 0x01);
+
+        let state = mbc.debug_state();
+        assert_eq!(
+            state.ram_selection,
+            Some(RamSelection::RtcRegister("seconds"))
+        );
+        assert!(state.extras[0].1.starts_with("day "));
+    }
 }
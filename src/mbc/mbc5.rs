@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    address_space::{
+        Address, EXTERNAL_RAM_START, FIRST_ROM_BANK_END, ROM_BANK_SIZE,
+        SINGLE_EXTERNAL_RAM_BANK_SIZE,
+    },
+    mbc::types::{Location, Mbc, MbcDebugInfo, MbcKind, RamSelection, RegisterHandle},
+};
+
+/// MBC5, used by most later GBC cartridges. Rumble cartridges wire bit 3 of the RAM bank number to
+/// the rumble motor, which is not emulated.
+#[derive(Serialize, Deserialize)]
+pub struct Mbc5 {
+    /// RAM Enable Register (0000–1FFF)
+    is_ram_enabled: bool,
+    /// ROM Bank Number, 9 bits. Low 8 bits at 2000-2FFF and the high bit at 3000-3FFF.
+    rom_bank_num: usize,
+    /// RAM Bank Number, 4 bits (4000–5FFF)
+    ram_bank_num: usize,
+    /// Mask to apply to full ROM bank number to ensure it doesn't exceed available banks
+    rom_size_mask: usize,
+    /// Mask to apply to RAM bank number to ensure it doesn't exceed available banks
+    ram_size_mask: usize,
+}
+
+impl Mbc5 {
+    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+        Mbc5 {
+            is_ram_enabled: false,
+            rom_bank_num: 1,
+            ram_bank_num: 0,
+            rom_size_mask: (rom_size / ROM_BANK_SIZE) - 1,
+            ram_size_mask: (ram_size / SINGLE_EXTERNAL_RAM_BANK_SIZE) - 1,
+        }
+    }
+}
+
+const RAM_ENABLE_REGISTER: RegisterHandle = 0;
+const ROM_BANK_NUMBER_LOW_REGISTER: RegisterHandle = 1;
+const ROM_BANK_NUMBER_HIGH_REGISTER: RegisterHandle = 2;
+const RAM_BANK_NUMBER_REGISTER: RegisterHandle = 3;
+
+/// Writes to 6000-7FFF have no effect
+const UNUSED_REGISTER: RegisterHandle = 4;
+
+/// Treat reads or writes to uninitialized RAM value register as reading/writing from a register
+/// that always returns 0xFF/is ignored respectively.
+const UNITIALIZED_RAM_VALUE_REGISTER: RegisterHandle = 5;
+
+impl Mbc5 {
+    fn rom_bank_number(&self) -> usize {
+        self.rom_bank_num & self.rom_size_mask
+    }
+
+    fn ram_bank_number(&self) -> usize {
+        self.ram_bank_num & self.ram_size_mask
+    }
+
+    /// Address expected to be in the range 0x4000-0x8000
+    fn physical_second_rom_bank_address(bank_num: usize, addr: Address) -> usize {
+        let physical_bank_start_offset = bank_num * ROM_BANK_SIZE;
+        let offset_in_bank = (addr - FIRST_ROM_BANK_END) as usize;
+
+        physical_bank_start_offset + offset_in_bank
+    }
+
+    /// Address expected to be in the range 0xA000-0xC000
+    fn physical_ram_bank_address(bank_num: usize, addr: Address) -> usize {
+        let physical_bank_start_offset = bank_num * SINGLE_EXTERNAL_RAM_BANK_SIZE;
+        let offset_in_bank = (addr - EXTERNAL_RAM_START) as usize;
+
+        physical_bank_start_offset + offset_in_bank
+    }
+
+    fn map_ram_address(&self, addr: Address) -> Location {
+        if !self.is_ram_enabled {
+            return Location::Register(UNITIALIZED_RAM_VALUE_REGISTER);
+        }
+
+        Location::Address(Self::physical_ram_bank_address(
+            self.ram_bank_number(),
+            addr,
+        ))
+    }
+}
+
+#[typetag::serde]
+impl Mbc for Mbc5 {
+    fn kind(&self) -> MbcKind {
+        MbcKind::Mbc5
+    }
+
+    fn map_read_rom_address(&self, addr: Address) -> usize {
+        if addr < FIRST_ROM_BANK_END {
+            addr as usize
+        } else {
+            Self::physical_second_rom_bank_address(self.rom_bank_number(), addr)
+        }
+    }
+
+    fn map_write_rom_address(&self, addr: Address) -> Location {
+        match addr {
+            0..0x2000 => Location::Register(RAM_ENABLE_REGISTER),
+            0x2000..0x3000 => Location::Register(ROM_BANK_NUMBER_LOW_REGISTER),
+            0x3000..0x4000 => Location::Register(ROM_BANK_NUMBER_HIGH_REGISTER),
+            0x4000..0x6000 => Location::Register(RAM_BANK_NUMBER_REGISTER),
+            0x6000..0x8000 => Location::Register(UNUSED_REGISTER),
+            _ => unreachable!(),
+        }
+    }
+
+    fn map_read_ram_address(&self, addr: Address) -> Location {
+        self.map_ram_address(addr)
+    }
+
+    fn map_write_ram_address(&self, addr: Address) -> Location {
+        self.map_ram_address(addr)
+    }
+
+    fn read_register(&self, reg: RegisterHandle) -> u8 {
+        match reg {
+            // The only readable register we need to implement is the unitialized RAM value
+            // register, which always returns 0xFF
+            UNITIALIZED_RAM_VALUE_REGISTER => 0xFF,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_register(&mut self, register: RegisterHandle, value: u8) {
+        match register {
+            // Unlike MBC1 and MBC3 only exactly 0x0A enables RAM
+            RAM_ENABLE_REGISTER => {
+                self.is_ram_enabled = value == 0x0A;
+            }
+            // Low 8 bits of the ROM bank number. Bank 0 is not remapped to bank 1.
+            ROM_BANK_NUMBER_LOW_REGISTER => {
+                self.rom_bank_num = (self.rom_bank_num & 0x100) | value as usize;
+            }
+            // Only lowest bit of the value is used, as bit 8 of the ROM bank number
+            ROM_BANK_NUMBER_HIGH_REGISTER => {
+                self.rom_bank_num = (self.rom_bank_num & 0xFF) | (((value & 0x1) as usize) << 8);
+            }
+            // Only lower 4 bits of the value are used
+            RAM_BANK_NUMBER_REGISTER => {
+                self.ram_bank_num = (value & 0xF) as usize;
+            }
+            UNUSED_REGISTER => {}
+            // Writes to unitialized RAM are modeled as a write to a register that is ignored
+            UNITIALIZED_RAM_VALUE_REGISTER => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn debug_state(&self) -> MbcDebugInfo {
+        MbcDebugInfo {
+            rom_bank: Some(self.rom_bank_number()),
+            ram_selection: Some(RamSelection::Bank(self.ram_bank_number())),
+            is_ram_enabled: Some(self.is_ram_enabled),
+            ..MbcDebugInfo::new(self.kind())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        cartridge::Cartridge,
+        emulator::EmulatorBuilder,
+        machine::Machine,
+        mbc::types::{Location, Mbc, MbcKind},
+        test_utils::{build_test_rom, with_large_stack, write_mbc_register},
+    };
+
+    use super::Mbc5;
+
+    #[test]
+    fn rom_bank_switching_beyond_bank_255() {
+        // 8MB ROM, the largest supported with 512 banks
+        let mut mbc = Mbc5::new(0x800000, 0x20000);
+
+        // Bank 1 is mapped initially, and bank 0 is always mapped at 0000-3FFF
+        assert_eq!(mbc.map_read_rom_address(0x4000), 0x4000);
+        assert_eq!(mbc.map_read_rom_address(0x3FFF), 0x3FFF);
+
+        write_mbc_register(&mut mbc, 0x2000, 0x34);
+        assert_eq!(mbc.map_read_rom_address(0x4123), 0x34 * 0x4000 + 0x123);
+
+        // Bit 8 selects banks 256-511 without changing the low bits
+        write_mbc_register(&mut mbc, 0x3000, 0x01);
+        assert_eq!(mbc.map_read_rom_address(0x4123), 0x134 * 0x4000 + 0x123);
+        assert_eq!(mbc.debug_state().rom_bank, Some(0x134));
+
+        write_mbc_register(&mut mbc, 0x2FFF, 0xFF);
+        assert_eq!(mbc.map_read_rom_address(0x7FFF), 0x800000 - 1);
+
+        // Bank 0 is selectable in the switchable bank
+        write_mbc_register(&mut mbc, 0x3FFF, 0x00);
+        write_mbc_register(&mut mbc, 0x2000, 0x00);
+        assert_eq!(mbc.map_read_rom_address(0x4000), 0x0000);
+
+        // Bank numbers wrap around the size of smaller ROMs
+        let mut mbc = Mbc5::new(0x100000, 0x2000);
+        write_mbc_register(&mut mbc, 0x3000, 0x01);
+        write_mbc_register(&mut mbc, 0x2000, 0x42);
+        assert_eq!(mbc.map_read_rom_address(0x4000), 0x02 * 0x4000);
+    }
+
+    #[test]
+    fn write_ram_bank_7() {
+        // 128KB RAM
+        let mut mbc = Mbc5::new(0x100000, 0x20000);
+
+        // RAM is disabled until exactly 0x0A is written
+        write_mbc_register(&mut mbc, 0x0000, 0x1A);
+        assert!(matches!(
+            mbc.map_write_ram_address(0xA000),
+            Location::Register(_)
+        ));
+
+        write_mbc_register(&mut mbc, 0x0000, 0x0A);
+        write_mbc_register(&mut mbc, 0x4000, 0x07);
+        assert!(matches!(
+            mbc.map_write_ram_address(0xA010),
+            Location::Address(0xE010)
+        ));
+        assert!(matches!(
+            mbc.map_read_ram_address(0xBFFF),
+            Location::Address(0xFFFF)
+        ));
+    }
+
+    #[test]
+    fn emulator_maps_mbc5_banks() {
+        with_large_stack(|| {
+            // MBC5+RAM+BATTERY with an 8MB ROM and 128KB of RAM, with a marker in bank 0x134
+            let mut rom = build_test_rom(0x1B, 0x08, 0x04, &[]);
+            rom[0x134 * 0x4000 + 0x10] = 0x5A;
+
+            let cartridge = Cartridge::new_from_rom_bytes(rom).unwrap();
+            assert_eq!(cartridge.mbc().kind(), MbcKind::Mbc5);
+
+            let mut emulator = EmulatorBuilder::new_cartridge(cartridge, Machine::Cgb)
+                .build()
+                .unwrap();
+            emulator.emulate_boot_sequence();
+
+            emulator.write_address(0x2000, 0x34);
+            emulator.write_address(0x3000, 0x01);
+            assert_eq!(emulator.read_address(0x4010), 0x5A);
+
+            emulator.write_address(0x0000, 0x0A);
+            emulator.write_address(0x4000, 0x07);
+            emulator.write_address(0xA123, 0xA5);
+            assert_eq!(emulator.cartridge().ram()[7 * 0x2000 + 0x123], 0xA5);
+            assert_eq!(emulator.read_address(0xA123), 0xA5);
+
+            emulator.write_address(0x4000, 0x06);
+            assert_eq!(emulator.read_address(0xA123), 0x00);
+        });
+    }
+}
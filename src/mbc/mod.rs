@@ -1,4 +1,6 @@
 mod mbc1;
+mod mbc2;
 mod mbc3;
+mod mbc5;
 mod no_mbc;
 pub mod types;
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     address_space::{Address, EXTERNAL_RAM_START},
-    mbc::types::{Location, Mbc, MbcKind, RegisterHandle},
+    mbc::types::{Location, Mbc, MbcDebugInfo, MbcKind, RamSelection, RegisterHandle},
 };
 
 #[derive(Serialize, Deserialize)]
@@ -46,4 +46,29 @@ impl Mbc for NoMbc {
     fn write_register(&mut self, _: RegisterHandle, _: u8) {
         // No registers
     }
+
+    fn debug_state(&self) -> MbcDebugInfo {
+        // Banks are fixed and RAM is always accessible
+        MbcDebugInfo {
+            rom_bank: Some(1),
+            ram_selection: Some(RamSelection::Bank(0)),
+            is_ram_enabled: Some(true),
+            ..MbcDebugInfo::new(self.kind())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mbc::types::Mbc;
+
+    use super::NoMbc;
+
+    #[test]
+    fn debug_state_is_fixed() {
+        assert_eq!(
+            NoMbc.debug_state().to_string(),
+            "No MBC, ROM bank 01, RAM bank 00 (enabled)"
+        );
+    }
 }
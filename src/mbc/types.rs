@@ -1,6 +1,8 @@
+use std::{fmt, time::SystemTime};
+
 use crate::{
     address_space::Address,
-    mbc::{mbc1::Mbc1, mbc3::Mbc3, no_mbc::NoMbc},
+    mbc::{mbc1::Mbc1, mbc2::Mbc2, mbc3::Mbc3, mbc5::Mbc5, no_mbc::NoMbc},
 };
 
 /// Memory Bank Controllers map the ROM and RAM banks into the GameBoy's address space.
@@ -27,21 +29,127 @@ pub trait Mbc {
 
     /// Write a byte to a register in the MBC
     fn write_register(&mut self, reg: RegisterHandle, value: u8);
+
+    /// Bits of each byte of external RAM that are stored. The other bits always read as 1.
+    fn ram_value_mask(&self) -> u8 {
+        0xFF
+    }
+
+    /// Set the current time for MBCs with a real time clock. The emulator's clock is the only source
+    /// of time, so that runs can be reproduced.
+    fn set_current_time(&mut self, _time: SystemTime) {}
+
+    /// RTC state to append to a raw save file, in the 48-byte format shared by other emulators:
+    /// the current and then latched seconds, minutes, hours, day low, and day high registers each
+    /// as a little-endian u32, followed by a little-endian u64 UNIX timestamp. None if the MBC has
+    /// no RTC.
+    fn rtc_footer(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Current banking state for debugging. Only reports the kind unless overridden.
+    fn debug_state(&self) -> MbcDebugInfo {
+        MbcDebugInfo::new(self.kind())
+    }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MbcKind {
     /// Cartridges without a Memory Bank Controller
     None,
     Mbc1,
+    /// MBC1 wired for multicarts (MBC1M), which contain several games in separate 256KB blocks
+    Mbc1Multicart,
+    Mbc2,
     Mbc3,
+    Mbc5,
 }
 
 pub fn create_mbc(kind: MbcKind, rom_size: usize, ram_size: usize) -> Box<dyn Mbc> {
     match kind {
         MbcKind::None => Box::new(NoMbc),
-        MbcKind::Mbc1 => Box::new(Mbc1::new(rom_size)),
+        MbcKind::Mbc1 => Box::new(Mbc1::new(rom_size, ram_size)),
+        MbcKind::Mbc1Multicart => Box::new(Mbc1::new_multicart(rom_size, ram_size)),
+        MbcKind::Mbc2 => Box::new(Mbc2::new(rom_size)),
         MbcKind::Mbc3 => Box::new(Mbc3::new(rom_size, ram_size)),
+        MbcKind::Mbc5 => Box::new(Mbc5::new(rom_size, ram_size)),
+    }
+}
+
+impl fmt::Display for MbcKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MbcKind::None => write!(f, "No MBC"),
+            MbcKind::Mbc1 => write!(f, "MBC1"),
+            MbcKind::Mbc1Multicart => write!(f, "MBC1 (multicart)"),
+            MbcKind::Mbc2 => write!(f, "MBC2"),
+            MbcKind::Mbc3 => write!(f, "MBC3"),
+            MbcKind::Mbc5 => write!(f, "MBC5"),
+        }
+    }
+}
+
+/// What is mapped into the external RAM area (A000-BFFF).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RamSelection {
+    Bank(usize),
+    RtcRegister(&'static str),
+}
+
+/// Snapshot of the banking state of an MBC, for debugging.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MbcDebugInfo {
+    pub kind: MbcKind,
+    /// ROM bank mapped at 4000-7FFF
+    pub rom_bank: Option<usize>,
+    /// RAM bank or RTC register mapped at A000-BFFF
+    pub ram_selection: Option<RamSelection>,
+    pub is_ram_enabled: Option<bool>,
+    /// Additional mapper-specific state, as (name, value) pairs
+    pub extras: Vec<(&'static str, String)>,
+}
+
+impl MbcDebugInfo {
+    /// Debug info containing only the kind of MBC
+    pub fn new(kind: MbcKind) -> Self {
+        MbcDebugInfo {
+            kind,
+            rom_bank: None,
+            ram_selection: None,
+            is_ram_enabled: None,
+            extras: vec![],
+        }
+    }
+}
+
+impl fmt::Display for MbcDebugInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+
+        if let Some(rom_bank) = self.rom_bank {
+            write!(f, ", ROM bank {:02X}", rom_bank)?;
+        }
+
+        match self.ram_selection {
+            Some(RamSelection::Bank(ram_bank)) => write!(f, ", RAM bank {:02X}", ram_bank)?,
+            Some(RamSelection::RtcRegister(name)) => write!(f, ", RTC register {}", name)?,
+            None => {}
+        }
+
+        if let Some(is_ram_enabled) = self.is_ram_enabled {
+            let state = if is_ram_enabled {
+                "enabled"
+            } else {
+                "disabled"
+            };
+            write!(f, " ({})", state)?;
+        }
+
+        for (name, value) in &self.extras {
+            write!(f, ", {}: {}", name, value)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -0,0 +1,107 @@
+//! Rules for which object is drawn where objects overlap, and whether it is drawn over the
+//! background and window.
+//!
+//! Each pixel is resolved in two steps, in the same order as the hardware:
+//!
+//! 1. Object priority picks a single winning object among the objects on the pixel, ignoring the
+//!    background. The winner is the highest priority object whose pixel is not transparent.
+//!    - On a DMG, and in DMG compatibility mode, the object with the lower x coordinate has priority,
+//!      then the object with the lower OAM index when x coordinates are equal.
+//!    - In CGB mode the object with the lower OAM index has priority, unless OPRI selects the DMG
+//!      order.
+//! 2. Background priority decides whether the winning object or the background is drawn. Objects
+//!    are always drawn over background color 0.
+//!    - On a DMG, the object's priority flag puts it behind background colors 1-3.
+//!    - In CGB mode, either the object's priority flag or the background tile's priority flag put the
+//!      object behind background colors 1-3, unless LCDC bit 0 is clear in which case objects are
+//!      always on top.
+//!
+//! Since the winner is chosen first, a winning object that is behind the background hides every
+//! lower priority object on that pixel, even objects that would be drawn over the background.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    emulator::Emulator,
+    machine::CompatMode,
+    ppu::{BackgroundTileAttributes, Object},
+};
+
+/// How overlapping objects are prioritized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpritePriority {
+    /// The object with the lower OAM index has priority, as on a CGB with OPRI clear
+    Oam,
+    /// The object with the lower x coordinate has priority, then the lower OAM index. Used by the
+    /// DMG, and by a CGB with OPRI set.
+    Coordinate,
+}
+
+impl FromStr for SpritePriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oam" => Ok(SpritePriority::Oam),
+            "coordinate" => Ok(SpritePriority::Coordinate),
+            _ => Err(format!("expected oam or coordinate but found {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SpritePriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpritePriority::Oam => write!(f, "oam"),
+            SpritePriority::Coordinate => write!(f, "coordinate"),
+        }
+    }
+}
+
+/// The object priority mode used for rendering. A forced mode from the options takes precedence,
+/// otherwise a DMG and DMG compatibility mode always prioritize by coordinate, and CGB mode follows
+/// OPRI. OPRI is never consulted on a DMG, where the register does not exist.
+pub fn sprite_priority(emulator: &Emulator) -> SpritePriority {
+    if let Some(sprite_priority) = emulator.forced_sprite_priority() {
+        return sprite_priority;
+    }
+
+    match emulator.compat_mode() {
+        CompatMode::Dmg | CompatMode::DmgCompat => SpritePriority::Coordinate,
+        CompatMode::Cgb | CompatMode::Pgb if emulator.opri() & 0x01 == 1 => {
+            SpritePriority::Coordinate
+        }
+        CompatMode::Cgb | CompatMode::Pgb => SpritePriority::Oam,
+    }
+}
+
+/// Sort objects found by the OAM scan, which are in OAM order, from highest to lowest priority. A
+/// stable sort keeps objects with equal x coordinates in OAM order.
+pub fn sort_by_priority(objects: &mut [Object], sprite_priority: SpritePriority) {
+    if sprite_priority == SpritePriority::Coordinate {
+        objects.sort_by_key(|object| object.x);
+    }
+}
+
+/// Whether the winning object's pixel is drawn over the background or window pixel. Background
+/// attributes are only present in CGB mode, and a background color index of None means the
+/// background is disabled.
+pub fn is_object_over_background(
+    object: &Object,
+    background_color_index: Option<u8>,
+    background_attributes: Option<&BackgroundTileAttributes>,
+    is_lcdc_cgb_bg_window_priority: bool,
+) -> bool {
+    let is_background_transparent = matches!(background_color_index, None | Some(0));
+    if is_background_transparent {
+        return true;
+    }
+
+    match background_attributes {
+        Some(background_attributes) => {
+            !is_lcdc_cgb_bg_window_priority
+                || (!object.in_background() && !background_attributes.in_foreground())
+        }
+        None => !object.in_background(),
+    }
+}
@@ -1,12 +1,34 @@
+use std::path::{Path, PathBuf};
+
 use clap::Parser;
 
+use crate::{
+    audio::{DEFAULT_AUDIO_LATENCY_FRAMES, TurboAudio},
+    emulator::{MAX_SPEED, MIN_SPEED},
+    gui::{
+        gamepad::GamepadMapping, key_bindings::KEY_BINDINGS_FILE_NAME,
+        window_layout::WINDOW_LAYOUT_FILE_NAME,
+    },
+    object_priority::SpritePriority,
+    ram_init::RamInit,
+    rewind::DEFAULT_REWIND_INTERVAL_FRAMES,
+    save_file::{SaveFormat, platform_data_dir},
+    screen_palette::ScreenColorPalette,
+    symbols::SymbolTable,
+    version::VERSION_STRING,
+};
+
 #[derive(Parser)]
-#[command(about)]
+#[command(about, version = VERSION_STRING)]
 pub struct Args {
     /// Print info about the ROM to stdout
     #[arg(long, default_value_t = false)]
     pub dump_rom_info: bool,
 
+    /// Print info about the ROM to stdout as JSON
+    #[arg(long, default_value_t = false)]
+    pub dump_rom_info_json: bool,
+
     /// Emulate a GameBoy Color instead of a regular GameBoy
     #[arg(long, default_value_t = false)]
     pub cgb: bool,
@@ -15,34 +37,561 @@ pub struct Args {
     #[arg(long, default_value_t = false)]
     pub log_frames: bool,
 
+    /// Print each instruction executed along with the CPU registers to stdout
+    #[arg(long, default_value_t = false)]
+    pub trace: bool,
+
+    /// Print each byte sent over the serial port to stdout
+    #[arg(long, default_value_t = false)]
+    pub serial_log: bool,
+
     /// Run in headless mode (no GUI)
     #[arg(long, default_value_t = false)]
     pub headless: bool,
 
+    /// Run in the terminal instead of a window, without audio
+    #[cfg(feature = "tui")]
+    #[arg(long, default_value_t = false)]
+    pub tui: bool,
+
     /// Run in test mode (print success or failure based on magic instruction)
     #[arg(long, default_value_t = false)]
     pub test: bool,
 
+    /// Initial contents of work RAM, VRAM, HRAM, and OAM at power-on: zero, ones, pattern, random,
+    /// or random:<seed>. Random also randomizes uninitialized registers such as OBP0 and OBP1.
+    #[arg(long, default_value_t = RamInit::Zero)]
+    pub ram_init: RamInit,
+
+    /// Seed for all randomness in the emulator, including --ram-init random. Also starts the clock
+    /// seen by the game at a fixed time instead of the current time, so that runs with the same ROM
+    /// and inputs are identical.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Colors that DMG games are shown in: grayscale, green, or
+    /// custom:#RRGGBB,#RRGGBB,#RRGGBB,#RRGGBB from lightest to darkest
+    #[arg(long, default_value_t = ScreenColorPalette::Grayscale)]
+    pub palette: ScreenColorPalette,
+
+    /// Number of frames of audio to buffer before playing. Higher values add latency but make
+    /// crackling less likely on slow machines.
+    #[arg(long, value_name = "FRAMES", default_value_t = DEFAULT_AUDIO_LATENCY_FRAMES)]
+    pub audio_latency: u32,
+
+    /// Gamepad buttons that the GameBoy's buttons and turbo mode are mapped to, e.g.
+    /// a=east,b=south,start=start,select=select,turbo=right-shoulder. Unlisted buttons keep their
+    /// default mapping. The D-pad and left stick are always the direction buttons.
+    #[arg(long, value_name = "MAPPING", default_value_t = GamepadMapping::default())]
+    pub gamepad_map: GamepadMapping,
+
+    /// TOML file of keyboard bindings, e.g. `a = "X"`. Defaults to keymap.toml in the platform data
+    /// directory. Rebinding keys in the Controls window writes to this file.
+    #[arg(long, value_name = "PATH")]
+    pub keymap: Option<PathBuf>,
+
+    /// How audio is played in turbo mode: pitch to play it faster at a higher pitch, or drop to
+    /// play at normal pitch while skipping audio that does not fit
+    #[arg(long, default_value_t = TurboAudio::Pitch)]
+    pub turbo_audio: TurboAudio,
+
+    /// Speed to run at as a multiple of the GameBoy's native speed, e.g. 0.5 for slow motion,
+    /// between 0.1 and 16
+    #[arg(long, value_name = "SPEED", value_parser = parse_speed)]
+    pub speed: Option<f64>,
+
+    /// Speed to run at while turbo is held, between 0.1 and 16. Defaults to 10.
+    #[arg(long, value_name = "SPEED", value_parser = parse_speed)]
+    pub turbo_speed: Option<f64>,
+
+    /// Number of frames between snapshots of the emulator state kept for rewinding, or 0 to disable
+    /// rewinding. Around 60 seconds of snapshots are kept.
+    #[arg(long, value_name = "FRAMES", default_value_t = DEFAULT_REWIND_INTERVAL_FRAMES)]
+    pub rewind_interval: u32,
+
+    /// Panic on accesses to echo RAM instead of mirroring work RAM, to catch bugs in homebrew ROMs
+    #[arg(long, default_value_t = false)]
+    pub strict_memory: bool,
+
+    /// Panic on invalid opcodes instead of locking up the CPU like real hardware, to catch bugs in
+    /// homebrew ROMs
+    #[arg(long, default_value_t = false)]
+    pub panic_on_invalid: bool,
+
+    /// Let the CPU access VRAM and OAM while the PPU is using them, and all memory during OAM DMA,
+    /// for debugging
+    #[arg(long, default_value_t = false)]
+    pub no_access_restrictions: bool,
+
+    /// Perform each memory access of an instruction on its own machine cycle instead of all at the
+    /// start of the instruction. More accurate but slower.
+    #[arg(long, default_value_t = false)]
+    pub cycle_accurate: bool,
+
+    /// Run every tick on its own instead of batching ticks in which only timers advance. Slower,
+    /// for checking that batching does not change behavior.
+    #[arg(long, default_value_t = false)]
+    pub per_tick_stepping: bool,
+
+    /// Write the save file to the platform data directory if it cannot be written next to the ROM
+    #[arg(long, default_value_t = false)]
+    pub save_fallback: bool,
+
+    /// Directory to write save files to instead of next to the ROM
+    #[arg(long, value_name = "DIR")]
+    pub save_dir: Option<PathBuf>,
+
+    /// Battery save format: native, or raw to also read and write a .sav file next to the save file
+    /// that is compatible with other emulators
+    #[arg(long, default_value_t = SaveFormat::Native)]
+    pub sav_format: SaveFormat,
+
+    /// Path to an RGBDS symbol file to load. Defaults to the ROM path with a .sym extension.
+    #[arg(long)]
+    pub symbols: Option<String>,
+
+    /// Run the given number of frames as fast as possible without a GUI, write the screen to a PNG
+    /// file, then exit
+    #[arg(long, num_args = 2, value_names = ["FRAMES", "OUT_PNG"])]
+    pub screenshot_after: Option<Vec<String>>,
+
+    /// Boot every ROM in a directory headless for a few seconds of emulated time, then write a
+    /// report of which ROMs ran cleanly, locked up, or panicked, along with their final screens
+    #[arg(long, value_name = "DIR")]
+    pub smoke_test_dir: Option<PathBuf>,
+
+    /// Emulated seconds to run each ROM for with --smoke-test-dir
+    #[arg(long, default_value_t = 5.0, requires = "smoke_test_dir")]
+    pub seconds: f64,
+
+    /// Directory to write the --smoke-test-dir report and screenshots to
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "smoke_test_report",
+        requires = "smoke_test_dir"
+    )]
+    pub smoke_test_out: PathBuf,
+
     /// Path to the boot ROM to use
     #[arg(long)]
     pub bios: Option<String>,
 
+    /// Start ROMs with an invalid Nintendo logo from the state after the boot ROM, instead of
+    /// running the boot ROM which locks up on them like on real hardware
+    #[arg(long, default_value_t = false)]
+    pub skip_boot_on_bad_logo: bool,
+
+    /// Prioritize overlapping objects by oam index or by x coordinate, overriding the mode the game
+    /// selects in CGB mode. Fixes objects in some DMG games that are drawn in the wrong order on a
+    /// CGB.
+    #[arg(long, value_name = "MODE")]
+    pub sprite_priority: Option<SpritePriority>,
+
+    /// Raise the priority of the emulator and audio threads, so that they are not descheduled on a
+    /// busy system. May require elevated permissions.
+    #[arg(long, default_value_t = false)]
+    pub high_priority: bool,
+
+    /// Pin the emulator thread to the given CPU core. Not supported on macOS.
+    #[arg(long, value_name = "CORE")]
+    pub pin_core: Option<usize>,
+
+    /// Save the state of the game when the emulator exits, and offer to resume from it the next
+    /// time the same ROM is run
+    #[arg(long, default_value_t = false)]
+    pub resume_on_launch: bool,
+
+    /// Resume from the state saved when the emulator last exited without asking first. Implies
+    /// --resume-on-launch.
+    #[arg(long, default_value_t = false)]
+    pub resume: bool,
+
     /// ROM or save file to run
-    #[arg(required = true)]
+    #[arg(required_unless_present = "smoke_test_dir", default_value = "")]
     pub rom_or_save: String,
 }
 
+/// Metadata for a command line option that overrides a default.
+pub struct OptionInfo {
+    /// Flag that sets the option
+    pub flag: &'static str,
+    /// Whether the option could crash the emulator at startup, in which case it is ignored in safe
+    /// mode
+    pub risky: bool,
+    /// Whether the option was set to something other than its default
+    is_set: fn(&Args) -> bool,
+    /// Reset the option to its default
+    reset: fn(&mut Args),
+}
+
+pub const OPTIONS_SCHEMA: [OptionInfo; 24] = [
+    OptionInfo {
+        flag: "--ram-init",
+        risky: false,
+        is_set: |args| args.ram_init != RamInit::Zero,
+        reset: |args| args.ram_init = RamInit::Zero,
+    },
+    OptionInfo {
+        flag: "--seed",
+        risky: false,
+        is_set: |args| args.seed.is_some(),
+        reset: |args| args.seed = None,
+    },
+    OptionInfo {
+        flag: "--palette",
+        risky: false,
+        is_set: |args| args.palette != ScreenColorPalette::Grayscale,
+        reset: |args| args.palette = ScreenColorPalette::Grayscale,
+    },
+    OptionInfo {
+        flag: "--audio-latency",
+        risky: true,
+        is_set: |args| args.audio_latency != DEFAULT_AUDIO_LATENCY_FRAMES,
+        reset: |args| args.audio_latency = DEFAULT_AUDIO_LATENCY_FRAMES,
+    },
+    OptionInfo {
+        flag: "--turbo-audio",
+        risky: false,
+        is_set: |args| args.turbo_audio != TurboAudio::Pitch,
+        reset: |args| args.turbo_audio = TurboAudio::Pitch,
+    },
+    OptionInfo {
+        flag: "--speed",
+        risky: false,
+        is_set: |args| args.speed.is_some(),
+        reset: |args| args.speed = None,
+    },
+    OptionInfo {
+        flag: "--turbo-speed",
+        risky: false,
+        is_set: |args| args.turbo_speed.is_some(),
+        reset: |args| args.turbo_speed = None,
+    },
+    OptionInfo {
+        flag: "--gamepad-map",
+        risky: false,
+        is_set: |args| args.gamepad_map != GamepadMapping::default(),
+        reset: |args| args.gamepad_map = GamepadMapping::default(),
+    },
+    OptionInfo {
+        flag: "--keymap",
+        risky: false,
+        is_set: |args| args.keymap.is_some(),
+        reset: |args| args.keymap = None,
+    },
+    OptionInfo {
+        flag: "--rewind-interval",
+        risky: false,
+        is_set: |args| args.rewind_interval != DEFAULT_REWIND_INTERVAL_FRAMES,
+        reset: |args| args.rewind_interval = DEFAULT_REWIND_INTERVAL_FRAMES,
+    },
+    OptionInfo {
+        flag: "--strict-memory",
+        risky: false,
+        is_set: |args| args.strict_memory,
+        reset: |args| args.strict_memory = false,
+    },
+    OptionInfo {
+        flag: "--panic-on-invalid",
+        risky: false,
+        is_set: |args| args.panic_on_invalid,
+        reset: |args| args.panic_on_invalid = false,
+    },
+    OptionInfo {
+        flag: "--no-access-restrictions",
+        risky: false,
+        is_set: |args| args.no_access_restrictions,
+        reset: |args| args.no_access_restrictions = false,
+    },
+    OptionInfo {
+        flag: "--cycle-accurate",
+        risky: true,
+        is_set: |args| args.cycle_accurate,
+        reset: |args| args.cycle_accurate = false,
+    },
+    OptionInfo {
+        flag: "--per-tick-stepping",
+        risky: false,
+        is_set: |args| args.per_tick_stepping,
+        reset: |args| args.per_tick_stepping = false,
+    },
+    OptionInfo {
+        flag: "--save-dir",
+        risky: false,
+        is_set: |args| args.save_dir.is_some(),
+        reset: |args| args.save_dir = None,
+    },
+    OptionInfo {
+        flag: "--symbols",
+        risky: true,
+        is_set: |args| args.symbols.is_some(),
+        reset: |args| args.symbols = None,
+    },
+    OptionInfo {
+        flag: "--bios",
+        risky: true,
+        is_set: |args| args.bios.is_some(),
+        reset: |args| args.bios = None,
+    },
+    OptionInfo {
+        flag: "--skip-boot-on-bad-logo",
+        risky: false,
+        is_set: |args| args.skip_boot_on_bad_logo,
+        reset: |args| args.skip_boot_on_bad_logo = false,
+    },
+    OptionInfo {
+        flag: "--sprite-priority",
+        risky: false,
+        is_set: |args| args.sprite_priority.is_some(),
+        reset: |args| args.sprite_priority = None,
+    },
+    OptionInfo {
+        flag: "--high-priority",
+        risky: false,
+        is_set: |args| args.high_priority,
+        reset: |args| args.high_priority = false,
+    },
+    OptionInfo {
+        flag: "--pin-core",
+        risky: false,
+        is_set: |args| args.pin_core.is_some(),
+        reset: |args| args.pin_core = None,
+    },
+    OptionInfo {
+        flag: "--resume-on-launch",
+        risky: false,
+        is_set: |args| args.resume_on_launch,
+        reset: |args| args.resume_on_launch = false,
+    },
+    // Resuming restores the state the last run exited in, which may be what crashed it
+    OptionInfo {
+        flag: "--resume",
+        risky: true,
+        is_set: |args| args.resume,
+        reset: |args| args.resume = false,
+    },
+];
+
+impl Args {
+    /// Reset each risky option that was set back to its default, returning the flags of the options
+    /// that were reset.
+    pub fn suppress_risky_options(&mut self) -> Vec<&'static str> {
+        let mut suppressed_options = vec![];
+
+        for option in OPTIONS_SCHEMA.iter().filter(|option| option.risky) {
+            if (option.is_set)(self) {
+                (option.reset)(self);
+                suppressed_options.push(option.flag);
+            }
+        }
+
+        suppressed_options
+    }
+}
+
 #[derive(Default)]
 pub struct Options {
     pub log_frames: bool,
+    pub trace: bool,
+    pub serial_log: bool,
     pub in_test_mode: bool,
+    pub ram_init: RamInit,
+    /// Seed for the emulator's RNG, which also fixes the start of its clock. Seeded from the
+    /// current time if None.
+    pub seed: Option<u64>,
+    pub screen_palette: ScreenColorPalette,
+    /// Number of frames of audio buffered by the audio output
+    pub audio_latency_frames: u32,
+    pub turbo_audio: TurboAudio,
+    /// Speed to start running at, if not normal speed
+    pub speed: Option<f64>,
+    /// Speed to run at in turbo mode, if not the default
+    pub turbo_speed: Option<f64>,
+    pub gamepad_mapping: GamepadMapping,
+    /// File that keyboard bindings are loaded from and saved to, if it can be determined
+    pub keymap_path: Option<PathBuf>,
+    /// File that the layout of the debug views is loaded from and saved to, if it can be determined
+    pub window_layout_path: Option<PathBuf>,
+    /// Number of frames between rewind snapshots, or 0 if rewinding is disabled
+    pub rewind_interval_frames: u32,
+    /// Whether accesses to echo RAM panic instead of mirroring work RAM
+    pub strict_memory: bool,
+    /// Whether invalid opcodes panic instead of locking up the CPU
+    pub panic_on_invalid: bool,
+    /// Whether the CPU can access VRAM and OAM while the PPU is using them, and all memory during
+    /// OAM DMA
+    pub no_access_restrictions: bool,
+    /// Whether memory accesses happen on the machine cycle of the instruction that performs them
+    pub cycle_accurate: bool,
+    /// Whether every tick is run on its own instead of batching ticks in which only timers advance
+    pub per_tick_stepping: bool,
+    /// Directory to write the save file to if it cannot be written to its usual location
+    pub save_fallback_dir: Option<PathBuf>,
+    /// Directory to write save files to instead of next to the ROM, if any
+    pub save_dir: Option<PathBuf>,
+    /// Whether ROMs with an invalid Nintendo logo skip the boot ROM
+    pub skip_boot_on_bad_logo: bool,
+    pub save_format: SaveFormat,
+    /// Object priority mode used for rendering instead of the one selected by OPRI, if any
+    pub force_sprite_priority: Option<SpritePriority>,
+    /// Symbol file to load labels for the debugger from, if it exists
+    pub symbols_path: Option<PathBuf>,
+    /// Flags of the risky options that were ignored because the emulator is in safe mode
+    pub suppressed_options: Vec<&'static str>,
+    /// ROM or save file given on the command line
+    pub rom_or_save_path: String,
+    /// Whether to raise the priority of the emulator and audio threads
+    pub high_priority: bool,
+    /// CPU core to pin the emulator thread to, if any
+    pub pin_core: Option<usize>,
+    /// Save the state on exit and offer to resume from it on launch
+    pub resume_on_launch: bool,
 }
 
 impl Options {
     pub fn from_args(args: &Args) -> Self {
         Options {
             log_frames: args.log_frames,
+            trace: args.trace,
+            serial_log: args.serial_log,
             in_test_mode: args.test,
+            // The seed replaces the seed of randomized RAM, so that it alone reproduces the run
+            ram_init: match (args.ram_init, args.seed) {
+                (RamInit::Random(_), Some(seed)) => RamInit::Random(seed),
+                (ram_init, _) => ram_init,
+            },
+            seed: args.seed,
+            screen_palette: args.palette,
+            audio_latency_frames: args.audio_latency,
+            turbo_audio: args.turbo_audio,
+            speed: args.speed,
+            turbo_speed: args.turbo_speed,
+            gamepad_mapping: args.gamepad_map,
+            keymap_path: match &args.keymap {
+                Some(keymap_path) => Some(keymap_path.clone()),
+                None => platform_data_dir().map(|dir| dir.join(KEY_BINDINGS_FILE_NAME)),
+            },
+            window_layout_path: platform_data_dir().map(|dir| dir.join(WINDOW_LAYOUT_FILE_NAME)),
+            rewind_interval_frames: args.rewind_interval,
+            strict_memory: args.strict_memory,
+            panic_on_invalid: args.panic_on_invalid,
+            no_access_restrictions: args.no_access_restrictions,
+            cycle_accurate: args.cycle_accurate,
+            per_tick_stepping: args.per_tick_stepping,
+            save_fallback_dir: if args.save_fallback {
+                platform_data_dir()
+            } else {
+                None
+            },
+            save_dir: args.save_dir.clone(),
+            skip_boot_on_bad_logo: args.skip_boot_on_bad_logo,
+            save_format: args.sav_format,
+            force_sprite_priority: args.sprite_priority,
+            symbols_path: Some(match &args.symbols {
+                Some(symbols_path) => PathBuf::from(symbols_path),
+                None => SymbolTable::path_for_rom(Path::new(&args.rom_or_save)),
+            }),
+            suppressed_options: vec![],
+            rom_or_save_path: args.rom_or_save.clone(),
+            high_priority: args.high_priority,
+            pin_core: args.pin_core,
+            resume_on_launch: args.resume_on_launch || args.resume,
         }
     }
 }
+
+/// Parse a speed, which must be between `MIN_SPEED` and `MAX_SPEED`.
+fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s
+        .parse()
+        .map_err(|_| format!("expected a number but found {}", s))?;
+
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(format!(
+            "speed must be between {} and {} but found {}",
+            MIN_SPEED, MAX_SPEED, s
+        ));
+    }
+
+    Ok(speed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use crate::{
+        audio::DEFAULT_AUDIO_LATENCY_FRAMES, object_priority::SpritePriority,
+        screen_palette::ScreenColorPalette,
+    };
+
+    use super::Args;
+
+    #[test]
+    fn safe_mode_suppresses_only_risky_options() {
+        let mut args = Args::parse_from([
+            "gbcemu",
+            "--bios",
+            "boot.bin",
+            "--audio-latency",
+            "8",
+            "--palette",
+            "green",
+            "game.gb",
+        ]);
+
+        assert_eq!(
+            args.suppress_risky_options(),
+            vec!["--audio-latency", "--bios"]
+        );
+        assert_eq!(args.bios, None);
+        assert_eq!(args.audio_latency, DEFAULT_AUDIO_LATENCY_FRAMES);
+
+        // Options that are not risky are kept
+        assert_eq!(args.palette, ScreenColorPalette::Green);
+        assert_eq!(args.rom_or_save, "game.gb");
+
+        // Nothing is reported when no risky options are set
+        let mut args = Args::parse_from(["gbcemu", "--palette", "green", "game.gb"]);
+        assert!(args.suppress_risky_options().is_empty());
+    }
+
+    #[test]
+    fn sprite_priority_flag() {
+        let args = Args::parse_from(["gbcemu", "--sprite-priority", "coordinate", "game.gb"]);
+        assert_eq!(args.sprite_priority, Some(SpritePriority::Coordinate));
+
+        let args = Args::parse_from(["gbcemu", "game.gb"]);
+        assert_eq!(args.sprite_priority, None);
+
+        assert!(Args::try_parse_from(["gbcemu", "--sprite-priority", "x", "game.gb"]).is_err());
+    }
+
+    #[test]
+    fn thread_priority_flags() {
+        let args = Args::parse_from(["gbcemu", "--high-priority", "--pin-core", "2", "game.gb"]);
+        assert!(args.high_priority);
+        assert_eq!(args.pin_core, Some(2));
+
+        let args = Args::parse_from(["gbcemu", "game.gb"]);
+        assert!(!args.high_priority);
+        assert_eq!(args.pin_core, None);
+
+        assert!(Args::try_parse_from(["gbcemu", "--pin-core", "-1", "game.gb"]).is_err());
+        assert!(Args::try_parse_from(["gbcemu", "--pin-core", "first", "game.gb"]).is_err());
+    }
+
+    #[test]
+    fn smoke_test_flags_do_not_need_a_rom() {
+        let args = Args::parse_from(["gbcemu", "--smoke-test-dir", "roms", "--seconds", "2.5"]);
+        assert_eq!(args.smoke_test_dir, Some(PathBuf::from("roms")));
+        assert_eq!(args.seconds, 2.5);
+        assert_eq!(args.smoke_test_out, PathBuf::from("smoke_test_report"));
+
+        assert!(Args::try_parse_from(["gbcemu"]).is_err());
+        assert!(Args::try_parse_from(["gbcemu", "--seconds", "2", "game.gb"]).is_err());
+    }
+}
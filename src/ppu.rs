@@ -1,37 +1,64 @@
-use std::{fmt::Debug, mem};
+use std::{array, fmt::Debug, mem};
 
 use eframe::egui::Color32;
 use serde::{Deserialize, Serialize};
 
-use crate::emulator::{CgbPaletteData, Emulator, SCREEN_WIDTH};
+use crate::{
+    emulator::{CgbPaletteData, Emulator, SCREEN_HEIGHT, SCREEN_WIDTH},
+    object_priority::{is_object_over_background, sort_by_priority, sprite_priority},
+};
+
+/// A generic video output which can be attached to an emulator.
+///
+/// Called on the emulator thread, so it should return quickly. See `examples/custom_video.rs` for
+/// an implementation.
+pub trait VideoSink {
+    /// Called at the end of every drawn frame with `SCREEN_HEIGHT` rows of `SCREEN_WIDTH` pixels,
+    /// with the screen palette applied. Frames that are skipped in turbo mode are not sent.
+    fn send_frame(&mut self, pixels: &[Color32]);
+}
 
 /// A sprite in OAM.
-struct Object {
-    y: u8,
-    x: u8,
-    tile_index: u8,
-    attributes: u8,
+pub struct Object {
+    /// Index of the object in OAM (0-39)
+    pub oam_index: u8,
+    pub y: u8,
+    pub x: u8,
+    pub tile_index: u8,
+    pub attributes: u8,
 }
 
 impl Object {
-    fn cgb_pallette_number(&self) -> usize {
+    /// Read the object at the given index (0-39) in OAM.
+    pub fn from_oam(oam: &[u8], index: usize) -> Self {
+        let start = index * 4;
+        Object {
+            oam_index: index as u8,
+            y: oam[start],
+            x: oam[start + 1],
+            tile_index: oam[start + 2],
+            attributes: oam[start + 3],
+        }
+    }
+
+    pub fn cgb_pallette_number(&self) -> usize {
         (self.attributes & 0x07) as usize
     }
 
-    fn vram_bank_number(&self) -> usize {
+    pub fn vram_bank_number(&self) -> usize {
         ((self.attributes & 0x08) >> 3) as usize
     }
 
     /// Whether the palette is OBP0 or OBP1
-    fn dmg_palette_number(&self) -> u8 {
+    pub fn dmg_palette_number(&self) -> u8 {
         (self.attributes & 0x10) >> 4
     }
 
-    fn is_horizontally_flipped(&self) -> bool {
+    pub fn is_horizontally_flipped(&self) -> bool {
         self.attributes & 0x20 != 0
     }
 
-    fn is_vertically_flipped(&self) -> bool {
+    pub fn is_vertically_flipped(&self) -> bool {
         self.attributes & 0x40 != 0
     }
 
@@ -46,7 +73,7 @@ impl Object {
     /// If true, object has priority to be drawn behind background.
     /// - LCDC priority flag overrides this
     /// - This is overridden by background tile's priority flag
-    fn in_background(&self) -> bool {
+    pub fn in_background(&self) -> bool {
         self.attributes & 0x80 != 0
     }
 }
@@ -57,26 +84,34 @@ pub struct BackgroundTileAttributes {
 }
 
 impl BackgroundTileAttributes {
-    fn color_palette(&self) -> usize {
+    pub fn new(raw: u8) -> Self {
+        BackgroundTileAttributes { raw }
+    }
+
+    pub fn raw(&self) -> u8 {
+        self.raw
+    }
+
+    pub fn color_palette(&self) -> usize {
         (self.raw & 0x07) as usize
     }
 
-    fn vram_bank_number(&self) -> usize {
+    pub fn vram_bank_number(&self) -> usize {
         ((self.raw & 0x08) >> 3) as usize
     }
 
-    fn is_horizontally_flipped(&self) -> bool {
+    pub fn is_horizontally_flipped(&self) -> bool {
         self.raw & 0x20 != 0
     }
 
-    fn is_vertically_flipped(&self) -> bool {
+    pub fn is_vertically_flipped(&self) -> bool {
         self.raw & 0x40 != 0
     }
 
     /// If true, background/window has priority to be drawn on top of objects.
     /// - LCDC priority flag overrides this
     /// - This overrides the object's priority flag
-    fn in_foreground(&self) -> bool {
+    pub fn in_foreground(&self) -> bool {
         self.raw & 0x80 != 0
     }
 }
@@ -92,11 +127,11 @@ fn screen_to_object_y(screen_y: u8) -> u8 {
 }
 
 /// Total number of objects in OAM.
-const NUM_OBJECTS: usize = 40;
+pub const NUM_OBJECTS: usize = 40;
 
 const MAX_OBJECTS_PER_SCANLINE: usize = 10;
 
-fn object_height(are_objects_double_size: bool) -> u8 {
+pub fn object_height(are_objects_double_size: bool) -> u8 {
     if are_objects_double_size { 16 } else { 8 }
 }
 
@@ -106,21 +141,16 @@ fn oam_scan(emulator: &Emulator, scanline: u8) -> Vec<Object> {
     let oam = &emulator.oam();
 
     for i in 0..NUM_OBJECTS {
-        let start = i * 4;
+        let object = Object::from_oam(oam, i);
 
         // Check if the object is visible on this scanline
-        let object_start_y = oam[start];
+        let object_start_y = object.y;
         let object_end_y =
             object_start_y.wrapping_add(object_height(emulator.is_lcdc_obj_double_size()));
         let scanline_y = screen_to_object_y(scanline);
 
         if (object_start_y..object_end_y).contains(&scanline_y) {
-            objects.push(Object {
-                y: object_start_y,
-                x: oam[start + 1],
-                tile_index: oam[start + 2],
-                attributes: oam[start + 3],
-            });
+            objects.push(object);
 
             if objects.len() == MAX_OBJECTS_PER_SCANLINE {
                 break;
@@ -128,16 +158,13 @@ fn oam_scan(emulator: &Emulator, scanline: u8) -> Vec<Object> {
         }
     }
 
-    // In DMG mode, sort by x coordinate (lower x has higher priority). A stable sort is used so
-    // that earlier objects in OAM have higher priority when x coordinates are equal.
-    if !emulator.is_cgb_machine() || emulator.opri() == 1 {
-        objects.sort_by_key(|obj| obj.x);
-    }
+    // Objects are drawn in priority order, with the first non-transparent object pixel winning
+    sort_by_priority(&mut objects, sprite_priority(emulator));
 
     objects
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Color {
     Dmg(DmgColor),
     Cgb(CgbColor),
@@ -159,7 +186,7 @@ impl Color {
 ///   3: Black
 pub type DmgColor = u8;
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CgbColor {
     raw: u16,
 }
@@ -179,6 +206,10 @@ impl CgbColor {
         Self { raw }
     }
 
+    pub fn raw(&self) -> u16 {
+        self.raw
+    }
+
     pub fn red(&self) -> u8 {
         (self.raw & 0x1F) as u8
     }
@@ -198,6 +229,15 @@ impl CgbColor {
             map_5_bit_color_to_8_bit(self.blue()),
         )
     }
+
+    /// The nearest CGB color to an RGB color. Inverse of `to_color32` for every CGB color.
+    pub fn from_color32(color: Color32) -> Self {
+        let red = map_8_bit_color_to_5_bit(color.r()) as u16;
+        let green = map_8_bit_color_to_5_bit(color.g()) as u16;
+        let blue = map_8_bit_color_to_5_bit(color.b()) as u16;
+
+        Self::new(red | (green << 5) | (blue << 10))
+    }
 }
 
 fn map_5_bit_color_to_8_bit(color: u8) -> u8 {
@@ -205,6 +245,10 @@ fn map_5_bit_color_to_8_bit(color: u8) -> u8 {
     (color << 3) | (color >> 2)
 }
 
+fn map_8_bit_color_to_5_bit(color: u8) -> u8 {
+    ((color as u16 * 31 + 127) / 255) as u8
+}
+
 const DMG_WHITE_COLOR: Color = Color::Dmg(0);
 
 pub enum ColorPalette {
@@ -212,7 +256,7 @@ pub enum ColorPalette {
     Cgb(u64),
 }
 
-const PALETTE_SIZE: usize = 4;
+pub const PALETTE_SIZE: usize = 4;
 
 /// Size of a single CGB color in bytes. CGB colors are stored as 15-bit RGB values.
 const CGB_COLOR_SIZE: usize = mem::size_of::<CgbColor>();
@@ -226,16 +270,16 @@ type ColorIndex = u8;
 const TRANSPARENT_COLOR_INDEX: ColorIndex = 0;
 
 /// Returns the color index of the background or window pixel at (x, y) on the screen. Also returns
-/// the background tile attributes in CGB mode.
+/// the background tile attributes in CGB mode, and whether the pixel is in the window.
 ///
 /// Returns None for color index if background and window are disabled in DMG mode.
 fn background_or_window_color_index(
     emulator: &mut Emulator,
     x: u8,
     y: u8,
-) -> (Option<ColorIndex>, Option<BackgroundTileAttributes>) {
-    if !emulator.in_cgb_mode() && !emulator.is_lcdc_dmg_bg_window_enabled() {
-        return (None, None);
+) -> (Option<ColorIndex>, Option<BackgroundTileAttributes>, bool) {
+    if !emulator.compat_mode().has_cgb_rendering() && !emulator.is_lcdc_dmg_bg_window_enabled() {
+        return (None, None, false);
     }
 
     // Find the tile map coordinates in the window if pixel is in the window
@@ -264,7 +308,7 @@ fn background_or_window_color_index(
     );
 
     // In CGB mode tile attributes are stored in a second tile map
-    let attributes = if emulator.in_cgb_mode() {
+    let attributes = if emulator.compat_mode().has_cgb_rendering() {
         Some(lookup_tile_attributes_in_tile_map(
             emulator,
             tile_map_number,
@@ -304,7 +348,7 @@ fn background_or_window_color_index(
         tile_map_coordinates.y_offset,
     );
 
-    (Some(color_index), attributes)
+    (Some(color_index), attributes, is_window)
 }
 
 /// An internal counter used for tracking the tilemap line number for the window. Only incremented
@@ -325,6 +369,10 @@ impl WindowLineCounter {
         }
     }
 
+    pub fn line(&self) -> u8 {
+        self.line
+    }
+
     /// Reset the internal counter at the start of each VBlank.
     pub fn reset(&mut self) {
         self.line = 0;
@@ -376,23 +424,25 @@ fn window_tile_map_coordinates(
     x: u8,
     y: u8,
 ) -> Option<TileMapCoordinates> {
-    // Window x register is offset by 7 to allow for specifying positions off-screen. Note that we
-    // must check for window start values that would be in the range [-7, 0) which are guaranteed to
-    // always be before the pixel.
-    let wx = emulator.wx();
-    let (window_start_x, is_window_start_x_negative) = wx.overflowing_sub(7);
-    let window_start_y = emulator.wy();
-
     // Check if the pixel is within the window both horizontally and vertically
-    if (window_start_x > x && !is_window_start_x_negative) || window_start_y > y {
+    let window_x = x as i16 - window_start_x(emulator);
+    if window_x < 0 || emulator.wy() > y {
         return None;
     }
 
-    // Final pixel index within the 256x256 window
-    let window_x = x.wrapping_sub(window_start_x);
+    // Final pixel index within the 256x256 window. The counter only advances on scanlines where
+    // a window pixel is actually drawn.
     let window_y = emulator.window_line_counter_mut().get_for_scanline(y);
 
-    Some(tile_map_coordinates(window_x, window_y))
+    Some(tile_map_coordinates(window_x as u8, window_y))
+}
+
+/// Screen column where the window starts. The window x register is offset by 7 to allow for
+/// specifying positions off-screen, so WX values below 7 start the window past the left edge of
+/// the screen and drop its first 7 - WX columns. WX values above 166 start the window past the
+/// right edge, so it is not drawn at all.
+fn window_start_x(emulator: &Emulator) -> i16 {
+    emulator.wx() as i16 - 7
 }
 
 /// Number of pixels in a row or column of a tile
@@ -456,7 +506,7 @@ fn lookup_tile_in_tile_map(emulator: &Emulator, tile_map_number: u8, tile_map_in
 }
 
 /// Lookup a tile map index to get the corresponding tile attributes (in CGB mode).
-fn lookup_tile_attributes_in_tile_map(
+pub fn lookup_tile_attributes_in_tile_map(
     emulator: &Emulator,
     tile_map_number: u8,
     tile_map_index: usize,
@@ -472,7 +522,8 @@ const TILE_DATA_2_BASE_ADDRESS: usize = 0x9000;
 /// Objects always use the first tile data area.
 const OBJECT_TILE_DATA_ADDRESSING_MODE: u8 = 1;
 
-const TILE_DATA_SIZE: usize = 16;
+/// Number of bytes of data in each tile, two for each row
+pub const TILE_DATA_SIZE: usize = 16;
 
 pub fn lookup_all_pixels_in_tile(
     emulator: &Emulator,
@@ -480,22 +531,112 @@ pub fn lookup_all_pixels_in_tile(
     tile_data_area_addressing_mode: u8,
     tile_index: u8,
 ) -> [[ColorIndex; 8]; 8] {
-    let mut pixels = [[0; 8]; 8];
+    let tile_data = lookup_tile_data(
+        emulator,
+        vram_bank_num,
+        tile_data_area_addressing_mode,
+        tile_index,
+    );
 
-    for y in 0..8 {
-        for x in 0..8 {
-            pixels[y as usize][x as usize] = lookup_color_index_in_tile(
-                emulator,
-                vram_bank_num,
-                tile_data_area_addressing_mode,
-                tile_index,
-                x,
-                y,
-            );
-        }
+    decode_tile_pixels(&tile_data)
+}
+
+/// Address of the start of a tile's data, using the tile data area provided (0 or 1).
+pub fn tile_data_address(tile_data_area_addressing_mode: u8, tile_index: u8) -> usize {
+    if tile_data_area_addressing_mode == 1 {
+        TILE_DATA_1_BASE_ADDRESS + (tile_index as usize * TILE_DATA_SIZE)
+    } else {
+        // In 0x8800 addressing mode the tile index is interpreted as signed
+        let tile_index_offset = tile_index as i8 as isize * TILE_DATA_SIZE as isize;
+        TILE_DATA_2_BASE_ADDRESS.wrapping_add_signed(tile_index_offset)
     }
+}
 
-    pixels
+/// Lookup the raw data of the specified tile.
+pub fn lookup_tile_data(
+    emulator: &Emulator,
+    vram_bank_num: usize,
+    tile_data_area_addressing_mode: u8,
+    tile_index: u8,
+) -> [u8; TILE_DATA_SIZE] {
+    let tile_data_start = tile_data_address(tile_data_area_addressing_mode, tile_index);
+    let vram_addr = Emulator::map_vram_address_in_bank(tile_data_start as u16, vram_bank_num);
+
+    emulator.vram()[vram_addr..(vram_addr + TILE_DATA_SIZE)]
+        .try_into()
+        .unwrap()
+}
+
+/// Decode the color index of every pixel in a tile from the tile's raw data.
+pub fn decode_tile_pixels(tile_data: &[u8; TILE_DATA_SIZE]) -> [[ColorIndex; 8]; 8] {
+    array::from_fn(|y| {
+        let low_byte = tile_data[y * 2];
+        let high_byte = tile_data[y * 2 + 1];
+
+        array::from_fn(|x| {
+            let mask = 1 << (7 - x);
+            let low_bit = low_byte & mask != 0;
+            let high_bit = high_byte & mask != 0;
+
+            ((high_bit as u8) << 1) | (low_bit as u8)
+        })
+    })
+}
+
+/// Lookup the color index of every pixel in an object, top row first, as it is drawn on screen with
+/// any flips applied. Objects are 8 or 16 pixels tall depending on LCDC.
+pub fn lookup_all_pixels_in_object(
+    emulator: &Emulator,
+    object: &Object,
+) -> Vec<[ColorIndex; TILE_SIZE]> {
+    let are_objects_double_size = emulator.is_lcdc_obj_double_size();
+    let height = object_height(are_objects_double_size);
+
+    // In CGB mode object attributes specify the VRAM bank
+    let vram_bank_num = if emulator.compat_mode().has_cgb_rendering() {
+        object.vram_bank_number()
+    } else {
+        0
+    };
+
+    (0..height)
+        .map(|y| {
+            let mut y_offset = if object.is_vertically_flipped() {
+                (height - 1) - y
+            } else {
+                y
+            };
+
+            // In double tile mode the lower bit of the tile index selects the top or bottom tile
+            let tile_index = if are_objects_double_size {
+                if y_offset >= 8 {
+                    y_offset -= 8;
+                    object.tile_index | 0x01
+                } else {
+                    object.tile_index & 0xFE
+                }
+            } else {
+                object.tile_index
+            };
+
+            array::from_fn(|x| {
+                let x_offset = if object.is_horizontally_flipped() {
+                    7 - x as u8
+                } else {
+                    x as u8
+                };
+
+                lookup_color_index_in_tile(
+                    emulator,
+                    vram_bank_num,
+                    OBJECT_TILE_DATA_ADDRESSING_MODE,
+                    tile_index,
+                    x_offset,
+                    y_offset,
+                )
+            })
+        })
+        .collect()
 }
 
 /// Lookup the color index at the given pixel offsets within the specified tile.
@@ -510,13 +651,7 @@ fn lookup_color_index_in_tile(
     y_offset: u8,
 ) -> ColorIndex {
     // Calculate the start of the tile data
-    let tile_data_start = if tile_data_area_addressing_mode == 1 {
-        TILE_DATA_1_BASE_ADDRESS + (tile_index as usize * TILE_DATA_SIZE)
-    } else {
-        // In 0x8800 addressing mode the tile index is interpreted as signed
-        let tile_index_offset = tile_index as i8 as isize * TILE_DATA_SIZE as isize;
-        TILE_DATA_2_BASE_ADDRESS.wrapping_add_signed(tile_index_offset)
-    };
+    let tile_data_start = tile_data_address(tile_data_area_addressing_mode, tile_index);
 
     // Each line in the tile is represented by 2 bytes
     let line_data_start = tile_data_start + (y_offset as usize * 2);
@@ -545,6 +680,50 @@ pub fn lookup_color_in_palette(palette: &ColorPalette, color_index: ColorIndex)
     }
 }
 
+/// The palette selected by a DMG palette register (BGP, OBP0, or OBP1).
+///
+/// In DMG compatibility mode on a CGB the register's 2-bit colors are indices into a CGB palette:
+/// background palette 0 for BGP, and object palettes 0 and 1 for OBP0 and OBP1.
+fn dmg_palette(
+    emulator: &Emulator,
+    dmg_palette: u8,
+    is_object: bool,
+    palette_number: usize,
+) -> ColorPalette {
+    if !emulator.compat_mode().uses_compat_palettes() {
+        return ColorPalette::Dmg(dmg_palette);
+    }
+
+    let cgb_palettes = if is_object {
+        emulator.cgb_object_palettes()
+    } else {
+        emulator.cgb_background_palettes()
+    };
+
+    let palette = (0..PALETTE_SIZE).fold(0, |palette, color_index| {
+        let dmg_color = (dmg_palette >> (color_index * 2)) & 0x03;
+        let color = lookup_cgb_color(cgb_palettes, palette_number, dmg_color as usize);
+        palette | ((color.raw() as u64) << (color_index * 16))
+    });
+
+    ColorPalette::Cgb(palette)
+}
+
+/// Lookup a single color in CGB palette data.
+pub fn lookup_cgb_color(
+    cgb_palettes: &CgbPaletteData,
+    palette_number: usize,
+    color_index: usize,
+) -> CgbColor {
+    let start = cgb_color_offset(palette_number, color_index);
+    CgbColor::new(u16::from_le_bytes([cgb_palettes[start], cgb_palettes[start + 1]]) & 0x7FFF)
+}
+
+/// Offset of a color within CGB palette data.
+pub fn cgb_color_offset(palette_number: usize, color_index: usize) -> usize {
+    palette_number * CGB_PALETTE_SIZE + color_index * CGB_COLOR_SIZE
+}
+
 fn lookup_cgb_palette(cgb_palletes: &CgbPaletteData, palette_number: usize) -> ColorPalette {
     let start = palette_number * CGB_PALETTE_SIZE;
     let palette_slice = &cgb_palletes[start..(start + CGB_PALETTE_SIZE)];
@@ -556,141 +735,1351 @@ pub fn background_color_palette(
     emulator: &Emulator,
     attributes: Option<&BackgroundTileAttributes>,
 ) -> ColorPalette {
-    // TODO: Handle CGB's DMG compatibility mode
-    if emulator.in_cgb_mode() {
+    if emulator.compat_mode().has_cgb_rendering() {
         return lookup_cgb_palette(
             emulator.cgb_background_palettes(),
             attributes.unwrap().color_palette(),
         );
     }
 
-    ColorPalette::Dmg(emulator.bgp())
+    dmg_palette(emulator, emulator.bgp(), false, 0)
 }
 
-fn object_color_palette(emulator: &Emulator, object: &Object) -> ColorPalette {
-    // TODO: Handle CGB's DMG compatibility mode
-    if emulator.in_cgb_mode() {
+/// The palette an object is drawn with: one of the CGB object palettes in CGB mode, otherwise OBP0
+/// or OBP1.
+pub fn object_color_palette(emulator: &Emulator, object: &Object) -> ColorPalette {
+    if emulator.compat_mode().has_cgb_rendering() {
         return lookup_cgb_palette(emulator.cgb_object_palettes(), object.cgb_pallette_number());
     }
 
-    if object.dmg_palette_number() == 0 {
-        ColorPalette::Dmg(emulator.obp0())
+    let palette_number = object.dmg_palette_number();
+    let obp = if palette_number == 0 {
+        emulator.obp0()
+    } else {
+        emulator.obp1()
+    };
+
+    dmg_palette(emulator, obp, true, palette_number as usize)
+}
+
+/// Returns the color of the pixel at (x, y) within the full 256x256 tile map, ignoring scroll and
+/// the window. Uses the current tile data addressing mode and background palettes.
+pub fn tile_map_pixel_color(emulator: &Emulator, tile_map_number: u8, x: u8, y: u8) -> Color {
+    let mut coordinates = tile_map_coordinates(x, y);
+
+    let tile_index = lookup_tile_in_tile_map(emulator, tile_map_number, coordinates.tile_map_index);
+    let attributes = if emulator.compat_mode().has_cgb_rendering() {
+        Some(lookup_tile_attributes_in_tile_map(
+            emulator,
+            tile_map_number,
+            coordinates.tile_map_index,
+        ))
     } else {
-        ColorPalette::Dmg(emulator.obp1())
+        None
+    };
+
+    let mut vram_bank_num = 0;
+    if let Some(attributes) = attributes.as_ref() {
+        if attributes.is_horizontally_flipped() {
+            coordinates.x_offset = 7 - coordinates.x_offset;
+        }
+
+        if attributes.is_vertically_flipped() {
+            coordinates.y_offset = 7 - coordinates.y_offset;
+        }
+
+        vram_bank_num = attributes.vram_bank_number();
     }
+
+    let color_index = lookup_color_index_in_tile(
+        emulator,
+        vram_bank_num,
+        emulator.lcdc_bg_window_tile_data_addressing_mode(),
+        tile_index,
+        coordinates.x_offset,
+        coordinates.y_offset,
+    );
+
+    let palette = background_color_palette(emulator, attributes.as_ref());
+    lookup_color_in_palette(&palette, color_index)
 }
 
-pub fn draw_scanline(emulator: &mut Emulator, scanline: u8) {
-    // Find the first 10 objects that intersect with this scanline
-    let objects = oam_scan(emulator, scanline);
+/// A palette color, along with how it is displayed with the screen palette applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaletteColor {
+    pub color: Color,
+    pub color32: Color32,
+}
 
-    let are_objects_double_size = emulator.is_lcdc_obj_double_size();
-    let object_height = object_height(are_objects_double_size);
+/// The colors of a palette, indexed by color index.
+pub type PaletteTable = [PaletteColor; PALETTE_SIZE];
+
+pub const NUM_CGB_PALETTES: usize = 8;
+
+/// Every palette decoded into a table of colors, so that finding the color of a pixel is a single
+/// index. Rebuilt at the start of a scanline, but only when a palette register or the screen palette
+/// has changed since the last rebuild.
+pub struct PaletteCache {
+    is_dirty: bool,
+    /// Color of pixels where the background and window are disabled in DMG mode
+    white: PaletteColor,
+    bgp: PaletteTable,
+    obp: [PaletteTable; 2],
+    cgb_background: [PaletteTable; NUM_CGB_PALETTES],
+    cgb_object: [PaletteTable; NUM_CGB_PALETTES],
+}
 
-    for x in 0..(SCREEN_WIDTH as u8) {
-        let (background_color_index, background_attributes) =
-            background_or_window_color_index(emulator, x, scanline);
-        let background_palette = background_color_palette(emulator, background_attributes.as_ref());
+impl PaletteCache {
+    /// An empty cache that is rebuilt before first use.
+    pub fn new() -> Self {
+        let white = PaletteColor {
+            color: DMG_WHITE_COLOR,
+            color32: Color32::WHITE,
+        };
 
-        let mut final_color_index_and_palette = (background_color_index, background_palette);
+        PaletteCache {
+            is_dirty: true,
+            white,
+            bgp: [white; PALETTE_SIZE],
+            obp: [[white; PALETTE_SIZE]; 2],
+            cgb_background: [[white; PALETTE_SIZE]; NUM_CGB_PALETTES],
+            cgb_object: [[white; PALETTE_SIZE]; NUM_CGB_PALETTES],
+        }
+    }
 
-        if emulator.is_lcdc_obj_enabled() {
-            for object in &objects {
-                let current_object_x = screen_to_object_x(x);
-                let current_object_y = screen_to_object_y(scanline);
+    pub fn mark_dirty(&mut self) {
+        self.is_dirty = true;
+    }
 
-                // Check if object intersects the current x coordinate
-                if current_object_x < object.x || current_object_x >= object.x + 8 {
-                    continue;
-                }
+    fn build(emulator: &Emulator) -> Self {
+        let screen_palette = emulator.screen_palette();
+        let to_palette_color = |color: Color| PaletteColor {
+            color,
+            color32: screen_palette.color_to_color32(color),
+        };
+        let decode = |palette: ColorPalette| -> PaletteTable {
+            array::from_fn(|i| to_palette_color(lookup_color_in_palette(&palette, i as ColorIndex)))
+        };
 
-                // Find the offsets within the object's tile
-                let x_offset = if object.is_horizontally_flipped() {
-                    7 - (current_object_x - object.x)
-                } else {
-                    current_object_x - object.x
-                };
+        PaletteCache {
+            is_dirty: false,
+            white: to_palette_color(DMG_WHITE_COLOR),
+            bgp: decode(dmg_palette(emulator, emulator.bgp(), false, 0)),
+            obp: [
+                decode(dmg_palette(emulator, emulator.obp0(), true, 0)),
+                decode(dmg_palette(emulator, emulator.obp1(), true, 1)),
+            ],
+            cgb_background: array::from_fn(|i| {
+                decode(lookup_cgb_palette(emulator.cgb_background_palettes(), i))
+            }),
+            cgb_object: array::from_fn(|i| {
+                decode(lookup_cgb_palette(emulator.cgb_object_palettes(), i))
+            }),
+        }
+    }
 
-                let mut y_offset = if object.is_vertically_flipped() {
-                    (object_height - 1) - (current_object_y - object.y)
-                } else {
-                    current_object_y - object.y
-                };
+    fn background_table(
+        &self,
+        in_cgb_mode: bool,
+        attributes: Option<&BackgroundTileAttributes>,
+    ) -> &PaletteTable {
+        if in_cgb_mode {
+            return &self.cgb_background[attributes.unwrap().color_palette()];
+        }
 
-                let tile_index = if are_objects_double_size {
-                    // In double tile mode the lower bit of the tile index is ignored and must be
-                    // set to 1 to access the second tile if pixel appears in the second tile.
-                    if y_offset >= 8 {
-                        y_offset -= 8;
-                        object.tile_index | 0x01
-                    } else {
-                        object.tile_index & 0xFE
-                    }
-                } else {
-                    object.tile_index
-                };
+        &self.bgp
+    }
 
-                // In CGB mode object attributes specify the VRAM bank
-                let vram_bank_num = if emulator.in_cgb_mode() {
-                    object.vram_bank_number()
-                } else {
-                    0
-                };
+    fn object_table(&self, in_cgb_mode: bool, object: &Object) -> &PaletteTable {
+        if in_cgb_mode {
+            return &self.cgb_object[object.cgb_pallette_number()];
+        }
 
-                // Find the color index for the pixel at those offsets in the tile
-                let object_color_index = lookup_color_index_in_tile(
-                    emulator,
-                    vram_bank_num,
-                    OBJECT_TILE_DATA_ADDRESSING_MODE,
-                    tile_index,
-                    x_offset,
-                    y_offset,
-                );
+        &self.obp[object.dmg_palette_number() as usize]
+    }
+}
 
-                // If the object's color index is transparent then search for the next object
-                if object_color_index == TRANSPARENT_COLOR_INDEX {
-                    continue;
-                }
+/// Rebuild the palette cache if any palette has changed.
+fn refresh_palette_cache(emulator: &mut Emulator) {
+    if emulator.palette_cache().is_dirty {
+        *emulator.palette_cache_mut() = PaletteCache::build(emulator);
+    }
+}
 
-                // Background attributes are only present in CGB mode
-                let is_object_on_top = if let Some(background_attributes) =
-                    background_attributes.as_ref()
-                {
-                    // Object is drawn on top of transparent background
-                    matches!(background_color_index, Some(TRANSPARENT_COLOR_INDEX)) ||
-                    // Object is drawn on top if lcdc priority flag forces bg/window behind objects
-                    !emulator.is_lcdc_cgb_bg_window_priority() ||
-                    // Object in background and bg/window in foreground flags are considered, with
-                    // bg/window flag overriding when necessary.
-                    (!object.in_background() && !background_attributes.in_foreground())
-                } else {
-                    // Object is not transparent so it will always be rendered unless flagged to be
-                    // in the background and the background is non-transparent.
-                    !object.in_background()
-                        || matches!(background_color_index, None | Some(TRANSPARENT_COLOR_INDEX))
-                };
+/// The layer that a pixel on the screen was drawn from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelLayer {
+    /// Background and window are disabled in DMG mode, so the pixel is white
+    #[default]
+    None,
+    Background,
+    Window,
+    /// The object with the given index in OAM
+    Object(u8),
+}
+
+/// Where a pixel on the screen came from, for debug views that explain how a frame was drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PixelProvenance {
+    pub layer: PixelLayer,
+    /// Palette the color was looked up in. One of the 8 CGB palettes in CGB mode. In DMG mode 0 for
+    /// BGP and OBP0, or 1 for OBP1.
+    pub palette: u8,
+    /// Index of the color in the palette
+    pub color_index: ColorIndex,
+}
+
+/// Provenance of every pixel on the screen, indexed by row then column.
+pub type PixelProvenanceBuffer = [[PixelProvenance; SCREEN_WIDTH]; SCREEN_HEIGHT];
+
+/// Number of pixels drawn from each background fetch.
+const PIXELS_PER_FETCH: usize = 8;
+
+/// Number of groups of pixels fetched for each scanline.
+const NUM_FETCH_GROUPS: usize = SCREEN_WIDTH / PIXELS_PER_FETCH;
+
+/// Draws a single scanline over the course of Draw mode, one group of 8 pixels at a time.
+///
+/// SCX, SCY, LCDC, and WX are read separately for each group of pixels, so that writes made
+/// partway through Draw mode affect the rest of the scanline. Groups are drawn at evenly spaced
+/// ticks across Draw mode. Objects and palettes are latched when the scanline starts.
+pub struct ScanlineRenderer {
+    scanline: u8,
+    /// Objects found by the OAM scan for this scanline
+    objects: Vec<Object>,
+    in_cgb_mode: bool,
+    are_objects_double_size: bool,
+    object_height: u8,
+    /// Length of Draw mode for this scanline
+    draw_ticks: usize,
+    /// The next group of pixels to draw
+    next_group: usize,
+}
+
+impl ScanlineRenderer {
+    /// Start drawing a scanline at the beginning of Draw mode. No pixels are drawn until
+    /// `draw_until` is called.
+    pub fn start(emulator: &mut Emulator, scanline: u8) -> Self {
+        // Find the first 10 objects that intersect with this scanline
+        let objects = oam_scan(emulator, scanline);
+
+        // Palettes cannot change while drawing a scanline. Palette writes only mark the cache as
+        // dirty, so it is not rebuilt until the next scanline starts.
+        refresh_palette_cache(emulator);
+
+        let has_window = is_window_visible_on_scanline(emulator, scanline);
+        let draw_ticks = draw_mode_ticks(emulator, &objects, has_window);
+
+        let are_objects_double_size = emulator.is_lcdc_obj_double_size();
+
+        ScanlineRenderer {
+            scanline,
+            objects,
+            in_cgb_mode: emulator.compat_mode().has_cgb_rendering(),
+            are_objects_double_size,
+            object_height: object_height(are_objects_double_size),
+            draw_ticks,
+            next_group: 0,
+        }
+    }
 
-                if is_object_on_top {
-                    let object_palette = object_color_palette(emulator, object);
-                    final_color_index_and_palette = (Some(object_color_index), object_palette);
+    /// Number of ticks that Draw mode lasts for this scanline.
+    pub fn draw_ticks(&self) -> usize {
+        self.draw_ticks
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_group == NUM_FETCH_GROUPS
+    }
+
+    /// Tick within Draw mode at which the next group of pixels is drawn, or `usize::MAX` if every
+    /// group has been drawn.
+    pub fn next_group_tick(&self) -> usize {
+        if self.is_finished() {
+            return usize::MAX;
+        }
+
+        self.next_group * self.draw_ticks / NUM_FETCH_GROUPS
+    }
+
+    /// Draw every group of pixels that is due by the given tick within Draw mode.
+    pub fn draw_until(&mut self, emulator: &mut Emulator, draw_tick: usize) {
+        while !self.is_finished() && self.next_group_tick() <= draw_tick {
+            let group_start = self.next_group * PIXELS_PER_FETCH;
+            for x in group_start..(group_start + PIXELS_PER_FETCH) {
+                self.draw_pixel(emulator, x as u8);
+            }
+
+            self.next_group += 1;
+        }
+    }
+
+    /// Draw all remaining pixels on the scanline.
+    pub fn finish(&mut self, emulator: &mut Emulator) {
+        self.draw_until(emulator, usize::MAX);
+    }
+
+    /// The highest priority object with a non-transparent pixel at the given x coordinate, along
+    /// with that pixel's color index. Background priority is not considered.
+    fn top_object_pixel(&self, emulator: &Emulator, x: u8) -> Option<(&Object, ColorIndex)> {
+        if !emulator.is_lcdc_obj_enabled() {
+            return None;
+        }
+
+        let current_object_x = screen_to_object_x(x);
+        let current_object_y = screen_to_object_y(self.scanline);
+
+        for object in &self.objects {
+            // Check if object intersects the current x coordinate
+            if current_object_x < object.x || current_object_x >= object.x + 8 {
+                continue;
+            }
+
+            // Find the offsets within the object's tile
+            let x_offset = if object.is_horizontally_flipped() {
+                7 - (current_object_x - object.x)
+            } else {
+                current_object_x - object.x
+            };
+
+            let mut y_offset = if object.is_vertically_flipped() {
+                (self.object_height - 1) - (current_object_y - object.y)
+            } else {
+                current_object_y - object.y
+            };
+
+            let tile_index = if self.are_objects_double_size {
+                // In double tile mode the lower bit of the tile index is ignored and must be set to
+                // 1 to access the second tile if pixel appears in the second tile.
+                if y_offset >= 8 {
+                    y_offset -= 8;
+                    object.tile_index | 0x01
+                } else {
+                    object.tile_index & 0xFE
                 }
+            } else {
+                object.tile_index
+            };
+
+            // In CGB mode object attributes specify the VRAM bank
+            let vram_bank_num = if self.in_cgb_mode {
+                object.vram_bank_number()
+            } else {
+                0
+            };
+
+            // Find the color index for the pixel at those offsets in the tile
+            let object_color_index = lookup_color_index_in_tile(
+                emulator,
+                vram_bank_num,
+                OBJECT_TILE_DATA_ADDRESSING_MODE,
+                tile_index,
+                x_offset,
+                y_offset,
+            );
 
-                // Always stop after we find a non-transparent object pixel, even if the background
-                // should be draw on top of this object pixel.
-                break;
+            // The first non-transparent object pixel wins, otherwise search for the next object
+            if object_color_index != TRANSPARENT_COLOR_INDEX {
+                return Some((object, object_color_index));
             }
         }
 
-        // Finally lookup color from the palette
-        let (color_index, palette) = final_color_index_and_palette;
+        None
+    }
 
+    fn draw_pixel(&self, emulator: &mut Emulator, x: u8) {
+        let (background_color_index, background_attributes, is_window) =
+            background_or_window_color_index(emulator, x, self.scanline);
+
+        // The winning object is chosen before background priority is applied, so a winning object
+        // behind the background hides lower priority objects as well
+        let top_object = self.top_object_pixel(emulator, x).filter(|(object, _)| {
+            is_object_over_background(
+                object,
+                background_color_index,
+                background_attributes.as_ref(),
+                emulator.is_lcdc_cgb_bg_window_priority(),
+            )
+        });
+
+        let palettes = emulator.palette_cache();
+        let (color_index, palette) = match top_object {
+            Some((object, object_color_index)) => (
+                Some(object_color_index),
+                palettes.object_table(self.in_cgb_mode, object),
+            ),
+            None => (
+                background_color_index,
+                palettes.background_table(self.in_cgb_mode, background_attributes.as_ref()),
+            ),
+        };
+
+        // Finally lookup color from the palette
         let color = if let Some(color_index) = color_index {
-            lookup_color_in_palette(&palette, color_index)
+            // Masking the index lets the compiler drop the bounds check from this hot loop
+            palette[(color_index & 0x03) as usize]
         } else {
             // No color so pixel defaults to white. This can only occur in DMG mode.
-            DMG_WHITE_COLOR
+            palettes.white
         };
 
-        emulator.write_color(x, scanline, color);
+        emulator.write_color(x, self.scanline, color);
+
+        if let Some(provenance) = emulator.pixel_provenance_mut() {
+            let (layer, palette) = match top_object {
+                Some((object, _)) if self.in_cgb_mode => (
+                    PixelLayer::Object(object.oam_index),
+                    object.cgb_pallette_number() as u8,
+                ),
+                Some((object, _)) => (
+                    PixelLayer::Object(object.oam_index),
+                    object.dmg_palette_number(),
+                ),
+                None if color_index.is_none() => (PixelLayer::None, 0),
+                None => (
+                    if is_window {
+                        PixelLayer::Window
+                    } else {
+                        PixelLayer::Background
+                    },
+                    background_attributes
+                        .as_ref()
+                        .map_or(0, |attributes| attributes.color_palette() as u8),
+                ),
+            };
+
+            provenance[self.scanline as usize][x as usize] = PixelProvenance {
+                layer,
+                palette,
+                color_index: color_index.unwrap_or(TRANSPARENT_COLOR_INDEX),
+            };
+        }
+    }
+}
+
+/// Draw an entire scanline to the screen at once, returning the number of ticks that Draw mode lasts
+/// for this scanline.
+pub fn draw_scanline(emulator: &mut Emulator, scanline: u8) -> usize {
+    let mut renderer = ScanlineRenderer::start(emulator, scanline);
+    renderer.finish(emulator);
+
+    renderer.draw_ticks()
+}
+
+/// Whether any pixel on the scanline will be in the window, assuming the window registers do not
+/// change while the scanline is drawn.
+fn is_window_visible_on_scanline(emulator: &Emulator, scanline: u8) -> bool {
+    let is_bg_window_visible =
+        emulator.compat_mode().has_cgb_rendering() || emulator.is_lcdc_dmg_bg_window_enabled();
+    let is_window_on_screen = window_start_x(emulator) < SCREEN_WIDTH as i16;
+
+    is_bg_window_visible
+        && emulator.is_lcdc_window_enabled()
+        && is_window_on_screen
+        && emulator.wy() <= scanline
+}
+
+/// Process a scanline like `draw_scanline` without writing any pixels, for frames that will never
+/// be displayed. Advances the window line counter and returns the length of Draw mode exactly as
+/// drawing the scanline would.
+pub fn skip_scanline(emulator: &mut Emulator, scanline: u8) -> usize {
+    let objects = oam_scan(emulator, scanline);
+
+    // The window line counter advances if any pixel on the scanline is in the window
+    let has_window = is_window_visible_on_scanline(emulator, scanline);
+    if has_window {
+        emulator
+            .window_line_counter_mut()
+            .get_for_scanline(scanline);
+    }
+
+    draw_mode_ticks(emulator, &objects, has_window)
+}
+
+/// Minimum number of ticks in Draw mode, when no penalties apply.
+pub const MIN_DRAW_TICKS: usize = 172;
+
+/// Maximum number of ticks in Draw mode.
+pub const MAX_DRAW_TICKS: usize = 289;
+
+/// Calculate the length of Draw mode for a scanline, given whether the window is drawn on it.
+///
+/// Draw mode is extended by discarding pixels for fine scroll, by restarting the fetcher when the
+/// window starts, and by fetching each object. Objects also stall the background fetcher for the
+/// rest of the background tile they overlap, but only for the first object on each tile.
+fn draw_mode_ticks(emulator: &Emulator, objects: &[Object], has_window: bool) -> usize {
+    let fine_scroll = (emulator.scx() % 8) as usize;
+    let mut ticks = MIN_DRAW_TICKS + fine_scroll;
+
+    if has_window {
+        ticks += 6;
+    }
+
+    if emulator.is_lcdc_obj_enabled() {
+        // Background tiles that have already been stalled by an object fetch, indexed by column.
+        // Includes the partial tiles at either end of the scanline.
+        let mut is_tile_stalled = [false; SCREEN_WIDTH / TILE_SIZE + 2];
+
+        for object in objects {
+            // Objects past the right edge of the screen are never fetched
+            if object.x as usize >= SCREEN_WIDTH + 8 {
+                continue;
+            }
+
+            ticks += 6;
+
+            let background_x = object.x as usize + fine_scroll;
+            let tile_column = background_x / TILE_SIZE;
+            if !is_tile_stalled[tile_column] {
+                is_tile_stalled[tile_column] = true;
+                ticks += 5 - (background_x % TILE_SIZE).min(5);
+            }
+        }
+    }
+
+    ticks.min(MAX_DRAW_TICKS)
+}
+
+/// Number of buckets in the histogram of Draw mode lengths.
+pub const DRAW_TICKS_HISTOGRAM_SIZE: usize = 8;
+
+/// Distribution of Draw mode lengths over the scanlines of a single frame. Shows how close a game
+/// is to exhausting its HBlank budget.
+#[derive(Clone, Copy)]
+pub struct DrawTimingMetrics {
+    /// Number of scanlines in each bucket. Buckets evenly divide the range from `MIN_DRAW_TICKS` to
+    /// `MAX_DRAW_TICKS` inclusive.
+    pub histogram: [u8; DRAW_TICKS_HISTOGRAM_SIZE],
+    /// Number of scanlines where Draw mode lasted for `MAX_DRAW_TICKS`
+    pub num_scanlines_at_max: u8,
+    /// Longest Draw mode in the frame
+    pub max_draw_ticks: usize,
+}
+
+impl DrawTimingMetrics {
+    pub fn new() -> Self {
+        Self {
+            histogram: [0; DRAW_TICKS_HISTOGRAM_SIZE],
+            num_scanlines_at_max: 0,
+            max_draw_ticks: 0,
+        }
+    }
+
+    pub fn record_scanline(&mut self, draw_ticks: usize) {
+        self.histogram[Self::bucket_for_ticks(draw_ticks)] += 1;
+        self.max_draw_ticks = self.max_draw_ticks.max(draw_ticks);
+
+        if draw_ticks >= MAX_DRAW_TICKS {
+            self.num_scanlines_at_max += 1;
+        }
+    }
+
+    pub fn bucket_for_ticks(draw_ticks: usize) -> usize {
+        let range = MAX_DRAW_TICKS - MIN_DRAW_TICKS + 1;
+        let offset = draw_ticks.clamp(MIN_DRAW_TICKS, MAX_DRAW_TICKS) - MIN_DRAW_TICKS;
+        offset * DRAW_TICKS_HISTOGRAM_SIZE / range
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        emulator::{Emulator, SCREEN_WIDTH},
+        machine::{CompatMode, Machine},
+        object_priority::{SpritePriority, sprite_priority},
+        options::Options,
+        test_utils::{
+            build_cgb_test_rom, build_test_rom, new_test_emulator, new_test_emulator_builder,
+            with_large_stack, write_header_checksum,
+        },
+    };
+
+    use eframe::egui::Color32;
+
+    use super::{
+        CgbColor, Color, DrawTimingMetrics, MIN_DRAW_TICKS, Object, PaletteCache, PixelLayer,
+        PixelProvenance, decode_tile_pixels, draw_scanline, lookup_all_pixels_in_object,
+        lookup_cgb_color, lookup_color_in_palette, lookup_color_index_in_tile, lookup_tile_data,
+        object_color_palette, skip_scanline, tile_data_address,
+    };
+
+    /// Emulator that loops forever after booting, with objects enabled.
+    fn new_idle_emulator() -> Emulator {
+        let mut emulator = new_test_emulator(Machine::Dmg, &[0x18, 0xFE]);
+
+        let lcdc = emulator.lcdc();
+        emulator.write_lcdc(lcdc | 0x02);
+
+        emulator
+    }
+
+    /// Place 10 objects on the first 8 scanlines, spread across the screen.
+    fn place_objects(emulator: &mut Emulator) {
+        for i in 0..10 {
+            // Written directly since OAM cannot be accessed while scanning OAM after boot
+            emulator.write_memory_bulk(0xFE00 + i * 4, &[16, 8 + (i as u8) * 16]);
+        }
+    }
+
+    #[test]
+    fn objects_extend_draw_mode() {
+        with_large_stack(|| {
+            let mut emulator = new_idle_emulator();
+            place_objects(&mut emulator);
+
+            let empty_scanline_ticks = draw_scanline(&mut emulator, 50);
+            let object_scanline_ticks = draw_scanline(&mut emulator, 0);
+
+            assert_eq!(empty_scanline_ticks, MIN_DRAW_TICKS);
+            assert_eq!(object_scanline_ticks, MIN_DRAW_TICKS + 10 * 11);
+        });
+    }
+
+    #[test]
+    fn skip_scanline_matches_draw_scanline() {
+        with_large_stack(|| {
+            for (wx, wy) in [(60, 40), (0, 0), (166, 0), (167, 0), (60, 144)] {
+                let mut drawn_emulator = new_idle_emulator();
+                place_objects(&mut drawn_emulator);
+
+                let lcdc = drawn_emulator.lcdc();
+                drawn_emulator.write_lcdc(lcdc | 0x20);
+                drawn_emulator.write_address(0xFF4A, wy);
+                drawn_emulator.write_address(0xFF4B, wx);
+
+                let mut skipped_emulator = new_idle_emulator();
+                place_objects(&mut skipped_emulator);
+                skipped_emulator.write_lcdc(lcdc | 0x20);
+                skipped_emulator.write_address(0xFF4A, wy);
+                skipped_emulator.write_address(0xFF4B, wx);
+
+                for scanline in 0..144 {
+                    assert_eq!(
+                        skip_scanline(&mut skipped_emulator, scanline),
+                        draw_scanline(&mut drawn_emulator, scanline),
+                        "WX={} WY={} scanline {}",
+                        wx,
+                        wy,
+                        scanline
+                    );
+                    assert_eq!(
+                        skipped_emulator.window_line_counter().line(),
+                        drawn_emulator.window_line_counter().line()
+                    );
+                }
+            }
+        });
+    }
+
+    /// An emulator with the window enabled at the given WX and WY=0. Background pixels are color 0,
+    /// while each window tile has color 1 in its first column and color 3 in the others.
+    fn new_window_emulator(wx: u8) -> Emulator {
+        let mut emulator = new_idle_emulator();
+
+        // Tile 1 has color index 1 in the first column and 3 elsewhere
+        for row in 0..8 {
+            emulator.write_address(0x8010 + row * 2, 0xFF);
+            emulator.write_address(0x8010 + row * 2 + 1, 0x7F);
+        }
+
+        // Background uses the first tile map which is all tile 0, window uses the second tile map
+        // which is all tile 1.
+        for i in 0..0x400 {
+            emulator.write_address(0x9C00 + i, 0x01);
+        }
+
+        let lcdc = emulator.lcdc();
+        emulator.write_lcdc(lcdc | 0x60);
+        emulator.write_bgp(0b11_10_01_00);
+        emulator.write_address(0xFF4A, 0);
+        emulator.write_address(0xFF4B, wx);
+
+        emulator
+    }
+
+    fn scanline_pixels(emulator: &Emulator, scanline: usize) -> Vec<u8> {
+        (0..SCREEN_WIDTH)
+            .map(|x| emulator.read_pixel(x, scanline).unwrap_dmg())
+            .collect()
+    }
+
+    #[test]
+    fn window_horizontal_position() {
+        with_large_stack(|| {
+            for wx in [0, 6, 7, 165, 166, 167] {
+                let mut emulator = new_window_emulator(wx);
+                draw_scanline(&mut emulator, 0);
+
+                // Columns before the window start show the background. The window starts at column
+                // WX - 7, and for WX < 7 the columns cut off by the left edge are skipped.
+                let expected: Vec<u8> = (0..SCREEN_WIDTH as i16)
+                    .map(|x| {
+                        let window_x = x - (wx as i16 - 7);
+                        if window_x < 0 {
+                            0
+                        } else if window_x % 8 == 0 {
+                            1
+                        } else {
+                            3
+                        }
+                    })
+                    .collect();
+
+                assert_eq!(scanline_pixels(&emulator, 0), expected, "WX={}", wx);
+            }
+
+            // Spot check the edges
+            let mut emulator = new_window_emulator(0);
+            draw_scanline(&mut emulator, 0);
+            assert_eq!(scanline_pixels(&emulator, 0)[..2], [3, 1]);
+
+            let mut emulator = new_window_emulator(166);
+            draw_scanline(&mut emulator, 0);
+            assert_eq!(scanline_pixels(&emulator, 0)[158..], [0, 1]);
+        });
+    }
+
+    #[test]
+    fn window_line_counter_only_advances_when_drawn() {
+        with_large_stack(|| {
+            // Window is off screen for the first 10 scanlines
+            let mut emulator = new_window_emulator(167);
+            for scanline in 0..10 {
+                draw_scanline(&mut emulator, scanline);
+            }
+            assert_eq!(emulator.window_line_counter().line(), 0);
+
+            // The first scanline with window pixels draws window line 0
+            emulator.write_address(0xFF4B, 166);
+            draw_scanline(&mut emulator, 10);
+            assert_eq!(emulator.window_line_counter().line(), 0);
+
+            draw_scanline(&mut emulator, 11);
+            assert_eq!(emulator.window_line_counter().line(), 1);
+
+            // Moving the window off screen pauses the counter
+            emulator.write_address(0xFF4B, 200);
+            draw_scanline(&mut emulator, 12);
+            emulator.write_address(0xFF4B, 0);
+            draw_scanline(&mut emulator, 13);
+            assert_eq!(emulator.window_line_counter().line(), 2);
+        });
+    }
+
+    #[test]
+    fn pixel_provenance_for_each_layer() {
+        with_large_stack(|| {
+            // Window starts at column 80, and object 3 uses OBP1 with tile 1 at column 16
+            let mut emulator = new_window_emulator(87);
+            emulator.write_memory_bulk(0xFE00 + 3 * 4, &[16, 24, 1, 0x10]);
+
+            // Nothing is recorded until enabled
+            draw_scanline(&mut emulator, 0);
+            assert_eq!(emulator.pixel_provenance(0, 0), None);
+
+            emulator.set_record_pixel_provenance(true);
+            draw_scanline(&mut emulator, 0);
+
+            let provenance = |layer, palette, color_index| {
+                Some(PixelProvenance {
+                    layer,
+                    palette,
+                    color_index,
+                })
+            };
+
+            assert_eq!(
+                emulator.pixel_provenance(0, 0),
+                provenance(PixelLayer::Background, 0, 0)
+            );
+            assert_eq!(
+                emulator.pixel_provenance(16, 0),
+                provenance(PixelLayer::Object(3), 1, 1)
+            );
+            assert_eq!(
+                emulator.pixel_provenance(17, 0),
+                provenance(PixelLayer::Object(3), 1, 3)
+            );
+            assert_eq!(
+                emulator.pixel_provenance(24, 0),
+                provenance(PixelLayer::Background, 0, 0)
+            );
+            assert_eq!(
+                emulator.pixel_provenance(80, 0),
+                provenance(PixelLayer::Window, 0, 1)
+            );
+            assert_eq!(
+                emulator.pixel_provenance(81, 0),
+                provenance(PixelLayer::Window, 0, 3)
+            );
+
+            // Pixels are not drawn from any layer when background and window are disabled
+            let lcdc = emulator.lcdc();
+            emulator.write_lcdc(lcdc & !0x01);
+            draw_scanline(&mut emulator, 0);
+            assert_eq!(
+                emulator.pixel_provenance(0, 0),
+                provenance(PixelLayer::None, 0, 0)
+            );
+            assert_eq!(
+                emulator.pixel_provenance(16, 0),
+                provenance(PixelLayer::Object(3), 1, 1)
+            );
+
+            emulator.set_record_pixel_provenance(false);
+            draw_scanline(&mut emulator, 0);
+            assert_eq!(emulator.pixel_provenance(0, 0), None);
+        });
+    }
+
+    #[test]
+    fn frame_metrics_histogram() {
+        with_large_stack(|| {
+            let mut emulator = new_idle_emulator();
+            place_objects(&mut emulator);
+
+            emulator.run_frame();
+            emulator.run_frame();
+
+            let metrics = emulator.draw_timing_metrics();
+            let object_bucket = DrawTimingMetrics::bucket_for_ticks(MIN_DRAW_TICKS + 10 * 11);
+
+            assert_eq!(metrics.histogram[0], 144 - 8);
+            assert_eq!(metrics.histogram[object_bucket], 8);
+            assert_eq!(metrics.max_draw_ticks, MIN_DRAW_TICKS + 10 * 11);
+            assert_eq!(metrics.num_scanlines_at_max, 0);
+        });
+    }
+
+    #[test]
+    fn palette_cache_tracks_palette_writes() {
+        with_large_stack(|| {
+            let mut emulator = new_idle_emulator();
+
+            // VRAM is empty so every background pixel has color index 0
+            emulator.write_bgp(0b00_01_10_11);
+            draw_scanline(&mut emulator, 0);
+            assert_eq!(emulator.read_pixel(0, 0).unwrap_dmg(), 3);
+
+            emulator.write_bgp(0b11_10_01_00);
+            draw_scanline(&mut emulator, 0);
+            assert_eq!(emulator.read_pixel(0, 0).unwrap_dmg(), 0);
+
+            // Raw writes bypass register handlers but still invalidate the cache
+            emulator.write_memory_bulk(0xFF47, &[0b00_00_00_10]);
+            draw_scanline(&mut emulator, 0);
+            assert_eq!(emulator.read_pixel(0, 0).unwrap_dmg(), 2);
+        });
+    }
+
+    #[test]
+    fn palette_cache_decodes_cgb_palettes() {
+        with_large_stack(|| {
+            let mut emulator = new_idle_emulator();
+
+            // Color 2 of background palette 5 and color 3 of object palette 7
+            emulator.cgb_background_palettes_mut()[5 * 8 + 4..5 * 8 + 6]
+                .copy_from_slice(&0x7C1Fu16.to_le_bytes());
+            emulator.cgb_object_palettes_mut()[7 * 8 + 6..7 * 8 + 8]
+                .copy_from_slice(&0xFFFFu16.to_le_bytes());
+
+            let cache = PaletteCache::build(&emulator);
+            assert!(
+                matches!(cache.cgb_background[5][2].color, Color::Cgb(color) if color.raw() == 0x7C1F)
+            );
+            // Top bit is not part of the color
+            assert!(
+                matches!(cache.cgb_object[7][3].color, Color::Cgb(color) if color.raw() == 0x7FFF)
+            );
+        });
+    }
+
+    #[test]
+    fn dmg_compatibility_mode_uses_compat_palettes() {
+        with_large_stack(|| {
+            let mut rom = build_test_rom(0x00, 0x00, 0x00, &[0x18, 0xFE]);
+            rom[0x0134..0x0144].copy_from_slice(b"POKEMON RED\0\0\0\0\0");
+            rom[0x014B] = 0x01;
+            write_header_checksum(&mut rom);
+
+            let mut emulator = new_test_emulator_builder(Machine::Cgb, rom)
+                .build()
+                .unwrap();
+            emulator.emulate_boot_sequence();
+            assert_eq!(emulator.compat_mode(), CompatMode::DmgCompat);
+            assert_eq!(sprite_priority(&emulator), SpritePriority::Coordinate);
+
+            // VRAM is empty so every background pixel has color index 0, which BGP maps to one of
+            // the colors of the red background palette.
+            for (dmg_color, cgb_color) in [0x7FFF, 0x421F, 0x1CF2, 0x0000].into_iter().enumerate() {
+                emulator.write_bgp(dmg_color as u8);
+                draw_scanline(&mut emulator, 0);
+                assert_eq!(
+                    emulator.read_pixel(0, 0),
+                    Color::Cgb(CgbColor::new(cgb_color))
+                );
+            }
+
+            // Object palettes map through the green and blue object palettes
+            emulator.write_obp0(0b11_10_01_00);
+            emulator.write_obp1(0b00_00_00_10);
+            let cache = PaletteCache::build(&emulator);
+            assert_eq!(cache.obp[0][2].color, Color::Cgb(CgbColor::new(0x0200)));
+            assert_eq!(cache.obp[1][0].color, Color::Cgb(CgbColor::new(0x7C00)));
+        });
+    }
+
+    #[test]
+    fn cgb_color_conversion_round_trips() {
+        for raw in 0..0x8000 {
+            let color = CgbColor::new(raw);
+            assert_eq!(CgbColor::from_color32(color.to_color32()), color);
+        }
+
+        // Arbitrary colors map to the nearest CGB color
+        let color = CgbColor::from_color32(Color32::from_rgb(0x04, 0x05, 0xFB));
+        assert_eq!((color.red(), color.green(), color.blue()), (0, 1, 31));
+    }
+
+    #[test]
+    fn write_cgb_palette_color_updates_palettes() {
+        with_large_stack(|| {
+            let mut emulator = new_idle_emulator();
+
+            emulator.write_cgb_palette_color(false, 3, 1, CgbColor::new(0x1234));
+            emulator.write_cgb_palette_color(true, 7, 3, CgbColor::new(0x7FFF));
+
+            let background_palettes = emulator.cgb_background_palettes();
+            assert_eq!(lookup_cgb_color(background_palettes, 3, 1).raw(), 0x1234);
+            assert_eq!(background_palettes[3 * 8 + 2..3 * 8 + 4], [0x34, 0x12]);
+            assert_eq!(
+                lookup_cgb_color(emulator.cgb_object_palettes(), 7, 3).raw(),
+                0x7FFF
+            );
+
+            // Written colors are picked up when the palette cache is next rebuilt
+            assert!(emulator.palette_cache().is_dirty);
+            let cache = PaletteCache::build(&emulator);
+            assert!(
+                matches!(cache.cgb_background[3][1].color, Color::Cgb(color) if color.raw() == 0x1234)
+            );
+        });
+    }
+
+    /// Emulator with two objects on the first scanline, each given as an OAM x coordinate and tile
+    /// index. Tiles 1 and 5 are all color 1, tile 2 is all color 2, tile 3 is transparent in its
+    /// left half and color 3 in its right half, and tile 4 is all color 3.
+    fn new_overlapping_objects_emulator(
+        machine: Machine,
+        opri: u8,
+        objects: [(u8, u8); 2],
+    ) -> Emulator {
+        new_overlapping_objects_emulator_with_priority(machine, opri, objects, None)
+    }
+
+    /// Like `new_overlapping_objects_emulator`, but forcing the given object priority mode.
+    fn new_overlapping_objects_emulator_with_priority(
+        machine: Machine,
+        opri: u8,
+        objects: [(u8, u8); 2],
+        force_sprite_priority: Option<SpritePriority>,
+    ) -> Emulator {
+        let rom = match machine {
+            Machine::Dmg => build_test_rom(0x00, 0x00, 0x00, &[0x18, 0xFE]),
+            Machine::Cgb => build_cgb_test_rom(0x00, 0x00, 0x00, &[0x18, 0xFE]),
+        };
+        let options = Options {
+            force_sprite_priority,
+            ..Options::default()
+        };
+        let mut emulator = new_test_emulator_builder(machine, rom)
+            .with_options(Arc::new(options))
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+
+        let lcdc = emulator.lcdc();
+        emulator.write_lcdc(lcdc | 0x02);
+        emulator.write_obp0(0b11_10_01_00);
+
+        // OPRI can only be written while booting
+        emulator.set_is_booting(true);
+        emulator.write_opri(opri);
+        emulator.set_is_booting(false);
+
+        // Object palette 0 maps color indices 1-3 to red, green, and blue in CGB mode
+        emulator.write_ocps(0x80);
+        for byte in [0x00, 0x00, 0x1F, 0x00, 0xE0, 0x03, 0x00, 0x7C] {
+            emulator.write_address(0xFF6B, byte);
+        }
+
+        for (tile, (low, high)) in [
+            (1, (0xFF, 0x00)),
+            (2, (0x00, 0xFF)),
+            (3, (0x0F, 0x0F)),
+            (4, (0xFF, 0xFF)),
+            (5, (0xFF, 0x00)),
+        ] {
+            for row in 0..8 {
+                emulator.write_memory_bulk(0x8000 + tile * 16 + row * 2, &[low, high]);
+            }
+        }
+
+        for (i, (x, tile)) in objects.into_iter().enumerate() {
+            emulator.write_memory_bulk(0xFE00 + i as u16 * 4, &[16, x, tile, 0x00]);
+        }
+
+        emulator
+    }
+
+    /// Color index of each pixel on the first scanline, in either DMG or CGB mode.
+    fn object_color_indices(emulator: &Emulator, xs: std::ops::Range<usize>) -> Vec<u8> {
+        xs.map(|x| match emulator.read_pixel(x, 0) {
+            Color::Dmg(shade) => shade,
+            Color::Cgb(color) => match color.raw() {
+                0x001F => 1,
+                0x03E0 => 2,
+                0x7C00 => 3,
+                _ => 0,
+            },
+        })
+        .collect()
+    }
+
+    #[test]
+    fn overlapping_object_priority() {
+        with_large_stack(|| {
+            // (machine, OPRI, whether lower x has priority)
+            let modes = [
+                (Machine::Dmg, 0, true),
+                (Machine::Cgb, 0, false),
+                (Machine::Cgb, 1, true),
+            ];
+
+            for (machine, opri, is_priority_by_x) in modes {
+                // Object 0 (color 1) covers screen x 12-19, object 1 (color 2) covers 8-15. Where
+                // they overlap the object with lower x wins, or the lower OAM index in CGB mode.
+                let mut emulator =
+                    new_overlapping_objects_emulator(machine, opri, [(20, 1), (16, 2)]);
+                draw_scanline(&mut emulator, 0);
+
+                let overlap_color = if is_priority_by_x { 2 } else { 1 };
+                assert_eq!(
+                    object_color_indices(&emulator, 8..20),
+                    [[2; 4], [overlap_color; 4], [1; 4]].concat(),
+                    "{:?} OPRI={}",
+                    machine,
+                    opri
+                );
+
+                // When x is equal the lower OAM index wins in all modes
+                let mut emulator =
+                    new_overlapping_objects_emulator(machine, opri, [(16, 1), (16, 2)]);
+                draw_scanline(&mut emulator, 0);
+                assert_eq!(object_color_indices(&emulator, 8..16), [1; 8]);
+
+                // Transparent pixels of the higher priority object show the object behind it
+                let mut emulator =
+                    new_overlapping_objects_emulator(machine, opri, [(16, 3), (16, 2)]);
+                draw_scanline(&mut emulator, 0);
+                assert_eq!(
+                    object_color_indices(&emulator, 8..16),
+                    [[2; 4], [3; 4]].concat()
+                );
+            }
+        });
+    }
+
+    /// Give the first scanline's background under screen x 0-15 color 1, and set the priority flag
+    /// of each object given by OAM index.
+    fn set_background_and_object_priority(emulator: &mut Emulator, in_background: [bool; 2]) {
+        emulator.write_bgp(0b11_10_01_00);
+        emulator.write_memory_bulk(0x9800, &[1, 1]);
+
+        // Background palette 0 maps color index 1 to red in CGB mode
+        emulator.write_bcps(0x80);
+        for byte in [0x00, 0x00, 0x1F, 0x00] {
+            emulator.write_address(0xFF69, byte);
+        }
+
+        for (i, in_background) in in_background.into_iter().enumerate() {
+            let attributes = if in_background { 0x80 } else { 0x00 };
+            emulator.write_memory_bulk(0xFE03 + i as u16 * 4, &[attributes]);
+        }
+    }
+
+    #[test]
+    fn object_priority_is_resolved_before_background_priority() {
+        with_large_stack(|| {
+            for (machine, is_priority_by_x) in [(Machine::Dmg, true), (Machine::Cgb, false)] {
+                // Object 0 (color 2) in front of the background wins over object 1 (color 3)
+                // behind it
+                let mut emulator = new_overlapping_objects_emulator(machine, 0, [(16, 2), (16, 4)]);
+                set_background_and_object_priority(&mut emulator, [false, true]);
+                draw_scanline(&mut emulator, 0);
+                assert_eq!(
+                    object_color_indices(&emulator, 8..16),
+                    [2; 8],
+                    "{:?}",
+                    machine
+                );
+
+                // Object 0 behind the background wins, so the background hides both objects even
+                // though object 1 is in front of the background
+                set_background_and_object_priority(&mut emulator, [true, false]);
+                draw_scanline(&mut emulator, 0);
+                assert_eq!(
+                    object_color_indices(&emulator, 8..16),
+                    [1; 8],
+                    "{:?}",
+                    machine
+                );
+
+                // Object 0 behind the background covers screen x 12-19 and object 1 in front covers
+                // 8-15. Where they overlap, object 1 wins by x on a DMG while object 0 wins by OAM
+                // index on a CGB and is hidden by the background.
+                let mut emulator = new_overlapping_objects_emulator(machine, 0, [(20, 2), (16, 4)]);
+                set_background_and_object_priority(&mut emulator, [true, false]);
+                draw_scanline(&mut emulator, 0);
+
+                let overlap_color = if is_priority_by_x { 3 } else { 1 };
+                assert_eq!(
+                    object_color_indices(&emulator, 8..16),
+                    [[3; 4], [overlap_color; 4]].concat(),
+                    "{:?}",
+                    machine
+                );
+            }
+        });
+    }
+
+    /// An emulator with the background all color 2, the window all color 3 from screen x 80, and
+    /// objects drawn behind the background with color 1 at screen x 0-7 and 120-127.
+    fn new_bg_window_enable_emulator(machine: Machine, lcdc_bits: u8) -> Emulator {
+        let mut emulator = new_overlapping_objects_emulator(machine, 0, [(8, 1), (128, 1)]);
+
+        // Both objects are drawn behind non-zero background and window colors
+        emulator.write_memory_bulk(0xFE03, &[0x80]);
+        emulator.write_memory_bulk(0xFE07, &[0x80]);
+
+        // Background palette 0 maps color indices 1-3 to red, green, and blue in CGB mode
+        emulator.write_address(0xFF68, 0x80);
+        for byte in [0xFF, 0x7F, 0x1F, 0x00, 0xE0, 0x03, 0x00, 0x7C] {
+            emulator.write_address(0xFF69, byte);
+        }
+
+        // Background is all tile 0 with color 2, window is all tile 4 with color 3
+        for row in 0..8 {
+            emulator.write_memory_bulk(0x8000 + row * 2, &[0x00, 0xFF]);
+        }
+        for i in 0..0x400 {
+            emulator.write_address(0x9800 + i, 0x00);
+            emulator.write_address(0x9C00 + i, 0x04);
+        }
+
+        let lcdc = emulator.lcdc() & !0x21;
+        emulator.write_lcdc(lcdc | 0x40 | lcdc_bits);
+        emulator.write_bgp(0b11_10_01_00);
+        emulator.write_address(0xFF4A, 0);
+        emulator.write_address(0xFF4B, 87);
+
+        emulator
+    }
+
+    #[test]
+    fn bg_and_window_enable_bits() {
+        with_large_stack(|| {
+            // (machine, LCDC bits 0 and 5, colors at the background, window, object over the
+            // background, and object over the window)
+            let cases = [
+                // On DMG bit 0 blanks the background and window regardless of bit 5, leaving
+                // objects visible
+                (Machine::Dmg, 0x21, [2, 3, 2, 3]),
+                (Machine::Dmg, 0x01, [2, 2, 2, 2]),
+                (Machine::Dmg, 0x20, [0, 0, 1, 1]),
+                (Machine::Dmg, 0x00, [0, 0, 1, 1]),
+                // On CGB the background and window are always drawn and bit 0 instead removes
+                // their priority over objects
+                (Machine::Cgb, 0x21, [2, 3, 2, 3]),
+                (Machine::Cgb, 0x01, [2, 2, 2, 2]),
+                (Machine::Cgb, 0x20, [2, 3, 1, 1]),
+                (Machine::Cgb, 0x00, [2, 2, 1, 1]),
+            ];
+
+            for (machine, lcdc_bits, expected) in cases {
+                let mut emulator = new_bg_window_enable_emulator(machine, lcdc_bits);
+                draw_scanline(&mut emulator, 0);
+
+                let colors =
+                    [40, 100, 4, 124].map(|x| object_color_indices(&emulator, x..x + 1)[0]);
+                assert_eq!(
+                    colors, expected,
+                    "{:?} LCDC bits={:02X}",
+                    machine, lcdc_bits
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn forced_sprite_priority_overrides_opri() {
+        with_large_stack(|| {
+            // (machine, OPRI, forced mode, whether lower x has priority)
+            let modes = [
+                (Machine::Dmg, 0, None, true),
+                (Machine::Dmg, 0, Some(SpritePriority::Oam), false),
+                (Machine::Cgb, 0, None, false),
+                (Machine::Cgb, 0, Some(SpritePriority::Coordinate), true),
+                (Machine::Cgb, 1, None, true),
+                (Machine::Cgb, 1, Some(SpritePriority::Oam), false),
+            ];
+
+            for (machine, opri, force_sprite_priority, is_priority_by_x) in modes {
+                // Object 0 (color 1) covers screen x 12-19, object 1 (color 2) covers 8-15
+                let mut emulator = new_overlapping_objects_emulator_with_priority(
+                    machine,
+                    opri,
+                    [(20, 1), (16, 2)],
+                    force_sprite_priority,
+                );
+                draw_scanline(&mut emulator, 0);
+
+                let overlap_color = if is_priority_by_x { 2 } else { 1 };
+                assert_eq!(
+                    object_color_indices(&emulator, 12..16),
+                    [overlap_color; 4],
+                    "{:?} OPRI={} forced={:?}",
+                    machine,
+                    opri,
+                    force_sprite_priority
+                );
+
+                // The register itself is unaffected by the override
+                if machine == Machine::Cgb {
+                    assert_eq!(emulator.opri() & 0x01, opri);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn opri_only_exists_on_cgb() {
+        with_large_stack(|| {
+            // (machine, OPRI written while booting, OPRI read, whether lower x has priority)
+            let modes = [
+                (Machine::Dmg, 0, 0xFF, true),
+                (Machine::Dmg, 1, 0xFF, true),
+                (Machine::Cgb, 0, 0xFE, false),
+                (Machine::Cgb, 1, 0xFF, true),
+                (Machine::Cgb, 0xFE, 0xFE, false),
+            ];
+
+            for (machine, opri, opri_read, is_priority_by_x) in modes {
+                let mut emulator =
+                    new_overlapping_objects_emulator(machine, opri, [(20, 1), (16, 2)]);
+                assert_eq!(emulator.read_address(0xFF6C), opri_read);
+
+                draw_scanline(&mut emulator, 0);
+                let overlap_color = if is_priority_by_x { 2 } else { 1 };
+                assert_eq!(
+                    object_color_indices(&emulator, 12..16),
+                    [overlap_color; 4],
+                    "{:?} OPRI={}",
+                    machine,
+                    opri
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn double_size_object_priority() {
+        with_large_stack(|| {
+            for (machine, opri) in [(Machine::Dmg, 0), (Machine::Cgb, 0)] {
+                // Object 0 is 8x16 using tiles 2 (top) and 3 (bottom). Its odd tile index is
+                // ignored for the top half.
+                let mut emulator =
+                    new_overlapping_objects_emulator(machine, opri, [(16, 3), (16, 4)]);
+                let lcdc = emulator.lcdc();
+                emulator.write_lcdc(lcdc | 0x04);
+
+                // Object 1 is also 8x16, using tiles 4 (top) and 5 (bottom)
+                draw_scanline(&mut emulator, 0);
+                assert_eq!(object_color_indices(&emulator, 8..16), [2; 8]);
+
+                // Move both objects up so their bottom halves are on the first scanline. The
+                // transparent half of object 0's bottom tile shows object 1 behind it.
+                emulator.write_memory_bulk(0xFE00, &[8]);
+                emulator.write_memory_bulk(0xFE04, &[8]);
+                draw_scanline(&mut emulator, 0);
+                assert_eq!(
+                    object_color_indices(&emulator, 8..16),
+                    [[1; 4], [3; 4]].concat()
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn object_pixels_match_drawn_object() {
+        with_large_stack(|| {
+            for machine in [Machine::Dmg, Machine::Cgb] {
+                // 8x16 object using tiles 2 (top) and 3 (bottom), flipped both ways
+                let mut emulator = new_overlapping_objects_emulator(machine, 0, [(8, 3), (200, 0)]);
+                let lcdc = emulator.lcdc();
+                emulator.write_lcdc(lcdc | 0x04);
+                emulator.write_memory_bulk(0xFE03, &[0x60]);
+
+                let object = Object::from_oam(emulator.oam(), 0);
+                let pixels = lookup_all_pixels_in_object(&emulator, &object);
+                assert_eq!(pixels.len(), 16);
+
+                // The bottom tile is drawn on top, with its opaque right half on the left
+                assert_eq!(pixels[0], [3, 3, 3, 3, 0, 0, 0, 0]);
+                assert_eq!(pixels[15], [2; 8]);
+
+                // Thumbnails use the same colors as the screen
+                draw_scanline(&mut emulator, 0);
+                let palette = object_color_palette(&emulator, &object);
+                for (x, color_index) in pixels[0].iter().enumerate().take(4) {
+                    assert_eq!(
+                        emulator.read_pixel(x, 0),
+                        lookup_color_in_palette(&palette, *color_index)
+                    );
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn tile_data_lookup() {
+        with_large_stack(|| {
+            let mut emulator = new_idle_emulator();
+
+            // Tile 0xFF in the signed addressing mode is the tile just before 0x9000
+            assert_eq!(tile_data_address(1, 0x01), 0x8010);
+            assert_eq!(tile_data_address(0, 0x01), 0x9010);
+            assert_eq!(tile_data_address(0, 0xFF), 0x8FF0);
+
+            let tile_data: Vec<u8> = (0..16).map(|i| i * 17).collect();
+            emulator.write_memory_bulk(0x8FF0, &tile_data);
+
+            let looked_up = lookup_tile_data(&emulator, 0, 0, 0xFF);
+            assert_eq!(looked_up.as_slice(), tile_data.as_slice());
+            assert_eq!(lookup_tile_data(&emulator, 0, 1, 0xFF), looked_up);
+
+            // Decoding raw data matches looking up each pixel
+            let pixels = decode_tile_pixels(&looked_up);
+            for y in 0..8 {
+                for x in 0..8 {
+                    assert_eq!(
+                        pixels[y as usize][x as usize],
+                        lookup_color_index_in_tile(&emulator, 0, 0, 0xFF, x, y)
+                    );
+                }
+            }
+        });
     }
 }
@@ -0,0 +1,423 @@
+//! Dumps of all PPU state to a directory, for offline analysis of rendering bugs.
+//!
+//! A dump directory contains:
+//! - `vram_bank_0.bin` and `vram_bank_1.bin` (CGB only): raw VRAM banks
+//! - `oam.bin`: raw OAM
+//! - `io_registers.bin`: the raw IO register file from 0xFF00 to 0xFF7F
+//! - `cgb_background_palettes.bin` and `cgb_object_palettes.bin`: raw CGB palette memory
+//! - `tile_map_0.png` and `tile_map_1.png`: both 256x256 tile maps as rendered by the background
+//! - `tile_data_bank_0.png` and `tile_data_bank_1.png` (CGB only): all 384 tiles in each bank
+//! - `framebuffer.png`: the current contents of the screen
+//! - `registers.json`: LCD and palette registers, decoded, along with the MBC's banking state and
+//!   the version of the emulator that wrote the dump
+
+use std::{
+    array, fs, io,
+    path::{Path, PathBuf},
+};
+
+use image::RgbImage;
+use serde::Serialize;
+
+use crate::{
+    address_space::{IO_REGISTERS_SIZE, IO_REGISTERS_START, SINGLE_VRAM_BANK_SIZE},
+    emulator::{Emulator, SCREEN_HEIGHT, SCREEN_WIDTH},
+    ppu::{
+        BackgroundTileAttributes, Color, TILE_MAP_SIZE, TILE_SIZE, background_color_palette,
+        lookup_all_pixels_in_tile, lookup_color_in_palette, tile_map_pixel_color,
+    },
+    version::VERSION_STRING,
+};
+
+/// Number of tiles in the tile data area of a single VRAM bank
+pub(crate) const NUM_TILES_PER_BANK: usize = 384;
+
+/// Number of tiles in each row of a tile data sheet
+const TILE_DATA_SHEET_WIDTH: usize = 16;
+
+/// Size of the background and window in pixels
+const TILE_MAP_PIXEL_SIZE: usize = TILE_MAP_SIZE * TILE_SIZE;
+
+/// Write a dump of the PPU state to a new directory at `path`.
+///
+/// The dump is first written to a sibling directory then renamed into place, so a dump directory
+/// is never left partially written.
+pub fn dump_ppu_state(emulator: &Emulator, path: &Path) -> io::Result<()> {
+    let partial_path = partial_dump_path(path);
+    if partial_path.exists() {
+        fs::remove_dir_all(&partial_path)?;
+    }
+
+    fs::create_dir_all(&partial_path)?;
+
+    if let Err(error) = write_dump_files(emulator, &partial_path) {
+        let _ = fs::remove_dir_all(&partial_path);
+        return Err(error);
+    }
+
+    fs::rename(&partial_path, path)
+}
+
+/// Load the VRAM, OAM, IO registers, and palettes from a dump into an emulator. The emulator should
+/// be running the same cartridge and machine that the dump was taken from.
+pub fn load_ppu_dump(emulator: &mut Emulator, path: &Path) -> io::Result<()> {
+    let num_vram_banks = emulator.vram().len() / SINGLE_VRAM_BANK_SIZE;
+    for bank in 0..num_vram_banks {
+        let bank_path = path.join(format!("vram_bank_{}.bin", bank));
+        let bank_bytes = read_dump_file(&bank_path, SINGLE_VRAM_BANK_SIZE)?;
+        emulator.vram_mut()[bank * SINGLE_VRAM_BANK_SIZE..(bank + 1) * SINGLE_VRAM_BANK_SIZE]
+            .copy_from_slice(&bank_bytes);
+    }
+
+    let oam_size = emulator.oam().len();
+    let oam_bytes = read_dump_file(&path.join("oam.bin"), oam_size)?;
+    let io_register_bytes = read_dump_file(&path.join("io_registers.bin"), IO_REGISTERS_SIZE)?;
+    let background_palettes_size = emulator.cgb_background_palettes().len();
+    let background_palette_bytes = read_dump_file(
+        &path.join("cgb_background_palettes.bin"),
+        background_palettes_size,
+    )?;
+    let object_palettes_size = emulator.cgb_object_palettes().len();
+    let object_palette_bytes =
+        read_dump_file(&path.join("cgb_object_palettes.bin"), object_palettes_size)?;
+
+    emulator.oam_mut().copy_from_slice(&oam_bytes);
+    emulator.write_memory_bulk(IO_REGISTERS_START, &io_register_bytes);
+    emulator
+        .cgb_background_palettes_mut()
+        .copy_from_slice(&background_palette_bytes);
+    emulator
+        .cgb_object_palettes_mut()
+        .copy_from_slice(&object_palette_bytes);
+
+    Ok(())
+}
+
+/// Read a file from a dump, which must have the given size.
+fn read_dump_file(path: &Path, size: usize) -> io::Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    if bytes.len() != size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is {} bytes but should be {} bytes",
+                path.display(),
+                bytes.len(),
+                size
+            ),
+        ));
+    }
+
+    Ok(bytes)
+}
+
+fn partial_dump_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".partial");
+    path.with_file_name(file_name)
+}
+
+fn write_dump_files(emulator: &Emulator, dir: &Path) -> io::Result<()> {
+    let num_vram_banks = emulator.vram().len() / SINGLE_VRAM_BANK_SIZE;
+    for bank in 0..num_vram_banks {
+        let bank_bytes =
+            &emulator.vram()[bank * SINGLE_VRAM_BANK_SIZE..(bank + 1) * SINGLE_VRAM_BANK_SIZE];
+        fs::write(dir.join(format!("vram_bank_{}.bin", bank)), bank_bytes)?;
+
+        save_png(
+            &render_tile_data_sheet(emulator, bank),
+            &dir.join(format!("tile_data_bank_{}.png", bank)),
+        )?;
+    }
+
+    fs::write(dir.join("oam.bin"), emulator.oam())?;
+    fs::write(
+        dir.join("io_registers.bin"),
+        emulator.read_memory_bulk(IO_REGISTERS_START, IO_REGISTERS_SIZE),
+    )?;
+    fs::write(
+        dir.join("cgb_background_palettes.bin"),
+        emulator.cgb_background_palettes(),
+    )?;
+    fs::write(
+        dir.join("cgb_object_palettes.bin"),
+        emulator.cgb_object_palettes(),
+    )?;
+
+    for tile_map_number in 0..2 {
+        save_png(
+            &render_tile_map(emulator, tile_map_number),
+            &dir.join(format!("tile_map_{}.png", tile_map_number)),
+        )?;
+    }
+
+    save_png(&render_framebuffer(emulator), &dir.join("framebuffer.png"))?;
+
+    fs::write(dir.join("registers.json"), registers_json(emulator))
+}
+
+pub(crate) fn save_png(image: &RgbImage, path: &Path) -> io::Result<()> {
+    image.save(path).map_err(io::Error::other)
+}
+
+fn color_to_rgb(emulator: &Emulator, color: Color) -> image::Rgb<u8> {
+    let color32 = emulator.screen_palette().color_to_color32(color);
+    image::Rgb([color32.r(), color32.g(), color32.b()])
+}
+
+/// Render the screen as currently shown, using the emulator's screen palette for DMG colors.
+pub fn render_framebuffer(emulator: &Emulator) -> RgbImage {
+    RgbImage::from_fn(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, |x, y| {
+        color_to_rgb(emulator, emulator.read_pixel(x as usize, y as usize))
+    })
+}
+
+/// Write the screen as currently shown to a PNG file.
+pub fn save_screenshot(emulator: &Emulator, path: &Path) -> io::Result<()> {
+    save_png(&render_framebuffer(emulator), path)
+}
+
+fn render_tile_map(emulator: &Emulator, tile_map_number: u8) -> RgbImage {
+    let size = TILE_MAP_PIXEL_SIZE as u32;
+    RgbImage::from_fn(size, size, |x, y| {
+        color_to_rgb(
+            emulator,
+            tile_map_pixel_color(emulator, tile_map_number, x as u8, y as u8),
+        )
+    })
+}
+
+/// Render every tile in a VRAM bank using the first background palette.
+pub(crate) fn render_tile_data_sheet(emulator: &Emulator, vram_bank_num: usize) -> RgbImage {
+    let width = TILE_DATA_SHEET_WIDTH * TILE_SIZE;
+    let height = (NUM_TILES_PER_BANK / TILE_DATA_SHEET_WIDTH) * TILE_SIZE;
+    let mut image = RgbImage::new(width as u32, height as u32);
+
+    let attributes = emulator
+        .in_cgb_mode()
+        .then(|| BackgroundTileAttributes::new(0));
+    let palette = background_color_palette(emulator, attributes.as_ref());
+
+    for tile in 0..NUM_TILES_PER_BANK {
+        // The first 256 tiles are addressed from 0x8000, the rest from 0x9000
+        let (addressing_mode, tile_index) = if tile < 256 {
+            (1, tile as u8)
+        } else {
+            (0, (tile - 256) as u8)
+        };
+
+        let pixels =
+            lookup_all_pixels_in_tile(emulator, vram_bank_num, addressing_mode, tile_index);
+
+        let tile_x = (tile % TILE_DATA_SHEET_WIDTH) * TILE_SIZE;
+        let tile_y = (tile / TILE_DATA_SHEET_WIDTH) * TILE_SIZE;
+
+        for (y, row) in pixels.iter().enumerate() {
+            for (x, color_index) in row.iter().enumerate() {
+                let color = lookup_color_in_palette(&palette, *color_index);
+                image.put_pixel(
+                    (tile_x + x) as u32,
+                    (tile_y + y) as u32,
+                    color_to_rgb(emulator, color),
+                );
+            }
+        }
+    }
+
+    image
+}
+
+/// Contents of `registers.json`
+#[derive(Serialize)]
+struct RegistersDump {
+    emulator_version: &'static str,
+    in_cgb_mode: bool,
+    lcdc: u8,
+    lcd_enabled: bool,
+    window_tile_map: u8,
+    window_enabled: bool,
+    bg_window_tile_data_addressing_mode: u8,
+    bg_tile_map: u8,
+    obj_double_size: bool,
+    obj_enabled: bool,
+    bg_window_enable_or_priority: bool,
+    stat: u8,
+    mode: String,
+    ly: u8,
+    lyc: u8,
+    scx: u8,
+    scy: u8,
+    wx: u8,
+    wy: u8,
+    /// Shade of each color index
+    bgp: [u8; 4],
+    obp0: [u8; 4],
+    obp1: [u8; 4],
+    /// Raw 15-bit colors of each palette, as hex strings
+    cgb_background_palettes: Vec<Vec<String>>,
+    cgb_object_palettes: Vec<Vec<String>>,
+    mbc: String,
+}
+
+fn registers_json(emulator: &Emulator) -> String {
+    let dmg_palette = |palette: u8| -> [u8; 4] { array::from_fn(|i| (palette >> (i * 2)) & 0x03) };
+
+    let cgb_palettes = |palettes: &[u8]| -> Vec<Vec<String>> {
+        palettes
+            .chunks(8)
+            .map(|palette| {
+                palette
+                    .chunks(2)
+                    .map(|color| {
+                        let raw = u16::from_le_bytes([color[0], color[1]]) & 0x7FFF;
+                        format!("0x{:04X}", raw)
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+
+    let registers = RegistersDump {
+        emulator_version: VERSION_STRING,
+        in_cgb_mode: emulator.in_cgb_mode(),
+        lcdc: emulator.lcdc(),
+        lcd_enabled: emulator.is_lcdc_lcd_enabled(),
+        window_tile_map: emulator.lcdc_window_tile_map_number(),
+        window_enabled: emulator.is_lcdc_window_enabled(),
+        bg_window_tile_data_addressing_mode: emulator.lcdc_bg_window_tile_data_addressing_mode(),
+        bg_tile_map: emulator.lcdc_bg_tile_map_number(),
+        obj_double_size: emulator.is_lcdc_obj_double_size(),
+        obj_enabled: emulator.is_lcdc_obj_enabled(),
+        bg_window_enable_or_priority: emulator.lcdc() & 0x01 != 0,
+        stat: emulator.stat(),
+        mode: format!("{:?}", emulator.mode()),
+        ly: emulator.ly(),
+        lyc: emulator.lyc(),
+        scx: emulator.scx(),
+        scy: emulator.scy(),
+        wx: emulator.wx(),
+        wy: emulator.wy(),
+        bgp: dmg_palette(emulator.bgp()),
+        obp0: dmg_palette(emulator.obp0()),
+        obp1: dmg_palette(emulator.obp1()),
+        cgb_background_palettes: cgb_palettes(emulator.cgb_background_palettes()),
+        cgb_object_palettes: cgb_palettes(emulator.cgb_object_palettes()),
+        mbc: emulator.mbc_debug_state().to_string(),
+    };
+
+    let mut json = serde_json::to_string_pretty(&registers).unwrap();
+    json.push('\n');
+    json
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, io, path::PathBuf, process};
+
+    use crate::{
+        emulator::SCREEN_HEIGHT,
+        machine::Machine,
+        ppu::draw_scanline,
+        test_utils::{
+            FILL_VRAM_PROGRAM, build_test_rom, new_test_emulator, new_test_emulator_builder,
+            with_large_stack,
+        },
+    };
+
+    use super::{dump_ppu_state, load_ppu_dump, render_framebuffer, save_screenshot};
+
+    #[test]
+    fn dumped_vram_renders_dumped_framebuffer() {
+        with_large_stack(|| {
+            let dir = env::temp_dir().join(format!("gbcemu-ppu-dump-{}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+
+            // Static scene once the program has finished filling VRAM
+            let mut emulator = new_test_emulator(Machine::Cgb, &FILL_VRAM_PROGRAM);
+            for _ in 0..60 {
+                emulator.run_frame();
+            }
+
+            dump_ppu_state(&emulator, &dir).unwrap();
+
+            for file in [
+                "vram_bank_0.bin",
+                "vram_bank_1.bin",
+                "oam.bin",
+                "tile_map_0.png",
+                "tile_map_1.png",
+                "tile_data_bank_0.png",
+                "tile_data_bank_1.png",
+                "registers.json",
+            ] {
+                assert!(dir.join(file).exists(), "missing {}", file);
+            }
+
+            let registers: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(dir.join("registers.json")).unwrap())
+                    .unwrap();
+            // The fixture is a DMG ROM, so the CGB runs it in DMG compatibility mode
+            assert_eq!(registers["in_cgb_mode"], false);
+            assert_eq!(
+                registers["cgb_background_palettes"]
+                    .as_array()
+                    .unwrap()
+                    .len(),
+                8
+            );
+
+            // Re-render the dumped state in a fresh emulator
+            let mut emulator = new_test_emulator(Machine::Cgb, &FILL_VRAM_PROGRAM);
+            load_ppu_dump(&mut emulator, &dir).unwrap();
+            for scanline in 0..SCREEN_HEIGHT as u8 {
+                draw_scanline(&mut emulator, scanline);
+            }
+
+            let dumped_framebuffer = image::open(dir.join("framebuffer.png")).unwrap().to_rgb8();
+            assert_eq!(render_framebuffer(&emulator), dumped_framebuffer);
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn wrongly_sized_dump_is_invalid_data() {
+        with_large_stack(|| {
+            let dir = env::temp_dir().join(format!("gbcemu-ppu-dump-size-{}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+
+            let emulator = new_test_emulator(Machine::Cgb, &FILL_VRAM_PROGRAM);
+            dump_ppu_state(&emulator, &dir).unwrap();
+            fs::write(dir.join("oam.bin"), [0; 16]).unwrap();
+
+            let mut emulator = new_test_emulator(Machine::Cgb, &FILL_VRAM_PROGRAM);
+            let error = load_ppu_dump(&mut emulator, &dir).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    /// Write the ROM and reference screenshot used by the headless screenshot integration test.
+    /// Run manually, then check that the screenshot is correct before committing it.
+    #[test]
+    #[ignore]
+    fn generate_screenshot_fixtures() {
+        with_large_stack(|| {
+            let fixtures_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("fixtures");
+
+            let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+            fs::write(fixtures_dir.join("fill_vram.gb"), &rom).unwrap();
+
+            let mut emulator = new_test_emulator_builder(Machine::Dmg, rom)
+                .build()
+                .unwrap();
+            emulator.power_on();
+            emulator.run_frames(60);
+
+            save_screenshot(&emulator, &fixtures_dir.join("fill_vram.png")).unwrap();
+        });
+    }
+}
@@ -0,0 +1,221 @@
+//! Initial contents of RAM at power-on.
+//!
+//! Real hardware does not clear RAM at power-on. DMG work RAM contains semi-random patterns while
+//! CGB RAM is mostly zeroed, and a few games depend on these contents (e.g. to seed an RNG). The
+//! contents can be chosen so that such behavior can be reproduced or ruled out.
+
+use std::{fmt, str::FromStr};
+
+use crate::determinism::{EmulatorRng, entropy_seed};
+
+/// Length of each run of 0x00 or 0xFF bytes in `RamInit::Pattern`.
+const PATTERN_RUN_LENGTH: usize = 8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RamInit {
+    /// All bytes are 0x00
+    #[default]
+    Zero,
+    /// All bytes are 0xFF
+    Ones,
+    /// Bytes are drawn from the emulator's RNG, which is seeded with the given seed so that a run
+    /// can be reproduced. IO registers that are uninitialized at power-on are randomized as well.
+    Random(u64),
+    /// Alternating runs of 8 0x00 bytes and 8 0xFF bytes
+    Pattern,
+}
+
+impl RamInit {
+    /// Random initialization with a seed taken from the current time.
+    pub fn random_from_entropy() -> Self {
+        RamInit::Random(entropy_seed())
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        match self {
+            RamInit::Random(seed) => Some(*seed),
+            _ => None,
+        }
+    }
+
+    /// Create a filler for a sequence of RAM regions that draws random contents from `rng`. Random
+    /// contents continue from one region to the next, so regions must always be filled in the same
+    /// order.
+    pub fn filler<'a>(&self, rng: &'a mut EmulatorRng) -> RamFiller<'a> {
+        RamFiller {
+            ram_init: *self,
+            rng,
+        }
+    }
+}
+
+pub struct RamFiller<'a> {
+    ram_init: RamInit,
+    rng: &'a mut EmulatorRng,
+}
+
+impl RamFiller<'_> {
+    pub fn fill(&mut self, bytes: &mut [u8]) {
+        match self.ram_init {
+            RamInit::Zero => bytes.fill(0x00),
+            RamInit::Ones => bytes.fill(0xFF),
+            RamInit::Random(_) => self.rng.fill_bytes(bytes),
+            RamInit::Pattern => {
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    let is_ones_run = (i / PATTERN_RUN_LENGTH) % 2 == 1;
+                    *byte = if is_ones_run { 0xFF } else { 0x00 };
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for RamInit {
+    type Err = String;
+
+    /// Parse `zero`, `ones`, `pattern`, `random`, or `random:<seed>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(RamInit::Zero),
+            "ones" => Ok(RamInit::Ones),
+            "pattern" => Ok(RamInit::Pattern),
+            "random" => Ok(RamInit::random_from_entropy()),
+            _ => match s.strip_prefix("random:") {
+                Some(seed) => seed
+                    .parse()
+                    .map(RamInit::Random)
+                    .map_err(|_| format!("invalid seed: {}", seed)),
+                None => Err(format!(
+                    "expected zero, ones, pattern, random, or random:<seed> but found {}",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for RamInit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RamInit::Zero => write!(f, "zero"),
+            RamInit::Ones => write!(f, "ones"),
+            RamInit::Random(seed) => write!(f, "random:{}", seed),
+            RamInit::Pattern => write!(f, "pattern"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        emulator::Emulator,
+        machine::Machine,
+        test_utils::{
+            FILL_VRAM_PROGRAM, build_test_rom, new_test_emulator_builder, with_large_stack,
+        },
+    };
+
+    use super::RamInit;
+
+    fn new_ram_init_emulator(ram_init: RamInit) -> Emulator {
+        let rom = build_test_rom(0x00, 0x00, 0x00, &FILL_VRAM_PROGRAM);
+        new_test_emulator_builder(Machine::Cgb, rom)
+            .with_ram_init(ram_init)
+            .build()
+            .unwrap()
+    }
+
+    /// Contents of work RAM, VRAM, HRAM, and OAM.
+    fn ram_contents(emulator: &Emulator) -> Vec<u8> {
+        let mut bytes = emulator.read_memory_bulk(0xC000, 0x2000);
+        bytes.extend(emulator.vram());
+        bytes.extend(emulator.read_memory_bulk(0xFF80, 0x7F));
+        bytes.extend(emulator.oam());
+        bytes
+    }
+
+    #[test]
+    fn fixed_modes_fill_as_specified() {
+        with_large_stack(|| {
+            let zero = ram_contents(&new_ram_init_emulator(RamInit::Zero));
+            assert!(zero.iter().all(|byte| *byte == 0x00));
+
+            let ones = ram_contents(&new_ram_init_emulator(RamInit::Ones));
+            assert!(ones.iter().all(|byte| *byte == 0xFF));
+
+            let emulator = new_ram_init_emulator(RamInit::Pattern);
+            let work_ram = emulator.read_memory_bulk(0xC000, 32);
+            assert_eq!(work_ram[..8], [0x00; 8]);
+            assert_eq!(work_ram[8..16], [0xFF; 8]);
+            assert_eq!(work_ram[16..24], [0x00; 8]);
+            assert_eq!(work_ram[24..], [0xFF; 8]);
+        });
+    }
+
+    #[test]
+    fn random_seed_reproduces_contents() {
+        with_large_stack(|| {
+            let emulator = new_ram_init_emulator(RamInit::Random(1234));
+            assert_eq!(emulator.ram_init_seed(), Some(1234));
+
+            let first = ram_contents(&emulator);
+            let second = ram_contents(&new_ram_init_emulator(RamInit::Random(1234)));
+            let other_seed = ram_contents(&new_ram_init_emulator(RamInit::Random(5678)));
+
+            assert_eq!(first, second);
+            assert_ne!(first, other_seed);
+
+            // Roughly half of all bits are set
+            let ones: u32 = first.iter().map(|byte| byte.count_ones()).sum();
+            let total = first.len() as u32 * 8;
+            assert!(ones > total * 45 / 100 && ones < total * 55 / 100);
+        });
+    }
+
+    /// OBP0 and OBP1, followed by all other IO registers.
+    fn io_registers(emulator: &Emulator) -> ((u8, u8), Vec<u8>) {
+        let mut other_registers = emulator.read_memory_bulk(0xFF00, 0x80);
+        other_registers.drain(0x48..=0x49);
+
+        ((emulator.obp0(), emulator.obp1()), other_registers)
+    }
+
+    #[test]
+    fn uninitialized_registers_follow_ram_init() {
+        with_large_stack(|| {
+            let (zero_palettes, zero_registers) =
+                io_registers(&new_ram_init_emulator(RamInit::Zero));
+            assert_eq!(zero_palettes, (0xFF, 0xFF));
+
+            // Deterministic modes keep the typical values
+            for ram_init in [RamInit::Ones, RamInit::Pattern] {
+                let registers = io_registers(&new_ram_init_emulator(ram_init));
+                assert_eq!(registers, (zero_palettes, zero_registers.clone()));
+            }
+
+            // Random mode only changes uninitialized registers, reproducibly for each seed
+            let (first_palettes, first_registers) =
+                io_registers(&new_ram_init_emulator(RamInit::Random(1234)));
+            let (second_palettes, _) = io_registers(&new_ram_init_emulator(RamInit::Random(1234)));
+            let (other_seed_palettes, _) =
+                io_registers(&new_ram_init_emulator(RamInit::Random(5678)));
+
+            assert_eq!(first_registers, zero_registers);
+            assert_eq!(first_palettes, second_palettes);
+            assert_ne!(first_palettes, other_seed_palettes);
+        });
+    }
+
+    #[test]
+    fn parse_ram_init() {
+        assert_eq!("zero".parse(), Ok(RamInit::Zero));
+        assert_eq!("ones".parse(), Ok(RamInit::Ones));
+        assert_eq!("pattern".parse(), Ok(RamInit::Pattern));
+        assert_eq!("random:42".parse(), Ok(RamInit::Random(42)));
+        assert!(matches!("random".parse(), Ok(RamInit::Random(_))));
+        assert!("random:abc".parse::<RamInit>().is_err());
+        assert!("noise".parse::<RamInit>().is_err());
+
+        assert_eq!(RamInit::Random(42).to_string(), "random:42");
+    }
+}
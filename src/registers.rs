@@ -69,6 +69,24 @@ impl Registers {
         }
     }
 
+    /// State at power-on, before the BIOS runs.
+    pub fn init_pre_boot() -> Self {
+        Registers {
+            pc: 0x0000,
+            sp: 0x0000,
+            a: 0x00,
+            bc: [0x00, 0x00],
+            de: [0x00, 0x00],
+            hl: [0x00, 0x00],
+            zero_flag: false,
+            subtraction_flag: false,
+            half_carry_flag: false,
+            carry_flag: false,
+            interrupts_enabled: false,
+        }
+    }
+
+    /// State after the BIOS completes.
     pub fn init_for_machine(machine: Machine) -> Self {
         match machine {
             Machine::Dmg => Self::new_for_dmg(),
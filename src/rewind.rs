@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+use crate::emulator::REFRESH_RATE;
+
+/// Number of frames between rewind snapshots by default
+pub const DEFAULT_REWIND_INTERVAL_FRAMES: u32 = 6;
+
+/// How far back in time the emulator can be rewound, in seconds of emulated time
+const REWIND_HISTORY_SECS: f64 = 60.0;
+
+/// Maximum total size of all snapshots. Snapshots are around 200KB each, so at the default interval
+/// a little over half of `REWIND_HISTORY_SECS` fits.
+const MAX_REWIND_BYTES: usize = 64 * 1024 * 1024;
+
+/// Ring buffer of periodic snapshots of the emulator state, newest last. Once full the oldest
+/// snapshots are dropped to make room for each new one, so that the snapshots never cover more than
+/// `REWIND_HISTORY_SECS` or take up more than `MAX_REWIND_BYTES`.
+pub struct RewindBuffer {
+    snapshots: VecDeque<Vec<u8>>,
+    /// Total size of all snapshots in bytes
+    num_bytes: usize,
+    max_bytes: usize,
+    /// Number of frames completed since the last snapshot was taken
+    frames_since_snapshot: u32,
+}
+
+impl RewindBuffer {
+    pub fn new() -> Self {
+        Self::with_max_bytes(MAX_REWIND_BYTES)
+    }
+
+    fn with_max_bytes(max_bytes: usize) -> Self {
+        RewindBuffer {
+            snapshots: VecDeque::new(),
+            num_bytes: 0,
+            max_bytes,
+            frames_since_snapshot: 0,
+        }
+    }
+
+    /// Maximum number of snapshots kept when taking one every `interval_frames` frames.
+    pub fn capacity(interval_frames: u32) -> usize {
+        (REWIND_HISTORY_SECS * REFRESH_RATE / interval_frames as f64).ceil() as usize
+    }
+
+    /// Record that a frame completed, returning whether a snapshot is due. Never due if the
+    /// interval is 0, which disables rewinding.
+    pub fn frame_complete(&mut self, interval_frames: u32) -> bool {
+        if interval_frames == 0 {
+            return false;
+        }
+
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot < interval_frames {
+            return false;
+        }
+
+        self.frames_since_snapshot = 0;
+        true
+    }
+
+    pub fn push(&mut self, snapshot: Vec<u8>, interval_frames: u32) {
+        let capacity = Self::capacity(interval_frames);
+        while self.snapshots.len() >= capacity
+            || (!self.snapshots.is_empty() && self.num_bytes + snapshot.len() > self.max_bytes)
+        {
+            let oldest_snapshot = self.snapshots.pop_front().unwrap();
+            self.num_bytes -= oldest_snapshot.len();
+        }
+
+        self.num_bytes += snapshot.len();
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Remove and return the newest snapshot. Snapshots are only taken again once a full interval
+    /// has passed after rewinding stops.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.frames_since_snapshot = 0;
+
+        let snapshot = self.snapshots.pop_back()?;
+        self.num_bytes -= snapshot.len();
+        Some(snapshot)
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RewindBuffer;
+
+    #[test]
+    fn snapshot_interval() {
+        let mut buffer = RewindBuffer::new();
+        let due_frames = (1..=12)
+            .filter(|_| buffer.frame_complete(4))
+            .collect::<Vec<_>>();
+        assert_eq!(due_frames, vec![4, 8, 12]);
+
+        // An interval of 0 disables snapshots
+        assert!((0..100).all(|_| !buffer.frame_complete(0)));
+    }
+
+    #[test]
+    fn oldest_snapshots_are_dropped_when_full() {
+        let interval_frames = 60;
+        let capacity = RewindBuffer::capacity(interval_frames);
+        assert_eq!(capacity, 60);
+
+        let mut buffer = RewindBuffer::new();
+        for i in 0..capacity + 5 {
+            buffer.push(vec![i as u8], interval_frames);
+        }
+        assert_eq!(buffer.len(), capacity);
+
+        // Newest snapshots are popped first, and the first 5 were dropped
+        assert_eq!(buffer.pop(), Some(vec![(capacity + 4) as u8]));
+        while buffer.len() > 1 {
+            buffer.pop();
+        }
+        assert_eq!(buffer.pop(), Some(vec![5]));
+        assert_eq!(buffer.pop(), None);
+    }
+    #[test]
+    fn oldest_snapshots_are_dropped_over_byte_budget() {
+        let mut buffer = RewindBuffer::with_max_bytes(10);
+        for i in 0..5 {
+            buffer.push(vec![i; 4], 1);
+        }
+
+        // Only two 4 byte snapshots fit in 10 bytes
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.pop(), Some(vec![4; 4]));
+        assert_eq!(buffer.pop(), Some(vec![3; 4]));
+
+        // A snapshot larger than the budget is still kept on its own
+        buffer.push(vec![0; 4], 1);
+        buffer.push(vec![1; 16], 1);
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.pop(), Some(vec![1; 16]));
+        assert_eq!(buffer.num_bytes, 0);
+    }
+}
@@ -0,0 +1,122 @@
+//! Reading ROMs from disk, either directly or from the first ROM in a zip archive.
+
+use std::{
+    fs,
+    io::{Read, Seek},
+};
+
+use zip::ZipArchive;
+
+use crate::{
+    address_space::ROM_BANK_SIZE,
+    error::Error,
+    save_paths::{has_rom_extension, has_zip_extension},
+};
+
+/// Largest ROM size that a cartridge header can declare
+const MAX_ROM_SIZE: usize = (2 * ROM_BANK_SIZE) << 0x08;
+
+/// Read the ROM at the given path. Zip archives are read from the first ROM they contain.
+pub fn read_rom_file(rom_path: &str) -> Result<Vec<u8>, Error> {
+    if has_zip_extension(rom_path) {
+        read_rom_from_zip(fs::File::open(rom_path)?)
+    } else {
+        Ok(fs::read(rom_path)?)
+    }
+}
+
+/// Read the first file in a zip archive with a ROM extension, ignoring case.
+///
+/// The archive's declared file sizes are not trusted, so the ROM is read no further than the
+/// largest supported ROM size.
+pub fn read_rom_from_zip(reader: impl Read + Seek) -> Result<Vec<u8>, Error> {
+    let mut archive = ZipArchive::new(reader)?;
+
+    for index in 0..archive.len() {
+        let file = archive.by_index(index)?;
+        if !file.is_file() || !has_rom_extension(file.name()) {
+            continue;
+        }
+
+        let too_large_error = Error::RomTooLarge {
+            max_size: MAX_ROM_SIZE,
+        };
+        if file.size() > MAX_ROM_SIZE as u64 {
+            return Err(too_large_error);
+        }
+
+        let mut rom_bytes = Vec::with_capacity(file.size() as usize);
+        file.take(MAX_ROM_SIZE as u64 + 1)
+            .read_to_end(&mut rom_bytes)?;
+
+        if rom_bytes.len() > MAX_ROM_SIZE {
+            return Err(too_large_error);
+        }
+
+        return Ok(rom_bytes);
+    }
+
+    Err(Error::NoRomInArchive)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use crate::error::Error;
+
+    use super::{MAX_ROM_SIZE, read_rom_from_zip};
+
+    fn build_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, contents) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn reads_first_rom_in_zip() {
+        let zip = build_zip(&[
+            ("readme.txt", b"not a rom"),
+            ("GAME.GBC", &[1, 2, 3]),
+            ("other.gb", &[4, 5, 6]),
+        ]);
+
+        assert_eq!(read_rom_from_zip(Cursor::new(zip)).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn zip_errors() {
+        let zip = build_zip(&[("readme.txt", b"not a rom")]);
+        assert!(matches!(
+            read_rom_from_zip(Cursor::new(zip)),
+            Err(Error::NoRomInArchive)
+        ));
+
+        assert!(matches!(
+            read_rom_from_zip(Cursor::new(b"not a zip".to_vec())),
+            Err(Error::InvalidArchive(_))
+        ));
+
+        let zip = build_zip(&[("game.gb", &vec![0; MAX_ROM_SIZE + 1])]);
+        assert!(matches!(
+            read_rom_from_zip(Cursor::new(zip)),
+            Err(Error::RomTooLarge { .. })
+        ));
+
+        // Truncated archives are missing their central directory
+        let mut zip = build_zip(&[("game.gb", &[1, 2, 3])]);
+        zip.truncate(zip.len() / 2);
+        assert!(matches!(
+            read_rom_from_zip(Cursor::new(zip)),
+            Err(Error::InvalidArchive(_))
+        ));
+    }
+}
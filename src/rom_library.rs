@@ -0,0 +1,416 @@
+//! Header metadata for a directory of ROMs, cached on disk.
+//!
+//! Scanning a large ROM collection means reading every ROM's header. The results are kept in a
+//! single cache file keyed by path, and an entry is only used while the ROM's size and modification
+//! time are unchanged. Entries for ROMs that no longer exist are pruned on each scan.
+//!
+//! The cache file is versioned. A cache written with a different version, or that cannot be
+//! decoded, is discarded and rebuilt.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{cartridge::Cartridge, save_file::platform_cache_dir, save_paths::has_rom_extension};
+
+const CACHE_FILE_NAME: &str = "rom_metadata_cache";
+
+/// Version of the cache file format. Must be bumped whenever `RomReport` or `CacheEntry` change so
+/// that caches written by older versions are discarded.
+const CACHE_FORMAT_VERSION: u32 = 1;
+
+/// Address of the end of the cartridge header, the only part of the ROM that is read.
+const HEADER_END: usize = 0x0150;
+
+/// Metadata from a ROM's header.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomReport {
+    pub title: String,
+    /// Whether the ROM supports CGB features
+    pub is_cgb: bool,
+    pub cartridge_type: u8,
+    pub rom_size_byte: u8,
+    pub ram_size_byte: u8,
+    pub has_valid_header_checksum: bool,
+}
+
+impl RomReport {
+    /// Parse the header at the start of a ROM. Returns `None` if the bytes are too short to contain
+    /// a header.
+    pub fn from_header(header: &[u8]) -> Option<Self> {
+        if header.len() < HEADER_END {
+            return None;
+        }
+
+        let title = header[0x0134..0x0144]
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect::<String>();
+
+        Some(RomReport {
+            title: title.trim_end().to_string(),
+            is_cgb: header[0x0143] & 0x80 != 0,
+            cartridge_type: header[0x0147],
+            rom_size_byte: header[0x0148],
+            ram_size_byte: header[0x0149],
+            has_valid_header_checksum: Cartridge::compute_header_checksum(header) == header[0x014D],
+        })
+    }
+
+    /// Read and parse the header of the ROM at the given path.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut header = vec![];
+        fs::File::open(path)?
+            .take(HEADER_END as u64)
+            .read_to_end(&mut header)?;
+
+        Self::from_header(&header).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "file is too small to be a ROM")
+        })
+    }
+}
+
+/// Size and modification time of a file, used to detect when a cached report is stale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+impl FileStamp {
+    fn of(metadata: &fs::Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+
+        FileStamp {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    stamp: FileStamp,
+    report: RomReport,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+/// Cached header metadata for ROMs, keyed by path.
+#[derive(Default)]
+pub struct RomMetadataCache {
+    entries: HashMap<PathBuf, CacheEntry>,
+    /// Whether the cache has changed since it was loaded
+    is_dirty: bool,
+}
+
+impl RomMetadataCache {
+    /// The cache file in the platform cache directory, if it can be determined.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(platform_cache_dir()?.join(CACHE_FILE_NAME))
+    }
+
+    /// Load the cache file at the given path. A missing cache file results in an empty cache, as
+    /// does a cache file that is corrupted or from another version, which is then rebuilt.
+    pub fn load(path: &Path) -> Self {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    eprintln!("Could not read ROM cache {}: {}", path.display(), error);
+                }
+
+                return Self::default();
+            }
+        };
+
+        match rmp_serde::from_slice::<CacheFile>(&bytes) {
+            Ok(cache_file) if cache_file.version == CACHE_FORMAT_VERSION => RomMetadataCache {
+                entries: cache_file.entries,
+                is_dirty: false,
+            },
+            Ok(_) => Self::rebuilt(),
+            Err(error) => {
+                eprintln!("ROM cache {} is corrupted: {}", path.display(), error);
+                Self::rebuilt()
+            }
+        }
+    }
+
+    /// An empty cache that replaces an unusable cache file the next time it is saved.
+    fn rebuilt() -> Self {
+        RomMetadataCache {
+            entries: HashMap::new(),
+            is_dirty: true,
+        }
+    }
+
+    /// Write the cache file if anything has changed since it was loaded.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        if !self.is_dirty {
+            return Ok(());
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let cache_file = CacheFile {
+            version: CACHE_FORMAT_VERSION,
+            entries: self.entries.clone(),
+        };
+        fs::write(path, rmp_serde::to_vec(&cache_file).unwrap())?;
+
+        self.is_dirty = false;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The cached reports for ROMs in a directory, sorted by path, without checking whether they
+    /// are stale. Allows showing a directory immediately while `scan_rom_dir` validates it.
+    pub fn cached_reports(&self, dir: &Path) -> Vec<(PathBuf, RomReport)> {
+        let mut reports = self
+            .entries
+            .iter()
+            .filter(|(path, _)| path.starts_with(dir))
+            .map(|(path, entry)| (path.clone(), entry.report.clone()))
+            .collect::<Vec<_>>();
+        reports.sort_by(|(a, _), (b, _)| a.cmp(b));
+        reports
+    }
+
+    /// The report for the ROM at the given path, reading its header only if there is no cached
+    /// report or the cached report is stale.
+    fn report(&mut self, path: &Path, metadata: &fs::Metadata) -> io::Result<RomReport> {
+        let stamp = FileStamp::of(metadata);
+        if let Some(entry) = self.entries.get(path)
+            && entry.stamp == stamp
+        {
+            return Ok(entry.report.clone());
+        }
+
+        let report = RomReport::read(path)?;
+        self.entries.insert(
+            path.to_path_buf(),
+            CacheEntry {
+                stamp,
+                report: report.clone(),
+            },
+        );
+        self.is_dirty = true;
+
+        Ok(report)
+    }
+
+    /// Remove cached reports for ROMs in a directory that were not seen in a scan of it.
+    fn prune(&mut self, dir: &Path, seen: &HashSet<PathBuf>) {
+        let num_entries = self.entries.len();
+        self.entries
+            .retain(|path, _| !path.starts_with(dir) || seen.contains(path));
+
+        if self.entries.len() != num_entries {
+            self.is_dirty = true;
+        }
+    }
+}
+
+/// Reports for every `.gb` and `.gbc` file under a directory, sorted by path. Cached reports are
+/// used where they are not stale, and the cache is updated with everything that had to be read.
+/// Files that cannot be read are skipped.
+pub fn scan_rom_dir(dir: &Path, cache: &mut RomMetadataCache) -> Vec<(PathBuf, RomReport)> {
+    let mut reports = vec![];
+    let mut seen = HashSet::new();
+    scan_rom_dir_into(dir, cache, &mut reports, &mut seen);
+
+    cache.prune(dir, &seen);
+
+    reports.sort_by(|(a, _), (b, _)| a.cmp(b));
+    reports
+}
+
+fn scan_rom_dir_into(
+    dir: &Path,
+    cache: &mut RomMetadataCache,
+    reports: &mut Vec<(PathBuf, RomReport)>,
+    seen: &mut HashSet<PathBuf>,
+) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            scan_rom_dir_into(&path, cache, reports, seen);
+            continue;
+        }
+
+        if !path.to_str().is_some_and(has_rom_extension) {
+            continue;
+        }
+
+        match cache.report(&path, &metadata) {
+            Ok(report) => {
+                seen.insert(path.clone());
+                reports.push((path, report));
+            }
+            Err(error) => eprintln!("Could not read ROM {}: {}", path.display(), error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        env, fs,
+        path::{Path, PathBuf},
+        process,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::test_utils::build_test_rom;
+
+    use super::{CACHE_FORMAT_VERSION, CacheFile, RomMetadataCache, RomReport, scan_rom_dir};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("gbcemu-rom-library-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("roms").join("nested")).unwrap();
+        dir
+    }
+
+    fn set_modified(path: &Path, modified: SystemTime) {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn reports_are_cached_until_stale() {
+        let dir = test_dir("stale");
+        let roms_dir = dir.join("roms");
+        let rom_path = roms_dir.join("nested").join("game.gb");
+        fs::write(&rom_path, build_test_rom(0x00, 0x00, 0x00, &[])).unwrap();
+        fs::write(roms_dir.join("notes.txt"), "not a ROM").unwrap();
+        set_modified(&rom_path, UNIX_EPOCH + Duration::from_secs(1000));
+
+        let mut cache = RomMetadataCache::default();
+        let reports = scan_rom_dir(&roms_dir, &mut cache);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].0, rom_path);
+        assert_eq!(reports[0].1.title, "TESTROM");
+        assert!(!reports[0].1.is_cgb);
+        assert!(reports[0].1.has_valid_header_checksum);
+
+        // Same size and modification time, so the cached report is used even though the contents
+        // changed
+        let mut rom = build_test_rom(0x00, 0x00, 0x00, &[]);
+        rom[0x0134..0x0138].copy_from_slice(b"NEW\0");
+        fs::write(&rom_path, &rom).unwrap();
+        set_modified(&rom_path, UNIX_EPOCH + Duration::from_secs(1000));
+        assert_eq!(scan_rom_dir(&roms_dir, &mut cache), reports);
+        assert_eq!(cache.cached_reports(&roms_dir), reports);
+
+        // A new modification time makes the cached report stale
+        set_modified(&rom_path, UNIX_EPOCH + Duration::from_secs(2000));
+        let reports = scan_rom_dir(&roms_dir, &mut cache);
+        assert_eq!(reports[0].1.title, "NEW");
+        assert!(!reports[0].1.has_valid_header_checksum);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cache_round_trips_and_prunes_missing_roms() {
+        let dir = test_dir("prune");
+        let roms_dir = dir.join("roms");
+        let cache_path = dir.join("cache").join("rom_metadata_cache");
+        for name in ["a.gb", "b.gbc"] {
+            fs::write(roms_dir.join(name), build_test_rom(0x00, 0x00, 0x00, &[])).unwrap();
+        }
+
+        let mut cache = RomMetadataCache::load(&cache_path);
+        assert!(cache.is_empty());
+        scan_rom_dir(&roms_dir, &mut cache);
+        cache.save(&cache_path).unwrap();
+
+        let mut cache = RomMetadataCache::load(&cache_path);
+        assert_eq!(cache.len(), 2);
+
+        // Entries outside the scanned directory are kept
+        fs::remove_file(roms_dir.join("b.gbc")).unwrap();
+        let reports = scan_rom_dir(&roms_dir.join("nested"), &mut cache);
+        assert!(reports.is_empty());
+        assert_eq!(cache.len(), 2);
+
+        let reports = scan_rom_dir(&roms_dir, &mut cache);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(cache.len(), 1);
+        cache.save(&cache_path).unwrap();
+        assert_eq!(RomMetadataCache::load(&cache_path).len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupted_cache_is_rebuilt() {
+        let dir = test_dir("corrupted");
+        let roms_dir = dir.join("roms");
+        let cache_path = dir.join("rom_metadata_cache");
+        fs::write(roms_dir.join("a.gb"), build_test_rom(0x00, 0x00, 0x00, &[])).unwrap();
+        fs::write(&cache_path, [0xC1, 0x00, 0xFF]).unwrap();
+
+        let mut cache = RomMetadataCache::load(&cache_path);
+        assert!(cache.is_empty());
+        assert_eq!(scan_rom_dir(&roms_dir, &mut cache).len(), 1);
+        cache.save(&cache_path).unwrap();
+        assert_eq!(RomMetadataCache::load(&cache_path).len(), 1);
+
+        // Caches from other versions are discarded
+        let cache_file = CacheFile {
+            version: CACHE_FORMAT_VERSION + 1,
+            entries: HashMap::new(),
+        };
+        fs::write(&cache_path, rmp_serde::to_vec(&cache_file).unwrap()).unwrap();
+        assert!(RomMetadataCache::load(&cache_path).is_empty());
+
+        // Files too small to be a ROM are skipped
+        fs::write(roms_dir.join("b.gb"), [0; 16]).unwrap();
+        assert!(RomReport::read(&roms_dir.join("b.gb")).is_err());
+        assert_eq!(scan_rom_dir(&roms_dir, &mut cache).len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
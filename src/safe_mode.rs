@@ -0,0 +1,258 @@
+//! Recovery from a crash at startup.
+//!
+//! A crash marker file is written when the emulator thread starts and removed when the emulator
+//! shuts down cleanly. If the marker is still present at the next startup then the last run crashed,
+//! and the emulator starts in safe mode with risky options ignored so that a bad option cannot wedge
+//! the user in a crash loop. Each ROM has its own marker, so running one game never affects whether
+//! another starts in safe mode.
+
+use std::{
+    env,
+    ffi::OsString,
+    fs, io,
+    path::{self, Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+};
+
+use crate::{ram_init::RamInit, save_compat, save_file::platform_data_dir};
+
+const CRASH_MARKER_FILE_PREFIX: &str = "crash_marker";
+
+/// Argument for emulating a GameBoy Color
+const CGB_ARG: &str = "--cgb";
+
+/// Argument for the contents of RAM at power-on, followed by its value
+const RAM_INIT_ARG: &str = "--ram-init";
+
+/// Marks that an emulator is running. The marker holds the ID of the process that wrote it, so that
+/// a process only ever removes its own marker.
+#[derive(Clone)]
+pub struct CrashMarker {
+    path: PathBuf,
+}
+
+impl CrashMarker {
+    pub fn new(path: PathBuf) -> Self {
+        CrashMarker { path }
+    }
+
+    /// The crash marker for a ROM or save file in the platform data directory, if it can be
+    /// determined. Markers are keyed by the absolute path of the ROM or save file.
+    pub fn in_platform_data_dir(rom_or_save_path: &str) -> Option<Self> {
+        let rom_or_save_path =
+            path::absolute(rom_or_save_path).unwrap_or_else(|_| rom_or_save_path.into());
+        let file_name = format!(
+            "{}-{:08x}",
+            CRASH_MARKER_FILE_PREFIX,
+            save_compat::checksum(rom_or_save_path.as_os_str().as_encoded_bytes())
+        );
+
+        Some(Self::new(platform_data_dir()?.join(file_name)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether a marker was left behind by a run that did not shut down cleanly.
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn write(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&self.path, process::id().to_string())
+    }
+
+    /// Remove the marker if it was written by this process.
+    pub fn remove(&self) -> io::Result<()> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) if contents == process::id().to_string() => fs::remove_file(&self.path),
+            Ok(_) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// A new instance of the emulator to start once this instance has shut down. The new instance is
+/// only started after this instance has flushed its save file and removed its crash marker, so that
+/// the two instances never write the same files at once.
+#[derive(Clone, Default)]
+pub struct PendingRestart {
+    args: Arc<Mutex<Option<Vec<OsString>>>>,
+}
+
+impl PendingRestart {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restart with the same arguments, which will not be in safe mode. The caller is responsible
+    /// for closing this instance.
+    pub fn request_with_all_options(&self) {
+        self.request(env::args_os().skip(1).collect());
+    }
+
+    /// Restart with the same arguments, except running `rom_or_save_path` instead of
+    /// `current_rom_or_save_path`. The caller is responsible for closing this instance.
+    pub fn request_with_rom(&self, current_rom_or_save_path: &str, rom_or_save_path: &Path) {
+        self.request(replace_rom_arg(
+            env::args_os().skip(1).collect(),
+            current_rom_or_save_path,
+            rom_or_save_path,
+        ));
+    }
+
+    /// Restart with the same arguments, but emulating a GameBoy Color. The caller is responsible for
+    /// closing this instance.
+    pub fn request_as_cgb(&self) {
+        self.request(add_cgb_arg(env::args_os().skip(1).collect()));
+    }
+
+    /// Restart with the same arguments, but with different RAM contents at power-on. The caller is
+    /// responsible for closing this instance.
+    pub fn request_with_ram_init(&self, ram_init: RamInit) {
+        self.request(replace_ram_init_arg(
+            env::args_os().skip(1).collect(),
+            ram_init,
+        ));
+    }
+
+    fn request(&self, args: Vec<OsString>) {
+        *self.args.lock().unwrap() = Some(args);
+    }
+
+    /// Start the requested new instance, if any. Must only be called once this instance has shut
+    /// down.
+    pub fn spawn_if_requested(&self) -> io::Result<()> {
+        let Some(args) = self.args.lock().unwrap().take() else {
+            return Ok(());
+        };
+
+        process::Command::new(env::current_exe()?)
+            .args(args)
+            .spawn()?;
+
+        Ok(())
+    }
+}
+
+/// The ROM is the only positional argument, so it is the last argument with its value. Option
+/// values that happen to equal the ROM path come before it.
+fn replace_rom_arg(
+    mut args: Vec<OsString>,
+    current_rom_or_save_path: &str,
+    rom_or_save_path: &Path,
+) -> Vec<OsString> {
+    match args.iter().rposition(|arg| arg == current_rom_or_save_path) {
+        Some(index) => args[index] = rom_or_save_path.as_os_str().to_owned(),
+        None => args.push(rom_or_save_path.as_os_str().to_owned()),
+    }
+
+    args
+}
+
+fn add_cgb_arg(mut args: Vec<OsString>) -> Vec<OsString> {
+    if !args.iter().any(|arg| arg == CGB_ARG) {
+        args.insert(0, OsString::from(CGB_ARG));
+    }
+
+    args
+}
+
+fn replace_ram_init_arg(args: Vec<OsString>, ram_init: RamInit) -> Vec<OsString> {
+    let ram_init_prefix = format!("{}=", RAM_INIT_ARG);
+
+    let mut new_args = vec![OsString::from(RAM_INIT_ARG), ram_init.to_string().into()];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == RAM_INIT_ARG {
+            // Skip the value as well
+            args.next();
+        } else if !arg.to_string_lossy().starts_with(&ram_init_prefix) {
+            new_args.push(arg);
+        }
+    }
+
+    new_args
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, ffi::OsString, fs, path::Path, process};
+
+    use crate::ram_init::RamInit;
+
+    use super::{CrashMarker, add_cgb_arg, replace_ram_init_arg, replace_rom_arg};
+
+    #[test]
+    fn crash_markers_are_keyed_by_rom() {
+        let marker_path = |rom_or_save_path| {
+            CrashMarker::in_platform_data_dir(rom_or_save_path).map(|marker| marker.path)
+        };
+
+        assert_eq!(marker_path("roms/game.gb"), marker_path("roms/game.gb"));
+        if marker_path("roms/game.gb").is_some() {
+            assert_ne!(marker_path("roms/game.gb"), marker_path("roms/other.gb"));
+        }
+    }
+
+    #[test]
+    fn crash_marker_is_only_removed_by_its_writer() {
+        let dir = env::temp_dir().join(format!("gbcemu-crash-marker-{}", process::id()));
+        let marker = CrashMarker::new(dir.join("crash_marker"));
+
+        assert!(!marker.exists());
+        marker.remove().unwrap();
+
+        marker.write().unwrap();
+        assert!(marker.exists());
+        marker.remove().unwrap();
+        assert!(!marker.exists());
+
+        // Markers written by another process are left alone
+        fs::write(marker.path(), "0").unwrap();
+        marker.remove().unwrap();
+        assert!(marker.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rom_arg_is_replaced() {
+        let args = ["--cgb", "--symbols", "game.gb", "game.gb"].map(OsString::from);
+        assert_eq!(
+            replace_rom_arg(args.to_vec(), "game.gb", Path::new("other.zip")),
+            ["--cgb", "--symbols", "game.gb", "other.zip"].map(OsString::from)
+        );
+    }
+
+    #[test]
+    fn cgb_arg_is_added_once() {
+        let args = ["--symbols", "game.sym", "game.gb"].map(OsString::from);
+        let cgb_args = ["--cgb", "--symbols", "game.sym", "game.gb"].map(OsString::from);
+
+        assert_eq!(add_cgb_arg(args.to_vec()), cgb_args);
+        assert_eq!(add_cgb_arg(cgb_args.to_vec()), cgb_args);
+    }
+    #[test]
+    fn ram_init_arg_is_replaced() {
+        let args =
+            ["--ram-init", "ones", "--ram-init=zero", "--cgb", "game.gb"].map(OsString::from);
+        assert_eq!(
+            replace_ram_init_arg(args.to_vec(), RamInit::Random(7)),
+            ["--ram-init", "random:7", "--cgb", "game.gb"].map(OsString::from)
+        );
+
+        let args = ["game.gb"].map(OsString::from);
+        assert_eq!(
+            replace_ram_init_arg(args.to_vec(), RamInit::Pattern),
+            ["--ram-init", "pattern", "game.gb"].map(OsString::from)
+        );
+    }
+}
@@ -0,0 +1,511 @@
+//! Versioning and migration for serialized save data.
+//!
+//! Save files, quick saves, and exported state files are wrapped in a small header containing a
+//! magic number, the format version, a checksum of the payload, and (since version 3) the version
+//! of the emulator that wrote it. Data written before the header was introduced has no magic number
+//! and is treated as version 0. When loading, payloads are migrated one version at a time until
+//! they reach the current format version.
+//!
+//! Migrations deserialize into copies of the old layouts defined in this module, so that changes to
+//! the live types do not affect how old data is read. The one exception is MBC state, which is
+//! carried through migrations with the live `Mbc` types. Changing the serialized layout of an MBC
+//! requires a new migration that rewrites it.
+
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+use crate::{
+    mbc::types::Mbc,
+    save_file::{NUM_QUICK_SAVE_SLOTS, SaveFileError},
+    version::VERSION_STRING,
+};
+
+/// The version of the save format written by this build.
+pub const CURRENT_FORMAT_VERSION: u16 = 3;
+
+/// Size of the fixed part of the header: magic (4 bytes), version (2 bytes), checksum (4 bytes).
+const HEADER_SIZE: usize = 10;
+
+/// From this version on the fixed header is followed by the version string of the emulator that
+/// wrote the blob, prefixed by its length as a single byte.
+const FIRST_VERSION_WITH_WRITER: u16 = 3;
+
+/// The kinds of blobs that are versioned. Each has its own magic number so that one kind can't be
+/// mistakenly loaded as the other.
+#[derive(Clone, Copy)]
+pub enum BlobKind {
+    /// A full save file, containing cartridge state and quick saves
+    SaveFile,
+    /// The serialized state of the entire emulator
+    QuickSave,
+    /// A quick save exported to a standalone file
+    StateFile,
+}
+
+impl BlobKind {
+    fn magic(&self) -> &'static [u8; 4] {
+        match self {
+            BlobKind::SaveFile => b"GBCS",
+            BlobKind::QuickSave => b"GBCQ",
+            BlobKind::StateFile => b"GBCX",
+        }
+    }
+}
+
+/// Upgrades a payload of the given kind from one format version to the next.
+type Migration = fn(BlobKind, Vec<u8>) -> Result<Vec<u8>, SaveFileError>;
+
+/// Migration at index `i` upgrades a payload from version `i` to version `i + 1`.
+const MIGRATIONS: [Migration; CURRENT_FORMAT_VERSION as usize] =
+    [migrate_header_only, migrate_v1_to_v2, migrate_header_only];
+
+/// Version 1 introduced the header and version 3 added the writer's version to it. Neither changed
+/// the payload itself.
+fn migrate_header_only(_: BlobKind, payload: Vec<u8>) -> Result<Vec<u8>, SaveFileError> {
+    Ok(payload)
+}
+
+/// Cartridge as serialized up to version 1, which included the entire ROM.
+#[derive(Deserialize)]
+struct CartridgeV1 {
+    #[serde(with = "serde_bytes")]
+    rom: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    ram: Vec<u8>,
+    /// Not frozen, read with the live MBC types
+    mbc: Box<dyn Mbc>,
+    entry_point_code: [u8; 4],
+    title: String,
+    cartridge_type_byte: u8,
+    cgb_byte: u8,
+}
+
+/// Cartridge as serialized in version 2, where the ROM is replaced by its checksum.
+#[derive(Serialize)]
+struct CartridgeV2 {
+    rom_checksum: u32,
+    #[serde(with = "serde_bytes")]
+    ram: Vec<u8>,
+    mbc: Box<dyn Mbc>,
+    entry_point_code: [u8; 4],
+    title: String,
+    cartridge_type_byte: u8,
+    cgb_byte: u8,
+}
+
+impl CartridgeV1 {
+    /// Split into the ROM and the version 2 cartridge state.
+    fn into_v2(self) -> (Vec<u8>, CartridgeV2) {
+        let cartridge = CartridgeV2 {
+            rom_checksum: checksum(&self.rom),
+            ram: self.ram,
+            mbc: self.mbc,
+            entry_point_code: self.entry_point_code,
+            title: self.title,
+            cartridge_type_byte: self.cartridge_type_byte,
+            cgb_byte: self.cgb_byte,
+        };
+
+        (self.rom, cartridge)
+    }
+}
+
+#[derive(Deserialize)]
+struct SaveFileV1 {
+    #[serde(with = "serde_bytes")]
+    cartridge: Vec<u8>,
+    quick_saves: [Option<ByteBuf>; NUM_QUICK_SAVE_SLOTS],
+}
+
+#[derive(Serialize)]
+struct SaveFileV2 {
+    #[serde(with = "serde_bytes")]
+    rom: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    cartridge: Vec<u8>,
+    quick_saves: [Option<ByteBuf>; NUM_QUICK_SAVE_SLOTS],
+}
+
+/// Version 2 stores the ROM once in the save file instead of inside every serialized cartridge.
+fn migrate_v1_to_v2(kind: BlobKind, payload: Vec<u8>) -> Result<Vec<u8>, SaveFileError> {
+    match kind {
+        BlobKind::SaveFile => {
+            let save_file: SaveFileV1 = rmp_serde::from_slice(&payload)?;
+            let cartridge: CartridgeV1 = rmp_serde::from_slice(&save_file.cartridge)?;
+            let (rom, cartridge) = cartridge.into_v2();
+
+            // Quick saves are versioned independently and are migrated when they are loaded
+            let save_file = SaveFileV2 {
+                rom,
+                cartridge: rmp_serde::to_vec(&cartridge).unwrap(),
+                quick_saves: save_file.quick_saves,
+            };
+
+            Ok(rmp_serde::to_vec(&save_file).unwrap())
+        }
+        BlobKind::QuickSave => {
+            // The emulator is serialized as an array whose first element is the cartridge. Only
+            // the cartridge is rewritten, all following fields are copied over unchanged.
+            let mut reader = payload.as_slice();
+            let num_fields = rmp::decode::read_array_len(&mut reader)
+                .map_err(|error| SaveFileError::Corrupt(error.to_string()))?;
+            let cartridge: CartridgeV1 = rmp_serde::from_read(&mut reader)?;
+            let (_, cartridge) = cartridge.into_v2();
+
+            let mut migrated = Vec::with_capacity(payload.len());
+            rmp::encode::write_array_len(&mut migrated, num_fields).unwrap();
+            rmp_serde::encode::write(&mut migrated, &cartridge).unwrap();
+            migrated.extend_from_slice(reader);
+
+            Ok(migrated)
+        }
+        // State files were introduced in version 3, so there are no older ones to migrate
+        BlobKind::StateFile => Ok(payload),
+    }
+}
+
+/// Wrap a payload in a header for the current format version.
+pub fn encode(kind: BlobKind, payload: &[u8]) -> Vec<u8> {
+    let writer = &VERSION_STRING.as_bytes()[..VERSION_STRING.len().min(u8::MAX as usize)];
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + 1 + writer.len() + payload.len());
+    bytes.extend_from_slice(kind.magic());
+    bytes.extend_from_slice(&CURRENT_FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&checksum(payload).to_le_bytes());
+    bytes.push(writer.len() as u8);
+    bytes.extend_from_slice(writer);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// The parsed header of a blob.
+struct Header<'a> {
+    version: u16,
+    checksum: u32,
+    /// Version of the emulator that wrote the blob, if recorded
+    written_by: Option<String>,
+    payload: &'a [u8],
+}
+
+/// Parse the header of a blob. Returns None for legacy blobs without a header.
+fn parse_header(kind: BlobKind, bytes: &[u8]) -> Result<Option<Header<'_>>, SaveFileError> {
+    if !bytes.starts_with(kind.magic()) {
+        return Ok(None);
+    }
+
+    if bytes.len() < HEADER_SIZE {
+        return Err(SaveFileError::Corrupt("header is truncated".to_string()));
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    let checksum = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    let mut payload = &bytes[HEADER_SIZE..];
+
+    let mut written_by = None;
+    if version >= FIRST_VERSION_WITH_WRITER {
+        let Some((&writer_len, rest)) = payload.split_first() else {
+            return Err(SaveFileError::Corrupt("header is truncated".to_string()));
+        };
+
+        let Some((writer, rest)) = rest.split_at_checked(writer_len as usize) else {
+            return Err(SaveFileError::Corrupt("header is truncated".to_string()));
+        };
+
+        written_by = Some(String::from_utf8_lossy(writer).into_owned());
+        payload = rest;
+    }
+
+    Ok(Some(Header {
+        version,
+        checksum,
+        written_by,
+        payload,
+    }))
+}
+
+/// Version of the emulator that wrote a blob, if the blob records it.
+pub fn written_by(kind: BlobKind, bytes: &[u8]) -> Option<String> {
+    parse_header(kind, bytes).ok()??.written_by
+}
+
+/// Validate the header of a blob and migrate its payload to the current format version.
+///
+/// Blobs without a header are legacy version 0 blobs and are migrated from the beginning.
+pub fn decode(kind: BlobKind, bytes: &[u8]) -> Result<Vec<u8>, SaveFileError> {
+    let (version, payload) = match parse_header(kind, bytes)? {
+        Some(header) => {
+            if header.version > CURRENT_FORMAT_VERSION {
+                return Err(SaveFileError::SaveFormatMismatch {
+                    found: header.version,
+                    supported: CURRENT_FORMAT_VERSION,
+                    written_by: header.written_by,
+                });
+            }
+
+            if checksum(header.payload) != header.checksum {
+                return Err(SaveFileError::Corrupt("checksum mismatch".to_string()));
+            }
+
+            (header.version, header.payload)
+        }
+        None => (0, bytes),
+    };
+
+    let mut payload = payload.to_vec();
+    for migration in &MIGRATIONS[version as usize..] {
+        payload = migration(kind, payload)?;
+    }
+
+    Ok(payload)
+}
+
+/// 32-bit FNV-1a hash, used to detect corrupted payloads. Unlike the standard library's hasher it is
+/// stable across releases, so it is also used in file names that must not change.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811C9DC5;
+    for byte in bytes {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf, sync::Arc};
+
+    use serde_bytes::ByteBuf;
+
+    use crate::{
+        emulator::{Emulator, EmulatorBuilder, SCREEN_HEIGHT, SCREEN_WIDTH},
+        machine::Machine,
+        options::Options,
+        ppu::Color,
+        save_file::{SaveFile, SaveFileError},
+        test_utils::{FILL_VRAM_PROGRAM, new_test_emulator, with_large_stack},
+        version::VERSION_STRING,
+    };
+
+    use super::{BlobKind, checksum, decode, encode, written_by};
+
+    /// Hash of the framebuffer produced by the fixture ROM once it has finished filling VRAM.
+    const EXPECTED_FRAMEBUFFER_HASH: u32 = 0x73D2FCC5;
+
+    const SAVE_FILE_FIXTURES: [&str; 4] = [
+        "save_v0.svgb",
+        "save_v1.svgb",
+        "save_v2.svgb",
+        "save_v3.svgb",
+    ];
+    const QUICK_SAVE_FIXTURES: [&str; 4] = [
+        "quick_save_v0.bin",
+        "quick_save_v1.bin",
+        "quick_save_v2.bin",
+        "quick_save_v3.bin",
+    ];
+
+    fn fixture_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join(name)
+    }
+
+    fn read_fixture(name: &str) -> Vec<u8> {
+        fs::read(fixture_path(name)).unwrap()
+    }
+
+    fn framebuffer_hash(emulator: &Emulator) -> u32 {
+        let mut bytes = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 2);
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let raw = match emulator.read_pixel(x, y) {
+                    Color::Dmg(index) => index as u16,
+                    Color::Cgb(color) => color.raw(),
+                };
+                bytes.extend_from_slice(&raw.to_le_bytes());
+            }
+        }
+        checksum(&bytes)
+    }
+
+    fn run_60_frames(emulator: &mut Emulator) {
+        for _ in 0..60 {
+            emulator.run_frame();
+        }
+    }
+
+    #[test]
+    fn save_file_fixtures_load() {
+        with_large_stack(|| {
+            for fixture in SAVE_FILE_FIXTURES {
+                let save_file = SaveFile::from_bytes(&read_fixture(fixture)).unwrap();
+                let quick_save = save_file.quick_saves[0].as_ref().unwrap().to_vec();
+
+                // Fresh boot from the saved cartridge. The fixture ROM fills VRAM without waiting
+                // for the PPU, so VRAM access restrictions are disabled to match the saved state.
+                let options = Options {
+                    no_access_restrictions: true,
+                    ..Options::default()
+                };
+                let mut emulator =
+                    EmulatorBuilder::from_saved_cartidge(save_file.clone(), Machine::Dmg)
+                        .unwrap()
+                        .with_options(Arc::new(options))
+                        .build()
+                        .unwrap();
+                emulator.emulate_boot_sequence();
+                run_60_frames(&mut emulator);
+                assert_eq!(framebuffer_hash(&emulator), EXPECTED_FRAMEBUFFER_HASH);
+
+                // Resume from the quick save stored in the save file
+                let mut emulator =
+                    EmulatorBuilder::from_quick_save_bytes(save_file, &quick_save, Machine::Dmg)
+                        .unwrap()
+                        .build()
+                        .unwrap();
+                run_60_frames(&mut emulator);
+                assert_eq!(framebuffer_hash(&emulator), EXPECTED_FRAMEBUFFER_HASH);
+            }
+        });
+    }
+
+    #[test]
+    fn quick_save_fixtures_load() {
+        with_large_stack(|| {
+            let save_file = SaveFile::from_bytes(&read_fixture(SAVE_FILE_FIXTURES[0])).unwrap();
+
+            for fixture in QUICK_SAVE_FIXTURES {
+                let mut emulator = EmulatorBuilder::from_quick_save_bytes(
+                    save_file.clone(),
+                    &read_fixture(fixture),
+                    Machine::Dmg,
+                )
+                .unwrap()
+                .build()
+                .unwrap();
+                run_60_frames(&mut emulator);
+                assert_eq!(framebuffer_hash(&emulator), EXPECTED_FRAMEBUFFER_HASH);
+            }
+        });
+    }
+
+    #[test]
+    fn truncated_save_files_are_corrupt() {
+        for fixture in SAVE_FILE_FIXTURES {
+            let bytes = read_fixture(fixture);
+            for len in [0, 3, 8, bytes.len() / 2, bytes.len() - 1] {
+                let result = SaveFile::from_bytes(&bytes[..len]);
+                assert!(matches!(result, Err(SaveFileError::Corrupt(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn flipped_bytes_are_corrupt() {
+        let bytes = read_fixture("save_v1.svgb");
+        for index in [20, bytes.len() / 2, bytes.len() - 1] {
+            let mut corrupted = bytes.clone();
+            corrupted[index] ^= 0xFF;
+
+            let result = SaveFile::from_bytes(&corrupted);
+            assert!(matches!(result, Err(SaveFileError::Corrupt(_))));
+        }
+
+        let mut corrupted = read_fixture("quick_save_v1.bin");
+        corrupted[100] ^= 0x01;
+        assert!(matches!(
+            decode(BlobKind::QuickSave, &corrupted),
+            Err(SaveFileError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn newer_format_version_is_mismatch() {
+        let mut bytes = encode(BlobKind::SaveFile, &[0x90]);
+        bytes[4] = 0xFF;
+
+        let result = SaveFile::from_bytes(&bytes);
+        assert!(matches!(
+            result,
+            Err(SaveFileError::SaveFormatMismatch { found: 0x00FF, .. })
+        ));
+    }
+
+    #[test]
+    fn header_records_writer_version() {
+        let bytes = encode(BlobKind::QuickSave, &[0x90]);
+        assert_eq!(
+            written_by(BlobKind::QuickSave, &bytes),
+            Some(VERSION_STRING.to_string())
+        );
+        assert_eq!(decode(BlobKind::QuickSave, &bytes).unwrap(), vec![0x90]);
+
+        // Blobs written before the writer was recorded, or without a header at all
+        assert_eq!(
+            written_by(BlobKind::SaveFile, &read_fixture("save_v2.svgb")),
+            None
+        );
+        assert_eq!(
+            written_by(BlobKind::SaveFile, &read_fixture("save_v0.svgb")),
+            None
+        );
+
+        // The writer is included in format mismatch errors
+        let mut bytes = encode(BlobKind::SaveFile, &[0x90]);
+        bytes[4] = 0xFF;
+        let error = SaveFile::from_bytes(&bytes).err().unwrap();
+        assert!(matches!(
+            &error,
+            SaveFileError::SaveFormatMismatch { written_by: Some(writer), .. }
+                if writer == VERSION_STRING
+        ));
+        assert!(
+            error
+                .to_string()
+                .contains(&format!("written by version {}", VERSION_STRING))
+        );
+
+        // A writer that runs past the end of the blob is corrupt
+        let bytes = encode(BlobKind::SaveFile, &[]);
+        assert!(matches!(
+            decode(BlobKind::SaveFile, &bytes[..bytes.len() - 1]),
+            Err(SaveFileError::Corrupt(_))
+        ));
+    }
+
+    /// Write fixtures for the current format version. Run manually after bumping the format
+    /// version, then add the new fixtures to the lists above. Existing fixtures must never be
+    /// regenerated.
+    #[test]
+    #[ignore]
+    fn generate_current_version_fixtures() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Dmg, &FILL_VRAM_PROGRAM);
+
+            for _ in 0..30 {
+                emulator.run_frame();
+            }
+
+            let payload = rmp_serde::to_vec(&emulator).unwrap();
+            let quick_save = encode(BlobKind::QuickSave, &payload);
+
+            let mut save_file = SaveFile::new(emulator.cartridge());
+            save_file.quick_saves[0] = Some(ByteBuf::from(quick_save.clone()));
+
+            let version = super::CURRENT_FORMAT_VERSION;
+            save_file
+                .flush_to_disk(
+                    fixture_path(&format!("save_v{}.svgb", version))
+                        .to_str()
+                        .unwrap(),
+                )
+                .unwrap();
+            fs::write(
+                fixture_path(&format!("quick_save_v{}.bin", version)),
+                quick_save,
+            )
+            .unwrap();
+        });
+    }
+}
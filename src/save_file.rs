@@ -1,23 +1,56 @@
-use std::{array, fs};
+use std::{
+    array, env, fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 
-use crate::cartridge::Cartridge;
+use crate::{
+    cartridge::Cartridge,
+    save_compat::{self, BlobKind},
+};
 
 /// The file extension for our custom save file format.
 pub const SAVE_FILE_EXTENSION: &str = ".svgb";
 
+/// The file extension for raw battery-backed RAM, as read and written by most other emulators.
+pub const RAW_SAVE_FILE_EXTENSION: &str = ".sav";
+
+/// The file extension for save states exported to standalone files.
+pub const STATE_FILE_EXTENSION: &str = ".state";
+
+/// The file extension for the state written on exit when resuming on launch is enabled. Kept apart
+/// from the quick save slots so that it never replaces one of the user's own saves.
+pub const AUTO_STATE_FILE_EXTENSION: &str = ".autostate";
+
+/// Size of the RTC footer appended to raw save files for cartridges with an MBC3 timer.
+pub const RTC_FOOTER_SIZE: usize = 48;
+
+/// Some emulators write the RTC footer with a 32-bit timestamp instead.
+const SHORT_RTC_FOOTER_SIZE: usize = 44;
+
 /// Automatically flush the save file to disk every 5 seconds.
 pub const SAVE_FILE_AUTO_FLUSH_INTERVAL_SECS: u64 = 5;
 
+/// After repeated failures to write the save file the flush interval doubles, up to 2^6 times the
+/// normal interval (a little over 5 minutes).
+const MAX_SAVE_FILE_FLUSH_BACKOFF_SHIFT: u32 = 6;
+
 pub const NUM_QUICK_SAVE_SLOTS: usize = 10;
 
 /// A save file for a ROM. Includes both the saved data on the cartridge as well as the save states
 /// for this ROM.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SaveFile {
-    /// The serialized state of the cartridge. Only cartridge state.
+    /// The ROM for this save file. Stored once here and shared by the cartridge state and every
+    /// quick save, none of which include the ROM themselves.
+    #[serde(with = "shared_bytes")]
+    pub rom: Arc<[u8]>,
+
+    /// The serialized state of the cartridge. Only cartridge state, not including the ROM.
     #[serde(with = "serde_bytes")]
     pub cartridge: Vec<u8>,
 
@@ -25,23 +58,479 @@ pub struct SaveFile {
     pub quick_saves: [Option<ByteBuf>; NUM_QUICK_SAVE_SLOTS],
 }
 
+/// Errors that can occur when loading save data. All are recoverable, the caller can choose to
+/// continue without the save data.
+#[derive(Debug)]
+pub enum SaveFileError {
+    /// The save data was written with a newer format version than this build supports. Includes
+    /// the version of the emulator that wrote it, if known.
+    SaveFormatMismatch {
+        found: u16,
+        supported: u16,
+        written_by: Option<String>,
+    },
+    /// The save data is truncated or malformed
+    Corrupt(String),
+    /// The saved state belongs to a different ROM than the one it is being loaded with
+    RomMismatch,
+    /// A raw save file does not match the RAM size declared in the cartridge header
+    RawSizeMismatch { found: usize, expected: usize },
+}
+
+impl fmt::Display for SaveFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveFileError::SaveFormatMismatch {
+                found,
+                supported,
+                written_by,
+            } => {
+                write!(f, "save format version {}", found)?;
+                if let Some(written_by) = written_by {
+                    write!(f, " written by version {}", written_by)?;
+                }
+                write!(
+                    f,
+                    " is not supported (newest supported version is {})",
+                    supported
+                )
+            }
+            SaveFileError::Corrupt(reason) => write!(f, "save data is corrupt: {}", reason),
+            SaveFileError::RomMismatch => write!(f, "save data belongs to a different ROM"),
+            SaveFileError::RawSizeMismatch { found, expected } => write!(
+                f,
+                "raw save file is {} bytes but the cartridge has {} bytes of RAM",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveFileError {}
+
+impl From<rmp_serde::decode::Error> for SaveFileError {
+    fn from(error: rmp_serde::decode::Error) -> Self {
+        SaveFileError::Corrupt(error.to_string())
+    }
+}
+
 impl SaveFile {
     pub fn new(cartridge: &Cartridge) -> Self {
         let cartridge_bytes = rmp_serde::to_vec(cartridge).unwrap();
 
         SaveFile {
+            rom: cartridge.shared_rom(),
             cartridge: cartridge_bytes,
             quick_saves: array::from_fn(|_| None),
         }
     }
 
+    /// Read a save file written by any supported version of the save format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Box<SaveFile>, SaveFileError> {
+        let payload = save_compat::decode(BlobKind::SaveFile, bytes)?;
+        Ok(Box::new(rmp_serde::from_slice(&payload)?))
+    }
+
+    /// Serialize the save file in the current version of the save format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let payload = rmp_serde::to_vec(self).unwrap();
+        save_compat::encode(BlobKind::SaveFile, &payload)
+    }
+
     pub fn update_cartridge_state(&mut self, cartridge: &Cartridge) {
         let cartridge_bytes = rmp_serde::to_vec(cartridge).unwrap();
         self.cartridge = cartridge_bytes;
     }
 
-    pub fn flush_to_disk(&self, path: &str) {
-        let save_file_bytes = rmp_serde::to_vec(self).unwrap();
-        fs::write(path, save_file_bytes).unwrap();
+    pub fn flush_to_disk(&self, path: &str) -> io::Result<()> {
+        write_file_atomically(Path::new(path), &self.to_bytes())
+    }
+}
+
+/// A quick save exported to a standalone file. Records the checksum of the ROM it belongs to, so
+/// that importing it alongside a different ROM fails before any emulator state is replaced.
+#[derive(Serialize, Deserialize)]
+pub struct StateFile {
+    /// Checksum of the ROM the state belongs to
+    pub rom_checksum: u32,
+
+    /// The serialized quick save. Versioned independently, like the quick saves in a save file.
+    pub quick_save: ByteBuf,
+}
+
+impl StateFile {
+    pub fn new(cartridge: &Cartridge, quick_save: Vec<u8>) -> Self {
+        StateFile {
+            rom_checksum: cartridge.rom_checksum(),
+            quick_save: ByteBuf::from(quick_save),
+        }
+    }
+
+    /// Read a state file written by any supported version of the save format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<StateFile, SaveFileError> {
+        let payload = save_compat::decode(BlobKind::StateFile, bytes)?;
+        Ok(rmp_serde::from_slice(&payload)?)
+    }
+
+    /// Serialize the state file in the current version of the save format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let payload = rmp_serde::to_vec(self).unwrap();
+        save_compat::encode(BlobKind::StateFile, &payload)
+    }
+
+    /// Check that this state belongs to the ROM in the given cartridge.
+    pub fn check_rom(&self, cartridge: &Cartridge) -> Result<(), SaveFileError> {
+        if self.rom_checksum != cartridge.rom_checksum() {
+            return Err(SaveFileError::RomMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+/// How battery-backed cartridge RAM is saved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SaveFormat {
+    /// Only our own save file format, which also holds quick saves
+    #[default]
+    Native,
+    /// Also read and write a raw .sav file next to the ROM, compatible with other emulators
+    Raw,
+}
+
+impl FromStr for SaveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(SaveFormat::Native),
+            "raw" => Ok(SaveFormat::Raw),
+            _ => Err(format!("expected raw or native but found {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SaveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveFormat::Native => write!(f, "native"),
+            SaveFormat::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+/// Contents of the raw save file for a cartridge. None if the cartridge has no battery so nothing
+/// would persist.
+pub fn raw_save_bytes(cartridge: &Cartridge) -> Option<Vec<u8>> {
+    if !cartridge.has_battery() {
+        return None;
+    }
+
+    Some(raw_ram_bytes(cartridge))
+}
+
+/// The RAM declared in the header followed by the RTC footer, if any, in the raw save file format.
+pub fn raw_ram_bytes(cartridge: &Cartridge) -> Vec<u8> {
+    let mut bytes = cartridge.ram()[..cartridge.header_ram_size()].to_vec();
+    if cartridge.has_rtc()
+        && let Some(footer) = cartridge.mbc().rtc_footer()
+    {
+        bytes.extend_from_slice(&footer);
+    }
+
+    bytes
+}
+
+/// Load a raw save file into the cartridge's RAM. The size must match the RAM size declared in the
+/// header, plus an optional RTC footer for cartridges with a timer.
+///
+/// The RTC footer is validated but otherwise ignored, since the RTC always follows the emulator's
+/// clock.
+pub fn load_raw_save(cartridge: &mut Cartridge, bytes: &[u8]) -> Result<(), SaveFileError> {
+    let ram_size = cartridge.header_ram_size();
+    let footer_size = bytes.len().wrapping_sub(ram_size);
+
+    let is_valid_size = footer_size == 0
+        || (cartridge.has_rtc()
+            && (footer_size == RTC_FOOTER_SIZE || footer_size == SHORT_RTC_FOOTER_SIZE));
+    if !is_valid_size {
+        return Err(SaveFileError::RawSizeMismatch {
+            found: bytes.len(),
+            expected: ram_size,
+        });
+    }
+
+    cartridge.ram_mut()[..ram_size].copy_from_slice(&bytes[..ram_size]);
+
+    Ok(())
+}
+
+/// Tracks failures to write the save file, so that retries back off and the user is only notified
+/// of the first failure.
+#[derive(Default)]
+pub struct SaveFileFlushState {
+    num_consecutive_failures: u32,
+    has_reported_failure: bool,
+}
+
+impl SaveFileFlushState {
+    /// Seconds between automatic flushes of the save file.
+    pub fn flush_interval_secs(&self) -> u64 {
+        SAVE_FILE_AUTO_FLUSH_INTERVAL_SECS
+            << self
+                .num_consecutive_failures
+                .min(MAX_SAVE_FILE_FLUSH_BACKOFF_SHIFT)
+    }
+
+    pub fn record_success(&mut self) {
+        self.num_consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&mut self) {
+        self.num_consecutive_failures += 1;
+    }
+
+    /// Whether a problem writing the save file should be reported to the user. Only returns true
+    /// the first time it is called.
+    pub fn should_report_failure(&mut self) -> bool {
+        !std::mem::replace(&mut self.has_reported_failure, true)
+    }
+}
+
+/// Write a file by first writing a sibling file then renaming it into place, so that a failed or
+/// interrupted write (such as on a full disk) never destroys the existing file.
+pub fn write_file_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".partial");
+    let partial_path = path.with_file_name(file_name);
+
+    if let Err(error) = fs::write(&partial_path, bytes) {
+        let _ = fs::remove_file(&partial_path);
+        return Err(error);
+    }
+
+    fs::rename(&partial_path, path)
+}
+
+/// The directory for application data on this platform, if it can be determined.
+pub fn platform_data_dir() -> Option<PathBuf> {
+    let base_dir = if cfg!(target_os = "windows") {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(env::var_os("HOME")?)
+            .join("Library")
+            .join("Application Support")
+    } else {
+        match env::var_os("XDG_DATA_HOME") {
+            Some(data_home) => PathBuf::from(data_home),
+            None => PathBuf::from(env::var_os("HOME")?)
+                .join(".local")
+                .join("share"),
+        }
+    };
+
+    Some(base_dir.join("gbcemu"))
+}
+
+/// The directory for cached data on this platform, if it can be determined. Anything in it can be
+/// rebuilt if it is deleted.
+pub fn platform_cache_dir() -> Option<PathBuf> {
+    let base_dir = if cfg!(target_os = "windows") {
+        PathBuf::from(env::var_os("LOCALAPPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(env::var_os("HOME")?)
+            .join("Library")
+            .join("Caches")
+    } else {
+        match env::var_os("XDG_CACHE_HOME") {
+            Some(cache_home) => PathBuf::from(cache_home),
+            None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        }
+    };
+
+    Some(base_dir.join("gbcemu"))
+}
+
+/// Path to write a save file to within the fallback directory, keeping the original file name.
+pub fn fallback_save_file_path(fallback_dir: &Path, save_file_path: &str) -> Option<String> {
+    let file_name = Path::new(save_file_path).file_name()?;
+    Some(fallback_dir.join(file_name).to_str()?.to_string())
+}
+
+/// Write a file at `path`, moving it into the fallback directory if it cannot be written there.
+/// On success through the fallback directory `path` is updated to the new location.
+///
+/// On failure returns the error from the original location along with the fallback path that was
+/// written to, if any.
+pub fn write_with_fallback(
+    path: &mut String,
+    fallback_dir: Option<&Path>,
+    write: impl Fn(&str) -> io::Result<()>,
+) -> Result<(), (io::Error, Option<String>)> {
+    let error = match write(path.as_str()) {
+        Ok(()) => return Ok(()),
+        Err(error) => error,
+    };
+
+    eprintln!("Unable to write {}: {}", path, error);
+
+    let fallback_path = fallback_dir.and_then(|fallback_dir| {
+        let fallback_path = fallback_save_file_path(fallback_dir, path)?;
+        if fallback_path == *path {
+            return None;
+        }
+
+        match fs::create_dir_all(fallback_dir).and_then(|_| write(&fallback_path)) {
+            Ok(()) => Some(fallback_path),
+            Err(error) => {
+                eprintln!("Unable to write {}: {}", fallback_path, error);
+                None
+            }
+        }
+    });
+
+    if let Some(fallback_path) = &fallback_path {
+        path.clone_from(fallback_path);
+    }
+
+    Err((error, fallback_path))
+}
+
+/// Serialize a shared byte slice as a single msgpack binary value.
+mod shared_bytes {
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde_bytes::ByteBuf;
+
+    pub fn serialize<S: Serializer>(bytes: &Arc<[u8]>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<[u8]>, D::Error> {
+        Ok(ByteBuf::deserialize(deserializer)?.into_vec().into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use crate::{
+        cartridge::Cartridge, emulator::EmulatorBuilder, error::Error, machine::Machine,
+        test_utils::build_test_rom,
+    };
+
+    use super::{
+        RTC_FOOTER_SIZE, SaveFile, SaveFileError, load_raw_save, raw_save_bytes,
+        write_file_atomically,
+    };
+
+    #[test]
+    fn raw_save_round_trip() {
+        // MBC1+RAM+BATTERY with 8KB of RAM
+        let rom = build_test_rom(0x03, 0x01, 0x02, &[]);
+        let mut cartridge = Cartridge::new_from_rom_bytes(rom.clone()).unwrap();
+        for (i, byte) in cartridge.ram_mut().iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        let bytes = raw_save_bytes(&cartridge).unwrap();
+        assert_eq!(bytes.len(), 8 * 1024);
+
+        let mut loaded_cartridge = Cartridge::new_from_rom_bytes(rom).unwrap();
+        load_raw_save(&mut loaded_cartridge, &bytes).unwrap();
+        assert_eq!(loaded_cartridge.ram(), cartridge.ram());
+
+        // Cartridges without a battery have no raw save
+        let cartridge =
+            Cartridge::new_from_rom_bytes(build_test_rom(0x02, 0x01, 0x02, &[])).unwrap();
+        assert!(raw_save_bytes(&cartridge).is_none());
+    }
+
+    #[test]
+    fn raw_save_size_mismatch() {
+        let mut cartridge =
+            Cartridge::new_from_rom_bytes(build_test_rom(0x03, 0x01, 0x02, &[])).unwrap();
+
+        let result = load_raw_save(&mut cartridge, &[0xFF; 100]);
+        assert!(matches!(
+            result,
+            Err(SaveFileError::RawSizeMismatch {
+                found: 100,
+                expected: 0x2000
+            })
+        ));
+        assert!(cartridge.ram().iter().all(|byte| *byte == 0));
+
+        // An RTC footer is only allowed for cartridges with a timer
+        let bytes = vec![0xFF; 0x2000 + RTC_FOOTER_SIZE];
+        assert!(load_raw_save(&mut cartridge, &bytes).is_err());
+    }
+
+    #[test]
+    fn raw_save_rtc_footer() {
+        // MBC3+TIMER+RAM+BATTERY with 32KB of RAM
+        let rom = build_test_rom(0x10, 0x01, 0x03, &[]);
+        let mut cartridge = Cartridge::new_from_rom_bytes(rom).unwrap();
+
+        let bytes = raw_save_bytes(&cartridge).unwrap();
+        assert_eq!(bytes.len(), 32 * 1024 + RTC_FOOTER_SIZE);
+
+        // Footers with either a 64 or 32 bit timestamp are accepted, as is no footer at all
+        for footer_size in [RTC_FOOTER_SIZE, 44, 0] {
+            let bytes = vec![0xAB; 32 * 1024 + footer_size];
+            load_raw_save(&mut cartridge, &bytes).unwrap();
+            assert!(cartridge.ram().iter().all(|byte| *byte == 0xAB));
+        }
+
+        assert!(load_raw_save(&mut cartridge, &vec![0; 32 * 1024 + 10]).is_err());
+
+        // MBC3+TIMER+BATTERY without RAM only saves the RTC
+        let cartridge =
+            Cartridge::new_from_rom_bytes(build_test_rom(0x0F, 0x01, 0x00, &[])).unwrap();
+        assert_eq!(raw_save_bytes(&cartridge).unwrap().len(), RTC_FOOTER_SIZE);
+    }
+
+    #[test]
+    fn flipped_save_file_byte_is_an_error() {
+        let rom = build_test_rom(0x03, 0x01, 0x02, &[]);
+        let cartridge = Cartridge::new_from_rom_bytes(rom).unwrap();
+        let bytes = SaveFile::new(&cartridge).to_bytes();
+
+        let save_file = SaveFile::from_bytes(&bytes).unwrap();
+        assert!(EmulatorBuilder::from_saved_cartidge(save_file, Machine::Dmg).is_ok());
+
+        // Corruption anywhere in the file is reported instead of panicking, including in the header
+        for i in (0..bytes.len()).step_by(61) {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= 0x01;
+
+            let error = SaveFile::from_bytes(&corrupted)
+                .and_then(|save_file| EmulatorBuilder::from_saved_cartidge(save_file, Machine::Dmg))
+                .err()
+                .unwrap_or_else(|| panic!("flipped byte {} was not detected", i));
+            assert!(
+                matches!(
+                    Error::from(error),
+                    Error::Decode(_) | Error::SaveVersionMismatch { .. }
+                ),
+                "flipped byte {}",
+                i
+            );
+        }
+    }
+    #[test]
+    fn atomic_write_replaces_file() {
+        let dir = env::temp_dir().join(format!("gbcemu-atomic-write-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.svgb");
+
+        write_file_atomically(&path, &[1, 2, 3]).unwrap();
+        write_file_atomically(&path, &[4, 5]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [4, 5]);
+        assert!(!dir.join("game.svgb.partial").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
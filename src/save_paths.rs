@@ -0,0 +1,268 @@
+//! Where the save files for a ROM are written.
+//!
+//! By default save files are written next to the ROM with the ROM's file extension replaced. When a
+//! save directory is given they are written there instead. ROMs with the same file name in different
+//! directories would then collide, so the name is suffixed with a hash of the ROM's directory.
+
+use std::{
+    fmt, fs, io,
+    path::{self, Path, PathBuf},
+};
+
+use crate::{
+    save_compat,
+    save_file::{AUTO_STATE_FILE_EXTENSION, RAW_SAVE_FILE_EXTENSION, SAVE_FILE_EXTENSION},
+};
+
+// Files with these extensions are always loaded as ROMs. Files with other extensions are only
+// loaded if their contents look like a ROM.
+const GB_FILE_EXTENSION: &str = ".gb";
+const GBC_FILE_EXTENSION: &str = ".gbc";
+
+/// Zip archives are loaded from the first ROM they contain
+const ZIP_FILE_EXTENSION: &str = ".zip";
+
+/// Whether a path has a ROM extension, ignoring case.
+pub fn has_rom_extension(rom_path: &str) -> bool {
+    let rom_path = rom_path.to_ascii_lowercase();
+    rom_path.ends_with(GB_FILE_EXTENSION) || rom_path.ends_with(GBC_FILE_EXTENSION)
+}
+
+/// Whether a path has a zip extension, ignoring case.
+pub fn has_zip_extension(rom_path: &str) -> bool {
+    rom_path.to_ascii_lowercase().ends_with(ZIP_FILE_EXTENSION)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SavePaths {
+    pub save_file_path: String,
+    /// Path of the raw .sav file, only used when the raw save format is selected
+    pub raw_save_file_path: String,
+    /// Path of the state to resume from on launch, only used when resuming on launch is enabled
+    pub auto_state_path: String,
+}
+
+#[derive(Debug)]
+pub enum SavePathsError {
+    Io(io::Error),
+    /// Save file paths are stored as strings, so must be valid UTF-8
+    NonUtf8Path(PathBuf),
+}
+
+impl fmt::Display for SavePathsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SavePathsError::Io(error) => write!(f, "could not create save directory: {}", error),
+            SavePathsError::NonUtf8Path(path) => {
+                write!(f, "save path {} is not valid UTF-8", path.display())
+            }
+        }
+    }
+}
+
+impl From<io::Error> for SavePathsError {
+    fn from(error: io::Error) -> Self {
+        SavePathsError::Io(error)
+    }
+}
+
+/// The save file paths for a ROM, written to the save directory if one is given. The save directory
+/// is created if it does not exist.
+pub fn save_paths_for_rom(
+    rom_path: &str,
+    save_dir: Option<&Path>,
+) -> Result<SavePaths, SavePathsError> {
+    // Save files for ROMs with other extensions keep the full file name, so that e.g. game.bin
+    // and game.gb do not share a save file
+    let rom_base_path = strip_extension(
+        rom_path,
+        &[GB_FILE_EXTENSION, GBC_FILE_EXTENSION, ZIP_FILE_EXTENSION],
+    );
+
+    let base_path = match save_dir {
+        None => rom_base_path.to_string(),
+        Some(save_dir) => {
+            let base_path = save_dir.join(save_dir_file_name(rom_base_path)?);
+            let base_path = base_path
+                .into_os_string()
+                .into_string()
+                .map_err(|path| SavePathsError::NonUtf8Path(PathBuf::from(path)))?;
+
+            fs::create_dir_all(save_dir)?;
+
+            base_path
+        }
+    };
+
+    Ok(SavePaths {
+        save_file_path: base_path.clone() + SAVE_FILE_EXTENSION,
+        raw_save_file_path: base_path.clone() + RAW_SAVE_FILE_EXTENSION,
+        auto_state_path: base_path + AUTO_STATE_FILE_EXTENSION,
+    })
+}
+
+/// The path of the state to resume from on launch when running a save file directly.
+pub fn auto_state_path_for_save_file(save_file_path: &str) -> String {
+    let base_path = save_file_path
+        .strip_suffix(SAVE_FILE_EXTENSION)
+        .unwrap_or(save_file_path);
+
+    base_path.to_string() + AUTO_STATE_FILE_EXTENSION
+}
+
+/// Remove the first of the given extensions that the path ends with, ignoring case. Only a single
+/// extension is removed, so game.gb.gb becomes game.gb.
+fn strip_extension<'a>(path: &'a str, extensions: &[&str]) -> &'a str {
+    for extension in extensions {
+        let Some(split) = path.len().checked_sub(extension.len()) else {
+            continue;
+        };
+
+        if path.is_char_boundary(split) && path[split..].eq_ignore_ascii_case(extension) {
+            return &path[..split];
+        }
+    }
+
+    path
+}
+
+/// File name without extension for a ROM's save files within the save directory.
+fn save_dir_file_name(rom_base_path: &str) -> Result<String, SavePathsError> {
+    let rom_base_path = Path::new(rom_base_path);
+    let file_name = rom_base_path
+        .file_name()
+        .unwrap_or(rom_base_path.as_os_str())
+        .to_string_lossy();
+
+    let rom_dir = match rom_base_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let rom_dir = path::absolute(rom_dir)?;
+
+    Ok(format!(
+        "{}-{:08x}",
+        file_name,
+        save_compat::checksum(rom_dir.as_os_str().as_encoded_bytes())
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, path::Path, process};
+
+    use super::{
+        SavePaths, auto_state_path_for_save_file, has_rom_extension, has_zip_extension,
+        save_paths_for_rom,
+    };
+
+    #[test]
+    fn extensions_ignore_case() {
+        assert!(has_rom_extension("game.gb"));
+        assert!(has_rom_extension("GAME.GBC"));
+        assert!(!has_rom_extension("game.zip"));
+
+        assert!(has_zip_extension("game.zip"));
+        assert!(has_zip_extension("GAME.ZIP"));
+        assert!(!has_zip_extension("game.gb"));
+    }
+
+    #[test]
+    fn saves_next_to_rom_by_default() {
+        assert_eq!(
+            save_paths_for_rom("roms/game.gbc", None).unwrap(),
+            SavePaths {
+                save_file_path: "roms/game.svgb".to_string(),
+                raw_save_file_path: "roms/game.sav".to_string(),
+                auto_state_path: "roms/game.autostate".to_string(),
+            }
+        );
+        assert_eq!(
+            auto_state_path_for_save_file("roms/game.svgb"),
+            "roms/game.autostate"
+        );
+
+        // Unknown extensions are kept
+        assert_eq!(
+            save_paths_for_rom("roms/game.bin", None)
+                .unwrap()
+                .save_file_path,
+            "roms/game.bin.svgb"
+        );
+
+        // Extensions are replaced regardless of case, and only once
+        assert_eq!(
+            save_paths_for_rom("roms/Game.GB", None)
+                .unwrap()
+                .save_file_path,
+            "roms/Game.svgb"
+        );
+        assert_eq!(
+            save_paths_for_rom("roms/game.gb.gb", None)
+                .unwrap()
+                .save_file_path,
+            "roms/game.gb.svgb"
+        );
+
+        // Zipped ROMs are named after the archive
+        assert_eq!(
+            save_paths_for_rom("roms/game.zip", None)
+                .unwrap()
+                .save_file_path,
+            "roms/game.svgb"
+        );
+    }
+
+    #[test]
+    fn saves_to_save_dir() {
+        let save_dir = env::temp_dir().join(format!("gbcemu-save-dir-{}", process::id()));
+        let paths = save_paths_for_rom("/roms/game.gb", Some(&save_dir)).unwrap();
+
+        // Save directory is created
+        assert!(save_dir.is_dir());
+
+        let save_file_path = Path::new(&paths.save_file_path);
+        assert_eq!(save_file_path.parent(), Some(save_dir.as_path()));
+
+        let file_name = save_file_path.file_name().unwrap().to_str().unwrap();
+        assert!(file_name.starts_with("game-"));
+        assert!(file_name.ends_with(".svgb"));
+        assert_eq!(
+            paths.raw_save_file_path,
+            paths.save_file_path.replace(".svgb", ".sav")
+        );
+
+        // The same ROM always gets the same save file
+        assert_eq!(
+            save_paths_for_rom("/roms/game.gb", Some(&save_dir)).unwrap(),
+            paths
+        );
+
+        // ROMs with the same name in different directories do not collide
+        let other_paths = save_paths_for_rom("/other/game.gb", Some(&save_dir)).unwrap();
+        assert_ne!(other_paths.save_file_path, paths.save_file_path);
+
+        fs::remove_dir_all(save_dir).unwrap();
+    }
+
+    #[test]
+    fn save_dir_keeps_non_ascii_rom_names() {
+        let save_dir = env::temp_dir().join(format!("gbcemu-save-dir-names-{}", process::id()));
+        let paths = save_paths_for_rom("/roms/ポケモン.gb", Some(&save_dir)).unwrap();
+
+        let file_name = Path::new(&paths.save_file_path).file_name().unwrap();
+        assert!(file_name.to_str().unwrap().starts_with("ポケモン-"));
+
+        fs::remove_dir_all(save_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_save_dir_is_rejected() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let save_dir = env::temp_dir().join(OsStr::from_bytes(b"gbcemu-\xFF"));
+        assert!(save_paths_for_rom("game.gb", Some(&save_dir)).is_err());
+        assert!(!save_dir.exists());
+    }
+}
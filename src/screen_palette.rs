@@ -0,0 +1,184 @@
+//! Colors that the four DMG shades are displayed as.
+
+use std::{fmt, str::FromStr};
+
+use eframe::egui::Color32;
+
+use crate::ppu::{Color, DmgColor};
+
+/// The default grayscale color palette.
+pub const SCREEN_COLOR_PALETTE_GRAYSCALE: [Color32; 4] = [
+    Color32::from_rgb(0xFF, 0xFF, 0xFF),
+    Color32::from_rgb(0xAA, 0xAA, 0xAA),
+    Color32::from_rgb(0x55, 0x55, 0x55),
+    Color32::from_rgb(0x00, 0x00, 0x00),
+];
+
+/// A green color palette for the original GameBoy screen.
+pub const SCREEN_COLOR_PALETTE_GREEN: [Color32; 4] = [
+    Color32::from_rgb(0x9B, 0xBC, 0x0F),
+    Color32::from_rgb(0x8B, 0xAC, 0x0F),
+    Color32::from_rgb(0x30, 0x62, 0x30),
+    Color32::from_rgb(0x0F, 0x38, 0x0F),
+];
+
+/// The color palettes available for DMG (non-CGB) games.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScreenColorPalette {
+    #[default]
+    Grayscale,
+    Green,
+    /// Colors for each shade from lightest to darkest
+    Custom([Color32; 4]),
+}
+
+impl ScreenColorPalette {
+    pub fn colors(&self) -> [Color32; 4] {
+        match self {
+            ScreenColorPalette::Grayscale => SCREEN_COLOR_PALETTE_GRAYSCALE,
+            ScreenColorPalette::Green => SCREEN_COLOR_PALETTE_GREEN,
+            ScreenColorPalette::Custom(colors) => *colors,
+        }
+    }
+
+    pub fn shade_to_color32(&self, shade: DmgColor) -> Color32 {
+        self.colors()[shade as usize]
+    }
+
+    /// DMG colors are mapped through the palette, CGB colors are shown as is.
+    pub fn color_to_color32(&self, color: Color) -> Color32 {
+        match color {
+            Color::Dmg(shade) => self.shade_to_color32(shade),
+            Color::Cgb(color) => color.to_color32(),
+        }
+    }
+}
+
+fn parse_hex_color(s: &str) -> Result<Color32, String> {
+    let invalid_color = || format!("invalid color {}, expected #RRGGBB", s);
+
+    let hex = s.strip_prefix('#').ok_or_else(invalid_color)?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid_color());
+    }
+
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| invalid_color())?;
+    let [_, r, g, b] = rgb.to_be_bytes();
+
+    Ok(Color32::from_rgb(r, g, b))
+}
+
+impl FromStr for ScreenColorPalette {
+    type Err = String;
+
+    /// Parse `grayscale`, `green`, or `custom:#RRGGBB,#RRGGBB,#RRGGBB,#RRGGBB` with colors from
+    /// lightest to darkest.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grayscale" => Ok(ScreenColorPalette::Grayscale),
+            "green" => Ok(ScreenColorPalette::Green),
+            _ => match s.strip_prefix("custom:") {
+                Some(colors) => {
+                    let colors = colors
+                        .split(',')
+                        .map(parse_hex_color)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let num_colors = colors.len();
+
+                    colors
+                        .try_into()
+                        .map(ScreenColorPalette::Custom)
+                        .map_err(|_| format!("expected 4 colors but found {}", num_colors))
+                }
+                None => Err(format!(
+                    "expected grayscale, green, or custom:#RRGGBB,#RRGGBB,#RRGGBB,#RRGGBB but found {}",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ScreenColorPalette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScreenColorPalette::Grayscale => write!(f, "grayscale"),
+            ScreenColorPalette::Green => write!(f, "green"),
+            ScreenColorPalette::Custom(colors) => {
+                let colors = colors
+                    .iter()
+                    .map(|color| format!("#{:02X}{:02X}{:02X}", color.r(), color.g(), color.b()))
+                    .collect::<Vec<_>>();
+                write!(f, "custom:{}", colors.join(","))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use eframe::egui::Color32;
+
+    use crate::ppu::{CgbColor, Color};
+
+    use super::ScreenColorPalette;
+
+    #[test]
+    fn built_in_palettes_map_all_shades() {
+        let grayscale = ScreenColorPalette::Grayscale;
+        let expected = [0xFF, 0xAA, 0x55, 0x00].map(Color32::from_gray);
+        for (shade, color) in expected.into_iter().enumerate() {
+            assert_eq!(grayscale.color_to_color32(Color::Dmg(shade as u8)), color);
+        }
+
+        let green = ScreenColorPalette::Green;
+        let expected = [
+            Color32::from_rgb(0x9B, 0xBC, 0x0F),
+            Color32::from_rgb(0x8B, 0xAC, 0x0F),
+            Color32::from_rgb(0x30, 0x62, 0x30),
+            Color32::from_rgb(0x0F, 0x38, 0x0F),
+        ];
+        for (shade, color) in expected.into_iter().enumerate() {
+            assert_eq!(green.color_to_color32(Color::Dmg(shade as u8)), color);
+        }
+
+        // CGB colors are not affected by the palette
+        let cgb_color = Color::Cgb(CgbColor::new(0x001F));
+        assert_eq!(
+            green.color_to_color32(cgb_color),
+            Color32::from_rgb(0xFF, 0, 0)
+        );
+    }
+
+    #[test]
+    fn parse_palettes() {
+        assert_eq!(
+            ScreenColorPalette::from_str("green"),
+            Ok(ScreenColorPalette::Green)
+        );
+
+        let custom = "custom:#E0F8D0,#88C070,#346856,#081820";
+        let palette = ScreenColorPalette::from_str(custom).unwrap();
+        assert_eq!(
+            palette.shade_to_color32(1),
+            Color32::from_rgb(0x88, 0xC0, 0x70)
+        );
+        assert_eq!(palette.to_string(), custom);
+
+        assert_eq!(
+            ScreenColorPalette::from_str("custom:#FFFFFF,#000000"),
+            Err("expected 4 colors but found 2".to_string())
+        );
+        assert_eq!(
+            ScreenColorPalette::from_str("custom:#FFFFFF,#000000,#12345G,#000000"),
+            Err("invalid color #12345G, expected #RRGGBB".to_string())
+        );
+        assert_eq!(
+            ScreenColorPalette::from_str("custom:FFFFFF,#000000,#000000,#000000"),
+            Err("invalid color FFFFFF, expected #RRGGBB".to_string())
+        );
+        assert!(ScreenColorPalette::from_str("sepia").is_err());
+    }
+}
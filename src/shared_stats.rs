@@ -0,0 +1,80 @@
+//! Performance statistics written by the emulator and audio threads and read by the GUI's
+//! performance overlay. Each statistic is a separate atomic, so readers may see values from
+//! slightly different frames.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+pub struct SharedStats {
+    /// Total number of frames the emulator has run
+    frames_run: AtomicU64,
+
+    /// Percentage of the frame budget used by the last frame, as the bits of an f32
+    frame_budget_used_percent: AtomicU32,
+
+    /// How full the audio buffer is compared to its target, as the bits of an f32. NaN until the
+    /// audio thread first reports it.
+    audio_buffer_fill_percent: AtomicU32,
+}
+
+impl SharedStats {
+    pub fn new() -> Self {
+        Self {
+            frames_run: AtomicU64::new(0),
+            frame_budget_used_percent: AtomicU32::new(0.0f32.to_bits()),
+            audio_buffer_fill_percent: AtomicU32::new(f32::NAN.to_bits()),
+        }
+    }
+
+    /// Record that a frame was run, along with the percentage of the frame budget it used.
+    pub fn frame_complete(&self, frame_budget_used_percent: f32) {
+        self.frames_run.fetch_add(1, Ordering::Relaxed);
+        self.frame_budget_used_percent
+            .store(frame_budget_used_percent.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn frames_run(&self) -> u64 {
+        self.frames_run.load(Ordering::Relaxed)
+    }
+
+    pub fn frame_budget_used_percent(&self) -> f32 {
+        f32::from_bits(self.frame_budget_used_percent.load(Ordering::Relaxed))
+    }
+
+    pub fn set_audio_buffer_fill_percent(&self, fill_percent: f32) {
+        self.audio_buffer_fill_percent
+            .store(fill_percent.to_bits(), Ordering::Relaxed);
+    }
+
+    /// How full the audio buffer is compared to its target, or None if there is no audio output.
+    pub fn audio_buffer_fill_percent(&self) -> Option<f32> {
+        let fill_percent = f32::from_bits(self.audio_buffer_fill_percent.load(Ordering::Relaxed));
+        (!fill_percent.is_nan()).then_some(fill_percent)
+    }
+}
+
+impl Default for SharedStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SharedStats;
+
+    #[test]
+    fn stats_round_trip() {
+        let stats = SharedStats::new();
+        assert_eq!(stats.frames_run(), 0);
+        assert_eq!(stats.frame_budget_used_percent(), 0.0);
+        assert_eq!(stats.audio_buffer_fill_percent(), None);
+
+        stats.frame_complete(25.0);
+        stats.frame_complete(42.5);
+        assert_eq!(stats.frames_run(), 2);
+        assert_eq!(stats.frame_budget_used_percent(), 42.5);
+
+        stats.set_audio_buffer_fill_percent(87.5);
+        assert_eq!(stats.audio_buffer_fill_percent(), Some(87.5));
+    }
+}
@@ -0,0 +1,375 @@
+//! Headless smoke tests over a directory of ROMs.
+//!
+//! Each ROM is booted without a GUI for a fixed amount of emulated time, and the run is recorded as
+//! clean, locked up, or panicked along with a hash and thumbnail of the final screen. Results are
+//! written as a markdown report so that regressions across a ROM collection can be spotted at a
+//! glance, or by diffing the screen hashes between two reports.
+//!
+//! ROMs are run in parallel by a pool of worker threads, one per CPU. Every run uses the same fixed
+//! seed so that reports are reproducible.
+
+use std::{
+    any::Any,
+    fmt::{self, Write},
+    fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::channel,
+    },
+    thread,
+};
+
+use image::{RgbImage, imageops};
+
+use crate::{
+    cartridge::Cartridge,
+    emulator::{EmulatorBuilder, REFRESH_RATE, SCREEN_HEIGHT, SCREEN_WIDTH},
+    error::Error,
+    machine::Machine,
+    ppu_dump::{render_framebuffer, save_png},
+    rom_file::read_rom_file,
+    test_runner::screen_hash,
+};
+
+/// Seed used for every run, so that the same ROM always produces the same screen.
+const SMOKE_TEST_SEED: u64 = 0;
+
+/// Stack size of each worker thread. The emulator is large and debug builds place several copies
+/// of it on the stack while building.
+const WORKER_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Thumbnails are the screen scaled down by this factor.
+const THUMBNAIL_SCALE: u32 = 2;
+
+const REPORT_FILE_NAME: &str = "report.md";
+const THUMBNAILS_DIR_NAME: &str = "thumbnails";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SmokeTestOutcome {
+    /// Ran for the whole time without panicking or locking up
+    Clean,
+    /// The CPU stopped executing instructions for good, after the given number of frames
+    LockedUp { num_frames: usize },
+    /// The emulator panicked with the given message
+    Panicked(String),
+    /// The ROM could not be read or loaded
+    LoadFailed(String),
+}
+
+impl fmt::Display for SmokeTestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmokeTestOutcome::Clean => write!(f, "clean"),
+            SmokeTestOutcome::LockedUp { num_frames } => {
+                write!(f, "locked up after {} frames", num_frames)
+            }
+            SmokeTestOutcome::Panicked(message) => write!(f, "panicked: {}", message),
+            SmokeTestOutcome::LoadFailed(error) => write!(f, "failed to load: {}", error),
+        }
+    }
+}
+
+/// Result of running a single ROM.
+pub struct SmokeTestResult {
+    pub rom_path: PathBuf,
+    pub outcome: SmokeTestOutcome,
+    /// Hash of the final screen as computed by `screen_hash`, if the run did not panic
+    pub screen_hash: Option<u64>,
+    /// The final screen scaled down, if the run did not panic
+    pub thumbnail: Option<RgbImage>,
+}
+
+/// Number of frames run for the given number of emulated seconds.
+pub fn frames_for_seconds(seconds: f64) -> usize {
+    (seconds * REFRESH_RATE).round() as usize
+}
+
+/// Boot a ROM headless and run it for `num_frames` frames, stopping early if the CPU locks up.
+/// Runs on a CGB if the ROM supports CGB features, otherwise on a DMG. Panics are caught and
+/// reported as the outcome.
+pub fn run_smoke_test(rom_path: &Path, num_frames: usize) -> SmokeTestResult {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<_, Error> {
+        let rom = read_rom_file(&rom_path.to_string_lossy())?;
+        let cartridge = Cartridge::new_from_rom_bytes(rom)?;
+        let machine = if cartridge.is_cgb() {
+            Machine::Cgb
+        } else {
+            Machine::Dmg
+        };
+
+        let mut emulator = EmulatorBuilder::new_cartridge(cartridge, machine)
+            .with_seed(Some(SMOKE_TEST_SEED))
+            .build()?;
+        emulator.power_on();
+
+        let mut outcome = SmokeTestOutcome::Clean;
+        for frame in 1..=num_frames {
+            emulator.run_frames(1);
+
+            if emulator.is_locked_up() {
+                outcome = SmokeTestOutcome::LockedUp { num_frames: frame };
+                break;
+            }
+        }
+
+        let screen = render_framebuffer(&emulator);
+        let thumbnail = imageops::thumbnail(
+            &screen,
+            SCREEN_WIDTH as u32 / THUMBNAIL_SCALE,
+            SCREEN_HEIGHT as u32 / THUMBNAIL_SCALE,
+        );
+
+        Ok((outcome, screen_hash(&emulator), thumbnail))
+    }));
+
+    let (outcome, screen_hash, thumbnail) = match result {
+        Ok(Ok((outcome, screen_hash, thumbnail))) => (outcome, Some(screen_hash), Some(thumbnail)),
+        Ok(Err(error)) => (SmokeTestOutcome::LoadFailed(error.to_string()), None, None),
+        Err(payload) => (
+            SmokeTestOutcome::Panicked(panic_message(payload.as_ref())),
+            None,
+            None,
+        ),
+    };
+
+    SmokeTestResult {
+        rom_path: rom_path.to_path_buf(),
+        outcome,
+        screen_hash,
+        thumbnail,
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Number of worker threads to use, one per CPU.
+pub fn default_num_workers() -> usize {
+    thread::available_parallelism().map_or(1, |num_cpus| num_cpus.get())
+}
+
+/// Run every ROM for `num_frames` frames on a pool of `num_workers` threads. Results are in the same
+/// order as `rom_paths`. `on_result` is called on the calling thread as each run finishes, in the
+/// order the runs finish.
+pub fn run_smoke_tests(
+    rom_paths: &[PathBuf],
+    num_frames: usize,
+    num_workers: usize,
+    mut on_result: impl FnMut(&SmokeTestResult),
+) -> Vec<SmokeTestResult> {
+    let next_index = AtomicUsize::new(0);
+    let (results_tx, results_rx) = channel();
+
+    let mut results = thread::scope(|scope| {
+        for _ in 0..num_workers.clamp(1, rom_paths.len().max(1)) {
+            let results_tx = results_tx.clone();
+            let next_index = &next_index;
+
+            thread::Builder::new()
+                .name("smoke-test".to_string())
+                .stack_size(WORKER_STACK_SIZE)
+                .spawn_scoped(scope, move || {
+                    loop {
+                        let index = next_index.fetch_add(1, Ordering::Relaxed);
+                        let Some(rom_path) = rom_paths.get(index) else {
+                            break;
+                        };
+
+                        let result = run_smoke_test(rom_path, num_frames);
+                        if results_tx.send((index, result)).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .unwrap();
+        }
+
+        // Only the workers hold senders now, so the loop ends once every worker has finished
+        drop(results_tx);
+
+        let mut results = vec![];
+        for (index, result) in results_rx {
+            on_result(&result);
+            results.push((index, result));
+        }
+
+        results
+    });
+
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Render the results as a markdown report, with ROM paths relative to `rom_dir`.
+fn report_markdown(results: &[SmokeTestResult], num_frames: usize, rom_dir: &Path) -> String {
+    let count = |is_outcome: fn(&SmokeTestOutcome) -> bool| {
+        results
+            .iter()
+            .filter(|result| is_outcome(&result.outcome))
+            .count()
+    };
+
+    let mut report = String::new();
+    let _ = writeln!(report, "# Smoke test report");
+    let _ = writeln!(report);
+    let _ = writeln!(
+        report,
+        "{} ROMs in `{}` run for {} frames each: {} clean, {} locked up, {} panicked, {} failed to \
+         load.",
+        results.len(),
+        rom_dir.display(),
+        num_frames,
+        count(|outcome| matches!(outcome, SmokeTestOutcome::Clean)),
+        count(|outcome| matches!(outcome, SmokeTestOutcome::LockedUp { .. })),
+        count(|outcome| matches!(outcome, SmokeTestOutcome::Panicked(_))),
+        count(|outcome| matches!(outcome, SmokeTestOutcome::LoadFailed(_))),
+    );
+    let _ = writeln!(report);
+    let _ = writeln!(report, "| ROM | Result | Screen hash | Screen |");
+    let _ = writeln!(report, "| --- | --- | --- | --- |");
+
+    for (index, result) in results.iter().enumerate() {
+        let rom_path = result
+            .rom_path
+            .strip_prefix(rom_dir)
+            .unwrap_or(&result.rom_path);
+        let screen_hash = match result.screen_hash {
+            Some(screen_hash) => format!("`{:016X}`", screen_hash),
+            None => "-".to_string(),
+        };
+        let thumbnail = match result.thumbnail {
+            Some(_) => format!("![]({})", thumbnail_path(index)),
+            None => "-".to_string(),
+        };
+
+        // Keep panic messages from breaking out of their table cell
+        let outcome = result
+            .outcome
+            .to_string()
+            .replace('|', "\\|")
+            .replace('\n', " ");
+
+        let _ = writeln!(
+            report,
+            "| `{}` | {} | {} | {} |",
+            rom_path.display(),
+            outcome,
+            screen_hash,
+            thumbnail
+        );
+    }
+
+    report
+}
+
+/// Path of a result's thumbnail, relative to the report.
+fn thumbnail_path(index: usize) -> String {
+    format!("{}/{:04}.png", THUMBNAILS_DIR_NAME, index)
+}
+
+/// Write the report and thumbnails to `out_dir`, returning the path of the report.
+pub fn write_report(
+    results: &[SmokeTestResult],
+    num_frames: usize,
+    rom_dir: &Path,
+    out_dir: &Path,
+) -> io::Result<PathBuf> {
+    let thumbnails_dir = out_dir.join(THUMBNAILS_DIR_NAME);
+    if thumbnails_dir.exists() {
+        fs::remove_dir_all(&thumbnails_dir)?;
+    }
+    fs::create_dir_all(&thumbnails_dir)?;
+
+    for (index, result) in results.iter().enumerate() {
+        if let Some(thumbnail) = &result.thumbnail {
+            save_png(thumbnail, &out_dir.join(thumbnail_path(index)))?;
+        }
+    }
+
+    let report_path = out_dir.join(REPORT_FILE_NAME);
+    fs::write(&report_path, report_markdown(results, num_frames, rom_dir))?;
+
+    Ok(report_path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, path::PathBuf, process};
+
+    use crate::{test_runner::find_test_roms, test_utils::build_test_rom};
+
+    use super::{SmokeTestOutcome, run_smoke_tests, write_report};
+
+    fn test_dir() -> PathBuf {
+        let dir = env::temp_dir().join(format!("gbcemu-smoke-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("roms")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn smoke_tests_classify_runs_and_write_report() {
+        let dir = test_dir();
+        let rom_dir = dir.join("roms");
+
+        // Loops forever
+        let clean_rom = build_test_rom(0x00, 0x00, 0x00, &[0x18, 0xFE]);
+        fs::write(rom_dir.join("clean.gb"), clean_rom).unwrap();
+
+        // Executes an invalid opcode
+        let invalid_opcode_rom = build_test_rom(0x00, 0x00, 0x00, &[0x00, 0xD3]);
+        fs::write(rom_dir.join("invalid_opcode.gb"), invalid_opcode_rom).unwrap();
+
+        // Halts with interrupts disabled
+        let halt_rom = build_test_rom(0x00, 0x00, 0x00, &[0xAF, 0xE0, 0xFF, 0x76]);
+        fs::write(rom_dir.join("halt.gb"), halt_rom).unwrap();
+
+        fs::write(rom_dir.join("truncated.gb"), [0x00; 16]).unwrap();
+
+        let rom_paths = find_test_roms(&rom_dir);
+        let mut num_finished = 0;
+        let results = run_smoke_tests(&rom_paths, 10, 2, |_| num_finished += 1);
+        assert_eq!(num_finished, 4);
+
+        let outcomes = results
+            .iter()
+            .map(|result| {
+                let name = result.rom_path.file_name().unwrap().to_str().unwrap();
+                (name, result.outcome.clone())
+            })
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            outcomes.as_slice(),
+            [
+                ("clean.gb", SmokeTestOutcome::Clean),
+                ("halt.gb", SmokeTestOutcome::LockedUp { .. }),
+                ("invalid_opcode.gb", SmokeTestOutcome::LockedUp { .. }),
+                ("truncated.gb", SmokeTestOutcome::LoadFailed(_)),
+            ]
+        ));
+        assert!(results[0].screen_hash.is_some());
+        assert!(results[3].thumbnail.is_none());
+
+        let out_dir = dir.join("report");
+        let report_path = write_report(&results, 10, &rom_dir, &out_dir).unwrap();
+        let report = fs::read_to_string(report_path).unwrap();
+
+        assert!(report.contains("4 ROMs"));
+        assert!(report.contains("1 clean, 2 locked up, 0 panicked, 1 failed to load"));
+        assert!(report.contains("| `clean.gb` | clean |"));
+        assert!(report.contains("![](thumbnails/0000.png)"));
+        assert!(out_dir.join("thumbnails/0002.png").exists());
+        assert!(!out_dir.join("thumbnails/0003.png").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
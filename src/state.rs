@@ -0,0 +1,125 @@
+//! Structured views of emulator state for external tooling (debuggers, tracers, test harnesses).
+//!
+//! These types are plain copies of the relevant state so that tooling never needs access to the
+//! emulator's internal fields.
+
+use crate::emulator::Mode;
+
+/// All CPU registers, flags, and interrupt state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuState {
+    pub a: u8,
+    /// Flags register. Only the upper 4 bits are stored, the lower 4 bits always read as zero.
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    /// Interrupt master enable
+    pub ime: bool,
+    /// Whether the CPU is halted waiting for an interrupt
+    pub is_halted: bool,
+}
+
+impl CpuState {
+    pub fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f])
+    }
+
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+}
+
+/// Current position of the PPU within the frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PpuState {
+    pub mode: Mode,
+    /// Current scanline (LY), including the scanlines in VBlank
+    pub scanline: u8,
+    /// Internal line counter used when rendering the window
+    pub window_line_counter: u8,
+    /// Tick within the current frame
+    pub tick: u32,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        machine::Machine,
+        test_utils::{FILL_VRAM_PROGRAM, new_test_emulator, with_large_stack},
+    };
+
+    use super::CpuState;
+
+    #[test]
+    fn cpu_state_round_trip() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Cgb, &FILL_VRAM_PROGRAM);
+
+            let state = CpuState {
+                a: 0x12,
+                f: 0xA0,
+                b: 0x34,
+                c: 0x56,
+                d: 0x78,
+                e: 0x9A,
+                h: 0xBC,
+                l: 0xDE,
+                sp: 0xCFF0,
+                pc: 0x1234,
+                ime: true,
+                is_halted: true,
+            };
+
+            emulator.set_cpu_state(state);
+            assert_eq!(emulator.cpu_state(), state);
+            assert_eq!(emulator.cpu_state().hl(), 0xBCDE);
+        });
+    }
+
+    #[test]
+    fn memory_bulk_round_trip() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Cgb, &FILL_VRAM_PROGRAM);
+
+            // Work RAM written in bulk reads back both directly and through its echo RAM mirror
+            let bytes: Vec<u8> = (0..0x80).collect();
+            emulator.write_memory_bulk(0xC000, &bytes);
+            assert_eq!(emulator.read_memory_bulk(0xC000, 0x80), bytes);
+            assert_eq!(emulator.read_memory_bulk(0xE000, 0x80), bytes);
+
+            // Raw IO register writes bypass handlers, e.g. DIV is not reset
+            emulator.write_memory_bulk(0xFF04, &[0x42]);
+            assert_eq!(emulator.read_memory_bulk(0xFF04, 1), vec![0x42]);
+
+            let hram: Vec<u8> = (0..0x7F).rev().collect();
+            emulator.write_memory_bulk(0xFF80, &hram);
+            assert_eq!(emulator.read_memory_bulk(0xFF80, 0x7F), hram);
+        });
+    }
+
+    #[test]
+    fn ppu_state_tracks_frame() {
+        with_large_stack(|| {
+            let mut emulator = new_test_emulator(Machine::Cgb, &FILL_VRAM_PROGRAM);
+
+            emulator.run_frame();
+            let state = emulator.ppu_state();
+            assert_eq!(state.tick, 0);
+            assert_eq!(state.scanline, 153);
+        });
+    }
+}
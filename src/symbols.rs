@@ -0,0 +1,224 @@
+//! Symbol files mapping banked addresses to labels, in the format emitted by RGBDS (`rgblink -n`).
+//!
+//! Each line contains a bank and address in hex followed by a label, e.g. `01:4A30 PlayerUpdate`.
+//! Comments start with `;`.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::address_space::Address;
+
+/// Extension of symbol files, which are found next to the ROM
+pub const SYMBOL_FILE_EXTENSION: &str = "sym";
+
+/// An address along with the bank that must be mapped at that address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BankedAddress {
+    pub bank: u16,
+    pub address: Address,
+}
+
+impl BankedAddress {
+    pub fn new(bank: u16, address: Address) -> Self {
+        BankedAddress { bank, address }
+    }
+
+    /// Parse `<bank>:<address>` where both are in hex, e.g. `01:4A30`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (bank, address) = s.split_once(':')?;
+        let bank = u16::from_str_radix(bank, 16).ok()?;
+        let address = u16::from_str_radix(address, 16).ok()?;
+
+        Some(BankedAddress { bank, address })
+    }
+}
+
+impl fmt::Display for BankedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02X}:{:04X}", self.bank, self.address)
+    }
+}
+
+/// A line of a symbol file that could not be parsed.
+#[derive(Debug, PartialEq)]
+pub struct SymbolParseError {
+    /// 1-indexed line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SymbolParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Default)]
+pub struct SymbolTable {
+    labels: HashMap<BankedAddress, String>,
+    addresses: HashMap<String, BankedAddress>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the contents of a symbol file. Malformed lines are skipped and returned alongside the
+    /// symbols that could be parsed.
+    pub fn parse(contents: &str) -> (Self, Vec<SymbolParseError>) {
+        let mut table = SymbolTable::new();
+        let mut errors = vec![];
+
+        for (i, line) in contents.lines().enumerate() {
+            // Strip comments
+            let line = match line.split_once(';') {
+                Some((before_comment, _)) => before_comment,
+                None => line,
+            };
+
+            let mut parts = line.split_whitespace();
+            let Some(location) = parts.next() else {
+                continue;
+            };
+
+            let error = |message: String| SymbolParseError {
+                line: i + 1,
+                message,
+            };
+
+            let Some(banked_address) = BankedAddress::parse(location) else {
+                errors.push(error(format!("invalid bank and address `{}`", location)));
+                continue;
+            };
+
+            let Some(label) = parts.next() else {
+                errors.push(error(format!("missing label for {}", banked_address)));
+                continue;
+            };
+
+            if parts.next().is_some() {
+                errors.push(error(format!("unexpected text after label `{}`", label)));
+                continue;
+            }
+
+            table.insert(banked_address, label.to_string());
+        }
+
+        (table, errors)
+    }
+
+    /// Load the symbol file at the given path.
+    pub fn load(path: &Path) -> io::Result<(Self, Vec<SymbolParseError>)> {
+        let contents = fs::read_to_string(path)?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Path of the symbol file for a ROM, which has the same name as the ROM.
+    pub fn path_for_rom(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension(SYMBOL_FILE_EXTENSION)
+    }
+
+    pub fn insert(&mut self, banked_address: BankedAddress, label: String) {
+        // The first label at an address is used for display
+        self.labels
+            .entry(banked_address)
+            .or_insert_with(|| label.clone());
+        self.addresses.insert(label, banked_address);
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn label(&self, banked_address: BankedAddress) -> Option<&str> {
+        self.labels.get(&banked_address).map(String::as_str)
+    }
+
+    pub fn lookup(&self, label: &str) -> Option<BankedAddress> {
+        self.addresses.get(label).copied()
+    }
+
+    /// Format a banked address along with its label, if any.
+    pub fn format(&self, banked_address: BankedAddress) -> String {
+        match self.label(banked_address) {
+            Some(label) => format!("{} <{}>", banked_address, label),
+            None => banked_address.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BankedAddress, SymbolTable};
+
+    #[test]
+    fn parse_symbol_file() {
+        let contents = "\
+; File generated by rgblink
+00:0150 Start
+01:4A30 PlayerUpdate
+01:4A30 PlayerUpdate_Alias
+05:4A30 EnemyUpdate ; trailing comment
+
+C0:C000 wBuffer
+";
+
+        let (table, errors) = SymbolTable::parse(contents);
+        assert!(errors.is_empty());
+        assert_eq!(table.len(), 5);
+
+        assert_eq!(table.lookup("Start"), Some(BankedAddress::new(0, 0x0150)));
+        assert_eq!(
+            table.lookup("PlayerUpdate_Alias"),
+            Some(BankedAddress::new(1, 0x4A30))
+        );
+        assert_eq!(
+            table.lookup("wBuffer"),
+            Some(BankedAddress::new(0xC0, 0xC000))
+        );
+
+        // Same address in different banks has different labels
+        assert_eq!(
+            table.label(BankedAddress::new(1, 0x4A30)),
+            Some("PlayerUpdate")
+        );
+        assert_eq!(
+            table.label(BankedAddress::new(5, 0x4A30)),
+            Some("EnemyUpdate")
+        );
+        assert_eq!(table.label(BankedAddress::new(2, 0x4A30)), None);
+
+        assert_eq!(
+            table.format(BankedAddress::new(5, 0x4A30)),
+            "05:4A30 <EnemyUpdate>"
+        );
+    }
+
+    #[test]
+    fn parse_malformed_lines() {
+        let contents = "\
+00:0150 Start
+0150 NoBank
+01:XYZW BadAddress
+02:4000
+03:4000 Two Labels
+10000:4000 BankTooLarge
+04:4000 Valid
+";
+
+        let (table, errors) = SymbolTable::parse(contents);
+        assert_eq!(table.len(), 2);
+        assert!(table.lookup("Valid").is_some());
+
+        let error_lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(error_lines, vec![2, 3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,277 @@
+//! Headless runner for accuracy test ROMs such as the Blargg and Mooneye suites.
+//!
+//! A test ROM is run as fast as possible until it reports a result or its tick budget runs out.
+//! Results are detected in any of the ways the common suites report them:
+//! - Mooneye: B, C, D, E, H, and L hold the Fibonacci numbers 3, 5, 8, 13, 21, 34 on success, or
+//!   0x42 on failure
+//! - Blargg: "Passed" or "Failed" is printed over the serial port
+//! - Screen hash: the screen matches a known hash, for ROMs that only report on screen
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use eframe::egui::Color32;
+
+use crate::{
+    cartridge::Cartridge,
+    emulator::{
+        Emulator, EmulatorBuilder, SCREEN_HEIGHT, SCREEN_WIDTH, TICKS_PER_FRAME, TestResult,
+    },
+    error::Error,
+    machine::Machine,
+};
+
+/// Default number of ticks a test ROM may run for, two minutes of emulated time. Long enough for
+/// the slowest Blargg suites.
+pub const DEFAULT_TICK_BUDGET: usize = 120 * 60 * TICKS_PER_FRAME;
+
+/// Number of ticks run between checks for a result
+const TICKS_PER_CHECK: usize = TICKS_PER_FRAME;
+
+/// How a test ROM reports that it has finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Completion {
+    /// Either the Mooneye register convention or Blargg serial output
+    Auto,
+    /// Mooneye register convention
+    Registers,
+    /// Blargg serial output
+    Serial,
+    /// The screen matches the given hash, as computed by `screen_hash`
+    ScreenHash(u64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TestRomOutcome {
+    Passed,
+    Failed,
+    /// The tick budget ran out before the ROM reported a result
+    TimedOut,
+}
+
+impl fmt::Display for TestRomOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestRomOutcome::Passed => write!(f, "passed"),
+            TestRomOutcome::Failed => write!(f, "failed"),
+            TestRomOutcome::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+/// Result of running a single test ROM.
+#[derive(Debug)]
+pub struct TestRomReport {
+    pub outcome: TestRomOutcome,
+    /// Number of ticks run before the result was detected
+    pub num_ticks: usize,
+    /// Everything the ROM printed over the serial port
+    pub serial_output: String,
+}
+
+/// Run a test ROM headless until it reports a result or `tick_budget` ticks have run. Fails if the
+/// ROM cannot be loaded.
+pub fn run_test_rom(
+    rom: Vec<u8>,
+    machine: Machine,
+    completion: Completion,
+    tick_budget: usize,
+) -> Result<TestRomReport, Error> {
+    let cartridge = Cartridge::new_from_rom_bytes(rom)?;
+    let mut emulator = EmulatorBuilder::new_cartridge(cartridge, machine)
+        .with_serial_capture()
+        .build()?;
+    emulator.power_on();
+
+    let mut num_ticks = 0;
+    let outcome = loop {
+        if let Some(outcome) = check_completion(&emulator, completion) {
+            break outcome;
+        }
+
+        if num_ticks >= tick_budget {
+            break TestRomOutcome::TimedOut;
+        }
+
+        num_ticks += emulator.run_ticks(TICKS_PER_CHECK.min(tick_budget - num_ticks));
+    };
+
+    Ok(TestRomReport {
+        outcome,
+        num_ticks,
+        serial_output: String::from_utf8_lossy(emulator.serial_output()).into_owned(),
+    })
+}
+
+fn check_completion(emulator: &Emulator, completion: Completion) -> Option<TestRomOutcome> {
+    match completion {
+        Completion::Auto => check_registers(emulator).or_else(|| check_serial_output(emulator)),
+        Completion::Registers => check_registers(emulator),
+        Completion::Serial => check_serial_output(emulator),
+        Completion::ScreenHash(hash) => {
+            (screen_hash(emulator) == hash).then_some(TestRomOutcome::Passed)
+        }
+    }
+}
+
+fn check_registers(emulator: &Emulator) -> Option<TestRomOutcome> {
+    match emulator.test_result()? {
+        TestResult::Passed => Some(TestRomOutcome::Passed),
+        TestResult::Failed => Some(TestRomOutcome::Failed),
+    }
+}
+
+fn check_serial_output(emulator: &Emulator) -> Option<TestRomOutcome> {
+    let output = String::from_utf8_lossy(emulator.serial_output());
+    if output.contains("Passed") {
+        Some(TestRomOutcome::Passed)
+    } else if output.contains("Failed") {
+        Some(TestRomOutcome::Failed)
+    } else {
+        None
+    }
+}
+
+/// FNV-1a hash of the screen's colors with the screen palette applied. Stable across runs and
+/// platforms, so that expected hashes can be checked in.
+pub fn screen_hash(emulator: &Emulator) -> u64 {
+    let mut colors = vec![Color32::TRANSPARENT; SCREEN_WIDTH * SCREEN_HEIGHT];
+    emulator.copy_screen_colors(&mut colors);
+
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for byte in colors.iter().flat_map(|color| color.to_array()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+
+    hash
+}
+
+/// Machine to run a test ROM on, going by its file extension.
+pub fn machine_for_rom_path(path: &Path) -> Machine {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("gbc") => Machine::Cgb,
+        _ => Machine::Dmg,
+    }
+}
+
+/// Every `.gb` and `.gbc` file under `dir`, sorted. Returns nothing if `dir` does not exist.
+pub fn find_test_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = vec![];
+    let Ok(entries) = fs::read_dir(dir) else {
+        return roms;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            roms.extend(find_test_roms(&path));
+        } else if matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("gb" | "gbc")
+        ) {
+            roms.push(path);
+        }
+    }
+
+    roms.sort();
+    roms
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use crate::{
+        machine::Machine,
+        test_utils::{build_test_rom, with_large_stack},
+    };
+
+    use super::{Completion, TestRomOutcome, run_test_rom};
+
+    /// Print "Passed" over the serial port, then report success in the Mooneye registers.
+    #[rustfmt::skip]
+    const PASSING_PROGRAM: [u8; 44] = [
+        0x21, 0x74, 0x01, // ld hl, message
+        // send_byte:
+        0x2A,             // ld a, [hl+]
+        0xA7,             // and a
+        0x28, 0x0E,       // jr z, done
+        0xE0, 0x01,       // ldh [SB], a
+        0x3E, 0x81,       // ld a, 0x81 (start a transfer with the internal clock)
+        0xE0, 0x02,       // ldh [SC], a
+        // wait:
+        0xF0, 0x02,       // ldh a, [SC]
+        0xCB, 0x7F,       // bit 7, a
+        0x20, 0xFA,       // jr nz, wait
+        0x18, 0xEE,       // jr send_byte
+        // done:
+        0x06, 0x03,       // ld b, 3
+        0x0E, 0x05,       // ld c, 5
+        0x16, 0x08,       // ld d, 8
+        0x1E, 0x0D,       // ld e, 13
+        0x26, 0x15,       // ld h, 21
+        0x2E, 0x22,       // ld l, 34
+        0x40,             // ld b, b
+        0x18, 0xFE,       // jr -2
+        // message:
+        b'P', b'a', b's', b's', b'e', b'd', b'\n', 0x00,
+    ];
+
+    /// Report failure in the Mooneye registers.
+    #[rustfmt::skip]
+    const FAILING_PROGRAM: [u8; 11] = [
+        0x3E, 0x42,       // ld a, 0x42
+        0x47,             // ld b, a
+        0x4F,             // ld c, a
+        0x57,             // ld d, a
+        0x5F,             // ld e, a
+        0x67,             // ld h, a
+        0x6F,             // ld l, a
+        0x40,             // ld b, b
+        0x18, 0xFE,       // jr -2
+    ];
+
+    #[test]
+    fn passing_rom_is_detected_by_registers_and_serial() {
+        with_large_stack(|| {
+            let rom = build_test_rom(0x00, 0x00, 0x00, &PASSING_PROGRAM);
+
+            for completion in [Completion::Auto, Completion::Registers, Completion::Serial] {
+                let report =
+                    run_test_rom(rom.clone(), Machine::Dmg, completion, 10_000_000).unwrap();
+                assert_eq!(report.outcome, TestRomOutcome::Passed, "{:?}", completion);
+                assert!(report.serial_output.starts_with("Passed"));
+            }
+        });
+    }
+
+    #[test]
+    fn failing_and_hanging_roms() {
+        with_large_stack(|| {
+            let rom = build_test_rom(0x00, 0x00, 0x00, &FAILING_PROGRAM);
+            let report = run_test_rom(rom, Machine::Dmg, Completion::Auto, 10_000_000).unwrap();
+            assert_eq!(report.outcome, TestRomOutcome::Failed);
+
+            // Loops forever without reporting anything
+            let rom = build_test_rom(0x00, 0x00, 0x00, &[0x18, 0xFE]);
+            let report = run_test_rom(rom, Machine::Dmg, Completion::Auto, 1_000_000).unwrap();
+            assert_eq!(report.outcome, TestRomOutcome::TimedOut);
+            assert!(report.num_ticks >= 1_000_000);
+            assert!(report.serial_output.is_empty());
+        });
+    }
+
+    /// Write the passing ROM used by the test ROM harness integration test. Run manually.
+    #[test]
+    #[ignore]
+    fn generate_harness_fixture() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("harness_pass.gb");
+        fs::write(path, build_test_rom(0x00, 0x00, 0x00, &PASSING_PROGRAM)).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Shared helpers for unit tests.
+
+use std::thread;
+
+use crate::{
+    address_space::{Address, ROM_BANK_SIZE},
+    cartridge::{Cartridge, NINTENDO_LOGO},
+    emulator::{Emulator, EmulatorBuilder},
+    machine::Machine,
+    mbc::types::{Location, Mbc},
+};
+
+/// Address that test programs are placed at, directly after the header.
+pub const PROGRAM_START: usize = 0x0150;
+
+/// A program that fills all of VRAM tile data and the first tile map with the low byte of each
+/// address, then loops forever. Produces a static, non-trivial screen after a few frames.
+#[rustfmt::skip]
+pub const FILL_VRAM_PROGRAM: [u8; 12] = [
+    0x21, 0x00, 0x80, // ld hl, 0x8000
+    0x7D,             // loop: ld a, l
+    0x22,             // ld (hl+), a
+    0x7C,             // ld a, h
+    0xFE, 0x9C,       // cp 0x9C
+    0x20, 0xF9,       // jr nz, loop
+    0x18, 0xFE,       // jr -2
+];
+
+/// Build a valid ROM with the given header bytes whose entry point jumps to `program`.
+pub fn build_test_rom(
+    cartridge_type: u8,
+    rom_size_byte: u8,
+    ram_size_byte: u8,
+    program: &[u8],
+) -> Vec<u8> {
+    let mut rom = vec![0; (2 * ROM_BANK_SIZE) << rom_size_byte];
+
+    // Entry point: nop, jp 0x0150
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x0134..0x013F].copy_from_slice(b"TESTROM\0\0\0\0");
+    rom[0x0147] = cartridge_type;
+    rom[0x0148] = rom_size_byte;
+    rom[0x0149] = ram_size_byte;
+    write_header_checksum(&mut rom);
+
+    rom[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(program);
+
+    rom
+}
+
+/// Build a ROM like `build_test_rom` that runs in CGB mode on a CGB.
+pub fn build_cgb_test_rom(
+    cartridge_type: u8,
+    rom_size_byte: u8,
+    ram_size_byte: u8,
+    program: &[u8],
+) -> Vec<u8> {
+    let mut rom = build_test_rom(cartridge_type, rom_size_byte, ram_size_byte, program);
+    rom[0x0143] = 0x80;
+    write_header_checksum(&mut rom);
+
+    rom
+}
+
+/// Build a ROM like `build_test_rom` with an invalid Nintendo logo, as some homebrew ROMs have.
+pub fn build_bad_logo_test_rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = build_test_rom(0x00, 0x00, 0x00, program);
+    rom[0x0104..0x0134].fill(0x00);
+
+    rom
+}
+
+/// Fix up the header checksum after modifying the header.
+pub fn write_header_checksum(rom: &mut [u8]) {
+    rom[0x014D] = Cartridge::compute_header_checksum(rom);
+}
+
+/// Builder for an emulator running the given ROM.
+pub fn new_test_emulator_builder(machine: Machine, rom: Vec<u8>) -> EmulatorBuilder {
+    let cartridge = Cartridge::new_from_rom_bytes(rom).unwrap();
+    EmulatorBuilder::new_cartridge(cartridge, machine)
+}
+
+/// Emulator running `program` from a ROM built by `build_test_rom`, with the boot sequence already
+/// emulated.
+pub fn new_test_emulator(machine: Machine, program: &[u8]) -> Emulator {
+    let rom = build_test_rom(0x00, 0x00, 0x00, program);
+    let mut emulator = new_test_emulator_builder(machine, rom).build().unwrap();
+    emulator.emulate_boot_sequence();
+
+    emulator
+}
+
+/// Write a value to the MBC register mapped at an address in the ROM area.
+pub fn write_mbc_register(mbc: &mut dyn Mbc, addr: Address, value: u8) {
+    match mbc.map_write_rom_address(addr) {
+        Location::Register(register) => mbc.write_register(register, value),
+        Location::Address(_) => panic!("No MBC register at 0x{:04X}", addr),
+    }
+}
+
+/// Run a test body on a thread with a large stack. The emulator is large and debug builds place
+/// several copies of it on the stack while building and deserializing.
+pub fn with_large_stack(f: impl FnOnce() + Send + 'static) {
+    thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap();
+}
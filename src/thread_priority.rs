@@ -0,0 +1,197 @@
+//! Raising the priority of the current thread and pinning it to a CPU core, so that the emulator and
+//! audio threads are not descheduled at inopportune moments on a busy system.
+//!
+//! Each platform has its own implementation. Platforms without one return an `Unsupported` error.
+
+use std::io;
+
+/// Raise the scheduling priority of the current thread. Usually fails without elevated permissions
+/// on Linux, where lowering a thread's nice value requires `CAP_SYS_NICE`.
+pub fn raise_current_thread_priority() -> io::Result<()> {
+    platform::raise_current_thread_priority()
+}
+
+/// Restrict the current thread to run only on the given CPU core.
+pub fn pin_current_thread_to_core(core: usize) -> io::Result<()> {
+    platform::pin_current_thread_to_core(core)
+}
+
+/// Apply the requested priority and affinity to the current thread, logging what was applied or
+/// why it failed. `thread_name` is only used for logging.
+pub fn configure_current_thread(thread_name: &str, high_priority: bool, pin_core: Option<usize>) {
+    if high_priority {
+        match raise_current_thread_priority() {
+            Ok(()) => println!("Raised {} thread priority", thread_name),
+            Err(error) => eprintln!("Could not raise {} thread priority: {}", thread_name, error),
+        }
+    }
+
+    if let Some(core) = pin_core {
+        match pin_current_thread_to_core(core) {
+            Ok(()) => println!("Pinned {} thread to core {}", thread_name, core),
+            Err(error) => eprintln!(
+                "Could not pin {} thread to core {}: {}",
+                thread_name, core, error
+            ),
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", windows))]
+fn core_out_of_range(core: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("core {} is out of range", core),
+    )
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::{io, mem};
+
+    use super::core_out_of_range;
+
+    /// Nice value for raised threads. Negative values take priority over normal threads.
+    const HIGH_PRIORITY_NICE: libc::c_int = -10;
+
+    pub fn raise_current_thread_priority() -> io::Result<()> {
+        // On Linux the priority of a thread ID only applies to that thread, not the whole process
+        let thread_id = unsafe { libc::gettid() };
+        let result = unsafe {
+            libc::setpriority(
+                libc::PRIO_PROCESS,
+                thread_id as libc::id_t,
+                HIGH_PRIORITY_NICE,
+            )
+        };
+
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub fn pin_current_thread_to_core(core: usize) -> io::Result<()> {
+        if core >= libc::CPU_SETSIZE as usize {
+            return Err(core_out_of_range(core));
+        }
+
+        let result = unsafe {
+            let mut cpu_set: libc::cpu_set_t = mem::zeroed();
+            libc::CPU_SET(core, &mut cpu_set);
+            libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &cpu_set)
+        };
+
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::io;
+
+    pub fn raise_current_thread_priority() -> io::Result<()> {
+        let result = unsafe {
+            libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_USER_INTERACTIVE, 0)
+        };
+
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+
+        Ok(())
+    }
+
+    pub fn pin_current_thread_to_core(_: usize) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "macOS does not support pinning threads to cores",
+        ))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadAffinityMask, SetThreadPriority, THREAD_PRIORITY_HIGHEST,
+    };
+
+    use super::core_out_of_range;
+
+    pub fn raise_current_thread_priority() -> io::Result<()> {
+        let result = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_HIGHEST) };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub fn pin_current_thread_to_core(core: usize) -> io::Result<()> {
+        if core >= usize::BITS as usize {
+            return Err(core_out_of_range(core));
+        }
+
+        // Returns the previous affinity mask, or 0 on failure
+        let result = unsafe { SetThreadAffinityMask(GetCurrentThread(), 1 << core) };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use std::io;
+
+    pub fn raise_current_thread_priority() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "thread priority is not supported on this platform",
+        ))
+    }
+
+    pub fn pin_current_thread_to_core(_: usize) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "pinning threads to cores is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, thread};
+
+    use super::{pin_current_thread_to_core, raise_current_thread_priority};
+
+    /// Each call runs on its own thread so that the test threads are left unchanged. Whether the
+    /// calls succeed depends on the permissions and cores the tests run with, so only check that
+    /// they return instead of crashing.
+    #[test]
+    fn shim_functions_return_results() {
+        thread::spawn(|| {
+            let _ = raise_current_thread_priority();
+            let _ = pin_current_thread_to_core(0);
+        })
+        .join()
+        .unwrap();
+
+        let result = thread::spawn(|| pin_current_thread_to_core(usize::MAX))
+            .join()
+            .unwrap();
+        assert!(matches!(
+            result.map_err(|error| error.kind()),
+            Err(io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported)
+        ));
+    }
+}
@@ -0,0 +1,478 @@
+//! Export of a tile map as a Tiled JSON map, for ripping levels out of games.
+//!
+//! An export of `level.json` writes two files:
+//! - `level.json`: a map in the Tiled JSON format with a single tile layer covering the 32x32 tile
+//!   map. Tiles are referenced by their position in the tile sheet, with X and Y flips stored in
+//!   Tiled's flip flags. A `cells` array that Tiled ignores lists the raw tile index of each cell
+//!   along with its decoded CGB attributes.
+//! - `level_tiles.png`: the tile sheet the map references, holding all 384 tiles of each VRAM bank
+//!   in the same layout as the tile data sheets in PPU dumps.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use image::RgbImage;
+use serde::Serialize;
+
+use crate::{
+    address_space::SINGLE_VRAM_BANK_SIZE,
+    emulator::Emulator,
+    ppu::{
+        BackgroundTileAttributes, TILE_DATA_SIZE, TILE_MAP_SIZE, TILE_MAP_TOTAL_TILES, TILE_SIZE,
+        lookup_byte_in_tile_map, lookup_tile_attributes_in_tile_map, tile_data_address,
+    },
+    ppu_dump::{NUM_TILES_PER_BANK, render_tile_data_sheet, save_png},
+};
+
+/// Tiled stores flips in the high bits of each tile's global ID
+const TILED_HORIZONTAL_FLIP_FLAG: u32 = 0x8000_0000;
+const TILED_VERTICAL_FLIP_FLAG: u32 = 0x4000_0000;
+
+/// Tile data starts at 0x8000 in both VRAM banks
+const TILE_DATA_START: usize = 0x8000;
+
+/// A single cell of a tile map.
+pub struct TileMapCell {
+    /// Tile index as stored in the tile map
+    pub tile_index: u8,
+    /// Attributes of the cell, only present in CGB mode
+    pub attributes: Option<BackgroundTileAttributes>,
+    /// Position of the cell's tile in the tile sheet
+    pub sheet_index: usize,
+}
+
+impl TileMapCell {
+    /// The Tiled global ID of the cell's tile, including flip flags. Global IDs start at 1 since 0
+    /// means an empty cell.
+    fn tiled_gid(&self) -> u32 {
+        let mut gid = self.sheet_index as u32 + 1;
+
+        if let Some(attributes) = &self.attributes {
+            if attributes.is_horizontally_flipped() {
+                gid |= TILED_HORIZONTAL_FLIP_FLAG;
+            }
+
+            if attributes.is_vertically_flipped() {
+                gid |= TILED_VERTICAL_FLIP_FLAG;
+            }
+        }
+
+        gid
+    }
+}
+
+/// Read every cell of a tile map (0 or 1), interpreting tile indices with the given tile data
+/// addressing mode (0 or 1).
+pub fn read_tile_map_cells(
+    emulator: &Emulator,
+    tile_map_number: u8,
+    addressing_mode: u8,
+) -> Vec<TileMapCell> {
+    (0..TILE_MAP_TOTAL_TILES)
+        .map(|tile_map_index| {
+            let tile_index = lookup_byte_in_tile_map(emulator, 0, tile_map_number, tile_map_index);
+            let attributes = emulator.in_cgb_mode().then(|| {
+                lookup_tile_attributes_in_tile_map(emulator, tile_map_number, tile_map_index)
+            });
+
+            // Tile sheets hold the tile data of each bank in address order
+            let vram_bank_num = attributes
+                .as_ref()
+                .map_or(0, |attributes| attributes.vram_bank_number());
+            let tile_offset = tile_data_address(addressing_mode, tile_index) - TILE_DATA_START;
+            let sheet_index = vram_bank_num * NUM_TILES_PER_BANK + tile_offset / TILE_DATA_SIZE;
+
+            TileMapCell {
+                tile_index,
+                attributes,
+                sheet_index,
+            }
+        })
+        .collect()
+}
+
+/// Path of the tile sheet written alongside the map at `path`.
+pub fn tile_sheet_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_tiles.png", stem))
+}
+
+/// Render the tile data of every VRAM bank into a single sheet, one bank below the other.
+fn render_tile_sheet(emulator: &Emulator) -> RgbImage {
+    let num_vram_banks = emulator.vram().len() / SINGLE_VRAM_BANK_SIZE;
+    let bank_sheets: Vec<RgbImage> = (0..num_vram_banks)
+        .map(|bank| render_tile_data_sheet(emulator, bank))
+        .collect();
+
+    let bank_height = bank_sheets[0].height();
+    let mut sheet = RgbImage::new(bank_sheets[0].width(), bank_height * num_vram_banks as u32);
+    for (bank, bank_sheet) in bank_sheets.iter().enumerate() {
+        image::imageops::replace(
+            &mut sheet,
+            bank_sheet,
+            0,
+            (bank as u32 * bank_height) as i64,
+        );
+    }
+
+    sheet
+}
+
+/// A map in the Tiled JSON format, with the fields Tiled requires in the order it writes them.
+#[derive(Serialize)]
+struct TiledMap<'a> {
+    #[serde(rename = "type")]
+    map_type: &'static str,
+    version: &'static str,
+    orientation: &'static str,
+    renderorder: &'static str,
+    infinite: bool,
+    width: usize,
+    height: usize,
+    tilewidth: usize,
+    tileheight: usize,
+    nextlayerid: u32,
+    nextobjectid: u32,
+    properties: [TiledProperty; 2],
+    tilesets: [TiledTileset<'a>; 1],
+    layers: [TiledLayer; 1],
+    /// Ignored by Tiled
+    cells: Vec<CellInfo>,
+}
+
+#[derive(Serialize)]
+struct TiledProperty {
+    name: &'static str,
+    #[serde(rename = "type")]
+    property_type: &'static str,
+    value: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct TiledTileset<'a> {
+    firstgid: u32,
+    name: &'static str,
+    image: &'a str,
+    imagewidth: u32,
+    imageheight: u32,
+    tilewidth: usize,
+    tileheight: usize,
+    tilecount: usize,
+    columns: usize,
+    margin: u32,
+    spacing: u32,
+}
+
+#[derive(Serialize)]
+struct TiledLayer {
+    id: u32,
+    name: &'static str,
+    #[serde(rename = "type")]
+    layer_type: &'static str,
+    x: u32,
+    y: u32,
+    width: usize,
+    height: usize,
+    opacity: u32,
+    visible: bool,
+    data: Vec<u32>,
+}
+
+/// The raw tile index of a cell, along with its decoded attributes in CGB mode.
+#[derive(Serialize)]
+struct CellInfo {
+    tile_index: u8,
+    #[serde(flatten)]
+    attributes: Option<CellAttributesInfo>,
+}
+
+#[derive(Serialize)]
+struct CellAttributesInfo {
+    attributes: u8,
+    palette: usize,
+    bank: usize,
+    x_flip: bool,
+    y_flip: bool,
+    priority: bool,
+}
+
+/// Build the Tiled JSON map for the given cells, referencing a tile sheet image of the given size.
+pub fn tile_map_json(
+    cells: &[TileMapCell],
+    tile_map_number: u8,
+    addressing_mode: u8,
+    tile_sheet_file_name: &str,
+    tile_sheet_size: (u32, u32),
+) -> String {
+    let (sheet_width, sheet_height) = tile_sheet_size;
+    let columns = sheet_width as usize / TILE_SIZE;
+    let tile_count = columns * (sheet_height as usize / TILE_SIZE);
+
+    let tile_map_address = if tile_map_number == 0 { 0x9800 } else { 0x9C00 };
+
+    let cell_infos = cells
+        .iter()
+        .map(|cell| CellInfo {
+            tile_index: cell.tile_index,
+            attributes: cell
+                .attributes
+                .as_ref()
+                .map(|attributes| CellAttributesInfo {
+                    attributes: attributes.raw(),
+                    palette: attributes.color_palette(),
+                    bank: attributes.vram_bank_number(),
+                    x_flip: attributes.is_horizontally_flipped(),
+                    y_flip: attributes.is_vertically_flipped(),
+                    priority: attributes.in_foreground(),
+                }),
+        })
+        .collect();
+
+    let map = TiledMap {
+        map_type: "map",
+        version: "1.10",
+        orientation: "orthogonal",
+        renderorder: "right-down",
+        infinite: false,
+        width: TILE_MAP_SIZE,
+        height: TILE_MAP_SIZE,
+        tilewidth: TILE_SIZE,
+        tileheight: TILE_SIZE,
+        nextlayerid: 2,
+        nextobjectid: 1,
+        properties: [
+            TiledProperty {
+                name: "tile_map_address",
+                property_type: "string",
+                value: format!("0x{:04X}", tile_map_address).into(),
+            },
+            TiledProperty {
+                name: "tile_data_addressing_mode",
+                property_type: "int",
+                value: addressing_mode.into(),
+            },
+        ],
+        tilesets: [TiledTileset {
+            firstgid: 1,
+            name: "tiles",
+            image: tile_sheet_file_name,
+            imagewidth: sheet_width,
+            imageheight: sheet_height,
+            tilewidth: TILE_SIZE,
+            tileheight: TILE_SIZE,
+            tilecount: tile_count,
+            columns,
+            margin: 0,
+            spacing: 0,
+        }],
+        layers: [TiledLayer {
+            id: 1,
+            name: "Tile Map",
+            layer_type: "tilelayer",
+            x: 0,
+            y: 0,
+            width: TILE_MAP_SIZE,
+            height: TILE_MAP_SIZE,
+            opacity: 1,
+            visible: true,
+            data: cells.iter().map(TileMapCell::tiled_gid).collect(),
+        }],
+        cells: cell_infos,
+    };
+
+    let mut json = serde_json::to_string_pretty(&map).unwrap();
+    json.push('\n');
+    json
+}
+
+/// Write a tile map (0 or 1) to `path` as a Tiled JSON map, along with the tile sheet it
+/// references.
+pub fn export_tile_map(
+    emulator: &Emulator,
+    tile_map_number: u8,
+    addressing_mode: u8,
+    path: &Path,
+) -> io::Result<()> {
+    let sheet = render_tile_sheet(emulator);
+    let sheet_path = tile_sheet_path(path);
+    save_png(&sheet, &sheet_path)?;
+
+    let sheet_file_name = sheet_path.file_name().unwrap_or_default().to_string_lossy();
+    let cells = read_tile_map_cells(emulator, tile_map_number, addressing_mode);
+    let json = tile_map_json(
+        &cells,
+        tile_map_number,
+        addressing_mode,
+        &sheet_file_name,
+        sheet.dimensions(),
+    );
+
+    std::fs::write(path, json)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, path::Path, process};
+
+    use crate::{
+        emulator::Emulator,
+        machine::Machine,
+        test_utils::{
+            build_cgb_test_rom, build_test_rom, new_test_emulator_builder, with_large_stack,
+        },
+    };
+
+    use serde_json::{Value, json};
+
+    use super::{export_tile_map, read_tile_map_cells, tile_map_json, tile_sheet_path};
+
+    fn new_idle_emulator(machine: Machine) -> Emulator {
+        let program = [0x18, 0xFE];
+        let rom = match machine {
+            Machine::Dmg => build_test_rom(0x00, 0x00, 0x00, &program),
+            Machine::Cgb => build_cgb_test_rom(0x00, 0x00, 0x00, &program),
+        };
+        let mut emulator = new_test_emulator_builder(machine, rom).build().unwrap();
+        emulator.emulate_boot_sequence();
+        emulator
+    }
+
+    /// Write a byte to the given VRAM bank directly, since VRAM may be inaccessible to the CPU.
+    fn write_vram(emulator: &mut Emulator, bank: usize, addr: u16, value: u8) {
+        emulator.vram_mut()[Emulator::map_vram_address_in_bank(addr, bank)] = value;
+    }
+
+    fn parse_json(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// The numbers in the layer's `data` array, in order.
+    fn layer_data(json: &Value) -> Vec<u32> {
+        json["layers"][0]["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|gid| gid.as_u64().unwrap() as u32)
+            .collect()
+    }
+
+    #[test]
+    fn dmg_tile_map_schema() {
+        with_large_stack(|| {
+            let mut emulator = new_idle_emulator(Machine::Dmg);
+            write_vram(&mut emulator, 0, 0x9C00, 0x01);
+            write_vram(&mut emulator, 0, 0x9C01, 0x80);
+            write_vram(&mut emulator, 0, 0x9FFF, 0x7F);
+
+            // In the signed addressing mode indices below 0x80 come after the 0x8000 tiles
+            let cells = read_tile_map_cells(&emulator, 1, 0);
+            assert_eq!(cells.len(), 32 * 32);
+            assert_eq!(cells[0].sheet_index, 256 + 0x01);
+            assert_eq!(cells[1].sheet_index, 0x80);
+            assert_eq!(cells[1023].sheet_index, 256 + 0x7F);
+            assert!(cells.iter().all(|cell| cell.attributes.is_none()));
+
+            let json = parse_json(&tile_map_json(&cells, 1, 0, "map_tiles.png", (128, 192)));
+            assert_eq!(json["width"], 32);
+            assert_eq!(json["tilewidth"], 8);
+            assert_eq!(json["tilesets"][0]["image"], "map_tiles.png");
+            assert_eq!(json["tilesets"][0]["tilecount"], 384);
+            assert_eq!(json["tilesets"][0]["columns"], 16);
+            assert_eq!(json["properties"][0]["value"], "0x9C00");
+            assert_eq!(json["cells"][1], json!({"tile_index": 128}));
+            let cell_infos = json["cells"].as_array().unwrap();
+            assert!(cell_infos.iter().all(|cell| cell.get("palette").is_none()));
+
+            let data = layer_data(&json);
+            assert_eq!(data.len(), 32 * 32);
+            assert_eq!(&data[..2], &[258, 129]);
+            assert_eq!(data[1023], 384);
+
+            // In the unsigned addressing mode indices map directly onto the sheet
+            let cells = read_tile_map_cells(&emulator, 1, 1);
+            assert_eq!(cells[0].sheet_index, 0x01);
+            assert_eq!(cells[1023].sheet_index, 0x7F);
+        });
+    }
+
+    #[test]
+    fn cgb_tile_map_attributes() {
+        with_large_stack(|| {
+            let mut emulator = new_idle_emulator(Machine::Cgb);
+
+            // Tile 0x05 from bank 1 with palette 3, flipped both ways and drawn over objects
+            write_vram(&mut emulator, 0, 0x9800, 0x05);
+            write_vram(&mut emulator, 1, 0x9800, 0x80 | 0x40 | 0x20 | 0x08 | 0x03);
+
+            // Tile 0x06 from bank 0 with palette 1, only flipped horizontally
+            write_vram(&mut emulator, 0, 0x9801, 0x06);
+            write_vram(&mut emulator, 1, 0x9801, 0x20 | 0x01);
+
+            let cells = read_tile_map_cells(&emulator, 0, 1);
+            assert_eq!(cells[0].sheet_index, 384 + 0x05);
+            assert_eq!(cells[1].sheet_index, 0x06);
+
+            let json = parse_json(&tile_map_json(&cells, 0, 1, "map_tiles.png", (128, 384)));
+            assert_eq!(json["tilesets"][0]["tilecount"], 768);
+            assert_eq!(
+                json["cells"][0],
+                json!({
+                    "tile_index": 5, "attributes": 235, "palette": 3, "bank": 1,
+                    "x_flip": true, "y_flip": true, "priority": true
+                })
+            );
+            assert_eq!(
+                json["cells"][1],
+                json!({
+                    "tile_index": 6, "attributes": 33, "palette": 1, "bank": 0,
+                    "x_flip": true, "y_flip": false, "priority": false
+                })
+            );
+
+            let data = layer_data(&json);
+            assert_eq!(data[0], 0xC000_0000 | (384 + 0x05 + 1));
+            assert_eq!(data[1], 0x8000_0000 | (0x06 + 1));
+        });
+    }
+
+    #[test]
+    fn tile_sheet_file_name_is_escaped() {
+        with_large_stack(|| {
+            let emulator = new_idle_emulator(Machine::Dmg);
+            let cells = read_tile_map_cells(&emulator, 0, 1);
+
+            let file_name = "map \"1\"\\tiles.png";
+            let json = parse_json(&tile_map_json(&cells, 0, 1, file_name, (128, 192)));
+            assert_eq!(json["tilesets"][0]["image"], file_name);
+        });
+    }
+
+    #[test]
+    fn export_writes_map_and_tile_sheet() {
+        with_large_stack(|| {
+            let dir = env::temp_dir().join(format!("gbcemu-tile-map-export-{}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+
+            let emulator = new_idle_emulator(Machine::Cgb);
+            let path = dir.join("level.json");
+            export_tile_map(&emulator, 0, 1, &path).unwrap();
+
+            let sheet_path = tile_sheet_path(&path);
+            assert_eq!(sheet_path, dir.join("level_tiles.png"));
+            assert_eq!(image::image_dimensions(&sheet_path).unwrap(), (128, 384));
+
+            let json = parse_json(&fs::read_to_string(&path).unwrap());
+            assert_eq!(json["tilesets"][0]["image"], "level_tiles.png");
+
+            // Fails without leaving a map behind when the directory does not exist
+            let missing_path = dir.join("missing").join("level.json");
+            assert!(export_tile_map(&emulator, 0, 1, &missing_path).is_err());
+            assert!(!Path::new(&missing_path).exists());
+
+            fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+}
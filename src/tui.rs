@@ -0,0 +1,348 @@
+//! A minimal terminal frontend, for running without a display server (e.g. over ssh).
+//!
+//! The screen is drawn with half-block characters in 24-bit color, so each terminal cell shows two
+//! vertically stacked pixels. Emulation runs at the GameBoy's native framerate without audio while
+//! the terminal is only redrawn a few times per second.
+//!
+//! This is also the smallest complete example of driving an [`Emulator`] from outside the crate:
+//! power it on, alternate between `run_frame`, `handle_update_pressed_buttons`, and reading the
+//! screen with `read_pixel`, then call `flush_on_shutdown` once done.
+
+use std::{
+    io::{self, Write},
+    panic::{self, PanicHookInfo},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{self, Color as TermColor, Print},
+    terminal,
+};
+
+use crate::{
+    emulator::{Button, Emulator, REFRESH_RATE, SCREEN_HEIGHT, SCREEN_WIDTH, TestResult},
+    ppu::Color,
+};
+
+/// Number of emulated frames between each redraw of the terminal, redrawing at ~10 fps
+const FRAMES_PER_REDRAW: usize = 6;
+
+/// Terminals do not report key releases, so a key press holds its button down for this long
+const BUTTON_HOLD_DURATION: Duration = Duration::from_millis(150);
+
+/// Upper half block. The foreground color is the top pixel and the background color the bottom.
+const HALF_BLOCK: char = '\u{2580}';
+
+pub type Rgb = [u8; 3];
+
+/// A single terminal cell showing two vertically stacked pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HalfBlockCell {
+    pub top: Rgb,
+    pub bottom: Rgb,
+}
+
+/// Convert a row-major `SCREEN_WIDTH` x `SCREEN_HEIGHT` framebuffer into rows of cells that fit
+/// within `columns` x `rows` terminal cells. The aspect ratio is preserved using nearest-neighbor
+/// scaling, and the bottom half of the last row is black if the image has an odd height.
+pub fn framebuffer_to_cells(frame: &[Rgb], columns: usize, rows: usize) -> Vec<Vec<HalfBlockCell>> {
+    let scale = f64::min(
+        columns as f64 / SCREEN_WIDTH as f64,
+        (rows * 2) as f64 / SCREEN_HEIGHT as f64,
+    );
+
+    let width = ((SCREEN_WIDTH as f64 * scale) as usize).min(columns);
+    let height = ((SCREEN_HEIGHT as f64 * scale) as usize).min(rows * 2);
+
+    let pixel = |x: usize, y: usize| -> Rgb {
+        if y >= height {
+            return [0, 0, 0];
+        }
+
+        let source_x = ((x as f64 / scale) as usize).min(SCREEN_WIDTH - 1);
+        let source_y = ((y as f64 / scale) as usize).min(SCREEN_HEIGHT - 1);
+        frame[source_y * SCREEN_WIDTH + source_x]
+    };
+
+    (0..height.div_ceil(2))
+        .map(|row| {
+            (0..width)
+                .map(|x| HalfBlockCell {
+                    top: pixel(x, row * 2),
+                    bottom: pixel(x, row * 2 + 1),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn color_to_rgb(emulator: &Emulator, color: Color) -> Rgb {
+    let color32 = emulator.screen_palette().color_to_color32(color);
+    [color32.r(), color32.g(), color32.b()]
+}
+
+fn read_framebuffer(emulator: &Emulator) -> Vec<Rgb> {
+    (0..SCREEN_HEIGHT)
+        .flat_map(|y| {
+            (0..SCREEN_WIDTH).map(move |x| color_to_rgb(emulator, emulator.read_pixel(x, y)))
+        })
+        .collect()
+}
+
+fn key_to_button(key_code: KeyCode) -> Option<Button> {
+    match key_code {
+        KeyCode::Up => Some(Button::Up),
+        KeyCode::Down => Some(Button::Down),
+        KeyCode::Left => Some(Button::Left),
+        KeyCode::Right => Some(Button::Right),
+        KeyCode::Char('x') => Some(Button::A),
+        KeyCode::Char('z') => Some(Button::B),
+        KeyCode::Char('s') | KeyCode::Enter => Some(Button::Start),
+        KeyCode::Char('a') => Some(Button::Select),
+        _ => None,
+    }
+}
+
+fn is_quit_key(key: &KeyEvent) -> bool {
+    matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+        || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+}
+
+type PanicHook = dyn Fn(&PanicHookInfo<'_>) + Send + Sync;
+
+/// Puts the terminal into raw mode on the alternate screen, restoring it and the previous panic
+/// hook when dropped.
+struct TerminalGuard {
+    previous_hook: Arc<PanicHook>,
+}
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            io::stdout(),
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+
+        // Restore the terminal before the panic message is printed so that it is readable
+        let previous_hook: Arc<PanicHook> = Arc::from(panic::take_hook());
+        let hook = previous_hook.clone();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            hook(info);
+        }));
+
+        Ok(TerminalGuard { previous_hook })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+
+        // The panic hook cannot be changed while panicking
+        if !thread::panicking() {
+            let previous_hook = self.previous_hook.clone();
+            panic::set_hook(Box::new(move |info| previous_hook(info)));
+        }
+    }
+}
+
+fn restore_terminal() {
+    let _ = execute!(
+        io::stdout(),
+        style::ResetColor,
+        cursor::Show,
+        terminal::LeaveAlternateScreen
+    );
+    let _ = terminal::disable_raw_mode();
+}
+
+/// Run the emulator in the terminal until the user quits, then flush the save file in the same
+/// way as when the GUI shuts down.
+pub fn run_tui(emulator: &mut Emulator) -> io::Result<()> {
+    let guard = TerminalGuard::enter()?;
+
+    emulator.power_on();
+    let result = run_frames(emulator);
+
+    drop(guard);
+    emulator.flush_on_shutdown();
+
+    result
+}
+
+/// Run frames and redraw the terminal until the user quits.
+fn run_frames(emulator: &mut Emulator) -> io::Result<()> {
+    let frame_duration = Duration::from_secs_f64(1.0 / REFRESH_RATE);
+    let mut next_frame_time = Instant::now();
+
+    // Time at which each held button is released
+    let mut held_buttons: Vec<(Button, Instant)> = vec![];
+
+    let mut fps_window_start = Instant::now();
+    let mut fps_window_frames = 0;
+    let mut fps = 0.0;
+
+    let mut frame_number: usize = 0;
+
+    loop {
+        // Handle all pending input without blocking
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => {
+                    if is_quit_key(&key) {
+                        return Ok(());
+                    }
+
+                    if let Some(button) = key_to_button(key.code) {
+                        let release_time = Instant::now() + BUTTON_HOLD_DURATION;
+                        held_buttons.retain(|(held, _)| *held as u8 != button as u8);
+                        held_buttons.push((button, release_time));
+                    }
+                }
+                Event::Resize(_, _) => {
+                    execute!(io::stdout(), terminal::Clear(terminal::ClearType::All))?;
+                }
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        held_buttons.retain(|(_, release_time)| *release_time > now);
+        let pressed_buttons = held_buttons
+            .iter()
+            .fold(0, |buttons, (button, _)| buttons | *button as u8);
+        emulator.handle_update_pressed_buttons(pressed_buttons);
+
+        emulator.run_frame();
+
+        fps_window_frames += 1;
+        let fps_window_duration = fps_window_start.elapsed();
+        if fps_window_duration >= Duration::from_secs(1) {
+            fps = fps_window_frames as f64 / fps_window_duration.as_secs_f64();
+            fps_window_start = Instant::now();
+            fps_window_frames = 0;
+        }
+
+        if frame_number.is_multiple_of(FRAMES_PER_REDRAW) {
+            draw(emulator, fps)?;
+        }
+
+        frame_number += 1;
+        next_frame_time += frame_duration;
+        let now = Instant::now();
+        if next_frame_time > now {
+            thread::sleep(next_frame_time - now);
+        } else {
+            // Fell behind, so do not try to catch up
+            next_frame_time = now;
+        }
+    }
+}
+
+fn draw(emulator: &Emulator, fps: f64) -> io::Result<()> {
+    let (columns, rows) = terminal::size()?;
+
+    // Leave the last row for the status line
+    let screen_rows = (rows as usize).saturating_sub(1);
+    let cells = framebuffer_to_cells(&read_framebuffer(emulator), columns as usize, screen_rows);
+
+    let mut stdout = io::stdout().lock();
+
+    for (y, row) in cells.iter().enumerate() {
+        queue!(stdout, cursor::MoveTo(0, y as u16))?;
+        for cell in row {
+            let [top_r, top_g, top_b] = cell.top;
+            let [bottom_r, bottom_g, bottom_b] = cell.bottom;
+            queue!(
+                stdout,
+                style::SetForegroundColor(TermColor::Rgb {
+                    r: top_r,
+                    g: top_g,
+                    b: top_b
+                }),
+                style::SetBackgroundColor(TermColor::Rgb {
+                    r: bottom_r,
+                    g: bottom_g,
+                    b: bottom_b
+                }),
+                Print(HALF_BLOCK)
+            )?;
+        }
+    }
+
+    let mut status = format!("{:.1} FPS", fps);
+    if emulator.in_test_mode() {
+        status.push_str(match emulator.test_result() {
+            Some(TestResult::Passed) => " | Test passed!",
+            Some(TestResult::Failed) => " | Test failed!",
+            None => " | Test running",
+        });
+    }
+    status.push_str(" | arrows, z/x, a/s, q to quit");
+
+    queue!(
+        stdout,
+        style::ResetColor,
+        cursor::MoveTo(0, rows.saturating_sub(1)),
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        Print(status)
+    )?;
+
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    use super::{HalfBlockCell, Rgb, framebuffer_to_cells};
+
+    /// Each pixel's red channel is its x coordinate and green channel is its y coordinate.
+    fn coordinate_framebuffer() -> Vec<Rgb> {
+        (0..SCREEN_HEIGHT)
+            .flat_map(|y| (0..SCREEN_WIDTH).map(move |x| [x as u8, y as u8, 0]))
+            .collect()
+    }
+
+    #[test]
+    fn framebuffer_to_cells_scales_to_fit() {
+        let frame = coordinate_framebuffer();
+
+        // Exactly one cell per pixel column and two pixel rows
+        let cells = framebuffer_to_cells(&frame, SCREEN_WIDTH, SCREEN_HEIGHT / 2);
+        assert_eq!(cells.len(), SCREEN_HEIGHT / 2);
+        assert!(cells.iter().all(|row| row.len() == SCREEN_WIDTH));
+        assert_eq!(
+            cells[3][5],
+            HalfBlockCell {
+                top: [5, 6, 0],
+                bottom: [5, 7, 0]
+            }
+        );
+
+        // Half size, limited by the number of columns
+        let cells = framebuffer_to_cells(&frame, SCREEN_WIDTH / 2, 1000);
+        assert_eq!(cells.len(), SCREEN_HEIGHT / 4);
+        assert_eq!(cells[0].len(), SCREEN_WIDTH / 2);
+        assert_eq!(cells[1][1].top, [2, 4, 0]);
+        assert_eq!(cells[1][1].bottom, [2, 6, 0]);
+
+        // An odd number of pixel rows leaves the bottom of the last row black
+        let cells = framebuffer_to_cells(&frame, 50, 1000);
+        let last_row = cells.last().unwrap();
+        assert_eq!(cells.len(), 23);
+        assert_eq!(last_row[0].bottom, [0, 0, 0]);
+
+        // A terminal too small for any pixels has no cells
+        assert!(framebuffer_to_cells(&frame, 0, 0).is_empty());
+    }
+}
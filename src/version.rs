@@ -0,0 +1,36 @@
+//! Version and build information for this build of the emulator.
+//!
+//! The version string is shown in the About dialog and embedded in save files, quick saves, and
+//! PPU dumps so that bug reports identify the build that produced them.
+
+/// Version of the crate
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Short hash of the git commit this build was made from, or `unknown`
+pub const GIT_COMMIT_HASH: &str = env!("GBCEMU_GIT_COMMIT_HASH");
+
+/// UTC date of this build as `YYYY-MM-DD`
+pub const BUILD_DATE: &str = env!("GBCEMU_BUILD_DATE");
+
+/// Version, commit hash, and build date, e.g. `0.1.0 (5485a96 2026-10-16)`.
+pub const VERSION_STRING: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("GBCEMU_GIT_COMMIT_HASH"),
+    " ",
+    env!("GBCEMU_BUILD_DATE"),
+    ")"
+);
+
+/// Optional cargo features enabled in this build.
+pub fn enabled_features() -> Vec<&'static str> {
+    let mut features = vec![];
+    if cfg!(feature = "tui") {
+        features.push("tui");
+    }
+    if cfg!(feature = "frame-timing") {
+        features.push("frame-timing");
+    }
+
+    features
+}
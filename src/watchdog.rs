@@ -0,0 +1,190 @@
+//! Detection of a stalled emulator thread.
+//!
+//! The emulator increments a heartbeat counter every frame. The GUI samples the heartbeat on each
+//! update, and if it has not changed for `STALL_TIMEOUT` while the emulator is not paused then the
+//! emulator thread is considered stalled (e.g. deadlocked or stuck in an infinite loop).
+
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{emulator::Emulator, version::VERSION_STRING};
+
+/// How long the heartbeat must go without changing before the emulator is considered stalled.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Number of recently executed instruction addresses that are kept for diagnostics.
+pub const PC_HISTORY_SIZE: usize = 64;
+
+/// Ring buffer of the addresses of the most recently executed instructions.
+pub struct PcHistory {
+    pcs: [u16; PC_HISTORY_SIZE],
+    /// Index where the next address is written
+    next_index: usize,
+    /// Number of addresses recorded, saturating at `PC_HISTORY_SIZE`
+    len: usize,
+}
+
+impl PcHistory {
+    pub fn new() -> Self {
+        PcHistory {
+            pcs: [0; PC_HISTORY_SIZE],
+            next_index: 0,
+            len: 0,
+        }
+    }
+
+    pub fn record(&mut self, pc: u16) {
+        self.pcs[self.next_index] = pc;
+        self.next_index = (self.next_index + 1) % PC_HISTORY_SIZE;
+        self.len = (self.len + 1).min(PC_HISTORY_SIZE);
+    }
+
+    /// Recorded addresses from oldest to newest.
+    pub fn to_vec(&self) -> Vec<u16> {
+        let start = (self.next_index + PC_HISTORY_SIZE - self.len) % PC_HISTORY_SIZE;
+        (0..self.len)
+            .map(|i| self.pcs[(start + i) % PC_HISTORY_SIZE])
+            .collect()
+    }
+}
+
+/// Tracks the emulator's heartbeat to decide whether the emulator thread has stalled.
+pub struct StallWatchdog {
+    last_heartbeat: u64,
+    /// When the heartbeat last changed, or when the emulator was last seen paused
+    last_progress_time: Instant,
+}
+
+impl StallWatchdog {
+    pub fn new(heartbeat: u64, now: Instant) -> Self {
+        StallWatchdog {
+            last_heartbeat: heartbeat,
+            last_progress_time: now,
+        }
+    }
+
+    /// Sample the heartbeat, returning how long the emulator has been stalled if it is considered
+    /// stalled. A paused emulator is never stalled, and the timeout restarts once it is resumed.
+    pub fn update(&mut self, heartbeat: u64, is_paused: bool, now: Instant) -> Option<Duration> {
+        if is_paused || heartbeat != self.last_heartbeat {
+            self.last_heartbeat = heartbeat;
+            self.last_progress_time = now;
+            return None;
+        }
+
+        let stalled_for = now.saturating_duration_since(self.last_progress_time);
+        if stalled_for > STALL_TIMEOUT {
+            Some(stalled_for)
+        } else {
+            None
+        }
+    }
+}
+
+/// A plain text report of the emulator's state, for diagnosing a stall.
+///
+/// The emulator thread is not stopped while the report is collected, so values may be slightly
+/// inconsistent with each other. Backtraces can only be captured for the current thread, so the
+/// report does not include one for the emulator thread.
+pub fn stall_diagnostics(emulator: &Emulator, stalled_for: Duration) -> String {
+    let mut report = String::new();
+
+    let _ = writeln!(report, "gbcemu {}", VERSION_STRING);
+    let _ = writeln!(
+        report,
+        "No frames completed for {:.1}s (heartbeat {})",
+        stalled_for.as_secs_f64(),
+        emulator.heartbeat()
+    );
+    let _ = writeln!(report);
+    let _ = writeln!(report, "CPU: {:?}", emulator.cpu_state());
+    let _ = writeln!(report, "PPU: {:?}", emulator.ppu_state());
+    let _ = writeln!(report, "MBC: {}", emulator.mbc_debug_state());
+    let _ = writeln!(report);
+
+    let _ = writeln!(report, "Recently executed instructions (oldest first):");
+    for pc in emulator.pc_history().to_vec() {
+        let _ = writeln!(report, "  {:04X}", pc);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use super::{PC_HISTORY_SIZE, PcHistory, STALL_TIMEOUT, StallWatchdog};
+
+    #[test]
+    fn detects_stalled_heartbeat() {
+        let heartbeat = AtomicU64::new(0);
+        let start = Instant::now();
+        let mut watchdog = StallWatchdog::new(heartbeat.load(Ordering::Relaxed), start);
+
+        let at = |millis: u64| start + Duration::from_millis(millis);
+
+        // Heartbeat advances every frame
+        for frame in 1..100 {
+            heartbeat.fetch_add(1, Ordering::Relaxed);
+            let now = at(frame * 16);
+            assert_eq!(
+                watchdog.update(heartbeat.load(Ordering::Relaxed), false, now),
+                None
+            );
+        }
+
+        // Heartbeat stops, which is only a stall once the timeout has passed
+        let last_frame = 99 * 16;
+        let stalled_at = last_frame + STALL_TIMEOUT.as_millis() as u64 + 1;
+        assert_eq!(watchdog.update(99, false, at(last_frame + 1000)), None);
+        assert_eq!(
+            watchdog.update(99, false, at(stalled_at)),
+            Some(Duration::from_millis(stalled_at - last_frame))
+        );
+
+        // Recovers as soon as the heartbeat advances again
+        heartbeat.fetch_add(1, Ordering::Relaxed);
+        let now = at(stalled_at + 16);
+        assert_eq!(
+            watchdog.update(heartbeat.load(Ordering::Relaxed), false, now),
+            None
+        );
+    }
+
+    #[test]
+    fn paused_emulator_is_not_stalled() {
+        let start = Instant::now();
+        let mut watchdog = StallWatchdog::new(10, start);
+
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        assert_eq!(watchdog.update(10, true, at(5)), None);
+        assert_eq!(watchdog.update(10, true, at(60)), None);
+
+        // The timeout restarts when the emulator is resumed
+        assert_eq!(watchdog.update(10, false, at(61)), None);
+        assert!(watchdog.update(10, false, at(63)).is_some());
+    }
+
+    #[test]
+    fn pc_history_keeps_most_recent() {
+        let mut history = PcHistory::new();
+        assert!(history.to_vec().is_empty());
+
+        history.record(0x0100);
+        history.record(0x0101);
+        assert_eq!(history.to_vec(), vec![0x0100, 0x0101]);
+
+        for pc in 0..(PC_HISTORY_SIZE as u16 + 10) {
+            history.record(pc);
+        }
+
+        let expected: Vec<u16> = (10..(PC_HISTORY_SIZE as u16 + 10)).collect();
+        assert_eq!(history.to_vec(), expected);
+    }
+}
@@ -0,0 +1,112 @@
+//! Checks that the audio path does not allocate once it reaches a steady state. Lives in its own
+//! test binary since it replaces the global allocator.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    path::Path,
+};
+
+use gbcemu::{
+    audio::{
+        BufferedSource, DEFAULT_AUDIO_LATENCY_FRAMES, SAMPLE_RATE, TurboAudio,
+        buffered_audio_channel,
+    },
+    cartridge::Cartridge,
+    emulator::{Emulator, EmulatorBuilder, REFRESH_RATE},
+    machine::Machine,
+};
+
+/// Allocator that counts the allocations made on each thread, so that tests running in parallel
+/// do not affect each other's counts.
+struct CountingAllocator;
+
+thread_local! {
+    static NUM_ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn record_allocation() {
+    let _ = NUM_ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_allocation();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_allocation();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Number of allocations made on this thread while running `f`
+fn count_allocations(f: impl FnOnce()) -> usize {
+    let start = NUM_ALLOCATIONS.with(Cell::get);
+    f();
+    NUM_ALLOCATIONS.with(Cell::get) - start
+}
+
+const NUM_WARM_UP_FRAMES: usize = 60;
+
+const NUM_MEASURED_FRAMES: usize = 120;
+
+fn new_emulator() -> EmulatorBuilder {
+    let rom_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fill_vram.gb");
+    let cartridge = Cartridge::new_from_rom_bytes(std::fs::read(rom_path).unwrap()).unwrap();
+    EmulatorBuilder::new_cartridge(cartridge, Machine::Dmg)
+}
+
+/// Pull a frame's worth of interleaved samples from the source, as the audio device would.
+fn play_frame(source: &mut BufferedSource) {
+    let num_samples = (2.0 * SAMPLE_RATE as f64 / REFRESH_RATE) as usize;
+    for _ in 0..num_samples {
+        source.next().unwrap();
+    }
+}
+
+fn run_frames(emulator: &mut Emulator, mut source: Option<&mut BufferedSource>, num_frames: usize) {
+    for _ in 0..num_frames {
+        emulator.run_frame();
+
+        if let Some(source) = source.as_mut() {
+            play_frame(source);
+        }
+    }
+}
+
+#[test]
+fn audio_path_does_not_allocate_in_steady_state() {
+    let (audio_output, mut source) =
+        buffered_audio_channel(DEFAULT_AUDIO_LATENCY_FRAMES, TurboAudio::Pitch);
+
+    let mut with_audio = new_emulator()
+        .with_audio_output(Box::new(audio_output))
+        .build()
+        .unwrap();
+    let mut without_audio = new_emulator().build().unwrap();
+
+    with_audio.emulate_boot_sequence();
+    without_audio.emulate_boot_sequence();
+
+    // Let buffers reach their steady state sizes
+    run_frames(&mut with_audio, Some(&mut source), NUM_WARM_UP_FRAMES);
+    run_frames(&mut without_audio, None, NUM_WARM_UP_FRAMES);
+
+    // Any allocations made while emulating happen with or without audio, so the audio path itself
+    // must not have allocated if the counts match.
+    let with_audio_allocations =
+        count_allocations(|| run_frames(&mut with_audio, Some(&mut source), NUM_MEASURED_FRAMES));
+    let without_audio_allocations =
+        count_allocations(|| run_frames(&mut without_audio, None, NUM_MEASURED_FRAMES));
+
+    assert_eq!(with_audio_allocations, without_audio_allocations);
+}
@@ -0,0 +1,363 @@
+//! Drives the emulator through the same commands channel the GUI uses, without a display, and
+//! checks the observable effect of each command.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc::{Receiver, Sender, channel},
+    thread,
+};
+
+use gbcemu::{
+    cartridge::Cartridge,
+    debugger::Watchpoint,
+    emulator::{
+        Button, Command, CommandError, CommandId, DEFAULT_TURBO_SPEED, Emulator, EmulatorBuilder,
+        EmulatorEvent, MAX_SPEED, NORMAL_SPEED, SharedInputAdapter,
+    },
+    machine::Machine,
+    ppu::lookup_cgb_color,
+    screen_palette::ScreenColorPalette,
+    symbols::BankedAddress,
+};
+
+/// An emulator along with both ends of the channels the GUI would hold.
+struct Harness {
+    emulator: Emulator,
+    commands_tx: Sender<Command>,
+    events_rx: Receiver<EmulatorEvent>,
+    next_command_id: CommandId,
+    dir: PathBuf,
+}
+
+impl Harness {
+    /// Runs the VRAM fill fixture, patched to have 8KB of battery-backed cartridge RAM.
+    fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("gbcemu-commands-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let (commands_tx, commands_rx) = channel();
+        let (events_tx, events_rx) = channel();
+
+        let cartridge = Cartridge::new_from_rom_bytes(rom_with_cartridge_ram()).unwrap();
+        let mut emulator = EmulatorBuilder::new_cartridge(cartridge, Machine::Dmg)
+            .with_save_file_path(path_string(&dir.join("game.svgb")))
+            .with_input_adapter(SharedInputAdapter::new(commands_rx, events_tx))
+            .build()
+            .unwrap();
+        emulator.emulate_boot_sequence();
+        emulator.run_frames(2);
+
+        Harness {
+            emulator,
+            commands_tx,
+            events_rx,
+            next_command_id: 0,
+            dir,
+        }
+    }
+
+    /// Send a command and run a frame, during which it is handled.
+    fn send(&mut self, command: Command) {
+        self.commands_tx.send(command).unwrap();
+        self.emulator.run_frame();
+    }
+
+    /// Send a command that can fail, run a frame, and return its result.
+    fn send_fallible(
+        &mut self,
+        command: impl FnOnce(CommandId) -> Command,
+    ) -> Result<(), CommandError> {
+        let command_id = self.next_command_id;
+        self.next_command_id += 1;
+        self.send(command(command_id));
+
+        self.events_rx
+            .try_iter()
+            .find_map(|event| match event {
+                EmulatorEvent::CommandResult {
+                    command_id: result_id,
+                    result,
+                } if result_id == command_id => Some(result),
+                _ => None,
+            })
+            .expect("no result for command")
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn rom_with_cartridge_ram() -> Vec<u8> {
+    let rom_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("fill_vram.gb");
+    let mut rom = fs::read(rom_path).unwrap();
+
+    // MBC1 with RAM and a battery, and 8KB of RAM
+    rom[0x0147] = 0x03;
+    rom[0x0149] = 0x02;
+    rom[0x014D] = rom[0x0134..0x014D].iter().fold(0u8, |checksum, byte| {
+        checksum.wrapping_sub(*byte).wrapping_sub(1)
+    });
+
+    rom
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_str().unwrap().to_string()
+}
+
+/// Serializing the emulator for saves needs more stack than the default test thread has.
+fn with_large_stack(f: impl FnOnce() + Send + 'static) {
+    thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn button_updates_reach_the_joypad() {
+    with_large_stack(|| {
+        let mut harness = Harness::new("buttons");
+
+        // Select the action buttons
+        harness.emulator.write_address(0xFF00, 0x10);
+        assert_eq!(harness.emulator.read_address(0xFF00) & 0x0F, 0x0F);
+
+        harness.send(Command::UpdatePressedButtons(
+            Button::Start as u8 | Button::A as u8,
+        ));
+        assert_eq!(harness.emulator.read_address(0xFF00) & 0x0F, 0x06);
+
+        harness.send(Command::UpdatePressedButtons(0));
+        assert_eq!(harness.emulator.read_address(0xFF00) & 0x0F, 0x0F);
+    });
+}
+
+#[test]
+fn save_and_quick_save_commands() {
+    with_large_stack(|| {
+        let mut harness = Harness::new("saves");
+        let save_file_path = harness.dir.join("game.svgb");
+
+        assert_eq!(harness.send_fallible(Command::Save), Ok(()));
+        assert!(save_file_path.exists());
+
+        // Quick saves round trip emulator state
+        harness.emulator.write_address(0xC000, 0x12);
+        assert_eq!(
+            harness.send_fallible(|id| Command::QuickSave(0, id)),
+            Ok(())
+        );
+        harness.emulator.write_address(0xC000, 0x34);
+        assert_eq!(
+            harness.send_fallible(|id| Command::LoadQuickSave(0, id)),
+            Ok(())
+        );
+        assert_eq!(harness.emulator.read_address(0xC000), 0x12);
+
+        assert!(matches!(
+            harness.send_fallible(|id| Command::LoadQuickSave(1, id)),
+            Err(CommandError::NotFound(_))
+        ));
+
+        // State files round trip emulator state
+        let state_path = harness.dir.join("game.state");
+        let path = state_path.clone();
+        assert_eq!(
+            harness.send_fallible(|id| Command::ExportState(path, id)),
+            Ok(())
+        );
+        harness.emulator.write_address(0xC000, 0x56);
+        let path = state_path.clone();
+        assert_eq!(
+            harness.send_fallible(|id| Command::ImportState(path, id)),
+            Ok(())
+        );
+        assert_eq!(harness.emulator.read_address(0xC000), 0x12);
+
+        let path = harness.dir.join("missing.state");
+        assert!(matches!(
+            harness.send_fallible(|id| Command::ImportState(path, id)),
+            Err(CommandError::Io(_))
+        ));
+    });
+}
+
+#[test]
+fn cartridge_ram_commands() {
+    with_large_stack(|| {
+        let mut harness = Harness::new("cartridge-ram");
+
+        assert_eq!(
+            harness.send_fallible(|id| Command::PatchCartridgeRam(0x10, vec![1, 2, 3], id)),
+            Ok(())
+        );
+        assert_eq!(&harness.emulator.cartridge_ram()[0x10..0x13], &[1, 2, 3]);
+
+        assert!(matches!(
+            harness.send_fallible(|id| Command::PatchCartridgeRam(0x1FFF, vec![1, 2], id)),
+            Err(CommandError::InvalidData(_))
+        ));
+
+        let ram_path = harness.dir.join("game.sav");
+        let path = ram_path.clone();
+        assert_eq!(
+            harness.send_fallible(|id| Command::ExportCartridgeRam(path, id)),
+            Ok(())
+        );
+        let mut ram = fs::read(&ram_path).unwrap();
+        assert_eq!(ram.len(), 0x2000);
+        assert_eq!(&ram[0x10..0x13], &[1, 2, 3]);
+
+        ram[0] = 0xAB;
+        fs::write(&ram_path, &ram).unwrap();
+        let path = ram_path.clone();
+        assert_eq!(
+            harness.send_fallible(|id| Command::ImportCartridgeRam(path, id)),
+            Ok(())
+        );
+        assert_eq!(harness.emulator.cartridge_ram()[0], 0xAB);
+    });
+}
+
+#[test]
+fn dump_commands_write_files() {
+    with_large_stack(|| {
+        let mut harness = Harness::new("dumps");
+
+        let dump_dir = harness.dir.join("ppu");
+        let path = dump_dir.clone();
+        assert_eq!(
+            harness.send_fallible(|id| Command::DumpPpuState(path, id)),
+            Ok(())
+        );
+        assert!(dump_dir.is_dir());
+
+        let map_path = harness.dir.join("map.json");
+        let path = map_path.clone();
+        assert_eq!(
+            harness.send_fallible(|id| Command::ExportTileMap(0, 1, path, id)),
+            Ok(())
+        );
+        assert!(map_path.exists());
+        assert!(harness.dir.join("map_tiles.png").exists());
+    });
+}
+
+#[test]
+fn settings_commands_change_state() {
+    with_large_stack(|| {
+        let mut harness = Harness::new("settings");
+
+        harness.send(Command::SetTurboMode(true));
+        assert_eq!(harness.emulator.speed(), DEFAULT_TURBO_SPEED);
+        harness.send(Command::SetTurboMode(false));
+        assert_eq!(harness.emulator.speed(), NORMAL_SPEED);
+
+        harness.send(Command::SetSpeed(0.5));
+        assert_eq!(harness.emulator.speed(), 0.5);
+        harness.send(Command::SetSpeed(100.0));
+        assert_eq!(harness.emulator.speed(), MAX_SPEED);
+        harness.send(Command::SetSpeed(NORMAL_SPEED));
+
+        harness.send(Command::SetRewinding(true));
+        assert!(harness.emulator.is_rewinding());
+        harness.send(Command::SetRewinding(false));
+        assert!(!harness.emulator.is_rewinding());
+
+        let volume_index = harness.emulator.apu().system_volume_index();
+        harness.send(Command::VolumeDown);
+        assert_eq!(
+            harness.emulator.apu().system_volume_index(),
+            volume_index - 1
+        );
+        harness.send(Command::VolumeUp);
+        assert_eq!(harness.emulator.apu().system_volume_index(), volume_index);
+
+        harness.send(Command::ToggleMute);
+        assert!(harness.emulator.apu().is_muted());
+        harness.send(Command::ToggleMute);
+        assert!(!harness.emulator.apu().is_muted());
+
+        harness.send(Command::ToggleAudioChannel(3));
+        assert!(harness.emulator.apu().is_channel_disabled(3));
+        assert!(!harness.emulator.apu().is_channel_disabled(1));
+        harness.send(Command::ToggleAudioChannel(3));
+        assert!(!harness.emulator.apu().is_channel_disabled(3));
+
+        harness.send(Command::ToggleHpf);
+        assert!(harness.emulator.apu().is_hpf_disabled());
+
+        harness.send(Command::SetScreenPalette(ScreenColorPalette::Green));
+        harness.emulator.run_frame();
+        assert_eq!(harness.emulator.screen_palette(), ScreenColorPalette::Green);
+
+        harness.send(Command::WriteCgbPalette {
+            is_object: true,
+            palette: 2,
+            index: 3,
+            color: 0x1234,
+        });
+        let color = lookup_cgb_color(harness.emulator.cgb_object_palettes(), 2, 3);
+        assert_eq!(color.raw(), 0x1234);
+    });
+}
+
+#[test]
+fn debugger_and_pause_commands() {
+    with_large_stack(|| {
+        let mut harness = Harness::new("debugger");
+
+        let breakpoint = BankedAddress::new(0, 0x0150);
+        harness.send(Command::AddBreakpoint(breakpoint));
+        assert!(harness.emulator.debugger().is_breakpoint(breakpoint));
+        harness.send(Command::RemoveBreakpoint(breakpoint));
+        assert!(!harness.emulator.debugger().is_breakpoint(breakpoint));
+
+        let watchpoint = Watchpoint {
+            address: 0xC000,
+            on_write: true,
+        };
+        harness.send(Command::AddWatchpoint(watchpoint));
+        assert!(harness.emulator.debugger().is_watchpoint(watchpoint));
+        harness.send(Command::RemoveWatchpoint(watchpoint));
+        assert!(!harness.emulator.debugger().is_watchpoint(watchpoint));
+
+        // Commands queued behind a pause are handled while paused. Every command is sent before the
+        // frame runs, so the emulator must end up resumed or the frame would never finish.
+        for command in [
+            Command::TogglePause,
+            Command::FrameAdvance,
+            Command::ToggleMute,
+            Command::TogglePause,
+        ] {
+            harness.commands_tx.send(command).unwrap();
+        }
+        harness.emulator.run_frame();
+
+        assert!(!harness.emulator.is_paused());
+        assert!(harness.emulator.apu().is_muted());
+    });
+}
+
+#[test]
+fn shutdown_stops_run() {
+    with_large_stack(|| {
+        let mut harness = Harness::new("shutdown");
+
+        harness.commands_tx.send(Command::Shutdown).unwrap();
+        harness.emulator.run();
+
+        assert!(harness.dir.join("game.svgb").exists());
+    });
+}
@@ -1,11 +1,13 @@
 mod utils;
 
-use std::path::Path;
+use std::{env, fs, path::Path, process::Command, sync::Arc};
 
-use gbcemu::{emulator::EmulatorBuilder, machine::Machine};
+use gbcemu::{
+    emulator::EmulatorBuilder, machine::Machine, options::Options, ppu_dump::render_framebuffer,
+};
 use utils::{
     assert_emulator_matches_image, read_cartridge_file, read_image_file, resolve_blarggs_path,
-    run_emulator_for_n_frames,
+    resolve_checked_in_fixture_path, run_emulator_for_n_frames,
 };
 
 use crate::utils::resolve_gameboy_test_roms_path;
@@ -22,9 +24,28 @@ fn run_screenshot_test(
     image_path: &Path,
     machine: Machine,
     num_frames_to_run: usize,
+) {
+    run_screenshot_test_with_options(
+        rom_path,
+        image_path,
+        machine,
+        num_frames_to_run,
+        Options::default(),
+    );
+}
+
+fn run_screenshot_test_with_options(
+    rom_path: &Path,
+    image_path: &Path,
+    machine: Machine,
+    num_frames_to_run: usize,
+    options: Options,
 ) {
     let cartridge = read_cartridge_file(rom_path);
-    let mut emulator = EmulatorBuilder::new_cartridge(cartridge, machine).build();
+    let mut emulator = EmulatorBuilder::new_cartridge(cartridge, machine)
+        .with_options(Arc::new(options))
+        .build()
+        .unwrap();
 
     run_emulator_for_n_frames(&mut emulator, num_frames_to_run);
 
@@ -73,6 +94,36 @@ fn blarggs_instr_timing() {
     );
 }
 
+/// Memory access timing is only exact when accesses happen on their machine cycle
+fn cycle_accurate_options() -> Options {
+    Options {
+        cycle_accurate: true,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn blarggs_mem_timing() {
+    run_screenshot_test_with_options(
+        &resolve_blarggs_path("mem_timing/mem_timing.gb"),
+        &resolve_blarggs_path("mem_timing/mem_timing-dmg-cgb.png"),
+        Machine::Dmg,
+        300,
+        cycle_accurate_options(),
+    );
+}
+
+#[test]
+fn blarggs_mem_timing_2() {
+    run_screenshot_test_with_options(
+        &resolve_blarggs_path("mem_timing-2/mem_timing.gb"),
+        &resolve_blarggs_path("mem_timing-2/mem_timing-dmg-cgb.png"),
+        Machine::Dmg,
+        300,
+        cycle_accurate_options(),
+    );
+}
+
 #[test]
 fn mbc3_tester() {
     run_screenshot_test(
@@ -89,3 +140,45 @@ fn mbc3_tester() {
         40,
     );
 }
+
+#[test]
+fn headless_frame_capture() {
+    let cartridge = read_cartridge_file(&resolve_checked_in_fixture_path("fill_vram.gb"));
+
+    // The fixture ROM fills VRAM without waiting for the PPU
+    let options = Options {
+        no_access_restrictions: true,
+        ..Options::default()
+    };
+    let mut emulator = EmulatorBuilder::new_cartridge(cartridge, Machine::Dmg)
+        .with_options(Arc::new(options))
+        .build()
+        .unwrap();
+
+    emulator.power_on();
+    emulator.run_frames(60);
+
+    let expected_image = read_image_file(&resolve_checked_in_fixture_path("fill_vram.png"));
+    assert_eq!(render_framebuffer(&emulator), expected_image);
+}
+
+#[test]
+fn screenshot_after_flag() {
+    let out_path = env::temp_dir().join(format!("gbcemu-screenshot-{}.png", std::process::id()));
+    let _ = fs::remove_file(&out_path);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_gbcemu"))
+        .arg("--no-access-restrictions")
+        .arg("--screenshot-after")
+        .arg("60")
+        .arg(&out_path)
+        .arg(resolve_checked_in_fixture_path("fill_vram.gb"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let expected_image = read_image_file(&resolve_checked_in_fixture_path("fill_vram.png"));
+    assert_eq!(read_image_file(&out_path), expected_image);
+
+    fs::remove_file(out_path).unwrap();
+}
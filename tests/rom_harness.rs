@@ -0,0 +1,84 @@
+//! Runs accuracy test ROMs such as the Blargg and Mooneye suites headless and reports the result of
+//! each.
+//!
+//! ROMs are found in the file or directory given by the `GBCEMU_TEST_ROMS` environment variable,
+//! or under `test_roms/` by default. ROMs that are not present are skipped, so this passes when no
+//! ROMs have been installed.
+
+use std::{env, fs, path::PathBuf};
+
+use gbcemu::{
+    machine::Machine,
+    test_runner::{
+        Completion, DEFAULT_TICK_BUDGET, TestRomOutcome, find_test_roms, machine_for_rom_path,
+        run_test_rom,
+    },
+};
+
+fn test_roms() -> Vec<PathBuf> {
+    let path = match env::var_os("GBCEMU_TEST_ROMS") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_roms"),
+    };
+
+    if path.is_file() {
+        vec![path]
+    } else {
+        find_test_roms(&path)
+    }
+}
+
+#[test]
+fn checked_in_rom_passes() {
+    let rom_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("harness_pass.gb");
+    let rom = fs::read(rom_path).unwrap();
+
+    let report = run_test_rom(rom, Machine::Dmg, Completion::Auto, DEFAULT_TICK_BUDGET).unwrap();
+    assert_eq!(report.outcome, TestRomOutcome::Passed);
+    assert_eq!(report.serial_output, "Passed\n");
+}
+
+#[test]
+fn test_roms_pass() {
+    let roms = test_roms();
+    if roms.is_empty() {
+        eprintln!("No test ROMs found, skipping");
+        return;
+    }
+
+    let mut failures = vec![];
+    for rom_path in roms {
+        let Ok(rom) = fs::read(&rom_path) else {
+            eprintln!("{}: skipped, could not be read", rom_path.display());
+            continue;
+        };
+
+        let machine = machine_for_rom_path(&rom_path);
+        let report = match run_test_rom(rom, machine, Completion::Auto, DEFAULT_TICK_BUDGET) {
+            Ok(report) => report,
+            Err(error) => {
+                failures.push(format!(
+                    "{}: could not be loaded: {}",
+                    rom_path.display(),
+                    error
+                ));
+                continue;
+            }
+        };
+        eprintln!("{}: {}", rom_path.display(), report.outcome);
+
+        if report.outcome != TestRomOutcome::Passed {
+            failures.push(format!(
+                "{}: {}\n{}",
+                rom_path.display(),
+                report.outcome,
+                report.serial_output
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
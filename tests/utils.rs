@@ -18,7 +18,7 @@ pub fn read_cartridge_file(rom_path: &Path) -> Cartridge {
         )
     });
 
-    Cartridge::new_from_rom_bytes(rom_bytes)
+    Cartridge::new_from_rom_bytes(rom_bytes).unwrap()
 }
 
 /// Read an image file
@@ -46,6 +46,14 @@ pub fn resolve_fixture_path(path: &str) -> PathBuf {
     Path::new("deps").join("test").join(path)
 }
 
+/// Fixtures that are checked into the repository, rather than installed as test dependencies.
+pub fn resolve_checked_in_fixture_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(path)
+}
+
 pub fn resolve_gameboy_test_roms_path(path: &str) -> PathBuf {
     resolve_fixture_path("game-boy-test-roms").join(path)
 }